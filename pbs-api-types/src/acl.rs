@@ -5,9 +5,11 @@ use serde::{Deserialize, Serialize};
 
 use proxmox_lang::constnamedbitmap;
 use proxmox_schema::{
-    api, const_regex, ApiStringFormat, BooleanSchema, EnumEntry, Schema, StringSchema,
+    api, const_regex, ApiStringFormat, BooleanSchema, EnumEntry, Schema, StringSchema, Updater,
 };
 
+use crate::{Authid, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_SCHEMA};
+
 const_regex! {
     pub ACL_PATH_REGEX = concat!(r"^(?:/|", r"(?:/", PROXMOX_SAFE_ID_REGEX_STR!(), ")+", r")$");
 }
@@ -84,6 +86,19 @@ pub fn privs_to_priv_names(privs: u64) -> Vec<&'static str> {
         })
 }
 
+/// Returns the combined privilege bits for a list of privilege names.
+///
+/// Fails if any of the names is not a known [privilege](PRIVILEGES).
+pub fn priv_names_to_privs<S: AsRef<str>>(names: &[S]) -> Result<u64, anyhow::Error> {
+    names.iter().try_fold(0, |privs, name| {
+        let name = name.as_ref();
+        match PRIVILEGES.iter().find(|(priv_name, _)| *priv_name == name) {
+            Some((_, value)) => Ok(privs | value),
+            None => anyhow::bail!("unknown privilege '{name}'"),
+        }
+    })
+}
+
 /// Admin always has all privileges. It can do everything except a few actions
 /// which are limited to the 'root@pam` superuser
 pub const ROLE_ADMIN: u64 = u64::MAX;
@@ -240,6 +255,78 @@ impl FromStr for Role {
     }
 }
 
+pub const ROLE_ID_SCHEMA: Schema = StringSchema::new("Role ID.")
+    .format(&PROXMOX_SAFE_ID_FORMAT)
+    .min_length(2)
+    .max_length(32)
+    .schema();
+
+pub const PRIVILEGE_NAME_FORMAT: ApiStringFormat =
+    ApiStringFormat::VerifyFn(|name| priv_names_to_privs(&[name]).map(drop));
+
+pub const PRIVILEGE_NAME_SCHEMA: Schema = StringSchema::new("Privilege name.")
+    .format(&PRIVILEGE_NAME_FORMAT)
+    .schema();
+
+#[api(
+    properties: {
+        roleid: {
+            schema: ROLE_ID_SCHEMA,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        privs: {
+            type: Array,
+            items: {
+                schema: PRIVILEGE_NAME_SCHEMA,
+            },
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Updater)]
+#[serde(rename_all = "kebab-case")]
+/// Custom role, composed of a set of privileges.
+pub struct CustomRole {
+    #[updater(skip)]
+    pub roleid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// The privileges granted by this role.
+    pub privs: Vec<String>,
+}
+
+#[api(
+    properties: {
+        roleid: {
+            schema: ROLE_ID_SCHEMA,
+        },
+        comment: {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        privs: {
+            type: Array,
+            items: {
+                schema: PRIVILEGE_NAME_SCHEMA,
+            },
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Role list entry, covering both built-in and custom roles.
+pub struct RoleListItem {
+    pub roleid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// The privileges granted by this role.
+    pub privs: Vec<String>,
+    /// True for the pre-defined roles, which cannot be modified.
+    pub builtin: bool,
+}
+
 pub const ACL_PATH_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&ACL_PATH_REGEX);
 
 pub const ACL_PATH_SCHEMA: Schema = StringSchema::new("Access control path.")
@@ -276,7 +363,7 @@ pub const ACL_UGID_TYPE_SCHEMA: Schema = StringSchema::new("Type of 'ugid' prope
             description: "User or Group ID.",
         },
 	roleid: {
-            type: Role,
+            schema: ROLE_ID_SCHEMA,
         }
     }
 )]
@@ -289,3 +376,132 @@ pub struct AclListItem {
     pub propagate: bool,
     pub roleid: String,
 }
+
+#[api(
+    properties: {
+        path: {
+            schema: ACL_PATH_SCHEMA,
+        },
+        "auth-id": {
+            type: Authid,
+        },
+        roleid: {
+            schema: ROLE_ID_SCHEMA,
+        },
+        propagate: {
+            schema: ACL_PROPAGATE_SCHEMA,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A role assignment contributing to the permissions on an ACL path.
+pub struct PermissionRoleOrigin {
+    /// The ACL path the role is assigned on.
+    pub path: String,
+    /// The user or API token the role is assigned to.
+    pub auth_id: Authid,
+    pub roleid: String,
+    pub propagate: bool,
+    /// True if the role is assigned on a parent path and propagated down.
+    pub inherited: bool,
+    /// False if the role is overridden by an ACL on a more specific path.
+    pub effective: bool,
+}
+
+#[api(
+    properties: {
+        path: {
+            schema: ACL_PATH_SCHEMA,
+        },
+        "auth-id": {
+            type: Authid,
+        },
+        privs: {
+            type: Array,
+            items: {
+                type: String,
+                description: "Privilege name.",
+            },
+        },
+        "propagated-privs": {
+            type: Array,
+            items: {
+                type: String,
+                description: "Privilege name.",
+            },
+        },
+        roles: {
+            type: Array,
+            items: {
+                type: PermissionRoleOrigin,
+            },
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Resolved permissions of a user or API token on a single ACL path.
+pub struct PermissionDetails {
+    pub path: String,
+    pub auth_id: Authid,
+    /// True for the superuser, which has all privileges regardless of ACLs.
+    pub superuser: bool,
+    /// The privileges on the path.
+    pub privs: Vec<String>,
+    /// The privileges which are also propagated to paths below.
+    pub propagated_privs: Vec<String>,
+    /// The role assignments the privileges result from.
+    pub roles: Vec<PermissionRoleOrigin>,
+}
+
+#[api(
+    properties: {
+        "auth-id": {
+            type: Authid,
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Effective permissions of a user or API token on an ACL path, grouped by common operations.
+pub struct EffectivePermissions {
+    pub auth_id: Authid,
+    /// True for the superuser, which has all privileges regardless of ACLs.
+    pub superuser: bool,
+    /// Create new backups (Datastore.Backup), restricted to owned backup groups.
+    pub backup: bool,
+    /// Read any backup contents (Datastore.Read).
+    pub read: bool,
+    /// Modify the datastore and its contents (Datastore.Modify).
+    pub modify: bool,
+    /// Prune backups (Datastore.Modify, or Datastore.Prune restricted to owned backup groups).
+    pub prune: bool,
+    /// Verify backups (Datastore.Verify).
+    pub verify: bool,
+    /// Know about the datastore and list its contents (Datastore.Audit).
+    pub audit: bool,
+    /// True if the privileges are granted on a parent path and propagated down.
+    pub inherited: bool,
+    /// The ACL path the effective role assignments are defined on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+impl EffectivePermissions {
+    /// Group the privilege bitmap `privs` of `auth_id` into the common operations.
+    pub fn from_privs(auth_id: Authid, superuser: bool, privs: u64) -> Self {
+        Self {
+            auth_id,
+            superuser,
+            backup: privs & PRIV_DATASTORE_BACKUP != 0,
+            read: privs & PRIV_DATASTORE_READ != 0,
+            modify: privs & PRIV_DATASTORE_MODIFY != 0,
+            prune: privs & (PRIV_DATASTORE_MODIFY | PRIV_DATASTORE_PRUNE) != 0,
+            verify: privs & PRIV_DATASTORE_VERIFY != 0,
+            audit: privs & PRIV_DATASTORE_AUDIT != 0,
+            inherited: false,
+            origin: None,
+        }
+    }
+}
@@ -5,17 +5,22 @@ use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
 
 use proxmox_schema::{
-    api, const_regex, ApiStringFormat, ApiType, ArraySchema, EnumEntry, IntegerSchema, ReturnType,
-    Schema, StringSchema, Updater, UpdaterType,
+    api, const_regex, ApiStringFormat, ApiType, ArraySchema, BooleanSchema, EnumEntry,
+    IntegerSchema, ReturnType, Schema, StringSchema, Updater, UpdaterType,
 };
 
 use crate::{
-    Authid, CryptMode, Fingerprint, GroupFilter, MaintenanceMode, Userid,
-    DATASTORE_NOTIFY_STRING_SCHEMA, GC_SCHEDULE_SCHEMA, PROXMOX_SAFE_ID_FORMAT,
-    PRUNE_SCHEDULE_SCHEMA, SHA256_HEX_REGEX, SINGLE_LINE_COMMENT_SCHEMA, UPID,
+    Authid, CryptMode, Fingerprint, GroupFilter, MaintenanceMode, MaintenanceType, Userid,
+    DATASTORE_NOTIFY_STRING_SCHEMA, DATASTORE_NOTIFY_TARGET_LIST_SCHEMA, GC_SCHEDULE_SCHEMA,
+    HTTP_URL_SCHEMA, PROXMOX_SAFE_ID_FORMAT, PRUNE_SCHEDULE_SCHEMA, SHA256_HEX_REGEX,
+    SINGLE_LINE_COMMENT_SCHEMA, UPID,
 };
 
 const_regex! {
+    pub S3_BUCKET_NAME_REGEX = r"^[a-z0-9][a-z0-9.\-]*[a-z0-9]$";
+
+    pub S3_REGION_REGEX = r"^[a-z0-9][a-z0-9\-]*$";
+
     pub BACKUP_NAMESPACE_REGEX = concat!(r"^", BACKUP_NS_RE!(), r"$");
 
     pub BACKUP_TYPE_REGEX = concat!(r"^(", BACKUP_TYPE_RE!(), r")$");
@@ -44,6 +49,19 @@ pub const DIR_NAME_SCHEMA: Schema = StringSchema::new("Directory name")
     .max_length(4096)
     .schema();
 
+pub const EXTRA_CHUNK_DIR_SCHEMA: Schema =
+    StringSchema::new("Absolute path of an additional chunk directory.")
+        .min_length(1)
+        .max_length(4096)
+        .schema();
+
+pub const EXTRA_CHUNK_DIR_LIST_SCHEMA: Schema = ArraySchema::new(
+    "Additional chunk directories. The chunks are distributed over the datastore's own chunk \
+    directory and these directories by their digest prefix.",
+    &EXTRA_CHUNK_DIR_SCHEMA,
+)
+.schema();
+
 pub const BACKUP_ARCHIVE_NAME_SCHEMA: Schema = StringSchema::new("Backup archive name.")
     .format(&PROXMOX_SAFE_ID_FORMAT)
     .schema();
@@ -96,12 +114,26 @@ IntegerSchema::new("How many levels of namespaces should be operated on (0 == no
     .maximum(MAX_NAMESPACE_DEPTH as isize)
     .schema();
 
+pub const LOCK_WAIT_SCHEMA: Schema = IntegerSchema::new(
+    "Wait up to this many seconds for backup group and snapshot locks held by other tasks, \
+    instead of failing immediately.",
+)
+.minimum(0)
+.maximum(3600)
+.schema();
+
 pub const DATASTORE_SCHEMA: Schema = StringSchema::new("Datastore name.")
     .format(&PROXMOX_SAFE_ID_FORMAT)
     .min_length(3)
     .max_length(32)
     .schema();
 
+pub const REUSE_DATASTORE_SCHEMA: Schema = BooleanSchema::new(
+    "Adopt an existing chunk store at the datastore path instead of creating a new one.",
+)
+.default(false)
+.schema();
+
 pub const CHUNK_DIGEST_SCHEMA: Schema = StringSchema::new("Chunk digest (SHA256).")
     .format(&CHUNK_DIGEST_FORMAT)
     .schema();
@@ -167,6 +199,42 @@ pub enum ChunkOrder {
     /// Iterate chunks in inode order
     #[default]
     Inode,
+    /// Iterate chunks in random order
+    Random,
+}
+
+#[api]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Compression of chunk data on the wire in backup and reader protocol sessions.
+///
+/// Independent of the chunk compression, chunks are stored as they were encoded by the client.
+pub enum WireCompression {
+    /// Transfer chunks as they are.
+    #[default]
+    None,
+    /// LZ4 block compression, fast with a moderate ratio.
+    Lz4,
+    /// Zstandard compression, slower but with a better ratio.
+    Zstd,
+}
+serde_plain::derive_display_from_serialize!(WireCompression);
+serde_plain::derive_fromstr_from_deserialize!(WireCompression);
+
+#[api(
+    properties: {
+        wire: {
+            type: WireCompression,
+            optional: true,
+        },
+    },
+)]
+#[derive(Default, Serialize, Deserialize)]
+/// Client side compression options.
+pub struct ClientCompressionOptions {
+    /// Preferred compression of chunk data on the wire, used if the server supports it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wire: Option<WireCompression>,
 }
 
 #[api]
@@ -200,12 +268,41 @@ pub enum DatastoreFSyncLevel {
     Filesystem,
 }
 
+serde_plain::derive_display_from_serialize!(DatastoreFSyncLevel);
+
+/// Default lookback window for the estimation of the full date, in days.
+pub const DEFAULT_ESTIMATION_WINDOW: u64 = 30;
+
+/// Default time in minutes a chunk must be unused before garbage collection removes it (24 hours
+/// plus 5 minutes safety gap).
+pub const GC_GRACE_DEFAULT: u64 = 24 * 60 + 5;
+/// Minimum of the `gc-grace` tuning option, in minutes.
+pub const GC_GRACE_MIN: u64 = 60;
+
 #[api(
     properties: {
         "chunk-order": {
             type: ChunkOrder,
             optional: true,
         },
+        "estimation-window": {
+            optional: true,
+            minimum: 1,
+            maximum: 365,
+            default: DEFAULT_ESTIMATION_WINDOW as isize,
+        },
+        "estimation-damping": {
+            optional: true,
+            minimum: 0,
+            maximum: 50,
+            default: 0,
+        },
+        "gc-grace": {
+            optional: true,
+            minimum: GC_GRACE_MIN as isize,
+            maximum: 30 * 24 * 60,
+            default: GC_GRACE_DEFAULT as isize,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Default)]
@@ -217,14 +314,130 @@ pub struct DatastoreTuning {
     pub chunk_order: Option<ChunkOrder>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sync_level: Option<DatastoreFSyncLevel>,
+    /// Abort garbage collection if the access time self-test fails (default true).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_atime_check: Option<bool>,
+    /// Days of usage history used to estimate when the datastore is full.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimation_window: Option<u64>,
+    /// Percentage of the steepest usage changes, for example from pruning, which are ignored
+    /// when estimating when the datastore is full.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimation_damping: Option<u64>,
+    /// Minutes a chunk must be unused before garbage collection removes it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_grace: Option<u64>,
 }
 
+pub const TRASH_RETENTION_SCHEMA: Schema = IntegerSchema::new(
+    "Keep deleted snapshots in the trash for this many hours before purging them, 0 deletes \
+    them immediately.",
+)
+.minimum(0)
+.maximum(24 * 365)
+.default(0)
+.schema();
+
 pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore tuning options")
     .format(&ApiStringFormat::PropertyString(
         &DatastoreTuning::API_SCHEMA,
     ))
     .schema();
 
+#[api]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Storage backend for the chunks of a datastore.
+pub enum DatastoreBackendType {
+    /// Chunk files in the chunk directories of the datastore.
+    #[default]
+    Filesystem,
+    /// Objects in a bucket of an S3-compatible object store. The chunk directories only keep an
+    /// empty marker file per chunk, used for lookups and by garbage collection.
+    S3,
+}
+serde_plain::derive_display_from_serialize!(DatastoreBackendType);
+
+pub const S3_BUCKET_NAME_SCHEMA: Schema = StringSchema::new("Name of the S3 bucket.")
+    .format(&ApiStringFormat::Pattern(&S3_BUCKET_NAME_REGEX))
+    .min_length(3)
+    .max_length(63)
+    .schema();
+
+pub const S3_REGION_SCHEMA: Schema = StringSchema::new("Region of the S3 bucket.")
+    .format(&ApiStringFormat::Pattern(&S3_REGION_REGEX))
+    .min_length(1)
+    .max_length(64)
+    .schema();
+
+#[api(
+    properties: {
+        type: {
+            type: DatastoreBackendType,
+            optional: true,
+        },
+        endpoint: {
+            schema: HTTP_URL_SCHEMA,
+            optional: true,
+        },
+        bucket: {
+            schema: S3_BUCKET_NAME_SCHEMA,
+            optional: true,
+        },
+        region: {
+            schema: S3_REGION_SCHEMA,
+            optional: true,
+        },
+        "key-id": {
+            optional: true,
+            min_length: 1,
+            max_length: 128,
+        },
+        "secret-file": {
+            optional: true,
+            min_length: 1,
+            max_length: 4096,
+        },
+        "path-style": {
+            optional: true,
+            default: false,
+        },
+    },
+    default_key: "type",
+)]
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+/// Storage backend of a datastore
+pub struct DatastoreBackendConfig {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub ty: Option<DatastoreBackendType>,
+    /// Endpoint of the object store, for example 'https://s3.eu-central-1.amazonaws.com'.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// Defaults to 'us-east-1'.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// Access key ID used for the requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Absolute path of a file containing the secret access key, it must be readable by the
+    /// backup user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_file: Option<String>,
+    /// Address the bucket in the request path instead of the host name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_style: Option<bool>,
+}
+
+pub const DATASTORE_BACKEND_STRING_SCHEMA: Schema =
+    StringSchema::new("Storage backend of the datastore chunks.")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreBackendConfig::API_SCHEMA,
+        ))
+        .schema();
+
 #[api(
     properties: {
         name: {
@@ -241,6 +454,14 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             schema: DATASTORE_NOTIFY_STRING_SCHEMA,
         },
+        "notify-target": {
+            optional: true,
+            schema: DATASTORE_NOTIFY_TARGET_LIST_SCHEMA,
+        },
+        "extra-chunk-dir": {
+            optional: true,
+            schema: EXTRA_CHUNK_DIR_LIST_SCHEMA,
+        },
         comment: {
             optional: true,
             schema: SINGLE_LINE_COMMENT_SCHEMA,
@@ -261,10 +482,18 @@ pub const DATASTORE_TUNING_STRING_SCHEMA: Schema = StringSchema::new("Datastore
             optional: true,
             type: bool,
         },
+        "trash-retention": {
+            optional: true,
+            schema: TRASH_RETENTION_SCHEMA,
+        },
         tuning: {
             optional: true,
             schema: DATASTORE_TUNING_STRING_SCHEMA,
         },
+        backend: {
+            optional: true,
+            schema: DATASTORE_BACKEND_STRING_SCHEMA,
+        },
         "maintenance-mode": {
             optional: true,
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
@@ -298,6 +527,9 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub verify_new: Option<bool>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trash_retention: Option<u64>,
+
     /// Send job email notification to this user
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notify_user: Option<Userid>,
@@ -306,10 +538,23 @@ pub struct DataStoreConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notify: Option<String>,
 
+    /// Notification targets per event type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_target: Option<Vec<String>>,
+
+    /// Additional chunk directories the chunks are sharded over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_chunk_dir: Option<Vec<String>>,
+
     /// Datastore tuning options
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tuning: Option<String>,
 
+    /// Storage backend of the chunks, can only be set on creation
+    #[updater(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+
     /// Maintenance mode, type is either 'offline' or 'read-only', message should be enclosed in "
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance_mode: Option<String>,
@@ -325,9 +570,13 @@ impl DataStoreConfig {
             prune_schedule: None,
             keep: Default::default(),
             verify_new: None,
+            trash_retention: None,
             notify_user: None,
             notify: None,
+            notify_target: None,
+            extra_chunk_dir: None,
             tuning: None,
+            backend: None,
             maintenance_mode: None,
         }
     }
@@ -353,7 +602,11 @@ impl DataStoreConfig {
             optional: true,
             format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
             type: String,
-        }
+        },
+        "maintenance-type": {
+            optional: true,
+            type: MaintenanceType,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -365,6 +618,9 @@ pub struct DataStoreListItem {
     /// If the datastore is in maintenance mode, information about it
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintenance: Option<String>,
+    /// The parsed type of the maintenance mode, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance_type: Option<MaintenanceType>,
 }
 
 #[api(
@@ -376,6 +632,10 @@ pub struct DataStoreListItem {
             type: CryptMode,
             optional: true,
         },
+        excludes: {
+            type: ArchiveExcludes,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -389,6 +649,31 @@ pub struct BackupContent {
     /// Archive size (from backup manifest).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<u64>,
+    /// Exclude patterns used for the archive, only returned when listing the files of a snapshot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excludes: Option<ArchiveExcludes>,
+}
+
+#[api(
+    properties: {
+        patterns: {
+            type: Array,
+            items: {
+                type: String,
+                description: "Exclude pattern in '.pxarexclude' syntax.",
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Exclude patterns in effect when a file archive was created (from backup manifest).
+pub struct ArchiveExcludes {
+    /// Command line exclude patterns followed by the ones read from '.pxarexclude' files.
+    pub patterns: Vec<String>,
+    /// Set if the list was cut off to keep the manifest small.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
 }
 
 #[api()]
@@ -410,6 +695,9 @@ pub enum VerifyState {
         state: {
             type: VerifyState,
         },
+        time: {
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -419,6 +707,118 @@ pub struct SnapshotVerifyState {
     pub upid: UPID,
     /// State of the verification. Enum.
     pub state: VerifyState,
+    /// Time the verification finished (not recorded by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<i64>,
+}
+
+#[api()]
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Progress of a verification task.
+pub struct VerifyProgress {
+    /// Progress of the whole task in percent.
+    pub percentage: f64,
+    /// Number of snapshots to verify, as far as known yet (groups are listed one by one).
+    pub snapshots_total: u64,
+    /// Number of snapshots verified or skipped so far.
+    pub snapshots_done: u64,
+    /// Number of chunks verified so far.
+    pub chunks_verified: u64,
+    /// Number of bytes read from the chunk store so far.
+    pub bytes_read: u64,
+    /// The snapshot currently being verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_snapshot: Option<String>,
+}
+
+#[api(
+    properties: {
+        state: {
+            type: VerifyState,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Verification result of a single snapshot.
+pub struct VerifySnapshotResult {
+    /// The snapshot, including its namespace.
+    pub snapshot: String,
+    pub state: VerifyState,
+    /// The errors encountered, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api(
+    properties: {
+        digests: {
+            type: Array,
+            items: {
+                description: "Chunk digest.",
+                type: String,
+            },
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A snapshot referencing chunks which were found to be bad.
+pub struct VerifyBadChunkReference {
+    /// The snapshot, including its namespace.
+    pub snapshot: String,
+    /// The digests of the bad chunks referenced by the snapshot.
+    pub digests: Vec<String>,
+}
+
+#[api(
+    properties: {
+        progress: {
+            type: VerifyProgress,
+        },
+        results: {
+            type: Array,
+            items: {
+                type: VerifySnapshotResult,
+            },
+        },
+        "bad-chunks": {
+            type: Array,
+            optional: true,
+            items: {
+                description: "Chunk digest.",
+                type: String,
+            },
+        },
+        "bad-chunk-report": {
+            type: Array,
+            optional: true,
+            items: {
+                type: VerifyBadChunkReference,
+            },
+        },
+    },
+)]
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Status of a running or finished verification task.
+pub struct VerifyTaskStatus {
+    /// Whether the task is still running.
+    pub running: bool,
+    pub progress: VerifyProgress,
+    /// Results of the snapshots verified so far. Only a limited number is kept, failed
+    /// snapshots take precedence over successfully verified ones.
+    pub results: Vec<VerifySnapshotResult>,
+    /// Number of snapshot results which were left out of `results`.
+    #[serde(default)]
+    pub omitted_results: u64,
+    /// Digests of the chunks renamed to `.bad` by this task.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bad_chunks: Vec<String>,
+    /// Snapshots referencing bad chunks, set once the datastore-wide search finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bad_chunk_report: Option<Vec<VerifyBadChunkReference>>,
 }
 
 /// A namespace provides a logical separation between backup groups from different domains
@@ -658,6 +1058,15 @@ impl BackupNamespace {
         Ok(())
     }
 
+    /// Returns the ACL path components of this namespace on datastore `store`.
+    ///
+    /// Example:
+    /// ```
+    /// # use pbs_api_types::BackupNamespace;
+    /// let ns: BackupNamespace = "a/b".parse().unwrap();
+    /// assert_eq!(ns.acl_path("store1"), ["datastore", "store1", "a", "b"]);
+    /// assert_eq!(BackupNamespace::root().acl_path("store1"), ["datastore", "store1"]);
+    /// ```
     pub fn acl_path<'a>(&'a self, store: &'a str) -> Vec<&'a str> {
         let mut path: Vec<&str> = vec!["datastore", store];
 
@@ -1075,6 +1484,10 @@ impl std::str::FromStr for BackupPart {
             schema: SINGLE_LINE_COMMENT_SCHEMA,
             optional: true,
         },
+        "notes-digest": {
+            type: String,
+            optional: true,
+        },
         verification: {
             type: SnapshotVerifyState,
             optional: true,
@@ -1092,6 +1505,9 @@ impl std::str::FromStr for BackupPart {
             type: Authid,
             optional: true,
         },
+        "upload-info": {
+            type: SnapshotUploadInfo,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
@@ -1103,6 +1519,9 @@ pub struct SnapshotListItem {
     /// The first line from manifest "notes"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
+    /// SHA-256 digest of the full manifest "notes", if there are any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes_digest: Option<String>,
     /// The result of the last run verify task
     #[serde(skip_serializing_if = "Option::is_none")]
     pub verification: Option<SnapshotVerifyState>,
@@ -1120,6 +1539,77 @@ pub struct SnapshotListItem {
     /// Protection from prunes
     #[serde(default)]
     pub protected: bool,
+    /// Client and upload information, only available for snapshots created by newer servers
+    #[serde(flatten)]
+    pub upload_info: SnapshotUploadInfo,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        backup: {
+            type: BackupDir,
+            flatten: true,
+        },
+        owner: {
+            type: Authid,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A deleted snapshot in the trash of a datastore.
+pub struct TrashItem {
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupDir,
+    /// Time the snapshot was deleted (epoch)
+    pub deleted: i64,
+    /// Time the snapshot will be purged (epoch), according to the current trash retention
+    pub purge_after: i64,
+    /// The owner of the group at the time of deletion
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    /// Protection from prunes
+    #[serde(default)]
+    pub protected: bool,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Client and upload information, recorded in the manifest when a backup gets finished.
+pub struct SnapshotUploadInfo {
+    /// Version of the backup client, as reported by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_version: Option<String>,
+    /// Hostname of the backup source, as reported by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_hostname: Option<String>,
+    /// Duration of the backup in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i64>,
+    /// Bytes uploaded by the client
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_size: Option<u64>,
+    /// Bytes referenced by the snapshot but not uploaded, as the chunks already existed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reused_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A single line of a streamed snapshot list.
+pub enum SnapshotListRecord {
+    /// A listed snapshot.
+    Data(SnapshotListItem),
+    /// Listing failed after the response was started, this is always the last record.
+    Error(String),
 }
 
 #[api(
@@ -1158,30 +1648,189 @@ pub struct GroupListItem {
     /// The first line from group "notes"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
-}
-
-#[api()]
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "kebab-case")]
-/// Basic information about a backup namespace.
-pub struct NamespaceListItem {
-    /// A backup namespace
-    pub ns: BackupNamespace,
-
-    // TODO?
-    //pub group_count: u64,
-    //pub ns_count: u64,
-    /// The first line from the namespace's "notes"
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub comment: Option<String>,
+    /// Whether the group "notes" contain more than the first line
+    #[serde(default)]
+    pub notes_truncated: bool,
 }
 
 #[api(
     properties: {
-        "backup": { type: BackupDir },
+        "backup": { type: BackupGroup },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        owner: {
+            type: Authid,
+            optional: true,
+        },
     },
 )]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of changing the owner of a single backup group.
+pub struct GroupOwnerChangeResult {
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    /// The namespace the group is located in, if not the root namespace
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    /// The owner of the group before the change, if it could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    /// Whether the owner was changed
+    pub success: bool,
+    /// The reason why the owner was not changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api()]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of updating the protection of multiple snapshots.
+pub struct ProtectionBulkResult {
+    /// Number of snapshots whose protection was changed
+    pub changed: u64,
+    /// Number of snapshots which already had the requested protection
+    pub unchanged: u64,
+    /// Number of snapshots skipped because they are still being written or in use
+    pub skipped: u64,
+}
+
+#[api()]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// Kind of inconsistency found in the directory structure of a datastore.
+pub enum ScrubMetaIssue {
+    /// Group directory with snapshots but without owner file
+    MissingOwner,
+    /// Group directory without any snapshot
+    EmptyGroup,
+    /// Snapshot directory without manifest
+    MissingManifest,
+    /// Namespace directories below the maximal namespace depth
+    NamespaceTooDeep,
+}
+
+#[api(
+    properties: {
+        issue: { type: ScrubMetaIssue },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// An inconsistency found in the directory structure of a datastore.
+pub struct ScrubMetaEntry {
+    /// Path relative to the datastore base directory
+    pub path: String,
+    pub issue: ScrubMetaIssue,
+    /// Whether the inconsistency got repaired
+    pub repaired: bool,
+    /// Details about the inconsistency or why it was not repaired
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[api()]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Basic information about a backup namespace.
+pub struct NamespaceListItem {
+    /// A backup namespace
+    pub ns: BackupNamespace,
+
+    // TODO?
+    //pub group_count: u64,
+    //pub ns_count: u64,
+    /// The first line from the namespace's "notes"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// The quota of the namespace in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+
+    /// The logical usage of the namespace and its children in bytes, as recorded by the last
+    /// garbage collection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<u64>,
+}
+
+#[api()]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Quota and recorded usage of a backup namespace.
+pub struct NamespaceQuotaStatus {
+    /// The quota of the namespace in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+
+    /// The logical usage of the namespace and its children in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<u64>,
+
+    /// Time when the usage was recorded (epoch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_time: Option<i64>,
+}
+
+#[api(
+    properties: {
+        source: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Automatic verification of new snapshots in a backup namespace.
+pub struct NamespaceVerifyNewStatus {
+    /// The setting stored on the namespace itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_new: Option<bool>,
+
+    /// Whether new snapshots in the namespace are verified
+    pub effective: bool,
+
+    /// The namespace the effective setting is configured on, not set if it is the datastore's
+    /// 'verify-new' setting
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<BackupNamespace>,
+}
+
+#[api(
+    properties: {
+        "skipped-protected": {
+            type: Array,
+            items: {
+                type: String,
+                description: "Protected snapshot, including its namespace.",
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Summary of a namespace deletion.
+pub struct NamespaceDeleteSummary {
+    /// Number of removed backup groups
+    pub removed_groups: u64,
+    /// Number of removed snapshots
+    pub removed_snapshots: u64,
+    /// Number of removed namespaces
+    pub removed_namespaces: u64,
+    /// Protected snapshots which were kept, together with their groups and namespaces
+    pub skipped_protected: Vec<String>,
+}
+
+#[api(
+    properties: {
+        "backup": { type: BackupDir },
+    },
+)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 /// Prune result.
 pub struct PruneListItem {
@@ -1190,6 +1839,14 @@ pub struct PruneListItem {
 
     /// Keep snapshot
     pub keep: bool,
+
+    /// Snapshot is protected and will always be kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected: Option<bool>,
+
+    /// Reason why a snapshot is kept regardless of the keep options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
 }
 
 #[api(
@@ -1235,12 +1892,220 @@ pub struct TypeCounts {
     pub snapshots: u64,
 }
 
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+        },
+        counts: {
+            type: Counts,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+/// Group/Snapshot counts of a single namespace.
+pub struct NamespaceCounts {
+    pub ns: BackupNamespace,
+    pub counts: Counts,
+}
+
+#[api(
+    properties: {
+        owner: {
+            type: Authid,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Groups, snapshots and logical size owned by a single backup owner.
+pub struct OwnerUsage {
+    pub owner: Authid,
+    /// Number of backup groups.
+    pub groups: u64,
+    /// Number of snapshots.
+    pub snapshots: u64,
+    /// Summed logical size of all snapshots, as recorded in their manifests.
+    pub size: u64,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+        },
+        "max-depth": {
+            schema: NS_MAX_DEPTH_SCHEMA,
+        },
+        owners: {
+            type: Array,
+            items: {
+                type: OwnerUsage,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Usage of a namespace subtree per backup owner, sorted by size.
+pub struct OwnerReport {
+    pub ns: BackupNamespace,
+    pub max_depth: usize,
+    /// Time the report was generated.
+    pub time: i64,
+    pub owners: Vec<OwnerUsage>,
+}
+
+#[api(
+    properties: {
+        report: {
+            type: OwnerReport,
+            optional: true,
+        },
+        upid: {
+            type: UPID,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+/// An owner report, or the task generating it.
+pub struct OwnerReportResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<OwnerReport>,
+    /// The task generating the report, the report can be retrieved once it finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+}
+
+/// Default of the maximum age of the newest snapshot of healthy groups, in seconds.
+pub const GROUP_HEALTH_MAX_AGE_DEFAULT: i64 = 24 * 3600;
+
+#[api()]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Why a backup group is reported as unhealthy.
+pub enum GroupHealthProblem {
+    /// There is no finished snapshot newer than the maximum age.
+    Outdated,
+    /// The verification of the newest finished snapshot failed.
+    VerifyFailed,
+}
+
+#[api(
+    properties: {
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+        backup: {
+            type: BackupGroup,
+            flatten: true,
+        },
+        problem: {
+            type: GroupHealthProblem,
+        },
+    },
+)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A backup group without a recent, successful backup.
+pub struct UnhealthyGroup {
+    #[serde(default, skip_serializing_if = "BackupNamespace::is_root")]
+    pub ns: BackupNamespace,
+    #[serde(flatten)]
+    pub backup: BackupGroup,
+    /// Time of the newest finished snapshot (epoch), if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_backup: Option<i64>,
+    pub problem: GroupHealthProblem,
+}
+
+#[api(
+    properties: {
+        groups: {
+            type: Array,
+            items: {
+                type: UnhealthyGroup,
+            },
+        },
+    },
+)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Backup groups of a namespace subtree without a recent, successful backup.
+pub struct DataStoreHealth {
+    /// Number of checked groups.
+    pub checked: u64,
+    /// Number of groups skipped because of the ignore list.
+    pub ignored: u64,
+    /// Number of groups without a finished snapshot newer than the maximum age.
+    pub outdated: u64,
+    /// Number of groups whose newest finished snapshot failed verification.
+    pub verify_failed: u64,
+    /// The unhealthy groups.
+    pub groups: Vec<UnhealthyGroup>,
+}
+
+#[api()]
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// The phase a garbage collection is currently in.
+pub enum GarbageCollectionPhase {
+    /// Phase 1, marking all chunks referenced by index files as used.
+    #[default]
+    IndexScan,
+    /// Phase 2, removing unused chunks from the chunk store.
+    Sweep,
+}
+
+#[api(
+    properties: {
+        phase: {
+            type: GarbageCollectionPhase,
+        },
+    },
+)]
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Progress of a currently running garbage collection.
+pub struct GarbageCollectionProgress {
+    pub phase: GarbageCollectionPhase,
+    /// Progress of the current phase in percent.
+    pub percentage: usize,
+    /// Number of index files processed so far.
+    pub index_files_processed: usize,
+    /// Total number of index files to process.
+    pub index_files_total: usize,
+    /// Number of chunks touched (marked as used) so far.
+    pub chunks_touched: u64,
+    /// Sum of removed bytes so far.
+    pub removed_bytes: u64,
+    /// Estimated end time of the current phase (epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<i64>,
+}
+
 #[api(
     properties: {
         "upid": {
             optional: true,
             type: UPID,
         },
+        running: {
+            optional: true,
+            default: false,
+        },
+        progress: {
+            type: GarbageCollectionProgress,
+            optional: true,
+        },
+        resumed: {
+            optional: true,
+            default: false,
+        },
+        "gc-grace": {
+            optional: true,
+        },
     },
 )]
 #[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
@@ -1248,6 +2113,12 @@ pub struct TypeCounts {
 /// Garbage collection status.
 pub struct GarbageCollectionStatus {
     pub upid: Option<String>,
+    /// Whether a garbage collection is currently running.
+    #[serde(default)]
+    pub running: bool,
+    /// Progress of the currently running garbage collection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<GarbageCollectionProgress>,
     /// Number of processed index files.
     pub index_file_count: usize,
     /// Sum of bytes referred by index files.
@@ -1268,6 +2139,51 @@ pub struct GarbageCollectionStatus {
     pub removed_bad: usize,
     /// Number of chunks still marked as .bad after garbage collection.
     pub still_bad: usize,
+    /// Whether the run resumed the mark phase of an interrupted garbage collection.
+    #[serde(default)]
+    pub resumed: bool,
+    /// Grace period in minutes used by the run, chunks unused for a shorter time were kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_grace: Option<u64>,
+}
+
+#[api]
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of the access time self-test run before garbage collection.
+pub struct GcAtimeCheckStatus {
+    /// Time of the check (epoch).
+    pub time: i64,
+    /// Whether access time updates work as required by garbage collection.
+    pub ok: bool,
+    /// Whether a failed check aborted the garbage collection.
+    pub enforced: bool,
+    /// Reason of a failed check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+        },
+        "last-check": {
+            type: GcAtimeCheckStatus,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Access time self-test state of a datastore.
+pub struct GcAtimeCheckListItem {
+    pub store: String,
+    /// Whether a failed check aborts garbage collection, see the `gc-atime-check` tuning option.
+    pub enforced: bool,
+    /// Result of the last check, unset if no garbage collection ran yet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_check: Option<GcAtimeCheckStatus>,
 }
 
 #[api(
@@ -1280,6 +2196,17 @@ pub struct GarbageCollectionStatus {
             type: Counts,
             optional: true,
         },
+        "namespace-counts": {
+            type: Array,
+            optional: true,
+            items: {
+                type: NamespaceCounts,
+            },
+        },
+        "active-operations": {
+            type: ActiveOperationCounts,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -1298,6 +2225,81 @@ pub struct DataStoreStatus {
     /// Group/Snapshot counts
     #[serde(skip_serializing_if = "Option::is_none")]
     pub counts: Option<Counts>,
+    /// Group/Snapshot counts per namespace, only namespaces with accessible groups are included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_counts: Option<Vec<NamespaceCounts>>,
+    /// Next scheduled garbage collection run (epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_gc_run: Option<i64>,
+    /// Next scheduled run of any enabled prune job of the datastore (epoch).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_prune_run: Option<i64>,
+    /// Number of currently active operations on the datastore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_operations: Option<ActiveOperationCounts>,
+}
+
+#[api()]
+#[derive(Clone, Copy, Default, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Number of active read and write operations on a datastore.
+pub struct ActiveOperationCounts {
+    /// Active read operations, for example restores, verifications or sync sources.
+    pub read: i64,
+    /// Active write operations, for example backups, prunes or garbage collection.
+    pub write: i64,
+}
+
+#[api()]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Chunk deduplication statistics of a backup group.
+///
+/// All sizes are logical (uncompressed) chunk sizes.
+pub struct GroupDedupStats {
+    /// Time the statistics were computed (epoch).
+    pub computed: i64,
+    /// Number of index files of the group.
+    pub index_file_count: usize,
+    /// Sum of bytes referenced by the index files of the group.
+    pub referenced_bytes: u64,
+    /// Number of distinct chunks referenced by the group.
+    pub chunk_count: usize,
+    /// Bytes of distinct chunks only referenced by this group.
+    pub unique_bytes: u64,
+    /// Number of distinct chunks only referenced by this group.
+    pub unique_chunks: usize,
+    /// Bytes of distinct chunks also referenced by other groups.
+    pub shared_bytes: u64,
+    /// Number of distinct chunks also referenced by other groups.
+    pub shared_chunks: usize,
+}
+
+#[api(
+    properties: {
+        upid: {
+            optional: true,
+            type: UPID,
+        },
+        stats: {
+            type: GroupDedupStats,
+            optional: true,
+        },
+    },
+)]
+#[derive(Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Cached deduplication statistics of a backup group, or the task computing them.
+pub struct GroupDedupStatsResult {
+    /// Task computing new statistics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// Cached statistics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<GroupDedupStats>,
+    /// Age of the cached statistics in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age: Option<i64>,
 }
 
 #[api(
@@ -1340,8 +2342,9 @@ pub struct DataStoreStatusListItem {
     pub history_delta: Option<u64>,
     /// Estimation of the UNIX epoch when the storage will be full.
     /// It's calculated via a simple Linear Regression (Least Squares) over the RRD data of the
-    /// last Month. Missing if not enough data points are available yet. An estimate in the past
-    /// means that usage is declining or not changing.
+    /// last Month, or the window configured in the datastore tuning options. Missing if not enough
+    /// data points are available yet. An estimate in the past means that usage is declining or
+    /// not changing.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub estimated_full_date: Option<i64>,
     /// An error description, for example, when the datastore could not be looked up
@@ -1352,6 +2355,28 @@ pub struct DataStoreStatusListItem {
     pub gc_status: Option<GarbageCollectionStatus>,
 }
 
+#[api]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Estimation when a datastore is full, including the quality of the estimation.
+pub struct DataStoreUsageEstimate {
+    /// Days of usage history used for the estimation.
+    pub window: u64,
+    /// Percentage of the steepest usage changes which were ignored.
+    pub damping: u64,
+    /// Number of usage data points used for the estimation.
+    pub points: u64,
+    /// Estimation of the UNIX epoch when the storage will be full. Missing if not enough data
+    /// points are available yet. An estimate in the past means that usage is declining or not
+    /// changing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_full_date: Option<i64>,
+    /// Coefficient of determination (r²) of the linear fit, between 0.0 and 1.0. Values close
+    /// to 1.0 mean that the usage grew steadily, so the estimate is reliable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r_squared: Option<f64>,
+}
+
 impl DataStoreStatusListItem {
     pub fn empty(store: &str, err: Option<String>) -> Self {
         DataStoreStatusListItem {
@@ -1378,6 +2403,15 @@ pub const ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE: ReturnType = ReturnType {
     .schema(),
 };
 
+pub const ADMIN_DATASTORE_LIST_TRASH_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new(
+        "Returns the list of snapshots in the trash.",
+        &TrashItem::API_SCHEMA,
+    )
+    .schema(),
+};
+
 pub const ADMIN_DATASTORE_LIST_SNAPSHOT_FILES_RETURN_TYPE: ReturnType = ReturnType {
     optional: false,
     schema: &ArraySchema::new(
@@ -1387,6 +2421,15 @@ pub const ADMIN_DATASTORE_LIST_SNAPSHOT_FILES_RETURN_TYPE: ReturnType = ReturnTy
     .schema(),
 };
 
+pub const ADMIN_DATASTORE_VERIFY_HISTORY_RETURN_TYPE: ReturnType = ReturnType {
+    optional: false,
+    schema: &ArraySchema::new(
+        "Returns the recorded verification results of a backup snapshot, oldest first.",
+        &SnapshotVerifyState::API_SCHEMA,
+    )
+    .schema(),
+};
+
 pub const ADMIN_DATASTORE_LIST_GROUPS_RETURN_TYPE: ReturnType = ReturnType {
     optional: false,
     schema: &ArraySchema::new(
@@ -1478,3 +2521,70 @@ pub fn print_store_and_ns(store: &str, ns: &BackupNamespace) -> String {
         format!("datastore '{}', namespace '{}'", store, ns)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_filters(filters: &[&str]) -> Vec<GroupFilter> {
+        filters.iter().map(|f| f.parse().unwrap()).collect()
+    }
+
+    fn group(group: &str) -> BackupGroup {
+        group.parse().unwrap()
+    }
+
+    #[test]
+    fn test_group_filter_include_exclude() {
+        let filters = parse_filters(&["type:vm", "exclude:regex:^vm/1.*$"]);
+
+        assert!(group("vm/200").apply_filters(&filters));
+        assert!(group("vm/900").apply_filters(&filters));
+        assert!(!group("vm/100").apply_filters(&filters));
+        assert!(!group("vm/1234").apply_filters(&filters));
+        assert!(!group("ct/200").apply_filters(&filters));
+    }
+
+    #[test]
+    fn test_group_filter_exclude_only() {
+        let filters = parse_filters(&["exclude:group:vm/900", "exclude:group:vm/901"]);
+
+        assert!(group("vm/100").apply_filters(&filters));
+        assert!(group("ct/900").apply_filters(&filters));
+        assert!(group("host/foo").apply_filters(&filters));
+        assert!(!group("vm/900").apply_filters(&filters));
+        assert!(!group("vm/901").apply_filters(&filters));
+    }
+
+    #[test]
+    fn test_group_filter_exclude_wins_over_include() {
+        let filters = parse_filters(&["group:vm/100", "type:ct", "exclude:type:vm"]);
+
+        assert!(!group("vm/100").apply_filters(&filters));
+        assert!(group("ct/100").apply_filters(&filters));
+        assert!(!group("host/foo").apply_filters(&filters));
+
+        // filter order does not matter
+        let filters = parse_filters(&["exclude:type:vm", "group:vm/100", "type:ct"]);
+
+        assert!(!group("vm/100").apply_filters(&filters));
+        assert!(group("ct/100").apply_filters(&filters));
+    }
+
+    #[test]
+    fn test_group_filter_empty() {
+        assert!(group("vm/100").apply_filters(&[]));
+    }
+
+    #[test]
+    fn test_group_filter_roundtrip() {
+        for filter in ["type:vm", "exclude:group:vm/900", "exclude:regex:^vm/1.*$"] {
+            let parsed: GroupFilter = filter.parse().unwrap();
+            assert_eq!(parsed.to_string(), filter);
+        }
+
+        let parsed: GroupFilter = "include:type:ct".parse().unwrap();
+        assert!(!parsed.is_exclude);
+        assert_eq!(parsed.to_string(), "type:ct");
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::api;
+
+static API_ERROR_HOOK: OnceLock<fn(&ApiError)> = OnceLock::new();
+
+/// Set a function called with every created [`ApiError`].
+///
+/// The API daemons use this to send the code of an error along with the response of the request
+/// it was created for, in the `code` field next to the `message`. Can only be set once.
+pub fn set_api_error_hook(hook: fn(&ApiError)) {
+    let _ = API_ERROR_HOOK.set(hook);
+}
+
+#[api]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// Stable, machine readable classification of API errors.
+///
+/// Clients should use these instead of matching on error messages, which may change between
+/// versions. They are sent in the `code` field of the JSON body of error responses.
+pub enum ApiErrorCode {
+    /// The requested object (datastore, namespace, user, ...) does not exist.
+    NotFound,
+    /// The datastore is in a maintenance mode which does not allow the operation.
+    MaintenanceMode,
+    /// The configuration was modified concurrently, the passed digest does not match.
+    DigestMismatch,
+    /// The user or API token lacks the privileges for the operation.
+    PermissionDenied,
+    /// The object is locked by another operation.
+    LockContention,
+    /// The operation would exceed a configured quota.
+    QuotaExceeded,
+    /// A remote could not be reached.
+    RemoteUnreachable,
+    /// The certificate of a remote does not match the configured fingerprint, or is not
+    /// trusted if none is configured.
+    RemoteCertificate,
+    /// A remote rejected the stored credentials.
+    RemoteAuthFailed,
+    /// Another backup session is writing the same snapshot.
+    BackupInProgress,
+    /// The snapshot already exists.
+    SnapshotExists,
+}
+serde_plain::derive_display_from_serialize!(ApiErrorCode);
+serde_plain::derive_fromstr_from_deserialize!(ApiErrorCode);
+
+impl ApiErrorCode {
+    /// The HTTP status code used for errors with this code.
+    pub fn http_status(self) -> u16 {
+        match self {
+            ApiErrorCode::NotFound => 404,
+            ApiErrorCode::MaintenanceMode => 503,
+            ApiErrorCode::DigestMismatch => 400,
+            ApiErrorCode::PermissionDenied => 403,
+            ApiErrorCode::LockContention => 409,
+            ApiErrorCode::QuotaExceeded => 403,
+            ApiErrorCode::RemoteUnreachable => 502,
+            ApiErrorCode::RemoteCertificate => 502,
+            ApiErrorCode::RemoteAuthFailed => 502,
+            ApiErrorCode::BackupInProgress => 409,
+            ApiErrorCode::SnapshotExists => 409,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// An error with a stable [`ApiErrorCode`].
+///
+/// Can be returned from code without access to the HTTP error types. If it is the error of an API
+/// request, its code is sent along with the response, see [`set_api_error_hook`].
+pub struct ApiError {
+    code: ApiErrorCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ApiErrorCode, message: impl std::fmt::Display) -> Self {
+        let err = Self {
+            code,
+            message: message.to_string(),
+        };
+        if let Some(hook) = API_ERROR_HOOK.get() {
+            hook(&err);
+        }
+        err
+    }
+
+    /// Returns the error code.
+    pub fn code(&self) -> ApiErrorCode {
+        self.code
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_code_format() {
+        assert_eq!(ApiErrorCode::LockContention.to_string(), "lock-contention");
+        assert_eq!(
+            "maintenance-mode".parse::<ApiErrorCode>().unwrap(),
+            ApiErrorCode::MaintenanceMode
+        );
+        assert!("unknown-code".parse::<ApiErrorCode>().is_err());
+
+        let err = ApiError::new(ApiErrorCode::NotFound, "no such datastore 'test'");
+        assert_eq!(err.code(), ApiErrorCode::NotFound);
+        assert_eq!(err.to_string(), "no such datastore 'test'");
+    }
+}
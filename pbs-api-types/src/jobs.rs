@@ -57,6 +57,15 @@ pub const VERIFICATION_SCHEDULE_SCHEMA: Schema =
         .type_text("<calendar-event>")
         .schema();
 
+pub const CONCURRENCY_GROUP_SCHEMA: Schema = StringSchema::new(
+    "Scheduled jobs in the same concurrency group run one after another. Defaults to the \
+    datastore name.",
+)
+.format(&PROXMOX_SAFE_ID_FORMAT)
+.min_length(3)
+.max_length(32)
+.schema();
+
 pub const REMOVE_VANISHED_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
     "Delete vanished backups. This remove the local copy if the remote backup was deleted.",
 )
@@ -85,6 +94,15 @@ pub const REMOVE_VANISHED_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
             optional: true,
             type: Integer,
         },
+        "waiting-for-group": {
+            description: "Concurrency group the job is currently waiting for.",
+            optional: true,
+            type: String,
+        },
+        "last-run-summary": {
+            optional: true,
+            type: JobSummary,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
@@ -99,6 +117,82 @@ pub struct JobScheduleStatus {
     pub last_run_upid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_run_endtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waiting_for_group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_run_summary: Option<JobSummary>,
+}
+
+/// Maximum number of failures recorded in a [`JobSummary`].
+pub const JOB_SUMMARY_MAX_FAILURES: usize = 10;
+
+#[api()]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A single failed item of a job run.
+pub struct JobSummaryFailure {
+    /// The failed item, for example a backup group or snapshot.
+    pub item: String,
+    /// The error the item failed with.
+    pub error: String,
+}
+
+#[api(
+    properties: {
+        failures: {
+            type: Array,
+            items: {
+                type: JobSummaryFailure,
+            },
+        },
+    }
+)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result summary of a job run.
+pub struct JobSummary {
+    /// Number of items processed successfully.
+    pub succeeded: u64,
+    /// Number of failed items.
+    pub failed: u64,
+    /// Number of skipped items.
+    pub skipped: u64,
+    /// Duration of the run in seconds.
+    pub duration: f64,
+    /// Number of bytes transferred or freed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    /// The first failed items, at most `JOB_SUMMARY_MAX_FAILURES`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<JobSummaryFailure>,
+}
+
+impl JobSummary {
+    /// Counts a failed item, the details are only kept for the first
+    /// `JOB_SUMMARY_MAX_FAILURES` failures.
+    pub fn add_failure(&mut self, item: impl Into<String>, error: impl ToString) {
+        self.failed += 1;
+        if self.failures.len() < JOB_SUMMARY_MAX_FAILURES {
+            self.failures.push(JobSummaryFailure {
+                item: item.into(),
+                error: error.to_string(),
+            });
+        }
+    }
+
+    /// Adds the item counts and failures of `other`, the duration and bytes are left untouched.
+    pub fn merge(&mut self, other: JobSummary) {
+        self.succeeded += other.succeeded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        let free = JOB_SUMMARY_MAX_FAILURES.saturating_sub(self.failures.len());
+        self.failures.extend(other.failures.into_iter().take(free));
+    }
+
+    /// Number of failures which are counted, but not listed in `failures`.
+    pub fn omitted_failures(&self) -> u64 {
+        self.failed.saturating_sub(self.failures.len() as u64)
+    }
 }
 
 #[api()]
@@ -159,12 +253,123 @@ pub const DATASTORE_NOTIFY_STRING_SCHEMA: Schema = StringSchema::new(
 ))
 .schema();
 
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Datastore events which can trigger a notification
+pub enum NotifyEvent {
+    /// Garbage collection
+    Gc,
+    /// Verify jobs
+    Verify,
+    /// Prune jobs
+    Prune,
+    /// Sync jobs
+    Sync,
+    /// Tape backup jobs
+    Tape,
+}
+serde_plain::derive_display_from_serialize!(NotifyEvent);
+serde_plain::derive_fromstr_from_deserialize!(NotifyEvent);
+
+#[api()]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Severity of a notification
+pub enum NotifySeverity {
+    /// Successful jobs
+    Info,
+    /// Jobs which finished with warnings
+    Warning,
+    /// Failed jobs
+    Error,
+}
+
+pub const NOTIFY_EVENT_SCHEMA: Schema =
+    StringSchema::new("Event type, one of 'gc', 'verify', 'prune', 'sync' or 'tape'.")
+        .format(&PROXMOX_SAFE_ID_FORMAT)
+        .schema();
+
+#[api(
+    properties: {
+        event: {
+            schema: NOTIFY_EVENT_SCHEMA,
+        },
+        severity: {
+            type: NotifySeverity,
+            optional: true,
+        },
+        target: {
+            type: Userid,
+        },
+    },
+)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Notification target for datastore events
+pub struct DatastoreNotifyTarget {
+    /// The event type. This is kept as plain string, so that entries with event types unknown
+    /// to this version do not break parsing the datastore config.
+    pub event: String,
+    /// Minimum severity to notify about (default: error)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<NotifySeverity>,
+    /// The user to notify, mails are sent to their configured email address
+    pub target: Userid,
+}
+
+impl DatastoreNotifyTarget {
+    /// Returns the event type, `None` if it is unknown.
+    pub fn event_type(&self) -> Option<NotifyEvent> {
+        self.event.parse().ok()
+    }
+
+    /// Returns the minimum severity this target is notified about.
+    pub fn min_severity(&self) -> NotifySeverity {
+        self.severity.unwrap_or(NotifySeverity::Error)
+    }
+}
+
+impl std::str::FromStr for DatastoreNotifyTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = DatastoreNotifyTarget::API_SCHEMA.parse_property_string(s)?;
+        Ok(DatastoreNotifyTarget::deserialize(value)?)
+    }
+}
+
+pub const DATASTORE_NOTIFY_TARGET_SCHEMA: Schema =
+    StringSchema::new("Notification target for datastore events.")
+        .format(&ApiStringFormat::PropertyString(
+            &DatastoreNotifyTarget::API_SCHEMA,
+        ))
+        .schema();
+
+pub const DATASTORE_NOTIFY_TARGET_LIST_SCHEMA: Schema = ArraySchema::new(
+    "List of notification targets, these take precedence over 'notify' and 'notify-user' \
+    for all events with at least one target.",
+    &DATASTORE_NOTIFY_TARGET_SCHEMA,
+)
+.schema();
+
 pub const IGNORE_VERIFIED_BACKUPS_SCHEMA: Schema = BooleanSchema::new(
     "Do not verify backups that are already verified if their verification is not outdated.",
 )
 .default(true)
 .schema();
 
+pub const RE_VERIFY_FAILED_SCHEMA: Schema = BooleanSchema::new(
+    "Verify backups again whose last verification failed, even if it is not outdated.",
+)
+.default(true)
+.schema();
+
+pub const VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA: Schema = BooleanSchema::new(
+    "Do not search all index files for snapshots referencing chunks found to be bad.",
+)
+.default(false)
+.schema();
+
 pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
     IntegerSchema::new("Days after that a verification becomes outdated. (0 is deprecated)'")
         .minimum(0)
@@ -186,6 +391,10 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: VERIFICATION_OUTDATED_AFTER_SCHEMA,
         },
+        "skip-bad-chunk-report": {
+            optional: true,
+            schema: VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA,
+        },
         comment: {
             optional: true,
             schema: SINGLE_LINE_COMMENT_SCHEMA,
@@ -202,6 +411,10 @@ pub const VERIFICATION_OUTDATED_AFTER_SCHEMA: Schema =
             optional: true,
             schema: crate::NS_MAX_DEPTH_SCHEMA,
         },
+        "concurrency-group": {
+            optional: true,
+            schema: CONCURRENCY_GROUP_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -221,6 +434,9 @@ pub struct VerificationJobConfig {
     /// Reverify snapshots after X days, never if 0. Ignored if 'ignore_verified' is false.
     pub outdated_after: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    /// if set, do not report which snapshots reference chunks found to be bad
+    pub skip_bad_chunk_report: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     /// when to schedule this job in calendar event notation
@@ -232,9 +448,16 @@ pub struct VerificationJobConfig {
     /// how deep the verify should go from the `ns` level downwards. Passing 0 verifies only the
     /// snapshots on the same level as the passed `ns`, or the datastore root if none.
     pub max_depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
 }
 
 impl VerificationJobConfig {
+    /// The concurrency group of the job, the datastore name if not set explicitly.
+    pub fn concurrency_group(&self) -> &str {
+        self.concurrency_group.as_deref().unwrap_or(&self.store)
+    }
+
     pub fn acl_path(&self) -> Vec<&str> {
         match self.ns.as_ref() {
             Some(ns) => ns.acl_path(&self.store),
@@ -274,6 +497,10 @@ pub struct VerificationJobStatus {
         drive: {
             schema: DRIVE_NAME_SCHEMA,
         },
+        "extra-drives": {
+            schema: TAPE_EXTRA_DRIVE_LIST_SCHEMA,
+            optional: true,
+        },
         "eject-media": {
             description: "Eject media upon job completion.",
             type: bool,
@@ -314,6 +541,9 @@ pub struct TapeBackupJobSetup {
     pub store: String,
     pub pool: String,
     pub drive: String,
+    /// Additional drives (of the same changer) written to in parallel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_drives: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub eject_media: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -347,6 +577,10 @@ pub struct TapeBackupJobSetup {
             optional: true,
             schema: SYNC_SCHEDULE_SCHEMA,
         },
+        "concurrency-group": {
+            optional: true,
+            schema: CONCURRENCY_GROUP_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
@@ -361,6 +595,17 @@ pub struct TapeBackupJobConfig {
     pub comment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
+}
+
+impl TapeBackupJobConfig {
+    /// The concurrency group of the job, the datastore name if not set explicitly.
+    pub fn concurrency_group(&self) -> &str {
+        self.concurrency_group
+            .as_deref()
+            .unwrap_or(&self.setup.store)
+    }
 }
 
 #[api(
@@ -490,11 +735,73 @@ pub const GROUP_FILTER_SCHEMA: Schema = StringSchema::new(
 pub const GROUP_FILTER_LIST_SCHEMA: Schema =
     ArraySchema::new("List of group filters.", &GROUP_FILTER_SCHEMA).schema();
 
+pub const TAPE_EXTRA_DRIVE_LIST_SCHEMA: Schema = ArraySchema::new(
+    "List of additional drives, each writing a part of the backup groups to its own media set.",
+    &DRIVE_NAME_SCHEMA,
+)
+.schema();
+
 pub const TRANSFER_LAST_SCHEMA: Schema =
     IntegerSchema::new("Limit transfer to last N snapshots (per group), skipping others")
         .minimum(1)
         .schema();
 
+pub const VERIFY_DOWNLOADS_SCHEMA: Schema = BooleanSchema::new(
+    "Verify every downloaded chunk before writing it, checking its crypt mode and its digest, \
+    or the CRC for encrypted chunks.",
+)
+.default(false)
+.schema();
+
+pub const SYNC_METADATA_SCHEMA: Schema = BooleanSchema::new(
+    "Sync the notes and the protected flag of snapshots, also updating them for snapshots which \
+    were already synced.",
+)
+.default(true)
+.schema();
+
+pub const ENCRYPTED_ONLY_SCHEMA: Schema = BooleanSchema::new(
+    "Only sync snapshots whose archives are all encrypted, snapshots containing any unencrypted \
+    or only signed archive are skipped.",
+)
+.default(false)
+.schema();
+
+pub const VERIFIED_ONLY_SCHEMA: Schema = BooleanSchema::new(
+    "Only sync snapshots whose last verification was successful, unverified snapshots are \
+    skipped.",
+)
+.default(false)
+.schema();
+
+pub const PRESERVE_OWNER_SCHEMA: Schema = BooleanSchema::new(
+    "Use the owner of the source group for newly created groups, if the source exposes it and \
+    it exists locally. Falls back to 'owner' otherwise.",
+)
+.default(false)
+.schema();
+
+pub const FORCE_OWNER_SCHEMA: Schema = BooleanSchema::new(
+    "Sync into existing local groups with a different owner, changing their owner to the one \
+    used by the job.",
+)
+.default(false)
+.schema();
+
+#[api]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Direction of a sync job
+pub enum SyncDirection {
+    /// Pull from the remote (or source datastore) into the local datastore.
+    #[default]
+    Pull,
+    /// Push from the local datastore to the remote.
+    Push,
+}
+
+serde_plain::derive_display_from_serialize!(SyncDirection);
+
 #[api(
     properties: {
         id: {
@@ -511,6 +818,14 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
             type: Authid,
             optional: true,
         },
+        "preserve-owner": {
+            schema: PRESERVE_OWNER_SCHEMA,
+            optional: true,
+        },
+        "force-owner": {
+            schema: FORCE_OWNER_SCHEMA,
+            optional: true,
+        },
         remote: {
             schema: REMOTE_ID_SCHEMA,
             optional: true,
@@ -549,6 +864,30 @@ pub const TRANSFER_LAST_SCHEMA: Schema =
             schema: TRANSFER_LAST_SCHEMA,
             optional: true,
         },
+        "verify-downloads": {
+            schema: VERIFY_DOWNLOADS_SCHEMA,
+            optional: true,
+        },
+        "sync-metadata": {
+            schema: SYNC_METADATA_SCHEMA,
+            optional: true,
+        },
+        "encrypted-only": {
+            schema: ENCRYPTED_ONLY_SCHEMA,
+            optional: true,
+        },
+        "verified-only": {
+            schema: VERIFIED_ONLY_SCHEMA,
+            optional: true,
+        },
+        "sync-direction": {
+            type: SyncDirection,
+            optional: true,
+        },
+        "concurrency-group": {
+            optional: true,
+            schema: CONCURRENCY_GROUP_SCHEMA,
+        },
     }
 )]
 #[derive(Serialize, Deserialize, Clone, Updater, PartialEq)]
@@ -563,6 +902,10 @@ pub struct SyncJobConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub owner: Option<Authid>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_owner: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_owner: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     /// None implies local sync.
     pub remote: Option<String>,
     pub remote_store: String,
@@ -582,9 +925,31 @@ pub struct SyncJobConfig {
     pub limit: RateLimitConfig,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub transfer_last: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_downloads: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypted_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verified_only: Option<bool>,
+    /// With `push`, the local datastore is the source and the remote the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_direction: Option<SyncDirection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
 }
 
 impl SyncJobConfig {
+    pub fn sync_direction(&self) -> SyncDirection {
+        self.sync_direction.unwrap_or_default()
+    }
+
+    /// The concurrency group of the job, the local datastore name if not set explicitly.
+    pub fn concurrency_group(&self) -> &str {
+        self.concurrency_group.as_deref().unwrap_or(&self.store)
+    }
+
     pub fn acl_path(&self) -> Vec<&str> {
         match self.ns.as_ref() {
             Some(ns) => ns.acl_path(&self.store),
@@ -739,6 +1104,10 @@ impl PruneJobOptions {
         options: {
             type: PruneJobOptions,
         },
+        "concurrency-group": {
+            optional: true,
+            schema: CONCURRENCY_GROUP_SCHEMA,
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater, Clone, PartialEq)]
@@ -763,9 +1132,17 @@ pub struct PruneJobConfig {
 
     #[serde(flatten)]
     pub options: PruneJobOptions,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
 }
 
 impl PruneJobConfig {
+    /// The concurrency group of the job, the datastore name if not set explicitly.
+    pub fn concurrency_group(&self) -> &str {
+        self.concurrency_group.as_deref().unwrap_or(&self.store)
+    }
+
     pub fn acl_path(&self) -> Vec<&str> {
         self.options.acl_path(&self.store)
     }
@@ -794,3 +1171,34 @@ pub struct PruneJobStatus {
     #[serde(flatten)]
     pub status: JobScheduleStatus,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_summary_failure_cap() {
+        let mut summary = JobSummary::default();
+        for i in 0..JOB_SUMMARY_MAX_FAILURES + 2 {
+            summary.add_failure(format!("vm/{i}"), "error");
+        }
+        assert_eq!(summary.failed, JOB_SUMMARY_MAX_FAILURES as u64 + 2);
+        assert_eq!(summary.failures.len(), JOB_SUMMARY_MAX_FAILURES);
+        assert_eq!(summary.failures[0].item, "vm/0");
+        assert_eq!(summary.omitted_failures(), 2);
+
+        // merging keeps the earliest failures and counts the rest
+        let mut total = JobSummary {
+            succeeded: 1,
+            ..Default::default()
+        };
+        total.add_failure("ct/1", "first");
+        total.merge(summary);
+        assert_eq!(total.succeeded, 1);
+        assert_eq!(total.failed, JOB_SUMMARY_MAX_FAILURES as u64 + 3);
+        assert_eq!(total.failures.len(), JOB_SUMMARY_MAX_FAILURES);
+        assert_eq!(total.failures[0].item, "ct/1");
+        assert_eq!(total.failures[1].item, "vm/0");
+        assert_eq!(total.omitted_failures(), 3);
+    }
+}
@@ -51,7 +51,11 @@ pub enum LdapMode {
         "bind-dn" : {
             schema: LDAP_DOMAIN_SCHEMA,
             optional: true,
-        }
+        },
+        "tfa-required": {
+            optional: true,
+            default: false,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Updater, Clone)]
@@ -106,6 +110,9 @@ pub struct LdapRealmConfig {
     /// User ``objectClass`` classes to sync
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_classes: Option<String>,
+    /// Require users of this realm to log in with a second factor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tfa_required: Option<bool>,
 }
 
 #[api(
@@ -8,7 +8,8 @@ pub mod common_regex;
 pub mod percent_encoding;
 
 use proxmox_schema::{
-    api, const_regex, ApiStringFormat, ApiType, ArraySchema, ReturnType, Schema, StringSchema,
+    api, const_regex, ApiStringFormat, ApiType, ArraySchema, EnumEntry, ReturnType, Schema,
+    StringSchema,
 };
 use proxmox_time::parse_daily_duration;
 
@@ -72,6 +73,9 @@ pub use acl::*;
 mod datastore;
 pub use datastore::*;
 
+mod error;
+pub use error::*;
+
 mod jobs;
 pub use jobs::*;
 
@@ -113,6 +117,12 @@ pub use openid::*;
 mod ldap;
 pub use ldap::*;
 
+mod pam;
+pub use pam::*;
+
+mod pbs;
+pub use pbs::*;
+
 mod remote;
 pub use remote::*;
 
@@ -163,6 +173,7 @@ const_regex! {
     pub UUID_REGEX = r"^[0-9a-f]{8}(?:-[0-9a-f]{4}){3}-[0-9a-f]{12}$";
 
     pub SYSTEMD_DATETIME_REGEX = r"^\d{4}-\d{2}-\d{2}( \d{2}:\d{2}(:\d{2})?)?$"; //  fixme: define in common_regex ?
+    pub SYSTEMD_UNIT_NAME_REGEX = r"^[A-Za-z0-9][A-Za-z0-9:_.@\-]*$";
 
     pub FINGERPRINT_SHA256_REGEX = r"^(?:[0-9a-fA-F][0-9a-fA-F])(?::[0-9a-fA-F][0-9a-fA-F]){31}$";
 
@@ -217,6 +228,8 @@ pub const SUBSCRIPTION_KEY_FORMAT: ApiStringFormat =
     ApiStringFormat::Pattern(&SUBSCRIPTION_KEY_REGEX);
 pub const SYSTEMD_DATETIME_FORMAT: ApiStringFormat =
     ApiStringFormat::Pattern(&SYSTEMD_DATETIME_REGEX);
+pub const SYSTEMD_UNIT_NAME_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&SYSTEMD_UNIT_NAME_REGEX);
 pub const HOSTNAME_FORMAT: ApiStringFormat = ApiStringFormat::Pattern(&HOSTNAME_REGEX);
 pub const OPENSSL_CIPHERS_TLS_FORMAT: ApiStringFormat =
     ApiStringFormat::Pattern(&OPENSSL_CIPHERS_REGEX);
@@ -359,6 +372,25 @@ pub const SUBSCRIPTION_KEY_SCHEMA: Schema =
 
 pub const SERVICE_ID_SCHEMA: Schema = StringSchema::new("Service ID.").max_length(256).schema();
 
+pub const SYSTEMD_UNIT_NAME_SCHEMA: Schema = StringSchema::new("Systemd unit name.")
+    .format(&SYSTEMD_UNIT_NAME_FORMAT)
+    .max_length(128)
+    .schema();
+
+pub const JOURNAL_PRIORITY_SCHEMA: Schema =
+    StringSchema::new("Only show entries with this syslog priority or a more important one.")
+        .format(&ApiStringFormat::Enum(&[
+            EnumEntry::new("emerg", "System is unusable."),
+            EnumEntry::new("alert", "Action must be taken immediately."),
+            EnumEntry::new("crit", "Critical conditions."),
+            EnumEntry::new("err", "Error conditions."),
+            EnumEntry::new("warning", "Warning conditions."),
+            EnumEntry::new("notice", "Normal, but significant conditions."),
+            EnumEntry::new("info", "Informational messages."),
+            EnumEntry::new("debug", "Debug-level messages."),
+        ]))
+        .schema();
+
 pub const PROXMOX_CONFIG_DIGEST_SCHEMA: Schema = StringSchema::new(
     "Prevent changes if current configuration file has different \
     SHA256 digest. This can be used to prevent concurrent \
@@ -418,6 +450,50 @@ pub struct APTUpdateInfo {
     pub extra_info: Option<String>,
 }
 
+#[api()]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Installed and available version of a Proxmox Backup Server package.
+pub struct ServerPackageUpdate {
+    /// Package name
+    pub package: String,
+    /// Installed version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installed_version: Option<String>,
+    /// Newest version available from the configured repositories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_version: Option<String>,
+    /// Whether the available version is newer than the installed one
+    pub update_available: bool,
+    /// Changelog entries of the versions newer than the installed one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog: Option<String>,
+}
+
+#[api(
+    properties: {
+        packages: {
+            type: Array,
+            items: {
+                type: ServerPackageUpdate,
+            },
+        },
+    },
+)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Update state of the Proxmox Backup Server packages, based on the local package index.
+pub struct ServerUpdateStatus {
+    /// Whether an update of any of the packages is available
+    pub updates_available: bool,
+    pub packages: Vec<ServerPackageUpdate>,
+    /// Time of the last package index update (epoch), unknown if it was never updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_updated: Option<i64>,
+    /// Time the update state was determined (epoch)
+    pub checked: i64,
+}
+
 #[api()]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -472,6 +548,37 @@ pub struct TaskListItem {
     /// Task end status
     #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    /// Datastore bytes read by the task (only for running tasks, if requested)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_bytes: Option<u64>,
+    /// Datastore bytes written by the task (only for running tasks, if requested)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_bytes: Option<u64>,
+    /// Recent read throughput in bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_rate: Option<u64>,
+    /// Recent write throughput in bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_rate: Option<u64>,
+}
+
+#[api(
+    properties: {
+        upid: { schema: UPID::API_SCHEMA },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+/// Datastore IO of a running task.
+pub struct TaskIoStats {
+    pub upid: String,
+    /// Datastore bytes read by the task
+    pub read_bytes: u64,
+    /// Datastore bytes written by the task
+    pub write_bytes: u64,
+    /// Recent read throughput in bytes per second
+    pub read_rate: u64,
+    /// Recent write throughput in bytes per second
+    pub write_rate: u64,
 }
 
 pub const NODE_TASKS_LIST_TASKS_RETURN_TYPE: ReturnType = ReturnType {
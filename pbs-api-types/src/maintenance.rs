@@ -1,4 +1,4 @@
-use anyhow::{bail, Error};
+use anyhow::Error;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
@@ -33,7 +33,7 @@ pub enum Operation {
 }
 
 #[api]
-#[derive(Deserialize, Serialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 /// Maintenance type.
 pub enum MaintenanceType {
@@ -44,6 +44,9 @@ pub enum MaintenanceType {
     //    cleaned
     /// Only read operations are allowed on the datastore.
     ReadOnly,
+    /// Like `ReadOnly`, but the datastore is explicitly kept available as source for remote sync
+    /// pulls and restores, e.g., while it is being migrated to another host.
+    SyncSource,
     /// Neither read nor write operations are allowed on the datastore.
     Offline,
     /// The datastore is being deleted.
@@ -67,7 +70,7 @@ serde_plain::derive_fromstr_from_deserialize!(MaintenanceType);
 #[derive(Deserialize, Serialize)]
 /// Maintenance mode
 pub struct MaintenanceMode {
-    /// Type of maintenance ("read-only" or "offline").
+    /// Type of maintenance ("read-only", "sync-source" or "offline").
     #[serde(rename = "type")]
     ty: MaintenanceType,
 
@@ -77,24 +80,130 @@ pub struct MaintenanceMode {
 }
 
 impl MaintenanceMode {
+    /// Returns the type of this maintenance mode.
+    pub fn maintenance_type(&self) -> MaintenanceType {
+        self.ty
+    }
+
+    /// Returns true if only read operations are allowed in this mode.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self.ty,
+            MaintenanceType::ReadOnly | MaintenanceType::SyncSource
+        )
+    }
+
+    /// Check whether `operation` is allowed in this mode.
+    ///
+    /// If not, the returned error can be downcast to a [`MaintenanceModeError`].
     pub fn check(&self, operation: Option<Operation>) -> Result<(), Error> {
+        let error = || {
+            let message =
+                percent_encoding::percent_decode_str(self.message.as_deref().unwrap_or(""))
+                    .decode_utf8()
+                    .unwrap_or(Cow::Borrowed(""));
+            Error::from(MaintenanceModeError {
+                ty: self.ty,
+                message: message.into_owned(),
+            })
+        };
+
         if self.ty == MaintenanceType::Delete {
-            bail!("datastore is being deleted");
+            return Err(error());
         }
 
-        let message = percent_encoding::percent_decode_str(self.message.as_deref().unwrap_or(""))
-            .decode_utf8()
-            .unwrap_or(Cow::Borrowed(""));
-
         if let Some(Operation::Lookup) = operation {
             return Ok(());
         } else if self.ty == MaintenanceType::Offline {
-            bail!("offline maintenance mode: {}", message);
-        } else if self.ty == MaintenanceType::ReadOnly {
+            return Err(error());
+        } else if self.is_read_only() {
             if let Some(Operation::Write) = operation {
-                bail!("read-only maintenance mode: {}", message);
+                return Err(error());
             }
         }
         Ok(())
     }
 }
+
+#[derive(Debug)]
+/// An operation was rejected because of the maintenance mode of a datastore.
+pub struct MaintenanceModeError {
+    ty: MaintenanceType,
+    message: String,
+}
+
+impl MaintenanceModeError {
+    /// Returns the type of the maintenance mode which rejected the operation.
+    pub fn maintenance_type(&self) -> MaintenanceType {
+        self.ty
+    }
+
+    /// Returns the (decoded) maintenance message, may be empty.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for MaintenanceModeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.ty == MaintenanceType::Delete {
+            return f.write_str("delete maintenance mode, the datastore is being deleted");
+        }
+        write!(f, "{} maintenance mode: {}", self.ty, self.message)
+    }
+}
+
+impl std::error::Error for MaintenanceModeError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(ty: &str, operation: Operation) -> Option<MaintenanceType> {
+        let mode = MaintenanceMode {
+            ty: ty.parse().unwrap(),
+            message: Some("some%20reason".to_string()),
+        };
+        mode.check(Some(operation)).err().map(|err| {
+            let err = err.downcast_ref::<MaintenanceModeError>().unwrap();
+            if err.ty != MaintenanceType::Delete {
+                assert_eq!(
+                    err.to_string(),
+                    format!("{ty} maintenance mode: some reason")
+                );
+            }
+            err.ty
+        })
+    }
+
+    #[test]
+    fn test_maintenance_mode_error() {
+        // reader connections
+        assert_eq!(
+            check("offline", Operation::Read),
+            Some(MaintenanceType::Offline)
+        );
+        assert_eq!(check("read-only", Operation::Read), None);
+        assert_eq!(check("sync-source", Operation::Read), None);
+
+        // writer connections
+        assert_eq!(
+            check("offline", Operation::Write),
+            Some(MaintenanceType::Offline)
+        );
+        assert_eq!(
+            check("read-only", Operation::Write),
+            Some(MaintenanceType::ReadOnly)
+        );
+        assert_eq!(
+            check("sync-source", Operation::Write),
+            Some(MaintenanceType::SyncSource)
+        );
+
+        assert_eq!(check("offline", Operation::Lookup), None);
+        assert_eq!(
+            check("delete", Operation::Lookup),
+            Some(MaintenanceType::Delete)
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::ffi::OsStr;
 use proxmox_schema::*;
 use serde::{Deserialize, Serialize};
 
-use crate::StorageStatus;
+use crate::{ServerUpdateStatus, StorageStatus, DATASTORE_SCHEMA, UPID_SCHEMA};
 
 #[api]
 #[derive(Serialize, Deserialize, Default)]
@@ -133,7 +133,11 @@ pub struct NodeCpuInformation {
         },
         info: {
             type: NodeInformation,
-        }
+        },
+        updates: {
+            type: ServerUpdateStatus,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -159,4 +163,89 @@ pub struct NodeStatus {
     pub info: NodeInformation,
     /// Current boot mode
     pub boot_info: BootModeInformation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updates: Option<ServerUpdateStatus>,
+}
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A single changed configuration property.
+pub struct AuditLogChange {
+    /// The property name.
+    pub property: String,
+    /// The old value (JSON encoded, sensitive values masked).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    /// The new value (JSON encoded, sensitive values masked).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+#[api(
+    properties: {
+        changes: {
+            type: Array,
+            items: {
+                type: AuditLogChange,
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// An entry of the configuration audit log.
+pub struct AuditLogEntry {
+    /// Time of the change (Epoch).
+    pub time: i64,
+    /// The authenticated entity which made the change.
+    pub auth_id: String,
+    /// The API path of the modified configuration object.
+    pub path: String,
+    /// The changed properties.
+    pub changes: Vec<AuditLogChange>,
+    /// Configuration digest before the change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_before: Option<String>,
+    /// Configuration digest after the change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest_after: Option<String>,
+}
+
+#[api(
+    properties: {
+        store: {
+            schema: DATASTORE_SCHEMA,
+            optional: true,
+        },
+        upid: {
+            schema: UPID_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// A currently held backup group or snapshot lock.
+pub struct NodeLockEntry {
+    /// The locked directory.
+    pub path: String,
+    /// The datastore containing the locked directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// What is locked, for example "backup group" or "snapshot".
+    pub what: String,
+    /// Whether the lock is held exclusively.
+    pub exclusive: bool,
+    /// Process ID of the lock holder.
+    pub pid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    /// The operation holding the lock, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+    /// Time the lock was acquired (Epoch).
+    pub since: i64,
+    /// Seconds since the lock was acquired.
+    pub age: i64,
 }
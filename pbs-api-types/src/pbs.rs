@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use proxmox_schema::{api, Updater};
+
+use super::{REALM_ID_SCHEMA, SINGLE_LINE_COMMENT_SCHEMA};
+
+#[api(
+    properties: {
+        "realm": {
+            schema: REALM_ID_SCHEMA,
+        },
+        "comment": {
+            optional: true,
+            schema: SINGLE_LINE_COMMENT_SCHEMA,
+        },
+        "tfa-required": {
+            optional: true,
+            default: false,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Updater, Clone)]
+#[serde(rename_all = "kebab-case")]
+/// Proxmox Backup authentication server realm configuration properties.
+pub struct PbsRealmConfig {
+    #[updater(skip)]
+    pub realm: String,
+    /// Comment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// Require users of this realm to log in with a second factor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tfa_required: Option<bool>,
+}
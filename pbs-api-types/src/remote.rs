@@ -23,6 +23,14 @@ pub const REMOTE_ID_SCHEMA: Schema = StringSchema::new("Remote ID.")
     .max_length(32)
     .schema();
 
+pub const REMOTE_CHECK_SCHEDULE_SCHEMA: Schema =
+    StringSchema::new("Run a health check of the remote at specified schedule.")
+        .format(&ApiStringFormat::VerifyFn(
+            proxmox_time::verify_calendar_event,
+        ))
+        .type_text("<calendar-event>")
+        .schema();
+
 #[api(
     properties: {
         comment: {
@@ -44,6 +52,10 @@ pub const REMOTE_ID_SCHEMA: Schema = StringSchema::new("Remote ID.")
             optional: true,
             schema: CERT_FINGERPRINT_SHA256_SCHEMA,
         },
+        "check-schedule": {
+            optional: true,
+            schema: REMOTE_CHECK_SCHEDULE_SCHEMA,
+        },
     },
 )]
 #[derive(Serialize, Deserialize, Updater, Clone, PartialEq)]
@@ -58,6 +70,8 @@ pub struct RemoteConfig {
     pub auth_id: Authid,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_schedule: Option<String>,
 }
 
 #[api(
@@ -104,3 +118,53 @@ pub struct RemoteWithoutPassword {
     #[serde(flatten)]
     pub config: RemoteConfig,
 }
+
+#[api]
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a remote health check.
+pub struct RemoteCheckResult {
+    /// Time of the check (epoch).
+    pub time: i64,
+    /// Whether a TLS connection to the remote could be established.
+    pub reachable: bool,
+    /// Whether the certificate matches the configured fingerprint, or is trusted if there is
+    /// none configured.
+    pub fingerprint_ok: bool,
+    /// Whether the login with the stored credentials succeeded.
+    pub auth_ok: bool,
+    /// Version of the remote server.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Number of datastores accessible on the remote.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastore_count: Option<u64>,
+    /// Reason of a failed check.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[api(
+    properties: {
+        name: {
+            schema: REMOTE_ID_SCHEMA,
+        },
+        config: {
+            type: RemoteConfig,
+        },
+        "last-check": {
+            type: RemoteCheckResult,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Remote properties with the result of the last health check.
+pub struct RemoteListItem {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: RemoteConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_check: Option<RemoteCheckResult>,
+}
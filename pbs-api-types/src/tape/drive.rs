@@ -22,11 +22,32 @@ pub const CHANGER_DRIVENUM_SCHEMA: Schema =
         .default(0)
         .schema();
 
+#[api()]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Kind of failure injected by virtual tape drives (only for test and debug)
+pub enum VirtualTapeFailMode {
+    /// Report the (logical) end of tape while writing
+    #[default]
+    Eot,
+    /// Fail writes with an IO error
+    IoError,
+    /// Fail reads with an IO error
+    ReadError,
+}
+
 #[api(
     properties: {
         name: {
             schema: DRIVE_NAME_SCHEMA,
-        }
+        },
+        "fail-after-bytes": {
+            optional: true,
+        },
+        "fail-mode": {
+            type: VirtualTapeFailMode,
+            optional: true,
+        },
     }
 )]
 #[derive(Serialize, Deserialize)]
@@ -39,6 +60,12 @@ pub struct VirtualTapeDrive {
     /// Virtual tape size
     #[serde(skip_serializing_if = "Option::is_none")]
     pub max_size: Option<usize>,
+    /// Inject failures after this many bytes were transferred since the drive was opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_after_bytes: Option<u64>,
+    /// Kind of failure to inject (defaults to 'eot')
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_mode: Option<VirtualTapeFailMode>,
 }
 
 #[api(
@@ -156,12 +183,81 @@ impl TryFrom<u8> for TapeDensity {
     }
 }
 
+#[api()]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// What the drive is currently doing (DT device activity, see SSC-4 VHF data log parameter)
+pub enum DeviceActivity {
+    /// No activity
+    NoActivity,
+    /// Cleaning
+    Cleaning,
+    /// Loading
+    Loading,
+    /// Unloading
+    Unloading,
+    /// Other unspecified medium activity
+    Other,
+    /// Reading
+    Reading,
+    /// Writing
+    Writing,
+    /// Locating
+    Locating,
+    /// Rewinding
+    Rewinding,
+    /// Erasing
+    Erasing,
+    /// Formatting
+    Formatting,
+    /// Calibrating
+    Calibrating,
+    /// Other unspecified activity
+    OtherDT,
+    /// Updating microcode
+    MicrocodeUpdate,
+    /// Reading encrypted data
+    ReadingEncrypted,
+    /// Writing encrypted data
+    WritingEncrypted,
+}
+
+impl TryFrom<u8> for DeviceActivity {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => DeviceActivity::NoActivity,
+            0x01 => DeviceActivity::Cleaning,
+            0x02 => DeviceActivity::Loading,
+            0x03 => DeviceActivity::Unloading,
+            0x04 => DeviceActivity::Other,
+            0x05 => DeviceActivity::Reading,
+            0x06 => DeviceActivity::Writing,
+            0x07 => DeviceActivity::Locating,
+            0x08 => DeviceActivity::Rewinding,
+            0x09 => DeviceActivity::Erasing,
+            0x0A => DeviceActivity::Formatting,
+            0x0B => DeviceActivity::Calibrating,
+            0x0C => DeviceActivity::OtherDT,
+            0x0D => DeviceActivity::MicrocodeUpdate,
+            0x0E => DeviceActivity::ReadingEncrypted,
+            0x0F => DeviceActivity::WritingEncrypted,
+            _ => bail!("unknown device activity code 0x{:02x}", value),
+        })
+    }
+}
+
 #[api(
     properties: {
         density: {
             type: TapeDensity,
             optional: true,
         },
+        "drive-activity": {
+            type: DeviceActivity,
+            optional: true,
+        },
     },
 )]
 #[derive(Serialize, Deserialize)]
@@ -169,7 +265,9 @@ impl TryFrom<u8> for TapeDensity {
 /// Drive/Media status for Lto SCSI drives.
 ///
 /// Media related data is optional - only set if there is a medium
-/// loaded.
+/// loaded. While the drive is locked by a task, only the identification,
+/// the activity and the lock owner are reported, so that the running
+/// operation is not disturbed.
 pub struct LtoDriveAndMediaStatus {
     /// Vendor
     pub vendor: String,
@@ -178,13 +276,26 @@ pub struct LtoDriveAndMediaStatus {
     /// Revision
     pub revision: String,
     /// Block size (0 is variable size)
-    pub blocksize: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocksize: Option<u32>,
     /// Compression enabled
-    pub compression: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<bool>,
     /// Drive buffer mode
-    pub buffer_mode: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffer_mode: Option<u8>,
     /// Tape density
-    pub density: TapeDensity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub density: Option<TapeDensity>,
+    /// Current drive activity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drive_activity: Option<DeviceActivity>,
+    /// UPID of the task holding the drive lock, or the operation in progress
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_owner: Option<String>,
+    /// Time since the drive got locked (seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_duration: Option<u64>,
     /// Media is write protected
     #[serde(skip_serializing_if = "Option::is_none")]
     pub write_protect: Option<bool>,
@@ -3,7 +3,7 @@ use ::serde::{Deserialize, Serialize};
 use proxmox_schema::*;
 use proxmox_uuid::Uuid;
 
-use crate::{MediaLocation, MediaStatus, UUID_FORMAT};
+use crate::{BackupNamespace, MediaLocation, MediaStatus, UUID_FORMAT};
 
 pub const MEDIA_SET_UUID_SCHEMA: Schema = StringSchema::new(
     "MediaSet Uuid (We use the all-zero Uuid to reseve an empty media for a specific pool).",
@@ -33,6 +33,12 @@ pub struct MediaSetListEntry {
     pub media_set_ctime: i64,
     /// Media Pool
     pub pool: String,
+    /// Encryption key fingerprint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption_key_fingerprint: Option<String>,
+    /// Media set data key is wrapped by the encryption key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_wrapped: Option<bool>,
 }
 
 #[api(
@@ -83,6 +89,101 @@ pub struct MediaListEntry {
     pub pool: Option<String>,
 }
 
+#[api()]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// Forecast of when a media can be (re)written
+pub enum MediaForecastState {
+    /// Writable now (empty, expired or appendable)
+    Writable,
+    /// Writable once its retention period expires
+    Expires,
+    /// Kept forever by the pool's allocation or retention policy
+    Keep,
+    /// Damaged or retired, will not be reused
+    Unusable,
+    /// State cannot be determined
+    Unknown,
+}
+
+#[api(
+    properties: {
+        location: {
+            type: MediaLocation,
+        },
+        status: {
+            type: MediaStatus,
+        },
+        state: {
+            type: MediaForecastState,
+        },
+        uuid: {
+            schema: MEDIA_UUID_SCHEMA,
+        },
+        "media-set-uuid": {
+            schema: MEDIA_SET_UUID_SCHEMA,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Media retention forecast entry
+pub struct MediaForecastEntry {
+    /// Media label text (or Barcode)
+    pub label_text: String,
+    pub uuid: Uuid,
+    pub location: MediaLocation,
+    pub status: MediaStatus,
+    pub state: MediaForecastState,
+    /// Time when the retention period expires (not set if it never expires)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire_time: Option<i64>,
+    /// Media set name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_set_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_set_uuid: Option<Uuid>,
+    /// Media set seq_nr
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq_nr: Option<u64>,
+    /// MediaSet creation time stamp
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_set_ctime: Option<i64>,
+    /// Why the state could not be determined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[api(
+    properties: {
+        media: {
+            type: Array,
+            items: {
+                type: MediaForecastEntry,
+            },
+        },
+    },
+)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Media retention forecast for a pool
+pub struct MediaPoolForecast {
+    /// Media Pool
+    pub pool: String,
+    /// Number of media writable now
+    pub writable_now: u64,
+    /// Number of media writable within 30 days (including those writable now)
+    pub writable_in_30_days: u64,
+    /// Number of media writable within 90 days (including those writable now)
+    pub writable_in_90_days: u64,
+    /// Number of media which never become writable
+    pub never: u64,
+    /// Number of media with unknown state
+    pub unknown: u64,
+    pub media: Vec<MediaForecastEntry>,
+}
+
 #[api(
     properties: {
         uuid: {
@@ -174,3 +275,45 @@ pub struct MediaContentEntry {
     /// Snapshot creation time (epoch)
     pub backup_time: i64,
 }
+
+#[api(
+    properties: {
+        uuid: {
+            schema: MEDIA_UUID_SCHEMA,
+            optional: true,
+        },
+        ns: {
+            type: BackupNamespace,
+            optional: true,
+        },
+    },
+)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+/// Media set content list entry
+pub struct MediaSetContentEntry {
+    /// Media set seq_nr
+    pub seq_nr: u64,
+    /// Media label text (or Barcode)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// Datastore Name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ns: Option<BackupNamespace>,
+    /// Backup snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Tape file number of the snapshot archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_nr: Option<u64>,
+    /// Referenced size of the snapshot, if it is still present in the datastore
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Why the content of this media is unknown (missing media or catalog)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
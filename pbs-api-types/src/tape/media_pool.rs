@@ -14,7 +14,7 @@ use proxmox_schema::{api, ApiStringFormat, Schema, StringSchema, Updater};
 use proxmox_time::{CalendarEvent, TimeSpan};
 
 use crate::{
-    PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
+    Fingerprint, PROXMOX_SAFE_ID_FORMAT, SINGLE_LINE_COMMENT_FORMAT, SINGLE_LINE_COMMENT_SCHEMA,
     TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
 
@@ -127,6 +127,13 @@ impl std::str::FromStr for RetentionPolicy {
             schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
             optional: true,
         },
+        "encrypt-history": {
+            type: Array,
+            optional: true,
+            items: {
+                schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+        },
         comment: {
             optional: true,
             schema: SINGLE_LINE_COMMENT_SCHEMA,
@@ -156,6 +163,50 @@ pub struct MediaPoolConfig {
     /// If set, encrypt all data using the specified key.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encrypt: Option<String>,
+    /// Previously used encryption key fingerprints (newest first)
+    ///
+    /// Media sets written before a key rotation still need these keys for restore.
+    #[updater(skip)]
+    #[serde(rename = "encrypt-history", skip_serializing_if = "Option::is_none")]
+    pub encrypt_history: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub comment: Option<String>,
 }
+
+fn same_fingerprint(a: &str, b: &str) -> bool {
+    match (a.parse::<Fingerprint>(), b.parse::<Fingerprint>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl MediaPoolConfig {
+    /// Switch to a new encryption key (or disable encryption), remembering the current key in
+    /// the key history.
+    pub fn rotate_encryption_key(&mut self, fingerprint: Option<String>) {
+        if let Some(old) = self.encrypt.take() {
+            let unchanged = fingerprint
+                .as_ref()
+                .map(|new| same_fingerprint(&old, new))
+                .unwrap_or(false);
+            if !unchanged {
+                let history = self.encrypt_history.get_or_insert_with(Vec::new);
+                history.retain(|fp| !same_fingerprint(fp, &old));
+                history.insert(0, old);
+            }
+        }
+        if let (Some(history), Some(new)) = (self.encrypt_history.as_mut(), fingerprint.as_ref()) {
+            history.retain(|fp| !same_fingerprint(fp, new));
+        }
+        self.encrypt = fingerprint;
+    }
+
+    /// Returns true if `fingerprint` is the current or a previous encryption key of this pool.
+    pub fn uses_encryption_key(&self, fingerprint: &Fingerprint) -> bool {
+        let fingerprint = fingerprint.signature();
+        self.encrypt
+            .iter()
+            .chain(self.encrypt_history.iter().flatten())
+            .any(|fp| same_fingerprint(fp, &fingerprint))
+    }
+}
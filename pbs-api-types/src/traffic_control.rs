@@ -71,6 +71,26 @@ impl RateLimitConfig {
             burst_out: burst,
         }
     }
+
+    /// Returns the effective inbound rate and burst, `None` means unlimited.
+    pub fn effective_in(&self) -> Option<(HumanByte, HumanByte)> {
+        effective_limit(self.rate_in, self.burst_in)
+    }
+
+    /// Returns the effective outbound rate and burst, `None` means unlimited.
+    pub fn effective_out(&self) -> Option<(HumanByte, HumanByte)> {
+        effective_limit(self.rate_out, self.burst_out)
+    }
+}
+
+// A rate of zero means unlimited, a missing or zero burst defaults to the rate.
+fn effective_limit(
+    rate: Option<HumanByte>,
+    burst: Option<HumanByte>,
+) -> Option<(HumanByte, HumanByte)> {
+    let rate = rate.filter(|rate| rate.as_u64() > 0)?;
+    let burst = burst.filter(|burst| burst.as_u64() > 0).unwrap_or(rate);
+    Some((rate, burst))
 }
 
 #[api(
@@ -147,16 +147,12 @@ pub struct ApiToken {
 
 impl ApiToken {
     pub fn is_active(&self) -> bool {
-        if !self.enable.unwrap_or(true) {
-            return false;
-        }
-        if let Some(expire) = self.expire {
-            let now = proxmox_time::epoch_i64();
-            if expire > 0 && expire <= now {
-                return false;
-            }
-        }
-        true
+        self.enable.unwrap_or(true) && !self.is_expired(proxmox_time::epoch_i64())
+    }
+
+    /// Returns whether the token is expired at `now`.
+    pub fn is_expired(&self, now: i64) -> bool {
+        matches!(self.expire, Some(expire) if expire > 0 && expire <= now)
     }
 }
 
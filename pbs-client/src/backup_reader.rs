@@ -7,13 +7,15 @@ use std::sync::Arc;
 use futures::future::AbortHandle;
 use serde_json::{json, Value};
 
-use pbs_api_types::{BackupDir, BackupNamespace};
+use pbs_api_types::{BackupDir, BackupNamespace, WireCompression};
 use pbs_datastore::data_blob::DataBlob;
 use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
+use pbs_datastore::file_formats::EncryptedDataBlobHeader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::MANIFEST_BLOB_NAME;
+use pbs_datastore::wire_compression;
 use pbs_datastore::{BackupManifest, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::sha::sha256;
@@ -25,6 +27,7 @@ pub struct BackupReader {
     h2: H2Client,
     abort: AbortHandle,
     crypt_config: Option<Arc<CryptConfig>>,
+    wire_compression: WireCompression,
 }
 
 impl Drop for BackupReader {
@@ -34,11 +37,17 @@ impl Drop for BackupReader {
 }
 
 impl BackupReader {
-    fn new(h2: H2Client, abort: AbortHandle, crypt_config: Option<Arc<CryptConfig>>) -> Arc<Self> {
+    fn new(
+        h2: H2Client,
+        abort: AbortHandle,
+        crypt_config: Option<Arc<CryptConfig>>,
+        wire_compression: WireCompression,
+    ) -> Arc<Self> {
         Arc::new(Self {
             h2,
             abort,
             crypt_config,
+            wire_compression,
         })
     }
 
@@ -72,11 +81,16 @@ impl BackupReader {
         )
         .unwrap();
 
-        let (h2, abort) = client
+        let (h2, abort, wire_compression) = client
             .start_h2_connection(req, String::from(PROXMOX_BACKUP_READER_PROTOCOL_ID_V1!()))
-            .await?;
+            .await
+            .map_err(|err| super::map_datastore_unavailable_error(err, datastore))?;
+
+        if wire_compression != WireCompression::None {
+            log::info!("using wire compression: {wire_compression}");
+        }
 
-        Ok(BackupReader::new(h2, abort, crypt_config))
+        Ok(BackupReader::new(h2, abort, crypt_config, wire_compression))
     }
 
     /// Execute a GET request
@@ -109,14 +123,30 @@ impl BackupReader {
     }
 
     /// Download a specific chunk
+    ///
+    /// The chunk blob is written to `output` as it is stored on the server, independent of the
+    /// wire compression.
     pub async fn download_chunk<W: Write + Send>(
         &self,
         digest: &[u8; 32],
-        output: W,
+        mut output: W,
     ) -> Result<(), Error> {
         let path = "chunk";
         let param = json!({ "digest": hex::encode(digest) });
-        self.h2.download(path, Some(param), output).await
+
+        if self.wire_compression == WireCompression::None {
+            return self.h2.download(path, Some(param), output).await;
+        }
+
+        let mut raw_data = Vec::new();
+        self.h2.download(path, Some(param), &mut raw_data).await?;
+
+        let max_size = 16 * 1024 * 1024 + std::mem::size_of::<EncryptedDataBlobHeader>();
+        let data = wire_compression::decode(self.wire_compression, raw_data, max_size)
+            .map_err(|err| format_err!("chunk {} - {err}", hex::encode(digest)))?;
+        output.write_all(&data)?;
+
+        Ok(())
     }
 
     pub fn force_close(self) {
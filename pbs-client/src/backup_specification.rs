@@ -1,13 +1,15 @@
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 
 use proxmox_schema::*;
 
+use pbs_api_types::BackupNamespace;
+
 const_regex! {
-    BACKUPSPEC_REGEX = r"^([a-zA-Z0-9_-]+\.(pxar|img|conf|log)):(.+)$";
+    BACKUPSPEC_REGEX = r"^([a-zA-Z0-9_-]+\.(pxar|img|conf|log|didx)):(.+)$";
 }
 
 pub const BACKUP_SOURCE_SCHEMA: Schema =
-    StringSchema::new("Backup source specification ([<label>:<path>]).")
+    StringSchema::new("Backup source specification ([<label>:<path>[#ns=<namespace>]]).")
         .format(&ApiStringFormat::Pattern(&BACKUPSPEC_REGEX))
         .schema();
 
@@ -16,32 +18,85 @@ pub enum BackupSpecificationType {
     IMAGE,
     CONFIG,
     LOGFILE,
+    /// Raw data stream of unknown size, stored as dynamic index. The source path `-` denotes
+    /// standard input.
+    STREAM,
 }
 
 pub struct BackupSpecification {
     pub archive_name: String,  // left part
     pub config_string: String, // right part
     pub spec_type: BackupSpecificationType,
+    /// Target namespace, overriding the one of the backup.
+    pub ns: Option<BackupNamespace>,
 }
 
 pub fn parse_backup_specification(value: &str) -> Result<BackupSpecification, Error> {
     if let Some(caps) = (BACKUPSPEC_REGEX.regex_obj)().captures(value) {
         let archive_name = caps.get(1).unwrap().as_str().into();
         let extension = caps.get(2).unwrap().as_str();
-        let config_string = caps.get(3).unwrap().as_str().into();
+        let (config_string, ns) = match caps.get(3).unwrap().as_str().rsplit_once("#ns=") {
+            Some((path, ns)) => {
+                let ns = ns.parse::<BackupNamespace>().map_err(|err| {
+                    format_err!("invalid namespace in backup specification '{value}' - {err}")
+                })?;
+                (path.to_string(), Some(ns))
+            }
+            None => (caps.get(3).unwrap().as_str().to_string(), None),
+        };
+        if config_string.is_empty() {
+            bail!("missing source path in backup specification '{value}'");
+        }
         let spec_type = match extension {
             "pxar" => BackupSpecificationType::PXAR,
             "img" => BackupSpecificationType::IMAGE,
             "conf" => BackupSpecificationType::CONFIG,
             "log" => BackupSpecificationType::LOGFILE,
+            "didx" => BackupSpecificationType::STREAM,
             _ => bail!("unknown backup source type '{}'", extension),
         };
         return Ok(BackupSpecification {
             archive_name,
             config_string,
             spec_type,
+            ns,
         });
     }
 
     bail!("unable to parse backup source specification '{}'", value);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_namespace_override() -> Result<(), Error> {
+        let spec = parse_backup_specification("root.pxar:/")?;
+        assert_eq!(spec.archive_name, "root.pxar");
+        assert_eq!(spec.config_string, "/");
+        assert!(matches!(spec.spec_type, BackupSpecificationType::PXAR));
+        assert!(spec.ns.is_none());
+
+        let spec = parse_backup_specification("data.pxar:/srv/data#ns=infra/data")?;
+        assert_eq!(spec.archive_name, "data.pxar");
+        assert_eq!(spec.config_string, "/srv/data");
+        assert_eq!(spec.ns, Some("infra/data".parse()?));
+
+        // only the last '#ns=' is the override, the path may contain it as well
+        let spec = parse_backup_specification("disk.img:/dev/a#ns=b#ns=c")?;
+        assert!(matches!(spec.spec_type, BackupSpecificationType::IMAGE));
+        assert_eq!(spec.config_string, "/dev/a#ns=b");
+        assert_eq!(spec.ns, Some("c".parse()?));
+
+        // an empty namespace is the root namespace
+        let spec = parse_backup_specification("etc.pxar:/etc#ns=")?;
+        assert_eq!(spec.ns, Some(BackupNamespace::root()));
+
+        assert!(parse_backup_specification("data.pxar:#ns=data").is_err());
+        assert!(parse_backup_specification("data.pxar:/srv#ns=in valid").is_err());
+        assert!(parse_backup_specification("data.pxar:/srv#ns=a/../b").is_err());
+
+        Ok(())
+    }
+}
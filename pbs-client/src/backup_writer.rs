@@ -7,18 +7,23 @@ use std::sync::{Arc, Mutex};
 use anyhow::{bail, format_err, Error};
 use futures::future::{self, AbortHandle, Either, FutureExt, TryFutureExt};
 use futures::stream::{Stream, StreamExt, TryStreamExt};
+use http::header::HeaderValue;
 use serde_json::{json, Value};
 use tokio::io::AsyncReadExt;
 use tokio::sync::{mpsc, oneshot};
 use tokio_stream::wrappers::ReceiverStream;
 
-use pbs_api_types::{BackupDir, BackupNamespace};
+use pbs_api_types::{BackupDir, BackupNamespace, WireCompression};
 use pbs_datastore::data_blob::{ChunkInfo, DataBlob, DataChunkBuilder};
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
-use pbs_datastore::{CATALOG_NAME, PROXMOX_BACKUP_PROTOCOL_ID_V1};
+use pbs_datastore::wire_compression;
+use pbs_datastore::{
+    BACKUP_CLIENT_HOSTNAME_HEADER, BACKUP_CLIENT_VERSION_HEADER, CATALOG_NAME,
+    PROXMOX_BACKUP_PROTOCOL_ID_V1,
+};
 use pbs_tools::crypt_config::CryptConfig;
 
 use proxmox_human_byte::HumanByte;
@@ -31,6 +36,7 @@ pub struct BackupWriter {
     h2: H2Client,
     abort: AbortHandle,
     crypt_config: Option<Arc<CryptConfig>>,
+    wire_compression: WireCompression,
 }
 
 impl Drop for BackupWriter {
@@ -44,6 +50,18 @@ pub struct BackupStats {
     pub csum: [u8; 32],
 }
 
+/// Statistics of an index upload from already encoded chunks
+pub struct IndexUploadStats {
+    /// Size and checksum of the uploaded index
+    pub stats: BackupStats,
+    /// Number of chunks referenced by the index
+    pub chunk_count: usize,
+    /// Number of chunks which had to be uploaded
+    pub uploaded_chunks: usize,
+    /// Decoded size of the uploaded chunks
+    pub uploaded_bytes: u64,
+}
+
 /// Options for uploading blobs/streams to the server
 #[derive(Default, Clone)]
 pub struct UploadOptions {
@@ -51,6 +69,11 @@ pub struct UploadOptions {
     pub compress: bool,
     pub encrypt: bool,
     pub fixed_size: Option<u64>,
+    /// Maximum number of chunk uploads in flight at the same time, defaults to 1.
+    pub upload_concurrency: Option<usize>,
+    /// Finished snapshots with the same group and time in other namespaces, whose chunks are
+    /// reused instead of being uploaded again.
+    pub sibling_manifests: Vec<(BackupNamespace, Arc<BackupManifest>)>,
 }
 
 struct UploadStats {
@@ -67,11 +90,17 @@ type UploadQueueSender = mpsc::Sender<(MergedChunkInfo, Option<h2::client::Respo
 type UploadResultReceiver = oneshot::Receiver<Result<(), Error>>;
 
 impl BackupWriter {
-    fn new(h2: H2Client, abort: AbortHandle, crypt_config: Option<Arc<CryptConfig>>) -> Arc<Self> {
+    fn new(
+        h2: H2Client,
+        abort: AbortHandle,
+        crypt_config: Option<Arc<CryptConfig>>,
+        wire_compression: WireCompression,
+    ) -> Arc<Self> {
         Arc::new(Self {
             h2,
             abort,
             crypt_config,
+            wire_compression,
         })
     }
 
@@ -85,6 +114,8 @@ impl BackupWriter {
         backup: &BackupDir,
         debug: bool,
         benchmark: bool,
+        ignore_quota: bool,
+        client_hostname: Option<&str>,
     ) -> Result<Arc<BackupWriter>, Error> {
         let mut param = json!({
             "backup-type": backup.ty(),
@@ -99,7 +130,12 @@ impl BackupWriter {
             param["ns"] = serde_json::to_value(ns)?;
         }
 
-        let req = HttpClient::request_builder(
+        // only send when set, older servers don't know about quotas
+        if ignore_quota {
+            param["ignore-quota"] = true.into();
+        }
+
+        let mut req = HttpClient::request_builder(
             client.server(),
             client.port(),
             "GET",
@@ -108,11 +144,37 @@ impl BackupWriter {
         )
         .unwrap();
 
-        let (h2, abort) = client
+        // informational only, so just skip values that are not valid header values
+        let client_version = format!(
+            "{}.{}",
+            pbs_buildcfg::PROXMOX_PKG_VERSION,
+            pbs_buildcfg::PROXMOX_PKG_RELEASE,
+        );
+        let headers = [
+            (BACKUP_CLIENT_VERSION_HEADER, Some(client_version.as_str())),
+            (BACKUP_CLIENT_HOSTNAME_HEADER, client_hostname),
+        ];
+        for (name, value) in headers {
+            if let Some(Ok(value)) = value.map(HeaderValue::from_str) {
+                req.headers_mut().insert(name, value);
+            }
+        }
+
+        let (h2, abort, wire_compression) = client
             .start_h2_connection(req, String::from(PROXMOX_BACKUP_PROTOCOL_ID_V1!()))
-            .await?;
+            .await
+            .map_err(|err| super::map_datastore_unavailable_error(err, datastore))?;
+
+        if wire_compression != WireCompression::None {
+            log::info!("using wire compression: {wire_compression}");
+        }
+
+        Ok(BackupWriter::new(h2, abort, crypt_config, wire_compression))
+    }
 
-        Ok(BackupWriter::new(h2, abort, crypt_config))
+    /// The compression of chunk data on the wire negotiated for this session.
+    pub fn wire_compression(&self) -> WireCompression {
+        self.wire_compression
     }
 
     pub async fn get(&self, path: &str, param: Option<Value>) -> Result<Value, Error> {
@@ -322,6 +384,28 @@ impl BackupWriter {
             }
         }
 
+        for (ns, manifest) in options.sibling_manifests.iter() {
+            for file in manifest.files() {
+                let same_type = match ArchiveType::from_path(&file.filename) {
+                    Ok(ArchiveType::FixedIndex) => options.fixed_size.is_some(),
+                    Ok(ArchiveType::DynamicIndex) => options.fixed_size.is_none(),
+                    _ => false,
+                };
+                if !same_type || file.filename == CATALOG_NAME {
+                    continue;
+                }
+                if let Err(err) = self
+                    .download_sibling_index(ns, &file.filename, manifest, known_chunks.clone())
+                    .await
+                {
+                    log::warn!(
+                        "Error downloading '{}' from namespace '{ns}': {err}",
+                        file.filename
+                    );
+                }
+            }
+        }
+
         let wid = self
             .h2
             .post(&index_path, Some(param))
@@ -341,6 +425,8 @@ impl BackupWriter {
                 None
             },
             options.compress,
+            self.wire_compression,
+            options.upload_concurrency.unwrap_or(1).max(1),
         )
         .await?;
 
@@ -412,6 +498,109 @@ impl BackupWriter {
         })
     }
 
+    /// Upload an index archive whose chunks are already encoded, e.g. read from a datastore.
+    ///
+    /// `chunks` lists the digest and the decoded size of every chunk in index order. Chunks which
+    /// are not in `known_chunks` are loaded with `load_chunk` and uploaded as they are, so
+    /// encrypted chunks can be transferred without having the key. For fixed indexes,
+    /// `options.fixed_size` must be set to the image size.
+    pub async fn upload_index_chunks<F>(
+        &self,
+        archive_name: &str,
+        chunks: Vec<([u8; 32], u64)>,
+        mut load_chunk: F,
+        known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+        options: UploadOptions,
+    ) -> Result<IndexUploadStats, Error>
+    where
+        F: FnMut(&[u8; 32]) -> Result<DataBlob, Error>,
+    {
+        let mut param = json!({ "archive-name": archive_name });
+        let prefix = if let Some(size) = options.fixed_size {
+            param["size"] = size.into();
+            "fixed"
+        } else {
+            "dynamic"
+        };
+
+        let chunk_count = chunks.len();
+        let mut csum = openssl::sha::Sha256::new();
+        let mut offset = 0;
+        let mut uploaded_chunks = 0;
+        let mut uploaded_bytes = 0;
+        let mut list = Vec::with_capacity(chunk_count);
+        {
+            let mut known_chunks = known_chunks.lock().unwrap();
+            for (digest, chunk_len) in chunks {
+                let chunk_end = offset + chunk_len;
+                if options.fixed_size.is_none() {
+                    csum.update(&chunk_end.to_le_bytes());
+                }
+                csum.update(&digest);
+
+                let is_new = known_chunks.insert(digest);
+                if is_new {
+                    uploaded_chunks += 1;
+                    uploaded_bytes += chunk_len;
+                }
+                list.push((offset, digest, chunk_len, is_new));
+                offset = chunk_end;
+            }
+        }
+        let size = offset;
+        let csum = csum.finish();
+
+        let wid = self
+            .h2
+            .post(&format!("{}_index", prefix), Some(param))
+            .await?
+            .as_u64()
+            .unwrap();
+
+        let chunk_stream = futures::stream::iter(list).map(
+            move |(offset, digest, chunk_len, is_new)| -> Result<_, Error> {
+                if !is_new {
+                    return Ok(MergedChunkInfo::Known(vec![(offset, digest)]));
+                }
+                let chunk = load_chunk(&digest)?;
+                Ok(MergedChunkInfo::New(ChunkInfo {
+                    chunk,
+                    digest,
+                    chunk_len,
+                    offset,
+                }))
+            },
+        );
+
+        Self::upload_merged_chunk_stream(
+            self.h2.clone(),
+            wid,
+            prefix,
+            chunk_stream,
+            self.wire_compression,
+            options.upload_concurrency.unwrap_or(1).max(1),
+        )
+        .await?;
+
+        let param = json!({
+            "wid": wid,
+            "chunk-count": chunk_count,
+            "size": size,
+            "csum": hex::encode(csum),
+        });
+        let _value = self
+            .h2
+            .post(&format!("{}_close", prefix), Some(param))
+            .await?;
+
+        Ok(IndexUploadStats {
+            stats: BackupStats { size, csum },
+            chunk_count,
+            uploaded_chunks,
+            uploaded_bytes,
+        })
+    }
+
     fn response_queue() -> (
         mpsc::Sender<h2::client::ResponseFuture>,
         oneshot::Receiver<Result<(), Error>>,
@@ -556,6 +745,48 @@ impl BackupWriter {
         Ok(index)
     }
 
+    /// Add the chunks of an index in the snapshot with the same group and time in namespace
+    /// `ns` to the known chunks, so that they are registered and not uploaded again.
+    pub async fn download_sibling_index(
+        &self,
+        ns: &BackupNamespace,
+        archive_name: &str,
+        manifest: &BackupManifest,
+        known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    ) -> Result<(), Error> {
+        let mut tmpfile = std::fs::OpenOptions::new()
+            .write(true)
+            .read(true)
+            .custom_flags(libc::O_TMPFILE)
+            .open("/tmp")?;
+
+        let param = json!({ "archive-name": archive_name, "ns": ns });
+        self.h2
+            .download("previous", Some(param), &mut tmpfile)
+            .await?;
+
+        let index: Box<dyn IndexFile> = match ArchiveType::from_path(archive_name)? {
+            ArchiveType::FixedIndex => Box::new(FixedIndexReader::new(tmpfile)?),
+            ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(tmpfile)?),
+            ArchiveType::Blob => bail!("'{archive_name}' is no index"),
+        };
+        // Note: do not use values stored in index (not trusted) - instead, computed them again
+        let (csum, size) = index.compute_csum();
+        manifest.verify_file(archive_name, &csum, size)?;
+
+        let mut known_chunks = known_chunks.lock().unwrap();
+        for i in 0..index.index_count() {
+            known_chunks.insert(*index.index_digest(i).unwrap());
+        }
+
+        log::debug!(
+            "{archive_name}: {} known chunks from namespace '{ns}'",
+            index.index_count()
+        );
+
+        Ok(())
+    }
+
     pub async fn download_previous_dynamic_index(
         &self,
         archive_name: &str,
@@ -637,6 +868,8 @@ impl BackupWriter {
         known_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
         crypt_config: Option<Arc<CryptConfig>>,
         compress: bool,
+        wire_compression: WireCompression,
+        concurrency: usize,
     ) -> impl Future<Output = Result<UploadStats, Error>> {
         let total_chunks = Arc::new(AtomicUsize::new(0));
         let total_chunks2 = total_chunks.clone();
@@ -650,67 +883,112 @@ impl BackupWriter {
         let reused_len = Arc::new(AtomicUsize::new(0));
         let reused_len2 = reused_len.clone();
 
-        let append_chunk_path = format!("{}_index", prefix);
-        let upload_chunk_path = format!("{}_chunk", prefix);
         let is_fixed_chunk_size = prefix == "fixed";
 
-        let (upload_queue, upload_result) =
-            Self::append_chunk_queue(h2.clone(), wid, append_chunk_path);
-
         let start_time = std::time::Instant::now();
 
         let index_csum = Arc::new(Mutex::new(Some(openssl::sha::Sha256::new())));
         let index_csum_2 = index_csum.clone();
 
-        stream
-            .and_then(move |data| {
-                let chunk_len = data.len();
+        let chunk_stream = stream.and_then(move |data| {
+            let chunk_len = data.len();
 
-                total_chunks.fetch_add(1, Ordering::SeqCst);
-                let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
+            total_chunks.fetch_add(1, Ordering::SeqCst);
+            let offset = stream_len.fetch_add(chunk_len, Ordering::SeqCst) as u64;
 
-                let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
+            let mut chunk_builder = DataChunkBuilder::new(data.as_ref()).compress(compress);
 
-                if let Some(ref crypt_config) = crypt_config {
-                    chunk_builder = chunk_builder.crypt_config(crypt_config);
-                }
+            if let Some(ref crypt_config) = crypt_config {
+                chunk_builder = chunk_builder.crypt_config(crypt_config);
+            }
 
-                let mut known_chunks = known_chunks.lock().unwrap();
-                let digest = chunk_builder.digest();
+            let mut known_chunks = known_chunks.lock().unwrap();
+            let digest = chunk_builder.digest();
 
-                let mut guard = index_csum.lock().unwrap();
-                let csum = guard.as_mut().unwrap();
+            let mut guard = index_csum.lock().unwrap();
+            let csum = guard.as_mut().unwrap();
 
-                let chunk_end = offset + chunk_len as u64;
+            let chunk_end = offset + chunk_len as u64;
 
-                if !is_fixed_chunk_size {
-                    csum.update(&chunk_end.to_le_bytes());
-                }
-                csum.update(digest);
+            if !is_fixed_chunk_size {
+                csum.update(&chunk_end.to_le_bytes());
+            }
+            csum.update(digest);
 
-                let chunk_is_known = known_chunks.contains(digest);
-                if chunk_is_known {
-                    known_chunk_count.fetch_add(1, Ordering::SeqCst);
-                    reused_len.fetch_add(chunk_len, Ordering::SeqCst);
-                    future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
-                } else {
-                    let compressed_stream_len2 = compressed_stream_len.clone();
-                    known_chunks.insert(*digest);
-                    future::ready(chunk_builder.build().map(move |(chunk, digest)| {
-                        compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
-                        MergedChunkInfo::New(ChunkInfo {
-                            chunk,
-                            digest,
-                            chunk_len: chunk_len as u64,
-                            offset,
-                        })
-                    }))
-                }
+            let chunk_is_known = known_chunks.contains(digest);
+            if chunk_is_known {
+                known_chunk_count.fetch_add(1, Ordering::SeqCst);
+                reused_len.fetch_add(chunk_len, Ordering::SeqCst);
+                future::ok(MergedChunkInfo::Known(vec![(offset, *digest)]))
+            } else {
+                let compressed_stream_len2 = compressed_stream_len.clone();
+                known_chunks.insert(*digest);
+                future::ready(chunk_builder.build().map(move |(chunk, digest)| {
+                    compressed_stream_len2.fetch_add(chunk.raw_size(), Ordering::SeqCst);
+                    MergedChunkInfo::New(ChunkInfo {
+                        chunk,
+                        digest,
+                        chunk_len: chunk_len as u64,
+                        offset,
+                    })
+                }))
+            }
+        });
+
+        let upload = Self::upload_merged_chunk_stream(
+            h2,
+            wid,
+            prefix,
+            chunk_stream,
+            wire_compression,
+            concurrency,
+        );
+
+        upload.and_then(move |_| {
+            let duration = start_time.elapsed();
+            let chunk_count = total_chunks2.load(Ordering::SeqCst);
+            let chunk_reused = known_chunk_count2.load(Ordering::SeqCst);
+            let size = stream_len2.load(Ordering::SeqCst);
+            let size_reused = reused_len2.load(Ordering::SeqCst);
+            let size_compressed = compressed_stream_len2.load(Ordering::SeqCst) as usize;
+
+            let mut guard = index_csum_2.lock().unwrap();
+            let csum = guard.take().unwrap().finish();
+
+            futures::future::ok(UploadStats {
+                chunk_count,
+                chunk_reused,
+                size,
+                size_reused,
+                size_compressed,
+                duration,
+                csum,
             })
-            .merge_known_chunks()
-            .try_for_each(move |merged_chunk_info| {
-                let upload_queue = upload_queue.clone();
+        })
+    }
+
+    /// Upload the new chunks of `stream` and append all chunks to the index `wid`.
+    ///
+    /// Up to `concurrency` chunk uploads are kept in flight at the same time. The chunk data is
+    /// encoded with `wire_compression` for the transfer, the announced encoded size stays the one
+    /// of the chunk blob.
+    fn upload_merged_chunk_stream(
+        h2: H2Client,
+        wid: u64,
+        prefix: &str,
+        stream: impl Stream<Item = Result<MergedChunkInfo, Error>>,
+        wire_compression: WireCompression,
+        concurrency: usize,
+    ) -> impl Future<Output = Result<(), Error>> {
+        let append_chunk_path = format!("{}_index", prefix);
+        let upload_chunk_path = format!("{}_chunk", prefix);
+
+        let (upload_queue, upload_result) =
+            Self::append_chunk_queue(h2.clone(), wid, append_chunk_path);
 
+        stream
+            .merge_known_chunks()
+            .map_ok(move |merged_chunk_info| {
                 if let MergedChunkInfo::New(chunk_info) = merged_chunk_info {
                     let offset = chunk_info.offset;
                     let digest = chunk_info.digest;
@@ -740,55 +1018,41 @@ impl BackupWriter {
                         Some(ct),
                     )
                     .unwrap();
+                    let chunk_data = match wire_compression::encode(wire_compression, chunk_data) {
+                        Ok(data) => data,
+                        Err(err) => return Either::Right(future::err(err)),
+                    };
                     let upload_data = Some(bytes::Bytes::from(chunk_data));
 
                     let new_info = MergedChunkInfo::Known(vec![(offset, digest)]);
 
-                    Either::Left(h2.send_request(request, upload_data).and_then(
-                        move |response| async move {
-                            upload_queue
-                                .send((new_info, Some(response)))
-                                .await
-                                .map_err(|err| {
-                                    format_err!("failed to send to upload queue: {}", err)
-                                })
-                        },
-                    ))
+                    Either::Left(
+                        h2.send_request(request, upload_data)
+                            .map_ok(move |response| (new_info, Some(response))),
+                    )
                 } else {
-                    Either::Right(async move {
-                        upload_queue
-                            .send((merged_chunk_info, None))
-                            .await
-                            .map_err(|err| format_err!("failed to send to upload queue: {}", err))
-                    })
+                    Either::Right(future::ok((merged_chunk_info, None)))
                 }
             })
-            .then(move |result| async move { upload_result.await?.and(result) }.boxed())
-            .and_then(move |_| {
-                let duration = start_time.elapsed();
-                let chunk_count = total_chunks2.load(Ordering::SeqCst);
-                let chunk_reused = known_chunk_count2.load(Ordering::SeqCst);
-                let size = stream_len2.load(Ordering::SeqCst);
-                let size_reused = reused_len2.load(Ordering::SeqCst);
-                let size_compressed = compressed_stream_len2.load(Ordering::SeqCst) as usize;
-
-                let mut guard = index_csum_2.lock().unwrap();
-                let csum = guard.take().unwrap().finish();
-
-                futures::future::ok(UploadStats {
-                    chunk_count,
-                    chunk_reused,
-                    size,
-                    size_reused,
-                    size_compressed,
-                    duration,
-                    csum,
-                })
+            // keep up to `concurrency` chunk uploads in flight, results are still returned in
+            // stream order so the index gets appended in the correct order
+            .try_buffered(concurrency)
+            .try_for_each(move |(merged_chunk_info, response)| {
+                let upload_queue = upload_queue.clone();
+                async move {
+                    upload_queue
+                        .send((merged_chunk_info, response))
+                        .await
+                        .map_err(|err| format_err!("failed to send to upload queue: {}", err))
+                }
             })
+            .then(move |result| async move { upload_result.await?.and(result) }.boxed())
     }
 
     /// Upload speed test - prints result to stderr
-    pub async fn upload_speedtest(&self) -> Result<f64, Error> {
+    ///
+    /// Up to `concurrency` test uploads are kept in flight at the same time.
+    pub async fn upload_speedtest(&self, concurrency: usize) -> Result<f64, Error> {
         let mut data = vec![];
         // generate pseudo random byte sequence
         for i in 0..1024 * 1024 {
@@ -799,29 +1063,37 @@ impl BackupWriter {
         }
 
         let item_len = data.len();
+        let data = bytes::Bytes::from(data);
 
-        let mut repeat = 0;
+        let repeat = AtomicUsize::new(0);
 
         let (upload_queue, upload_result) = Self::response_queue();
 
         let start_time = std::time::Instant::now();
 
-        loop {
-            repeat += 1;
-            if start_time.elapsed().as_secs() >= 5 {
-                break;
-            }
-
-            log::debug!("send test data ({} bytes)", data.len());
-            let request =
-                H2Client::request_builder("localhost", "POST", "speedtest", None, None).unwrap();
-            let request_future = self
-                .h2
-                .send_request(request, Some(bytes::Bytes::from(data.clone())))
-                .await?;
+        futures::stream::repeat(())
+            .take_while(|_| future::ready(start_time.elapsed().as_secs() < 5))
+            .map(|_| {
+                repeat.fetch_add(1, Ordering::SeqCst);
+                log::debug!("send test data ({} bytes)", item_len);
+                let request =
+                    H2Client::request_builder("localhost", "POST", "speedtest", None, None)
+                        .unwrap();
+                self.h2.send_request(request, Some(data.clone()))
+            })
+            .buffered(concurrency.max(1))
+            .try_for_each(|request_future| {
+                let upload_queue = upload_queue.clone();
+                async move {
+                    upload_queue
+                        .send(request_future)
+                        .await
+                        .map_err(|err| format_err!("failed to send to response queue: {}", err))
+                }
+            })
+            .await?;
 
-            upload_queue.send(request_future).await?;
-        }
+        let repeat = repeat.load(Ordering::SeqCst).max(1);
 
         drop(upload_queue); // close queue
 
@@ -832,7 +1104,7 @@ impl BackupWriter {
             repeat,
             start_time.elapsed().as_secs()
         );
-        let speed = ((item_len * (repeat as usize)) as f64) / start_time.elapsed().as_secs_f64();
+        let speed = ((item_len * repeat) as f64) / start_time.elapsed().as_secs_f64();
         log::info!(
             "Time per request: {} microseconds.",
             (start_time.elapsed().as_micros()) / (repeat as u128)
@@ -0,0 +1,136 @@
+//! Parallel download of chunks which are read sequentially, like for image restores.
+
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use futures::stream::{self, Stream, StreamExt};
+
+use pbs_datastore::read_chunk::AsyncReadChunk;
+use pbs_tools::lru_cache::LruCache;
+
+/// Default number of chunk requests kept in flight.
+pub const DEFAULT_CHUNK_PREFETCH: usize = 4;
+
+type ChunkFuture<'a> = Shared<BoxFuture<'a, Result<Arc<Vec<u8>>, Arc<Error>>>>;
+
+/// Read the chunks identified by `digests` with up to `window` requests in flight.
+///
+/// The decoded chunks are returned in the order of `digests`. Digests which occur again within
+/// the last `window` distinct chunks share a single download. At most `2 * window` chunks are
+/// held in memory. Dropping the stream cancels all outstanding requests.
+pub fn prefetch_chunks<'a, R, I>(
+    reader: &'a R,
+    digests: I,
+    window: usize,
+) -> impl Stream<Item = Result<Arc<Vec<u8>>, Error>> + Send + 'a
+where
+    R: AsyncReadChunk,
+    I: IntoIterator<Item = [u8; 32]>,
+    I::IntoIter: Send + 'a,
+{
+    let window = window.max(1);
+    let mut recent: LruCache<[u8; 32], ChunkFuture<'a>> = LruCache::new(window);
+
+    stream::iter(digests)
+        .map(move |digest| {
+            if let Some(chunk) = recent.get_mut(digest) {
+                return chunk.clone();
+            }
+            let chunk = async move {
+                reader
+                    .read_chunk(&digest)
+                    .await
+                    .map(Arc::new)
+                    .map_err(Arc::new)
+            }
+            .boxed()
+            .shared();
+            recent.insert(digest, chunk.clone());
+            chunk
+        })
+        .buffered(window)
+        .map(|result| result.map_err(|err| format_err!("{err:#}")))
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::bail;
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use pbs_datastore::DataBlob;
+
+    use super::*;
+
+    fn yield_now() -> impl Future<Output = ()> {
+        let mut yielded = false;
+        futures::future::poll_fn(move |cx| {
+            if yielded {
+                return std::task::Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        })
+    }
+
+    #[derive(Default)]
+    struct CountingReader {
+        reads: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl AsyncReadChunk for CountingReader {
+        fn read_raw_chunk<'a>(
+            &'a self,
+            _digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<DataBlob, Error>> + Send + 'a>> {
+            Box::pin(async { bail!("not implemented") })
+        }
+
+        fn read_chunk<'a>(
+            &'a self,
+            digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>> {
+            Box::pin(async move {
+                self.reads.fetch_add(1, Ordering::SeqCst);
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                // give the other requests a chance to start
+                for _ in 0..4 {
+                    yield_now().await;
+                }
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(digest.to_vec())
+            })
+        }
+    }
+
+    #[test]
+    fn test_prefetch_order_and_duplicates() -> Result<(), Error> {
+        let digests: Vec<[u8; 32]> = [1u8, 2, 3, 2, 4, 5, 6, 1, 7, 8]
+            .iter()
+            .map(|i| [*i; 32])
+            .collect();
+
+        let reader = CountingReader::default();
+        let chunks: Vec<Arc<Vec<u8>>> =
+            block_on(prefetch_chunks(&reader, digests.clone(), 3).try_collect())?;
+
+        let expected: Vec<Vec<u8>> = digests.iter().map(|digest| digest.to_vec()).collect();
+        let chunks: Vec<Vec<u8>> = chunks.iter().map(|chunk| chunk.to_vec()).collect();
+        assert_eq!(chunks, expected);
+
+        // the second '2' is within the window, the second '1' is not
+        assert_eq!(reader.reads.load(Ordering::SeqCst), 9);
+        assert!(reader.max_in_flight.load(Ordering::SeqCst) <= 3);
+
+        Ok(())
+    }
+}
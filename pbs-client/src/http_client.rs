@@ -1,4 +1,6 @@
-use std::io::{IsTerminal, Write};
+use std::fs::File;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
 
@@ -6,7 +8,7 @@ use anyhow::{bail, format_err, Error};
 use futures::*;
 use http::header::HeaderValue;
 use http::Uri;
-use http::{Request, Response};
+use http::{Request, Response, StatusCode};
 use hyper::client::{Client, HttpConnector};
 use hyper::Body;
 use openssl::{
@@ -14,10 +16,10 @@ use openssl::{
     x509::X509StoreContextRef,
 };
 use percent_encoding::percent_encode;
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
 use xdg::BaseDirectories;
 
-use proxmox_router::HttpError;
 use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
 use proxmox_sys::linux::tty;
 
@@ -27,7 +29,10 @@ use proxmox_http::uri::{build_authority, json_object_to_query};
 use proxmox_http::{ProxyConfig, RateLimiter};
 
 use pbs_api_types::percent_encoding::DEFAULT_ENCODE_SET;
-use pbs_api_types::{Authid, RateLimitConfig, Userid};
+use pbs_api_types::{ApiErrorCode, Authid, RateLimitConfig, Userid, WireCompression};
+use pbs_datastore::{
+    wire_compression, BACKUP_CONTENT_SHA256_HEADER, BACKUP_WIRE_COMPRESSION_HEADER,
+};
 
 use super::pipe_to_stream::PipeToSendStream;
 use super::PROXMOX_BACKUP_TCP_KEEPALIVE_TIME;
@@ -52,6 +57,7 @@ pub struct HttpClientOptions {
     fingerprint_cache: bool,
     verify_cert: bool,
     limit: RateLimitConfig,
+    wire_compression: Option<WireCompression>,
 }
 
 impl HttpClientOptions {
@@ -114,6 +120,12 @@ impl HttpClientOptions {
         self.limit = rate_limit;
         self
     }
+
+    /// Preferred compression of chunk data in backup and reader protocol sessions.
+    pub fn wire_compression(mut self, wire_compression: Option<WireCompression>) -> Self {
+        self.wire_compression = wire_compression;
+        self
+    }
 }
 
 impl Default for HttpClientOptions {
@@ -127,6 +139,7 @@ impl Default for HttpClientOptions {
             fingerprint_cache: false,
             verify_cert: true,
             limit: RateLimitConfig::default(), // unlimited
+            wire_compression: None,
         }
     }
 }
@@ -140,7 +153,7 @@ pub struct HttpClient {
     first_auth: Option<BroadcastFuture<()>>,
     auth: Arc<RwLock<AuthInfo>>,
     ticket_abort: futures::future::AbortHandle,
-    _options: HttpClientOptions,
+    options: HttpClientOptions,
 }
 
 /// Delete stored ticket data (logout)
@@ -306,6 +319,67 @@ fn build_uri(server: &str, port: u16, path: &str, query: Option<String>) -> Resu
         .map_err(|err| format_err!("error building uri - {}", err))
 }
 
+/// Error response of a server, with the stable [`ApiErrorCode`] if it sent one.
+#[derive(Debug)]
+pub struct ApiResponseError {
+    pub status: StatusCode,
+    /// The human readable error message.
+    pub message: String,
+    pub code: Option<ApiErrorCode>,
+}
+
+impl ApiResponseError {
+    /// Parse the body of an error response, either the plain message or a JSON object with the
+    /// `message` and optionally the `code`.
+    pub fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(body);
+        if let Ok(data) = serde_json::from_str::<Value>(&text) {
+            if let Some(message) = data["message"].as_str() {
+                return Self {
+                    status,
+                    message: message.to_string(),
+                    code: data["code"].as_str().and_then(|code| code.parse().ok()),
+                };
+            }
+        }
+        Self {
+            status,
+            message: text.into_owned(),
+            code: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiResponseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ApiResponseError {}
+
+/// Get the stable [`ApiErrorCode`] of an error returned by the server, if it sent one.
+///
+/// Prefer this over matching on the error message, which may change between versions.
+pub fn api_error_code(err: &Error) -> Option<ApiErrorCode> {
+    err.downcast_ref::<ApiResponseError>()?.code
+}
+
+/// Map the `503 Service Unavailable` responses a server sends if the datastore is in a
+/// maintenance mode not permitting the requested access to an error naming the datastore.
+///
+/// Other errors are passed through unchanged, the error code (see [`api_error_code`]) is kept.
+pub fn map_datastore_unavailable_error(err: Error, store: &str) -> Error {
+    match err.downcast_ref::<ApiResponseError>() {
+        Some(ApiResponseError {
+            status, message, ..
+        }) if *status == StatusCode::SERVICE_UNAVAILABLE && message.trim().is_empty() => {
+            format_err!("datastore '{store}' is currently unavailable (maintenance mode)")
+        }
+        _ => err,
+    }
+}
+
 impl HttpClient {
     pub fn new(
         server: &str,
@@ -373,19 +447,17 @@ impl HttpClient {
             PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
         );
 
-        if let Some(rate_in) = options.limit.rate_in {
-            let burst_in = options.limit.burst_in.unwrap_or(rate_in).as_u64();
+        if let Some((rate_in, burst_in)) = options.limit.effective_in() {
             https.set_read_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
                 rate_in.as_u64(),
-                burst_in,
+                burst_in.as_u64(),
             )))));
         }
 
-        if let Some(rate_out) = options.limit.rate_out {
-            let burst_out = options.limit.burst_out.unwrap_or(rate_out).as_u64();
+        if let Some((rate_out, burst_out)) = options.limit.effective_out() {
             https.set_write_limiter(Some(Arc::new(Mutex::new(RateLimiter::new(
                 rate_out.as_u64(),
-                burst_out,
+                burst_out.as_u64(),
             )))));
         }
 
@@ -521,7 +593,7 @@ impl HttpClient {
             auth,
             ticket_abort,
             first_auth,
-            _options: options,
+            options,
         })
     }
 
@@ -629,6 +701,12 @@ impl HttpClient {
         let client = self.client.clone();
 
         let auth = self.login().await?;
+        Self::set_auth_headers(&mut req, &auth);
+
+        Self::api_request(client, req).await
+    }
+
+    fn set_auth_headers(req: &mut Request<Body>, auth: &AuthInfo) {
         if auth.auth_id.is_token() {
             let enc_api_token = format!(
                 "PBSAPIToken {}:{}",
@@ -651,8 +729,6 @@ impl HttpClient {
                 HeaderValue::from_str(&auth.token).unwrap(),
             );
         }
-
-        Self::api_request(client, req).await
     }
 
     pub async fn get(&self, path: &str, data: Option<Value>) -> Result<Value, Error> {
@@ -675,7 +751,83 @@ impl HttpClient {
         self.request(req).await
     }
 
+    /// Download a file to `output`.
+    ///
+    /// If the server sends the digest of the file, the downloaded data is verified with it.
     pub async fn download(&self, path: &str, output: &mut (dyn Write + Send)) -> Result<(), Error> {
+        let resp = self.start_download(path, 0).await?;
+        if !resp.status().is_success() {
+            HttpClient::api_response(resp)
+                .map(|_| Err(format_err!("unknown error")))
+                .await?
+        }
+
+        let expected = Self::content_sha256(&resp)?;
+        let hasher = expected.map(|_| openssl::sha::Sha256::new());
+        Self::receive_download(resp, output, hasher, expected).await
+    }
+
+    /// Download a file to `output` like [`download`](Self::download), resuming a partial download.
+    ///
+    /// Data already present in `output` is kept, only the remaining part of the file is requested
+    /// with a `Range` header. If the server sends the complete file instead, `output` is truncated
+    /// first. With a digest sent by the server, the complete file is verified, including the data
+    /// from previous attempts. If that fails, `output` has to be discarded.
+    pub async fn download_resume(&self, path: &str, output: &mut File) -> Result<(), Error> {
+        let offset = output.seek(SeekFrom::End(0))?;
+
+        let mut resp = self.start_download(path, offset).await?;
+        if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // the partial download is not shorter than the file, start over
+            resp = self.start_download(path, 0).await?;
+        }
+        if !resp.status().is_success() {
+            HttpClient::api_response(resp)
+                .map(|_| Err(format_err!("unknown error")))
+                .await?
+        }
+
+        Self::receive_resumed_download(resp, output, offset).await
+    }
+
+    /// Write a successful download response to `output`, which already contains the first
+    /// `offset` bytes of the file.
+    ///
+    /// Unless the response only contains the data after `offset`, `output` is truncated first.
+    async fn receive_resumed_download(
+        resp: Response<Body>,
+        output: &mut File,
+        mut offset: u64,
+    ) -> Result<(), Error> {
+        if resp.status() == StatusCode::PARTIAL_CONTENT {
+            let content_range = resp
+                .headers()
+                .get(http::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .ok_or_else(|| format_err!("partial response without content range"))?;
+            let (range, _size) = parse_content_range(content_range)?;
+            if range.start != offset {
+                bail!(
+                    "server sent data starting at {}, expected {offset}",
+                    range.start
+                );
+            }
+        } else if offset > 0 {
+            offset = 0;
+            output.set_len(0)?;
+        }
+        output.seek(SeekFrom::Start(offset))?;
+
+        let expected = Self::content_sha256(&resp)?;
+        let hasher = match expected {
+            Some(_) => Some(hash_file_prefix(output, offset)?),
+            None => None,
+        };
+        Self::receive_download(resp, output, hasher, expected).await
+    }
+
+    /// Send a `GET` request for downloading a file, for the data after `offset` only if set.
+    async fn start_download(&self, path: &str, offset: u64) -> Result<Response<Body>, Error> {
         let mut req = Self::request_builder(&self.server, self.port, "GET", path, None)?;
 
         let client = self.client.clone();
@@ -688,27 +840,103 @@ impl HttpClient {
         );
         req.headers_mut()
             .insert("Cookie", HeaderValue::from_str(&enc_ticket).unwrap());
+        if offset > 0 {
+            req.headers_mut().insert(
+                http::header::RANGE,
+                HeaderValue::from_str(&format!("bytes={offset}-")).unwrap(),
+            );
+        }
 
-        let resp = tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
+        tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
             .await
-            .map_err(|_| format_err!("http download request timed out"))??;
-        let status = resp.status();
-        if !status.is_success() {
-            HttpClient::api_response(resp)
-                .map(|_| Err(format_err!("unknown error")))
-                .await?
-        } else {
-            resp.into_body()
-                .map_err(Error::from)
-                .try_fold(output, move |acc, chunk| async move {
-                    acc.write_all(&chunk)?;
-                    Ok::<_, Error>(acc)
-                })
-                .await?;
+            .map_err(|_| format_err!("http download request timed out"))?
+            .map_err(Error::from)
+    }
+
+    /// Returns the digest of the complete file sent by the server with a download response.
+    fn content_sha256(resp: &Response<Body>) -> Result<Option<[u8; 32]>, Error> {
+        let value = match resp.headers().get(BACKUP_CONTENT_SHA256_HEADER) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut digest = [0u8; 32];
+        hex::decode_to_slice(value.as_bytes(), &mut digest)
+            .map_err(|err| format_err!("invalid content digest in response - {err}"))?;
+        Ok(Some(digest))
+    }
+
+    /// Write the body of a download response to `output`.
+    ///
+    /// `hasher` already contains the data of the file preceding the response, if it is set, the
+    /// complete file is verified against the `expected` digest.
+    async fn receive_download(
+        resp: Response<Body>,
+        output: &mut (dyn Write + Send),
+        mut hasher: Option<openssl::sha::Sha256>,
+        expected: Option<[u8; 32]>,
+    ) -> Result<(), Error> {
+        let mut body = resp.into_body();
+        while let Some(chunk) = body.try_next().await? {
+            output.write_all(&chunk)?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+        }
+
+        if let (Some(hasher), Some(expected)) = (hasher, expected) {
+            if hasher.finish() != expected {
+                bail!("downloaded file does not match the digest sent by the server");
+            }
         }
         Ok(())
     }
 
+    /// Perform a `GET` request on an API call returning newline delimited JSON records, like
+    /// `admin/datastore/{store}/snapshots-stream`.
+    ///
+    /// Every record is deserialized and passed to `callback` as soon as its line was received, so
+    /// the whole response never has to be kept in memory.
+    pub async fn get_json_lines<T, F>(
+        &self,
+        path: &str,
+        data: Option<Value>,
+        mut callback: F,
+    ) -> Result<(), Error>
+    where
+        T: DeserializeOwned,
+        F: FnMut(T) -> Result<(), Error>,
+    {
+        let mut req = Self::request_builder(&self.server, self.port, "GET", path, data)?;
+
+        let auth = self.login().await?;
+        Self::set_auth_headers(&mut req, &auth);
+
+        let resp = tokio::time::timeout(HTTP_TIMEOUT, self.client.request(req))
+            .await
+            .map_err(|_| format_err!("http request timed out"))??;
+
+        if !resp.status().is_success() {
+            HttpClient::api_response(resp).await?;
+            bail!("unknown error");
+        }
+
+        let mut body = resp.into_body();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = body.try_next().await? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                callback(serde_json::from_slice(&line)?)?;
+            }
+        }
+
+        if !buffer.is_empty() {
+            bail!("stream ended with an incomplete record");
+        }
+
+        Ok(())
+    }
+
     pub async fn upload(
         &self,
         content_type: &str,
@@ -733,11 +961,15 @@ impl HttpClient {
         self.request(req).await
     }
 
+    /// Upgrade `req` to a HTTP/2 connection of the protocol `protocol_name`.
+    ///
+    /// Also returns the wire compression negotiated with the server, always
+    /// [`WireCompression::None`] if no compression was requested in the client options.
     pub async fn start_h2_connection(
         &self,
         mut req: Request<Body>,
         protocol_name: String,
-    ) -> Result<(H2Client, futures::future::AbortHandle), Error> {
+    ) -> Result<(H2Client, futures::future::AbortHandle, WireCompression), Error> {
         let client = self.client.clone();
         let auth = self.login().await?;
 
@@ -769,6 +1001,14 @@ impl HttpClient {
         req.headers_mut()
             .insert("UPGRADE", HeaderValue::from_str(&protocol_name).unwrap());
 
+        if let Some(preferred) = self.options.wire_compression {
+            let offer = wire_compression::client_offer(preferred);
+            req.headers_mut().insert(
+                BACKUP_WIRE_COMPRESSION_HEADER,
+                HeaderValue::from_str(&offer).unwrap(),
+            );
+        }
+
         let resp = tokio::time::timeout(HTTP_TIMEOUT, client.request(req))
             .await
             .map_err(|_| format_err!("http upgrade request timed out"))??;
@@ -779,6 +1019,16 @@ impl HttpClient {
             bail!("unknown error");
         }
 
+        // older servers do not answer the offer, so they get uncompressed data
+        let wire_compression = match resp.headers().get(BACKUP_WIRE_COMPRESSION_HEADER) {
+            Some(value) if self.options.wire_compression.is_some() => value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<WireCompression>().ok())
+                .ok_or_else(|| format_err!("server selected unknown wire compression {value:?}"))?,
+            _ => WireCompression::None,
+        };
+
         let upgraded = hyper::upgrade::on(resp).await?;
 
         let max_window_size = (1 << 31) - 2;
@@ -803,7 +1053,7 @@ impl HttpClient {
 
         // Wait until the `SendRequest` handle has available capacity.
         let c = h2.ready().await?;
-        Ok((H2Client::new(c), abort))
+        Ok((H2Client::new(c), abort, wire_compression))
     }
 
     async fn credentials(
@@ -838,16 +1088,16 @@ impl HttpClient {
         let status = response.status();
         let data = hyper::body::to_bytes(response.into_body()).await?;
 
+        if !status.is_success() {
+            return Err(ApiResponseError::from_response(status, &data).into());
+        }
+
         let text = String::from_utf8(data.to_vec()).unwrap();
-        if status.is_success() {
-            if text.is_empty() {
-                Ok(Value::Null)
-            } else {
-                let value: Value = serde_json::from_str(&text)?;
-                Ok(value)
-            }
+        if text.is_empty() {
+            Ok(Value::Null)
         } else {
-            Err(Error::from(HttpError::new(status, text)))
+            let value: Value = serde_json::from_str(&text)?;
+            Ok(value)
         }
     }
 
@@ -1045,21 +1295,21 @@ impl H2Client {
             data.extend(chunk);
         }
 
+        if !status.is_success() {
+            return Err(ApiResponseError::from_response(status, &data).into());
+        }
+
         let text = String::from_utf8(data.to_vec()).unwrap();
-        if status.is_success() {
-            if text.is_empty() {
-                Ok(Value::Null)
-            } else {
-                let mut value: Value = serde_json::from_str(&text)?;
-                if let Some(map) = value.as_object_mut() {
-                    if let Some(data) = map.remove("data") {
-                        return Ok(data);
-                    }
+        if text.is_empty() {
+            Ok(Value::Null)
+        } else {
+            let mut value: Value = serde_json::from_str(&text)?;
+            if let Some(map) = value.as_object_mut() {
+                if let Some(data) = map.remove("data") {
+                    return Ok(data);
                 }
-                bail!("got result without data property");
             }
-        } else {
-            Err(Error::from(HttpError::new(status, text)))
+            bail!("got result without data property");
         }
     }
 
@@ -1099,3 +1349,194 @@ impl H2Client {
         Ok(request)
     }
 }
+
+/// Hash the first `len` bytes of `file`, leaving its position at `len`.
+fn hash_file_prefix(file: &mut File, len: u64) -> Result<openssl::sha::Sha256, Error> {
+    let mut hasher = openssl::sha::Sha256::new();
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut reader = (&*file).take(len);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    if file.stream_position()? != len {
+        bail!("partial download is shorter than expected");
+    }
+    Ok(hasher)
+}
+
+/// Parse the `Content-Range` header of a partial response, returning the range of the data and
+/// the size of the complete file.
+fn parse_content_range(value: &str) -> Result<(Range<u64>, u64), Error> {
+    let parse = || -> Option<(Range<u64>, u64)> {
+        let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = end.parse().ok()?;
+        let size: u64 = size.parse().ok()?;
+        (start <= end && end < size).then_some((start..(end + 1), size))
+    };
+    parse().ok_or_else(|| format_err!("invalid content range '{value}' in response"))
+}
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    const TEST_DIR: &str = ".testdir-download-resume";
+
+    fn test_file(name: &str, data: &[u8]) -> File {
+        let mut path = std::fs::canonicalize(".").unwrap();
+        path.push(TEST_DIR);
+        std::fs::create_dir_all(&path).unwrap();
+        path.push(name);
+
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        file.write_all(data).unwrap();
+        file
+    }
+
+    fn response(
+        data: &[u8],
+        range: Option<(u64, u64)>,
+        sha256: Option<&[u8; 32]>,
+    ) -> Response<Body> {
+        let mut builder = Response::builder();
+        builder = match range {
+            Some((start, size)) => builder.status(StatusCode::PARTIAL_CONTENT).header(
+                http::header::CONTENT_RANGE,
+                format!("bytes {start}-{}/{size}", start + data.len() as u64 - 1),
+            ),
+            None => builder.status(StatusCode::OK),
+        };
+        if let Some(sha256) = sha256 {
+            builder = builder.header(BACKUP_CONTENT_SHA256_HEADER, hex::encode(sha256));
+        }
+        builder.body(Body::from(data.to_vec())).unwrap()
+    }
+
+    fn resume(output: &mut File, resp: Response<Body>) -> Result<Vec<u8>, Error> {
+        let offset = output.seek(SeekFrom::End(0))?;
+        block_on(HttpClient::receive_resumed_download(resp, output, offset))?;
+
+        let mut data = Vec::new();
+        output.seek(SeekFrom::Start(0))?;
+        output.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_download_resume() -> Result<(), Error> {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let size = data.len() as u64;
+        let sha256 = openssl::sha::sha256(&data);
+
+        // the remaining data is appended and the complete file is verified
+        let mut output = test_file("partial", &data[..40_000]);
+        let resp = response(&data[40_000..], Some((40_000, size)), Some(&sha256));
+        assert!(resume(&mut output, resp)? == data);
+
+        // without range support, the complete file is sent and replaces the partial download
+        let mut output = test_file("full", &data[..40_000]);
+        let resp = response(&data, None, Some(&sha256));
+        assert!(resume(&mut output, resp)? == data);
+
+        // corrupted data of the previous attempt is detected
+        let mut partial = data[..40_000].to_vec();
+        partial[1000] ^= 1;
+        let mut output = test_file("corrupt", &partial);
+        let resp = response(&data[40_000..], Some((40_000, size)), Some(&sha256));
+        let err = resume(&mut output, resp).unwrap_err();
+        assert!(err.to_string().contains("does not match the digest"));
+
+        // without a digest, the data is not verified
+        let mut output = test_file("unverified", &partial);
+        let resp = response(&data[40_000..], Some((40_000, size)), None);
+        assert_eq!(resume(&mut output, resp)?.len(), data.len());
+
+        // data for a different offset is rejected
+        let mut output = test_file("offset", &data[..40_000]);
+        let resp = response(&data[30_000..], Some((30_000, size)), Some(&sha256));
+        let err = resume(&mut output, resp).unwrap_err();
+        assert!(err.to_string().contains("expected 40000"));
+
+        let _ = std::fs::remove_dir_all(TEST_DIR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(
+            parse_content_range("bytes 4500-13329/13330").unwrap(),
+            (4500..13330, 13330)
+        );
+        assert_eq!(parse_content_range("bytes 0-0/1").unwrap(), (0..1, 1));
+        assert!(parse_content_range("bytes 10-9/100").is_err());
+        assert!(parse_content_range("bytes 0-100/100").is_err());
+        assert!(parse_content_range("bytes */100").is_err());
+        assert!(parse_content_range("items 0-9/100").is_err());
+    }
+
+    #[test]
+    fn test_api_response_error() {
+        let body = br#"{"message":"no such datastore 'test'","code":"not-found"}"#;
+        let err = ApiResponseError::from_response(StatusCode::NOT_FOUND, body);
+        assert_eq!(err.message, "no such datastore 'test'");
+        assert_eq!(err.code, Some(ApiErrorCode::NotFound));
+        assert_eq!(api_error_code(&err.into()), Some(ApiErrorCode::NotFound));
+
+        // older servers only send the message
+        let err = ApiResponseError::from_response(StatusCode::BAD_REQUEST, b"some error\n");
+        assert_eq!(err.to_string(), "some error\n");
+        assert_eq!(err.code, None);
+
+        let body = br#"{"message":"from a newer server","code":"unknown-code"}"#;
+        let err = ApiResponseError::from_response(StatusCode::CONFLICT, body);
+        assert_eq!(err.message, "from a newer server");
+        assert_eq!(err.code, None);
+    }
+
+    #[test]
+    fn test_map_datastore_unavailable_error() {
+        let unavailable = |body: &[u8]| {
+            let err = ApiResponseError::from_response(StatusCode::SERVICE_UNAVAILABLE, body);
+            map_datastore_unavailable_error(err.into(), "store1")
+        };
+
+        let body = json!({
+            "message": "datastore 'store1' is in offline maintenance mode: disk swap",
+            "code": "maintenance-mode",
+        });
+        let err = unavailable(body.to_string().as_bytes());
+        assert_eq!(
+            err.to_string(),
+            "datastore 'store1' is in offline maintenance mode: disk swap"
+        );
+        assert_eq!(api_error_code(&err), Some(ApiErrorCode::MaintenanceMode));
+
+        let err = unavailable(b"");
+        assert_eq!(
+            err.to_string(),
+            "datastore 'store1' is currently unavailable (maintenance mode)"
+        );
+
+        let err = ApiResponseError::from_response(StatusCode::BAD_REQUEST, b"other error");
+        let err = map_datastore_unavailable_error(err.into(), "store1");
+        assert_eq!(err.to_string(), "other error");
+    }
+}
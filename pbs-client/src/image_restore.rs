@@ -0,0 +1,226 @@
+//! Restore of fixed index (image) archives, with support to resume an interrupted restore.
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+
+use pbs_datastore::index::ChunkReadInfo;
+use pbs_datastore::read_chunk::AsyncReadChunk;
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::prefetch_chunks;
+
+/// Statistics of an image restore.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImageRestoreStats {
+    /// Number of chunks downloaded and written
+    pub restored_chunks: usize,
+    /// Bytes downloaded and written
+    pub restored_bytes: u64,
+    /// Number of chunks already present in the target
+    pub skipped_chunks: usize,
+    /// Bytes already present in the target
+    pub skipped_bytes: u64,
+}
+
+/// Restore the `chunks` of an image into `target`, writing each chunk at its offset.
+///
+/// With `resume` set, the data already present in `target` is hashed per chunk and compared with
+/// the expected digest, only chunks which do not match are downloaded. `crypt_config` must be set
+/// for encrypted archives, as their chunk digests depend on the key. Up to `prefetch` chunks are
+/// downloaded in parallel.
+pub async fn restore_image<R: AsyncReadChunk>(
+    chunks: impl IntoIterator<Item = ChunkReadInfo>,
+    chunk_reader: &R,
+    target: &File,
+    crypt_config: Option<&CryptConfig>,
+    resume: bool,
+    prefetch: usize,
+) -> Result<ImageRestoreStats, Error> {
+    let mut stats = ImageRestoreStats::default();
+    let mut buffer = Vec::new();
+
+    let mut missing = Vec::new();
+    for info in chunks {
+        let size = info.size();
+
+        if resume {
+            buffer.resize(size as usize, 0);
+            if read_exact_at(target, &mut buffer, info.range.start)? {
+                let digest = match crypt_config {
+                    Some(crypt_config) => crypt_config.compute_digest(&buffer),
+                    None => openssl::sha::sha256(&buffer),
+                };
+                if digest == info.digest {
+                    stats.skipped_chunks += 1;
+                    stats.skipped_bytes += size;
+                    continue;
+                }
+            }
+        }
+
+        missing.push(info);
+    }
+
+    let digests: Vec<[u8; 32]> = missing.iter().map(|info| info.digest).collect();
+    let mut downloads = std::pin::pin!(prefetch_chunks(chunk_reader, digests, prefetch));
+
+    for info in missing {
+        let size = info.size();
+
+        let data = downloads
+            .try_next()
+            .await?
+            .ok_or_else(|| format_err!("chunk download stream ended early"))?;
+        if data.len() as u64 != size {
+            return Err(format_err!(
+                "chunk {} has wrong size ({} != {size})",
+                hex::encode(info.digest),
+                data.len(),
+            ));
+        }
+        target
+            .write_all_at(&data, info.range.start)
+            .map_err(|err| format_err!("writing image data failed - {err}"))?;
+
+        stats.restored_chunks += 1;
+        stats.restored_bytes += size;
+    }
+
+    Ok(stats)
+}
+
+// Returns `false` if the target is too short to contain the full range.
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> Result<bool, Error> {
+    match file.read_exact_at(buffer, offset) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(format_err!("reading existing image data failed - {err}")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::bail;
+    use futures::executor::block_on;
+
+    use pbs_datastore::DataBlob;
+
+    use super::*;
+
+    const CHUNK_SIZE: u64 = 4096;
+    const TEST_FILE: &str = ".testfile-image-restore";
+
+    struct TestChunkReader {
+        chunks: HashMap<[u8; 32], Vec<u8>>,
+        reads: AtomicUsize,
+        fail_after: Option<usize>,
+    }
+
+    impl AsyncReadChunk for TestChunkReader {
+        fn read_raw_chunk<'a>(
+            &'a self,
+            _digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<DataBlob, Error>> + Send + 'a>> {
+            Box::pin(async { bail!("not implemented") })
+        }
+
+        fn read_chunk<'a>(
+            &'a self,
+            digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>> {
+            Box::pin(async move {
+                let reads = self.reads.fetch_add(1, Ordering::SeqCst);
+                if Some(reads) == self.fail_after {
+                    bail!("connection lost");
+                }
+                self.chunks
+                    .get(digest)
+                    .cloned()
+                    .ok_or_else(|| format_err!("unknown chunk"))
+            })
+        }
+    }
+
+    fn test_image(chunk_count: u64) -> (Vec<u8>, Vec<ChunkReadInfo>, HashMap<[u8; 32], Vec<u8>>) {
+        let mut image = Vec::new();
+        let mut infos = Vec::new();
+        let mut chunks = HashMap::new();
+        for i in 0..chunk_count {
+            let data: Vec<u8> = (0..CHUNK_SIZE).map(|b| (b * (i + 1) % 251) as u8).collect();
+            let digest = openssl::sha::sha256(&data);
+            infos.push(ChunkReadInfo {
+                range: (i * CHUNK_SIZE)..((i + 1) * CHUNK_SIZE),
+                digest,
+            });
+            image.extend_from_slice(&data);
+            chunks.insert(digest, data);
+        }
+        (image, infos, chunks)
+    }
+
+    #[test]
+    fn test_resume_image_restore() -> Result<(), Error> {
+        let (image, infos, chunks) = test_image(16);
+
+        let path = std::env::current_dir()?.join(TEST_FILE);
+        let mut target = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        let _ = std::fs::remove_file(&path);
+
+        // interrupted after downloading half of the chunks
+        let reader = TestChunkReader {
+            chunks,
+            reads: AtomicUsize::new(0),
+            fail_after: Some(8),
+        };
+        let res = block_on(restore_image(
+            infos.clone(),
+            &reader,
+            &target,
+            None,
+            false,
+            1,
+        ));
+        assert!(res.is_err());
+
+        // corrupt one of the restored chunks
+        target.write_all_at(b"garbage", CHUNK_SIZE * 3 + 100)?;
+
+        let reader = TestChunkReader {
+            fail_after: None,
+            reads: AtomicUsize::new(0),
+            ..reader
+        };
+        let stats = block_on(restore_image(infos, &reader, &target, None, true, 4))?;
+        assert_eq!(
+            stats,
+            ImageRestoreStats {
+                restored_chunks: 9,
+                restored_bytes: 9 * CHUNK_SIZE,
+                skipped_chunks: 7,
+                skipped_bytes: 7 * CHUNK_SIZE,
+            }
+        );
+        assert_eq!(reader.reads.load(Ordering::SeqCst), 9);
+
+        let mut restored = Vec::new();
+        target.seek(SeekFrom::Start(0))?;
+        target.read_to_end(&mut restored)?;
+        assert!(restored == image, "resumed image differs");
+
+        Ok(())
+    }
+}
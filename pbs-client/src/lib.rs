@@ -40,4 +40,15 @@ pub use backup_specification::*;
 mod chunk_stream;
 pub use chunk_stream::{ChunkStream, FixedChunkStream};
 
+mod stream_source;
+pub use stream_source::*;
+
+mod chunk_prefetch;
+pub use chunk_prefetch::*;
+
+mod image_restore;
+pub use image_restore::*;
+
+pub mod vma;
+
 pub const PROXMOX_BACKUP_TCP_KEEPALIVE_TIME: u32 = 120;
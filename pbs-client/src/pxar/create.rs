@@ -43,6 +43,9 @@ pub struct PxarCreateOptions {
     pub skip_lost_and_found: bool,
     /// Skip xattrs of files that return E2BIG error
     pub skip_e2big_xattr: bool,
+    /// If set, all exclude patterns in effect are appended here, in '.pxarexclude' syntax
+    /// relative to the archive root
+    pub exclude_log: Option<Arc<Mutex<Vec<String>>>>,
 }
 
 fn detect_fs_type(fd: RawFd) -> Result<i64, Error> {
@@ -131,6 +134,7 @@ struct Archiver {
     hardlinks: HashMap<HardLinkInfo, (PathBuf, LinkOffset)>,
     file_copy_buffer: Vec<u8>,
     skip_e2big_xattr: bool,
+    exclude_log: Option<Arc<Mutex<Vec<String>>>>,
 }
 
 type Encoder<'a, T> = pxar::encoder::aio::Encoder<'a, T>;
@@ -182,6 +186,14 @@ where
         )?);
     }
 
+    if let Some(ref exclude_log) = options.exclude_log {
+        let content = generate_pxar_excludes_cli(&patterns);
+        exclude_log
+            .lock()
+            .unwrap()
+            .extend(String::from_utf8_lossy(&content).lines().map(String::from));
+    }
+
     let mut archiver = Archiver {
         feature_flags,
         fs_feature_flags,
@@ -197,6 +209,7 @@ where
         hardlinks: HashMap::new(),
         file_copy_buffer: vec::undefined(4 * 1024 * 1024),
         skip_e2big_xattr: options.skip_e2big_xattr,
+        exclude_log: options.exclude_log,
     };
 
     archiver
@@ -327,6 +340,7 @@ impl Archiver {
         };
 
         let old_pattern_count = self.patterns.len();
+        let mut logged_patterns = Vec::new();
 
         let path_bytes = self.path.as_os_str().as_bytes();
 
@@ -373,6 +387,9 @@ impl Archiver {
 
             match MatchEntry::parse_pattern(line, PatternFlag::PATH_NAME, mode) {
                 Ok(pattern) => {
+                    if self.exclude_log.is_some() {
+                        logged_patterns.push(log_pattern_entry(path_bytes, line, mode, anchored));
+                    }
                     if anchored {
                         self.patterns.push(pattern.add_flags(MatchFlag::ANCHORED));
                     } else {
@@ -385,6 +402,10 @@ impl Archiver {
             }
         }
 
+        if let Some(ref exclude_log) = self.exclude_log {
+            exclude_log.lock().unwrap().extend(logged_patterns);
+        }
+
         Ok(())
     }
 
@@ -1108,6 +1129,26 @@ fn process_acl(
     Ok(())
 }
 
+/// Format a pattern read from a `.pxarexclude` file relative to the archive root.
+///
+/// Anchored patterns already contain the directory path, unanchored ones from a subdirectory only
+/// apply below it.
+fn log_pattern_entry(dir: &[u8], pattern: &[u8], mode: MatchType, anchored: bool) -> String {
+    let pattern = String::from_utf8_lossy(pattern);
+    let pattern = if anchored && !pattern.starts_with('/') {
+        format!("/{pattern}")
+    } else if anchored || dir.is_empty() {
+        pattern.to_string()
+    } else {
+        format!("/{}/**/{pattern}", String::from_utf8_lossy(dir))
+    };
+
+    match mode {
+        MatchType::Include => format!("!{pattern}"),
+        MatchType::Exclude => pattern,
+    }
+}
+
 /// Note that our pattern lists are "positive". `MatchType::Include` means the file is included.
 /// Since we are generating an *exclude* list, we need to invert this, so includes get a `'!'`
 /// prefix.
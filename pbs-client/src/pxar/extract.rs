@@ -1,22 +1,23 @@
 //! Code for extraction of pxar contents onto the file system.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString, OsStr, OsString};
 use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, format_err, Context, Error};
 use bitflags::bitflags;
 use nix::dir::Dir;
-use nix::fcntl::OFlag;
-use nix::sys::stat::Mode;
+use nix::fcntl::{AtFlags, OFlag};
+use nix::sys::stat::{Mode, SFlag};
 
 use pathpatterns::{MatchEntry, MatchList, MatchType};
 use pxar::accessor::aio::{Accessor, FileContents, FileEntry};
-use pxar::decoder::{aio::Decoder, Contents};
+use pxar::decoder::aio::Decoder;
 use pxar::format::Device;
 use pxar::{Entry, EntryKind, Metadata};
 
@@ -36,6 +37,8 @@ pub struct PxarExtractOptions<'a> {
     pub allow_existing_dirs: bool,
     pub overwrite_flags: OverwriteFlags,
     pub on_error: Option<ErrorHandler>,
+    /// Progress journal to resume an interrupted extraction, implies overwriting existing entries.
+    pub journal: Option<ExtractJournal>,
 }
 
 bitflags! {
@@ -52,6 +55,84 @@ bitflags! {
 
 pub type ErrorHandler = Box<dyn FnMut(Error) -> Result<(), Error> + Send>;
 
+/// Minimal interval between two writes of the extraction journal.
+const EXTRACT_JOURNAL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress journal of an archive extraction.
+///
+/// The journal records how many entries of the archive were completely extracted. When
+/// extracting the same archive again, the entries before that point are only checked quickly:
+/// regular files are kept if size and modification time match, other entries if they exist.
+pub struct ExtractJournal {
+    path: PathBuf,
+    archive_id: String,
+    reached: u64,
+    current: u64,
+    last_save: Instant,
+}
+
+impl ExtractJournal {
+    /// Open the journal at `path`. Progress is only resumed if the journal was written for the
+    /// archive identified by `archive_id`, for example the checksum of its index.
+    pub fn open(path: PathBuf, archive_id: String) -> Result<Self, Error> {
+        let reached = match proxmox_sys::fs::file_read_optional_string(&path)? {
+            Some(data) => match data.trim().split_once(' ') {
+                Some((id, reached)) if id == archive_id => reached
+                    .parse()
+                    .with_context(|| format!("invalid extraction journal {path:?}"))?,
+                _ => 0,
+            },
+            None => 0,
+        };
+
+        Ok(Self {
+            path,
+            archive_id,
+            reached,
+            current: 0,
+            last_save: Instant::now(),
+        })
+    }
+
+    /// Number of entries extracted by a previous run.
+    pub fn reached(&self) -> u64 {
+        self.reached
+    }
+
+    // The current entry was already extracted by a previous run.
+    fn done_before(&self) -> bool {
+        self.current < self.reached
+    }
+
+    fn entry_done(&mut self) -> Result<(), Error> {
+        self.current += 1;
+        if self.current > self.reached && self.last_save.elapsed() >= EXTRACT_JOURNAL_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<(), Error> {
+        let reached = self.current.max(self.reached);
+        let data = format!("{} {reached}\n", self.archive_id);
+        proxmox_sys::fs::replace_file(&self.path, data.as_bytes(), CreateOptions::new(), false)
+            .with_context(|| format!("failed to write extraction journal {:?}", self.path))?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    fn remove(&self) -> Result<(), Error> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => bail!(
+                "failed to remove extraction journal {:?} - {err}",
+                self.path
+            ),
+        }
+    }
+}
+
 pub fn extract_archive<T, F>(
     decoder: pxar::decoder::Decoder<T>,
     destination: &Path,
@@ -63,10 +144,22 @@ where
     T: pxar::decoder::SeqRead,
     F: FnMut(&Path),
 {
-    ExtractorIter::new(decoder, destination, feature_flags, callback, options)
-        .context("failed to initialize extractor")?
+    let mut extractor = ExtractorIter::new(decoder, destination, feature_flags, callback, options)
+        .context("failed to initialize extractor")?;
+
+    let result = (&mut extractor)
         .collect::<Result<(), Error>>()
-        .context("encountered unexpected error during extraction")
+        .context("encountered unexpected error during extraction");
+
+    if let Some(journal) = &mut extractor.journal {
+        match result {
+            Ok(()) => journal.remove()?,
+            // keep the progress for the next attempt
+            Err(_) => journal.save()?,
+        }
+    }
+
+    result
 }
 
 struct ExtractorIterState {
@@ -89,6 +182,7 @@ where
     extractor: Extractor,
     match_list: &'a [MatchEntry],
     state: ExtractorIterState,
+    journal: Option<ExtractJournal>,
 }
 
 impl ExtractorIterState {
@@ -150,11 +244,18 @@ where
         )
         .with_context(|| format!("unable to open target directory {destination:?}"))?;
 
+        // a resumed extraction finds the entries of the previous attempt
+        let (allow_existing_dirs, overwrite_flags) = if options.journal.is_some() {
+            (true, OverwriteFlags::all())
+        } else {
+            (options.allow_existing_dirs, options.overwrite_flags)
+        };
+
         let mut extractor = Extractor::new(
             dir,
             root.metadata().clone(),
-            options.allow_existing_dirs,
-            options.overwrite_flags,
+            allow_existing_dirs,
+            overwrite_flags,
             feature_flags,
         );
 
@@ -168,6 +269,7 @@ where
             extractor,
             match_list: options.match_list,
             state,
+            journal: options.journal,
         })
     }
 
@@ -266,7 +368,19 @@ where
             None => self.state.current_match,
         };
 
+        let resumed = match &self.journal {
+            Some(journal) => journal.done_before(),
+            None => false,
+        };
+
         let extract_res = match (did_match, entry.kind()) {
+            (true, kind)
+                if resumed
+                    && !matches!(kind, EntryKind::Directory | EntryKind::GoodbyeTable)
+                    && self.extractor.is_extracted(&file_name, kind, metadata) =>
+            {
+                Ok(())
+            }
             (_, EntryKind::Directory) => {
                 self.callback(entry.path());
 
@@ -376,11 +490,14 @@ where
             (false, _) => Ok(()), // skip this
         };
 
-        Some(
-            extract_res
-                .with_context(|| format!("error at entry {file_name_os:?}"))
-                .or_else(&mut *self.extractor.on_error),
-        )
+        let res = extract_res
+            .with_context(|| format!("error at entry {file_name_os:?}"))
+            .or_else(&mut *self.extractor.on_error);
+
+        match (res, &mut self.journal) {
+            (Ok(()), Some(journal)) => Some(journal.entry_done()),
+            (res, _) => Some(res),
+        }
     }
 }
 
@@ -552,6 +669,31 @@ impl Extractor {
         self.feature_flags.contains(flag)
     }
 
+    /// Check whether the entry `file_name` of an earlier, interrupted extraction is complete.
+    ///
+    /// Regular files need to match size and modification time, which is applied only after the
+    /// contents got written. Other entries only need to exist.
+    fn is_extracted(&mut self, file_name: &CStr, kind: &EntryKind, metadata: &Metadata) -> bool {
+        let parent = match self.parent_fd() {
+            Ok(parent) => parent,
+            Err(_) => return false,
+        };
+        let stat = match nix::sys::stat::fstatat(parent, file_name, AtFlags::AT_SYMLINK_NOFOLLOW) {
+            Ok(stat) => stat,
+            Err(_) => return false,
+        };
+
+        match kind {
+            EntryKind::File { size, .. } => {
+                SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFREG
+                    && stat.st_size as u64 == *size
+                    && stat.st_mtime == metadata.stat.mtime.secs
+                    && stat.st_mtime_nsec == i64::from(metadata.stat.mtime.nanos)
+            }
+            _ => true,
+        }
+    }
+
     fn parent_fd(&mut self) -> Result<RawFd, Error> {
         self.dir_stack
             .last_dir_fd(self.allow_existing_dirs)
@@ -780,15 +922,15 @@ fn add_metadata_to_header(header: &mut tar::Header, metadata: &Metadata) {
     header.set_gid(metadata.stat.gid as u64);
 }
 
-async fn tar_add_file<'a, W, T>(
+async fn tar_add_file<W, R>(
     tar: &mut proxmox_compression::tar::Builder<W>,
-    contents: Option<Contents<'a, T>>,
+    contents: Option<R>,
     size: u64,
     metadata: &Metadata,
     path: &Path,
 ) -> Result<(), Error>
 where
-    T: pxar::decoder::SeqRead + Unpin + Send + Sync + 'static,
+    R: tokio::io::AsyncRead + Unpin,
     W: tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
     let mut header = tar::Header::new_gnu();
@@ -804,6 +946,123 @@ where
     .context("could not send file entry")
 }
 
+/// Appends a PAX extended header record (`"<length> <key>=<value>\n"`) to `records`.
+fn add_pax_record(records: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    // the length includes its own decimal representation
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + rest.to_string().len();
+    if rest + len.to_string().len() > len {
+        len += 1;
+    }
+
+    records.extend_from_slice(format!("{len} ").as_bytes());
+    records.extend_from_slice(key);
+    records.push(b'=');
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Adds a PAX extended header with the extended attributes and file capabilities of `metadata`,
+/// which applies to the entry added right after it.
+async fn tar_add_xattrs<W>(
+    tar: &mut proxmox_compression::tar::Builder<W>,
+    metadata: &Metadata,
+) -> Result<(), Error>
+where
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let mut records = Vec::new();
+    for xattr in &metadata.xattrs {
+        let key = [b"SCHILY.xattr.".as_slice(), xattr.name().to_bytes()].concat();
+        add_pax_record(&mut records, &key, xattr.value());
+    }
+    if let Some(fcaps) = &metadata.fcaps {
+        add_pax_record(
+            &mut records,
+            b"SCHILY.xattr.security.capability",
+            &fcaps.data,
+        );
+    }
+
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    tar.add_entry(&mut header, "././@PaxHeader", &records[..])
+        .await
+        .context("could not send extended header")
+}
+
+/// Selects the entries of an archive matched by a list of patterns, the same way
+/// [`extract_archive`] does: entries without a matching pattern inherit the state of their
+/// parent directory.
+struct EntryMatcher<'a> {
+    match_list: &'a [MatchEntry],
+    current_match: bool,
+    match_stack: Vec<bool>,
+}
+
+impl<'a> EntryMatcher<'a> {
+    fn new(match_list: &'a [MatchEntry]) -> Self {
+        Self {
+            match_list,
+            current_match: match_list.is_empty(),
+            match_stack: Vec::new(),
+        }
+    }
+
+    /// Returns whether `entry` is selected. Must be called for every entry, including goodbye
+    /// tables, in archive order.
+    fn matches(&mut self, entry: &Entry) -> bool {
+        if let EntryKind::GoodbyeTable = entry.kind() {
+            if let Some(matched) = self.match_stack.pop() {
+                self.current_match = matched;
+            }
+            return false;
+        }
+
+        // We can `unwrap()` safely here because we get a `Result<_, std::convert::Infallible>`
+        let match_result = self
+            .match_list
+            .matches(
+                entry.path().as_os_str().as_bytes(),
+                entry.metadata().file_type() as u32,
+            )
+            .unwrap();
+
+        let did_match = match match_result {
+            Some(MatchType::Include) => true,
+            Some(MatchType::Exclude) => false,
+            None => self.current_match,
+        };
+
+        if entry.is_dir() {
+            self.match_stack.push(self.current_match);
+            self.current_match = did_match;
+        }
+
+        did_match
+    }
+}
+
+/// Options for [`create_tar_with_options`]
+#[derive(Clone, Debug, Default)]
+pub struct TarOptions {
+    /// Skip device nodes (with a warning) instead of adding them to the archive
+    pub skip_devices: bool,
+    /// Abort once the file contents added to the archive exceed this many bytes
+    pub max_size: Option<u64>,
+    /// Add extended attributes and file capabilities as PAX extended headers
+    pub xattrs: bool,
+    /// Only add entries matching these patterns, all entries if empty
+    pub match_list: Vec<MatchEntry>,
+}
+
 /// Creates a tar file from `path` and writes it into `output`
 pub async fn create_tar<T, W, P>(output: W, accessor: Accessor<T>, path: P) -> Result<(), Error>
 where
@@ -811,6 +1070,32 @@ where
     W: tokio::io::AsyncWrite + Unpin + Send + 'static,
     P: AsRef<Path>,
 {
+    create_tar_with_options(output, accessor, path, TarOptions::default()).await
+}
+
+/// Creates a tar file from `path` and writes it into `output`, see [`TarOptions`]
+pub async fn create_tar_with_options<T, W, P>(
+    output: W,
+    accessor: Accessor<T>,
+    path: P,
+    options: TarOptions,
+) -> Result<(), Error>
+where
+    T: Clone + pxar::accessor::ReadAt + Unpin + Send + Sync + 'static,
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+    P: AsRef<Path>,
+{
+    let mut total_size: u64 = 0;
+    let mut account_size = |size: u64, path: &Path| -> Result<(), Error> {
+        total_size += size;
+        match options.max_size {
+            Some(max_size) if total_size > max_size => bail!(
+                "size limit of {max_size} bytes exceeded while adding {path:?} to tar archive"
+            ),
+            _ => Ok(()),
+        }
+    };
+
     let root = accessor.open_root().await?;
     let file = root
         .lookup(&path)
@@ -823,13 +1108,21 @@ where
 
     let mut tarencoder = proxmox_compression::tar::Builder::new(output);
     let mut hardlinks: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut matcher = EntryMatcher::new(&options.match_list);
+    // files not added because they did not match, hardlinks to them need to carry the contents
+    // (as absolute paths, hardlink targets are stored relative to the archive root)
+    let mut skipped_files: HashSet<PathBuf> = HashSet::new();
 
     if let Ok(dir) = file.enter_directory().await {
         let entry = dir.lookup_self().await?;
+        let selected = matcher.matches(&entry);
         let path = entry.path().strip_prefix(prefix)?;
 
-        if path != Path::new("/") {
+        if selected && path != Path::new("/") {
             let metadata = entry.metadata();
+            if options.xattrs {
+                tar_add_xattrs(&mut tarencoder, metadata).await?;
+            }
             let mut header = tar::Header::new_gnu();
             header.set_entry_type(tar::EntryType::Directory);
             add_metadata_to_header(&mut header, metadata);
@@ -842,16 +1135,28 @@ where
         }
 
         let mut decoder = dir.decode_full().await?;
-        decoder.enable_goodbye_entries(false);
+        // goodbye tables are needed to track the match state of directories
+        decoder.enable_goodbye_entries(true);
         while let Some(entry) = decoder.next().await {
             let entry = entry.context("cannot decode entry")?;
 
+            if !matcher.matches(&entry) {
+                if let EntryKind::File { .. } = entry.kind() {
+                    skipped_files.insert(Path::new("/").join(entry.path()));
+                }
+                continue;
+            }
+
             let metadata = entry.metadata();
             let path = entry.path().strip_prefix(prefix)?;
 
             match entry.kind() {
                 EntryKind::File { .. } => {
                     let size = decoder.content_size().unwrap_or(0);
+                    account_size(size, path)?;
+                    if options.xattrs {
+                        tar_add_xattrs(&mut tarencoder, metadata).await?;
+                    }
                     tar_add_file(&mut tarencoder, decoder.contents(), size, metadata, path).await?
                 }
                 EntryKind::Hardlink(link) => {
@@ -866,17 +1171,26 @@ where
 
                         log::debug!("adding '{}' to tar", path.display());
 
+                        let skipped = skipped_files.contains(&Path::new("/").join(realpath));
                         let stripped_path = match realpath.strip_prefix(prefix) {
-                            Ok(path) => path,
-                            Err(_) => {
-                                // outside of our tar archive, add the first occurrence to the tar
+                            Ok(path) if !skipped => path,
+                            _ => {
+                                // outside of our tar archive or not selected, add the first
+                                // occurrence with the contents of the real file to the tar
                                 if let Some(path) = hardlinks.get(realpath) {
                                     path
                                 } else {
-                                    let size = decoder.content_size().unwrap_or(0);
+                                    let size = match realfile.entry().kind() {
+                                        EntryKind::File { size, .. } => *size,
+                                        _ => 0,
+                                    };
+                                    account_size(size, path)?;
+                                    if options.xattrs {
+                                        tar_add_xattrs(&mut tarencoder, metadata).await?;
+                                    }
                                     tar_add_file(
                                         &mut tarencoder,
-                                        decoder.contents(),
+                                        Some(realfile.contents().await?),
                                         size,
                                         metadata,
                                         path,
@@ -900,6 +1214,9 @@ where
                 EntryKind::Symlink(link) if !link.data.is_empty() => {
                     log::debug!("adding '{}' to tar", path.display());
                     let realpath = Path::new(link);
+                    if options.xattrs {
+                        tar_add_xattrs(&mut tarencoder, metadata).await?;
+                    }
                     let mut header = tar::Header::new_gnu();
                     header.set_entry_type(tar::EntryType::Symlink);
                     add_metadata_to_header(&mut header, metadata);
@@ -911,6 +1228,9 @@ where
                 }
                 EntryKind::Fifo => {
                     log::debug!("adding '{}' to tar", path.display());
+                    if options.xattrs {
+                        tar_add_xattrs(&mut tarencoder, metadata).await?;
+                    }
                     let mut header = tar::Header::new_gnu();
                     header.set_entry_type(tar::EntryType::Fifo);
                     add_metadata_to_header(&mut header, metadata);
@@ -927,6 +1247,9 @@ where
                     log::debug!("adding '{}' to tar", path.display());
                     // we cannot add the root path itself
                     if path != Path::new("/") {
+                        if options.xattrs {
+                            tar_add_xattrs(&mut tarencoder, metadata).await?;
+                        }
                         let mut header = tar::Header::new_gnu();
                         header.set_entry_type(tar::EntryType::Directory);
                         add_metadata_to_header(&mut header, metadata);
@@ -938,6 +1261,9 @@ where
                             .context("could not send dir entry")?;
                     }
                 }
+                EntryKind::Device(_) if options.skip_devices => {
+                    log::warn!("skipping device node '{}'", path.display());
+                }
                 EntryKind::Device(device) => {
                     log::debug!("adding '{}' to tar", path.display());
                     let entry_type = if metadata.stat.is_chardev() {
@@ -945,6 +1271,9 @@ where
                     } else {
                         tar::EntryType::Block
                     };
+                    if options.xattrs {
+                        tar_add_xattrs(&mut tarencoder, metadata).await?;
+                    }
                     let mut header = tar::Header::new_gnu();
                     header.set_entry_type(entry_type);
                     header.set_device_major(device.major as u32)?;
@@ -956,6 +1285,9 @@ where
                         .await
                         .context("could not send device entry")?;
                 }
+                EntryKind::Socket => {
+                    log::warn!("skipping socket '{}', not supported by tar", path.display());
+                }
                 _ => {} // ignore all else
             }
         }
@@ -968,7 +1300,33 @@ where
     Ok(())
 }
 
+/// Options for [`create_zip_with_options`]
+#[derive(Clone, Debug, Default)]
+pub struct ZipOptions {
+    /// Only add entries matching these patterns, all entries if empty
+    pub match_list: Vec<MatchEntry>,
+}
+
+/// Creates a zip file from `path` and writes it into `output`
 pub async fn create_zip<T, W, P>(output: W, accessor: Accessor<T>, path: P) -> Result<(), Error>
+where
+    T: Clone + pxar::accessor::ReadAt + Unpin + Send + Sync + 'static,
+    W: tokio::io::AsyncWrite + Unpin + Send + 'static,
+    P: AsRef<Path>,
+{
+    create_zip_with_options(output, accessor, path, ZipOptions::default()).await
+}
+
+/// Creates a zip file from `path` and writes it into `output`, see [`ZipOptions`]
+///
+/// Entries which cannot be represented in zip files (symlinks, device nodes, fifos and sockets)
+/// are skipped with a warning.
+pub async fn create_zip_with_options<T, W, P>(
+    output: W,
+    accessor: Accessor<T>,
+    path: P,
+    options: ZipOptions,
+) -> Result<(), Error>
 where
     T: Clone + pxar::accessor::ReadAt + Unpin + Send + Sync + 'static,
     W: tokio::io::AsyncWrite + Unpin + Send + 'static,
@@ -987,11 +1345,13 @@ where
     };
 
     let mut zip = ZipEncoder::new(output);
+    let mut matcher = EntryMatcher::new(&options.match_list);
 
     if let Ok(dir) = file.enter_directory().await {
         let entry = dir.lookup_self().await?;
+        let selected = matcher.matches(&entry);
         let path = entry.path().strip_prefix(&prefix)?;
-        if path != Path::new("/") {
+        if selected && path != Path::new("/") {
             let metadata = entry.metadata();
             let entry = ZipEntry::new(
                 path,
@@ -1003,9 +1363,14 @@ where
         }
 
         let mut decoder = dir.decode_full().await?;
-        decoder.enable_goodbye_entries(false);
+        // goodbye tables are needed to track the match state of directories
+        decoder.enable_goodbye_entries(true);
         while let Some(entry) = decoder.next().await {
             let entry = entry?;
+            if !matcher.matches(&entry) {
+                continue;
+            }
+
             let metadata = entry.metadata();
             let path = entry.path().strip_prefix(&prefix)?;
 
@@ -1036,7 +1401,8 @@ where
                         metadata.stat.mode as u16,
                         true,
                     );
-                    zip.add_entry(entry, decoder.contents())
+                    // zip has no hardlinks, every link carries the contents of the real file
+                    zip.add_entry(entry, Some(realfile.contents().await?))
                         .await
                         .context("could not send file entry")?;
                 }
@@ -1050,6 +1416,24 @@ where
                     );
                     zip.add_entry::<FileContents<T>>(entry, None).await?;
                 }
+                EntryKind::Symlink(_) => {
+                    log::warn!(
+                        "skipping symlink '{}', not supported by zip",
+                        path.display()
+                    );
+                }
+                EntryKind::Device(_) => {
+                    log::warn!(
+                        "skipping device node '{}', not supported by zip",
+                        path.display()
+                    );
+                }
+                EntryKind::Fifo => {
+                    log::warn!("skipping fifo '{}', not supported by zip", path.display());
+                }
+                EntryKind::Socket => {
+                    log::warn!("skipping socket '{}', not supported by zip", path.display());
+                }
                 _ => {} // ignore all else
             };
         }
@@ -1300,3 +1684,250 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::executor::block_on;
+    use pathpatterns::PatternFlag;
+    use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
+
+    use super::*;
+
+    /// In-memory pxar archive for the accessor
+    #[derive(Clone)]
+    struct MemoryReadAt(Arc<Vec<u8>>);
+
+    impl ReadAt for MemoryReadAt {
+        fn start_read_at<'a>(
+            self: Pin<&'a Self>,
+            _cx: &mut Context,
+            buf: &'a mut [u8],
+            offset: u64,
+        ) -> MaybeReady<io::Result<usize>, ReadAtOperation<'a>> {
+            let data = &self.0[..];
+            let start = (offset as usize).min(data.len());
+            let len = buf.len().min(data.len() - start);
+            buf[..len].copy_from_slice(&data[start..start + len]);
+            MaybeReady::Ready(Ok(len))
+        }
+
+        fn poll_complete<'a>(
+            self: Pin<&'a Self>,
+            _op: ReadAtOperation<'a>,
+        ) -> MaybeReady<io::Result<usize>, ReadAtOperation<'a>> {
+            panic!("MemoryReadAt::start_read_at returned Pending");
+        }
+    }
+
+    /// Output of the tar encoder, which takes ownership of its writer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl tokio::io::AsyncWrite for SharedBuffer {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Archive with `/a/data`, `/a/other` and `/b/link`, a hardlink to `/a/data`
+    fn test_archive() -> Vec<u8> {
+        let dir_metadata = Metadata::dir_builder(0o755).build();
+        let file_metadata = Metadata::file_builder(0o644).build();
+
+        let mut data = Vec::new();
+        let writer = pxar::encoder::sync::StandardWriter::new(&mut data);
+        let mut encoder = pxar::encoder::sync::Encoder::new(writer, &dir_metadata).unwrap();
+
+        let mut dir = encoder.create_directory("a", &dir_metadata).unwrap();
+        let mut file = dir.create_file(&file_metadata, "data", 5).unwrap();
+        file.write_all(b"hello").unwrap();
+        let offset = file.file_offset();
+        drop(file);
+        let mut file = dir.create_file(&file_metadata, "other", 1).unwrap();
+        file.write_all(b"x").unwrap();
+        drop(file);
+        dir.finish().unwrap();
+
+        let mut dir = encoder.create_directory("b", &dir_metadata).unwrap();
+        dir.add_hardlink("link", Path::new("a/data"), offset)
+            .unwrap();
+        dir.finish().unwrap();
+
+        encoder.finish().unwrap();
+        data
+    }
+
+    fn patterns(list: &[(&str, MatchType)]) -> Vec<MatchEntry> {
+        list.iter()
+            .map(|(pattern, ty)| {
+                MatchEntry::parse_pattern(*pattern, PatternFlag::PATH_NAME, *ty).unwrap()
+            })
+            .collect()
+    }
+
+    fn matched_paths(match_list: &[MatchEntry]) -> Vec<String> {
+        let data = test_archive();
+        let mut decoder = pxar::decoder::sync::Decoder::from_std(&data[..]).unwrap();
+        decoder.enable_goodbye_entries(true);
+
+        let mut matcher = EntryMatcher::new(match_list);
+        let mut paths = Vec::new();
+        for entry in decoder {
+            let entry = entry.unwrap();
+            if matcher.matches(&entry) {
+                paths.push(entry.path().to_string_lossy().into_owned());
+            }
+        }
+        paths
+    }
+
+    /// Returns path, type, contents and link target of the entries of a tar archive
+    fn tar_entries(match_list: Vec<MatchEntry>) -> Vec<(String, tar::EntryType, String, String)> {
+        let data = test_archive();
+        let size = data.len() as u64;
+        let output = SharedBuffer::default();
+
+        block_on(async {
+            let accessor = Accessor::new(MemoryReadAt(Arc::new(data)), size).await?;
+            let options = TarOptions {
+                match_list,
+                ..Default::default()
+            };
+            create_tar_with_options(output.clone(), accessor, "/", options).await
+        })
+        .unwrap();
+
+        let tar_data = output.0.lock().unwrap().clone();
+        let mut archive = tar::Archive::new(&tar_data[..]);
+        let mut list = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let link = match entry.link_name().unwrap() {
+                Some(link) => link.to_string_lossy().into_owned(),
+                None => String::new(),
+            };
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            list.push((
+                path.trim_matches('/').to_string(),
+                entry.header().entry_type(),
+                contents,
+                link,
+            ));
+        }
+        list
+    }
+
+    #[test]
+    fn test_entry_matcher() {
+        assert_eq!(
+            matched_paths(&[]),
+            ["/", "/a", "/a/data", "/a/other", "/b", "/b/link"],
+        );
+        assert_eq!(
+            matched_paths(&patterns(&[("/b", MatchType::Include)])),
+            ["/b", "/b/link"],
+        );
+        // entries inherit the state of their parent, unless they match themselves
+        assert_eq!(
+            matched_paths(&patterns(&[
+                ("/a", MatchType::Include),
+                ("/a/other", MatchType::Exclude),
+            ])),
+            ["/a", "/a/data"],
+        );
+    }
+
+    #[test]
+    fn test_tar_hardlinks() {
+        let file = |path: &str, contents: &str| {
+            (
+                path.to_string(),
+                tar::EntryType::Regular,
+                contents.to_string(),
+                String::new(),
+            )
+        };
+        let dir = |path: &str| {
+            (
+                path.to_string(),
+                tar::EntryType::Directory,
+                String::new(),
+                String::new(),
+            )
+        };
+
+        // with its target in the archive, a hardlink stays a link
+        let link = (
+            "b/link".to_string(),
+            tar::EntryType::Link,
+            String::new(),
+            "a/data".to_string(),
+        );
+        assert_eq!(
+            tar_entries(Vec::new()),
+            [
+                dir("a"),
+                file("a/data", "hello"),
+                file("a/other", "x"),
+                dir("b"),
+                link,
+            ],
+        );
+
+        // the target was filtered out by the patterns, the link carries the contents
+        assert_eq!(
+            tar_entries(patterns(&[("/b", MatchType::Include)])),
+            [dir("b"), file("b/link", "hello")],
+        );
+        assert_eq!(
+            tar_entries(patterns(&[
+                ("/a", MatchType::Include),
+                ("/b", MatchType::Include),
+                ("/a/data", MatchType::Exclude),
+            ])),
+            [
+                dir("a"),
+                file("a/other", "x"),
+                dir("b"),
+                file("b/link", "hello"),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        // cover lengths around the points where the length field gains a digit
+        for value_len in 0..1100 {
+            let value = vec![b'x'; value_len];
+            let mut record = Vec::new();
+            add_pax_record(&mut record, b"SCHILY.xattr.user.test", &value);
+
+            let space = record.iter().position(|b| *b == b' ').unwrap();
+            let len: usize = std::str::from_utf8(&record[..space])
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(len, record.len(), "value length {value_len}");
+            assert_eq!(record.last(), Some(&b'\n'));
+        }
+    }
+}
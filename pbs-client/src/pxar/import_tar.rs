@@ -0,0 +1,641 @@
+//! Conversion of tar archives into pxar archives.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CString, OsStr};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, format_err, Context, Error};
+use pxar::encoder::{LinkOffset, SeqWrite};
+use pxar::format::acl::{Group, GroupObject, Permissions, User};
+use pxar::format::{mode, StatxTimestamp};
+use pxar::Metadata;
+
+use pbs_datastore::catalog::BackupCatalogWriter;
+
+type Encoder<'a, T> = pxar::encoder::sync::Encoder<'a, T>;
+
+/// Options for [`tar_to_pxar`].
+#[derive(Default)]
+pub struct TarImportOptions {
+    /// Regular files which are returned instead of being added to the pxar archive, as paths
+    /// relative to the archive root.
+    pub extract_files: Vec<PathBuf>,
+}
+
+/// Converts a tar archive into a pxar archive, written to `output`.
+///
+/// As pxar stores every directory as a whole, the entries of each directory have to be stored
+/// contiguously in the tar archive, as GNU tar does when archiving a directory tree. Parent
+/// directories without an entry of their own are created with default permissions. Extended
+/// attributes, file capabilities and ACLs are taken from the PAX headers written by GNU tar.
+///
+/// Returns the contents of the files listed in `options.extract_files` found in the archive.
+pub fn tar_to_pxar<R: Read, T: SeqWrite>(
+    input: R,
+    output: T,
+    catalog: Option<Arc<Mutex<dyn BackupCatalogWriter + Send>>>,
+    options: TarImportOptions,
+) -> Result<HashMap<PathBuf, Vec<u8>>, Error> {
+    let mut archive = tar::Archive::new(input);
+    let mut entries = TarEntries {
+        entries: archive.entries()?,
+        peeked: None,
+    };
+
+    let mut converter = TarConverter {
+        catalog,
+        extract_files: options.extract_files,
+        extracted: HashMap::new(),
+        hardlinks: HashMap::new(),
+        finished_dirs: HashSet::new(),
+    };
+
+    // vzdump puts the guest configuration in front of the root directory
+    while let Some(path) = entries.peek_path()? {
+        if !converter.extract_files.contains(&path) {
+            break;
+        }
+        let (path, entry) = entries.next()?.unwrap();
+        converter.extract(path, entry)?;
+    }
+
+    let root_metadata = match entries.peek_path()? {
+        Some(path) if path.as_os_str().is_empty() => {
+            let (_, mut entry) = entries.next()?.unwrap();
+            if entry.header().entry_type() != tar::EntryType::Directory {
+                bail!("root entry of tar archive is not a directory");
+            }
+            entry_metadata(&mut entry)?
+        }
+        _ => Metadata::dir_builder(0o755).build(),
+    };
+
+    let mut encoder = Encoder::new(output, &root_metadata)?;
+    converter.encode_dir(&mut encoder, Path::new(""), &mut entries)?;
+    encoder.finish()?;
+    drop(entries);
+
+    // tar stops at the first of the two zero blocks marking the end of the archive, so the
+    // second one is missing if the archive was truncated at an entry boundary
+    let mut input = archive.into_inner();
+    let mut end_marker = [0u8; 512];
+    input
+        .read_exact(&mut end_marker)
+        .map_err(|err| format_err!("tar archive is truncated (missing end marker) - {err}"))?;
+    if end_marker.iter().any(|b| *b != 0) {
+        bail!("tar archive is corrupt (invalid end marker)");
+    }
+
+    Ok(converter.extracted)
+}
+
+/// Normalizes the path of a tar entry to be relative to the archive root.
+fn normalize_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir | Component::RootDir => (),
+            Component::ParentDir | Component::Prefix(_) => {
+                bail!("refusing to import tar entry with path {path:?}")
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// The entries of a tar archive, with one entry of look-ahead.
+struct TarEntries<'a, R: 'a + Read> {
+    entries: tar::Entries<'a, R>,
+    peeked: Option<(PathBuf, tar::Entry<'a, R>)>,
+}
+
+impl<'a, R: Read> TarEntries<'a, R> {
+    fn peek_path(&mut self) -> Result<Option<PathBuf>, Error> {
+        while self.peeked.is_none() {
+            let entry = match self.entries.next() {
+                Some(entry) => entry.context("failed to read tar entry")?,
+                None => return Ok(None),
+            };
+            if entry.header().entry_type() == tar::EntryType::XGlobalHeader {
+                continue;
+            }
+            let path = normalize_path(&entry.path()?)?;
+            self.peeked = Some((path, entry));
+        }
+        Ok(self.peeked.as_ref().map(|(path, _)| path.clone()))
+    }
+
+    fn next(&mut self) -> Result<Option<(PathBuf, tar::Entry<'a, R>)>, Error> {
+        self.peek_path()?;
+        Ok(self.peeked.take())
+    }
+}
+
+struct TarConverter {
+    catalog: Option<Arc<Mutex<dyn BackupCatalogWriter + Send>>>,
+    extract_files: Vec<PathBuf>,
+    extracted: HashMap<PathBuf, Vec<u8>>,
+    /// Encoded regular files, as possible hardlink targets.
+    hardlinks: HashMap<PathBuf, LinkOffset>,
+    finished_dirs: HashSet<PathBuf>,
+}
+
+impl TarConverter {
+    fn extract<R: Read>(&mut self, path: PathBuf, mut entry: tar::Entry<R>) -> Result<(), Error> {
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("failed to read {path:?}"))?;
+        self.extracted.insert(path, data);
+        Ok(())
+    }
+
+    /// Encodes the entries below `dir` until the first entry outside of it.
+    fn encode_dir<R: Read, T: SeqWrite>(
+        &mut self,
+        encoder: &mut Encoder<T>,
+        dir: &Path,
+        entries: &mut TarEntries<R>,
+    ) -> Result<(), Error> {
+        while let Some(path) = entries.peek_path()? {
+            if path == dir {
+                log::warn!("ignoring late entry for directory {dir:?}");
+                entries.next()?;
+                continue;
+            }
+            let relative = match path.strip_prefix(dir) {
+                Ok(relative) => relative,
+                Err(_) => return Ok(()), // entry of a parent directory
+            };
+
+            let mut components = relative.components();
+            let name = components.next().unwrap().as_os_str().to_owned();
+            if components.next().is_some() {
+                // the entry is in a subdirectory without a tar entry of its own
+                let sub_dir = dir.join(&name);
+                let metadata = Metadata::dir_builder(0o755).build();
+                self.encode_sub_dir(encoder, &name, &metadata, &sub_dir, entries)?;
+                continue;
+            }
+
+            let (path, entry) = entries.next()?.unwrap();
+            self.add_entry(encoder, &name, path, entry, entries)
+                .with_context(|| format!("failed to import {:?}", dir.join(&name)))?;
+        }
+        Ok(())
+    }
+
+    fn encode_sub_dir<R: Read, T: SeqWrite>(
+        &mut self,
+        encoder: &mut Encoder<T>,
+        name: &OsStr,
+        metadata: &Metadata,
+        path: &Path,
+        entries: &mut TarEntries<R>,
+    ) -> Result<(), Error> {
+        if self.finished_dirs.contains(path) {
+            bail!("entries of directory {path:?} are not stored contiguously in the tar archive");
+        }
+
+        if let Some(catalog) = &self.catalog {
+            let name = CString::new(name.as_bytes())?;
+            catalog.lock().unwrap().start_directory(&name)?;
+        }
+
+        let mut dir_encoder = encoder.create_directory(name, metadata)?;
+        self.encode_dir(&mut dir_encoder, path, entries)?;
+        dir_encoder.finish()?;
+
+        if let Some(catalog) = &self.catalog {
+            catalog.lock().unwrap().end_directory()?;
+        }
+        self.finished_dirs.insert(path.to_owned());
+
+        Ok(())
+    }
+
+    fn add_entry<R: Read, T: SeqWrite>(
+        &mut self,
+        encoder: &mut Encoder<T>,
+        name: &OsStr,
+        path: PathBuf,
+        mut entry: tar::Entry<R>,
+        entries: &mut TarEntries<R>,
+    ) -> Result<(), Error> {
+        let entry_type = entry.header().entry_type();
+        let metadata = entry_metadata(&mut entry)?;
+        let c_name = CString::new(name.as_bytes())?;
+        let catalog = self.catalog.clone();
+        let mut catalog = catalog.as_ref().map(|catalog| catalog.lock().unwrap());
+
+        match entry_type {
+            tar::EntryType::Directory => {
+                drop(catalog);
+                self.encode_sub_dir(encoder, name, &metadata, &path, entries)?;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::GNUSparse => {
+                if self.extract_files.contains(&path) {
+                    return self.extract(path, entry);
+                }
+                let size = entry.size();
+                if let Some(catalog) = catalog.as_mut() {
+                    catalog.add_file(&c_name, size, metadata.stat.mtime.secs)?;
+                }
+                let mut file = encoder.create_file(&metadata, name, size)?;
+                let copied = io::copy(&mut (&mut entry).take(size), &mut file)?;
+                if copied != size {
+                    bail!("unexpected end of file data ({copied} of {size} bytes)");
+                }
+                let offset = file.file_offset();
+                drop(file);
+                self.hardlinks.insert(path, offset);
+            }
+            tar::EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| format_err!("hardlink without target"))?;
+                let target = normalize_path(&target)?;
+                let offset = self.hardlinks.get(&target).ok_or_else(|| {
+                    format_err!("hardlink target {target:?} not found in preceding entries")
+                })?;
+                if let Some(catalog) = catalog.as_mut() {
+                    catalog.add_hardlink(&c_name)?;
+                }
+                encoder.add_hardlink(name, &target, *offset)?;
+            }
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| format_err!("symlink without target"))?;
+                if let Some(catalog) = catalog.as_mut() {
+                    catalog.add_symlink(&c_name)?;
+                }
+                encoder.add_symlink(&metadata, name, &*target)?;
+            }
+            tar::EntryType::Char | tar::EntryType::Block => {
+                let device = pxar::format::Device {
+                    major: entry.header().device_major()?.unwrap_or(0).into(),
+                    minor: entry.header().device_minor()?.unwrap_or(0).into(),
+                };
+                if let Some(catalog) = catalog.as_mut() {
+                    if entry_type == tar::EntryType::Char {
+                        catalog.add_char_device(&c_name)?;
+                    } else {
+                        catalog.add_block_device(&c_name)?;
+                    }
+                }
+                encoder.add_device(&metadata, name, device)?;
+            }
+            tar::EntryType::Fifo => {
+                if let Some(catalog) = catalog.as_mut() {
+                    catalog.add_fifo(&c_name)?;
+                }
+                encoder.add_fifo(&metadata, name)?;
+            }
+            other => log::warn!("skipping tar entry {path:?} of unsupported type {other:?}"),
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the metadata of a tar entry, including the PAX extensions written by GNU tar.
+fn entry_metadata<R: Read>(entry: &mut tar::Entry<R>) -> Result<Metadata, Error> {
+    let header = entry.header();
+    let file_type = match header.entry_type() {
+        tar::EntryType::Directory => mode::IFDIR,
+        tar::EntryType::Symlink => mode::IFLNK,
+        tar::EntryType::Char => mode::IFCHR,
+        tar::EntryType::Block => mode::IFBLK,
+        tar::EntryType::Fifo => mode::IFIFO,
+        _ => mode::IFREG,
+    };
+    let mut metadata = Metadata {
+        stat: pxar::Stat {
+            mode: file_type | u64::from(header.mode()? & 0o7777),
+            flags: 0,
+            uid: header.uid()? as u32,
+            gid: header.gid()? as u32,
+            mtime: StatxTimestamp::new(header.mtime()? as i64, 0),
+        },
+        ..Default::default()
+    };
+
+    let extensions = match entry.pax_extensions()? {
+        Some(extensions) => extensions,
+        None => return Ok(metadata),
+    };
+    for extension in extensions {
+        let extension = extension?;
+        let key = match extension.key() {
+            Ok(key) => key,
+            Err(_) => continue,
+        };
+        let value = extension.value_bytes();
+
+        if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+            if name == "security.capability" {
+                metadata.fcaps = Some(pxar::format::FCaps {
+                    data: value.to_vec(),
+                });
+            } else {
+                metadata
+                    .xattrs
+                    .push(pxar::format::XAttr::new(name.as_bytes(), value));
+            }
+            continue;
+        }
+
+        match key {
+            "SCHILY.acl.access" => apply_tar_acl(&mut metadata, value, false)?,
+            "SCHILY.acl.default" => apply_tar_acl(&mut metadata, value, true)?,
+            "uid" => metadata.stat.uid = parse_pax_number(key, value)? as u32,
+            "gid" => metadata.stat.gid = parse_pax_number(key, value)? as u32,
+            "mtime" => {
+                let value = std::str::from_utf8(value)?;
+                let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+                let secs = secs.parse::<i64>()?;
+                // nanoseconds, from the first 9 digits of the fraction
+                let nanos = format!("{fraction:0<9}")[..9].parse::<u32>().unwrap_or(0);
+                metadata.stat.mtime = StatxTimestamp::new(secs, nanos);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(metadata)
+}
+
+fn parse_pax_number(key: &str, value: &[u8]) -> Result<u64, Error> {
+    std::str::from_utf8(value)?
+        .parse()
+        .map_err(|err| format_err!("invalid PAX header '{key}' - {err}"))
+}
+
+fn parse_acl_permissions(text: &str) -> Result<Permissions, Error> {
+    let mut permissions = 0;
+    for c in text.chars() {
+        permissions |= match c {
+            'r' => 4,
+            'w' => 2,
+            'x' => 1,
+            '-' => 0,
+            _ => bail!("invalid ACL permissions '{text}'"),
+        };
+    }
+    Ok(Permissions(permissions))
+}
+
+/// Applies an ACL in the text form stored by GNU tar, like `user::rw-,user:1000:r--,...`.
+fn apply_tar_acl(metadata: &mut Metadata, text: &[u8], default: bool) -> Result<(), Error> {
+    use pxar::format::acl as pxar_acl;
+
+    let text = std::str::from_utf8(text)?;
+
+    let mut acl_user = Vec::new();
+    let mut acl_group = Vec::new();
+    let mut user_obj_permissions = None;
+    let mut group_obj_permissions = None;
+    let mut other_permissions = None;
+    let mut mask_permissions = None;
+
+    let acl_entries = text
+        .split([',', '\n'])
+        .map(|entry| entry.split('#').next().unwrap().trim())
+        .filter(|entry| !entry.is_empty());
+
+    for acl_entry in acl_entries {
+        let parts: Vec<&str> = acl_entry.split(':').collect();
+        if parts.len() < 3 {
+            bail!("invalid ACL entry '{acl_entry}'");
+        }
+        let permissions = parse_acl_permissions(parts[2])?;
+        // numeric ids are either the qualifier, or appended by star compatible archivers
+        let id = || -> Result<u64, Error> {
+            parts[1]
+                .parse()
+                .or_else(|_| parts.get(3).unwrap_or(&"").parse())
+                .map_err(|_| format_err!("ACL entry '{acl_entry}' has no numeric id"))
+        };
+
+        match (parts[0], parts[1].is_empty()) {
+            ("user" | "u", true) => user_obj_permissions = Some(permissions),
+            ("user" | "u", false) => acl_user.push(User {
+                uid: id()?,
+                permissions,
+            }),
+            ("group" | "g", true) => group_obj_permissions = Some(permissions),
+            ("group" | "g", false) => acl_group.push(Group {
+                gid: id()?,
+                permissions,
+            }),
+            ("mask" | "m", _) => mask_permissions = Some(permissions),
+            ("other" | "o", _) => other_permissions = Some(permissions),
+            _ => bail!("invalid ACL entry '{acl_entry}'"),
+        }
+    }
+
+    acl_user.sort();
+    acl_group.sort();
+
+    if default {
+        if user_obj_permissions.is_some()
+            || group_obj_permissions.is_some()
+            || other_permissions.is_some()
+            || mask_permissions.is_some()
+        {
+            metadata.acl.default = Some(pxar_acl::Default {
+                user_obj_permissions: user_obj_permissions.unwrap_or(Permissions::NO_MASK),
+                group_obj_permissions: group_obj_permissions.unwrap_or(Permissions::NO_MASK),
+                other_permissions: other_permissions.unwrap_or(Permissions::NO_MASK),
+                mask_permissions: mask_permissions.unwrap_or(Permissions::NO_MASK),
+            });
+        }
+        metadata.acl.default_users = acl_user;
+        metadata.acl.default_groups = acl_group;
+    } else {
+        // as for ACLs read from the file system, the group object permissions are only stored
+        // if there is a mask, which then makes up the group permissions of the file mode
+        if let (Some(permissions), true) = (group_obj_permissions, mask_permissions.is_some()) {
+            metadata.acl.group_obj = Some(GroupObject { permissions });
+        }
+        metadata.acl.users = acl_user;
+        metadata.acl.groups = acl_group;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pxar::EntryKind;
+
+    use super::*;
+
+    fn add_entry(
+        builder: &mut tar::Builder<Vec<u8>>,
+        path: &str,
+        entry_type: tar::EntryType,
+        link: Option<&str>,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(if entry_type == tar::EntryType::Directory {
+            0o755
+        } else {
+            0o644
+        });
+        header.set_mtime(1_700_000_000);
+        header.set_size(data.len() as u64);
+        // set directly, as the tar crate normalizes paths when setting them
+        let gnu = header.as_gnu_mut().unwrap();
+        gnu.name[..path.len()].copy_from_slice(path.as_bytes());
+        if let Some(link) = link {
+            gnu.linkname[..link.len()].copy_from_slice(link.as_bytes());
+        }
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    /// Archive as written by vzdump for a container, with the configuration in front of the root
+    /// directory and `usr/bin` without an entry of its own
+    fn test_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        add_entry(
+            &mut builder,
+            "./etc/vzdump/pct.conf",
+            tar::EntryType::Regular,
+            None,
+            b"hostname: ct1\n",
+        );
+        add_entry(&mut builder, "./", tar::EntryType::Directory, None, b"");
+        add_entry(&mut builder, "./etc/", tar::EntryType::Directory, None, b"");
+        add_entry(
+            &mut builder,
+            "./etc/hostname",
+            tar::EntryType::Regular,
+            None,
+            b"ct1\n",
+        );
+        add_entry(
+            &mut builder,
+            "./etc/hostname.link",
+            tar::EntryType::Link,
+            Some("./etc/hostname"),
+            b"",
+        );
+        add_entry(
+            &mut builder,
+            "./etc/localtime",
+            tar::EntryType::Symlink,
+            Some("/usr/share/zoneinfo/UTC"),
+            b"",
+        );
+        add_entry(
+            &mut builder,
+            "./usr/bin/tool",
+            tar::EntryType::Regular,
+            None,
+            b"#!/bin/sh\n",
+        );
+        builder.into_inner().unwrap()
+    }
+
+    fn import(tar: &[u8]) -> Result<(Vec<u8>, HashMap<PathBuf, Vec<u8>>), Error> {
+        let mut pxar = Vec::new();
+        let options = TarImportOptions {
+            extract_files: vec![PathBuf::from("etc/vzdump/pct.conf")],
+        };
+        let writer = pxar::encoder::sync::StandardWriter::new(&mut pxar);
+        let extracted = tar_to_pxar(tar, writer, None, options)?;
+        Ok((pxar, extracted))
+    }
+
+    #[test]
+    fn test_tar_to_pxar() -> Result<(), Error> {
+        let (pxar, extracted) = import(&test_tar())?;
+
+        assert_eq!(extracted.len(), 1);
+        assert_eq!(
+            extracted[Path::new("etc/vzdump/pct.conf")],
+            b"hostname: ct1\n"
+        );
+
+        let mut decoder = pxar::decoder::sync::Decoder::from_std(&pxar[..])?;
+        let mut entries = Vec::new();
+        while let Some(entry) = decoder.next() {
+            let entry = entry?;
+            let path = entry.path().to_string_lossy().into_owned();
+            let description = match entry.kind() {
+                EntryKind::Directory => "dir".to_string(),
+                EntryKind::File { .. } => {
+                    let mut data = Vec::new();
+                    decoder.contents().unwrap().read_to_end(&mut data)?;
+                    format!("file {}", String::from_utf8(data)?.trim_end())
+                }
+                EntryKind::Hardlink(link) => format!("hardlink {:?}", link.as_os_str()),
+                EntryKind::Symlink(link) => format!("symlink {:?}", link.as_os_str()),
+                _ => "other".to_string(),
+            };
+            entries.push(format!("{path} {description}"));
+        }
+
+        assert_eq!(
+            entries,
+            vec![
+                "/ dir",
+                "/etc dir",
+                "/etc/hostname file ct1",
+                "/etc/hostname.link hardlink \"etc/hostname\"",
+                "/etc/localtime symlink \"/usr/share/zoneinfo/UTC\"",
+                "/usr dir",
+                "/usr/bin dir",
+                "/usr/bin/tool file #!/bin/sh",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_to_pxar_invalid() {
+        let tar = test_tar();
+
+        // without the end marker
+        assert!(import(&tar[..tar.len() - 512]).is_err());
+        // within the data of the last file
+        let last_data = tar.len() - 2 * 512 - 512;
+        assert!(import(&tar[..last_data + 5]).is_err());
+        // not a tar archive at all
+        assert!(import(&[0x42u8; 2048]).is_err());
+
+        // the entries of a directory have to be contiguous
+        let mut builder = tar::Builder::new(Vec::new());
+        add_entry(&mut builder, "./a/one", tar::EntryType::Regular, None, b"1");
+        add_entry(&mut builder, "./b/two", tar::EntryType::Regular, None, b"2");
+        add_entry(
+            &mut builder,
+            "./a/three",
+            tar::EntryType::Regular,
+            None,
+            b"3",
+        );
+        assert!(import(&builder.into_inner().unwrap()).is_err());
+
+        // paths leaving the archive root are refused
+        let mut builder = tar::Builder::new(Vec::new());
+        add_entry(
+            &mut builder,
+            "../escape",
+            tar::EntryType::Regular,
+            None,
+            b"x",
+        );
+        assert!(import(&builder.into_inner().unwrap()).is_err());
+    }
+}
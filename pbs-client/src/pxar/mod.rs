@@ -50,6 +50,7 @@
 pub(crate) mod create;
 pub(crate) mod dir_stack;
 pub(crate) mod extract;
+pub(crate) mod import_tar;
 pub(crate) mod metadata;
 pub(crate) mod tools;
 
@@ -58,9 +59,11 @@ pub use flags::Flags;
 
 pub use create::{create_archive, PxarCreateOptions};
 pub use extract::{
-    create_tar, create_zip, extract_archive, extract_sub_dir, extract_sub_dir_seq, ErrorHandler,
-    OverwriteFlags, PxarExtractContext, PxarExtractOptions,
+    create_tar, create_tar_with_options, create_zip, create_zip_with_options, extract_archive,
+    extract_sub_dir, extract_sub_dir_seq, ErrorHandler, ExtractJournal, OverwriteFlags,
+    PxarExtractContext, PxarExtractOptions, TarOptions, ZipOptions,
 };
+pub use import_tar::{tar_to_pxar, TarImportOptions};
 
 /// The format requires to build sorted directory lookup tables in
 /// memory, so we restrict the number of allowed entries to limit
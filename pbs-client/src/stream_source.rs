@@ -0,0 +1,294 @@
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use bytes::BytesMut;
+use futures::stream::Stream;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use proxmox_human_byte::HumanByte;
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Size and SHA-256 digest of all data read by a [`StreamSource`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamSourceStats {
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+/// Shared handle to the [`StreamSourceStats`], filled in once the source reached end of file.
+pub type StreamSourceStatsHandle = Arc<Mutex<Option<StreamSourceStats>>>;
+
+/// Turns a reader of unknown size (e.g. standard input) into a stream of data buffers.
+///
+/// Short reads are passed on as they are, so the stream is meant to be fed into a
+/// [`ChunkStream`](crate::ChunkStream). While reading, the data is hashed and the amount read so
+/// far is logged periodically if a progress label is set.
+pub struct StreamSource<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    hasher: openssl::sha::Sha256,
+    size: u64,
+    stats: StreamSourceStatsHandle,
+    progress: Option<(String, Instant)>,
+    done: bool,
+}
+
+impl<R: AsyncRead + Unpin> StreamSource<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![0u8; READ_BUFFER_SIZE],
+            hasher: openssl::sha::Sha256::new(),
+            size: 0,
+            stats: Arc::new(Mutex::new(None)),
+            progress: None,
+            done: false,
+        }
+    }
+
+    /// Periodically log the number of bytes read, prefixed with `label`.
+    pub fn with_progress(mut self, label: impl Into<String>) -> Self {
+        self.progress = Some((label.into(), Instant::now()));
+        self
+    }
+
+    /// Returns a handle to the stats, which are available after the stream has finished.
+    pub fn stats(&self) -> StreamSourceStatsHandle {
+        Arc::clone(&self.stats)
+    }
+
+    fn log_progress(&mut self, force: bool) {
+        if let Some((label, last)) = &mut self.progress {
+            if force || last.elapsed() >= PROGRESS_INTERVAL {
+                log::info!("{label}: read {}", HumanByte::from(self.size));
+                *last = Instant::now();
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for StreamSource<R> {
+    type Item = Result<BytesMut, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let mut read_buf = ReadBuf::new(&mut this.buffer);
+
+        match Pin::new(&mut this.reader).poll_read(cx, &mut read_buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(err)) => {
+                this.done = true;
+                Poll::Ready(Some(Err(err.into())))
+            }
+            Poll::Ready(Ok(())) => {
+                let len = read_buf.filled().len();
+                if len == 0 {
+                    this.done = true;
+                    this.log_progress(true);
+                    let hasher = std::mem::replace(&mut this.hasher, openssl::sha::Sha256::new());
+                    *this.stats.lock().unwrap() = Some(StreamSourceStats {
+                        size: this.size,
+                        sha256: hasher.finish(),
+                    });
+                    return Poll::Ready(None);
+                }
+
+                let data = BytesMut::from(&this.buffer[..len]);
+                this.hasher.update(&data);
+                this.size += len as u64;
+                this.log_progress(false);
+
+                Poll::Ready(Some(Ok(data)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use futures::executor::block_on;
+    use futures::TryStreamExt;
+
+    use crate::ChunkStream;
+
+    use super::*;
+
+    /// Deterministic pseudo-random data source which only ever returns a few bytes at a time,
+    /// similar to a pipe with a slow writer.
+    struct ShortReader {
+        remaining: u64,
+        state: u64,
+        reads: u64,
+    }
+
+    impl ShortReader {
+        fn new(size: u64) -> Self {
+            Self {
+                remaining: size,
+                state: 0x2545_f491_4f6c_dd1d,
+                reads: 0,
+            }
+        }
+
+        fn next_byte(&mut self) -> u8 {
+            // xorshift64
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            self.state as u8
+        }
+    }
+
+    impl Read for ShortReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            // vary the read size between 1 byte and 64 KiB to exercise short reads
+            let max = match self.reads % 4 {
+                0 => 1,
+                1 => 4096 + 7,
+                2 => 65536,
+                _ => 333,
+            };
+            let len = buf.len().min(max).min(self.remaining as usize);
+            for byte in buf[..len].iter_mut() {
+                *byte = self.next_byte();
+            }
+            self.remaining -= len as u64;
+            Ok(len)
+        }
+    }
+
+    impl AsyncRead for ShortReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            let len = Read::read(this, buf.initialize_unfilled())?;
+            buf.advance(len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn expected_digest(size: u64) -> [u8; 32] {
+        let mut reader = ShortReader::new(size);
+        let mut hasher = openssl::sha::Sha256::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            let len = reader.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buf[..len]);
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_stream_source_short_reads() -> Result<(), Error> {
+        let size = 64 * 1024 * 1024 + 17;
+
+        let source = StreamSource::new(ShortReader::new(size));
+        let stats = source.stats();
+        let chunks = ChunkStream::new(source, None);
+
+        let total =
+            block_on(chunks.try_fold(
+                0u64,
+                |acc, chunk| async move { Ok(acc + chunk.len() as u64) },
+            ))?;
+
+        assert_eq!(total, size);
+        let stats = stats.lock().unwrap().clone().unwrap();
+        assert_eq!(stats.size, size);
+        assert_eq!(stats.sha256, expected_digest(size));
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "pipes several GiB through the chunker, run explicitly"]
+    fn test_stream_source_large() -> Result<(), Error> {
+        let size = 3 * 1024 * 1024 * 1024 + 4711;
+
+        let source = StreamSource::new(ShortReader::new(size));
+        let stats = source.stats();
+        let chunks = ChunkStream::new(source, None);
+
+        let total =
+            block_on(chunks.try_fold(
+                0u64,
+                |acc, chunk| async move { Ok(acc + chunk.len() as u64) },
+            ))?;
+
+        assert_eq!(total, size);
+        assert_eq!(stats.lock().unwrap().as_ref().unwrap().size, size);
+
+        Ok(())
+    }
+
+    /// Fast source of a constant byte, for streams larger than 4 GiB.
+    struct ConstantReader {
+        remaining: u64,
+    }
+
+    impl AsyncRead for ConstantReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context,
+            buf: &mut ReadBuf,
+        ) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            let len = (buf.remaining() as u64).min(this.remaining) as usize;
+            buf.initialize_unfilled_to(len).fill(0x55);
+            buf.advance(len);
+            this.remaining -= len as u64;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_stream_source_size_above_4g() -> Result<(), Error> {
+        let size = 4 * 1024 * 1024 * 1024 + 4711;
+
+        let source = StreamSource::new(ConstantReader { remaining: size });
+        let stats = source.stats();
+
+        let total = block_on(
+            source.try_fold(0u64, |acc, data| async move { Ok(acc + data.len() as u64) }),
+        )?;
+
+        assert_eq!(total, size);
+        assert_eq!(stats.lock().unwrap().as_ref().unwrap().size, size);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_source_empty() -> Result<(), Error> {
+        let source = StreamSource::new(ShortReader::new(0));
+        let stats = source.stats();
+
+        let buffers: Vec<BytesMut> = block_on(source.try_collect())?;
+        assert!(buffers.is_empty());
+
+        let stats = stats.lock().unwrap().clone().unwrap();
+        assert_eq!(stats.size, 0);
+        assert_eq!(stats.sha256, openssl::sha::sha256(&[]));
+
+        Ok(())
+    }
+}
@@ -5,35 +5,62 @@ use std::sync::{
 
 use anyhow::{bail, Error};
 use futures::*;
+use serde::Serialize;
 use serde_json::{json, Value};
 use tokio::signal::unix::{signal, SignalKind};
 
 use proxmox_router::cli::format_and_print_result;
 
 use pbs_api_types::percent_encoding::percent_encode_component;
+use pbs_api_types::UPID;
 
 use super::HttpClient;
 
-/// Display task log on console
+/// Final state of a task whose log was followed until its end.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TaskResult {
+    /// The UPID of the task
+    pub upid: String,
+    /// The exit status, e.g. "OK", "WARNINGS: 1" or the error message
+    pub exitstatus: String,
+}
+
+impl TaskResult {
+    /// Returns an error if the task did not finish successfully.
+    pub fn check(&self) -> Result<(), Error> {
+        let status = &self.exitstatus;
+        if status == "OK" || status.starts_with("WARNINGS") {
+            Ok(())
+        } else {
+            bail!("task failed (status {status})");
+        }
+    }
+}
+
+/// Follow the task log until the task finished, passing each line to `line_callback`.
 ///
-/// This polls the task API and prints the log to the console. It also
-/// catches interrupt signals, and sends an abort request to the task if the
-/// user presses CTRL-C and `forward_interrupt` is true. Two interrupts cause an
-/// immediate end of the loop. The task may still run in that case.
-pub async fn display_task_log(
+/// Uses the long-poll mode of the task log API and falls back to polling on servers which do
+/// not support it. It also catches interrupt signals, and sends an abort request to the task if
+/// the user presses CTRL-C and `forward_interrupt` is true. Two interrupts cause an immediate end
+/// of the loop, in that case `None` is returned, as the task may still run.
+pub async fn follow_task_log<F: FnMut(&str)>(
     client: &HttpClient,
     upid_str: &str,
-    strip_date: bool,
     forward_interrupt: bool,
-) -> Result<(), Error> {
+    mut line_callback: F,
+) -> Result<Option<TaskResult>, Error> {
     let mut signal_stream = signal(SignalKind::interrupt())?;
     let abort_count = Arc::new(AtomicUsize::new(0));
     let abort_count2 = Arc::clone(&abort_count);
+    // wakes up a pending (long-poll) request, so that an interrupt is forwarded immediately
+    let (abort_tx, mut abort_rx) = futures::channel::mpsc::unbounded::<()>();
 
     let abort_future = async move {
         while signal_stream.recv().await.is_some() {
             log::info!("got shutdown request (SIGINT)");
             let prev_count = abort_count2.fetch_add(1, Ordering::SeqCst);
+            let _ = abort_tx.unbounded_send(());
             if prev_count >= 1 {
                 log::info!("forced exit (task still running)");
                 break;
@@ -45,6 +72,7 @@ pub async fn display_task_log(
     let request_future = async move {
         let mut start = 1;
         let limit = 500;
+        let mut follow = true;
 
         let upid_encoded = percent_encode_component(upid_str);
 
@@ -55,19 +83,43 @@ pub async fn display_task_log(
                     let path = format!("api2/json/nodes/localhost/tasks/{upid_encoded}");
                     let _ = client.delete(&path, None).await?;
                 } else {
-                    return Ok(());
+                    return Ok(None);
                 }
             }
 
-            let param = json!({ "start": start, "limit": limit, "test-status": true });
+            let mut param = json!({ "start": start, "limit": limit, "test-status": true });
+            if follow {
+                param["follow"] = true.into();
+            }
 
             let path = format!("api2/json/nodes/localhost/tasks/{upid_encoded}/log");
-            let result = client.get(&path, Some(param)).await?;
+            let request = client.get(&path, Some(param)).fuse();
+            futures::pin_mut!(request);
+            let result = futures::select! {
+                result = request => result,
+                _ = abort_rx.next() => continue, // check the abort state again
+            };
+
+            let result = match result {
+                Ok(result) => result,
+                Err(err) if follow && start == 1 => {
+                    // older servers reject the 'follow' parameter, poll instead
+                    log::debug!("following task log failed, falling back to polling - {err}");
+                    follow = false;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
 
             let active = result["active"].as_bool().unwrap();
             let total = result["total"].as_u64().unwrap();
             let data = result["data"].as_array().unwrap();
 
+            if result["gap"].as_bool().unwrap_or(false) {
+                log::warn!("task log got truncated, continuing after line {total}");
+                start = total + 1;
+            }
+
             let lines = data.len();
 
             for item in data {
@@ -76,18 +128,15 @@ pub async fn display_task_log(
                 if n != start {
                     bail!("got wrong line number in response data ({n} != {start}");
                 }
-                if strip_date && t.len() > 27 && &t[25..27] == ": " {
-                    let line = &t[27..];
-                    println!("{line}");
-                } else {
-                    println!("{t}");
-                }
+                line_callback(t);
                 start += 1;
             }
 
             if start > total {
                 if active {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+                    if !follow {
+                        tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+                    }
                 } else {
                     break;
                 }
@@ -98,21 +147,47 @@ pub async fn display_task_log(
 
         let status_path = format!("api2/json/nodes/localhost/tasks/{upid_encoded}/status");
         let task_result = &client.get(&status_path, None).await?["data"];
-        if task_result["status"].as_str() == Some("stopped") {
-            match task_result["exitstatus"].as_str() {
-                None => bail!("task stopped with unknown status"),
-                Some(status) if status == "OK" || status.starts_with("WARNINGS") => (),
-                Some(status) => bail!("task failed (status {status})"),
-            }
+        if task_result["status"].as_str() != Some("stopped") {
+            return Ok(None);
+        }
+        match task_result["exitstatus"].as_str() {
+            None => bail!("task stopped with unknown status"),
+            Some(status) => Ok(Some(TaskResult {
+                upid: upid_str.to_string(),
+                exitstatus: status.to_string(),
+            })),
         }
-
-        Ok(())
     };
 
     futures::select! {
-        request = request_future.fuse() => request?,
-        abort = abort_future.fuse() => abort?,
-    };
+        request = request_future.fuse() => request,
+        abort = abort_future.fuse() => abort.map(|_| None),
+    }
+}
+
+/// Display task log on console
+///
+/// This follows the task log and prints it to the console, see [`follow_task_log`] for how
+/// interrupt signals are handled.
+pub async fn display_task_log(
+    client: &HttpClient,
+    upid_str: &str,
+    strip_date: bool,
+    forward_interrupt: bool,
+) -> Result<(), Error> {
+    let result = follow_task_log(client, upid_str, forward_interrupt, |t| {
+        if strip_date && t.len() > 27 && &t[25..27] == ": " {
+            let line = &t[27..];
+            println!("{line}");
+        } else {
+            println!("{t}");
+        }
+    })
+    .await?;
+
+    if let Some(result) = result {
+        result.check()?;
+    }
 
     Ok(())
 }
@@ -121,16 +196,25 @@ pub async fn display_task_log(
 ///
 /// In case of a task log of a running task, this will forward interrupt signals
 /// to the task and potentially abort it!
+///
+/// For other output formats than "text", this waits for the task to finish and prints its
+/// [`TaskResult`].
 pub async fn view_task_result(
     client: &HttpClient,
     result: Value,
     output_format: &str,
 ) -> Result<(), Error> {
     let data = &result["data"];
+    let upid = data.as_str().filter(|upid| upid.parse::<UPID>().is_ok());
     if output_format == "text" {
         if let Some(upid) = data.as_str() {
             display_task_log(client, upid, true, true).await?;
         }
+    } else if let Some(upid) = upid {
+        if let Some(result) = follow_task_log(client, upid, true, |_| ()).await? {
+            format_and_print_result(&serde_json::to_value(&result)?, output_format);
+            result.check()?;
+        }
     } else {
         format_and_print_result(data, output_format);
     }
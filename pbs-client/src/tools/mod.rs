@@ -15,7 +15,10 @@ use proxmox_router::cli::{complete_file_name, shellword_split};
 use proxmox_schema::*;
 use proxmox_sys::fs::file_get_json;
 
-use pbs_api_types::{Authid, BackupNamespace, RateLimitConfig, UserWithTokens, BACKUP_REPO_URL};
+use pbs_api_types::{
+    ApiErrorCode, Authid, BackupNamespace, ClientCompressionOptions, RateLimitConfig,
+    UserWithTokens, WireCompression, BACKUP_REPO_URL,
+};
 
 use crate::{BackupRepository, HttpClient, HttpClientOptions};
 
@@ -23,6 +26,7 @@ pub mod key_source;
 
 const ENV_VAR_PBS_FINGERPRINT: &str = "PBS_FINGERPRINT";
 const ENV_VAR_PBS_PASSWORD: &str = "PBS_PASSWORD";
+const ENV_VAR_PBS_UPLOAD_CONCURRENCY: &str = "PBS_UPLOAD_CONCURRENCY";
 
 pub const REPO_URL_SCHEMA: Schema = StringSchema::new("Repository URL.")
     .format(&BACKUP_REPO_URL)
@@ -35,6 +39,20 @@ pub const CHUNK_SIZE_SCHEMA: Schema = IntegerSchema::new("Chunk size in KB. Must
     .default(4096)
     .schema();
 
+pub const UPLOAD_CONCURRENCY_SCHEMA: Schema =
+    IntegerSchema::new("Maximum number of chunk uploads in flight at the same time.")
+        .minimum(1)
+        .maximum(64)
+        .default(1)
+        .schema();
+
+pub const CLIENT_COMPRESSION_SCHEMA: Schema =
+    StringSchema::new("Compression options, e.g. 'wire=zstd' to compress chunks on the wire.")
+        .format(&ApiStringFormat::PropertyString(
+            &ClientCompressionOptions::API_SCHEMA,
+        ))
+        .schema();
+
 /// Helper to read a secret through a environment variable (ENV).
 ///
 /// Tries the following variable names in order and returns the value
@@ -117,6 +135,39 @@ pub fn get_default_repository() -> Option<String> {
     std::env::var("PBS_REPOSITORY").ok()
 }
 
+/// Get the preferred wire compression from the `compress` parameter.
+pub fn extract_wire_compression_from_value(
+    param: &Value,
+) -> Result<Option<WireCompression>, Error> {
+    match param["compress"].as_str() {
+        Some(options) => {
+            let options: ClientCompressionOptions = serde_json::from_value(
+                ClientCompressionOptions::API_SCHEMA.parse_property_string(options)?,
+            )?;
+            Ok(options.wire)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Get the upload concurrency from the `upload-concurrency` parameter, falling back to the
+/// `PBS_UPLOAD_CONCURRENCY` environment variable.
+pub fn extract_upload_concurrency_from_value(param: &Value) -> Result<Option<usize>, Error> {
+    if let Some(concurrency) = param["upload-concurrency"].as_u64() {
+        return Ok(Some(concurrency as usize));
+    }
+
+    match std::env::var(ENV_VAR_PBS_UPLOAD_CONCURRENCY) {
+        Ok(value) => {
+            let concurrency = UPLOAD_CONCURRENCY_SCHEMA
+                .parse_simple_value(value.trim())
+                .map_err(|err| format_err!("invalid {ENV_VAR_PBS_UPLOAD_CONCURRENCY}: {err}"))?;
+            Ok(concurrency.as_u64().map(|c| c as usize))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
 pub fn extract_repository_from_value(param: &Value) -> Result<BackupRepository, Error> {
     let repo_url = param["repository"]
         .as_str()
@@ -139,16 +190,24 @@ pub fn extract_repository_from_map(param: &HashMap<String, String>) -> Option<Ba
 
 pub fn connect(repo: &BackupRepository) -> Result<HttpClient, Error> {
     let rate_limit = RateLimitConfig::default(); // unlimited
-    connect_do(repo.host(), repo.port(), repo.auth_id(), rate_limit)
+    connect_do(repo.host(), repo.port(), repo.auth_id(), rate_limit, None)
         .map_err(|err| format_err!("error building client for repository {} - {}", repo, err))
 }
 
+/// Connect with a rate limit and the preferred wire compression for backup/reader sessions.
 pub fn connect_rate_limited(
     repo: &BackupRepository,
     rate_limit: RateLimitConfig,
+    wire_compression: Option<WireCompression>,
 ) -> Result<HttpClient, Error> {
-    connect_do(repo.host(), repo.port(), repo.auth_id(), rate_limit)
-        .map_err(|err| format_err!("error building client for repository {} - {}", repo, err))
+    connect_do(
+        repo.host(),
+        repo.port(),
+        repo.auth_id(),
+        rate_limit,
+        wire_compression,
+    )
+    .map_err(|err| format_err!("error building client for repository {} - {}", repo, err))
 }
 
 fn connect_do(
@@ -156,11 +215,14 @@ fn connect_do(
     port: u16,
     auth_id: &Authid,
     rate_limit: RateLimitConfig,
+    wire_compression: Option<WireCompression>,
 ) -> Result<HttpClient, Error> {
     let fingerprint = std::env::var(ENV_VAR_PBS_FINGERPRINT).ok();
 
     let password = get_secret_from_env(ENV_VAR_PBS_PASSWORD)?;
-    let options = HttpClientOptions::new_interactive(password, fingerprint).rate_limit(rate_limit);
+    let options = HttpClientOptions::new_interactive(password, fingerprint)
+        .rate_limit(rate_limit)
+        .wire_compression(wire_compression);
 
     HttpClient::new(server, port, auth_id, options)
 }
@@ -422,9 +484,78 @@ pub fn complete_repository(_arg: &str, _param: &HashMap<String, String>) -> Vec<
         }
     }
 
+    let maintenance = match base.place_cache_file(REPO_MAINTENANCE_CACHE) {
+        Ok(path) => file_get_json(path, None).unwrap_or_else(|_| json!({})),
+        _ => json!({}),
+    };
+    sort_by_availability(&mut result, &maintenance);
+
     result
 }
 
+/// Cache file of the repositories whose datastore was found in maintenance mode.
+const REPO_MAINTENANCE_CACHE: &str = "repo-maintenance";
+
+/// Lists repositories whose datastore was found in maintenance mode last.
+fn sort_by_availability(repos: &mut [String], maintenance: &Value) {
+    repos.sort_by_key(|repo| !maintenance[repo.as_str()].is_null());
+}
+
+/// Updates the maintenance `marks` of the repositories with the `result` of connecting to `repo`.
+///
+/// Returns whether the marks changed. Only errors caused by the maintenance mode mark the
+/// repository, any successful connection marks the maintenance as finished.
+fn update_maintenance_marks<T>(marks: &mut Value, repo: &str, result: &Result<T, Error>) -> bool {
+    let in_maintenance = match result {
+        Ok(_) => false,
+        Err(err) if crate::api_error_code(err) == Some(ApiErrorCode::MaintenanceMode) => true,
+        Err(_) => return false,
+    };
+
+    let marked = !marks[repo].is_null();
+    if in_maintenance == marked {
+        return false;
+    }
+    if !marks.is_object() {
+        *marks = json!({});
+    }
+    let map = marks.as_object_mut().unwrap();
+    if in_maintenance {
+        map.insert(repo.to_string(), json!(proxmox_time::epoch_i64()));
+    } else {
+        map.remove(repo);
+    }
+    true
+}
+
+/// Remembers whether the datastore of `repo` is in maintenance mode, from the `result` of
+/// connecting a reader or writer to it.
+///
+/// Such repositories are listed last by [`complete_repository`], until a later connection
+/// succeeds.
+pub fn record_repository_maintenance<T>(repo: &BackupRepository, result: &Result<T, Error>) {
+    let base = match BaseDirectories::with_prefix("proxmox-backup") {
+        Ok(v) => v,
+        _ => return,
+    };
+
+    // usually $HOME/.cache/proxmox-backup/repo-maintenance
+    let path = match base.place_cache_file(REPO_MAINTENANCE_CACHE) {
+        Ok(v) => v,
+        _ => return,
+    };
+
+    let mut marks = file_get_json(&path, None).unwrap_or_else(|_| json!({}));
+    if update_maintenance_marks(&mut marks, &repo.to_string(), result) {
+        let _ = proxmox_sys::fs::replace_file(
+            path,
+            marks.to_string().as_bytes(),
+            proxmox_sys::fs::CreateOptions::new(),
+            false,
+        );
+    }
+}
+
 pub fn complete_backup_source(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
     let mut result = vec![];
 
@@ -526,3 +657,45 @@ pub fn place_xdg_file(
         .and_then(|base| base.place_config_file(file_name).map_err(Error::from))
         .with_context(|| format!("failed to place {} in xdg home", description))
 }
+
+#[cfg(test)]
+mod test {
+    use http::StatusCode;
+
+    use super::*;
+    use crate::ApiResponseError;
+
+    fn response_error(status: StatusCode, body: &[u8]) -> Result<(), Error> {
+        Err(ApiResponseError::from_response(status, body).into())
+    }
+
+    #[test]
+    fn test_maintenance_marks() {
+        let repo = "root@pam@localhost:8007:store1";
+        let maintenance = json!({
+            "message": "datastore 'store1' is in offline maintenance mode: ",
+            "code": "maintenance-mode",
+        })
+        .to_string();
+        let mut marks = json!({});
+
+        // only errors caused by the maintenance mode mark the repository
+        let err = response_error(StatusCode::BAD_REQUEST, b"other error");
+        assert!(!update_maintenance_marks(&mut marks, repo, &err));
+        let err = response_error(StatusCode::SERVICE_UNAVAILABLE, maintenance.as_bytes());
+        assert!(update_maintenance_marks(&mut marks, repo, &err));
+        assert!(!marks[repo].is_null());
+        assert!(!update_maintenance_marks(&mut marks, repo, &err));
+
+        let mut repos = vec![repo.to_string(), "localhost:store2".to_string()];
+        sort_by_availability(&mut repos, &marks);
+        assert_eq!(repos, vec!["localhost:store2", repo]);
+
+        // other errors keep the mark, connecting successfully marks the maintenance as finished
+        let err = response_error(StatusCode::BAD_REQUEST, b"other error");
+        assert!(!update_maintenance_marks(&mut marks, repo, &err));
+        assert!(update_maintenance_marks(&mut marks, repo, &Ok(())));
+        assert_eq!(marks, json!({}));
+        assert!(!update_maintenance_marks(&mut marks, repo, &Ok(())));
+    }
+}
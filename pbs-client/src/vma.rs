@@ -0,0 +1,628 @@
+//! Reader for the VMA archives written by vzdump for QEMU virtual machines.
+//!
+//! A VMA archive starts with a header listing the guest configuration files and disk images,
+//! followed by a sequence of extents. Every extent holds up to 59 clusters of 64 KiB, of which
+//! only the 4 KiB blocks containing non-zero data are stored.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{bail, format_err, Error};
+use openssl::hash::{hash, MessageDigest};
+
+/// Size of the blocks stored in a VMA archive, all-zero blocks are omitted.
+pub const VMA_BLOCK_SIZE: usize = 4096;
+/// Size of the clusters referenced by VMA extents.
+pub const VMA_CLUSTER_SIZE: usize = 65536;
+
+const VMA_MAGIC: &[u8; 4] = b"VMA\0";
+const VMA_EXTENT_MAGIC: &[u8; 4] = b"VMAE";
+const VMA_VERSION: u32 = 1;
+const VMA_HEADER_FIXED_SIZE: usize = 12288;
+const VMA_MAX_HEADER_SIZE: usize = 16 * 1024 * 1024;
+const VMA_MAX_CONFIGS: usize = 256;
+const VMA_MAX_DEVICES: usize = 256;
+const VMA_EXTENT_HEADER_SIZE: usize = 512;
+const VMA_BLOCKS_PER_EXTENT: usize = 59;
+const VMA_BLOCKS_PER_CLUSTER: usize = VMA_CLUSTER_SIZE / VMA_BLOCK_SIZE;
+/// Extents address clusters with 32 bit numbers.
+const VMA_MAX_CLUSTERS: u64 = 1 << 32;
+/// Clusters per page of a [`ClusterBitmap`], 16 GiB of disk image in 32 KiB.
+const BITMAP_PAGE_CLUSTERS: u64 = 64 * 4096;
+
+// offsets of the fields in the (big endian) archive header
+const HEADER_UUID: usize = 8;
+const HEADER_CTIME: usize = 24;
+const HEADER_MD5SUM: usize = 32;
+const HEADER_BLOB_BUFFER_OFFSET: usize = 48;
+const HEADER_BLOB_BUFFER_SIZE: usize = 52;
+const HEADER_SIZE: usize = 56;
+const HEADER_CONFIG_NAMES: usize = 2044;
+const HEADER_CONFIG_DATA: usize = 3068;
+const HEADER_DEV_INFO: usize = 4096;
+const DEV_INFO_SIZE: usize = 32;
+
+// offsets of the fields in the (big endian) extent header
+const EXTENT_BLOCK_COUNT: usize = 6;
+const EXTENT_UUID: usize = 8;
+const EXTENT_MD5SUM: usize = 24;
+const EXTENT_BLOCK_INFO: usize = 40;
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn be_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Checks the MD5 sum stored at `offset`, which is computed with the sum itself zeroed.
+fn check_md5sum(data: &mut [u8], offset: usize) -> Result<bool, Error> {
+    let mut expected = [0u8; 16];
+    expected.copy_from_slice(&data[offset..offset + 16]);
+    data[offset..offset + 16].fill(0);
+    let md5sum = hash(MessageDigest::md5(), data)?;
+    data[offset..offset + 16].copy_from_slice(&expected);
+    Ok(md5sum[..] == expected[..])
+}
+
+/// Returns the blob at `pos` of the blob buffer, which is prefixed by its 16 bit length.
+fn blob_data(blobs: &[u8], pos: u32) -> Result<&[u8], Error> {
+    let pos = pos as usize;
+    if pos == 0 || pos + 2 > blobs.len() {
+        bail!("invalid VMA blob offset {pos}");
+    }
+    let len = u16::from_le_bytes([blobs[pos], blobs[pos + 1]]) as usize;
+    blobs
+        .get(pos + 2..pos + 2 + len)
+        .ok_or_else(|| format_err!("VMA blob at offset {pos} exceeds the blob buffer"))
+}
+
+fn blob_string(blobs: &[u8], pos: u32) -> Result<String, Error> {
+    match blob_data(blobs, pos)?.split_last() {
+        Some((0, text)) => Ok(std::str::from_utf8(text)?.to_string()),
+        _ => bail!("VMA string at offset {pos} is not null terminated"),
+    }
+}
+
+/// Fills `buf` completely, returns `false` if the reader is at EOF before the first byte.
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, Error> {
+    let mut done = 0;
+    while done < buf.len() {
+        match reader.read(&mut buf[done..]) {
+            Ok(0) if done == 0 => return Ok(false),
+            Ok(0) => bail!("unexpected end of file"),
+            Ok(got) => done += got,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Bitmap of the clusters of a disk image, with pages allocated on first use.
+///
+/// The memory used grows with the clusters stored in the archive, not with the device size stated
+/// in its header.
+#[derive(Default)]
+struct ClusterBitmap {
+    pages: HashMap<u64, Box<[u64]>>,
+}
+
+impl ClusterBitmap {
+    /// Marks `cluster` as seen, returns `false` if it was already.
+    fn insert(&mut self, cluster: u64) -> bool {
+        let page = self
+            .pages
+            .entry(cluster / BITMAP_PAGE_CLUSTERS)
+            .or_insert_with(|| vec![0u64; (BITMAP_PAGE_CLUSTERS / 64) as usize].into());
+        let index = cluster % BITMAP_PAGE_CLUSTERS;
+        let word = &mut page[(index / 64) as usize];
+        let bit = 1u64 << (index % 64);
+        if *word & bit != 0 {
+            return false;
+        }
+        *word |= bit;
+        true
+    }
+
+    /// Returns the first cluster below `clusters` which was not seen.
+    fn first_missing(&self, clusters: u64) -> Option<u64> {
+        for page_num in 0..clusters.div_ceil(BITMAP_PAGE_CLUSTERS) {
+            let start = page_num * BITMAP_PAGE_CLUSTERS;
+            let page = match self.pages.get(&page_num) {
+                Some(page) => page,
+                None => return Some(start),
+            };
+            for (i, word) in page.iter().enumerate() {
+                let cluster = start + i as u64 * 64 + u64::from(word.trailing_ones());
+                if cluster >= clusters {
+                    return None;
+                }
+                if *word != u64::MAX {
+                    return Some(cluster);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// A disk image contained in a VMA archive.
+#[derive(Clone, Debug)]
+pub struct VmaDevice {
+    /// Id referenced by the extents (1 to 255).
+    pub id: u8,
+    /// Device name, e.g. `drive-scsi0`.
+    pub name: String,
+    /// Size of the disk image in bytes.
+    pub size: u64,
+}
+
+/// Header of a VMA archive.
+#[derive(Clone, Debug)]
+pub struct VmaHeader {
+    pub uuid: [u8; 16],
+    /// Creation time of the archive (epoch).
+    pub ctime: i64,
+    /// Configuration files stored in the archive, as name and content.
+    pub configs: Vec<(String, Vec<u8>)>,
+    pub devices: Vec<VmaDevice>,
+}
+
+/// A cluster of a disk image, as read from a VMA extent.
+pub struct VmaCluster<'a> {
+    pub dev_id: u8,
+    /// Offset of the cluster in the disk image.
+    pub offset: u64,
+    mask: u16,
+    data: &'a [u8],
+}
+
+impl<'a> VmaCluster<'a> {
+    /// Returns whether the whole cluster is zero.
+    pub fn is_zero(&self) -> bool {
+        self.mask == 0
+    }
+
+    /// Returns the runs of consecutive non-zero blocks with their offset in the disk image.
+    ///
+    /// All other blocks of the cluster are zero.
+    pub fn data_runs(&self) -> Vec<(u64, &'a [u8])> {
+        let mut runs = Vec::new();
+        let mut data_pos = 0;
+        let mut block = 0;
+        while block < VMA_BLOCKS_PER_CLUSTER {
+            if self.mask & (1 << block) == 0 {
+                block += 1;
+                continue;
+            }
+            let start = block;
+            while block < VMA_BLOCKS_PER_CLUSTER && self.mask & (1 << block) != 0 {
+                block += 1;
+            }
+            let len = (block - start) * VMA_BLOCK_SIZE;
+            let offset = self.offset + (start * VMA_BLOCK_SIZE) as u64;
+            runs.push((offset, &self.data[data_pos..data_pos + len]));
+            data_pos += len;
+        }
+        runs
+    }
+}
+
+/// Sequential reader for VMA archives.
+///
+/// All checksums are verified while reading. Since extents can come in any order, the reader
+/// keeps track of the clusters seen, to detect truncated archives at their end.
+pub struct VmaReader<R> {
+    reader: R,
+    header: VmaHeader,
+    /// Device sizes indexed by device id, zero for unused ids.
+    device_sizes: Vec<u64>,
+    /// Bitmaps of the clusters read so far, indexed by device id.
+    clusters_seen: Vec<ClusterBitmap>,
+    data: Vec<u8>,
+}
+
+impl<R: Read> VmaReader<R> {
+    /// Reads and verifies the archive header.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut buffer = vec![0u8; VMA_HEADER_FIXED_SIZE];
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|err| format_err!("unable to read VMA header - {err}"))?;
+
+        if &buffer[0..4] != VMA_MAGIC {
+            bail!("not a VMA archive (wrong magic)");
+        }
+        let version = be_u32(&buffer, 4);
+        if version != VMA_VERSION {
+            bail!("unsupported VMA version {version}");
+        }
+
+        let header_size = be_u32(&buffer, HEADER_SIZE) as usize;
+        if !(VMA_HEADER_FIXED_SIZE..=VMA_MAX_HEADER_SIZE).contains(&header_size) {
+            bail!("invalid VMA header size {header_size}");
+        }
+        buffer.resize(header_size, 0);
+        reader
+            .read_exact(&mut buffer[VMA_HEADER_FIXED_SIZE..])
+            .map_err(|err| format_err!("unable to read VMA header - {err}"))?;
+
+        if !check_md5sum(&mut buffer, HEADER_MD5SUM)? {
+            bail!("VMA header checksum mismatch");
+        }
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&buffer[HEADER_UUID..HEADER_UUID + 16]);
+        let ctime = be_u64(&buffer, HEADER_CTIME) as i64;
+
+        let blob_offset = be_u32(&buffer, HEADER_BLOB_BUFFER_OFFSET) as usize;
+        let blob_size = be_u32(&buffer, HEADER_BLOB_BUFFER_SIZE) as usize;
+        if blob_offset < VMA_HEADER_FIXED_SIZE || blob_offset + blob_size > header_size {
+            bail!("invalid VMA blob buffer ({blob_offset}, {blob_size})");
+        }
+        let blobs = &buffer[blob_offset..blob_offset + blob_size];
+
+        let mut configs = Vec::new();
+        for i in 0..VMA_MAX_CONFIGS {
+            let name_pos = be_u32(&buffer, HEADER_CONFIG_NAMES + i * 4);
+            if name_pos == 0 {
+                continue;
+            }
+            let data_pos = be_u32(&buffer, HEADER_CONFIG_DATA + i * 4);
+            let name = blob_string(blobs, name_pos)?;
+            let data = blob_data(blobs, data_pos)?.to_vec();
+            configs.push((name, data));
+        }
+
+        let mut devices = Vec::new();
+        let mut device_sizes = vec![0; VMA_MAX_DEVICES];
+        let mut clusters_seen = Vec::new();
+        clusters_seen.resize_with(VMA_MAX_DEVICES, ClusterBitmap::default);
+        // device id 0 is reserved
+        for id in 1..VMA_MAX_DEVICES {
+            let info = HEADER_DEV_INFO + id * DEV_INFO_SIZE;
+            let name_pos = be_u32(&buffer, info);
+            if name_pos == 0 {
+                continue;
+            }
+            let name = blob_string(blobs, name_pos)?;
+            let size = be_u64(&buffer, info + 8);
+            if size == 0 {
+                bail!("VMA device '{name}' has size zero");
+            }
+            if size.div_ceil(VMA_CLUSTER_SIZE as u64) > VMA_MAX_CLUSTERS {
+                bail!("VMA device '{name}' is too large ({size} bytes)");
+            }
+            device_sizes[id] = size;
+            devices.push(VmaDevice {
+                id: id as u8,
+                name,
+                size,
+            });
+        }
+
+        Ok(Self {
+            reader,
+            header: VmaHeader {
+                uuid,
+                ctime,
+                configs,
+                devices,
+            },
+            device_sizes,
+            clusters_seen,
+            data: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> &VmaHeader {
+        &self.header
+    }
+
+    /// Reads the next extent and passes its clusters to `callback`.
+    ///
+    /// Returns `false` at the end of the archive, after checking that it contained every
+    /// cluster of every disk image.
+    pub fn read_extent<F>(&mut self, mut callback: F) -> Result<bool, Error>
+    where
+        F: FnMut(VmaCluster) -> Result<(), Error>,
+    {
+        let mut head = [0u8; VMA_EXTENT_HEADER_SIZE];
+        let got_header = read_exact_or_eof(&mut self.reader, &mut head)
+            .map_err(|err| format_err!("unable to read VMA extent header - {err}"))?;
+        if !got_header {
+            self.check_complete()?;
+            return Ok(false);
+        }
+
+        if &head[0..4] != VMA_EXTENT_MAGIC {
+            bail!("invalid VMA extent magic");
+        }
+        if head[EXTENT_UUID..EXTENT_UUID + 16] != self.header.uuid {
+            bail!("VMA extent belongs to a different archive (UUID mismatch)");
+        }
+        if !check_md5sum(&mut head, EXTENT_MD5SUM)? {
+            bail!("VMA extent header checksum mismatch");
+        }
+
+        let block_count =
+            u16::from_be_bytes([head[EXTENT_BLOCK_COUNT], head[EXTENT_BLOCK_COUNT + 1]]) as usize;
+
+        let mut clusters = Vec::with_capacity(VMA_BLOCKS_PER_EXTENT);
+        let mut blocks = 0;
+        for i in 0..VMA_BLOCKS_PER_EXTENT {
+            let info = be_u64(&head, EXTENT_BLOCK_INFO + i * 8);
+            let dev_id = ((info >> 32) & 0xff) as u8;
+            if dev_id == 0 {
+                continue;
+            }
+            let mask = (info >> 48) as u16;
+            let cluster_num = info & 0xffff_ffff;
+
+            let size = self.device_sizes[dev_id as usize];
+            if size == 0 {
+                bail!("VMA extent references unknown device {dev_id}");
+            }
+            let offset = cluster_num * VMA_CLUSTER_SIZE as u64;
+            if offset >= size {
+                bail!("VMA cluster {cluster_num} is beyond the end of device {dev_id}");
+            }
+
+            if !self.clusters_seen[dev_id as usize].insert(cluster_num) {
+                bail!("VMA cluster {cluster_num} of device {dev_id} is stored twice");
+            }
+
+            blocks += mask.count_ones() as usize;
+            clusters.push((dev_id, offset, mask));
+        }
+
+        if blocks != block_count {
+            bail!("VMA extent block count mismatch ({blocks} != {block_count})");
+        }
+
+        self.data.resize(block_count * VMA_BLOCK_SIZE, 0);
+        self.reader
+            .read_exact(&mut self.data)
+            .map_err(|err| format_err!("unable to read VMA extent data - {err}"))?;
+
+        let mut pos = 0;
+        for (dev_id, offset, mask) in clusters {
+            let len = mask.count_ones() as usize * VMA_BLOCK_SIZE;
+            callback(VmaCluster {
+                dev_id,
+                offset,
+                mask,
+                data: &self.data[pos..pos + len],
+            })?;
+            pos += len;
+        }
+
+        Ok(true)
+    }
+
+    fn check_complete(&self) -> Result<(), Error> {
+        for device in self.header.devices.iter() {
+            let clusters = device.size.div_ceil(VMA_CLUSTER_SIZE as u64);
+            if let Some(cluster_num) =
+                self.clusters_seen[device.id as usize].first_missing(clusters)
+            {
+                bail!(
+                    "VMA archive is incomplete - cluster {cluster_num} of device '{}' missing",
+                    device.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct TestDevice {
+        name: &'static str,
+        data: Vec<u8>,
+    }
+
+    fn add_blob(blobs: &mut Vec<u8>, data: &[u8]) -> u32 {
+        let pos = blobs.len() as u32;
+        blobs.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        blobs.extend_from_slice(data);
+        pos
+    }
+
+    fn set_md5sum(data: &mut [u8], offset: usize) {
+        let md5sum = hash(MessageDigest::md5(), data).unwrap();
+        data[offset..offset + 16].copy_from_slice(&md5sum);
+    }
+
+    /// Creates an archive with one extent per cluster, in reverse order.
+    fn create_vma(configs: &[(&str, &[u8])], devices: &[TestDevice]) -> Vec<u8> {
+        let uuid = [0x42u8; 16];
+        let mut header = vec![0u8; VMA_HEADER_FIXED_SIZE];
+        // offset zero is invalid, so skip the first byte
+        let mut blobs = vec![0u8];
+
+        for (i, (name, data)) in configs.iter().enumerate() {
+            let name_pos = add_blob(&mut blobs, format!("{name}\0").as_bytes());
+            let data_pos = add_blob(&mut blobs, data);
+            let offset = HEADER_CONFIG_NAMES + i * 4;
+            header[offset..offset + 4].copy_from_slice(&name_pos.to_be_bytes());
+            let offset = HEADER_CONFIG_DATA + i * 4;
+            header[offset..offset + 4].copy_from_slice(&data_pos.to_be_bytes());
+        }
+        for (i, device) in devices.iter().enumerate() {
+            let name_pos = add_blob(&mut blobs, format!("{}\0", device.name).as_bytes());
+            let info = HEADER_DEV_INFO + (i + 1) * DEV_INFO_SIZE;
+            header[info..info + 4].copy_from_slice(&name_pos.to_be_bytes());
+            let size = device.data.len() as u64;
+            header[info + 8..info + 16].copy_from_slice(&size.to_be_bytes());
+        }
+
+        header[0..4].copy_from_slice(VMA_MAGIC);
+        header[4..8].copy_from_slice(&VMA_VERSION.to_be_bytes());
+        header[HEADER_UUID..HEADER_UUID + 16].copy_from_slice(&uuid);
+        header[HEADER_CTIME..HEADER_CTIME + 8].copy_from_slice(&1_700_000_000u64.to_be_bytes());
+        let blob_offset = VMA_HEADER_FIXED_SIZE as u32;
+        let blob_size = blobs.len() as u32;
+        let header_size = blob_offset + blob_size;
+        header[HEADER_BLOB_BUFFER_OFFSET..HEADER_BLOB_BUFFER_OFFSET + 4]
+            .copy_from_slice(&blob_offset.to_be_bytes());
+        header[HEADER_BLOB_BUFFER_SIZE..HEADER_BLOB_BUFFER_SIZE + 4]
+            .copy_from_slice(&blob_size.to_be_bytes());
+        header[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&header_size.to_be_bytes());
+        header.extend_from_slice(&blobs);
+        set_md5sum(&mut header, HEADER_MD5SUM);
+
+        let mut archive = header;
+        for (i, device) in devices.iter().enumerate() {
+            let dev_id = (i + 1) as u64;
+            let clusters: Vec<&[u8]> = device.data.chunks(VMA_CLUSTER_SIZE).collect();
+            for (cluster_num, cluster) in clusters.iter().enumerate().rev() {
+                let mut mask = 0u64;
+                let mut data = Vec::new();
+                for (block, block_data) in cluster.chunks(VMA_BLOCK_SIZE).enumerate() {
+                    if block_data.iter().any(|b| *b != 0) {
+                        mask |= 1 << block;
+                        data.extend_from_slice(block_data);
+                        data.resize(data.len().next_multiple_of(VMA_BLOCK_SIZE), 0);
+                    }
+                }
+                let mut head = vec![0u8; VMA_EXTENT_HEADER_SIZE];
+                head[0..4].copy_from_slice(VMA_EXTENT_MAGIC);
+                let block_count = (data.len() / VMA_BLOCK_SIZE) as u16;
+                head[EXTENT_BLOCK_COUNT..EXTENT_BLOCK_COUNT + 2]
+                    .copy_from_slice(&block_count.to_be_bytes());
+                head[EXTENT_UUID..EXTENT_UUID + 16].copy_from_slice(&uuid);
+                let info = (mask << 48) | (dev_id << 32) | cluster_num as u64;
+                head[EXTENT_BLOCK_INFO..EXTENT_BLOCK_INFO + 8].copy_from_slice(&info.to_be_bytes());
+                set_md5sum(&mut head, EXTENT_MD5SUM);
+                archive.extend_from_slice(&head);
+                archive.extend_from_slice(&data);
+            }
+        }
+
+        archive
+    }
+
+    fn read_vma(archive: &[u8]) -> Result<(VmaHeader, Vec<Vec<u8>>), Error> {
+        let mut reader = VmaReader::new(archive)?;
+        let header = reader.header().clone();
+        let mut images: Vec<Vec<u8>> = header
+            .devices
+            .iter()
+            .map(|device| vec![0u8; device.size as usize])
+            .collect();
+        while reader.read_extent(|cluster| {
+            let image = &mut images[cluster.dev_id as usize - 1];
+            for (offset, data) in cluster.data_runs() {
+                let start = offset as usize;
+                let end = (start + data.len()).min(image.len());
+                image[start..end].copy_from_slice(&data[..end - start]);
+            }
+            Ok(())
+        })? {}
+        Ok((header, images))
+    }
+
+    fn test_devices() -> Vec<TestDevice> {
+        let mut disk0 = vec![0u8; 3 * VMA_CLUSTER_SIZE];
+        disk0[0..10].copy_from_slice(b"bootsector");
+        disk0[5 * VMA_BLOCK_SIZE..7 * VMA_BLOCK_SIZE].fill(0xaa);
+        // the last block of the second cluster and the first of the third one
+        disk0[2 * VMA_CLUSTER_SIZE - 1] = 1;
+        disk0[2 * VMA_CLUSTER_SIZE] = 2;
+
+        // size not aligned to the cluster size, with data in the last block
+        let mut disk1 = vec![0u8; VMA_CLUSTER_SIZE + 3 * 512];
+        *disk1.last_mut().unwrap() = 0xff;
+
+        vec![
+            TestDevice {
+                name: "drive-scsi0",
+                data: disk0,
+            },
+            TestDevice {
+                name: "drive-efidisk0",
+                data: disk1,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_vma_read() -> Result<(), Error> {
+        let devices = test_devices();
+        let archive = create_vma(&[("qemu-server.conf", b"memory: 2048\n")], &devices);
+
+        let (header, images) = read_vma(&archive)?;
+
+        assert_eq!(header.ctime, 1_700_000_000);
+        assert_eq!(header.configs.len(), 1);
+        assert_eq!(header.configs[0].0, "qemu-server.conf");
+        assert_eq!(header.configs[0].1, b"memory: 2048\n");
+        assert_eq!(header.devices.len(), 2);
+        assert_eq!(header.devices[0].name, "drive-scsi0");
+        assert_eq!(header.devices[1].id, 2);
+        assert_eq!(header.devices[1].size, devices[1].data.len() as u64);
+        assert!(images[0] == devices[0].data);
+        assert!(images[1] == devices[1].data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vma_corrupt() {
+        let devices = test_devices();
+        let archive = create_vma(&[("qemu-server.conf", b"memory: 2048\n")], &devices);
+
+        let mut corrupt = archive.clone();
+        corrupt[VMA_HEADER_FIXED_SIZE + 5] ^= 1;
+        assert!(read_vma(&corrupt).is_err());
+
+        // truncated within an extent
+        assert!(read_vma(&archive[..archive.len() - 1]).is_err());
+
+        // truncated at an extent boundary, the (all zero) first cluster of the last device is
+        // missing
+        assert!(read_vma(&archive[..archive.len() - VMA_EXTENT_HEADER_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_vma_device_size() {
+        let devices = test_devices();
+        let archive = create_vma(&[], &devices);
+
+        let with_size = |size: u64| {
+            let mut archive = archive.clone();
+            let info = HEADER_DEV_INFO + DEV_INFO_SIZE;
+            archive[info + 8..info + 16].copy_from_slice(&size.to_be_bytes());
+            let header_size = be_u32(&archive, HEADER_SIZE) as usize;
+            archive[HEADER_MD5SUM..HEADER_MD5SUM + 16].fill(0);
+            set_md5sum(&mut archive[..header_size], HEADER_MD5SUM);
+            archive
+        };
+
+        // the largest size extents can address, the clusters seen are tracked without
+        // allocating a bitmap of the whole device, so the missing clusters are detected
+        let archive = with_size(VMA_MAX_CLUSTERS * VMA_CLUSTER_SIZE as u64);
+        let mut reader = VmaReader::new(&archive[..]).unwrap();
+        let err = loop {
+            match reader.read_extent(|_| Ok(())) {
+                Ok(true) => continue,
+                Ok(false) => panic!("archive with missing clusters read successfully"),
+                Err(err) => break err,
+            }
+        };
+        assert!(err
+            .to_string()
+            .contains("cluster 3 of device 'drive-scsi0' missing"));
+
+        assert!(
+            VmaReader::new(&with_size(VMA_MAX_CLUSTERS * VMA_CLUSTER_SIZE as u64 + 1)[..]).is_err()
+        );
+        assert!(VmaReader::new(&with_size(u64::MAX)[..]).is_err());
+    }
+}
@@ -1,16 +1,18 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 
-use anyhow::{bail, Error};
+use anyhow::{bail, format_err, Error};
 
 use lazy_static::lazy_static;
 
 use proxmox_schema::{ApiStringFormat, ApiType, Schema, StringSchema};
 
-use pbs_api_types::{Authid, Role, Userid, ROLE_NAME_NO_ACCESS};
+use pbs_api_types::{
+    Authid, BackupNamespace, Role, Userid, MAX_NAMESPACE_DEPTH, ROLE_NAME_NO_ACCESS,
+};
 
 use crate::{open_backup_lockfile, replace_backup_config, BackupLockGuard};
 
@@ -85,9 +87,17 @@ pub fn check_acl_path(path: &str) -> Result<(), Error> {
             if components_len <= 2 {
                 return Ok(());
             }
-            if components_len > 2 && components_len <= 2 + pbs_api_types::MAX_NAMESPACE_DEPTH {
-                return Ok(());
+            // /datastore/{store}/{ns}/{ns}/...
+            let ns_depth = components_len - 2;
+            if ns_depth > MAX_NAMESPACE_DEPTH {
+                bail!(
+                    "invalid acl path '{path}' - namespace depth {ns_depth} exceeds limit of \
+                    {MAX_NAMESPACE_DEPTH}"
+                );
             }
+            BackupNamespace::new(&components[2..].join("/"))
+                .map_err(|err| format_err!("invalid acl path '{path}' - {err}"))?;
+            return Ok(());
         }
         "remote" => {
             // /remote/{remote}/{store}
@@ -309,6 +319,24 @@ impl AclTreeNode {
         }
     }
 
+    fn role_in_use(&self, role: &str) -> bool {
+        self.users.values().any(|roles| roles.contains_key(role))
+            || self.groups.values().any(|roles| roles.contains_key(role))
+            || self.children.values().any(|node| node.role_in_use(role))
+    }
+
+    fn delete_role(&mut self, role: &str) {
+        for node in self.children.values_mut() {
+            node.delete_role(role);
+        }
+        for roles in self.users.values_mut() {
+            roles.remove(role);
+        }
+        for roles in self.groups.values_mut() {
+            roles.remove(role);
+        }
+    }
+
     fn get_child_paths(
         &self,
         path: String,
@@ -446,6 +474,16 @@ impl AclTree {
         node.insert_user_role(auth_id.to_owned(), role.to_string(), propagate);
     }
 
+    /// Returns `true` if any ACL entry in the tree references `role`.
+    pub fn role_in_use(&self, role: &str) -> bool {
+        self.root.role_in_use(role)
+    }
+
+    /// Removes all ACL entries referencing `role` from the tree.
+    pub fn delete_role(&mut self, role: &str) {
+        self.root.delete_role(role);
+    }
+
     fn write_node_config(node: &AclTreeNode, path: &str, w: &mut dyn Write) -> Result<(), Error> {
         let mut role_ug_map0: HashMap<_, BTreeSet<_>> = HashMap::new();
         let mut role_ug_map1: HashMap<_, BTreeSet<_>> = HashMap::new();
@@ -547,7 +585,13 @@ impl AclTree {
         Self::write_node_config(&self.root, "", w)
     }
 
-    fn parse_acl_line(&mut self, line: &str) -> Result<(), Error> {
+    // Without `custom_roles`, e.g. if the role config is broken, unknown roles are kept so that
+    // the entries are not lost on the next write. They do not grant any privileges.
+    fn parse_acl_line(
+        &mut self,
+        line: &str,
+        custom_roles: Option<&HashSet<String>>,
+    ) -> Result<(), Error> {
         let items: Vec<&str> = line.split(':').collect();
 
         if items.len() != 5 {
@@ -576,7 +620,13 @@ impl AclTree {
 
         for user_or_group in &uglist {
             for role in &rolelist {
-                if !ROLE_NAMES.contains_key(role) {
+                let known = match custom_roles {
+                    Some(custom_roles) => {
+                        ROLE_NAMES.contains_key(role) || custom_roles.contains(*role)
+                    }
+                    None => true,
+                };
+                if !known {
                     bail!("unknown role '{}'", role);
                 }
                 if let Some(group) = user_or_group.strip_prefix('@') {
@@ -606,12 +656,15 @@ impl AclTree {
 
         let digest = openssl::sha::sha256(raw.as_bytes());
 
+        let custom_roles: Option<HashSet<String>> = crate::roles::config_or_builtin_only()
+            .map(|roles| roles.sections.into_keys().collect());
+
         for (linenr, line) in raw.lines().enumerate() {
             let line = line.trim();
             if line.is_empty() {
                 continue;
             }
-            if let Err(err) = tree.parse_acl_line(line) {
+            if let Err(err) = tree.parse_acl_line(line, custom_roles.as_ref()) {
                 bail!(
                     "unable to parse acl config {:?}, line {} - {}",
                     filename,
@@ -632,7 +685,7 @@ impl AclTree {
             if line.is_empty() {
                 continue;
             }
-            if let Err(err) = tree.parse_acl_line(line) {
+            if let Err(err) = tree.parse_acl_line(line, Some(&HashSet::new())) {
                 bail!(
                     "unable to parse acl config data, line {} - {}",
                     linenr + 1,
@@ -681,6 +734,53 @@ impl AclTree {
         role_map
     }
 
+    /// Returns the nodes along `path` which define applicable roles for `auth_id`.
+    ///
+    /// Each entry consists of the ACL path of the node and the roles with their propagate flag,
+    /// ordered from the root towards `path`. Like in [`roles`](Self::roles), only the last entry
+    /// is effective, as more specific ACLs override inherited ones.
+    pub fn role_origins(
+        &self,
+        auth_id: &Authid,
+        path: &[&str],
+    ) -> Vec<(String, HashMap<String, bool>)> {
+        let mut origins = Vec::new();
+
+        let mut node = &self.root;
+        let mut node_path = String::new();
+
+        let roles = node.extract_roles(auth_id, path.is_empty());
+        if !roles.is_empty() {
+            origins.push(("/".to_string(), roles));
+        }
+
+        let mut comp_iter = path.iter().peekable();
+
+        while let Some(comp) = comp_iter.next() {
+            let last_comp = comp_iter.peek().is_none();
+
+            let mut sub_comp_iter = comp.split('/').peekable();
+
+            while let Some(sub_comp) = sub_comp_iter.next() {
+                let last_sub_comp = last_comp && sub_comp_iter.peek().is_none();
+
+                node = match node.children.get(sub_comp) {
+                    Some(n) => n,
+                    None => return origins, // path not found
+                };
+                node_path.push('/');
+                node_path.push_str(sub_comp);
+
+                let roles = node.extract_roles(auth_id, last_sub_comp);
+                if !roles.is_empty() {
+                    origins.push((node_path.clone(), roles));
+                }
+            }
+        }
+
+        origins
+    }
+
     pub fn get_child_paths(&self, auth_id: &Authid, path: &[&str]) -> Result<Vec<String>, Error> {
         let mut res = Vec::new();
 
@@ -1014,6 +1114,55 @@ acl:1:/storage/store1:user1@pbs:DatastoreBackup
         Ok(())
     }
 
+    #[test]
+    fn test_parse_custom_roles() -> Result<(), Error> {
+        let user1: Authid = "user1@pbs".parse()?;
+        let custom_roles: std::collections::HashSet<String> = ["MyRole".to_string()].into();
+
+        let mut tree = AclTree::new();
+        tree.parse_acl_line("acl:1:/datastore:user1@pbs:MyRole", Some(&custom_roles))?;
+        assert!(tree
+            .parse_acl_line("acl:1:/datastore:user1@pbs:OtherRole", Some(&custom_roles))
+            .is_err());
+        check_roles(&tree, &user1, "/datastore", "MyRole");
+
+        // with an unreadable role config, entries are kept instead of failing
+        let mut tree = AclTree::new();
+        tree.parse_acl_line("acl:1:/datastore:user1@pbs:Audit,OtherRole", None)?;
+        check_roles(&tree, &user1, "/datastore", "Audit,OtherRole");
+
+        let mut raw: Vec<u8> = Vec::new();
+        tree.write_config(&mut raw)?;
+        let raw = std::str::from_utf8(&raw)?;
+        assert!(raw.contains("OtherRole"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_role() -> Result<(), Error> {
+        let mut tree = AclTree::new();
+
+        let user1: Authid = "user1@pbs".parse()?;
+        let user2: Authid = "user2@pbs".parse()?;
+
+        tree.insert_user_role("/", &user1, "Audit", true);
+        tree.insert_user_role("/datastore/store1", &user1, "MyRole", true);
+        tree.insert_user_role("/datastore/store1", &user2, "MyRole", false);
+        tree.insert_user_role("/datastore/store1", &user2, "DatastoreReader", false);
+
+        assert!(tree.role_in_use("MyRole"));
+        assert!(!tree.role_in_use("TapeAdmin"));
+
+        tree.delete_role("MyRole");
+        assert!(!tree.role_in_use("MyRole"));
+
+        check_roles(&tree, &user1, "/datastore/store1", "Audit");
+        check_roles(&tree, &user2, "/datastore/store1", "DatastoreReader");
+
+        Ok(())
+    }
+
     #[test]
     fn test_delete_authid() -> Result<(), Error> {
         let mut tree = AclTree::new();
@@ -1069,4 +1218,44 @@ acl:1:/storage/store1:user1@pbs:DatastoreBackup
 
         Ok(())
     }
+
+    #[test]
+    fn test_check_acl_path_namespaces() {
+        use super::check_acl_path;
+
+        assert!(check_acl_path("/datastore/store1").is_ok());
+        assert!(check_acl_path("/datastore/store1/ns1").is_ok());
+        assert!(check_acl_path("/datastore/store1/a/b/c/d/e/f/g").is_ok());
+
+        // too deep
+        assert!(check_acl_path("/datastore/store1/a/b/c/d/e/f/g/h").is_err());
+        // invalid namespace components
+        assert!(check_acl_path("/datastore/store1/ns1/n s").is_err());
+        assert!(check_acl_path("/datastore/store1/ns1/.hidden").is_err());
+    }
+
+    #[test]
+    fn test_role_origins() -> Result<(), Error> {
+        let tree = AclTree::from_raw(
+            "\
+            acl:1:/:user1@pbs:Audit\n\
+            acl:1:/datastore:user1@pbs:DatastoreReader\n\
+            acl:0:/datastore/store1/ns1:user1@pbs:DatastoreBackup\n\
+            ",
+        )?;
+
+        let user1: Authid = "user1@pbs".parse()?;
+
+        let origins = tree.role_origins(&user1, &["datastore", "store1", "ns1"]);
+        let paths: Vec<&str> = origins.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["/", "/datastore", "/datastore/store1/ns1"]);
+        assert!(origins[2].1.contains_key("DatastoreBackup"));
+
+        // non-propagated ACLs only apply to the exact path
+        let origins = tree.role_origins(&user1, &["datastore", "store1", "ns1", "ns2"]);
+        let paths: Vec<&str> = origins.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, ["/", "/datastore"]);
+
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
 //! Cached user info for fast ACL permission checks
 
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
 use anyhow::{bail, Error};
@@ -18,6 +19,7 @@ use crate::ConfigVersionCache;
 pub struct CachedUserInfo {
     user_cfg: Arc<SectionConfigData>,
     acl_tree: Arc<AclTree>,
+    custom_roles: HashMap<String, u64>,
 }
 
 struct ConfigCache {
@@ -54,9 +56,14 @@ impl CachedUserInfo {
             }
         }
 
+        let custom_roles = crate::roles::config_or_builtin_only()
+            .map(|roles| crate::roles::custom_role_privs(&roles))
+            .unwrap_or_default();
+
         let config = Arc::new(CachedUserInfo {
             user_cfg: crate::user::cached_config()?,
             acl_tree: crate::acl::cached_config()?,
+            custom_roles,
         });
 
         let mut cache = CACHED_CONFIG.write().unwrap();
@@ -70,9 +77,20 @@ impl CachedUserInfo {
     /// Only exposed for testing
     #[doc(hidden)]
     pub fn test_new(user_cfg: SectionConfigData, acl_tree: AclTree) -> Self {
+        Self::test_new_with_roles(user_cfg, acl_tree, HashMap::new())
+    }
+
+    /// Only exposed for testing
+    #[doc(hidden)]
+    pub fn test_new_with_roles(
+        user_cfg: SectionConfigData,
+        acl_tree: AclTree,
+        custom_roles: HashMap<String, u64>,
+    ) -> Self {
         Self {
             user_cfg: Arc::new(user_cfg),
             acl_tree: Arc::new(acl_tree),
+            custom_roles,
         }
     }
 
@@ -107,6 +125,21 @@ impl CachedUserInfo {
         true
     }
 
+    /// Test if an authentication id is an API token which has expired
+    pub fn is_expired_token(&self, auth_id: &Authid) -> bool {
+        if !auth_id.is_token() {
+            return false;
+        }
+
+        match self
+            .user_cfg
+            .lookup::<ApiToken>("token", &auth_id.to_string())
+        {
+            Ok(info) => info.is_expired(epoch_i64()),
+            Err(_) => false,
+        }
+    }
+
     pub fn check_privs(
         &self,
         auth_id: &Authid,
@@ -159,7 +192,7 @@ impl CachedUserInfo {
         let mut privs: u64 = 0;
         let mut propagated_privs: u64 = 0;
         for (role, propagate) in roles {
-            if let Some((role_privs, _)) = ROLE_NAMES.get(role.as_str()) {
+            if let Some(role_privs) = self.role_privs(&role) {
                 if propagate {
                     propagated_privs |= role_privs;
                 }
@@ -179,6 +212,25 @@ impl CachedUserInfo {
         (privs, propagated_privs)
     }
 
+    /// Returns the privileges granted by a built-in or custom role, if it exists.
+    pub fn role_privs(&self, role: &str) -> Option<u64> {
+        match ROLE_NAMES.get(role) {
+            Some((privs, _)) => Some(*privs),
+            None => self.custom_roles.get(role).copied(),
+        }
+    }
+
+    /// Returns the ACL paths and roles along `path` which apply to `auth_id`.
+    ///
+    /// See [`AclTree::role_origins`](crate::acl::AclTree::role_origins).
+    pub fn lookup_role_origins(
+        &self,
+        auth_id: &Authid,
+        path: &[&str],
+    ) -> Vec<(String, HashMap<String, bool>)> {
+        self.acl_tree.role_origins(auth_id, path)
+    }
+
     /// Checks whether the `auth_id` has any of the privilegs `privs` on any object below `path`.
     pub fn any_privs_below(
         &self,
@@ -223,3 +275,73 @@ impl UserInformation for CachedUserInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use anyhow::Error;
+
+    use pbs_api_types::{
+        Authid, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ, ROLE_AUDIT,
+    };
+
+    use super::CachedUserInfo;
+    use crate::acl::AclTree;
+
+    #[test]
+    fn test_custom_role_privs() -> Result<(), Error> {
+        let (user_cfg, _) = crate::user::test_cfg_from_str(
+            r###"
+user: user1@pbs
+
+user: user2@pbs
+
+"###,
+        )?;
+
+        let user1: Authid = "user1@pbs".parse()?;
+        let user2: Authid = "user2@pbs".parse()?;
+
+        let mut tree = AclTree::new();
+        tree.insert_user_role("/datastore", &user1, "BackupReader", true);
+        tree.insert_user_role("/datastore/store1", &user2, "BackupReader", false);
+        // e.g. from a role which was removed by a manual edit of the role config
+        tree.insert_user_role("/datastore/store1", &user2, "Unknown", true);
+
+        let privs = PRIV_DATASTORE_BACKUP | PRIV_DATASTORE_READ;
+        let custom_roles = HashMap::from([("BackupReader".to_string(), privs)]);
+        let user_info = CachedUserInfo::test_new_with_roles(user_cfg, tree, custom_roles);
+
+        assert_eq!(user_info.role_privs("BackupReader"), Some(privs));
+        assert_eq!(user_info.role_privs("Audit"), Some(ROLE_AUDIT));
+        assert_eq!(user_info.role_privs("Unknown"), None);
+
+        // propagated to the datastores below
+        assert_eq!(
+            user_info.lookup_privs_details(&user1, &["datastore"]),
+            (privs, privs)
+        );
+        assert_eq!(
+            user_info.lookup_privs(&user1, &["datastore", "store1"]),
+            privs
+        );
+
+        // not propagated, unknown roles grant nothing
+        assert_eq!(
+            user_info.lookup_privs_details(&user2, &["datastore", "store1"]),
+            (privs, 0)
+        );
+        assert_eq!(
+            user_info.lookup_privs(&user2, &["datastore", "store1", "ns1"]),
+            0
+        );
+
+        user_info.check_privs(&user2, &["datastore", "store1"], privs, false)?;
+        assert!(user_info
+            .check_privs(&user2, &["datastore", "store1"], PRIV_DATASTORE_AUDIT, true)
+            .is_err());
+
+        Ok(())
+    }
+}
@@ -8,7 +8,9 @@ use proxmox_schema::{ApiType, ObjectSchema};
 use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
 
 use crate::{open_backup_lockfile, replace_backup_config, BackupLockGuard};
-use pbs_api_types::{LdapRealmConfig, OpenIdRealmConfig, REALM_ID_SCHEMA};
+use pbs_api_types::{
+    LdapRealmConfig, OpenIdRealmConfig, PamRealmConfig, PbsRealmConfig, REALM_ID_SCHEMA,
+};
 
 lazy_static! {
     pub static ref CONFIG: SectionConfig = init();
@@ -17,6 +19,8 @@ lazy_static! {
 fn init() -> SectionConfig {
     const LDAP_SCHEMA: &ObjectSchema = LdapRealmConfig::API_SCHEMA.unwrap_object_schema();
     const OPENID_SCHEMA: &ObjectSchema = OpenIdRealmConfig::API_SCHEMA.unwrap_object_schema();
+    const PAM_SCHEMA: &ObjectSchema = PamRealmConfig::API_SCHEMA.unwrap_object_schema();
+    const PBS_SCHEMA: &ObjectSchema = PbsRealmConfig::API_SCHEMA.unwrap_object_schema();
 
     let mut config = SectionConfig::new(&REALM_ID_SCHEMA);
 
@@ -33,6 +37,17 @@ fn init() -> SectionConfig {
 
     config.register_plugin(plugin);
 
+    // the built-in realms only have a section if they were configured
+    let plugin =
+        SectionConfigPlugin::new("pam".to_string(), Some(String::from("realm")), PAM_SCHEMA);
+
+    config.register_plugin(plugin);
+
+    let plugin =
+        SectionConfigPlugin::new("pbs".to_string(), Some(String::from("realm")), PBS_SCHEMA);
+
+    config.register_plugin(plugin);
+
     config
 }
 
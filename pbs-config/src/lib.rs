@@ -9,6 +9,7 @@ pub mod metrics;
 pub mod network;
 pub mod prune;
 pub mod remote;
+pub mod roles;
 pub mod sync;
 pub mod tape_job;
 pub mod token_shadow;
@@ -0,0 +1,116 @@
+//! Custom roles, composed of a set of privileges.
+//!
+//! Built-in roles are defined in [`ROLE_NAMES`](crate::acl::ROLE_NAMES), custom roles are stored
+//! in [`ROLES_CFG_FILENAME`] and can be used in ACLs just like the built-in ones.
+
+use std::collections::HashMap;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use proxmox_schema::*;
+use proxmox_section_config::{SectionConfig, SectionConfigData, SectionConfigPlugin};
+
+use pbs_api_types::{priv_names_to_privs, CustomRole, ROLE_ID_SCHEMA};
+
+use crate::{open_backup_lockfile, BackupLockGuard, ConfigVersionCache};
+
+lazy_static! {
+    pub static ref CONFIG: SectionConfig = init();
+}
+
+fn init() -> SectionConfig {
+    let obj_schema = match CustomRole::API_SCHEMA {
+        Schema::Object(ref obj_schema) => obj_schema,
+        _ => unreachable!(),
+    };
+
+    let plugin =
+        SectionConfigPlugin::new("role".to_string(), Some("roleid".to_string()), obj_schema);
+    let mut config = SectionConfig::new(&ROLE_ID_SCHEMA);
+    config.register_plugin(plugin);
+
+    config
+}
+
+pub const ROLES_CFG_FILENAME: &str = "/etc/proxmox-backup/roles.cfg";
+pub const ROLES_CFG_LOCKFILE: &str = "/etc/proxmox-backup/.roles.lck";
+
+/// Get exclusive lock
+pub fn lock_config() -> Result<BackupLockGuard, Error> {
+    open_backup_lockfile(ROLES_CFG_LOCKFILE, None, true)
+}
+
+pub fn config() -> Result<(SectionConfigData, [u8; 32]), Error> {
+    let content =
+        proxmox_sys::fs::file_read_optional_string(ROLES_CFG_FILENAME)?.unwrap_or_default();
+
+    let digest = openssl::sha::sha256(content.as_bytes());
+    let data = CONFIG.parse(ROLES_CFG_FILENAME, &content)?;
+    Ok((data, digest))
+}
+
+pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
+    let raw = CONFIG.write(ROLES_CFG_FILENAME, config)?;
+    crate::replace_backup_config(ROLES_CFG_FILENAME, raw.as_bytes())?;
+
+    // make sure cached permission checks pick up the changed role definitions
+    let version_cache = ConfigVersionCache::new()?;
+    version_cache.increase_user_cache_generation();
+
+    Ok(())
+}
+
+/// Get the custom roles, or `None` if the config cannot be read.
+///
+/// A broken role config must not lock everyone out, so the error is only logged. Only the
+/// built-in roles grant privileges in that case.
+pub fn config_or_builtin_only() -> Option<SectionConfigData> {
+    match config() {
+        Ok((data, _digest)) => Some(data),
+        Err(err) => {
+            log::error!("unable to load custom roles, using built-in roles only - {err}");
+            None
+        }
+    }
+}
+
+/// Returns the privileges of all custom roles in `config`, indexed by role ID.
+///
+/// Roles referencing unknown privileges (e.g. after a manual edit) are skipped.
+pub fn custom_role_privs(config: &SectionConfigData) -> HashMap<String, u64> {
+    let mut map = HashMap::new();
+
+    for (roleid, (section_type, _)) in config.sections.iter() {
+        if section_type != "role" {
+            continue;
+        }
+        let role: CustomRole = match config.lookup("role", roleid) {
+            Ok(role) => role,
+            Err(_) => continue,
+        };
+        if let Ok(privs) = priv_names_to_privs(&role.privs) {
+            map.insert(role.roleid, privs);
+        }
+    }
+
+    map
+}
+
+// shell completion helper
+pub fn complete_custom_role_id(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
+    match config() {
+        Ok((data, _digest)) => data.sections.keys().map(|id| id.to_string()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// shell completion helper, includes built-in roles
+pub fn complete_role_id(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
+    let mut list: Vec<String> = crate::acl::ROLE_NAMES
+        .keys()
+        .map(|role| role.to_string())
+        .collect();
+    list.extend(complete_custom_role_id(arg, param));
+    list
+}
@@ -134,6 +134,34 @@ pub fn save_config(config: &SectionConfigData) -> Result<(), Error> {
     Ok(())
 }
 
+/// Remove all API tokens which expired more than `max_age` seconds before `now`.
+///
+/// Returns the removed tokens.
+pub fn purge_expired_tokens(now: i64, max_age: i64) -> Result<Vec<ApiToken>, Error> {
+    let _lock = lock_config()?;
+
+    let (mut config, _digest) = config()?;
+
+    let tokens: Vec<ApiToken> = config.convert_to_typed_array("token")?;
+    let expired: Vec<ApiToken> = tokens
+        .into_iter()
+        .filter(|token| token.is_expired(now - max_age))
+        .collect();
+
+    if expired.is_empty() {
+        return Ok(expired);
+    }
+
+    for token in expired.iter() {
+        config.sections.remove(&token.tokenid.to_string());
+        crate::token_shadow::delete_secret(&token.tokenid)?;
+    }
+
+    save_config(&config)?;
+
+    Ok(expired)
+}
+
 /// Only exposed for testing
 #[doc(hidden)]
 pub fn test_cfg_from_str(raw: &str) -> Result<(SectionConfigData, [u8; 32]), Error> {
@@ -5,18 +5,26 @@ use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
 
-use proxmox_sys::fs::{lock_dir_noblock, replace_file, CreateOptions};
+use proxmox_sys::fs::{replace_file, CreateOptions};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, GroupFilter, BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
+    Authid, BackupNamespace, BackupType, DatastoreFSyncLevel, GroupDedupStats, GroupFilter,
+    BACKUP_DATE_REGEX, BACKUP_FILE_REGEX,
 };
 use pbs_config::{open_backup_lockfile, BackupLockGuard};
 
+use crate::lock_tracking::lock_dir_noblock;
 use crate::manifest::{
     BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME, MANIFEST_LOCK_NAME,
 };
 use crate::{DataBlob, DataStore};
 
+/// File name of the free-form notes stored in a backup group directory.
+pub const GROUP_NOTES_FILE_NAME: &str = "notes";
+
+/// File name of the cached deduplication statistics stored in a backup group directory.
+pub const GROUP_DEDUP_STATS_FILE_NAME: &str = ".dedup-stats";
+
 /// BackupGroup is a directory containing a list of BackupDir
 #[derive(Clone)]
 pub struct BackupGroup {
@@ -70,6 +78,13 @@ impl BackupGroup {
         self.store.group_path(&self.ns, &self.group)
     }
 
+    /// Returns the path of the group notes file.
+    pub fn notes_path(&self) -> PathBuf {
+        let mut path = self.full_group_path();
+        path.push(GROUP_NOTES_FILE_NAME);
+        path
+    }
+
     pub fn relative_group_path(&self) -> PathBuf {
         let mut path = self.ns.path();
         path.push(self.group.ty.as_str());
@@ -200,8 +215,7 @@ impl BackupGroup {
     /// Returns true if all snapshots were removed, and false if some were protected
     pub fn destroy(&self) -> Result<bool, Error> {
         let path = self.full_group_path();
-        let _guard =
-            proxmox_sys::fs::lock_dir_noblock(&path, "backup group", "possible running backup")?;
+        let _guard = lock_dir_noblock(&path, "backup group", "possible running backup")?;
 
         log::info!("removing backup group {:?}", path);
         let mut removed_all_snaps = true;
@@ -235,6 +249,69 @@ impl BackupGroup {
         self.store
             .set_owner(&self.ns, self.as_ref(), auth_id, force)
     }
+
+    /// Change the backup owner while holding the group lock.
+    ///
+    /// If `expected_owner` is set, the owner is only changed if the current owner matches it.
+    /// Returns the previous owner.
+    pub fn change_owner(
+        &self,
+        new_owner: &Authid,
+        expected_owner: Option<&Authid>,
+    ) -> Result<Authid, Error> {
+        let _guard = lock_dir_noblock(
+            &self.full_group_path(),
+            "backup group",
+            "possible running backup",
+        )?;
+
+        let owner = self.get_owner()?;
+        if let Some(expected_owner) = expected_owner {
+            if owner != *expected_owner {
+                bail!("current owner '{owner}' does not match expected owner '{expected_owner}'");
+            }
+        }
+
+        self.set_owner(new_owner, true)?;
+
+        Ok(owner)
+    }
+
+    /// Returns the path of the cached deduplication statistics.
+    pub fn dedup_stats_path(&self) -> PathBuf {
+        let mut path = self.full_group_path();
+        path.push(GROUP_DEDUP_STATS_FILE_NAME);
+        path
+    }
+
+    /// Load the cached deduplication statistics, if any.
+    pub fn cached_dedup_stats(&self) -> Result<Option<GroupDedupStats>, Error> {
+        let path = self.dedup_stats_path();
+        match proxmox_sys::fs::file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data).map_err(|err| {
+                format_err!("unable to parse cached dedup stats {path:?} - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Compute the deduplication statistics of this group and store them in the cache.
+    pub fn update_dedup_stats(
+        &self,
+        worker: &dyn proxmox_sys::WorkerTaskContext,
+    ) -> Result<GroupDedupStats, Error> {
+        let stats = self.store.group_dedup_stats(self, worker)?;
+
+        let raw_data = serde_json::to_vec(&stats)?;
+        replace_file(
+            self.dedup_stats_path(),
+            &raw_data,
+            CreateOptions::new(),
+            false,
+        )?;
+
+        Ok(stats)
+    }
 }
 
 impl AsRef<pbs_api_types::BackupNamespace> for BackupGroup {
@@ -440,7 +517,9 @@ impl BackupDir {
 
     /// Destroy the whole snapshot, bails if it's protected
     ///
-    /// Setting `force` to true skips locking and thus ignores if the backup is currently in use.
+    /// With a trash retention configured on the datastore, the snapshot is moved to the trash
+    /// instead. Setting `force` to true skips locking and thus ignores if the backup is currently
+    /// in use, such snapshots are always removed immediately.
     pub fn destroy(&self, force: bool) -> Result<(), Error> {
         let full_path = self.full_path();
 
@@ -454,10 +533,14 @@ impl BackupDir {
             bail!("cannot remove protected snapshot"); // use special error type?
         }
 
-        log::info!("removing backup snapshot {:?}", full_path);
-        std::fs::remove_dir_all(&full_path).map_err(|err| {
-            format_err!("removing backup snapshot {:?} failed - {}", full_path, err,)
-        })?;
+        if !force && self.store.trash_retention() > 0 {
+            crate::trash::move_to_trash(self)?;
+        } else {
+            log::info!("removing backup snapshot {:?}", full_path);
+            std::fs::remove_dir_all(&full_path).map_err(|err| {
+                format_err!("removing backup snapshot {:?} failed - {}", full_path, err,)
+            })?;
+        }
 
         // the manifest doesn't exist anymore, no need to keep the lock (already done by guard?)
         if let Ok(path) = self.manifest_lock_path() {
@@ -512,7 +595,8 @@ impl BackupDir {
         path.push(MANIFEST_BLOB_NAME);
 
         // atomic replace invalidates flock - no other writes past this point!
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        let fsync = self.store.sync_level() == DatastoreFSyncLevel::File;
+        replace_file(&path, raw_data, CreateOptions::new(), fsync)?;
         Ok(())
     }
 
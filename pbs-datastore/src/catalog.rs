@@ -332,6 +332,7 @@ pub struct CatalogWriter<W> {
     writer: W,
     dirstack: Vec<DirInfo>,
     pos: u64,
+    file_count: u64,
 }
 
 impl<W: Write> CatalogWriter<W> {
@@ -341,6 +342,7 @@ impl<W: Write> CatalogWriter<W> {
             writer,
             dirstack: vec![DirInfo::new_rootdir()],
             pos: 0,
+            file_count: 0,
         };
         me.write_all(&PROXMOX_CATALOG_FILE_MAGIC_1_0)?;
         Ok(me)
@@ -352,6 +354,11 @@ impl<W: Write> CatalogWriter<W> {
         Ok(())
     }
 
+    /// Returns the number of regular files added so far
+    pub fn file_count(&self) -> u64 {
+        self.file_count
+    }
+
     /// Finish writing, flush all data
     ///
     /// This need to be called before drop.
@@ -417,6 +424,7 @@ impl<W: Write> BackupCatalogWriter for CatalogWriter<W> {
             name,
             attr: DirEntryAttribute::File { size, mtime },
         });
+        self.file_count += 1;
         Ok(())
     }
 
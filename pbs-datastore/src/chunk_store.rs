@@ -1,11 +1,16 @@
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
 
-use pbs_api_types::{DatastoreFSyncLevel, GarbageCollectionStatus};
+use pbs_api_types::{
+    DatastoreBackendConfig, DatastoreBackendType, DatastoreFSyncLevel, GarbageCollectionStatus,
+};
 use proxmox_io::ReadExt;
+use proxmox_schema::ApiType;
 use proxmox_sys::fs::{create_dir, create_path, file_type_from_file_stat, CreateOptions};
 use proxmox_sys::process_locker::{
     ProcessLockExclusiveGuard, ProcessLockSharedGuard, ProcessLocker,
@@ -16,16 +21,91 @@ use proxmox_sys::WorkerTaskContext;
 use crate::file_formats::{
     COMPRESSED_BLOB_MAGIC_1_0, ENCRYPTED_BLOB_MAGIC_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
 };
+use crate::s3_client::S3Client;
 use crate::DataBlob;
 
 /// File system based chunk store
+///
+/// Chunks can be sharded over additional chunk directories, see
+/// [`ChunkStore::set_extra_chunk_dirs`], or stored in an object store, see
+/// [`ChunkStore::set_backend`].
 pub struct ChunkStore {
     name: String, // used for error reporting
     pub(crate) base: PathBuf,
     chunk_dir: PathBuf,
+    extra_chunk_dirs: RwLock<Arc<Vec<PathBuf>>>,
+    backend: RwLock<ChunkBackend>,
     mutex: Mutex<()>,
+    // held shared while a chunk is uploaded to the object store, and exclusively while garbage
+    // collection removes one, taken before `mutex`
+    upload_lock: RwLock<()>,
     locker: Option<Arc<Mutex<ProcessLocker>>>,
     sync_level: DatastoreFSyncLevel,
+    traffic: ChunkTrafficCounters,
+}
+
+/// Snapshot of the chunk traffic of a chunk store, counted since the start of the process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChunkTrafficStats {
+    /// Bytes of chunks read from the store
+    pub read_bytes: u64,
+    /// Number of chunks read from the store
+    pub read_chunks: u64,
+    /// Bytes of new chunks written to the store
+    pub write_bytes: u64,
+    /// Number of new chunks written to the store
+    pub write_chunks: u64,
+}
+
+#[derive(Default)]
+struct ChunkTrafficCounters {
+    read_bytes: AtomicU64,
+    read_chunks: AtomicU64,
+    write_bytes: AtomicU64,
+    write_chunks: AtomicU64,
+}
+
+/// Storage backend of the chunks of a chunk store.
+#[derive(Clone, Default)]
+pub enum ChunkBackend {
+    /// Chunks are stored as files in the chunk directories.
+    #[default]
+    Filesystem,
+    /// Chunks are stored as objects in an S3 bucket. The chunk directories only contain an empty
+    /// marker file per chunk, which is used for lookups and the access time based marking of
+    /// garbage collection, just like a chunk file.
+    S3(Arc<S3Client>),
+}
+
+impl ChunkBackend {
+    /// Create the backend configured by the `backend` property string of a datastore.
+    pub fn from_config(backend: Option<&str>) -> Result<Self, Error> {
+        let config: DatastoreBackendConfig = serde_json::from_value(
+            DatastoreBackendConfig::API_SCHEMA.parse_property_string(backend.unwrap_or(""))?,
+        )?;
+
+        match config.ty.unwrap_or_default() {
+            DatastoreBackendType::Filesystem => Ok(Self::Filesystem),
+            DatastoreBackendType::S3 => Ok(Self::S3(Arc::new(S3Client::new(&config)?))),
+        }
+    }
+}
+
+/// Prefix of the keys of all chunk objects in a bucket.
+const CHUNK_OBJECT_PREFIX: &str = "chunks/";
+
+/// Key of the object of the chunk with `digest`, using the same prefix directories as the
+/// chunk directories.
+fn chunk_object_key(digest: &[u8; 32]) -> String {
+    let digest_str = hex::encode(digest);
+    format!("{CHUNK_OBJECT_PREFIX}{}/{digest_str}", &digest_str[..4])
+}
+
+/// Digest of the chunk stored as object `key`, `None` for keys not written by the chunk store.
+fn chunk_object_digest(key: &str) -> Option<[u8; 32]> {
+    let (prefix, digest_str) = key.strip_prefix(CHUNK_OBJECT_PREFIX)?.split_once('/')?;
+    let digest = <[u8; 32]>::from_hex(digest_str).ok()?;
+    (prefix.len() == 4 && digest_str.starts_with(prefix)).then_some(digest)
 }
 
 // TODO: what about sysctl setting vm.vfs_cache_pressure (0 - 100) ?
@@ -63,6 +143,15 @@ fn digest_to_prefix(digest: &[u8]) -> PathBuf {
     path.into()
 }
 
+/// Index of the chunk directory responsible for `digest` if the chunks are sharded over
+/// `shard_count` directories.
+///
+/// Each directory gets a contiguous range of the 64k digest prefixes.
+fn digest_to_shard(digest: &[u8], shard_count: usize) -> usize {
+    let prefix = u16::from_be_bytes([digest[0], digest[1]]) as usize;
+    (prefix * shard_count) >> 16
+}
+
 impl ChunkStore {
     #[doc(hidden)]
     pub unsafe fn panic_store() -> Self {
@@ -70,9 +159,13 @@ impl ChunkStore {
             name: String::new(),
             base: PathBuf::new(),
             chunk_dir: PathBuf::new(),
+            extra_chunk_dirs: Default::default(),
+            backend: Default::default(),
             mutex: Mutex::new(()),
+            upload_lock: RwLock::new(()),
             locker: None,
             sync_level: Default::default(),
+            traffic: Default::default(),
         }
     }
 
@@ -127,11 +220,61 @@ impl ChunkStore {
         let lockfile_path = Self::lockfile_path(&base);
         proxmox_sys::fs::replace_file(lockfile_path, b"", options.clone(), false)?;
 
-        // create 64*1024 subdirs
+        Self::create_chunk_subdirs(name, &chunk_dir, &options, worker)?;
+
+        Self::open(name, base, sync_level)
+    }
+
+    /// Create an additional chunk directory for sharding the chunk store `name`.
+    ///
+    /// The directory itself may already exist, for example as mount point, but must be empty.
+    pub fn create_extra_chunk_dir<P>(
+        name: &str,
+        path: P,
+        uid: nix::unistd::Uid,
+        gid: nix::unistd::Gid,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<(), Error>
+    where
+        P: Into<PathBuf>,
+    {
+        let chunk_dir: PathBuf = path.into();
+
+        if !chunk_dir.is_absolute() {
+            bail!("expected absolute path - got {chunk_dir:?}");
+        }
+
+        let options = CreateOptions::new().owner(uid).group(gid);
+
+        if let Err(err) = create_path(
+            &chunk_dir,
+            Some(CreateOptions::new()),
+            Some(options.clone()),
+        ) {
+            bail!("unable to create chunk store '{name}' chunk dir {chunk_dir:?} - {err}");
+        }
+
+        for entry in std::fs::read_dir(&chunk_dir)? {
+            if entry?.file_name() != "lost+found" {
+                bail!("unable to create chunk store '{name}' chunk dir {chunk_dir:?} - not empty");
+            }
+        }
+        nix::unistd::chown(&chunk_dir, Some(uid), Some(gid))?;
+
+        Self::create_chunk_subdirs(name, &chunk_dir, &options, worker)
+    }
+
+    // create 64*1024 subdirs
+    fn create_chunk_subdirs(
+        name: &str,
+        chunk_dir: &Path,
+        options: &CreateOptions,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<(), Error> {
         let mut last_percentage = 0;
 
         for i in 0..64 * 1024 {
-            let mut l1path = chunk_dir.clone();
+            let mut l1path = chunk_dir.to_owned();
             l1path.push(format!("{:04x}", i));
             if let Err(err) = create_dir(&l1path, options.clone()) {
                 bail!(
@@ -150,7 +293,100 @@ impl ChunkStore {
             }
         }
 
-        Self::open(name, base, sync_level)
+        Ok(())
+    }
+
+    /// Check whether the chunk directory at `path` contains any chunks.
+    ///
+    /// Used before removing an additional chunk directory from a chunk store.
+    pub fn chunk_dir_has_chunks<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let chunk_dir = path.as_ref();
+        for i in 0..64 * 1024 {
+            let mut l1path = chunk_dir.to_owned();
+            l1path.push(format!("{:04x}", i));
+            let entries = match std::fs::read_dir(&l1path) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => bail!("unable to read chunk dir {l1path:?} - {err}"),
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                let bytes = name.as_bytes();
+                if bytes.len() >= 64 && bytes.iter().take(64).all(u8::is_ascii_hexdigit) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check that `path` contains a complete chunk store owned by `uid`/`gid`, so that it can be
+    /// adopted by a new datastore configuration without initializing it again.
+    ///
+    /// Nothing is modified on disk.
+    pub fn verify_existing<P>(
+        name: &str,
+        path: P,
+        uid: nix::unistd::Uid,
+        gid: nix::unistd::Gid,
+        worker: Option<&dyn WorkerTaskContext>,
+    ) -> Result<(), Error>
+    where
+        P: Into<PathBuf>,
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let base: PathBuf = path.into();
+
+        if !base.is_absolute() {
+            bail!("expected absolute path - got {base:?}");
+        }
+
+        let check_entry = |path: &Path, is_dir: bool| -> Result<(), Error> {
+            let metadata = std::fs::metadata(path).map_err(|err| {
+                format_err!("unable to reuse chunk store '{name}' - {path:?}: {err}")
+            })?;
+            if metadata.is_dir() != is_dir {
+                bail!(
+                    "unable to reuse chunk store '{name}' - {path:?} is not a {}",
+                    if is_dir { "directory" } else { "file" },
+                );
+            }
+            if metadata.uid() != uid.as_raw() || metadata.gid() != gid.as_raw() {
+                bail!(
+                    "unable to reuse chunk store '{name}' - {path:?} has wrong owner ({}:{}, expected {uid}:{gid})",
+                    metadata.uid(),
+                    metadata.gid(),
+                );
+            }
+            Ok(())
+        };
+
+        check_entry(&base, true)?;
+        check_entry(&Self::lockfile_path(&base), false)?;
+
+        let chunk_dir = Self::chunk_dir(&base);
+        check_entry(&chunk_dir, true)?;
+
+        let mut last_percentage = 0;
+
+        for i in 0..64 * 1024 {
+            let mut l1path = chunk_dir.clone();
+            l1path.push(format!("{:04x}", i));
+            check_entry(&l1path, true)?;
+
+            let percentage = (i * 100) / (64 * 1024);
+            if percentage != last_percentage {
+                if let Some(worker) = worker {
+                    task_log!(worker, "Chunkstore verify: {}%", percentage)
+                }
+                last_percentage = percentage;
+            }
+        }
+
+        Ok(())
     }
 
     fn lockfile_path<P: Into<PathBuf>>(base: P) -> PathBuf {
@@ -189,12 +425,83 @@ impl ChunkStore {
             name: name.to_owned(),
             base,
             chunk_dir,
+            extra_chunk_dirs: Default::default(),
+            backend: Default::default(),
             locker: Some(locker),
             mutex: Mutex::new(()),
+            upload_lock: RwLock::new(()),
             sync_level,
+            traffic: Default::default(),
         })
     }
 
+    /// Shard the chunks over the chunk directory of the store and `dirs`.
+    ///
+    /// The chunk directories get a contiguous range of digest prefixes each, in order, with the
+    /// store's own chunk directory first. New chunks are always written to the directory
+    /// responsible for their digest, while lookups fall back to the other directories, so that
+    /// chunks written before the directories were changed are still found.
+    pub fn set_extra_chunk_dirs(&self, dirs: Vec<PathBuf>) -> Result<(), Error> {
+        for dir in dirs.iter() {
+            match std::fs::metadata(dir) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => bail!(
+                    "chunk store '{}' - chunk dir {dir:?} is not a directory",
+                    self.name
+                ),
+                Err(err) => bail!(
+                    "unable to open chunk store '{}' chunk dir {dir:?} - {err}",
+                    self.name
+                ),
+            }
+        }
+
+        *self.extra_chunk_dirs.write().unwrap() = Arc::new(dirs);
+        Ok(())
+    }
+
+    /// The additional chunk directories of this store.
+    pub fn extra_chunk_dirs(&self) -> Arc<Vec<PathBuf>> {
+        Arc::clone(&self.extra_chunk_dirs.read().unwrap())
+    }
+
+    /// Store the chunks in `backend`.
+    ///
+    /// Switching the backend of a store with chunks makes them inaccessible, so this must only be
+    /// used with the backend configured on creation.
+    pub fn set_backend(&self, backend: ChunkBackend) {
+        *self.backend.write().unwrap() = backend;
+    }
+
+    /// The storage backend of the chunks of this store.
+    pub fn backend(&self) -> ChunkBackend {
+        self.backend.read().unwrap().clone()
+    }
+
+    /// All chunk directories of this store, starting with the store's own one.
+    fn chunk_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.chunk_dir.clone()];
+        dirs.extend(self.extra_chunk_dirs().iter().cloned());
+        dirs
+    }
+
+    /// Returns the chunk traffic counted since this chunk store was opened.
+    pub fn traffic_stats(&self) -> ChunkTrafficStats {
+        ChunkTrafficStats {
+            read_bytes: self.traffic.read_bytes.load(Ordering::Relaxed),
+            read_chunks: self.traffic.read_chunks.load(Ordering::Relaxed),
+            write_bytes: self.traffic.write_bytes.load(Ordering::Relaxed),
+            write_chunks: self.traffic.write_chunks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Account a chunk of `size` bytes read from this store.
+    pub fn account_chunk_read(&self, size: u64) {
+        self.traffic.read_bytes.fetch_add(size, Ordering::Relaxed);
+        self.traffic.read_chunks.fetch_add(1, Ordering::Relaxed);
+        crate::task_io_stats::account_read(size);
+    }
+
     pub fn touch_chunk(&self, digest: &[u8; 32]) -> Result<(), Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
@@ -248,6 +555,94 @@ impl ChunkStore {
         Ok(true)
     }
 
+    /// Check that access time updates work on the chunk store file system as required by
+    /// garbage collection.
+    ///
+    /// A chunk-like test file with access and modification time set into the past is touched
+    /// like the mark phase does, its access time must then be updated while the modification
+    /// time is kept. The file is placed next to the chunk subdirectories, so that it is never
+    /// seen as chunk. With sharding, every chunk directory is checked.
+    pub fn check_fs_atime_updates(&self) -> Result<(), Error> {
+        // unwrap: only `None` in unit tests
+        assert!(self.locker.is_some());
+
+        for chunk_dir in self.chunk_dirs() {
+            self.check_fs_atime_updates_in(&chunk_dir)?;
+        }
+        Ok(())
+    }
+
+    fn check_fs_atime_updates_in(&self, chunk_dir: &Path) -> Result<(), Error> {
+        use nix::sys::stat::stat;
+
+        let mut path = chunk_dir.to_owned();
+        path.push(".gc-atime-check");
+
+        let blob = DataBlob::encode(&[0u8; 4096], None, true)?;
+        proxmox_sys::fs::replace_file(&path, blob.raw_data(), CreateOptions::new(), false)
+            .map_err(|err| format_err!("unable to create test file {path:?} - {err}"))?;
+
+        let result = (|| -> Result<(), Error> {
+            // older than the 24h of relatime, so that the update is not skipped
+            let past = proxmox_time::epoch_i64() - 2 * 24 * 3600;
+            let times = [
+                libc::timespec {
+                    tv_sec: past,
+                    tv_nsec: 0,
+                },
+                libc::timespec {
+                    tv_sec: past,
+                    tv_nsec: 0,
+                },
+            ];
+
+            use nix::NixPath;
+
+            let res = path.with_nix_path(|cstr| unsafe {
+                let tmp = libc::utimensat(-1, cstr.as_ptr(), &times[0], libc::AT_SYMLINK_NOFOLLOW);
+                nix::errno::Errno::result(tmp)
+            })?;
+            if let Err(err) = res {
+                bail!("setting access time of test file failed - {err}");
+            }
+
+            let before = stat(&path)?;
+            if before.st_atime != past || before.st_mtime != past {
+                bail!(
+                    "access time of test file not set (expected {past}, got atime {} and mtime {})",
+                    before.st_atime,
+                    before.st_mtime,
+                );
+            }
+
+            let start_time = proxmox_time::epoch_i64();
+            self.cond_touch_path(&path, true)?;
+
+            let after = stat(&path)?;
+            if after.st_atime < start_time {
+                bail!(
+                    "access time of test file not updated (expected at least {start_time}, got {})",
+                    after.st_atime,
+                );
+            }
+            if after.st_mtime != past {
+                bail!(
+                    "modification time of test file changed by access time update (expected \
+                    {past}, got {})",
+                    after.st_mtime,
+                );
+            }
+
+            Ok(())
+        })();
+
+        if let Err(err) = std::fs::remove_file(&path) {
+            log::warn!("unable to remove test file {path:?} - {err}");
+        }
+
+        result
+    }
+
     pub fn get_chunk_iterator(
         &self,
     ) -> Result<
@@ -262,14 +657,20 @@ impl ChunkStore {
         use nix::fcntl::OFlag;
         use nix::sys::stat::Mode;
 
-        let base_handle =
-            Dir::open(&self.chunk_dir, OFlag::O_RDONLY, Mode::empty()).map_err(|err| {
-                format_err!(
-                    "unable to open store '{}' chunk dir {:?} - {err}",
-                    self.name,
-                    self.chunk_dir,
-                )
-            })?;
+        // iterate the subdirs of all chunk directories one after another
+        let base_handles = self
+            .chunk_dirs()
+            .into_iter()
+            .map(|chunk_dir| {
+                Dir::open(&chunk_dir, OFlag::O_RDONLY, Mode::empty()).map_err(|err| {
+                    format_err!(
+                        "unable to open store '{}' chunk dir {chunk_dir:?} - {err}",
+                        self.name,
+                    )
+                })
+            })
+            .collect::<Result<Vec<Dir>, Error>>()?;
+        let subdir_count = base_handles.len() * 0x10000;
 
         let mut done = false;
         let mut inner: Option<proxmox_sys::fs::ReadDir> = None;
@@ -308,13 +709,14 @@ impl ChunkStore {
 
                 inner = None;
 
-                if at == 0x10000 {
+                if at == subdir_count {
                     done = true;
                     return None;
                 }
 
-                let subdir: &str = &format!("{:04x}", at);
-                percentage = (at * 100) / 0x10000;
+                let base_handle = &base_handles[at >> 16];
+                let subdir: &str = &format!("{:04x}", at & 0xffff);
+                percentage = (at * 100) / subdir_count;
                 at += 1;
                 match proxmox_sys::fs::read_subdir(base_handle.as_raw_fd(), subdir) {
                     Ok(dir) => {
@@ -347,26 +749,164 @@ impl ChunkStore {
         ProcessLocker::oldest_shared_lock(self.locker.clone().unwrap())
     }
 
+    /// Remove all chunks last accessed before `min_atime`, see [`gc_atime_cutoff`].
+    ///
+    /// Chunks accessed before `oldest_writer` are kept, but accounted as pending removal.
     pub fn sweep_unused_chunks(
         &self,
+        min_atime: i64,
         oldest_writer: i64,
-        phase1_start_time: i64,
         status: &mut GarbageCollectionStatus,
         worker: &dyn WorkerTaskContext,
+        progress: &mut dyn FnMut(usize, &GarbageCollectionStatus),
     ) -> Result<(), Error> {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
-        use nix::sys::stat::fstatat;
-        use nix::unistd::{unlinkat, UnlinkatFlags};
+        let client = match self.backend() {
+            ChunkBackend::Filesystem => {
+                return self.sweep_unused_files(min_atime, oldest_writer, status, worker, progress)
+            }
+            ChunkBackend::S3(client) => client,
+        };
+
+        self.sweep_unused_objects(&client, min_atime, oldest_writer, status, worker, progress)?;
+
+        // drop the markers of the removed objects and the stale bad markers, the chunk sizes are
+        // only known from the object list
+        task_log!(worker, "Removing unused chunk markers");
+        let mut marker_status = GarbageCollectionStatus::default();
+        self.sweep_unused_files(
+            min_atime,
+            oldest_writer,
+            &mut marker_status,
+            worker,
+            &mut |_, _| {},
+        )?;
+        status.removed_bad += marker_status.removed_bad;
+        status.still_bad += marker_status.still_bad;
+
+        Ok(())
+    }
+
+    /// Sweep phase for the S3 backend, lists all chunk objects and removes those whose marker
+    /// was last accessed before `min_atime` or is missing.
+    ///
+    /// Objects modified after `min_atime` are kept in any case, as their marker might not be
+    /// written yet.
+    fn sweep_unused_objects(
+        &self,
+        client: &S3Client,
+        min_atime: i64,
+        oldest_writer: i64,
+        status: &mut GarbageCollectionStatus,
+        worker: &dyn WorkerTaskContext,
+        progress: &mut dyn FnMut(usize, &GarbageCollectionStatus),
+    ) -> Result<(), Error> {
+        use std::os::unix::fs::MetadataExt;
 
-        let mut min_atime = phase1_start_time - 3600 * 24; // at least 24h (see mount option relatime)
+        let marker_atime = |path: &Path| match std::fs::symlink_metadata(path) {
+            Ok(metadata) => Ok(Some(metadata.atime())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format_err!("unable to stat chunk marker {path:?} - {err}")),
+        };
 
-        if oldest_writer < min_atime {
-            min_atime = oldest_writer;
+        let mut last_percentage = 0;
+        let mut object_count = 0;
+        let mut continuation_token = None;
+
+        loop {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            let list = proxmox_async::runtime::block_on(
+                client.list_objects(CHUNK_OBJECT_PREFIX, continuation_token.as_deref()),
+            )
+            .map_err(|err| {
+                format_err!(
+                    "listing chunks on chunk store '{}' failed - {err}",
+                    self.name
+                )
+            })?;
+
+            for object in list.objects {
+                let Some(digest) = chunk_object_digest(&object.key) else {
+                    continue;
+                };
+                object_count += 1;
+
+                // keys are listed in order, so the digest prefix tells the progress
+                let percentage = (u16::from_be_bytes([digest[0], digest[1]]) as usize * 100) >> 16;
+                if last_percentage != percentage {
+                    last_percentage = percentage;
+                    task_log!(worker, "processed {percentage}% ({object_count} chunks)");
+                    progress(percentage, status);
+                }
+
+                let (chunk_path, digest_str) = self.chunk_path(&digest);
+
+                let in_use = |atime: Option<i64>| {
+                    matches!(atime, Some(atime) if atime >= min_atime)
+                        || object.last_modified >= min_atime
+                };
+
+                let mut atime = marker_atime(&chunk_path)?;
+                if !in_use(atime) {
+                    // check again with uploads blocked, a backup might just have touched or
+                    // re-uploaded the chunk
+                    let _upload_guard = self.upload_lock.write().unwrap();
+                    let _lock = self.mutex.lock();
+
+                    atime = marker_atime(&chunk_path)?;
+                    if !in_use(atime) {
+                        worker.check_abort()?;
+                        proxmox_async::runtime::block_on(client.delete_object(&object.key))
+                            .map_err(|err| {
+                                format_err!(
+                                    "removing chunk {digest_str} failed on store '{}' - {err}",
+                                    self.name,
+                                )
+                            })?;
+                        if let Err(err) = std::fs::remove_file(&chunk_path) {
+                            if err.kind() != std::io::ErrorKind::NotFound {
+                                bail!("removing chunk marker {chunk_path:?} failed - {err}");
+                            }
+                        }
+                        status.removed_chunks += 1;
+                        status.removed_bytes += object.size;
+                        continue;
+                    }
+                }
+
+                // a missing marker means the object is only kept for its recent modification
+                if !matches!(atime, Some(atime) if atime >= oldest_writer) {
+                    status.pending_chunks += 1;
+                    status.pending_bytes += object.size;
+                } else {
+                    status.disk_chunks += 1;
+                    status.disk_bytes += object.size;
+                }
+            }
+
+            continuation_token = list.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
         }
 
-        min_atime -= 300; // add 5 mins gap for safety
+        Ok(())
+    }
+
+    fn sweep_unused_files(
+        &self,
+        min_atime: i64,
+        oldest_writer: i64,
+        status: &mut GarbageCollectionStatus,
+        worker: &dyn WorkerTaskContext,
+        progress: &mut dyn FnMut(usize, &GarbageCollectionStatus),
+    ) -> Result<(), Error> {
+        use nix::sys::stat::fstatat;
+        use nix::unistd::{unlinkat, UnlinkatFlags};
 
         let mut last_percentage = 0;
         let mut chunk_count = 0;
@@ -375,6 +915,7 @@ impl ChunkStore {
             if last_percentage != percentage {
                 last_percentage = percentage;
                 task_log!(worker, "processed {}% ({} chunks)", percentage, chunk_count,);
+                progress(percentage, status);
             }
 
             worker.check_abort()?;
@@ -443,6 +984,10 @@ impl ChunkStore {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
+        if let ChunkBackend::S3(client) = self.backend() {
+            return self.insert_chunk_object(&client, chunk, digest);
+        }
+
         //println!("DIGEST {}", hex::encode(digest));
 
         let (chunk_path, digest_str) = self.chunk_path(digest);
@@ -512,25 +1057,175 @@ impl ChunkStore {
 
         if self.sync_level == DatastoreFSyncLevel::File {
             // fsync dir handle to persist the tmp rename
-            let dir = std::fs::File::open(chunk_dir_path)?;
-            nix::unistd::fsync(dir.as_raw_fd())
-                .map_err(|err| format_err!("fsync failed: {err}"))?;
+            fsync_dir(chunk_dir_path)?;
+        }
+
+        drop(lock);
+
+        self.account_chunk_write(encoded_size);
+
+        Ok((false, encoded_size))
+    }
+
+    // The chunk is uploaded first and its marker only written afterwards, so that a marker always
+    // refers to a complete object. The chunk store mutex is only held for the marker, so that
+    // uploads run in parallel.
+    fn insert_chunk_object(
+        &self,
+        client: &S3Client,
+        chunk: &DataBlob,
+        digest: &[u8; 32],
+    ) -> Result<(bool, u64), Error> {
+        let (chunk_path, digest_str) = self.chunk_path(digest);
+
+        let raw_data = chunk.raw_data();
+        let encoded_size = raw_data.len() as u64;
+
+        let name = &self.name;
+
+        let _upload_guard = self.upload_lock.read().unwrap();
+
+        let lock = self.mutex.lock();
+        if self.cond_touch_path(&chunk_path, false)? {
+            return Ok((true, encoded_size));
+        }
+        drop(lock);
+
+        proxmox_async::runtime::block_on(client.put_object(&chunk_object_key(digest), raw_data))
+            .map_err(|err| {
+                format_err!("inserting chunk on store '{name}' failed for {digest_str} - {err}")
+            })?;
+
+        let lock = self.mutex.lock();
+
+        proxmox_sys::fs::replace_file(
+            &chunk_path,
+            b"",
+            CreateOptions::new(),
+            self.sync_level == DatastoreFSyncLevel::File,
+        )
+        .map_err(|err| {
+            format_err!("inserting chunk marker on store '{name}' failed for {digest_str} - {err}")
+        })?;
+
+        if self.sync_level == DatastoreFSyncLevel::File {
+            let chunk_dir_path = chunk_path
+                .parent()
+                .ok_or_else(|| format_err!("unable to get chunk dir"))?;
+            fsync_dir(chunk_dir_path)?;
         }
 
         drop(lock);
 
+        self.account_chunk_write(encoded_size);
+
         Ok((false, encoded_size))
     }
 
+    fn account_chunk_write(&self, size: u64) {
+        self.traffic.write_bytes.fetch_add(size, Ordering::Relaxed);
+        self.traffic.write_chunks.fetch_add(1, Ordering::Relaxed);
+        crate::task_io_stats::account_write(size);
+    }
+
+    /// Load the chunk with `digest` from the backend of this store.
+    ///
+    /// The read is not accounted in the traffic statistics.
+    pub fn load_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
+        match self.backend() {
+            ChunkBackend::Filesystem => {
+                let (chunk_path, _digest_str) = self.chunk_path(digest);
+                let mut file = std::fs::File::open(chunk_path)?;
+                DataBlob::load_from_reader(&mut file)
+            }
+            ChunkBackend::S3(client) => {
+                let key = self.existing_chunk_object_key(digest)?;
+                let raw_data = proxmox_async::runtime::block_on(client.get_object(&key))?;
+                let blob = DataBlob::from_raw(raw_data)?;
+                blob.verify_crc()?;
+                Ok(blob)
+            }
+        }
+    }
+
+    /// Read the raw data of the chunk with `digest` from the backend of this store, without
+    /// blocking the async runtime.
+    ///
+    /// The read is not accounted in the traffic statistics.
+    pub async fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        match self.backend() {
+            ChunkBackend::Filesystem => {
+                let (chunk_path, _digest_str) = self.chunk_path(digest);
+                proxmox_async::runtime::block_in_place(|| std::fs::read(&chunk_path))
+                    .map_err(|err| format_err!("reading chunk file {chunk_path:?} failed - {err}"))
+            }
+            ChunkBackend::S3(client) => {
+                let key = self.existing_chunk_object_key(digest)?;
+                client.get_object(&key).await
+            }
+        }
+    }
+
+    // A chunk only exists with its marker, the object of a chunk whose marker was renamed as bad
+    // by verification must not be used anymore.
+    fn existing_chunk_object_key(&self, digest: &[u8; 32]) -> Result<String, Error> {
+        let (chunk_path, digest_str) = self.chunk_path(digest);
+        if let Err(err) = std::fs::symlink_metadata(chunk_path) {
+            bail!("chunk {digest_str} not found - {err}");
+        }
+        Ok(chunk_object_key(digest))
+    }
+
+    /// The level of syncing done when writing into this store.
+    pub fn sync_level(&self) -> DatastoreFSyncLevel {
+        self.sync_level
+    }
+
+    /// Path of the chunk with `digest`.
+    ///
+    /// With sharding, this is the path in the chunk directory responsible for the digest, unless
+    /// the chunk only exists in one of the other chunk directories.
     pub fn chunk_path(&self, digest: &[u8; 32]) -> (PathBuf, String) {
         // unwrap: only `None` in unit tests
         assert!(self.locker.is_some());
 
-        let mut chunk_path = self.chunk_dir.clone();
         let prefix = digest_to_prefix(digest);
-        chunk_path.push(&prefix);
         let digest_str = hex::encode(digest);
-        chunk_path.push(&digest_str);
+
+        let extra_chunk_dirs = self.extra_chunk_dirs();
+        if extra_chunk_dirs.is_empty() {
+            let mut chunk_path = self.chunk_dir.clone();
+            chunk_path.push(&prefix);
+            chunk_path.push(&digest_str);
+            return (chunk_path, digest_str);
+        }
+
+        let chunk_dirs: Vec<&Path> = std::iter::once(self.chunk_dir.as_path())
+            .chain(extra_chunk_dirs.iter().map(PathBuf::as_path))
+            .collect();
+        let shard = digest_to_shard(digest, chunk_dirs.len());
+
+        let path_in = |chunk_dir: &Path| {
+            let mut chunk_path = chunk_dir.to_owned();
+            chunk_path.push(&prefix);
+            chunk_path.push(&digest_str);
+            chunk_path
+        };
+
+        let chunk_path = path_in(chunk_dirs[shard]);
+        if chunk_path.symlink_metadata().is_err() {
+            // fallback for chunks written before the chunk directories changed
+            for (index, chunk_dir) in chunk_dirs.iter().enumerate() {
+                if index == shard {
+                    continue;
+                }
+                let fallback_path = path_in(chunk_dir);
+                if fallback_path.symlink_metadata().is_ok() {
+                    return (fallback_path, digest_str);
+                }
+            }
+        }
+
         (chunk_path, digest_str)
     }
 
@@ -565,6 +1260,52 @@ impl ChunkStore {
     }
 }
 
+/// Returns the access time cutoff for the sweep phase of garbage collection.
+///
+/// Chunks last accessed before the cutoff are removed. The cutoff lies `grace` seconds before the
+/// start of the mark phase, and at least 5 minutes before the start of the oldest active writer,
+/// as the chunks of a running backup are not referenced by any index file yet.
+pub fn gc_atime_cutoff(phase1_start_time: i64, oldest_writer: i64, grace: i64) -> i64 {
+    (phase1_start_time - grace).min(oldest_writer - 300)
+}
+
+/// Remove all chunk objects from the bucket of an S3 backend, used when destroying a datastore.
+pub(crate) fn remove_chunk_objects(
+    client: &S3Client,
+    worker: &dyn WorkerTaskContext,
+) -> Result<(), Error> {
+    task_log!(worker, "Removing chunk objects...");
+
+    let mut removed = 0;
+    loop {
+        worker.check_abort()?;
+
+        // the removed objects are gone from the next list, so always start from the beginning
+        let list =
+            proxmox_async::runtime::block_on(client.list_objects(CHUNK_OBJECT_PREFIX, None))?;
+        if list.objects.is_empty() {
+            break;
+        }
+
+        for object in list.objects {
+            proxmox_async::runtime::block_on(client.delete_object(&object.key))?;
+            removed += 1;
+        }
+        task_log!(worker, "Removed {removed} chunk objects");
+    }
+
+    Ok(())
+}
+
+/// fsync a directory, to persist the creation, renaming or removal of its entries.
+pub(crate) fn fsync_dir(path: &Path) -> Result<(), Error> {
+    let dir = std::fs::File::open(path)
+        .map_err(|err| format_err!("unable to open directory {path:?} - {err}"))?;
+    nix::unistd::fsync(dir.as_raw_fd())
+        .map_err(|err| format_err!("fsync of directory {path:?} failed - {err}"))?;
+    Ok(())
+}
+
 #[test]
 fn test_chunk_store1() {
     let mut path = std::fs::canonicalize(".").unwrap(); // we need absolute path
@@ -610,3 +1351,308 @@ fn test_chunk_store1() {
 
     if let Err(_e) = std::fs::remove_dir_all(".testdir") { /* ignore */ }
 }
+
+#[test]
+fn test_gc_atime_cutoff() {
+    let now = 1_700_000_000;
+    let default_grace = pbs_api_types::GC_GRACE_DEFAULT as i64 * 60;
+
+    // default grace period, same as the former hardcoded 24h plus 5 minutes
+    assert_eq!(
+        gc_atime_cutoff(now, now, default_grace),
+        now - 24 * 3600 - 300
+    );
+
+    // shorter grace period, e.g. for archive datastores
+    assert_eq!(gc_atime_cutoff(now, now, 3600), now - 3600);
+
+    // longer grace period
+    let week = 7 * 24 * 3600;
+    assert_eq!(gc_atime_cutoff(now, now, week), now - week);
+
+    // an older writer moves the cutoff, including the safety gap
+    let writer = now - 2 * 3600;
+    assert_eq!(gc_atime_cutoff(now, writer, 3600), writer - 300);
+    assert_eq!(
+        gc_atime_cutoff(now, writer, default_grace),
+        now - default_grace
+    );
+
+    // the safety gap also applies to writers started shortly after the cutoff
+    let writer = now - 3600 + 60;
+    assert_eq!(gc_atime_cutoff(now, writer, 3600), writer - 300);
+
+    // recently started writers do not matter
+    let writer = now - 60;
+    assert_eq!(gc_atime_cutoff(now, writer, 3600), now - 3600);
+}
+
+#[test]
+fn test_chunk_object_key() {
+    let mut digest = [0u8; 32];
+    digest[0] = 0xab;
+    digest[1] = 0x01;
+    digest[31] = 0xff;
+
+    let key = chunk_object_key(&digest);
+    assert_eq!(
+        key,
+        "chunks/ab01/ab01\
+        0000000000000000000000000000000000000000000000000000000000ff"
+    );
+    assert_eq!(chunk_object_digest(&key), Some(digest));
+
+    // only keys written by the chunk store are chunks
+    assert_eq!(chunk_object_digest("chunks/ab01"), None);
+    assert_eq!(
+        chunk_object_digest(&key.replace("chunks/ab01", "chunks/0000")),
+        None
+    );
+    assert_eq!(chunk_object_digest(&key.replace("chunks/", "other/")), None);
+    assert_eq!(chunk_object_digest(&format!("{key}.bad")), None);
+}
+
+#[test]
+fn test_chunk_store_extra_chunk_dirs() {
+    let mut path = std::fs::canonicalize(".").unwrap(); // we need absolute path
+    path.push(".testdir-sharded");
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())
+        .unwrap()
+        .unwrap();
+    let chunk_store = ChunkStore::create(
+        "test",
+        path.join("store"),
+        user.uid,
+        user.gid,
+        None,
+        DatastoreFSyncLevel::None,
+    )
+    .unwrap();
+
+    // chunks with the digest prefix in the lower and upper half of the prefix range
+    let chunks: Vec<(DataBlob, [u8; 32])> = (0..64u8)
+        .map(|i| {
+            crate::data_blob::DataChunkBuilder::new(&[i])
+                .build()
+                .unwrap()
+        })
+        .collect();
+    let lower = chunks.iter().find(|(_, digest)| digest[0] < 0x80).unwrap();
+    let upper = chunks.iter().find(|(_, digest)| digest[0] >= 0x80).unwrap();
+
+    // written before sharding, must still be found afterwards
+    let (exists, _) = chunk_store.insert_chunk(&upper.0, &upper.1).unwrap();
+    assert!(!exists);
+
+    let extra_dir = path.join("extra");
+    ChunkStore::create_extra_chunk_dir("test", &extra_dir, user.uid, user.gid, None).unwrap();
+    assert!(!ChunkStore::chunk_dir_has_chunks(&extra_dir).unwrap());
+    chunk_store
+        .set_extra_chunk_dirs(vec![extra_dir.clone()])
+        .unwrap();
+
+    let (upper_path, _) = chunk_store.chunk_path(&upper.1);
+    assert!(upper_path.starts_with(path.join("store")));
+    let (exists, _) = chunk_store.insert_chunk(&upper.0, &upper.1).unwrap();
+    assert!(exists);
+
+    // new chunks go to the directory responsible for their digest
+    let (exists, _) = chunk_store.insert_chunk(&lower.0, &lower.1).unwrap();
+    assert!(!exists);
+    let (lower_path, _) = chunk_store.chunk_path(&lower.1);
+    assert!(lower_path.starts_with(path.join("store")));
+
+    let other = chunks
+        .iter()
+        .find(|(_, digest)| digest[0] >= 0x80 && digest != &upper.1)
+        .unwrap();
+    chunk_store.insert_chunk(&other.0, &other.1).unwrap();
+    let (other_path, _) = chunk_store.chunk_path(&other.1);
+    assert!(other_path.starts_with(&extra_dir));
+    assert!(ChunkStore::chunk_dir_has_chunks(&extra_dir).unwrap());
+
+    let count = chunk_store
+        .get_chunk_iterator()
+        .unwrap()
+        .filter(|(entry, _, _)| entry.is_ok())
+        .count();
+    assert_eq!(count, 3);
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+}
+
+#[test]
+fn test_check_fs_atime_updates() {
+    let mut path = std::fs::canonicalize(".").unwrap(); // we need absolute path
+    path.push(".testdir-atime-check");
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())
+        .unwrap()
+        .unwrap();
+    let chunk_store = ChunkStore::create(
+        "test",
+        path.join("store"),
+        user.uid,
+        user.gid,
+        None,
+        DatastoreFSyncLevel::None,
+    )
+    .unwrap();
+
+    let extra_dir = path.join("extra");
+    ChunkStore::create_extra_chunk_dir("test", &extra_dir, user.uid, user.gid, None).unwrap();
+    chunk_store
+        .set_extra_chunk_dirs(vec![extra_dir.clone()])
+        .unwrap();
+
+    chunk_store.check_fs_atime_updates().unwrap();
+
+    // the test files are removed again and never show up as chunks
+    for chunk_dir in chunk_store.chunk_dirs() {
+        assert!(!chunk_dir.join(".gc-atime-check").exists());
+    }
+    assert_eq!(chunk_store.get_chunk_iterator().unwrap().count(), 0);
+
+    // every chunk directory is checked
+    std::fs::remove_dir_all(&extra_dir).unwrap();
+    let err = chunk_store.check_fs_atime_updates().unwrap_err();
+    assert!(err.to_string().contains("unable to create test file"));
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+}
+
+#[cfg(test)]
+struct TestWorker;
+
+#[cfg(test)]
+impl WorkerTaskContext for TestWorker {
+    fn abort_requested(&self) -> bool {
+        false
+    }
+
+    fn shutdown_requested(&self) -> bool {
+        false
+    }
+
+    fn log(&self, _level: log::Level, _message: &std::fmt::Arguments) {}
+}
+
+#[test]
+fn test_chunk_store_s3_backend() -> Result<(), Error> {
+    use crate::data_blob::DataChunkBuilder;
+    use crate::s3_client::mock::{MockObject, MockS3};
+
+    // the client spawns its connections on the runtime
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _guard = runtime.enter();
+
+    let mut path = std::fs::canonicalize(".")?; // we need absolute path
+    path.push(".testdir-s3");
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+
+    let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+    let chunk_store = ChunkStore::create(
+        "test",
+        path.join("store"),
+        user.uid,
+        user.gid,
+        None,
+        DatastoreFSyncLevel::None,
+    )?;
+
+    let mock = MockS3::start("test-bucket")?;
+    let client = mock.client(&path.join("secret"))?;
+    chunk_store.set_backend(ChunkBackend::S3(Arc::new(client)));
+
+    let chunks = (0..3u8)
+        .map(|i| DataChunkBuilder::new(&[i]).build())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // chunks are uploaded and get an empty marker
+    for (chunk, digest) in chunks.iter() {
+        let (exists, size) = chunk_store.insert_chunk(chunk, digest)?;
+        assert!(!exists);
+        assert_eq!(size, chunk.raw_size());
+
+        let (marker, _) = chunk_store.chunk_path(digest);
+        assert_eq!(std::fs::metadata(marker)?.len(), 0);
+        let objects = mock.objects.lock().unwrap();
+        assert_eq!(objects[&chunk_object_key(digest)].data, chunk.raw_data());
+    }
+    let (exists, _) = chunk_store.insert_chunk(&chunks[0].0, &chunks[0].1)?;
+    assert!(exists);
+
+    let (chunk, digest) = &chunks[0];
+    assert_eq!(chunk_store.load_chunk(digest)?.raw_data(), chunk.raw_data());
+    let raw_data = proxmox_async::runtime::block_on(chunk_store.read_raw_chunk(digest))?;
+    assert_eq!(raw_data, chunk.raw_data());
+
+    // without its marker the chunk is treated as missing, even if the object exists
+    let (bad_marker, _) = chunk_store.chunk_path(&chunks[1].1);
+    let bad_marker_renamed = bad_marker.with_extension("0.bad");
+    std::fs::rename(&bad_marker, &bad_marker_renamed)?;
+    assert!(chunk_store.load_chunk(&chunks[1].1).is_err());
+    assert!(proxmox_async::runtime::block_on(chunk_store.read_raw_chunk(&chunks[1].1)).is_err());
+
+    let now = proxmox_time::epoch_i64();
+    let old = now - 2 * 24 * 3600;
+
+    // an object uploaded by a running backup, which did not write its marker yet
+    let (orphan, orphan_digest) = DataChunkBuilder::new(&[3]).build()?;
+    {
+        let mut objects = mock.objects.lock().unwrap();
+        for object in objects.values_mut() {
+            object.last_modified = old;
+        }
+        let data = orphan.raw_data().to_vec();
+        let object = MockObject {
+            data,
+            last_modified: now,
+        };
+        objects.insert(chunk_object_key(&orphan_digest), object);
+    }
+
+    // the last chunk is unused
+    let (unused_marker, _) = chunk_store.chunk_path(&chunks[2].1);
+    let atime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(old as u64);
+    std::fs::File::open(&unused_marker)?
+        .set_times(std::fs::FileTimes::new().set_accessed(atime))?;
+
+    let mut status = GarbageCollectionStatus::default();
+    chunk_store.sweep_unused_chunks(
+        now - 3600,
+        now - 60,
+        &mut status,
+        &TestWorker,
+        &mut |_, _| {},
+    )?;
+
+    assert_eq!(status.removed_chunks, 2);
+    assert_eq!(
+        status.removed_bytes,
+        chunks[1].0.raw_size() + chunks[2].0.raw_size()
+    );
+    assert_eq!(status.disk_chunks, 1);
+    assert_eq!(status.pending_chunks, 1);
+
+    let objects = mock.objects.lock().unwrap();
+    assert!(objects.contains_key(&chunk_object_key(&chunks[0].1)));
+    assert!(!objects.contains_key(&chunk_object_key(&chunks[1].1)));
+    assert!(!objects.contains_key(&chunk_object_key(&chunks[2].1)));
+    assert!(objects.contains_key(&chunk_object_key(&orphan_digest)));
+    drop(objects);
+
+    assert!(chunk_store.chunk_path(&chunks[0].1).0.exists());
+    assert!(bad_marker_renamed.exists());
+    assert!(!unused_marker.exists());
+
+    if let Err(_e) = std::fs::remove_dir_all(&path) { /* ignore */ }
+
+    Ok(())
+}
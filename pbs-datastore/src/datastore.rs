@@ -7,35 +7,139 @@ use std::sync::{Arc, Mutex};
 use anyhow::{bail, format_err, Error};
 use lazy_static::lazy_static;
 use nix::unistd::{unlinkat, UnlinkatFlags};
+use serde::{Deserialize, Serialize};
 
 use proxmox_human_byte::HumanByte;
 use proxmox_schema::ApiType;
 
 use proxmox_sys::error::SysError;
 use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
-use proxmox_sys::fs::{lock_dir_noblock, DirLockGuard};
 use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_sys::WorkerTaskContext;
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, BackupType, ChunkOrder, DataStoreConfig, DatastoreFSyncLevel,
-    DatastoreTuning, GarbageCollectionStatus, Operation, UPID,
+    ApiError, ApiErrorCode, Authid, BackupNamespace, BackupType, ChunkOrder, DataStoreConfig,
+    DatastoreFSyncLevel, DatastoreTuning, GarbageCollectionPhase, GarbageCollectionProgress,
+    GarbageCollectionStatus, GcAtimeCheckStatus, GroupDedupStats, KeepOptions, MaintenanceType,
+    NamespaceQuotaStatus, Operation, ScrubMetaEntry, ScrubMetaIssue, GC_GRACE_DEFAULT,
+    MAX_NAMESPACE_DEPTH, UPID,
 };
 
-use crate::backup_info::{BackupDir, BackupGroup};
-use crate::chunk_store::ChunkStore;
+use crate::backup_info::{
+    BackupDir, BackupGroup, GROUP_DEDUP_STATS_FILE_NAME, GROUP_NOTES_FILE_NAME,
+};
+use crate::chunk_store::{
+    fsync_dir, gc_atime_cutoff, remove_chunk_objects, ChunkBackend, ChunkStore, ChunkTrafficStats,
+};
 use crate::dynamic_index::{DynamicIndexReader, DynamicIndexWriter};
 use crate::fixed_index::{FixedIndexReader, FixedIndexWriter};
+use crate::gc_checkpoint::{GcMarkCheckpoint, GC_CHECKPOINT_FILE_NAME};
 use crate::hierarchy::{ListGroups, ListGroupsType, ListNamespaces, ListNamespacesRecursive};
 use crate::index::IndexFile;
+use crate::lock_tracking::{lock_dir_noblock, DirLockGuard};
 use crate::manifest::{archive_type, ArchiveType};
 use crate::task_tracking::{self, update_active_operations};
 use crate::DataBlob;
 
+/// File name of the per-namespace prune options, stored inside the namespace directory
+const NAMESPACE_PRUNE_OPTIONS_FILE_NAME: &str = ".prune-options";
+
+/// File name of the per-namespace quota, stored inside the namespace directory
+const NAMESPACE_QUOTA_FILE_NAME: &str = ".quota";
+
+/// File name of the verify-new setting of a namespace, stored in the namespace directory
+const NAMESPACE_VERIFY_NEW_FILE_NAME: &str = ".verify-new";
+
+/// File name of the recorded logical usage of all namespaces, stored in the datastore base
+const NAMESPACE_USAGE_FILE_NAME: &str = ".namespace-usage";
+
+/// File name of the result of the last GC access time check, stored in the datastore base
+const GC_ATIME_CHECK_FILE_NAME: &str = ".gc-atime-check";
+
+/// Logical usage of all namespaces of a datastore, as recorded by the last accounting pass.
+#[derive(Default, Serialize, Deserialize)]
+struct NamespaceUsage {
+    /// Time the usage was recorded
+    time: i64,
+    /// Logical bytes per namespace, including all child namespaces
+    usage: HashMap<String, u64>,
+}
+
 lazy_static! {
     static ref DATASTORE_MAP: Mutex<HashMap<String, Arc<DataStoreImpl>>> =
         Mutex::new(HashMap::new());
+    // keyed by datastore name, as the cached DataStoreImpl gets replaced on config changes
+    static ref GC_PROGRESS_MAP: Mutex<HashMap<String, GarbageCollectionProgress>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Minimal interval between two progress updates during the index scan phase of GC.
+const GC_PROGRESS_UPDATE_INTERVAL: i64 = 10;
+
+/// Tracks the progress of a running garbage collection and publishes it, so that status
+/// queries can report it while the GC is still running.
+///
+/// The published progress is removed again on drop.
+struct GarbageCollectionProgressTracker {
+    store: String,
+    phase_start: i64,
+    last_update: i64,
+    progress: GarbageCollectionProgress,
+}
+
+impl GarbageCollectionProgressTracker {
+    fn new(store: &str) -> Self {
+        let now = proxmox_time::epoch_i64();
+        let tracker = Self {
+            store: store.to_string(),
+            phase_start: now,
+            last_update: now,
+            progress: GarbageCollectionProgress::default(),
+        };
+        tracker.publish();
+        tracker
+    }
+
+    fn start_phase(&mut self, phase: GarbageCollectionPhase) {
+        self.phase_start = proxmox_time::epoch_i64();
+        self.progress.phase = phase;
+        self.progress.percentage = 0;
+        self.progress.eta = None;
+        self.publish();
+    }
+
+    /// Update the percentage of the current phase, publishes if the percentage changed or if
+    /// `force` is set.
+    fn update(&mut self, percentage: usize, force: bool) {
+        let now = proxmox_time::epoch_i64();
+        if !force
+            && percentage == self.progress.percentage
+            && now - self.last_update < GC_PROGRESS_UPDATE_INTERVAL
+        {
+            return;
+        }
+        self.progress.percentage = percentage;
+        if percentage > 0 {
+            let elapsed = now - self.phase_start;
+            self.progress.eta = Some(self.phase_start + elapsed * 100 / percentage as i64);
+        }
+        self.last_update = now;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        GC_PROGRESS_MAP
+            .lock()
+            .unwrap()
+            .insert(self.store.clone(), self.progress.clone());
+    }
+}
+
+impl Drop for GarbageCollectionProgressTracker {
+    fn drop(&mut self) {
+        GC_PROGRESS_MAP.lock().unwrap().remove(&self.store);
+    }
 }
 
 /// checks if auth_id is owner, or, if owner is a token, if
@@ -61,6 +165,9 @@ pub struct DataStoreImpl {
     chunk_order: ChunkOrder,
     last_digest: Option<[u8; 32]>,
     sync_level: DatastoreFSyncLevel,
+    gc_atime_check: bool,
+    gc_grace: u64,
+    trash_retention: u64,
 }
 
 impl DataStoreImpl {
@@ -75,6 +182,9 @@ impl DataStoreImpl {
             chunk_order: Default::default(),
             last_digest: None,
             sync_level: Default::default(),
+            gc_atime_check: true,
+            gc_grace: GC_GRACE_DEFAULT,
+            trash_retention: 0,
         })
     }
 }
@@ -82,6 +192,8 @@ impl DataStoreImpl {
 pub struct DataStore {
     inner: Arc<DataStoreImpl>,
     operation: Option<Operation>,
+    /// Task recorded as owner of the operation, see [`DataStore::set_operation_owner`].
+    operation_owner: Mutex<Option<String>>,
 }
 
 impl Clone for DataStore {
@@ -97,12 +209,18 @@ impl Clone for DataStore {
         DataStore {
             inner: self.inner.clone(),
             operation: new_operation,
+            operation_owner: Mutex::new(None),
         }
     }
 }
 
 impl Drop for DataStore {
     fn drop(&mut self) {
+        if let Some(upid) = self.operation_owner.get_mut().unwrap().take() {
+            if let Err(e) = task_tracking::update_operation_tasks(self.name(), &upid, false) {
+                log::error!("could not update active operation tasks - {}", e);
+            }
+        }
         if let Some(operation) = self.operation {
             if let Err(e) = update_active_operations(self.name(), operation, -1) {
                 log::error!("could not update active operations - {}", e);
@@ -118,9 +236,29 @@ impl DataStore {
         Arc::new(Self {
             inner: unsafe { DataStoreImpl::new_test() },
             operation: None,
+            operation_owner: Mutex::new(None),
         })
     }
 
+    /// Record the task `upid` as owner of the active operation of this instance.
+    ///
+    /// Datastores are usually looked up before the task using them is started, so the task has to
+    /// be set afterwards. It is listed by [`task_tracking::get_active_tasks`] until the instance
+    /// is dropped.
+    pub fn set_operation_owner(&self, upid: &str) {
+        if self.operation.is_none() {
+            return;
+        }
+        let mut owner = self.operation_owner.lock().unwrap();
+        if owner.is_some() {
+            return;
+        }
+        match task_tracking::update_operation_tasks(self.name(), upid, true) {
+            Ok(()) => *owner = Some(upid.to_string()),
+            Err(err) => log::error!("could not update active operation tasks - {err}"),
+        }
+    }
+
     pub fn lookup_datastore(
         name: &str,
         operation: Option<Operation>,
@@ -132,11 +270,20 @@ impl DataStore {
         // we could use the ConfigVersionCache's generation for staleness detection, but  we load
         // the config anyway -> just use digest, additional benefit: manual changes get detected
         let (config, digest) = pbs_config::datastore::config()?;
+        if !config.sections.contains_key(name) {
+            return Err(ApiError::new(
+                ApiErrorCode::NotFound,
+                format!("no such datastore '{name}'"),
+            )
+            .into());
+        }
         let config: DataStoreConfig = config.lookup("datastore", name)?;
 
         if let Some(maintenance_mode) = config.get_maintenance_mode() {
             if let Err(error) = maintenance_mode.check(operation) {
-                bail!("datastore '{name}' is in {error}");
+                // keep the `MaintenanceModeError` accessible for callers via downcasting
+                let message = format!("datastore '{name}' is in {error}");
+                return Err(error.context(ApiError::new(ApiErrorCode::MaintenanceMode, message)));
             }
         }
 
@@ -157,6 +304,7 @@ impl DataStore {
                 return Ok(Arc::new(Self {
                     inner: Arc::clone(datastore),
                     operation,
+                    operation_owner: Mutex::new(None),
                 }));
             }
             Arc::clone(&datastore.chunk_store)
@@ -180,6 +328,7 @@ impl DataStore {
         Ok(Arc::new(Self {
             inner: datastore,
             operation,
+            operation_owner: Mutex::new(None),
         }))
     }
 
@@ -241,7 +390,11 @@ impl DataStore {
             update_active_operations(&name, operation, 1)?;
         }
 
-        Ok(Arc::new(Self { inner, operation }))
+        Ok(Arc::new(Self {
+            inner,
+            operation,
+            operation_owner: Mutex::new(None),
+        }))
     }
 
     fn with_store_and_config(
@@ -269,6 +422,15 @@ impl DataStore {
                 .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
         )?;
 
+        let extra_chunk_dirs = config
+            .extra_chunk_dir
+            .iter()
+            .flatten()
+            .map(PathBuf::from)
+            .collect();
+        chunk_store.set_extra_chunk_dirs(extra_chunk_dirs)?;
+        chunk_store.set_backend(ChunkBackend::from_config(config.backend.as_deref())?);
+
         Ok(DataStoreImpl {
             chunk_store,
             gc_mutex: Mutex::new(()),
@@ -277,6 +439,9 @@ impl DataStore {
             chunk_order: tuning.chunk_order.unwrap_or_default(),
             last_digest,
             sync_level: tuning.sync_level.unwrap_or_default(),
+            gc_atime_check: tuning.gc_atime_check.unwrap_or(true),
+            gc_grace: tuning.gc_grace.unwrap_or(GC_GRACE_DEFAULT),
+            trash_retention: config.trash_retention.unwrap_or(0),
         })
     }
 
@@ -451,6 +616,379 @@ impl DataStore {
         path.exists()
     }
 
+    /// Returns the absolute path of the file holding the prune options of a namespace
+    pub fn namespace_prune_options_path(&self, ns: &BackupNamespace) -> PathBuf {
+        let mut path = self.namespace_path(ns);
+        path.push(NAMESPACE_PRUNE_OPTIONS_FILE_NAME);
+        path
+    }
+
+    /// Load the prune options stored directly on the given namespace, if any.
+    pub fn namespace_prune_options(
+        &self,
+        ns: &BackupNamespace,
+    ) -> Result<Option<KeepOptions>, Error> {
+        let path = self.namespace_prune_options_path(ns);
+        match file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data).map_err(|err| {
+                format_err!("unable to parse prune options for namespace '{ns}' - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the prune options of a namespace, `None` removes them.
+    ///
+    /// Options which keep nothing are refused, as they would keep all snapshots of the namespace
+    /// and its children instead of falling back to the options above.
+    pub fn set_namespace_prune_options(
+        &self,
+        ns: &BackupNamespace,
+        options: Option<&KeepOptions>,
+    ) -> Result<(), Error> {
+        if !self.namespace_exists(ns) {
+            bail!("namespace '{ns}' does not exist");
+        }
+
+        let path = self.namespace_prune_options_path(ns);
+
+        let options = match options {
+            Some(options) if !options.keeps_something() => {
+                bail!("no keep option set for namespace '{ns}' - remove the options instead");
+            }
+            Some(options) => options,
+            None => {
+                return match std::fs::remove_file(&path) {
+                    Ok(()) => Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                    Err(err) => Err(format_err!("unable to remove {path:?} - {err}")),
+                };
+            }
+        };
+
+        let data = serde_json::to_string(options)?;
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let create_options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(path, data.as_bytes(), create_options, true)
+    }
+
+    /// Returns the absolute path of the file holding the verify-new setting of a namespace
+    pub fn namespace_verify_new_path(&self, ns: &BackupNamespace) -> PathBuf {
+        let mut path = self.namespace_path(ns);
+        path.push(NAMESPACE_VERIFY_NEW_FILE_NAME);
+        path
+    }
+
+    /// Load the verify-new setting stored directly on the given namespace, if any.
+    pub fn namespace_verify_new(&self, ns: &BackupNamespace) -> Result<Option<bool>, Error> {
+        let path = self.namespace_verify_new_path(ns);
+        match file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(data.trim().parse().map_err(|err| {
+                format_err!("unable to parse verify-new setting for namespace '{ns}' - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the verify-new setting of a namespace, `None` removes it.
+    pub fn set_namespace_verify_new(
+        &self,
+        ns: &BackupNamespace,
+        verify_new: Option<bool>,
+    ) -> Result<(), Error> {
+        if !self.namespace_exists(ns) {
+            bail!("namespace '{ns}' does not exist");
+        }
+
+        let path = self.namespace_verify_new_path(ns);
+
+        let verify_new = match verify_new {
+            Some(verify_new) => verify_new,
+            None => {
+                return match std::fs::remove_file(&path) {
+                    Ok(()) => Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                    Err(err) => Err(format_err!("unable to remove {path:?} - {err}")),
+                };
+            }
+        };
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let create_options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(
+            path,
+            format!("{verify_new}\n").as_bytes(),
+            create_options,
+            true,
+        )
+    }
+
+    /// Resolve whether new snapshots in a namespace are verified automatically.
+    ///
+    /// Walks from `ns` up to the root namespace and returns the closest setting together with
+    /// the namespace it was set on. Falls back to the datastore's 'verify-new' option, returned
+    /// without namespace.
+    pub fn lookup_namespace_verify_new(
+        &self,
+        ns: &BackupNamespace,
+    ) -> Result<(bool, Option<BackupNamespace>), Error> {
+        let mut ns = ns.clone();
+        loop {
+            if let Some(verify_new) = self.namespace_verify_new(&ns)? {
+                return Ok((verify_new, Some(ns)));
+            }
+            if ns.is_root() {
+                return Ok((self.verify_new(), None));
+            }
+            ns.pop();
+        }
+    }
+
+    /// Remove the stored settings of a namespace if they are the only thing left
+    /// in its directory, so that the otherwise empty namespace can be removed.
+    fn remove_leftover_namespace_settings(&self, ns: &BackupNamespace) -> Result<(), Error> {
+        let ns_path = self.namespace_path(ns);
+        let entries = match std::fs::read_dir(&ns_path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut has_settings = false;
+        for entry in entries {
+            let file_name = entry?.file_name();
+            if file_name != NAMESPACE_PRUNE_OPTIONS_FILE_NAME
+                && file_name != NAMESPACE_QUOTA_FILE_NAME
+                && file_name != NAMESPACE_VERIFY_NEW_FILE_NAME
+            {
+                return Ok(());
+            }
+            has_settings = true;
+        }
+        if has_settings {
+            self.set_namespace_prune_options(ns, None)?;
+            self.set_namespace_quota(ns, None)?;
+            self.set_namespace_verify_new(ns, None)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve the prune options which apply to a namespace.
+    ///
+    /// Walks from `ns` up to the root namespace and returns the closest configured options
+    /// together with the namespace they were configured on.
+    pub fn lookup_namespace_prune_options(
+        &self,
+        ns: &BackupNamespace,
+    ) -> Result<Option<(BackupNamespace, KeepOptions)>, Error> {
+        let mut ns = ns.clone();
+        loop {
+            if let Some(options) = self.namespace_prune_options(&ns)? {
+                return Ok(Some((ns, options)));
+            }
+            if ns.is_root() {
+                return Ok(None);
+            }
+            ns.pop();
+        }
+    }
+
+    /// Returns the absolute path of the file holding the quota of a namespace
+    pub fn namespace_quota_path(&self, ns: &BackupNamespace) -> PathBuf {
+        let mut path = self.namespace_path(ns);
+        path.push(NAMESPACE_QUOTA_FILE_NAME);
+        path
+    }
+
+    /// Load the quota in bytes stored on the given namespace, if any.
+    pub fn namespace_quota(&self, ns: &BackupNamespace) -> Result<Option<u64>, Error> {
+        let path = self.namespace_quota_path(ns);
+        match file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(data.trim().parse().map_err(|err| {
+                format_err!("unable to parse quota for namespace '{ns}' - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the quota in bytes of a namespace, `None` removes it.
+    pub fn set_namespace_quota(
+        &self,
+        ns: &BackupNamespace,
+        quota: Option<u64>,
+    ) -> Result<(), Error> {
+        if !self.namespace_exists(ns) {
+            bail!("namespace '{ns}' does not exist");
+        }
+
+        let path = self.namespace_quota_path(ns);
+
+        let quota = match quota {
+            Some(quota) => quota,
+            None => {
+                return match std::fs::remove_file(&path) {
+                    Ok(()) => Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                    Err(err) => Err(format_err!("unable to remove {path:?} - {err}")),
+                };
+            }
+        };
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let create_options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(path, format!("{quota}\n").as_bytes(), create_options, true)
+    }
+
+    fn load_namespace_usage(&self) -> Result<Option<NamespaceUsage>, Error> {
+        let mut path = self.base_path();
+        path.push(NAMESPACE_USAGE_FILE_NAME);
+        match file_read_optional_string(&path)? {
+            Some(data) => Ok(Some(serde_json::from_str(&data).map_err(|err| {
+                format_err!("unable to parse recorded namespace usage - {err}")
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the logical usage per namespace recorded by the last accounting pass, keyed by
+    /// the namespace path.
+    pub fn recorded_namespace_usage(&self) -> Result<HashMap<String, u64>, Error> {
+        Ok(self
+            .load_namespace_usage()?
+            .map(|recorded| recorded.usage)
+            .unwrap_or_default())
+    }
+
+    /// Returns the quota of a namespace together with the logical usage of it and its children,
+    /// as recorded by the last accounting pass.
+    pub fn namespace_quota_status(
+        &self,
+        ns: &BackupNamespace,
+    ) -> Result<NamespaceQuotaStatus, Error> {
+        let quota = self.namespace_quota(ns)?;
+        let (usage, usage_time) = match self.load_namespace_usage()? {
+            Some(recorded) => (
+                recorded.usage.get(&ns.to_string()).copied(),
+                Some(recorded.time),
+            ),
+            None => (None, None),
+        };
+        Ok(NamespaceQuotaStatus {
+            quota,
+            usage: usage.or(usage_time.map(|_| 0)),
+            usage_time,
+        })
+    }
+
+    /// Check that neither the namespace nor any of its parents exceed their quota.
+    ///
+    /// Uses the usage recorded by the last accounting pass, so a namespace only gets rejected
+    /// once that pass noticed it went over its quota.
+    pub fn check_namespace_quota(&self, ns: &BackupNamespace) -> Result<(), Error> {
+        let recorded = match self.load_namespace_usage()? {
+            Some(recorded) => recorded,
+            None => return Ok(()),
+        };
+
+        let mut ns = ns.clone();
+        loop {
+            if let Some(quota) = self.namespace_quota(&ns)? {
+                let usage = recorded.usage.get(&ns.to_string()).copied().unwrap_or(0);
+                if usage > quota {
+                    bail!(
+                        "namespace '{ns}' exceeds its quota ({} used of {})",
+                        HumanByte::from(usage),
+                        HumanByte::from(quota),
+                    );
+                }
+            }
+            if ns.is_root() {
+                return Ok(());
+            }
+            ns.pop();
+        }
+    }
+
+    /// Record the logical usage of every namespace, including its children.
+    ///
+    /// The usage is the sum of the sizes of all index archives referenced by the snapshots'
+    /// manifests, i.e. the data size before deduplication and compression.
+    pub fn update_namespace_usage(
+        self: &Arc<Self>,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(), Error> {
+        let mut usage: HashMap<String, u64> = HashMap::new();
+
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            worker.check_abort()?;
+
+            let mut ns_bytes = 0u64;
+            for group in self.iter_backup_groups_ok(ns.clone())? {
+                for info in group.list_backups()? {
+                    let manifest = match info.backup_dir.load_manifest() {
+                        Ok((manifest, _)) => manifest,
+                        Err(_) => continue, // unfinished or broken snapshots don't count
+                    };
+                    ns_bytes += manifest.logical_size();
+                }
+            }
+
+            // account the namespace's own data to itself and all of its parents
+            let mut parent = ns;
+            loop {
+                *usage.entry(parent.to_string()).or_default() += ns_bytes;
+                if parent.is_root() {
+                    break;
+                }
+                parent.pop();
+            }
+        }
+
+        task_log!(
+            worker,
+            "Recorded logical usage of {} namespaces ({} in total)",
+            usage.len(),
+            HumanByte::from(usage.get("").copied().unwrap_or(0)),
+        );
+
+        let recorded = NamespaceUsage {
+            time: proxmox_time::epoch_i64(),
+            usage,
+        };
+
+        let mut path = self.base_path();
+        path.push(NAMESPACE_USAGE_FILE_NAME);
+
+        let backup_user = pbs_config::backup_user()?;
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+        let options = CreateOptions::new()
+            .perm(mode)
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+
+        replace_file(
+            path,
+            serde_json::to_string(&recorded)?.as_bytes(),
+            options,
+            false,
+        )
+    }
+
     /// Remove all backup groups of a single namespace level but not the namespace itself.
     ///
     /// Does *not* descends into child-namespaces and doesn't remoes the namespace itself either.
@@ -484,6 +1022,140 @@ impl DataStore {
         Ok(removed_all_groups)
     }
 
+    /// Check the namespace, group and snapshot directories for inconsistencies, for example left
+    /// behind by interrupted operations.
+    ///
+    /// With `repair` set, empty group directories get removed and missing owner files are
+    /// recreated with `default_owner`, if one is given. Groups which are currently locked, for
+    /// example by a running backup, are skipped.
+    pub fn scrub_metadata(
+        self: &Arc<Self>,
+        repair: bool,
+        default_owner: Option<&Authid>,
+    ) -> Result<Vec<ScrubMetaEntry>, Error> {
+        let mut entries = Vec::new();
+
+        for ns in self.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+            if ns.depth() >= MAX_NAMESPACE_DEPTH {
+                let mut path = ns.path();
+                path.push("ns");
+                if self.base_path().join(&path).exists() {
+                    entries.push(ScrubMetaEntry {
+                        path: path.to_string_lossy().into_owned(),
+                        issue: ScrubMetaIssue::NamespaceTooDeep,
+                        repaired: false,
+                        note: Some(format!("exceeds maximal depth of {MAX_NAMESPACE_DEPTH}")),
+                    });
+                }
+            }
+
+            for group in self.iter_backup_groups_ok(ns)? {
+                self.scrub_group_metadata(&group, repair, default_owner, &mut entries)?;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn scrub_group_metadata(
+        &self,
+        group: &BackupGroup,
+        repair: bool,
+        default_owner: Option<&Authid>,
+        entries: &mut Vec<ScrubMetaEntry>,
+    ) -> Result<(), Error> {
+        let group_path = group.full_group_path();
+        let path = group.relative_group_path().to_string_lossy().into_owned();
+
+        let _guard = match lock_dir_noblock(&group_path, "backup group", "possible running backup")
+        {
+            Ok(guard) => guard,
+            Err(err) => {
+                log::info!("skipping group {path} - {err}");
+                return Ok(());
+            }
+        };
+
+        let snapshots = group.list_backups()?;
+
+        if snapshots.is_empty() {
+            // only remove the group if nothing but the owner file (and cached stats) is left
+            let mut only_owner = true;
+            let mut has_notes = false;
+            for entry in std::fs::read_dir(&group_path)? {
+                let file_name = entry?.file_name();
+                if file_name == GROUP_NOTES_FILE_NAME {
+                    has_notes = true;
+                    only_owner = false;
+                } else if file_name != "owner" && file_name != GROUP_DEDUP_STATS_FILE_NAME {
+                    only_owner = false;
+                    break;
+                }
+            }
+
+            let (repaired, note) = if !repair {
+                (false, None)
+            } else if has_notes {
+                (false, Some("group has notes, not removing it".to_string()))
+            } else if !only_owner {
+                (
+                    false,
+                    Some("group directory contains unknown files".to_string()),
+                )
+            } else {
+                match std::fs::remove_dir_all(&group_path) {
+                    Ok(()) => (true, Some("removed group directory".to_string())),
+                    Err(err) => (
+                        false,
+                        Some(format!("removing group directory failed - {err}")),
+                    ),
+                }
+            };
+
+            entries.push(ScrubMetaEntry {
+                path,
+                issue: ScrubMetaIssue::EmptyGroup,
+                repaired,
+                note,
+            });
+            return Ok(());
+        }
+
+        if !self.owner_path(group.backup_ns(), group.as_ref()).exists() {
+            let (repaired, note) = match (repair, default_owner) {
+                (false, _) => (false, None),
+                (true, None) => (false, Some("no default owner given".to_string())),
+                (true, Some(owner)) => match group.set_owner(owner, false) {
+                    Ok(()) => (true, Some(format!("set owner to '{owner}'"))),
+                    Err(err) => (false, Some(err.to_string())),
+                },
+            };
+
+            entries.push(ScrubMetaEntry {
+                path: path.clone(),
+                issue: ScrubMetaIssue::MissingOwner,
+                repaired,
+                note,
+            });
+        }
+
+        // the group lock excludes running backups, so unfinished snapshots are left-overs
+        for info in snapshots.iter().filter(|info| !info.is_finished()) {
+            entries.push(ScrubMetaEntry {
+                path: info
+                    .backup_dir
+                    .relative_path()
+                    .to_string_lossy()
+                    .into_owned(),
+                issue: ScrubMetaIssue::MissingManifest,
+                repaired: false,
+                note: None,
+            });
+        }
+
+        Ok(())
+    }
+
     /// Remove a complete backup namespace optionally including all it's, and child namespaces',
     /// groups. If  `removed_groups` is false this only prunes empty namespaces.
     ///
@@ -523,6 +1195,10 @@ impl DataStore {
             let _ = unlinkat(Some(base_fd), &ns_dir, UnlinkatFlags::RemoveDir);
 
             if !ns.is_root() {
+                if let Err(err) = self.remove_leftover_namespace_settings(ns) {
+                    log::warn!("failed to remove settings of namespace {ns} - {err}");
+                }
+
                 match unlinkat(Some(base_fd), &ns.path(), UnlinkatFlags::RemoveDir) {
                     Ok(()) => log::debug!("removed namespace {ns}"),
                     Err(nix::errno::Errno::ENOENT) => {
@@ -543,6 +1219,39 @@ impl DataStore {
         Ok(removed_all_requested)
     }
 
+    /// Remove the directory of an empty namespace, together with its empty backup type and `ns`
+    /// subdirectories and its settings.
+    ///
+    /// Returns `false` if the namespace still contains groups or other namespaces. The root
+    /// namespace is never removed.
+    pub fn remove_empty_namespace(&self, ns: &BackupNamespace) -> Result<bool, Error> {
+        let base_file = std::fs::File::open(self.base_path())?;
+        let base_fd = base_file.as_raw_fd();
+
+        let mut subdirs = vec![ns.path().join("ns")];
+        subdirs.extend(BackupType::iter().map(|ty| ns.path().join(ty.to_string())));
+        for dir in subdirs {
+            match unlinkat(Some(base_fd), &dir, UnlinkatFlags::RemoveDir) {
+                Ok(()) | Err(nix::errno::Errno::ENOENT) | Err(nix::errno::Errno::ENOTEMPTY) => {}
+                Err(err) => bail!("failed to remove {dir:?} - {err}"),
+            }
+        }
+
+        if ns.is_root() {
+            return Ok(false);
+        }
+
+        if let Err(err) = self.remove_leftover_namespace_settings(ns) {
+            log::warn!("failed to remove settings of namespace {ns} - {err}");
+        }
+
+        match unlinkat(Some(base_fd), &ns.path(), UnlinkatFlags::RemoveDir) {
+            Ok(()) => Ok(true),
+            Err(nix::errno::Errno::ENOENT) | Err(nix::errno::Errno::ENOTEMPTY) => Ok(false),
+            Err(err) => bail!("failed to remove namespace {ns} - {err}"),
+        }
+    }
+
     /// Remove a complete backup group including all snapshots.
     ///
     /// Returns true if all snapshots were removed, and false if some were protected
@@ -556,6 +1265,70 @@ impl DataStore {
         backup_group.destroy()
     }
 
+    /// Move a complete backup group including all snapshots to another namespace.
+    ///
+    /// The group directory is only renamed, so owner, notes, protection flags and verification
+    /// states of the snapshots are kept. The group is locked during the move, so that concurrent
+    /// backups to this group fail instead of writing into a vanishing directory.
+    pub fn move_backup_group(
+        self: &Arc<Self>,
+        source_ns: &BackupNamespace,
+        target_ns: &BackupNamespace,
+        backup_group: &pbs_api_types::BackupGroup,
+    ) -> Result<(), Error> {
+        if source_ns == target_ns {
+            bail!("source and target namespace must not be the same");
+        }
+
+        if !self.namespace_exists(target_ns) {
+            bail!("target namespace '{target_ns}' does not exist");
+        }
+
+        let source_path = self.group_path(source_ns, backup_group);
+        let target_path = self.group_path(target_ns, backup_group);
+
+        let _guard = lock_dir_noblock(
+            &source_path,
+            "backup group",
+            "possible running backup, cannot move group",
+        )?;
+
+        // readers like restore, verify or sync only lock the snapshot they access
+        let mut _snapshot_guards = Vec::new();
+        for snapshot in self
+            .backup_group(source_ns.clone(), backup_group.clone())
+            .iter_snapshots()?
+        {
+            let snapshot = snapshot?;
+            _snapshot_guards.push(lock_dir_noblock(
+                &snapshot.full_path(),
+                "snapshot",
+                "snapshot in use, cannot move group",
+            )?);
+        }
+
+        std::fs::create_dir_all(self.type_path(target_ns, backup_group.ty))?;
+
+        // don't just check for existence beforehand, a backup could create the group in between
+        nix::fcntl::renameat2(
+            None,
+            &source_path,
+            None,
+            &target_path,
+            nix::fcntl::RenameFlags::RENAME_NOREPLACE,
+        )
+        .map_err(|err| match err {
+            nix::errno::Errno::EEXIST => format_err!(
+                "group '{backup_group}' already exists in target namespace '{target_ns}'"
+            ),
+            err => format_err!("moving group '{backup_group}' to {target_path:?} failed - {err}"),
+        })?;
+
+        log::info!("moved backup group {backup_group} from '{source_ns}' to '{target_ns}'");
+
+        Ok(())
+    }
+
     /// Remove a backup directory including all content
     pub fn remove_backup_dir(
         self: &Arc<Self>,
@@ -729,6 +1502,34 @@ impl DataStore {
         }
     }
 
+    /// Remove the files left behind in a snapshot directory by a backup session which did not
+    /// finish, and return their names.
+    ///
+    /// The caller must hold the exclusive lock of the snapshot, which ensures that the session
+    /// which created the files is gone.
+    pub fn remove_partial_backup_files(
+        &self,
+        ns: &BackupNamespace,
+        backup_dir: &pbs_api_types::BackupDir,
+    ) -> Result<Vec<String>, Error> {
+        let full_path = self.snapshot_path(ns, backup_dir);
+
+        let mut removed = Vec::new();
+        for entry in std::fs::read_dir(&full_path)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+            }
+            .map_err(|err| format_err!("removing partial file {path:?} failed - {err}"))?;
+            removed.push(entry.file_name().to_string_lossy().into_owned());
+        }
+
+        Ok(removed)
+    }
+
     /// Get a streaming iter over single-level backup namespaces of a datatstore
     ///
     /// The iterated item is still a Result that can contain errors from rather unexptected FS or
@@ -853,16 +1654,25 @@ impl DataStore {
     }
 
     pub fn list_images(&self) -> Result<Vec<PathBuf>, Error> {
-        let base = self.base_path();
+        self.list_index_files(&self.base_path())
+    }
 
+    /// List the index files below `dir`, which is either the datastore base or the trash.
+    fn list_index_files(&self, dir: &Path) -> Result<Vec<PathBuf>, Error> {
         let mut list = vec![];
 
         use walkdir::WalkDir;
 
-        let walker = WalkDir::new(base).into_iter();
+        let walker = WalkDir::new(dir).into_iter();
 
-        // make sure we skip .chunks (and other hidden files to keep it simple)
+        // make sure we skip .chunks (and other hidden files to keep it simple), but not the trash,
+        // its index files still reference their chunks
         fn is_hidden(entry: &walkdir::DirEntry) -> bool {
+            if entry.depth() == 0
+                || (entry.depth() == 1 && entry.file_name() == crate::trash::TRASH_DIR_NAME)
+            {
+                return false;
+            }
             entry
                 .file_name()
                 .to_str()
@@ -917,6 +1727,7 @@ impl DataStore {
         index: I,
         file_name: &Path, // only used for error reporting
         status: &mut GarbageCollectionStatus,
+        progress: &mut GarbageCollectionProgressTracker,
         worker: &dyn WorkerTaskContext,
     ) -> Result<(), Error> {
         status.index_file_count += 1;
@@ -945,16 +1756,38 @@ impl DataStore {
                 }
             }
         }
+
+        // only count the chunks once the whole index is marked, an abort leaves them out
+        progress.progress.chunks_touched += index.index_count() as u64;
+
         Ok(())
     }
 
     fn mark_used_chunks(
         &self,
         status: &mut GarbageCollectionStatus,
+        progress: &mut GarbageCollectionProgressTracker,
+        checkpoint: &mut GcMarkCheckpoint,
         worker: &dyn WorkerTaskContext,
     ) -> Result<(), Error> {
         let image_list = self.list_images()?;
+        self.mark_image_list(image_list, status, progress, checkpoint, worker)
+    }
+
+    fn mark_image_list(
+        &self,
+        image_list: Vec<PathBuf>,
+        status: &mut GarbageCollectionStatus,
+        progress: &mut GarbageCollectionProgressTracker,
+        checkpoint: &mut GcMarkCheckpoint,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<(), Error> {
         let image_count = image_list.len();
+        let trash_dir = self.base_path().join(crate::trash::TRASH_DIR_NAME);
+        let mut marked_trash = HashSet::new();
+
+        progress.progress.index_files_total = image_count;
+        progress.update(0, true);
 
         let mut last_percentage: usize = 0;
 
@@ -966,6 +1799,9 @@ impl DataStore {
 
             if let Some(backup_dir_path) = img.parent() {
                 let backup_dir_path = backup_dir_path.strip_prefix(self.base_path())?;
+                let backup_dir_path = backup_dir_path
+                    .strip_prefix(crate::trash::TRASH_DIR_NAME)
+                    .unwrap_or(backup_dir_path);
                 if let Some(backup_dir_str) = backup_dir_path.to_str() {
                     if pbs_api_types::parse_ns_and_snapshot(backup_dir_str).is_err() {
                         strange_paths_count += 1;
@@ -973,27 +1809,19 @@ impl DataStore {
                 }
             }
 
-            match std::fs::File::open(&img) {
-                Ok(file) => {
-                    if let Ok(archive_type) = archive_type(&img) {
-                        if archive_type == ArchiveType::FixedIndex {
-                            let index = FixedIndexReader::new(file).map_err(|e| {
-                                format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
-                            })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
-                        } else if archive_type == ArchiveType::DynamicIndex {
-                            let index = DynamicIndexReader::new(file).map_err(|e| {
-                                format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
-                            })?;
-                            self.index_mark_used_chunks(index, &img, status, worker)?;
-                        }
-                    }
-                }
-                Err(err) if err.kind() == io::ErrorKind::NotFound => (), // ignore vanished files
-                Err(err) => bail!("can't open index {} - {}", img.to_string_lossy(), err),
+            if self.mark_index_file(&img, status, progress, checkpoint, worker)?
+                && img.starts_with(&trash_dir)
+            {
+                marked_trash.insert(img);
+            }
+
+            if let Err(err) = checkpoint.save_if_due(proxmox_time::epoch_i64()) {
+                task_warn!(worker, "{err}");
             }
 
             let percentage = (i + 1) * 100 / image_count;
+            progress.progress.index_files_processed = i + 1;
+            progress.update(percentage, false);
             if percentage > last_percentage {
                 task_log!(
                     worker,
@@ -1006,6 +1834,21 @@ impl DataStore {
             }
         }
 
+        // snapshots moved to the trash during the marking were only listed at their old path,
+        // which vanished before it was opened, so their index files must be marked in the trash.
+        // Restoring from the trash is not possible while GC runs, so nothing can move the other
+        // way.
+        if trash_dir.exists() {
+            for img in self.list_index_files(&trash_dir)? {
+                if marked_trash.contains(&img) {
+                    continue;
+                }
+                worker.check_abort()?;
+                worker.fail_on_shutdown()?;
+                self.mark_index_file(&img, status, progress, checkpoint, worker)?;
+            }
+        }
+
         if strange_paths_count > 0 {
             task_log!(
                 worker,
@@ -1017,6 +1860,168 @@ impl DataStore {
         Ok(())
     }
 
+    /// Mark the chunks used by the index file `img`, returns false if the file vanished.
+    fn mark_index_file(
+        &self,
+        img: &Path,
+        status: &mut GarbageCollectionStatus,
+        progress: &mut GarbageCollectionProgressTracker,
+        checkpoint: &mut GcMarkCheckpoint,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<bool, Error> {
+        use std::os::unix::fs::MetadataExt;
+
+        // only index files with a valid UTF-8 path can be recorded in the checkpoint
+        let rel_path = img
+            .strip_prefix(self.base_path())
+            .ok()
+            .and_then(|path| path.to_str())
+            .map(str::to_string);
+
+        let file = match std::fs::File::open(img) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false), // vanished
+            Err(err) => bail!("can't open index {} - {}", img.to_string_lossy(), err),
+        };
+
+        let processed_bytes = match (&rel_path, file.metadata()) {
+            (Some(rel_path), Ok(metadata)) => {
+                checkpoint.processed_bytes(rel_path, metadata.mtime())
+            }
+            _ => None,
+        };
+
+        let index_data_bytes = status.index_data_bytes;
+        if let Some(bytes) = processed_bytes {
+            // already marked by the interrupted run we resume
+            status.index_file_count += 1;
+            status.index_data_bytes += bytes;
+        } else if let Ok(archive_type) = archive_type(img) {
+            if archive_type == ArchiveType::FixedIndex {
+                let index = FixedIndexReader::new(file).map_err(|e| {
+                    format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
+                })?;
+                self.index_mark_used_chunks(index, img, status, progress, worker)?;
+            } else if archive_type == ArchiveType::DynamicIndex {
+                let index = DynamicIndexReader::new(file).map_err(|e| {
+                    format_err!("can't read index '{}' - {}", img.to_string_lossy(), e)
+                })?;
+                self.index_mark_used_chunks(index, img, status, progress, worker)?;
+            }
+            if let Some(rel_path) = rel_path {
+                checkpoint.record(rel_path, status.index_data_bytes - index_data_bytes);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Compute the chunk deduplication statistics of a backup group.
+    ///
+    /// This reads all index files of the group to collect the referenced chunks, and then all
+    /// other index files of the datastore to find out which of them are shared with other groups.
+    pub fn group_dedup_stats(
+        &self,
+        group: &BackupGroup,
+        worker: &dyn WorkerTaskContext,
+    ) -> Result<GroupDedupStats, Error> {
+        let group_path = group.full_group_path();
+        let (group_images, other_images): (Vec<PathBuf>, Vec<PathBuf>) = self
+            .list_images()?
+            .into_iter()
+            .partition(|img| img.starts_with(&group_path));
+
+        let open_index = |img: &Path| -> Result<Option<Box<dyn IndexFile + Send>>, Error> {
+            let file = match std::fs::File::open(img) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => bail!("can't open index {img:?} - {err}"),
+            };
+            let index: Box<dyn IndexFile + Send> = match archive_type(img)? {
+                ArchiveType::FixedIndex => Box::new(FixedIndexReader::new(file)?),
+                ArchiveType::DynamicIndex => Box::new(DynamicIndexReader::new(file)?),
+                _ => return Ok(None),
+            };
+            Ok(Some(index))
+        };
+
+        let mut stats = GroupDedupStats {
+            computed: proxmox_time::epoch_i64(),
+            ..Default::default()
+        };
+
+        task_log!(
+            worker,
+            "reading {} index files of the group",
+            group_images.len()
+        );
+
+        // digest => (size, shared)
+        let mut chunks: HashMap<[u8; 32], (u64, bool)> = HashMap::new();
+        for img in group_images {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            let index = match open_index(&img) {
+                Ok(Some(index)) => index,
+                Ok(None) => continue,
+                Err(err) => bail!("can't read index {img:?} - {err}"),
+            };
+            stats.index_file_count += 1;
+            stats.referenced_bytes += index.index_bytes();
+
+            for pos in 0..index.index_count() {
+                let info = index.chunk_info(pos).unwrap();
+                chunks.entry(info.digest).or_insert((info.size(), false));
+            }
+        }
+
+        let image_count = other_images.len();
+        task_log!(worker, "checking {image_count} index files of other groups");
+
+        let mut last_percentage = 0;
+        for (i, img) in other_images.into_iter().enumerate() {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            match open_index(&img) {
+                Ok(Some(index)) => {
+                    for pos in 0..index.index_count() {
+                        let digest = index.index_digest(pos).unwrap();
+                        if let Some((_, shared)) = chunks.get_mut(digest) {
+                            *shared = true;
+                        }
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => task_warn!(worker, "skipping index {img:?} - {err}"),
+            }
+
+            let percentage = (i + 1) * 100 / image_count;
+            if percentage > last_percentage {
+                task_log!(
+                    worker,
+                    "checked {percentage}% ({} of {image_count} index files)",
+                    i + 1,
+                );
+                last_percentage = percentage;
+            }
+        }
+
+        stats.chunk_count = chunks.len();
+        for (size, shared) in chunks.into_values() {
+            if shared {
+                stats.shared_bytes += size;
+                stats.shared_chunks += 1;
+            } else {
+                stats.unique_bytes += size;
+                stats.unique_chunks += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
     pub fn last_gc_status(&self) -> GarbageCollectionStatus {
         self.inner.last_gc_status.lock().unwrap().clone()
     }
@@ -1025,6 +2030,16 @@ impl DataStore {
         self.inner.gc_mutex.try_lock().is_err()
     }
 
+    /// Keep garbage collection from starting, returns `None` if it is already running.
+    pub(crate) fn try_lock_gc(&self) -> Option<std::sync::MutexGuard<'_, ()>> {
+        self.inner.gc_mutex.try_lock().ok()
+    }
+
+    /// Returns the progress of the currently running garbage collection, if any.
+    pub fn garbage_collection_progress(&self) -> Option<GarbageCollectionProgress> {
+        GC_PROGRESS_MAP.lock().unwrap().get(self.name()).cloned()
+    }
+
     pub fn garbage_collection(
         &self,
         worker: &dyn WorkerTaskContext,
@@ -1036,6 +2051,8 @@ impl DataStore {
             // writer" information and thus no safe atime cutoff
             let _exclusive_lock = self.inner.chunk_store.try_exclusive_lock()?;
 
+            self.gc_atime_check(worker)?;
+
             let phase1_start_time = proxmox_time::epoch_i64();
             let oldest_writer = self
                 .inner
@@ -1045,21 +2062,108 @@ impl DataStore {
 
             let mut gc_status = GarbageCollectionStatus {
                 upid: Some(upid.to_string()),
+                gc_grace: Some(self.inner.gc_grace),
                 ..Default::default()
             };
 
+            let checkpoint_path = self.base_path().join(GC_CHECKPOINT_FILE_NAME);
+            let backup_user = pbs_config::backup_user()?;
+            let checkpoint_options = CreateOptions::new()
+                .perm(nix::sys::stat::Mode::from_bits_truncate(0o0644))
+                .owner(backup_user.uid)
+                .group(backup_user.gid);
+
+            let resumed =
+                match GcMarkCheckpoint::load(checkpoint_path.clone(), checkpoint_options.clone()) {
+                    Ok(Some(checkpoint))
+                        if checkpoint.resumable(oldest_writer, phase1_start_time) =>
+                    {
+                        task_log!(
+                            worker,
+                            "Resuming interrupted GC started at {}, {} index files already marked",
+                            proxmox_time::epoch_to_rfc3339_utc(checkpoint.start_time())?,
+                            checkpoint.processed_count(),
+                        );
+                        gc_status.resumed = true;
+                        Some(checkpoint)
+                    }
+                    Ok(Some(_)) => {
+                        task_log!(
+                            worker,
+                            "Discarding checkpoint of interrupted GC, atime cutoff changed"
+                        );
+                        None
+                    }
+                    Ok(None) => None,
+                    Err(err) => {
+                        task_warn!(worker, "{err}, starting over");
+                        None
+                    }
+                };
+            let mut checkpoint = resumed.unwrap_or_else(|| {
+                GcMarkCheckpoint::new(
+                    checkpoint_path.clone(),
+                    checkpoint_options,
+                    phase1_start_time,
+                    oldest_writer,
+                )
+            });
+
+            // when resuming, the cutoff must still cover the chunks marked by the interrupted run
+            let phase1_start_time = checkpoint.start_time();
+            let oldest_writer = checkpoint.oldest_writer();
+
+            match crate::trash::purge_trash(self, false) {
+                Ok(0) => (),
+                Ok(purged) => task_log!(worker, "Purged {purged} expired snapshots from the trash"),
+                Err(err) => task_warn!(worker, "purging the trash failed - {err}"),
+            }
+
+            let mut progress = GarbageCollectionProgressTracker::new(self.name());
+
             task_log!(worker, "Start GC phase1 (mark used chunks)");
 
-            self.mark_used_chunks(&mut gc_status, worker)?;
+            // the sweep phase must only run once all index files are marked, keep the progress
+            // so that the next run can resume from here
+            if let Err(err) =
+                self.mark_used_chunks(&mut gc_status, &mut progress, &mut checkpoint, worker)
+            {
+                if let Err(save_err) = checkpoint.save() {
+                    task_warn!(worker, "{save_err}");
+                }
+                return Err(err);
+            }
 
             task_log!(worker, "Start GC phase2 (sweep unused chunks)");
+            let min_atime = gc_atime_cutoff(
+                phase1_start_time,
+                oldest_writer,
+                self.inner.gc_grace as i64 * 60,
+            );
+            task_log!(
+                worker,
+                "Grace period: {} minutes, removing unused chunks last accessed before {}",
+                self.inner.gc_grace,
+                proxmox_time::epoch_to_rfc3339_utc(min_atime)?,
+            );
+            progress.start_phase(GarbageCollectionPhase::Sweep);
             self.inner.chunk_store.sweep_unused_chunks(
+                min_atime,
                 oldest_writer,
-                phase1_start_time,
                 &mut gc_status,
                 worker,
+                &mut |percentage, status| {
+                    progress.progress.removed_bytes = status.removed_bytes;
+                    progress.update(percentage, false);
+                },
             )?;
 
+            drop(progress);
+
+            if let Err(err) = GcMarkCheckpoint::remove(&checkpoint_path) {
+                task_warn!(worker, "{err}");
+            }
+
             task_log!(
                 worker,
                 "Removed garbage: {}",
@@ -1139,6 +2243,57 @@ impl DataStore {
         Ok(())
     }
 
+    /// Run the access time self-test of the chunk store, record and log its result.
+    ///
+    /// Fails if the check fails, unless disabled with the `gc-atime-check` tuning option.
+    fn gc_atime_check(&self, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+        let result = self.inner.chunk_store.check_fs_atime_updates();
+
+        let status = GcAtimeCheckStatus {
+            time: proxmox_time::epoch_i64(),
+            ok: result.is_ok(),
+            enforced: self.inner.gc_atime_check,
+            error: result.as_ref().err().map(|err| err.to_string()),
+        };
+
+        let backup_user = pbs_config::backup_user()?;
+        let options = CreateOptions::new()
+            .perm(nix::sys::stat::Mode::from_bits_truncate(0o0644))
+            .owner(backup_user.uid)
+            .group(backup_user.gid);
+        if let Err(err) = replace_file(
+            self.base_path().join(GC_ATIME_CHECK_FILE_NAME),
+            serde_json::to_string(&status)?.as_bytes(),
+            options,
+            false,
+        ) {
+            task_warn!(worker, "unable to save atime check result - {err}");
+        }
+
+        match result {
+            Ok(()) => {
+                task_log!(
+                    worker,
+                    "Access time check passed, atime updates work as required"
+                );
+                Ok(())
+            }
+            Err(err) if self.inner.gc_atime_check => bail!(
+                "Access time check failed - {err}\n\
+                Garbage collection relies on atime updates to find unused chunks and could \
+                remove chunks still in use. Check the mount options of the datastore file \
+                system, or set the 'gc-atime-check=false' tuning option to run it anyway."
+            ),
+            Err(err) => {
+                task_warn!(
+                    worker,
+                    "Access time check failed - {err}, continuing as 'gc-atime-check' is disabled",
+                );
+                Ok(())
+            }
+        }
+    }
+
     pub fn try_shared_chunk_store_lock(&self) -> Result<ProcessLockSharedGuard, Error> {
         self.inner.chunk_store.try_shared_lock()
     }
@@ -1147,6 +2302,18 @@ impl DataStore {
         self.inner.chunk_store.chunk_path(digest)
     }
 
+    /// The additional chunk directories the chunks of this datastore are sharded over.
+    pub fn extra_chunk_dirs(&self) -> Arc<Vec<PathBuf>> {
+        self.inner.chunk_store.extra_chunk_dirs()
+    }
+
+    /// Account a chunk read directly from its [`chunk_path`](Self::chunk_path) in the traffic
+    /// statistics, [`load_chunk`](Self::load_chunk) and [`read_raw_chunk`](Self::read_raw_chunk)
+    /// already do this on their own.
+    pub fn account_chunk_read(&self, size: u64) {
+        self.inner.chunk_store.account_chunk_read(size);
+    }
+
     pub fn cond_touch_chunk(&self, digest: &[u8; 32], assert_exists: bool) -> Result<bool, Error> {
         self.inner
             .chunk_store
@@ -1163,20 +2330,56 @@ impl DataStore {
     }
 
     pub fn load_chunk(&self, digest: &[u8; 32]) -> Result<DataBlob, Error> {
-        let (chunk_path, digest_str) = self.inner.chunk_store.chunk_path(digest);
-
-        proxmox_lang::try_block!({
-            let mut file = std::fs::File::open(&chunk_path)?;
-            DataBlob::load_from_reader(&mut file)
-        })
-        .map_err(|err| {
+        let blob = self.inner.chunk_store.load_chunk(digest).map_err(|err| {
             format_err!(
                 "store '{}', unable to load chunk '{}' - {}",
                 self.name(),
-                digest_str,
+                hex::encode(digest),
                 err,
             )
-        })
+        })?;
+
+        self.inner.chunk_store.account_chunk_read(blob.raw_size());
+
+        Ok(blob)
+    }
+
+    /// Read the raw data of the chunk with `digest` without blocking the async runtime, for
+    /// passing it on unmodified.
+    pub async fn read_raw_chunk(&self, digest: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let raw_data = self
+            .inner
+            .chunk_store
+            .read_raw_chunk(digest)
+            .await
+            .map_err(|err| {
+                format_err!(
+                    "store '{}', unable to read chunk '{}' - {err}",
+                    self.name(),
+                    hex::encode(digest),
+                )
+            })?;
+
+        self.inner
+            .chunk_store
+            .account_chunk_read(raw_data.len() as u64);
+
+        Ok(raw_data)
+    }
+
+    /// The storage backend of the chunks of this datastore.
+    pub fn chunk_backend(&self) -> ChunkBackend {
+        self.inner.chunk_store.backend()
+    }
+
+    /// Returns the chunk traffic of all datastores opened by this process.
+    pub fn chunk_traffic_stats() -> Vec<(String, ChunkTrafficStats)> {
+        DATASTORE_MAP
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, datastore)| (name.clone(), datastore.chunk_store.traffic_stats()))
+            .collect()
     }
 
     /// Updates the protection status of the specified snapshot.
@@ -1207,8 +2410,14 @@ impl DataStore {
         self.inner.verify_new
     }
 
-    /// returns a list of chunks sorted by their inode number on disk chunks that couldn't get
-    /// stat'ed are placed at the end of the list
+    /// How many hours deleted snapshots are kept in the trash, 0 if they are removed immediately.
+    pub fn trash_retention(&self) -> u64 {
+        self.inner.trash_retention
+    }
+
+    /// returns a list of chunks sorted according to the configured chunk order
+    ///
+    /// For inode order, chunks that couldn't get stat'ed are placed at the end of the list.
     pub fn get_chunks_in_order<F, A>(
         &self,
         index: &(dyn IndexFile + Send),
@@ -1221,6 +2430,13 @@ impl DataStore {
     {
         let index_count = index.index_count();
         let mut chunk_list = Vec::with_capacity(index_count);
+
+        let mut random_keys = Vec::new();
+        if self.inner.chunk_order == ChunkOrder::Random {
+            random_keys.resize(index_count * 8, 0u8);
+            openssl::rand::rand_bytes(&mut random_keys)?;
+        }
+
         use std::os::unix::fs::MetadataExt;
         for pos in 0..index_count {
             check_abort(pos)?;
@@ -1239,6 +2455,10 @@ impl DataStore {
                     }
                 }
                 ChunkOrder::None => 0,
+                ChunkOrder::Random => {
+                    let key = &random_keys[pos * 8..(pos + 1) * 8];
+                    u64::from_le_bytes(key.try_into().unwrap())
+                }
             };
 
             chunk_list.push((pos, ino));
@@ -1249,6 +2469,11 @@ impl DataStore {
             ChunkOrder::Inode => {
                 chunk_list.sort_unstable_by(|(_, ino_a), (_, ino_b)| ino_a.cmp(ino_b))
             }
+            // spreading the reads evenly helps wide RAID setups, where the inode order would
+            // keep hitting only a single disk at a time
+            ChunkOrder::Random => {
+                chunk_list.sort_unstable_by(|(_, key_a), (_, key_b)| key_a.cmp(key_b))
+            }
             ChunkOrder::None => {}
         }
 
@@ -1325,6 +2550,37 @@ impl DataStore {
     }
     */
 
+    /// The level of syncing done when writing into this datastore.
+    pub fn sync_level(&self) -> DatastoreFSyncLevel {
+        self.inner.sync_level
+    }
+
+    /// Ensure the files of a newly written snapshot reached the configured sync level.
+    ///
+    /// With [`DatastoreFSyncLevel::File`] the files themselves were already synced when they got
+    /// written, so only the directories leading to them are synced here. With
+    /// [`DatastoreFSyncLevel::Filesystem`] the whole filesystem is synced.
+    pub fn sync_snapshot(&self, backup_dir: &BackupDir) -> Result<(), Error> {
+        match self.inner.sync_level {
+            DatastoreFSyncLevel::None => Ok(()),
+            DatastoreFSyncLevel::File => {
+                let snapshot_path = backup_dir.full_path();
+                fsync_dir(&snapshot_path)?;
+                // persist a newly created group and its type directory as well
+                let mut path = snapshot_path.as_path();
+                for _ in 0..2 {
+                    match path.parent() {
+                        Some(parent) => path = parent,
+                        None => break,
+                    }
+                    fsync_dir(path)?;
+                }
+                Ok(())
+            }
+            DatastoreFSyncLevel::Filesystem => self.try_ensure_sync_level(),
+        }
+    }
+
     /// Syncs the filesystem of the datastore if 'sync_level' is set to
     /// [`DatastoreFSyncLevel::Filesystem`]. Uses syncfs(2).
     pub fn try_ensure_sync_level(&self) -> Result<(), Error> {
@@ -1342,6 +2598,8 @@ impl DataStore {
 
     /// Destroy a datastore. This requires that there are no active operations on the datastore.
     ///
+    /// Removing the data additionally requires the datastore to be in offline maintenance mode.
+    ///
     /// This is a synchronous operation and should be run in a worker-thread.
     pub fn destroy(
         name: &str,
@@ -1353,6 +2611,10 @@ impl DataStore {
         let (mut config, _digest) = pbs_config::datastore::config()?;
         let mut datastore_config: DataStoreConfig = config.lookup("datastore", name)?;
 
+        if destroy_data {
+            check_destroy_maintenance_mode(&datastore_config)?;
+        }
+
         datastore_config.maintenance_mode = Some("type=delete".to_string());
         config.set_data(name, "datastore", &datastore_config)?;
         pbs_config::datastore::save_config(&config)?;
@@ -1364,12 +2626,22 @@ impl DataStore {
             bail!("datastore is currently in use");
         }
 
-        let base = PathBuf::from(&datastore_config.path);
+        let base = if destroy_data {
+            checked_destroy_base_path(&datastore_config.path)?
+        } else {
+            PathBuf::from(&datastore_config.path)
+        };
 
         let mut ok = true;
         if destroy_data {
             let remove = |subdir, ok: &mut bool| {
-                if let Err(err) = std::fs::remove_dir_all(base.join(subdir)) {
+                let path = base.join(subdir);
+                if let Err(err) = check_path_below_base(&base, &path) {
+                    task_warn!(worker, "not removing {subdir:?} subdirectory: {err}");
+                    *ok = false;
+                    return;
+                }
+                if let Err(err) = std::fs::remove_dir_all(path) {
                     if err.kind() != io::ErrorKind::NotFound {
                         task_warn!(worker, "failed to remove {subdir:?} subdirectory: {err}");
                         *ok = false;
@@ -1378,23 +2650,59 @@ impl DataStore {
             };
 
             task_log!(worker, "Deleting datastore data...");
-            remove("ns", &mut ok); // ns first
-            remove("ct", &mut ok);
-            remove("vm", &mut ok);
-            remove("host", &mut ok);
+            for subdir in ["ns", "ct", "vm", "host"] {
+                // ns first
+                task_log!(worker, "Removing {subdir:?} subdirectory...");
+                remove(subdir, &mut ok);
+            }
 
             if ok {
-                if let Err(err) = std::fs::remove_file(base.join(".gc-status")) {
-                    if err.kind() != io::ErrorKind::NotFound {
-                        task_warn!(worker, "failed to remove .gc-status file: {err}");
-                        ok = false;
+                for file in [
+                    ".gc-status",
+                    GC_CHECKPOINT_FILE_NAME,
+                    GC_ATIME_CHECK_FILE_NAME,
+                    NAMESPACE_USAGE_FILE_NAME,
+                    NAMESPACE_PRUNE_OPTIONS_FILE_NAME,
+                    NAMESPACE_QUOTA_FILE_NAME,
+                    NAMESPACE_VERIFY_NEW_FILE_NAME,
+                ] {
+                    if let Err(err) = std::fs::remove_file(base.join(file)) {
+                        if err.kind() != io::ErrorKind::NotFound {
+                            task_warn!(worker, "failed to remove {file} file: {err}");
+                            ok = false;
+                        }
                     }
                 }
             }
 
             // chunks get removed last and only if the backups were successfully deleted
             if ok {
-                remove(".chunks", &mut ok);
+                let result = ChunkBackend::from_config(datastore_config.backend.as_deref())
+                    .and_then(|backend| match backend {
+                        ChunkBackend::S3(client) => remove_chunk_objects(&client, worker),
+                        ChunkBackend::Filesystem => Ok(()),
+                    });
+                if let Err(err) = result {
+                    task_warn!(worker, "failed to remove chunk objects: {err}");
+                    ok = false;
+                }
+            }
+            if ok {
+                if let Err(err) = remove_chunk_dirs(&base, worker) {
+                    task_warn!(worker, "failed to remove \".chunks\" subdirectory: {err}");
+                    ok = false;
+                }
+            }
+            if ok {
+                for chunk_dir in datastore_config.extra_chunk_dir.iter().flatten() {
+                    if let Err(err) = remove_extra_chunk_dir(Path::new(chunk_dir), worker) {
+                        task_warn!(
+                            worker,
+                            "failed to remove chunk directory {chunk_dir:?}: {err}"
+                        );
+                        ok = false;
+                    }
+                }
             }
         }
 
@@ -1446,3 +2754,488 @@ impl DataStore {
         Ok(())
     }
 }
+
+/// Read the result of the last GC access time check of the datastore at `base`, if any.
+pub fn read_gc_atime_check_status(base: &Path) -> Result<Option<GcAtimeCheckStatus>, Error> {
+    match file_read_optional_string(base.join(GC_ATIME_CHECK_FILE_NAME))? {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Data may only be destroyed on datastores in offline maintenance mode. The delete mode is
+/// accepted too, so that an interrupted removal can be retried.
+pub fn check_destroy_maintenance_mode(config: &DataStoreConfig) -> Result<(), Error> {
+    match config
+        .get_maintenance_mode()
+        .map(|mode| mode.maintenance_type())
+    {
+        Some(MaintenanceType::Offline | MaintenanceType::Delete) => Ok(()),
+        _ => bail!(
+            "datastore '{}' must be in offline maintenance mode to destroy its data",
+            config.name
+        ),
+    }
+}
+
+// Resolve the configured datastore path and make sure it looks like a chunk store.
+fn checked_destroy_base_path(path: &str) -> Result<PathBuf, Error> {
+    let base = std::fs::canonicalize(path)
+        .map_err(|err| format_err!("unable to resolve datastore path {path:?} - {err}"))?;
+
+    if base.parent().is_none() {
+        bail!("refusing to destroy data in {base:?}");
+    }
+
+    // compare component-wise, so that a trailing slash in the configured path is accepted
+    let configured: PathBuf = Path::new(path).components().collect();
+    if base != configured {
+        bail!("datastore path {path:?} resolves to {base:?}, refusing to destroy data");
+    }
+
+    if !base.join(".chunks").is_dir() {
+        bail!("{base:?} does not contain a chunk store, refusing to destroy data");
+    }
+
+    Ok(base)
+}
+
+// Make sure `path` is a direct child of `base`, even after resolving symlinks.
+fn check_path_below_base(base: &Path, path: &Path) -> Result<(), Error> {
+    let resolved = match std::fs::canonicalize(path) {
+        Ok(resolved) => resolved,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => bail!("unable to resolve {path:?} - {err}"),
+    };
+
+    if resolved.parent() != Some(base) {
+        bail!("{path:?} resolves to {resolved:?}, which is outside of the datastore");
+    }
+
+    Ok(())
+}
+
+// Remove the chunk store directory by directory to be able to log progress, this may take hours
+// on large datastores.
+fn remove_chunk_dirs(base: &Path, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+    let chunk_dir = base.join(".chunks");
+    check_path_below_base(base, &chunk_dir)?;
+
+    let subdirs = match std::fs::read_dir(&chunk_dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    task_log!(worker, "Removing chunks...");
+    remove_chunk_subdirs(subdirs, worker)?;
+
+    std::fs::remove_dir(&chunk_dir)?;
+
+    Ok(())
+}
+
+/// Remove the chunk subdirectories of an additional chunk directory, the directory itself is
+/// kept as it might be a mount point.
+fn remove_extra_chunk_dir(chunk_dir: &Path, worker: &dyn WorkerTaskContext) -> Result<(), Error> {
+    let subdirs = match std::fs::read_dir(chunk_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => {
+                    let name = entry.file_name();
+                    let name = name.to_str()?;
+                    let is_chunk_subdir =
+                        name.len() == 4 && name.bytes().all(|b| b.is_ascii_hexdigit());
+                    (is_chunk_subdir || name == ".gc-atime-check").then(|| Ok(entry.path()))
+                }
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<PathBuf>, _>>()?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    task_log!(worker, "Removing chunks in {chunk_dir:?}...");
+    remove_chunk_subdirs(subdirs, worker)
+}
+
+fn remove_chunk_subdirs(
+    mut subdirs: Vec<PathBuf>,
+    worker: &dyn WorkerTaskContext,
+) -> Result<(), Error> {
+    subdirs.sort_unstable();
+
+    let total = subdirs.len();
+    let mut last_percentage = 0;
+    for (done, subdir) in subdirs.into_iter().enumerate() {
+        worker.check_abort()?;
+
+        if subdir.is_dir() {
+            std::fs::remove_dir_all(&subdir)?;
+        } else {
+            std::fs::remove_file(&subdir)?;
+        }
+
+        let percentage = (done + 1) * 100 / total;
+        if percentage != last_percentage {
+            task_log!(worker, "removed {percentage}% of chunk directories");
+            last_percentage = percentage;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pbs_api_types::CryptMode;
+
+    use crate::data_blob::DataChunkBuilder;
+    use crate::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+
+    fn test_datastore(
+        path: &Path,
+        sync_level: DatastoreFSyncLevel,
+    ) -> Result<Arc<DataStore>, Error> {
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let chunk_store = ChunkStore::create("test", path, user.uid, user.gid, None, sync_level)?;
+
+        let mut config =
+            DataStoreConfig::new("test".to_string(), path.to_string_lossy().into_owned());
+        config.tuning = Some(format!("sync-level={sync_level}"));
+
+        let inner = DataStore::with_store_and_config(Arc::new(chunk_store), config, None)?;
+        Ok(Arc::new(DataStore {
+            inner: Arc::new(inner),
+            operation: None,
+            operation_owner: Mutex::new(None),
+        }))
+    }
+
+    #[test]
+    fn test_snapshot_sync_levels() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-sync-level");
+
+        for sync_level in [
+            DatastoreFSyncLevel::None,
+            DatastoreFSyncLevel::File,
+            DatastoreFSyncLevel::Filesystem,
+        ] {
+            let _ = std::fs::remove_dir_all(&path);
+
+            let datastore = test_datastore(&path, sync_level)?;
+            assert_eq!(datastore.sync_level(), sync_level);
+
+            let backup_dir = datastore.backup_dir_from_parts(
+                BackupNamespace::root(),
+                BackupType::Host,
+                "test".to_string(),
+                0,
+            )?;
+            std::fs::create_dir_all(backup_dir.full_path())?;
+
+            let (chunk, digest) = DataChunkBuilder::new(b"sync level test").build()?;
+            datastore.insert_chunk(&chunk, &digest)?;
+
+            let mut index_path = backup_dir.relative_path();
+            index_path.push("test.didx");
+            let mut writer = datastore.create_dynamic_writer(&index_path)?;
+            writer.add_chunk(15, &digest)?;
+            let csum = writer.close()?;
+
+            let mut manifest = BackupManifest::new(backup_dir.dir().clone());
+            manifest.add_file("test.didx".to_string(), 15, csum, CryptMode::None)?;
+            let manifest = DataBlob::encode(manifest.to_string(None)?.as_bytes(), None, true)?;
+            let mut manifest_path = backup_dir.full_path();
+            manifest_path.push(MANIFEST_BLOB_NAME);
+            replace_file(
+                &manifest_path,
+                manifest.raw_data(),
+                CreateOptions::new(),
+                sync_level == DatastoreFSyncLevel::File,
+            )?;
+
+            datastore.sync_snapshot(&backup_dir)?;
+
+            let (manifest, _) = backup_dir.load_manifest()?;
+            let index = datastore.open_dynamic_reader(&index_path)?;
+            let (index_csum, size) = index.compute_csum();
+            manifest.verify_file("test.didx", &index_csum, size)?;
+
+            assert_eq!(index.index_count(), 1);
+            assert_eq!(index.index_digest(0), Some(&digest));
+            assert!(datastore.stat_chunk(&digest)?.is_file());
+        }
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_snapshot_session() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-duplicate-session");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let datastore = test_datastore(&path, DatastoreFSyncLevel::None)?;
+        let ns = BackupNamespace::root();
+        let dir = pbs_api_types::BackupDir::from((BackupType::Host, "test".to_string(), 0));
+        let auth_id: Authid = "root@pam".parse()?;
+
+        // the first session holds the group and the snapshot lock while writing
+        let (_, group_guard) = datastore.create_locked_backup_group(&ns, &dir.group, &auth_id)?;
+        let (_, is_new, snap_guard) = datastore.create_locked_backup_dir(&ns, &dir)?;
+        assert!(is_new);
+        std::fs::write(
+            datastore.snapshot_path(&ns, &dir).join("root.pxar.didx"),
+            b"",
+        )?;
+
+        // a second session for the same snapshot fails on both locks, without touching it
+        let err = datastore
+            .create_locked_backup_group(&ns, &dir.group, &auth_id)
+            .err()
+            .unwrap();
+        assert!(crate::lock_tracking::is_lock_contention(&err));
+        let err = datastore.create_locked_backup_dir(&ns, &dir).err().unwrap();
+        assert!(crate::lock_tracking::is_lock_contention(&err));
+        assert!(datastore
+            .snapshot_path(&ns, &dir)
+            .join("root.pxar.didx")
+            .exists());
+
+        // once the first session died, the next one gets the locks and cleans up
+        drop(snap_guard);
+        drop(group_guard);
+        let (_, _group_guard) = datastore.create_locked_backup_group(&ns, &dir.group, &auth_id)?;
+        let (_, is_new, _snap_guard) = datastore.create_locked_backup_dir(&ns, &dir)?;
+        assert!(!is_new);
+        assert_eq!(
+            datastore.remove_partial_backup_files(&ns, &dir)?,
+            ["root.pxar.didx"]
+        );
+        assert_eq!(
+            std::fs::read_dir(datastore.snapshot_path(&ns, &dir))?.count(),
+            0
+        );
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    struct TestWorker;
+
+    impl WorkerTaskContext for TestWorker {
+        fn abort_requested(&self) -> bool {
+            false
+        }
+
+        fn shutdown_requested(&self) -> bool {
+            false
+        }
+
+        fn log(&self, _level: log::Level, _message: &std::fmt::Arguments) {}
+    }
+
+    /// Create a snapshot with a single dynamic index referencing a single chunk.
+    fn create_test_snapshot(
+        datastore: &Arc<DataStore>,
+        backup_time: i64,
+    ) -> Result<BackupDir, Error> {
+        let ns = BackupNamespace::root();
+        let group = pbs_api_types::BackupGroup::new(BackupType::Host, "test");
+        datastore.create_locked_backup_group(&ns, &group, Authid::root_auth_id())?;
+
+        let backup_dir = datastore.backup_dir_from_parts(
+            ns,
+            BackupType::Host,
+            "test".to_string(),
+            backup_time,
+        )?;
+        std::fs::create_dir_all(backup_dir.full_path())?;
+
+        let (chunk, digest) = DataChunkBuilder::new(b"trash test").build()?;
+        datastore.insert_chunk(&chunk, &digest)?;
+
+        let mut index_path = backup_dir.relative_path();
+        index_path.push("test.didx");
+        let mut writer = datastore.create_dynamic_writer(&index_path)?;
+        writer.add_chunk(10, &digest)?;
+        writer.close()?;
+
+        Ok(backup_dir)
+    }
+
+    fn mark_test_images(datastore: &DataStore, image_list: Vec<PathBuf>) -> Result<u64, Error> {
+        let mut status = GarbageCollectionStatus::default();
+        let mut progress = GarbageCollectionProgressTracker::new(datastore.name());
+        let mut checkpoint = GcMarkCheckpoint::new(
+            datastore.base_path().join(GC_CHECKPOINT_FILE_NAME),
+            CreateOptions::new(),
+            0,
+            0,
+        );
+        datastore.mark_image_list(
+            image_list,
+            &mut status,
+            &mut progress,
+            &mut checkpoint,
+            &TestWorker,
+        )?;
+        Ok(status.index_file_count)
+    }
+
+    #[test]
+    fn test_gc_marks_snapshot_trashed_during_mark_phase() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-gc-trash");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let datastore = test_datastore(&path, DatastoreFSyncLevel::None)?;
+        let snapshot = create_test_snapshot(&datastore, 0)?;
+
+        // the snapshot is moved to the trash after the index files were listed
+        let image_list = datastore.list_images()?;
+        assert_eq!(image_list.len(), 1);
+        crate::trash::move_to_trash(&snapshot)?;
+        assert!(!image_list[0].exists());
+        assert_eq!(mark_test_images(&datastore, image_list)?, 1);
+
+        // index files listed in the trash in the first place are not marked twice
+        let image_list = datastore.list_images()?;
+        assert_eq!(image_list.len(), 1);
+        assert_eq!(mark_test_images(&datastore, image_list)?, 1);
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_group_with_snapshot_in_use() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-move-group");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let datastore = test_datastore(&path, DatastoreFSyncLevel::None)?;
+        let snapshot = create_test_snapshot(&datastore, 0)?;
+        let group = snapshot.dir().group.clone();
+        let source_ns = BackupNamespace::root();
+        let target_ns = datastore.create_namespace(&source_ns, "target".to_string())?;
+
+        // a reader holds the snapshot
+        let reader_guard = crate::lock_tracking::lock_dir_noblock_shared(
+            &snapshot.full_path(),
+            "snapshot",
+            "in use",
+        )?;
+        let err = datastore
+            .move_backup_group(&source_ns, &target_ns, &group)
+            .unwrap_err();
+        assert!(err.to_string().contains("snapshot in use"), "{err}");
+        assert!(snapshot.full_path().join("test.didx").exists());
+        drop(reader_guard);
+
+        datastore.move_backup_group(&source_ns, &target_ns, &group)?;
+        assert!(!snapshot.full_path().exists());
+        assert!(datastore
+            .snapshot_path(&target_ns, snapshot.dir())
+            .join("test.didx")
+            .exists());
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_namespace_prune_options() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-ns-prune-options");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let datastore = test_datastore(&path, DatastoreFSyncLevel::None)?;
+        let root = BackupNamespace::root();
+        let a = datastore.create_namespace(&root, "a".to_string())?;
+        let b = datastore.create_namespace(&a, "b".to_string())?;
+        let c = datastore.create_namespace(&b, "c".to_string())?;
+
+        // written directly, storing them changes the owner to the backup user
+        let keep = |ns: &BackupNamespace, keep_last: u64| -> Result<KeepOptions, Error> {
+            let options = KeepOptions {
+                keep_last: Some(keep_last),
+                ..Default::default()
+            };
+            std::fs::write(
+                datastore.namespace_prune_options_path(ns),
+                serde_json::to_string(&options)?,
+            )?;
+            Ok(options)
+        };
+
+        assert!(datastore.lookup_namespace_prune_options(&c)?.is_none());
+
+        let root_options = keep(&root, 1)?;
+        let a_options = keep(&a, 2)?;
+        assert!(
+            datastore.lookup_namespace_prune_options(&c)? == Some((a.clone(), a_options.clone()))
+        );
+        assert!(datastore.lookup_namespace_prune_options(&a)? == Some((a.clone(), a_options)));
+
+        // up to the root namespace once the closer options are removed
+        datastore.set_namespace_prune_options(&a, None)?;
+        assert!(
+            datastore.lookup_namespace_prune_options(&c)?
+                == Some((root.clone(), root_options.clone()))
+        );
+        assert!(
+            datastore.lookup_namespace_prune_options(&root)? == Some((root.clone(), root_options))
+        );
+
+        // options keeping nothing would silently keep everything below
+        let err = datastore
+            .set_namespace_prune_options(&b, Some(&KeepOptions::default()))
+            .unwrap_err();
+        assert!(err.to_string().contains("no keep option set"), "{err}");
+        assert!(datastore.namespace_prune_options(&b)?.is_none());
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_from_trash_blocks_gc() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-restore-trash");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let datastore = test_datastore(&path, DatastoreFSyncLevel::None)?;
+        let snapshot = create_test_snapshot(&datastore, 0)?;
+        crate::trash::move_to_trash(&snapshot)?;
+        assert_eq!(crate::trash::list_trash(&datastore)?.len(), 1);
+
+        let gc_guard = datastore.try_lock_gc().unwrap();
+        assert!(
+            crate::trash::restore_from_trash(&datastore, snapshot.backup_ns(), snapshot.dir())
+                .is_err()
+        );
+        assert!(!snapshot.full_path().exists());
+        drop(gc_guard);
+
+        crate::trash::restore_from_trash(&datastore, snapshot.backup_ns(), snapshot.dir())?;
+        assert!(snapshot.full_path().join("test.didx").exists());
+        assert!(crate::trash::list_trash(&datastore)?.is_empty());
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+}
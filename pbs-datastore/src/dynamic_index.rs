@@ -15,13 +15,14 @@ use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_uuid::Uuid;
 use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
+use pbs_api_types::DatastoreFSyncLevel;
 use pbs_tools::lru_cache::LruCache;
 
 use crate::chunk_stat::ChunkStat;
-use crate::chunk_store::ChunkStore;
+use crate::chunk_store::{fsync_dir, ChunkStore};
 use crate::data_blob::{DataBlob, DataChunkBuilder};
 use crate::file_formats;
-use crate::index::{ChunkReadInfo, IndexFile};
+use crate::index::{check_index_tmp_file, index_tmp_path, ChunkReadInfo, IndexFile};
 use crate::read_chunk::ReadChunk;
 use crate::Chunker;
 
@@ -293,12 +294,11 @@ impl DynamicIndexWriter {
         let shared_lock = store.try_shared_lock()?;
 
         let full_path = store.relative_path(path);
-        let mut tmp_path = full_path.clone();
-        tmp_path.set_extension("tmp_didx");
+        let uuid = Uuid::generate();
+        let tmp_path = index_tmp_path(&full_path, "tmp_didx", uuid.as_bytes());
 
         let file = std::fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
+            .create_new(true)
             .read(true)
             .write(true)
             .open(&tmp_path)?;
@@ -307,8 +307,6 @@ impl DynamicIndexWriter {
 
         let ctime = proxmox_time::epoch_i64();
 
-        let uuid = Uuid::generate();
-
         let mut header = DynamicIndexHeader::zeroed();
         header.magic = file_formats::DYNAMIC_SIZED_CHUNK_INDEX_1_0;
         header.ctime = i64::to_le(ctime);
@@ -357,10 +355,22 @@ impl DynamicIndexWriter {
         self.writer.write_all(&index_csum)?;
         self.writer.flush()?;
 
+        let sync_file = self.store.sync_level() == DatastoreFSyncLevel::File;
+        if sync_file {
+            self.writer.get_ref().sync_all()?;
+        }
+
+        check_index_tmp_file(self.writer.get_ref(), &self.tmp_filename)?;
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
 
+        if sync_file {
+            if let Some(parent) = self.filename.parent() {
+                fsync_dir(parent)?;
+            }
+        }
+
         Ok(index_csum)
     }
 
@@ -732,3 +742,62 @@ impl<R: ReadChunk> ReadAt for LocalDynamicReadAt<R> {
         panic!("LocalDynamicReadAt::start_read_at returned Pending");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_writer_sessions() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-dynamic-index");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let store = Arc::new(ChunkStore::create(
+            "test",
+            &path,
+            user.uid,
+            user.gid,
+            None,
+            DatastoreFSyncLevel::None,
+        )?);
+
+        let snapshot = Path::new("host/test/2024-01-01T00:00:00Z");
+        std::fs::create_dir_all(path.join(snapshot))?;
+        let archive = snapshot.join("root.pxar.didx");
+
+        // two sessions started for the same snapshot write the same archive
+        let mut first = DynamicIndexWriter::create(Arc::clone(&store), &archive)?;
+        let mut second = DynamicIndexWriter::create(Arc::clone(&store), &archive)?;
+        assert_ne!(first.tmp_filename, second.tmp_filename);
+
+        first.add_chunk(100, &[1u8; 32])?;
+        second.add_chunk(200, &[2u8; 32])?;
+        second.add_chunk(300, &[2u8; 32])?;
+
+        // finishing one session must leave the file of the other one alone
+        second.close()?;
+        assert!(first.tmp_filename.exists());
+        first.close()?;
+
+        // the result is the complete index of one session, never a mix of both
+        let index = DynamicIndexReader::open(&path.join(&archive))?;
+        assert_eq!(index.index_count(), 1);
+        assert_eq!(index.index_digest(0), Some(&[1u8; 32]));
+
+        // a session must not rename a temporary file it did not create
+        let mut third = DynamicIndexWriter::create(Arc::clone(&store), &archive)?;
+        let tmp_filename = third.tmp_filename.clone();
+        std::fs::remove_file(&tmp_filename)?;
+        std::fs::write(&tmp_filename, b"foreign")?;
+        assert!(third.close().is_err());
+        drop(third);
+
+        let index = DynamicIndexReader::open(&path.join(&archive))?;
+        assert_eq!(index.index_digest(0), Some(&[1u8; 32]));
+
+        let _ = std::fs::remove_dir_all(&path);
+        Ok(())
+    }
+}
@@ -11,11 +11,13 @@ use proxmox_io::ReadExt;
 use proxmox_sys::process_locker::ProcessLockSharedGuard;
 use proxmox_uuid::Uuid;
 
+use pbs_api_types::DatastoreFSyncLevel;
+
 use crate::chunk_stat::ChunkStat;
-use crate::chunk_store::ChunkStore;
+use crate::chunk_store::{fsync_dir, ChunkStore};
 use crate::data_blob::ChunkInfo;
 use crate::file_formats;
-use crate::index::{ChunkReadInfo, IndexFile};
+use crate::index::{check_index_tmp_file, index_tmp_path, ChunkReadInfo, IndexFile};
 
 /// Header format definition for fixed index files (`.fidx`)
 #[repr(C)]
@@ -251,12 +253,11 @@ impl FixedIndexWriter {
         let shared_lock = store.try_shared_lock()?;
 
         let full_path = store.relative_path(path);
-        let mut tmp_path = full_path.clone();
-        tmp_path.set_extension("tmp_fidx");
+        let uuid = Uuid::generate();
+        let tmp_path = index_tmp_path(&full_path, "tmp_fidx", uuid.as_bytes());
 
         let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .truncate(true)
+            .create_new(true)
             .read(true)
             .write(true)
             .open(&tmp_path)?;
@@ -270,8 +271,6 @@ impl FixedIndexWriter {
 
         let ctime = proxmox_time::epoch_i64();
 
-        let uuid = Uuid::generate();
-
         let buffer = vec![0u8; header_size];
         let header = unsafe { &mut *(buffer.as_ptr() as *mut FixedIndexHeader) };
 
@@ -354,10 +353,22 @@ impl FixedIndexWriter {
         self.file.write_all(&index_csum)?;
         self.file.flush()?;
 
+        let sync_file = self.store.sync_level() == DatastoreFSyncLevel::File;
+        if sync_file {
+            self.file.sync_all()?;
+        }
+
+        check_index_tmp_file(&self.file, &self.tmp_filename)?;
         if let Err(err) = std::fs::rename(&self.tmp_filename, &self.filename) {
             bail!("Atomic rename file {:?} failed - {}", self.filename, err);
         }
 
+        if sync_file {
+            if let Some(parent) = self.filename.parent() {
+                fsync_dir(parent)?;
+            }
+        }
+
         Ok(index_csum)
     }
 
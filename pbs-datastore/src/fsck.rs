@@ -0,0 +1,322 @@
+//! Offline consistency check of a datastore
+//!
+//! Checks the index files of a datastore and the chunks they reference directly on disk, without
+//! the datastore configuration or the API. The chunk store is only read, and a shared lock is
+//! held during the check, like for a running backup, so that garbage collection does not remove
+//! chunks of newly written snapshots in the meantime.
+
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::Serialize;
+
+use pbs_api_types::DatastoreFSyncLevel;
+
+use crate::chunk_store::ChunkStore;
+use crate::dynamic_index::DynamicIndexReader;
+use crate::file_formats::{
+    DataBlobHeader, EncryptedDataBlobHeader, COMPRESSED_BLOB_MAGIC_1_0, ENCRYPTED_BLOB_MAGIC_1_0,
+    ENCR_COMPR_BLOB_MAGIC_1_0, UNCOMPRESSED_BLOB_MAGIC_1_0,
+};
+use crate::fixed_index::FixedIndexReader;
+use crate::index::IndexFile;
+use crate::DataBlob;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// A missing or corrupt file found by the check.
+pub struct FsckProblem {
+    /// Path of the index file or chunk, relative to the datastore.
+    pub path: String,
+    /// Description of the problem.
+    pub issue: String,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Result of a datastore check.
+pub struct FsckReport {
+    /// Whether the contents of the chunks were verified.
+    pub deep: bool,
+    /// Number of checked index files.
+    pub index_files: usize,
+    /// Number of distinct checked chunks.
+    pub chunks: usize,
+    /// Missing or corrupt index files and chunks.
+    pub problems: Vec<FsckProblem>,
+}
+
+/// Check all index files below `path` and the chunks they reference.
+///
+/// Verifies the checksum of each index and that every referenced chunk exists with a size
+/// matching the index. With `deep`, the chunks are read and their CRC, and for unencrypted
+/// chunks also their digest, is verified. Chunks of a sharded chunk store are looked up in
+/// `extra_chunk_dirs` too.
+pub fn check_datastore(
+    path: &Path,
+    extra_chunk_dirs: &[PathBuf],
+    deep: bool,
+) -> Result<FsckReport, Error> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|err| format_err!("unable to access datastore path {path:?} - {err}"))?;
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "fsck".to_string());
+    let chunk_store = ChunkStore::open(&name, &path, DatastoreFSyncLevel::None)?;
+    chunk_store.set_extra_chunk_dirs(extra_chunk_dirs.to_vec())?;
+    let _lock = chunk_store.try_shared_lock()?;
+
+    let mut report = FsckReport {
+        deep,
+        ..Default::default()
+    };
+    // shared chunks are only checked once
+    let mut checked: HashSet<[u8; 32]> = HashSet::new();
+
+    for index_path in list_index_files(&path)? {
+        let index_name = relative_path(&path, &index_path);
+
+        let index = match open_index(&index_path) {
+            Ok(Some(index)) => index,
+            Ok(None) => continue, // removed in the meantime, e.g. by prune
+            Err(err) => {
+                report.problems.push(FsckProblem {
+                    path: index_name,
+                    issue: format!("unable to read index - {err}"),
+                });
+                continue;
+            }
+        };
+        report.index_files += 1;
+
+        if let Err(err) = index.verify_csum() {
+            report.problems.push(FsckProblem {
+                path: index_name.clone(),
+                issue: err.to_string(),
+            });
+        }
+
+        let index = index.as_index();
+        for pos in 0..index.index_count() {
+            let info = index.chunk_info(pos).unwrap();
+            let size = info.size();
+
+            if checked.contains(&info.digest) {
+                continue;
+            }
+
+            if let Err(err) = check_chunk(&chunk_store, &info.digest, size, deep) {
+                // the snapshot might have been removed since the index was opened
+                if !index_path.exists() {
+                    continue;
+                }
+                let (chunk_path, _digest_str) = chunk_store.chunk_path(&info.digest);
+                report.problems.push(FsckProblem {
+                    path: relative_path(&path, &chunk_path),
+                    issue: format!("{err} (referenced by {index_name})"),
+                });
+            }
+            checked.insert(info.digest);
+        }
+    }
+
+    report.chunks = checked.len();
+
+    Ok(report)
+}
+
+enum Index {
+    Fixed(FixedIndexReader),
+    Dynamic(DynamicIndexReader),
+}
+
+impl Index {
+    fn as_index(&self) -> &dyn IndexFile {
+        match self {
+            Index::Fixed(index) => index,
+            Index::Dynamic(index) => index,
+        }
+    }
+
+    fn verify_csum(&self) -> Result<(), Error> {
+        let expected = match self {
+            Index::Fixed(index) => &index.index_csum,
+            Index::Dynamic(index) => &index.index_csum,
+        };
+        let (csum, _size) = self.as_index().compute_csum();
+        if &csum != expected {
+            bail!("index checksum mismatch");
+        }
+        Ok(())
+    }
+}
+
+// returns `None` if the index does not exist (anymore)
+fn open_index(path: &Path) -> Result<Option<Index>, Error> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let index = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("fidx") => Index::Fixed(FixedIndexReader::new(file)?),
+        Some("didx") => Index::Dynamic(DynamicIndexReader::new(file)?),
+        _ => bail!("unknown index type"),
+    };
+
+    Ok(Some(index))
+}
+
+fn list_index_files(base: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut list = Vec::new();
+
+    let walker = walkdir::WalkDir::new(base)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != ".chunks");
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // snapshots can be removed while walking the datastore
+            Err(err)
+                if err.io_error().map(|err| err.kind()) == Some(std::io::ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            Err(err) => bail!("unable to list datastore contents - {err}"),
+        };
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        match entry.path().extension().and_then(|ext| ext.to_str()) {
+            Some("fidx") | Some("didx") => list.push(entry.into_path()),
+            _ => (),
+        }
+    }
+
+    Ok(list)
+}
+
+fn relative_path(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn check_chunk(
+    chunk_store: &ChunkStore,
+    digest: &[u8; 32],
+    size: u64,
+    deep: bool,
+) -> Result<(), Error> {
+    let (chunk_path, _digest_str) = chunk_store.chunk_path(digest);
+
+    let mut file = match std::fs::File::open(&chunk_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => bail!("chunk is missing"),
+        Err(err) => bail!("unable to open chunk - {err}"),
+    };
+    let file_size = file.metadata()?.len();
+
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic)
+        .map_err(|err| format_err!("unable to read chunk header - {err}"))?;
+
+    let (header_size, compressed) = match magic {
+        UNCOMPRESSED_BLOB_MAGIC_1_0 => (std::mem::size_of::<DataBlobHeader>(), false),
+        COMPRESSED_BLOB_MAGIC_1_0 => (std::mem::size_of::<DataBlobHeader>(), true),
+        ENCRYPTED_BLOB_MAGIC_1_0 => (std::mem::size_of::<EncryptedDataBlobHeader>(), false),
+        ENCR_COMPR_BLOB_MAGIC_1_0 => (std::mem::size_of::<EncryptedDataBlobHeader>(), true),
+        _ => bail!("chunk has an unknown magic number"),
+    };
+
+    // compressed data is only stored if it is smaller than the original
+    let expected_size = header_size as u64 + size;
+    if (compressed && file_size >= expected_size) || (!compressed && file_size != expected_size) {
+        bail!("chunk has wrong size ({file_size} bytes, expected {expected_size} bytes)");
+    }
+
+    if deep {
+        let mut data = magic.to_vec();
+        file.read_to_end(&mut data)?;
+        let blob = DataBlob::from_raw(data)?;
+        blob.verify_crc()?;
+        blob.verify_unencrypted(size as usize, digest)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::data_blob::DataChunkBuilder;
+    use crate::dynamic_index::DynamicIndexWriter;
+
+    #[test]
+    fn test_check_datastore() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-fsck");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let store = Arc::new(ChunkStore::create(
+            "test",
+            &path,
+            user.uid,
+            user.gid,
+            None,
+            DatastoreFSyncLevel::None,
+        )?);
+
+        let mut digests = Vec::new();
+        for data in [&b"first chunk"[..], &b"second chunk"[..]] {
+            let (chunk, digest) = DataChunkBuilder::new(data).build()?;
+            store.insert_chunk(&chunk, &digest)?;
+            digests.push((digest, data.len() as u64));
+        }
+
+        std::fs::create_dir_all(path.join("host/test/2024-01-01T00:00:00Z"))?;
+        let mut writer = DynamicIndexWriter::create(
+            store.clone(),
+            Path::new("host/test/2024-01-01T00:00:00Z/test.didx"),
+        )?;
+        let mut offset = 0;
+        for (digest, size) in digests.iter() {
+            offset += size;
+            writer.add_chunk(offset, digest)?;
+        }
+        writer.close()?;
+        drop(store);
+
+        let report = check_datastore(&path, &[], true)?;
+        assert_eq!(report.index_files, 1);
+        assert_eq!(report.chunks, 2);
+        assert!(report.problems.is_empty());
+
+        let digest_str = hex::encode(digests[1].0);
+        std::fs::remove_file(
+            path.join(".chunks")
+                .join(&digest_str[..4])
+                .join(&digest_str),
+        )?;
+
+        let report = check_datastore(&path, &[], false)?;
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].issue.starts_with("chunk is missing"));
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+}
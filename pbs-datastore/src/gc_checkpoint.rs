@@ -0,0 +1,265 @@
+//! Checkpoint of the garbage collection mark phase
+//!
+//! Marking all chunks referenced by the index files of a big datastore can take many hours. To
+//! avoid starting over after an interruption (for example a node reboot), the mark phase
+//! periodically records which index files it already processed, together with the values the
+//! sweep phase derives its atime cutoff from. A later garbage collection can then resume from
+//! that checkpoint, as long as this cannot lead to removing chunks still in use:
+//!
+//! * The sweep phase keeps using the start time of the interrupted run, so all chunks touched
+//!   back then are still newer than the cutoff.
+//! * Index files which appeared since then are not recorded and thus get marked in the resumed
+//!   run, same as files recorded as processed but modified after the checkpointed start time.
+//! * The checkpoint is discarded if there is an active writer older than the recorded one, as
+//!   that would move the cutoff.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+
+/// File name of the mark phase checkpoint, stored in the datastore base directory
+pub(crate) const GC_CHECKPOINT_FILE_NAME: &str = ".gc-checkpoint";
+
+/// Minimal interval between two checkpoint writes during the mark phase, in seconds.
+const GC_CHECKPOINT_INTERVAL: i64 = 5 * 60;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct GcMarkState {
+    /// Start time of the mark phase the checkpoint belongs to
+    start_time: i64,
+    /// Start time of the oldest active writer at the start of the mark phase
+    oldest_writer: i64,
+    /// Index files whose chunks are marked, relative to the datastore base, with their size
+    processed: HashMap<String, u64>,
+}
+
+/// Progress of a garbage collection mark phase, persisted in the datastore.
+pub(crate) struct GcMarkCheckpoint {
+    path: PathBuf,
+    options: CreateOptions,
+    last_save: i64,
+    state: GcMarkState,
+}
+
+impl GcMarkCheckpoint {
+    /// Create a new, empty checkpoint for a mark phase started at `start_time`.
+    pub fn new(path: PathBuf, options: CreateOptions, start_time: i64, oldest_writer: i64) -> Self {
+        Self {
+            path,
+            options,
+            last_save: start_time,
+            state: GcMarkState {
+                start_time,
+                oldest_writer,
+                processed: HashMap::new(),
+            },
+        }
+    }
+
+    /// Load the checkpoint left over by an interrupted garbage collection, if any.
+    pub fn load(path: PathBuf, options: CreateOptions) -> Result<Option<Self>, Error> {
+        let data = match file_read_optional_string(&path)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let state: GcMarkState = serde_json::from_str(&data)
+            .map_err(|err| format_err!("unable to parse GC checkpoint {path:?} - {err}"))?;
+
+        Ok(Some(Self {
+            path,
+            options,
+            last_save: proxmox_time::epoch_i64(),
+            state,
+        }))
+    }
+
+    /// Check whether a garbage collection started at `now`, with `oldest_writer` as its oldest
+    /// active writer, can safely resume from this checkpoint.
+    pub fn resumable(&self, oldest_writer: i64, now: i64) -> bool {
+        self.state.start_time <= now && self.state.oldest_writer <= oldest_writer
+    }
+
+    /// Start time of the checkpointed mark phase, to be used as cutoff base of the sweep phase.
+    pub fn start_time(&self) -> i64 {
+        self.state.start_time
+    }
+
+    /// Oldest active writer at the start of the checkpointed mark phase.
+    pub fn oldest_writer(&self) -> i64 {
+        self.state.oldest_writer
+    }
+
+    /// Number of index files recorded as processed.
+    pub fn processed_count(&self) -> usize {
+        self.state.processed.len()
+    }
+
+    /// Returns the recorded index data bytes if the index file at `rel_path` (relative to the
+    /// datastore base) was already processed and not modified since.
+    pub fn processed_bytes(&self, rel_path: &str, mtime: i64) -> Option<u64> {
+        if mtime >= self.state.start_time {
+            return None;
+        }
+        self.state.processed.get(rel_path).copied()
+    }
+
+    /// Record that all chunks of the index file at `rel_path` are marked.
+    pub fn record(&mut self, rel_path: String, index_bytes: u64) {
+        self.state.processed.insert(rel_path, index_bytes);
+    }
+
+    /// Write the checkpoint, if the last write is at least [`GC_CHECKPOINT_INTERVAL`] ago.
+    pub fn save_if_due(&mut self, now: i64) -> Result<(), Error> {
+        if now - self.last_save < GC_CHECKPOINT_INTERVAL {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    /// Write the checkpoint.
+    pub fn save(&mut self) -> Result<(), Error> {
+        let data = serde_json::to_string(&self.state)?;
+        replace_file(&self.path, data.as_bytes(), self.options.clone(), false)
+            .map_err(|err| format_err!("unable to write GC checkpoint {:?} - {err}", self.path))?;
+        self.last_save = proxmox_time::epoch_i64();
+        Ok(())
+    }
+
+    /// Remove the checkpoint once the garbage collection finished.
+    pub fn remove(path: &Path) -> Result<(), Error> {
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(format_err!(
+                "unable to remove GC checkpoint {path:?} - {err}"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::bail;
+
+    use super::*;
+
+    const TEST_DIR: &str = ".testdir-gc-checkpoint";
+
+    // (relative path, mtime, index bytes)
+    type TestIndex = (String, i64, u64);
+
+    // simulates the mark phase, returns the index data bytes of all index files
+    fn mark(
+        checkpoint: &mut GcMarkCheckpoint,
+        index_files: &[TestIndex],
+        interrupt_after: Option<usize>,
+        marked: &mut Vec<String>,
+    ) -> Result<u64, Error> {
+        let mut index_data_bytes = 0;
+        for (rel_path, mtime, bytes) in index_files {
+            if let Some(bytes) = checkpoint.processed_bytes(rel_path, *mtime) {
+                index_data_bytes += bytes;
+                continue;
+            }
+            if interrupt_after == Some(marked.len()) {
+                checkpoint.save()?;
+                bail!("interrupted");
+            }
+            marked.push(rel_path.clone());
+            index_data_bytes += bytes;
+            checkpoint.record(rel_path.clone(), *bytes);
+        }
+        Ok(index_data_bytes)
+    }
+
+    #[test]
+    fn test_gc_checkpoint_resume() {
+        let mut path = std::fs::canonicalize(".").unwrap();
+        path.push(TEST_DIR);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir(&path).unwrap();
+        let file = path.join(GC_CHECKPOINT_FILE_NAME);
+
+        let start_time = 1_700_000_000;
+        let oldest_writer = start_time - 60;
+
+        let mut index_files: Vec<TestIndex> = (0..10)
+            .map(|i| (format!("vm/100/{i}/drive.fidx"), start_time - 3600, 100))
+            .collect();
+
+        assert!(GcMarkCheckpoint::load(file.clone(), CreateOptions::new())
+            .unwrap()
+            .is_none());
+
+        // first run, interrupted after 4 index files
+        let mut checkpoint = GcMarkCheckpoint::new(
+            file.clone(),
+            CreateOptions::new(),
+            start_time,
+            oldest_writer,
+        );
+        let mut marked = Vec::new();
+        assert!(mark(&mut checkpoint, &index_files, Some(4), &mut marked).is_err());
+        assert_eq!(marked.len(), 4);
+
+        let mut checkpoint = GcMarkCheckpoint::load(file.clone(), CreateOptions::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(checkpoint.processed_count(), 4);
+        assert_eq!(checkpoint.start_time(), start_time);
+        assert_eq!(checkpoint.oldest_writer(), oldest_writer);
+
+        // an older active writer, or a clock running backwards, would move the cutoff
+        assert!(checkpoint.resumable(start_time + 3600, start_time + 3600));
+        assert!(!checkpoint.resumable(oldest_writer - 1, start_time + 3600));
+        assert!(!checkpoint.resumable(start_time - 1, start_time - 1));
+
+        // second run, interrupted again, nothing is marked twice
+        let mut marked = Vec::new();
+        assert!(mark(&mut checkpoint, &index_files, Some(3), &mut marked).is_err());
+        assert_eq!(
+            marked,
+            [
+                "vm/100/4/drive.fidx",
+                "vm/100/5/drive.fidx",
+                "vm/100/6/drive.fidx"
+            ]
+        );
+
+        // new index files appear and a processed one got replaced in the meantime
+        index_files.push(("vm/101/0/drive.fidx".to_string(), start_time + 600, 50));
+        index_files[1].1 = start_time + 300;
+
+        let mut checkpoint = GcMarkCheckpoint::load(file.clone(), CreateOptions::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!(checkpoint.processed_count(), 7);
+        let mut marked = Vec::new();
+        let index_data_bytes = mark(&mut checkpoint, &index_files, None, &mut marked).unwrap();
+        assert_eq!(
+            marked,
+            [
+                "vm/100/1/drive.fidx",
+                "vm/100/7/drive.fidx",
+                "vm/100/8/drive.fidx",
+                "vm/100/9/drive.fidx",
+                "vm/101/0/drive.fidx",
+            ]
+        );
+        // the statistics still cover all index files
+        assert_eq!(index_data_bytes, 10 * 100 + 50);
+
+        GcMarkCheckpoint::remove(&file).unwrap();
+        assert!(GcMarkCheckpoint::load(file.clone(), CreateOptions::new())
+            .unwrap()
+            .is_none());
+        GcMarkCheckpoint::remove(&file).unwrap();
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
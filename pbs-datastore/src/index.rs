@@ -1,5 +1,10 @@
 use std::collections::HashMap;
+use std::fs::File;
 use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Error};
 
 #[derive(Clone)]
 pub struct ChunkReadInfo {
@@ -67,3 +72,24 @@ pub trait IndexFile {
         map
     }
 }
+
+/// Returns the temporary path for writing the index `path`.
+///
+/// The name is tagged with the `uuid` of the writer, so that concurrent writers of the same
+/// index, e.g. from two backup sessions for the same snapshot, never share a temporary file.
+pub(crate) fn index_tmp_path(path: &Path, extension: &str, uuid: &[u8; 16]) -> PathBuf {
+    let mut tmp_path = path.to_owned();
+    tmp_path.set_extension(format!("{extension}_{}", hex::encode(uuid)));
+    tmp_path
+}
+
+/// Make sure `path` still is the temporary `file` created by this writer, before renaming it
+/// into place.
+pub(crate) fn check_index_tmp_file(file: &File, path: &Path) -> Result<(), Error> {
+    let ours = file.metadata()?;
+    match std::fs::symlink_metadata(path) {
+        Ok(found) if found.dev() == ours.dev() && found.ino() == ours.ino() => Ok(()),
+        Ok(_) => bail!("temporary file {path:?} was replaced by another writer"),
+        Err(err) => bail!("temporary file {path:?} vanished - {err}"),
+    }
+}
@@ -167,6 +167,27 @@ macro_rules! PROXMOX_BACKUP_READER_PROTOCOL_ID_V1 {
     };
 }
 
+/// Header of backup protocol upgrade requests with the version of the client
+///
+/// Passed as header, as older servers reject unknown parameters of the upgrade request.
+pub const BACKUP_CLIENT_VERSION_HEADER: &str = "proxmox-backup-client-version";
+
+/// Header of backup protocol upgrade requests with the hostname of the backup source
+pub const BACKUP_CLIENT_HOSTNAME_HEADER: &str = "proxmox-backup-client-hostname";
+
+/// Header for negotiating the compression of chunk data on the wire
+///
+/// The client sends its supported codecs in order of preference, the server answers with the
+/// selected codec in the upgrade response. Without the header, chunks are sent as they are.
+pub const BACKUP_WIRE_COMPRESSION_HEADER: &str = "proxmox-backup-wire-compression";
+
+/// Header of file downloads with the SHA-256 digest of the complete file
+///
+/// Sent for raw downloads of files covered by the manifest and for decoded blobs, the digest of
+/// decoded index contents is not known in advance. Partial responses to range requests carry the
+/// digest of the complete file as well, so that resumed downloads can be verified as a whole.
+pub const BACKUP_CONTENT_SHA256_HEADER: &str = "proxmox-backup-content-sha256";
+
 pub mod backup_info;
 pub mod cached_chunk_reader;
 pub mod catalog;
@@ -181,13 +202,19 @@ pub mod data_blob;
 pub mod data_blob_reader;
 pub mod data_blob_writer;
 pub mod file_formats;
+pub mod fsck;
 pub mod index;
+pub mod lock_tracking;
 pub mod manifest;
 pub mod paperkey;
 pub mod prune;
 pub mod read_chunk;
+pub mod s3_client;
 pub mod store_progress;
+pub mod task_io_stats;
 pub mod task_tracking;
+pub mod trash;
+pub mod wire_compression;
 
 pub mod dynamic_index;
 pub mod fixed_index;
@@ -195,7 +222,7 @@ pub mod fixed_index;
 pub use backup_info::{BackupDir, BackupGroup, BackupInfo};
 pub use checksum_reader::ChecksumReader;
 pub use checksum_writer::ChecksumWriter;
-pub use chunk_store::ChunkStore;
+pub use chunk_store::{ChunkStore, ChunkTrafficStats};
 pub use chunker::Chunker;
 pub use crypt_reader::CryptReader;
 pub use crypt_writer::CryptWriter;
@@ -206,7 +233,11 @@ pub use manifest::BackupManifest;
 pub use store_progress::StoreProgress;
 
 mod datastore;
-pub use datastore::{check_backup_owner, DataStore};
+pub use datastore::{
+    check_backup_owner, check_destroy_maintenance_mode, read_gc_atime_check_status, DataStore,
+};
+
+mod gc_checkpoint;
 
 mod hierarchy;
 pub use hierarchy::{
@@ -0,0 +1,570 @@
+//! Tracking of held backup group and snapshot directory locks
+//!
+//! Group and snapshot locks are `flock`s on the respective directory, which do not reveal who
+//! holds them. To provide useful diagnostics, every acquired lock is recorded in its own file in a
+//! registry directory together with the holding process and, if known, the task and operation.
+//! Taking or releasing a lock only writes or removes its own record, so locking is not serialized
+//! by the registry. Records of processes which are not running anymore are ignored and removed,
+//! like for the active operations tracking.
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use libc::pid_t;
+use nix::dir::Dir;
+use nix::errno::Errno;
+use nix::fcntl::{FlockArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use pbs_api_types::{ApiError, ApiErrorCode};
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::linux::procfs;
+
+/// Directory where the currently held directory locks are recorded, one file per lock.
+pub const LOCK_REGISTRY_DIR: &str =
+    concat!(pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M!(), "/lock-registry");
+
+static NEXT_LOCK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A held directory lock as recorded in the registry.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockHolder {
+    /// The locked directory.
+    pub path: PathBuf,
+    /// What is locked, for example "backup group" or "snapshot".
+    pub what: String,
+    pub exclusive: bool,
+    pub pid: u32,
+    pub starttime: u64,
+    /// Epoch when the lock was acquired.
+    pub since: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+}
+
+impl LockHolder {
+    fn is_alive(&self) -> bool {
+        matches!(
+            procfs::check_process_running(self.pid as pid_t),
+            Some(stat) if stat.starttime == self.starttime
+        )
+    }
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operation = self.operation.as_deref().unwrap_or("unknown operation");
+        match self.upid {
+            Some(ref upid) => write!(f, "held by {operation} task {upid}")?,
+            None => {
+                let comm =
+                    std::fs::read_to_string(format!("/proc/{}/comm", self.pid)).unwrap_or_default();
+                write!(
+                    f,
+                    "held by {operation} in process {} ({})",
+                    self.pid,
+                    comm.trim()
+                )?;
+            }
+        }
+        let age = (proxmox_time::epoch_i64() - self.since).max(0) as u64;
+        let age = proxmox_time::TimeSpan::from(Duration::from_secs(age));
+        write!(f, " since {age}")
+    }
+}
+
+struct LockOwner {
+    upid: Option<String>,
+    operation: String,
+}
+
+thread_local! {
+    static LOCK_OWNER: RefCell<Option<LockOwner>> = RefCell::new(None);
+}
+
+struct LockOwnerGuard(Option<LockOwner>);
+
+impl Drop for LockOwnerGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        LOCK_OWNER.with(|owner| *owner.borrow_mut() = previous);
+    }
+}
+
+/// Record `upid` and `operation` as holder of all locks acquired by `func`.
+///
+/// This only covers locks acquired synchronously on the current thread, so it must be used
+/// directly around the code taking the locks and not across `await` points.
+pub fn with_lock_owner<R>(upid: Option<&str>, operation: &str, func: impl FnOnce() -> R) -> R {
+    let new_owner = LockOwner {
+        upid: upid.map(str::to_string),
+        operation: operation.to_string(),
+    };
+    let _guard = LockOwnerGuard(LOCK_OWNER.with(|owner| owner.replace(Some(new_owner))));
+    func()
+}
+
+/// Location of the lock records and the options to create them with.
+struct LockRegistry {
+    dir: PathBuf,
+    options: CreateOptions,
+}
+
+impl LockRegistry {
+    fn system() -> Result<Self, Error> {
+        let user = pbs_config::backup_user()?;
+        Ok(Self {
+            dir: PathBuf::from(LOCK_REGISTRY_DIR),
+            options: CreateOptions::new().owner(user.uid).group(user.gid),
+        })
+    }
+
+    fn register(&self, path: &Path, what: &str, exclusive: bool) -> Result<LockRecord, Error> {
+        let pid = std::process::id();
+        let starttime = procfs::PidStat::read_from_pid(Pid::from_raw(pid as pid_t))?.starttime;
+        let id = NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed);
+
+        let (upid, operation) = LOCK_OWNER.with(|owner| match *owner.borrow() {
+            Some(ref owner) => (owner.upid.clone(), Some(owner.operation.clone())),
+            None => (None, None),
+        });
+
+        let record = LockRecord {
+            file: self.dir.join(format!("{pid}-{id}.json")),
+            options: self.options.clone(),
+            holder: LockHolder {
+                path: path.to_owned(),
+                what: what.to_string(),
+                exclusive,
+                pid,
+                starttime,
+                since: proxmox_time::epoch_i64(),
+                upid,
+                operation,
+            },
+        };
+        record.write(&record.holder)?;
+
+        Ok(record)
+    }
+
+    fn read(&self) -> Result<Vec<LockHolder>, Error> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => bail!("unable to read lock registry {:?} - {err}", self.dir),
+        };
+
+        let mut holders = Vec::new();
+        for entry in entries {
+            let file = entry?.path();
+            // skips temporary files of records currently being written
+            if file.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+            // the record vanishes if the lock is released in the meantime
+            let data = match file_read_optional_string(&file)? {
+                Some(data) => data,
+                None => continue,
+            };
+            let holder: LockHolder = match serde_json::from_str(&data) {
+                Ok(holder) => holder,
+                Err(err) => {
+                    log::debug!("ignoring invalid lock registry entry {file:?} - {err}");
+                    continue;
+                }
+            };
+            if holder.is_alive() {
+                holders.push(holder);
+            } else {
+                // left behind by a process which did not release its locks
+                let _ = std::fs::remove_file(&file);
+            }
+        }
+        holders.sort_by_key(|holder| holder.since);
+
+        Ok(holders)
+    }
+
+    fn list_holders(&self, path: Option<&Path>) -> Result<Vec<LockHolder>, Error> {
+        let mut holders = self.read()?;
+        if let Some(path) = path {
+            holders.retain(|holder| holder.path == path);
+        }
+        Ok(holders)
+    }
+}
+
+/// The registry entry of a lock held by this process.
+struct LockRecord {
+    file: PathBuf,
+    options: CreateOptions,
+    holder: LockHolder,
+}
+
+impl LockRecord {
+    fn write(&self, holder: &LockHolder) -> Result<(), Error> {
+        let options = self
+            .options
+            .clone()
+            .perm(nix::sys::stat::Mode::from_bits_truncate(0o660));
+        replace_file(&self.file, &serde_json::to_vec(holder)?, options, false)
+    }
+}
+
+/// Guard for a tracked directory lock, the lock is released when it is dropped.
+pub struct DirLockGuard {
+    dir: Dir,
+    record: Option<LockRecord>,
+}
+
+impl DirLockGuard {
+    /// Update the task and operation recorded as holder of the lock.
+    ///
+    /// Useful if the lock has to be acquired before the task holding it is started.
+    pub fn set_owner(&self, upid: Option<&str>, operation: &str) {
+        let record = match self.record {
+            Some(ref record) => record,
+            None => return,
+        };
+        let mut holder = record.holder.clone();
+        holder.upid = upid.map(str::to_string);
+        holder.operation = Some(operation.to_string());
+        if let Err(err) = record.write(&holder) {
+            log::debug!("unable to update lock registry entry - {err}");
+        }
+    }
+}
+
+impl AsRawFd for DirLockGuard {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.dir.as_raw_fd()
+    }
+}
+
+impl Drop for DirLockGuard {
+    fn drop(&mut self) {
+        if let Some(ref record) = self.record {
+            if let Err(err) = std::fs::remove_file(&record.file) {
+                log::debug!("unable to remove lock registry entry - {err}");
+            }
+        }
+    }
+}
+
+/// Acquire an exclusive lock on a directory without blocking.
+///
+/// If the lock is held by someone else, the error is an [`ApiError`] with code
+/// [`ApiErrorCode::LockContention`] and names the known holders.
+pub fn lock_dir_noblock(
+    path: &Path,
+    what: &str,
+    would_block_msg: &str,
+) -> Result<DirLockGuard, Error> {
+    lock_dir_noblock_do(
+        system_registry().as_ref(),
+        path,
+        what,
+        would_block_msg,
+        true,
+    )
+}
+
+/// Acquire a shared lock on a directory without blocking.
+///
+/// See [`lock_dir_noblock`] for the error reported on contention.
+pub fn lock_dir_noblock_shared(
+    path: &Path,
+    what: &str,
+    would_block_msg: &str,
+) -> Result<DirLockGuard, Error> {
+    lock_dir_noblock_do(
+        system_registry().as_ref(),
+        path,
+        what,
+        would_block_msg,
+        false,
+    )
+}
+
+// the registry is only used for diagnostics, never fail locking because of it
+fn system_registry() -> Option<LockRegistry> {
+    match LockRegistry::system() {
+        Ok(registry) => Some(registry),
+        Err(err) => {
+            log::debug!("lock registry not available - {err}");
+            None
+        }
+    }
+}
+
+fn lock_dir_noblock_do(
+    registry: Option<&LockRegistry>,
+    path: &Path,
+    what: &str,
+    would_block_msg: &str,
+    exclusive: bool,
+) -> Result<DirLockGuard, Error> {
+    let dir = Dir::open(path, OFlag::O_RDONLY, Mode::empty()).map_err(|err| {
+        format_err!("unable to open {what} directory {path:?} for locking - {err}")
+    })?;
+
+    let arg = if exclusive {
+        FlockArg::LockExclusiveNonblock
+    } else {
+        FlockArg::LockSharedNonblock
+    };
+
+    match nix::fcntl::flock(dir.as_raw_fd(), arg) {
+        Ok(()) => (),
+        Err(Errno::EWOULDBLOCK) => {
+            let mut msg =
+                format!("unable to acquire lock on {what} directory {path:?} - {would_block_msg}");
+            let holders = registry
+                .and_then(|registry| registry.list_holders(Some(path)).ok())
+                .unwrap_or_default();
+            if !holders.is_empty() {
+                let holders: Vec<String> = holders.iter().map(|h| h.to_string()).collect();
+                msg.push_str(&format!(" ({})", holders.join(", ")));
+            }
+            return Err(ApiError::new(ApiErrorCode::LockContention, msg).into());
+        }
+        Err(err) => bail!("unable to acquire lock on {what} directory {path:?} - {err}"),
+    }
+
+    let record = registry.and_then(|registry| match registry.register(path, what, exclusive) {
+        Ok(record) => Some(record),
+        Err(err) => {
+            log::debug!("unable to record lock on {path:?} - {err}");
+            None
+        }
+    });
+
+    Ok(DirLockGuard { dir, record })
+}
+
+/// Returns true if `err` was caused by a lock held by someone else.
+pub fn is_lock_contention(err: &Error) -> bool {
+    err.downcast_ref::<ApiError>()
+        .map(|err| err.code() == ApiErrorCode::LockContention)
+        .unwrap_or(false)
+}
+
+/// Call `func` until it does not fail due to lock contention, for up to `wait`.
+///
+/// Retries with an increasing delay, without `wait` `func` is only called once.
+pub fn retry_on_lock_contention<T>(
+    wait: Option<Duration>,
+    mut func: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    let mut delay = Duration::from_millis(100);
+
+    loop {
+        match func() {
+            Err(err) if is_lock_contention(&err) => {
+                let remaining = match deadline {
+                    Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                    None => return Err(err),
+                };
+                if remaining.is_zero() {
+                    return Err(err);
+                }
+                std::thread::sleep(delay.min(remaining));
+                delay = (delay * 2).min(Duration::from_secs(5));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// List the held locks, optionally only those of `path`, oldest first.
+pub fn list_lock_holders(path: Option<&Path>) -> Result<Vec<LockHolder>, Error> {
+    LockRegistry::system()?.list_holders(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::*;
+
+    const TEST_DIR: &str = ".testdir-lock-tracking";
+
+    fn setup(name: &str) -> (PathBuf, LockRegistry) {
+        let mut path = std::fs::canonicalize(".").unwrap();
+        path.push(TEST_DIR);
+        path.push(name);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(path.join("group")).unwrap();
+        std::fs::create_dir_all(path.join("registry")).unwrap();
+        let registry = LockRegistry {
+            dir: path.join("registry"),
+            options: CreateOptions::new(),
+        };
+        (path, registry)
+    }
+
+    fn contention() -> Error {
+        ApiError::new(ApiErrorCode::LockContention, "locked".to_string()).into()
+    }
+
+    fn holder(upid: Option<&str>) -> LockHolder {
+        let pid = std::process::id();
+        LockHolder {
+            path: PathBuf::from("/datastore/vm/100"),
+            what: "backup group".to_string(),
+            exclusive: true,
+            pid,
+            starttime: procfs::PidStat::read_from_pid(Pid::from_raw(pid as pid_t))
+                .unwrap()
+                .starttime,
+            since: proxmox_time::epoch_i64(),
+            upid: upid.map(str::to_string),
+            operation: Some("verify".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_retry_on_lock_contention() {
+        let calls = Cell::new(0);
+        let result = retry_on_lock_contention(Some(Duration::from_secs(10)), || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                return Err(contention());
+            }
+            Ok(calls.get())
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // without a wait time there is no retry
+        calls.set(0);
+        let result: Result<(), Error> = retry_on_lock_contention(None, || {
+            calls.set(calls.get() + 1);
+            Err(contention())
+        });
+        assert!(is_lock_contention(&result.unwrap_err()));
+        assert_eq!(calls.get(), 1);
+
+        // other errors are not retried
+        calls.set(0);
+        let result: Result<(), Error> =
+            retry_on_lock_contention(Some(Duration::from_secs(10)), || {
+                calls.set(calls.get() + 1);
+                bail!("other error");
+            });
+        assert!(!is_lock_contention(&result.unwrap_err()));
+        assert_eq!(calls.get(), 1);
+
+        // gives up once the wait time is over
+        calls.set(0);
+        let start = Instant::now();
+        let result: Result<(), Error> =
+            retry_on_lock_contention(Some(Duration::from_millis(250)), || {
+                calls.set(calls.get() + 1);
+                Err(contention())
+            });
+        assert!(is_lock_contention(&result.unwrap_err()));
+        assert!(calls.get() > 1);
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_lock_holder_message() {
+        let upid = "UPID:pbs:00000001:00000002:00000000:00000003:verify:store:root@pam:";
+        let msg = holder(Some(upid)).to_string();
+        assert!(msg.starts_with(&format!("held by verify task {upid} since ")));
+
+        let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+        let mut without_task = holder(None);
+        without_task.operation = None;
+        let msg = without_task.to_string();
+        assert!(msg.starts_with(&format!(
+            "held by unknown operation in process {} ({}) since ",
+            std::process::id(),
+            comm.trim()
+        )));
+    }
+
+    #[test]
+    fn test_stale_lock_records() {
+        let (_path, registry) = setup("stale");
+
+        let write = |name: &str, holder: &LockHolder| {
+            let file = registry.dir.join(name);
+            std::fs::write(&file, serde_json::to_vec(holder).unwrap()).unwrap();
+            file
+        };
+
+        let alive = write("alive.json", &holder(None));
+
+        // a reused pid of a process that is gone
+        let mut reused = holder(None);
+        reused.starttime += 1;
+        let reused = write("reused.json", &reused);
+
+        // a pid that cannot exist
+        let mut dead = holder(None);
+        dead.pid = i32::MAX as u32;
+        let dead = write("dead.json", &dead);
+
+        let holders = registry.list_holders(None).unwrap();
+        assert_eq!(holders.len(), 1);
+        assert_eq!(holders[0].pid, std::process::id());
+
+        assert!(alive.exists());
+        assert!(!reused.exists());
+        assert!(!dead.exists());
+
+        assert!(registry
+            .list_holders(Some(Path::new("/datastore/vm/101")))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_lock_contention_names_holder() {
+        let (path, registry) = setup("contention");
+        let group = path.join("group");
+        let upid = "UPID:pbs:00000001:00000002:00000000:00000003:verify:store:root@pam:";
+
+        let guard = with_lock_owner(Some(upid), "verify", || {
+            lock_dir_noblock_do(Some(&registry), &group, "backup group", "busy", false)
+        })
+        .unwrap();
+        let shared =
+            lock_dir_noblock_do(Some(&registry), &group, "backup group", "busy", false).unwrap();
+        assert_eq!(registry.list_holders(Some(&group)).unwrap().len(), 2);
+        drop(shared);
+
+        let err = lock_dir_noblock_do(Some(&registry), &group, "backup group", "busy", true)
+            .err()
+            .unwrap();
+        assert!(is_lock_contention(&err));
+        let msg = err.to_string();
+        assert!(msg.contains("busy"));
+        assert!(msg.contains(&format!("held by verify task {upid}")));
+
+        guard.set_owner(None, "sync");
+        let holders = registry.list_holders(Some(&group)).unwrap();
+        assert_eq!(holders.len(), 1);
+        assert_eq!(holders[0].upid, None);
+        assert_eq!(holders[0].operation.as_deref(), Some("sync"));
+
+        drop(guard);
+        assert!(registry.list_holders(None).unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(&registry.dir).unwrap().count(), 0);
+
+        lock_dir_noblock_do(Some(&registry), &group, "backup group", "busy", true).unwrap();
+    }
+}
@@ -5,7 +5,7 @@ use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use pbs_api_types::{BackupType, CryptMode, Fingerprint};
+use pbs_api_types::{ArchiveExcludes, BackupType, CryptMode, Fingerprint, SnapshotVerifyState};
 use pbs_tools::crypt_config::CryptConfig;
 
 pub const MANIFEST_BLOB_NAME: &str = "index.json.blob";
@@ -13,6 +13,13 @@ pub const MANIFEST_LOCK_NAME: &str = ".index.json.lck";
 pub const CLIENT_LOG_BLOB_NAME: &str = "client.log.blob";
 pub const ENCRYPTED_KEY_BLOB_NAME: &str = "rsa-encrypted.key.blob";
 
+/// Maximum number of exclude patterns stored per archive in the manifest.
+pub const MANIFEST_EXCLUDES_MAX_COUNT: usize = 1024;
+/// Maximum total length of the exclude patterns stored per archive in the manifest.
+pub const MANIFEST_EXCLUDES_MAX_SIZE: usize = 64 * 1024;
+/// Maximum number of verification results kept in the manifest.
+pub const MANIFEST_VERIFY_HISTORY_MAX: usize = 10;
+
 fn crypt_mode_none() -> CryptMode {
     CryptMode::None
 }
@@ -114,6 +121,111 @@ impl BackupManifest {
         &self.files[..]
     }
 
+    /// The logical size of the snapshot, i.e. the summed size of all index archives before
+    /// deduplication and compression.
+    pub fn logical_size(&self) -> u64 {
+        self.files
+            .iter()
+            .filter(|file| {
+                matches!(
+                    archive_type(&file.filename),
+                    Ok(ArchiveType::FixedIndex | ArchiveType::DynamicIndex)
+                )
+            })
+            .map(|file| file.size)
+            .sum()
+    }
+
+    /// Record the exclude patterns used to create `archive_name`.
+    ///
+    /// They are stored in the unprotected part, capped to [`MANIFEST_EXCLUDES_MAX_COUNT`]
+    /// patterns and [`MANIFEST_EXCLUDES_MAX_SIZE`] bytes.
+    pub fn set_archive_excludes(
+        &mut self,
+        archive_name: &str,
+        patterns: Vec<String>,
+    ) -> Result<(), Error> {
+        let mut excludes = ArchiveExcludes::default();
+        let mut size = 0;
+        for pattern in patterns {
+            size += pattern.len();
+            if excludes.patterns.len() >= MANIFEST_EXCLUDES_MAX_COUNT
+                || size > MANIFEST_EXCLUDES_MAX_SIZE
+            {
+                excludes.truncated = Some(true);
+                break;
+            }
+            excludes.patterns.push(pattern);
+        }
+
+        if !self.unprotected["excludes"].is_object() {
+            self.unprotected["excludes"] = json!({});
+        }
+        self.unprotected["excludes"][archive_name] = serde_json::to_value(excludes)?;
+
+        Ok(())
+    }
+
+    /// Returns the exclude patterns recorded for `archive_name`, if any.
+    pub fn archive_excludes(&self, archive_name: &str) -> Option<ArchiveExcludes> {
+        match &self.unprotected["excludes"][archive_name] {
+            Value::Null => None,
+            value => serde_json::from_value(value.clone()).ok(),
+        }
+    }
+
+    /// Record the result of a verification.
+    ///
+    /// Sets it as current verify state and appends it to the verification history, dropping the
+    /// oldest entries beyond [`MANIFEST_VERIFY_HISTORY_MAX`]. Both are stored in the unprotected
+    /// part.
+    pub fn record_verify_state(&mut self, state: SnapshotVerifyState) {
+        let mut history = self.verify_history();
+        history.push(state.clone());
+        if history.len() > MANIFEST_VERIFY_HISTORY_MAX {
+            history.drain(..history.len() - MANIFEST_VERIFY_HISTORY_MAX);
+        }
+
+        self.unprotected["verify_state"] = json!(state);
+        self.unprotected["verify_history"] = json!(history);
+    }
+
+    /// Returns the recorded verification results, oldest first.
+    ///
+    /// Manifests written by older versions only contain the last result.
+    pub fn verify_history(&self) -> Vec<SnapshotVerifyState> {
+        match &self.unprotected["verify_history"] {
+            Value::Null => serde_json::from_value(self.unprotected["verify_state"].clone())
+                .map(|state| vec![state])
+                .unwrap_or_default(),
+            value => serde_json::from_value(value.clone()).unwrap_or_default(),
+        }
+    }
+
+    /// Mark the snapshot as partially restored, only `archives` (and the manifest) are present.
+    ///
+    /// This is stored in the unprotected part, so it does not invalidate the signature.
+    pub fn set_partial_archives(&mut self, archives: &[String]) {
+        self.unprotected["partial-restore"] = json!(archives);
+    }
+
+    /// Returns the archives present in a partially restored snapshot, or `None` if the
+    /// snapshot is complete.
+    pub fn partial_archives(&self) -> Option<Vec<String>> {
+        match &self.unprotected["partial-restore"] {
+            Value::Null => None,
+            value => serde_json::from_value(value.clone()).ok(),
+        }
+    }
+
+    /// Returns `false` if `archive` was left out by a partial restore.
+    pub fn archive_available(&self, archive: &str) -> bool {
+        match self.partial_archives() {
+            Some(archives) => archives.iter().any(|name| name == archive),
+            None => true,
+        }
+    }
+
     pub fn lookup_file_info(&self, name: &str) -> Result<&FileInfo, Error> {
         let info = self.files.iter().find(|item| item.filename == name);
 
@@ -305,3 +417,68 @@ fn test_manifest_signature() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn test_manifest_excludes() -> Result<(), Error> {
+    let mut manifest = BackupManifest::new("host/elsa/2020-06-26T13:56:05Z".parse()?);
+
+    manifest.add_file("root.pxar.didx".into(), 200, [1u8; 32], CryptMode::None)?;
+    assert!(manifest.archive_excludes("root.pxar.didx").is_none());
+
+    manifest.set_archive_excludes("root.pxar.didx", vec!["/tmp".into(), "!*.conf".into()])?;
+    let excludes = manifest.archive_excludes("root.pxar.didx").unwrap();
+    assert_eq!(excludes.patterns, vec!["/tmp", "!*.conf"]);
+    assert_eq!(excludes.truncated, None);
+
+    let patterns = (0..MANIFEST_EXCLUDES_MAX_COUNT + 10)
+        .map(|i| format!("/dir{i}"))
+        .collect();
+    manifest.set_archive_excludes("other.pxar.didx", patterns)?;
+    let excludes = manifest.archive_excludes("other.pxar.didx").unwrap();
+    assert_eq!(excludes.patterns.len(), MANIFEST_EXCLUDES_MAX_COUNT);
+    assert_eq!(excludes.truncated, Some(true));
+
+    // excludes are not covered by the signature, but must survive a round trip
+    let manifest = BackupManifest::from_data(manifest.to_string(None)?.as_bytes(), None)?;
+    assert!(manifest.archive_excludes("root.pxar.didx").is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_manifest_verify_history() -> Result<(), Error> {
+    use pbs_api_types::{VerifyState, UPID};
+
+    let mut manifest = BackupManifest::new("host/elsa/2020-06-26T13:56:05Z".parse()?);
+    assert!(manifest.verify_history().is_empty());
+
+    let upid: UPID =
+        "UPID:elsa:00000F5B:00055E9C:00000000:5EF5F7D5:verify:store\\x3ahost-elsa:root@pam:"
+            .parse()?;
+    let state = |time| SnapshotVerifyState {
+        upid: upid.clone(),
+        state: VerifyState::Ok,
+        time: Some(time),
+    };
+
+    // older versions only stored the last result
+    manifest.unprotected["verify_state"] = serde_json::to_value(SnapshotVerifyState {
+        time: None,
+        ..state(0)
+    })?;
+    assert_eq!(manifest.verify_history().len(), 1);
+
+    for time in 1..=MANIFEST_VERIFY_HISTORY_MAX as i64 {
+        manifest.record_verify_state(state(time));
+    }
+    let history = manifest.verify_history();
+    assert_eq!(history.len(), MANIFEST_VERIFY_HISTORY_MAX);
+    assert_eq!(history[0].time, Some(1));
+    assert_eq!(
+        history.last().unwrap().time,
+        Some(MANIFEST_VERIFY_HISTORY_MAX as i64)
+    );
+    assert!(manifest.unprotected["verify_state"] == serde_json::to_value(history.last())?);
+
+    Ok(())
+}
@@ -4,9 +4,6 @@ use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{bail, Error};
-use nix::dir::Dir;
-
-use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use pbs_api_types::{print_store_and_ns, BackupNamespace, Operation};
 
@@ -14,6 +11,7 @@ use crate::backup_info::BackupDir;
 use crate::dynamic_index::DynamicIndexReader;
 use crate::fixed_index::FixedIndexReader;
 use crate::index::IndexFile;
+use crate::lock_tracking::{lock_dir_noblock_shared, DirLockGuard};
 use crate::manifest::{archive_type, ArchiveType, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
 use crate::DataStore;
 
@@ -24,7 +22,7 @@ pub struct SnapshotReader {
     snapshot: BackupDir,
     datastore_name: String,
     file_list: Vec<String>,
-    locked_dir: Dir,
+    locked_dir: DirLockGuard,
 }
 
 impl SnapshotReader {
@@ -0,0 +1,372 @@
+//! Per task accounting of datastore IO
+//!
+//! Chunk reads and writes are attributed to the task whose IO context is set on the current
+//! thread, see [`track_task_io`], [`enter_task`] and [`instrument`]. Threads without context do
+//! not count anything, so the overhead is a thread local lookup and a relaxed atomic add per
+//! chunk.
+//!
+//! Every process periodically publishes the counters of its running tasks to a file in the run
+//! directory, so that the API daemons can report the IO of tasks running in other processes.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+use libc::pid_t;
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use pbs_api_types::TaskIoStats;
+use proxmox_sys::fs::{create_path, replace_file, CreateOptions};
+use proxmox_sys::linux::procfs;
+
+/// Directory where every process publishes the IO counters of its running tasks.
+pub const TASK_IO_STATS_DIR: &str =
+    concat!(pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M!(), "/task-io-stats");
+
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Cumulative IO counters of a single task.
+#[derive(Default)]
+pub struct TaskIoCounters {
+    read_bytes: AtomicU64,
+    write_bytes: AtomicU64,
+}
+
+impl TaskIoCounters {
+    pub fn account_read(&self, bytes: u64) {
+        self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn account_write(&self, bytes: u64) {
+        self.write_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn read_bytes(&self) -> u64 {
+        self.read_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn write_bytes(&self) -> u64 {
+        self.write_bytes.load(Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    static CURRENT_TASK_IO: RefCell<Option<Arc<TaskIoCounters>>> = RefCell::new(None);
+}
+
+/// Guard restoring the previous IO context of the thread on drop.
+pub struct TaskIoContextGuard(Option<Arc<TaskIoCounters>>);
+
+impl Drop for TaskIoContextGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT_TASK_IO.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Returns the IO counters of the task running on the current thread, if any.
+pub fn current() -> Option<Arc<TaskIoCounters>> {
+    CURRENT_TASK_IO.with(|current| current.borrow().clone())
+}
+
+/// Account `bytes` read by the task running on the current thread.
+pub fn account_read(bytes: u64) {
+    CURRENT_TASK_IO.with(|current| {
+        if let Some(counters) = current.borrow().as_ref() {
+            counters.account_read(bytes);
+        }
+    });
+}
+
+/// Account `bytes` written by the task running on the current thread.
+pub fn account_write(bytes: u64) {
+    CURRENT_TASK_IO.with(|current| {
+        if let Some(counters) = current.borrow().as_ref() {
+            counters.account_write(bytes);
+        }
+    });
+}
+
+/// Set `counters` as IO context of the current thread, until the returned guard is dropped.
+///
+/// Used to pass the context of a task to helper threads, see [`current`].
+pub fn set_task_io(counters: Option<Arc<TaskIoCounters>>) -> TaskIoContextGuard {
+    TaskIoContextGuard(CURRENT_TASK_IO.with(|current| current.replace(counters)))
+}
+
+/// Run `func` with `counters` as IO context of the current thread.
+pub fn with_task_io<R>(counters: Option<Arc<TaskIoCounters>>, func: impl FnOnce() -> R) -> R {
+    let _guard = set_task_io(counters);
+    func()
+}
+
+/// Future which sets an IO context while it is polled.
+pub struct TaskIoFuture<F> {
+    counters: Option<Arc<TaskIoCounters>>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for TaskIoFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        with_task_io(this.counters.clone(), || inner.as_mut().poll(cx))
+    }
+}
+
+/// Attribute the IO of `future` to the task running on the current thread.
+///
+/// Needed for futures which are spawned as separate tokio tasks.
+pub fn instrument<F: Future>(future: F) -> TaskIoFuture<F> {
+    TaskIoFuture {
+        counters: current(),
+        inner: Box::pin(future),
+    }
+}
+
+struct TaskEntry {
+    counters: Arc<TaskIoCounters>,
+    last_update: Instant,
+    last_read_bytes: u64,
+    last_write_bytes: u64,
+    read_rate: u64,
+    write_rate: u64,
+}
+
+lazy_static! {
+    static ref TASK_REGISTRY: Mutex<HashMap<String, TaskEntry>> = Mutex::new(HashMap::new());
+}
+
+/// Registration of a task, the task is removed from the published stats on drop.
+pub struct TaskIoGuard {
+    upid: String,
+    counters: Arc<TaskIoCounters>,
+}
+
+impl TaskIoGuard {
+    pub fn counters(&self) -> Arc<TaskIoCounters> {
+        Arc::clone(&self.counters)
+    }
+}
+
+impl Drop for TaskIoGuard {
+    fn drop(&mut self) {
+        TASK_REGISTRY.lock().unwrap().remove(&self.upid);
+    }
+}
+
+/// Register the task `upid` for publishing its IO counters.
+pub fn register_task(upid: &str) -> TaskIoGuard {
+    start_publisher();
+
+    let counters = Arc::new(TaskIoCounters::default());
+    let entry = TaskEntry {
+        counters: Arc::clone(&counters),
+        last_update: Instant::now(),
+        last_read_bytes: 0,
+        last_write_bytes: 0,
+        read_rate: 0,
+        write_rate: 0,
+    };
+    TASK_REGISTRY
+        .lock()
+        .unwrap()
+        .insert(upid.to_string(), entry);
+
+    TaskIoGuard {
+        upid: upid.to_string(),
+        counters,
+    }
+}
+
+/// Attribute the datastore IO done by the worker task future `future` to task `upid`.
+pub async fn track_task_io<F: Future>(upid: String, future: F) -> F::Output {
+    let guard = register_task(&upid);
+    TaskIoFuture {
+        counters: Some(guard.counters()),
+        inner: Box::pin(future),
+    }
+    .await
+}
+
+/// Registration of a task, which is also the IO context of the current thread.
+pub struct TaskIoThreadGuard {
+    _context: TaskIoContextGuard,
+    _task: TaskIoGuard,
+}
+
+/// Attribute the datastore IO done on the current thread to task `upid`, until the returned
+/// guard is dropped. Meant for worker tasks running in their own thread.
+pub fn enter_task(upid: &str) -> TaskIoThreadGuard {
+    let task = register_task(upid);
+    TaskIoThreadGuard {
+        _context: set_task_io(Some(task.counters())),
+        _task: task,
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct PublishedTaskIoStats {
+    pid: u32,
+    starttime: u64,
+    tasks: Vec<TaskIoStats>,
+}
+
+fn stats_file_path(pid: u32) -> PathBuf {
+    let mut path = PathBuf::from(TASK_IO_STATS_DIR);
+    path.push(format!("{pid}.json"));
+    path
+}
+
+fn collect_task_stats() -> Vec<TaskIoStats> {
+    let now = Instant::now();
+    let mut registry = TASK_REGISTRY.lock().unwrap();
+
+    let mut list = Vec::with_capacity(registry.len());
+    for (upid, entry) in registry.iter_mut() {
+        let read_bytes = entry.counters.read_bytes();
+        let write_bytes = entry.counters.write_bytes();
+
+        let elapsed = now.duration_since(entry.last_update).as_secs_f64();
+        if elapsed > 0.0 {
+            entry.read_rate = ((read_bytes - entry.last_read_bytes) as f64 / elapsed) as u64;
+            entry.write_rate = ((write_bytes - entry.last_write_bytes) as f64 / elapsed) as u64;
+        }
+        entry.last_update = now;
+        entry.last_read_bytes = read_bytes;
+        entry.last_write_bytes = write_bytes;
+
+        list.push(TaskIoStats {
+            upid: upid.clone(),
+            read_bytes,
+            write_bytes,
+            read_rate: entry.read_rate,
+            write_rate: entry.write_rate,
+        });
+    }
+
+    list
+}
+
+fn publish_task_stats(tasks: Vec<TaskIoStats>) -> Result<(), Error> {
+    let pid = std::process::id();
+    let path = stats_file_path(pid);
+
+    if tasks.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        };
+    }
+
+    let user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .group(user.gid)
+        .owner(user.uid)
+        .perm(nix::sys::stat::Mode::from_bits_truncate(0o644));
+    create_path(TASK_IO_STATS_DIR, None, Some(options.clone()))?;
+
+    let stats = PublishedTaskIoStats {
+        pid,
+        starttime: procfs::PidStat::read_from_pid(Pid::from_raw(pid as pid_t))?.starttime,
+        tasks,
+    };
+    replace_file(path, &serde_json::to_vec(&stats)?, options, false)
+}
+
+fn start_publisher() {
+    static START: Once = Once::new();
+
+    START.call_once(|| {
+        let result = std::thread::Builder::new()
+            .name("task io stats".to_string())
+            .spawn(|| {
+                let mut published = false;
+                loop {
+                    std::thread::sleep(PUBLISH_INTERVAL);
+                    let tasks = collect_task_stats();
+                    if tasks.is_empty() && !published {
+                        continue;
+                    }
+                    published = !tasks.is_empty();
+                    if let Err(err) = publish_task_stats(tasks) {
+                        log::debug!("unable to publish task io stats - {err}");
+                    }
+                }
+            });
+        if let Err(err) = result {
+            log::error!("unable to start task io stats thread - {err}");
+        }
+    });
+}
+
+/// Returns the IO stats of the running tasks of all processes.
+///
+/// The stats are published every few seconds, so they lag slightly behind.
+pub fn read_task_io_stats() -> Result<Vec<TaskIoStats>, Error> {
+    let dir = match std::fs::read_dir(TASK_IO_STATS_DIR) {
+        Ok(dir) => dir,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut list = Vec::new();
+    for entry in dir {
+        let path = entry?.path();
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let stats: PublishedTaskIoStats = match serde_json::from_slice(&data) {
+            Ok(stats) => stats,
+            Err(err) => {
+                log::debug!("skipping invalid task io stats file {path:?} - {err}");
+                continue;
+            }
+        };
+
+        let alive = matches!(
+            procfs::check_process_running(stats.pid as pid_t),
+            Some(stat) if stat.starttime == stats.starttime
+        );
+        if alive {
+            list.extend(stats.tasks);
+        }
+    }
+
+    Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_task_io_context() {
+        let counters = Arc::new(TaskIoCounters::default());
+
+        account_read(10); // no context, not counted
+        with_task_io(Some(Arc::clone(&counters)), || {
+            account_read(100);
+            account_write(5);
+            with_task_io(None, || account_read(1));
+            account_write(2);
+        });
+        account_write(7);
+
+        assert_eq!(counters.read_bytes(), 100);
+        assert_eq!(counters.write_bytes(), 7);
+        assert!(current().is_none());
+    }
+}
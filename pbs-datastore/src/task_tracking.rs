@@ -32,6 +32,9 @@ struct TaskOperations {
     pid: u32,
     starttime: u64,
     active_operations: ActiveOperationStats,
+    /// UPIDs of the tasks holding operations in this process, if known
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tasks: Vec<String>,
 }
 
 fn open_lock_file(name: &str) -> Result<(std::fs::File, CreateOptions), Error> {
@@ -133,6 +136,7 @@ pub fn update_active_operations(name: &str, operation: Operation, count: i64) ->
                 Operation::Write => ActiveOperationStats { read: 0, write: 1 },
                 Operation::Lookup => ActiveOperationStats { read: 0, write: 0 },
             },
+            tasks: Vec::new(),
         })
     }
     replace_file(
@@ -142,3 +146,57 @@ pub fn update_active_operations(name: &str, operation: Operation, count: i64) ->
         false,
     )
 }
+
+/// Returns the UPIDs of the tasks holding active operations on datastore `name`.
+///
+/// Only operations whose owner was recorded with [`update_operation_tasks`] are included.
+pub fn get_active_tasks(name: &str) -> Result<Vec<String>, Error> {
+    let path = PathBuf::from(format!("{}/{}", crate::ACTIVE_OPERATIONS_DIR, name));
+
+    let mut tasks: Vec<String> = match file_read_optional_string(path)? {
+        Some(data) => serde_json::from_str::<Vec<TaskOperations>>(&data)?
+            .into_iter()
+            .filter(
+                |task| match procfs::check_process_running(task.pid as pid_t) {
+                    Some(stat) => task.starttime == stat.starttime,
+                    None => false,
+                },
+            )
+            .flat_map(|task| task.tasks)
+            .collect(),
+        None => Vec::new(),
+    };
+    tasks.sort();
+    tasks.dedup();
+
+    Ok(tasks)
+}
+
+/// Add (or remove) `upid` to the tasks holding operations on datastore `name` in this process.
+pub fn update_operation_tasks(name: &str, upid: &str, add: bool) -> Result<(), Error> {
+    let path = PathBuf::from(format!("{}/{}", crate::ACTIVE_OPERATIONS_DIR, name));
+
+    let (_lock, options) = open_lock_file(name)?;
+
+    let pid = std::process::id();
+    let mut tasks: Vec<TaskOperations> = match file_read_optional_string(&path)? {
+        Some(data) => serde_json::from_str(&data)?,
+        None => Vec::new(),
+    };
+
+    // the entry is created when the operation is registered, nothing to do without one
+    if let Some(task) = tasks.iter_mut().find(|task| task.pid == pid) {
+        if add {
+            task.tasks.push(upid.to_string());
+        } else if let Some(pos) = task.tasks.iter().position(|task| task == upid) {
+            task.tasks.remove(pos);
+        }
+    }
+
+    replace_file(
+        &path,
+        serde_json::to_string(&tasks)?.as_bytes(),
+        options,
+        false,
+    )
+}
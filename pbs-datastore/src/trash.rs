@@ -0,0 +1,229 @@
+//! Trash for deleted snapshots.
+//!
+//! With a trash retention configured, deleting a snapshot only moves its directory into the
+//! `.trash` directory of the datastore, using the same `[ns/<ns>/]<type>/<id>/<time>` layout as
+//! the datastore itself. The index files still reference their chunks there, so garbage collection
+//! keeps them until the entry is purged after the retention.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use proxmox_sys::fs::{replace_file, CreateOptions};
+
+use pbs_api_types::{parse_ns_and_snapshot, Authid, BackupNamespace, TrashItem};
+
+use crate::lock_tracking::lock_dir_noblock;
+use crate::{BackupDir, DataStore};
+
+/// Name of the trash directory in the datastore base directory.
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+/// Metadata stored in the directory of a trashed snapshot.
+const TRASH_INFO_FILE_NAME: &str = ".trash-info";
+
+#[derive(Serialize, Deserialize)]
+struct TrashInfo {
+    /// Time of the deletion
+    deleted: i64,
+    /// Owner of the group at the time of deletion, used if the group is gone on restore
+    owner: Option<Authid>,
+}
+
+fn trash_path(store: &DataStore, ns: &BackupNamespace, dir: &pbs_api_types::BackupDir) -> PathBuf {
+    let mut path = store.base_path();
+    path.push(TRASH_DIR_NAME);
+    path.push(ns.path());
+    path.push(dir.to_string());
+    path
+}
+
+fn read_trash_info(path: &Path) -> Result<TrashInfo, Error> {
+    let path = path.join(TRASH_INFO_FILE_NAME);
+    let data =
+        std::fs::read(&path).map_err(|err| format_err!("unable to read {path:?} - {err}"))?;
+    serde_json::from_slice(&data).map_err(|err| format_err!("unable to parse {path:?} - {err}"))
+}
+
+/// Move a snapshot to the trash. The caller must hold the snapshot and manifest locks.
+///
+/// An older trash entry of the same snapshot is replaced. This is safe while garbage collection
+/// runs, its mark phase looks for index files moved into the trash behind its back at the end.
+pub(crate) fn move_to_trash(snapshot: &BackupDir) -> Result<(), Error> {
+    let store = snapshot.datastore();
+    let source = snapshot.full_path();
+    let target = trash_path(store, snapshot.backup_ns(), snapshot.dir());
+
+    if target.exists() {
+        std::fs::remove_dir_all(&target)
+            .map_err(|err| format_err!("removing old trash entry {target:?} failed - {err}"))?;
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|err| format_err!("unable to create trash directory {parent:?} - {err}"))?;
+    }
+
+    let info = TrashInfo {
+        deleted: proxmox_time::epoch_i64(),
+        owner: snapshot.get_owner().ok(),
+    };
+    let info_path = source.join(TRASH_INFO_FILE_NAME);
+    replace_file(
+        &info_path,
+        &serde_json::to_vec(&info)?,
+        CreateOptions::new(),
+        false,
+    )?;
+
+    log::info!("moving backup snapshot {source:?} to the trash");
+    if let Err(err) = std::fs::rename(&source, &target) {
+        let _ = std::fs::remove_file(&info_path);
+        bail!("moving backup snapshot {source:?} to the trash failed - {err}");
+    }
+
+    Ok(())
+}
+
+/// List the snapshots in the trash of `store`.
+pub fn list_trash(store: &DataStore) -> Result<Vec<TrashItem>, Error> {
+    let trash_dir = store.base_path().join(TRASH_DIR_NAME);
+    let retention = store.trash_retention() as i64 * 3600;
+
+    let mut list = Vec::new();
+    for entry in walkdir::WalkDir::new(&trash_dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => {
+                continue; // no trash yet, or purged concurrently
+            }
+            Err(err) => bail!("unable to list trash - {err}"),
+        };
+        if entry.file_name() != TRASH_INFO_FILE_NAME {
+            continue;
+        }
+        let snapshot_path = match entry.path().parent() {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let (ns, backup) = match snapshot_path
+            .strip_prefix(&trash_dir)
+            .ok()
+            .and_then(Path::to_str)
+            .map(parse_ns_and_snapshot)
+        {
+            Some(Ok(parsed)) => parsed,
+            _ => {
+                log::warn!("ignoring unexpected trash entry {snapshot_path:?}");
+                continue;
+            }
+        };
+        let info = read_trash_info(snapshot_path)?;
+
+        list.push(TrashItem {
+            ns,
+            backup,
+            deleted: info.deleted,
+            purge_after: info.deleted + retention,
+            owner: info.owner,
+            protected: snapshot_path.join(".protected").exists(),
+        });
+    }
+
+    list.sort_unstable_by(|a, b| (&a.ns, &a.backup).cmp(&(&b.ns, &b.backup)));
+
+    Ok(list)
+}
+
+/// Restore a snapshot from the trash back into its group.
+///
+/// Fails if a snapshot with the same backup time exists in the group again. If the group was
+/// removed in the meantime, it is recreated with the owner at the time of deletion.
+pub fn restore_from_trash(
+    store: &DataStore,
+    ns: &BackupNamespace,
+    dir: &pbs_api_types::BackupDir,
+) -> Result<(), Error> {
+    // the index files must not move past the marking of a running garbage collection, keep it
+    // from starting until the snapshot is back in place
+    let _gc_guard = store.try_lock_gc().ok_or_else(|| {
+        format_err!("cannot restore snapshot {dir} while garbage collection is running")
+    })?;
+
+    let source = trash_path(store, ns, dir);
+    if !source.exists() {
+        bail!("snapshot {dir} not found in trash");
+    }
+    let _trash_guard = lock_dir_noblock(&source, "trash entry", "possibly being purged")?;
+    let info = read_trash_info(&source)?;
+
+    if !store.namespace_exists(ns) {
+        bail!("namespace '{ns}' of snapshot {dir} does not exist anymore");
+    }
+
+    let owner = match info.owner {
+        Some(owner) => owner,
+        None => Authid::root_auth_id().clone(),
+    };
+    let (_owner, _group_guard) = store.create_locked_backup_group(ns, &dir.group, &owner)?;
+
+    let target = store.snapshot_path(ns, dir);
+    nix::fcntl::renameat2(
+        None,
+        &source,
+        None,
+        &target,
+        nix::fcntl::RenameFlags::RENAME_NOREPLACE,
+    )
+    .map_err(|err| match err {
+        nix::errno::Errno::EEXIST | nix::errno::Errno::ENOTEMPTY => {
+            format_err!("snapshot {dir} was created again, cannot restore it from the trash")
+        }
+        err => format_err!("restoring snapshot {dir} from the trash failed - {err}"),
+    })?;
+
+    let _ = std::fs::remove_file(target.join(TRASH_INFO_FILE_NAME));
+    log::info!("restored backup snapshot {target:?} from the trash");
+
+    Ok(())
+}
+
+/// Remove the trash entries older than the trash retention, or all of them with `all`.
+///
+/// Returns the number of purged snapshots.
+pub fn purge_trash(store: &DataStore, all: bool) -> Result<usize, Error> {
+    let trash_dir = store.base_path().join(TRASH_DIR_NAME);
+    let cutoff = proxmox_time::epoch_i64() - store.trash_retention() as i64 * 3600;
+
+    let mut purged = 0;
+    for item in list_trash(store)? {
+        if !all && item.deleted > cutoff {
+            continue;
+        }
+
+        let path = trash_path(store, &item.ns, &item.backup);
+        let _guard = match lock_dir_noblock(&path, "trash entry", "possibly being restored") {
+            Ok(guard) => guard,
+            Err(err) => {
+                log::warn!("skipping trash entry {path:?} - {err}");
+                continue;
+            }
+        };
+        std::fs::remove_dir_all(&path)
+            .map_err(|err| format_err!("purging trash entry {path:?} failed - {err}"))?;
+        purged += 1;
+
+        // remove the then empty group, type and namespace directories
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != trash_dir) {
+            if std::fs::remove_dir(dir).is_err() {
+                break;
+            }
+            parent = dir.parent();
+        }
+    }
+
+    Ok(purged)
+}
@@ -0,0 +1,116 @@
+//! Compression of chunk data transferred in backup and reader protocol sessions
+//!
+//! Chunk uploads and downloads are wrapped in the codec negotiated with the
+//! [`BACKUP_WIRE_COMPRESSION_HEADER`](crate::BACKUP_WIRE_COMPRESSION_HEADER) at session start.
+//! This only affects the transfer, the chunks themselves are stored unchanged.
+
+use anyhow::{bail, format_err, Error};
+
+use pbs_api_types::WireCompression;
+
+/// Codecs supported by this implementation, in default order of preference.
+pub const SUPPORTED_CODECS: [WireCompression; 3] = [
+    WireCompression::Zstd,
+    WireCompression::Lz4,
+    WireCompression::None,
+];
+
+/// Returns the header value for a client preferring `preferred`.
+///
+/// The preferred codec comes first, followed by all other supported codecs.
+pub fn client_offer(preferred: WireCompression) -> String {
+    std::iter::once(preferred)
+        .chain(SUPPORTED_CODECS.into_iter().filter(|c| *c != preferred))
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Select the codec for a session from the offer of a client.
+///
+/// Picks the first codec of the offer which is supported, unknown codecs are skipped. Falls back
+/// to no compression without a usable offer.
+pub fn select_codec(offer: &str) -> WireCompression {
+    offer
+        .split(',')
+        .filter_map(|codec| codec.trim().parse::<WireCompression>().ok())
+        .find(|codec| SUPPORTED_CODECS.contains(codec))
+        .unwrap_or_default()
+}
+
+/// Upper bound of the encoded size of `size` bytes of data.
+pub fn max_encoded_size(codec: WireCompression, size: usize) -> usize {
+    match codec {
+        WireCompression::None => size,
+        WireCompression::Lz4 => 4 + lz4_flex::block::get_maximum_output_size(size),
+        WireCompression::Zstd => zstd::zstd_safe::compress_bound(size),
+    }
+}
+
+/// Encode `data` for the transfer.
+pub fn encode(codec: WireCompression, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match codec {
+        WireCompression::None => Ok(data),
+        WireCompression::Lz4 => Ok(lz4_flex::block::compress_prepend_size(&data)),
+        WireCompression::Zstd => Ok(zstd::bulk::compress(&data, 1)?),
+    }
+}
+
+/// Decode transferred data, which must not decode to more than `max_size` bytes.
+pub fn decode(codec: WireCompression, data: Vec<u8>, max_size: usize) -> Result<Vec<u8>, Error> {
+    let decoded = match codec {
+        WireCompression::None => data,
+        WireCompression::Lz4 => {
+            if data.len() < 4 {
+                bail!("lz4 encoded data too short");
+            }
+            let (size, data) = data.split_at(4);
+            let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+            if size > max_size {
+                bail!("lz4 encoded data too large ({size} > {max_size})");
+            }
+            lz4_flex::block::decompress(data, size)
+                .map_err(|err| format_err!("lz4 decoding failed - {err}"))?
+        }
+        WireCompression::Zstd => zstd::bulk::decompress(&data, max_size)
+            .map_err(|err| format_err!("zstd decoding failed - {err}"))?,
+    };
+
+    if decoded.len() > max_size {
+        bail!("decoded data too large ({} > {max_size})", decoded.len());
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_codec_selection() {
+        assert_eq!(select_codec(""), WireCompression::None);
+        assert_eq!(select_codec("brotli, lz4,zstd"), WireCompression::Lz4);
+        assert_eq!(
+            select_codec(&client_offer(WireCompression::Zstd)),
+            WireCompression::Zstd
+        );
+        assert_eq!(client_offer(WireCompression::Lz4), "lz4,zstd,none");
+    }
+
+    #[test]
+    fn test_codec_roundtrip() -> Result<(), Error> {
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+
+        for codec in SUPPORTED_CODECS {
+            let encoded = encode(codec, data.clone())?;
+            assert!(encoded.len() <= max_encoded_size(codec, data.len()));
+            assert_eq!(decode(codec, encoded.clone(), data.len())?, data);
+            if codec != WireCompression::None {
+                assert!(decode(codec, encoded, data.len() - 1).is_err());
+            }
+        }
+
+        Ok(())
+    }
+}
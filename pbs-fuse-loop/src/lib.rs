@@ -1,4 +1,5 @@
 pub mod loopdev;
+pub mod nbd;
 
 mod fuse_loop;
 pub use fuse_loop::*;
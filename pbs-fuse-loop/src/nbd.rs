@@ -0,0 +1,357 @@
+//! Map a raw data reader as a read-only network block device
+//!
+//! This uses the kernel NBD client with a local socket pair, so no network is involved. The
+//! kernel sends its requests over the socket and we answer them from the reader.
+
+use std::fs::{read_to_string, remove_file, File, OpenOptions};
+use std::io::SeekFrom;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, format_err, Error};
+use futures::channel::mpsc::Receiver;
+use futures::stream::StreamExt;
+use nix::sys::signal::{self, Signal};
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use nix::unistd::Pid;
+use regex::Regex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+use proxmox_time::epoch_i64;
+
+const RUN_DIR: &str = "/run/pbs-nbd";
+
+lazy_static::lazy_static! {
+    static ref NBD_DEV_REGEX: Regex = Regex::new(r"^/dev/nbd(\d+)$").unwrap();
+}
+
+const NBD_IOCTL: libc::c_ulong = 0xab;
+const NBD_SET_SOCK: libc::c_ulong = 0;
+const NBD_SET_BLKSIZE: libc::c_ulong = 1;
+const NBD_SET_SIZE: libc::c_ulong = 2;
+const NBD_DO_IT: libc::c_ulong = 3;
+const NBD_CLEAR_SOCK: libc::c_ulong = 4;
+const NBD_CLEAR_QUE: libc::c_ulong = 5;
+const NBD_DISCONNECT: libc::c_ulong = 8;
+const NBD_SET_FLAGS: libc::c_ulong = 10;
+
+const NBD_FLAG_HAS_FLAGS: libc::c_ulong = 1 << 0;
+const NBD_FLAG_READ_ONLY: libc::c_ulong = 1 << 1;
+
+const NBD_REQUEST_MAGIC: u32 = 0x25609513;
+const NBD_REPLY_MAGIC: u32 = 0x67446698;
+
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_CMD_FLUSH: u16 = 3;
+
+const NBD_BLOCK_SIZE: u64 = 512;
+// the kernel never sends larger requests, but do not trust it blindly
+const NBD_MAX_REQUEST_SIZE: u32 = 32 * 1024 * 1024;
+
+fn nbd_ioctl(fd: RawFd, request: libc::c_ulong, arg: libc::c_ulong) -> Result<(), Error> {
+    let res = unsafe { libc::ioctl(fd, ((NBD_IOCTL << 8) | request) as _, arg) };
+    nix::errno::Errno::result(res)?;
+    Ok(())
+}
+
+/// Returns the name of a NBD device path, e.g. "nbd0" for "/dev/nbd0".
+fn device_name(device: &str) -> Result<&str, Error> {
+    if !NBD_DEV_REGEX.is_match(device) {
+        bail!("malformed NBD device path, must be in format '/dev/nbdX'");
+    }
+    Ok(&device[5..])
+}
+
+fn run_path(device: &str) -> Result<PathBuf, Error> {
+    let mut path = PathBuf::from(RUN_DIR);
+    path.push(device_name(device)?);
+    Ok(path)
+}
+
+/// Represents a reader which has been connected to a NBD device. Create with map_nbd, then
+/// daemonize or otherwise finish setup and poll the future returned by 'main' until completion.
+pub struct NbdSession<R: AsyncRead + AsyncSeek + Unpin> {
+    reader: R,
+    size: u64,
+    device: File,
+    socket: Option<UnixStream>,
+    run_path: PathBuf,
+    pub device_path: String,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> NbdSession<R> {
+    /// Connect the given reader to the NBD device at `device_path` (/dev/nbdN) in read-only
+    /// mode. Creates a run file containing our PID and `name` for listing and unmapping.
+    pub async fn map_nbd<P: AsRef<str>>(
+        device_path: &str,
+        size: u64,
+        mut reader: R,
+        name: P,
+    ) -> Result<Self, Error> {
+        let dev_name = device_name(device_path)?;
+
+        // attempt a single read to check if the reader is configured correctly
+        let _ = reader.read_u8().await?;
+
+        if Path::new(&format!("/sys/block/{dev_name}/pid")).exists() {
+            bail!("NBD device {device_path} is already in use");
+        }
+
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(device_path)
+            .map_err(|err| {
+                format_err!("unable to open {device_path} (is the 'nbd' module loaded?) - {err}")
+            })?;
+
+        let (ours, theirs) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )?;
+        let ours = unsafe { OwnedFd::from_raw_fd(ours) };
+        let theirs = unsafe { OwnedFd::from_raw_fd(theirs) };
+
+        let fd = device.as_raw_fd();
+        let setup = || -> Result<(), Error> {
+            nbd_ioctl(fd, NBD_SET_BLKSIZE, NBD_BLOCK_SIZE as libc::c_ulong)?;
+            nbd_ioctl(fd, NBD_SET_SIZE, size as libc::c_ulong)?;
+            nbd_ioctl(fd, NBD_SET_FLAGS, NBD_FLAG_HAS_FLAGS | NBD_FLAG_READ_ONLY)?;
+            nbd_ioctl(fd, NBD_SET_SOCK, theirs.as_raw_fd() as libc::c_ulong)?;
+            Ok(())
+        };
+        if let Err(err) = setup() {
+            let _ = nbd_ioctl(fd, NBD_CLEAR_SOCK, 0);
+            bail!("unable to set up NBD device {device_path} - {err}");
+        }
+        // the kernel holds its own reference to the socket now
+        drop(theirs);
+
+        let socket = std::os::unix::net::UnixStream::from(ours);
+        socket.set_nonblocking(true)?;
+        let socket = UnixStream::from_std(socket)?;
+
+        // write run file so unmap can later send us a signal to exit
+        std::fs::create_dir_all(RUN_DIR)?;
+        let run_path = run_path(device_path)?;
+        let pid = unsafe { libc::getpid() };
+        std::fs::write(&run_path, format!("{pid}\n{}\n", name.as_ref()))?;
+
+        Ok(Self {
+            reader,
+            size,
+            device,
+            socket: Some(socket),
+            run_path,
+            device_path: device_path.to_string(),
+        })
+    }
+
+    /// Serves the requests of the kernel until the device is disconnected. Send a message on
+    /// abort_chan to disconnect the device, which also happens on unmap.
+    pub async fn main(&mut self, mut abort_chan: Receiver<()>) -> Result<(), Error> {
+        let mut socket = match self.socket.take() {
+            Some(socket) => socket,
+            None => panic!("internal error: nbd::main called twice"),
+        };
+
+        let do_it_device = self.device.try_clone()?;
+        let do_it = tokio::task::spawn_blocking(move || {
+            // blocks until the device is disconnected
+            let res = nbd_ioctl(do_it_device.as_raw_fd(), NBD_DO_IT, 0);
+            let _ = nbd_ioctl(do_it_device.as_raw_fd(), NBD_CLEAR_QUE, 0);
+            let _ = nbd_ioctl(do_it_device.as_raw_fd(), NBD_CLEAR_SOCK, 0);
+            res
+        });
+
+        let abort_device = self.device.try_clone()?;
+        let abort = tokio::spawn(async move {
+            if abort_chan.next().await.is_some() {
+                // the kernel answers with a disconnect request, ending the loop below
+                if let Err(err) = nbd_ioctl(abort_device.as_raw_fd(), NBD_DISCONNECT, 0) {
+                    log::error!("error while disconnecting NBD device - {err}");
+                }
+            }
+        });
+
+        let res = self.serve(&mut socket).await;
+
+        drop(socket);
+        abort.abort();
+        let do_it_res = do_it.await?;
+        let _ = remove_file(&self.run_path);
+
+        res?;
+        if let Err(err) = do_it_res {
+            // disconnecting the device is reported as error by some kernels
+            log::debug!("NBD_DO_IT returned error - {err}");
+        }
+
+        Ok(())
+    }
+
+    async fn serve(&mut self, socket: &mut UnixStream) -> Result<(), Error> {
+        let mut header = [0u8; 28];
+        loop {
+            match socket.read_exact(&mut header).await {
+                Ok(_) => (),
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => bail!("error reading NBD request - {err}"),
+            }
+
+            let magic = u32::from_be_bytes(header[0..4].try_into().unwrap());
+            if magic != NBD_REQUEST_MAGIC {
+                bail!("invalid NBD request magic {magic:#x}");
+            }
+            let command = u16::from_be_bytes(header[6..8].try_into().unwrap());
+            let handle = &header[8..16];
+            let offset = u64::from_be_bytes(header[16..24].try_into().unwrap());
+            let length = u32::from_be_bytes(header[24..28].try_into().unwrap());
+
+            let (error, data) = match command {
+                NBD_CMD_READ => match self.read(offset, length).await {
+                    Ok(data) => (0, Some(data)),
+                    Err(err) => {
+                        log::error!("NBD read of {length} bytes at {offset} failed - {err}");
+                        (libc::EIO, None)
+                    }
+                },
+                NBD_CMD_WRITE => {
+                    // drain the payload, the device is read-only
+                    let mut payload = (&mut *socket).take(length as u64);
+                    tokio::io::copy(&mut payload, &mut tokio::io::sink()).await?;
+                    (libc::EPERM, None)
+                }
+                NBD_CMD_FLUSH => (0, None),
+                NBD_CMD_DISC => return Ok(()),
+                _ => (libc::EINVAL, None),
+            };
+
+            let mut reply = Vec::with_capacity(16 + data.as_ref().map(Vec::len).unwrap_or(0));
+            reply.extend_from_slice(&NBD_REPLY_MAGIC.to_be_bytes());
+            reply.extend_from_slice(&(error as u32).to_be_bytes());
+            reply.extend_from_slice(handle);
+            if let Some(data) = data {
+                reply.extend_from_slice(&data);
+            }
+            socket.write_all(&reply).await?;
+        }
+    }
+
+    async fn read(&mut self, offset: u64, length: u32) -> Result<Vec<u8>, Error> {
+        if length > NBD_MAX_REQUEST_SIZE {
+            bail!("request too large");
+        }
+        if offset
+            .checked_add(length as u64)
+            .map_or(true, |end| end > self.size)
+        {
+            bail!("request beyond end of image");
+        }
+
+        let mut data = vec![0u8; length as usize];
+        self.reader.seek(SeekFrom::Start(offset)).await?;
+        self.reader.read_exact(&mut data).await?;
+        Ok(data)
+    }
+}
+
+/// Returns the PID and name stored in the run file, removing it if the process is gone.
+fn read_run_file(path: &Path) -> Result<Option<(Pid, String)>, Error> {
+    let content = match read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => bail!("error reading run file {path:?} - {err}"),
+    };
+
+    let mut lines = content.lines();
+    let pid_str = lines.next().unwrap_or_default();
+    let pid = pid_str
+        .parse::<i32>()
+        .map_err(|err| format_err!("malformed PID ({pid_str}) in run file - {err}"))?;
+    let name = lines.next().unwrap_or_default().to_string();
+
+    let pid = Pid::from_raw(pid);
+    match signal::kill(pid, None) {
+        Ok(()) => Ok(Some((pid, name))),
+        Err(nix::errno::Errno::ESRCH) => {
+            let _ = remove_file(path);
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Returns the currently active NBD mappings as ("name", "/dev/nbdX") tuples.
+pub fn find_all_mappings() -> Result<Vec<(String, String)>, Error> {
+    let mut list = Vec::new();
+
+    if !Path::new(RUN_DIR).exists() {
+        return Ok(list);
+    }
+
+    for ent in proxmox_sys::fs::read_subdir(libc::AT_FDCWD, Path::new(RUN_DIR))?.flatten() {
+        let file = ent.file_name().to_string_lossy();
+        if file == "." || file == ".." {
+            continue;
+        }
+        let mut path = PathBuf::from(RUN_DIR);
+        path.push(file.as_ref());
+        if let Ok(Some((_pid, name))) = read_run_file(&path) {
+            list.push((name, format!("/dev/{file}")));
+        }
+    }
+
+    Ok(list)
+}
+
+/// Try and unmap a running proxmox-backup-client instance from the given /dev/nbdN device
+pub fn unmap_device<S: AsRef<str>>(device: S) -> Result<(), Error> {
+    let device = device.as_ref();
+    let pid = match read_run_file(&run_path(device)?)? {
+        Some((pid, _name)) => pid,
+        None => bail!("nothing mapped to {device}"),
+    };
+
+    // send SIGINT to trigger disconnect and exit in target process
+    match signal::kill(pid, Signal::SIGINT) {
+        Ok(()) => {}
+        Err(nix::errno::Errno::ESRCH) => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+
+    // block until unmap is complete or timeout
+    let start = epoch_i64();
+    loop {
+        match signal::kill(pid, None) {
+            Ok(_) => {
+                // 10 second timeout, then assume failure
+                if (epoch_i64() - start) > 10 {
+                    bail!("timed out waiting for PID '{pid}' to exit");
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(nix::errno::Errno::ESRCH) => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Try and unmap a running proxmox-backup-client instance from the given name. Returns false
+/// if no NBD mapping with that name exists.
+pub fn unmap_name<S: AsRef<str>>(name: S) -> Result<bool, Error> {
+    for (mapping, device) in find_all_mappings()? {
+        if mapping.ends_with(name.as_ref()) {
+            unmap_device(device)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -11,6 +11,11 @@ use pbs_api_types::{Fingerprint, Kdf, KeyInfo};
 
 use pbs_tools::crypt_config::CryptConfig;
 
+/// Scrypt cost parameters used for newly encrypted keys.
+const SCRYPT_N: u64 = 65536;
+const SCRYPT_R: u64 = 8;
+const SCRYPT_P: u64 = 1;
+
 /// Key derivation function configuration
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum KeyDerivationConfig {
@@ -29,6 +34,19 @@ pub enum KeyDerivationConfig {
 }
 
 impl KeyDerivationConfig {
+    /// Returns true if the configuration is weaker than what is used for newly created keys.
+    ///
+    /// PBKDF2 is always considered legacy, scrypt if any of its cost parameters is lower than the
+    /// current default.
+    pub fn is_legacy(&self) -> bool {
+        match self {
+            KeyDerivationConfig::Scrypt { n, r, p, .. } => {
+                *n < SCRYPT_N || *r < SCRYPT_R || *p < SCRYPT_P
+            }
+            KeyDerivationConfig::PBKDF2 { .. } => true,
+        }
+    }
+
     /// Derive a key from provided passphrase
     pub fn derive_key(&self, passphrase: &[u8]) -> Result<[u8; 32], Error> {
         let mut key = [0u8; 32];
@@ -130,9 +148,9 @@ impl KeyConfig {
 
         let kdf = match kdf {
             Kdf::Scrypt => KeyDerivationConfig::Scrypt {
-                n: 65536,
-                r: 8,
-                p: 1,
+                n: SCRYPT_N,
+                r: SCRYPT_R,
+                p: SCRYPT_P,
                 salt,
             },
             Kdf::PBKDF2 => KeyDerivationConfig::PBKDF2 { iter: 65535, salt },
@@ -393,3 +411,29 @@ fn fingerprint_checks() -> Result<(), Error> {
 
     Ok(())
 }
+
+#[test]
+fn legacy_kdf_checks() -> Result<(), Error> {
+    let salt = vec![0u8; 32];
+
+    let current = KeyDerivationConfig::Scrypt {
+        n: SCRYPT_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: salt.clone(),
+    };
+    assert!(!current.is_legacy());
+
+    let weak = KeyDerivationConfig::Scrypt {
+        n: 16384,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: salt.clone(),
+    };
+    assert!(weak.is_legacy());
+
+    let pbkdf2 = KeyDerivationConfig::PBKDF2 { iter: 65535, salt };
+    assert!(pbkdf2.is_legacy());
+
+    Ok(())
+}
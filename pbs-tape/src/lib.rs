@@ -268,6 +268,43 @@ impl MtxStatus {
         Ok(self.slots[(slot - 1) as usize].element_address)
     }
 
+    /// Check that media can be transferred from slot `from` to slot `to`.
+    ///
+    /// The source slot must contain media, and the destination slot must be empty.
+    pub fn check_transfer(&self, from: u64, to: u64) -> Result<(), Error> {
+        self.slot_address(from)?;
+        self.slot_address(to)?;
+
+        if from == to {
+            bail!("source and destination slot are the same ({})", from);
+        }
+
+        if let ElementStatus::Empty = self.slots[(from - 1) as usize].status {
+            let loaded_in = self
+                .drives
+                .iter()
+                .position(|drive| drive.loaded_slot == Some(from));
+            match loaded_in {
+                Some(drivenum) => bail!(
+                    "source slot {} is empty (media is loaded in drive {})",
+                    from,
+                    drivenum
+                ),
+                None => bail!("source slot {} is empty", from),
+            }
+        }
+
+        match &self.slots[(to - 1) as usize].status {
+            ElementStatus::Empty => Ok(()),
+            ElementStatus::VolumeTag(label_text) => bail!(
+                "destination slot {} is not empty (contains '{}')",
+                to,
+                label_text
+            ),
+            ElementStatus::Full => bail!("destination slot {} is not empty", to),
+        }
+    }
+
     pub fn drive_address(&self, drivenum: u64) -> Result<u16, Error> {
         if drivenum >= (self.drives.len() as u64) {
             bail!("invalid drive number '{}'", drivenum);
@@ -222,6 +222,8 @@ pub fn transfer_medium<F: AsRawFd>(
 ) -> Result<(), Error> {
     let status = read_element_status(file)?;
 
+    status.check_transfer(from_slot, to_slot)?;
+
     let transport_address = status.transport_address();
     let source_element_address = status.slot_address(from_slot)?;
     let target_element_address = status.slot_address(to_slot)?;
@@ -964,4 +966,41 @@ mod test {
         assert_eq!(page.storage_slots.len(), 2);
         Ok(())
     }
+
+    #[test]
+    fn transfer_slot_checks() -> Result<(), Error> {
+        let slot = |status| StorageElementStatus {
+            import_export: false,
+            status,
+            element_address: 0,
+        };
+        let status = MtxStatus {
+            drives: vec![DriveStatus {
+                loaded_slot: Some(2),
+                status: ElementStatus::VolumeTag("TAPE02".to_string()),
+                drive_serial_number: None,
+                vendor: None,
+                model: None,
+                element_address: 0,
+            }],
+            slots: vec![
+                slot(ElementStatus::VolumeTag("TAPE01".to_string())),
+                slot(ElementStatus::Empty),
+                slot(ElementStatus::Empty),
+                slot(ElementStatus::Full),
+            ],
+            transports: Vec::new(),
+        };
+
+        status.check_transfer(1, 3)?;
+        assert!(status.check_transfer(1, 1).is_err());
+        assert!(status.check_transfer(1, 5).is_err());
+        assert!(status.check_transfer(0, 1).is_err());
+        // source empty, media is in the drive
+        assert!(status.check_transfer(2, 3).is_err());
+        // destination occupied
+        assert!(status.check_transfer(1, 4).is_err());
+        assert!(status.check_transfer(4, 1).is_err());
+        Ok(())
+    }
 }
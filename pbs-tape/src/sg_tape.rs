@@ -24,11 +24,15 @@ pub use mam::*;
 mod report_density;
 pub use report_density::*;
 
+mod device_status;
+pub use device_status::*;
+
 use proxmox_io::{ReadExt, WriteExt};
 use proxmox_sys::error::SysResult;
 
 use pbs_api_types::{
-    Lp17VolumeStatistics, LtoDriveAndMediaStatus, LtoTapeDrive, MamAttribute, TapeDensity,
+    DeviceActivity, Lp17VolumeStatistics, LtoDriveAndMediaStatus, LtoTapeDrive, MamAttribute,
+    TapeDensity,
 };
 
 use crate::linux_list_drives::open_lto_tape_device;
@@ -651,6 +655,11 @@ impl SgTape {
         read_volume_statistics(&mut self.file)
     }
 
+    /// Read the current device activity
+    pub fn device_activity(&mut self) -> Result<DeviceActivity, Error> {
+        read_device_activity(&mut self.file)
+    }
+
     pub fn set_encryption(&mut self, key_data: Option<([u8; 32], Uuid)>) -> Result<(), Error> {
         let key = if let Some((ref key, ref uuid)) = key_data {
             // derive specialized key for each media-set
@@ -966,10 +975,13 @@ impl SgTape {
             vendor: self.info().vendor.clone(),
             product: self.info().product.clone(),
             revision: self.info().revision.clone(),
-            blocksize: drive_status.block_length,
-            compression: drive_status.compression,
-            buffer_mode: drive_status.buffer_mode,
-            density: drive_status.density_code.try_into()?,
+            blocksize: Some(drive_status.block_length),
+            compression: Some(drive_status.compression),
+            buffer_mode: Some(drive_status.buffer_mode),
+            density: Some(drive_status.density_code.try_into()?),
+            drive_activity: self.device_activity().ok(),
+            lock_owner: None,
+            lock_duration: None,
             alert_flags,
             write_protect: None,
             file_number: None,
@@ -0,0 +1,110 @@
+use std::os::unix::io::AsRawFd;
+
+use anyhow::{bail, format_err, Error};
+
+use proxmox_io::ReadExt;
+
+use pbs_api_types::DeviceActivity;
+
+use crate::sgutils2::SgRaw;
+
+/// SCSI command to query the current device activity
+///
+/// CDB: LOG SENSE / LP11h DT Device Status, parameter 0000h (Very high
+/// frequency data)
+pub fn read_device_activity<F: AsRawFd>(file: &mut F) -> Result<DeviceActivity, Error> {
+    let data = sg_read_device_status(file)?;
+
+    decode_device_activity(&data)
+}
+
+#[allow(clippy::vec_init_then_push)]
+fn sg_read_device_status<F: AsRawFd>(file: &mut F) -> Result<Vec<u8>, Error> {
+    let alloc_len: u16 = 8192;
+    let mut sg_raw = SgRaw::new(file, alloc_len as usize)?;
+
+    let mut cmd = Vec::new();
+    cmd.push(0x4D); // LOG SENSE
+    cmd.push(0);
+    cmd.push((1 << 6) | 0x11); // DT Device Status log page
+    cmd.push(0); // Subpage 0
+    cmd.push(0);
+    cmd.push(0); // parameter pointer
+    cmd.push(0);
+    cmd.extend(alloc_len.to_be_bytes()); // alloc len
+    cmd.push(0u8); // control byte
+
+    sg_raw
+        .do_command(&cmd)
+        .map_err(|err| format_err!("read tape device status failed - {}", err))
+        .map(|v| v.to_vec())
+}
+
+fn decode_device_activity(data: &[u8]) -> Result<DeviceActivity, Error> {
+    proxmox_lang::try_block!({
+        if !((data[0] & 0x7f) == 0x11 && data[1] == 0) {
+            bail!("invalid response");
+        }
+
+        let mut reader = &data[2..];
+
+        let page_len: u16 = unsafe { reader.read_be_value()? };
+        let page_len = page_len as usize;
+
+        if (page_len + 4) > data.len() {
+            bail!("invalid page length");
+        }
+        reader = &data[4..page_len + 4];
+
+        loop {
+            if reader.len() < 4 {
+                bail!("missing very high frequency data parameter");
+            }
+            let parameter_code: u16 = unsafe { reader.read_be_value()? };
+            let _control: u8 = unsafe { reader.read_be_value()? };
+            let parameter_len: u8 = unsafe { reader.read_be_value()? };
+            let parameter_len = parameter_len as usize;
+
+            if parameter_len > reader.len() {
+                bail!("invalid parameter length");
+            }
+
+            if parameter_code == 0x0000 {
+                // byte 2 of the VHF data contains the DT device activity
+                if parameter_len < 4 {
+                    bail!("invalid very high frequency data length");
+                }
+                return DeviceActivity::try_from(reader[2]);
+            }
+
+            reader = &reader[parameter_len..];
+        }
+    })
+    .map_err(|err: Error| format_err!("decode device activity failed - {}", err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_device_activity() -> Result<(), Error> {
+        let mut page = vec![
+            0x11, 0x00, 0x00, 0x10, // header, page length 16
+            0x00, 0x01, 0x03, 0x04, 0x01, 0x02, 0x03, 0x04, // unrelated parameter 0001h
+            0x00, 0x00, 0x03, 0x04, 0x81, 0x01, 0x06, 0x00, // VHF data, writing
+        ];
+        assert_eq!(decode_device_activity(&page)?, DeviceActivity::Writing);
+
+        page[18] = 0x08;
+        assert_eq!(decode_device_activity(&page)?, DeviceActivity::Rewinding);
+
+        page[18] = 0x42;
+        assert!(decode_device_activity(&page).is_err());
+
+        // truncated page
+        assert!(decode_device_activity(&page[..12]).is_err());
+
+        Ok(())
+    }
+}
@@ -64,3 +64,16 @@ pub fn render_bytes_human_readable(value: &Value, _record: &Value) -> Result<Str
     };
     Ok(text)
 }
+
+pub fn render_duration(value: &Value, _record: &Value) -> Result<String, Error> {
+    if value.is_null() {
+        return Ok(String::new());
+    }
+    let text = match value.as_u64() {
+        Some(secs) => {
+            proxmox_time::TimeSpan::from(std::time::Duration::from_secs(secs)).to_string()
+        }
+        None => value.to_string(),
+    };
+    Ok(text)
+}
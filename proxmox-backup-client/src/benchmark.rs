@@ -16,6 +16,7 @@ use proxmox_schema::{api, ApiType, ReturnType};
 
 use pbs_api_types::{BackupNamespace, BackupType};
 use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::tools::{extract_upload_concurrency_from_value, UPLOAD_CONCURRENCY_SCHEMA};
 use pbs_client::{BackupRepository, BackupWriter};
 use pbs_datastore::data_blob::{DataBlob, DataChunkBuilder};
 use pbs_key_config::{load_and_decrypt_key, KeyDerivationConfig};
@@ -113,6 +114,10 @@ static BENCHMARK_RESULT_2020_TOP: BenchmarkResult = BenchmarkResult {
                schema: KEYFILE_SCHEMA,
                optional: true,
            },
+           "upload-concurrency": {
+               schema: UPLOAD_CONCURRENCY_SCHEMA,
+               optional: true,
+           },
            "output-format": {
                schema: OUTPUT_FORMAT,
                optional: true,
@@ -132,6 +137,8 @@ pub async fn benchmark(
 
     let output_format = get_output_format(&param);
 
+    let upload_concurrency = extract_upload_concurrency_from_value(&param)?.unwrap_or(1);
+
     let crypt_config = match keyfile {
         None => None,
         Some(path) => {
@@ -145,7 +152,13 @@ pub async fn benchmark(
 
     // do repo tests first, because this may prompt for a password
     if let Some(repo) = repo {
-        test_upload_speed(&mut benchmark_result, repo, crypt_config.clone()).await?;
+        test_upload_speed(
+            &mut benchmark_result,
+            repo,
+            crypt_config.clone(),
+            upload_concurrency,
+        )
+        .await?;
     }
 
     test_crypt_speed(&mut benchmark_result)?;
@@ -221,6 +234,7 @@ async fn test_upload_speed(
     benchmark_result: &mut BenchmarkResult,
     repo: BackupRepository,
     crypt_config: Option<Arc<CryptConfig>>,
+    upload_concurrency: usize,
 ) -> Result<(), Error> {
     let backup_time = proxmox_time::epoch_i64();
 
@@ -236,11 +250,13 @@ async fn test_upload_speed(
         &(BackupType::Host, "benchmark".to_string(), backup_time).into(),
         false,
         true,
+        false,
+        None,
     )
     .await?;
 
-    log::debug!("Start TLS speed test");
-    let speed = client.upload_speedtest().await?;
+    log::debug!("Start TLS speed test ({upload_concurrency} concurrent uploads)");
+    let speed = client.upload_speedtest(upload_concurrency).await?;
 
     log::info!("TLS speed: {:.2} MB/s", speed / 1_000_000.0);
 
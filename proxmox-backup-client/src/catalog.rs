@@ -3,23 +3,26 @@ use std::os::unix::fs::OpenOptionsExt;
 use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
-use serde_json::Value;
+use serde_json::{json, Value};
 
+use pathpatterns::{MatchEntry, MatchType, PatternFlag};
 use proxmox_router::cli::*;
 use proxmox_schema::api;
 
-use pbs_api_types::BackupNamespace;
+use pbs_api_types::{print_ns_and_snapshot, BackupGroup, BackupNamespace};
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_client::{BackupReader, RemoteChunkReader};
 use pbs_tools::crypt_config::CryptConfig;
 use pbs_tools::json::required_string_param;
 
+use crate::local_catalog::{list_local_catalogs, prune_local_catalogs, search_local_catalog};
 use crate::{
-    complete_backup_snapshot, complete_group_or_snapshot, complete_namespace,
-    complete_pxar_archive_name, complete_repository, connect, crypto_parameters, decrypt_key,
-    dir_or_last_from_group, extract_repository_from_value, format_key_source, optional_ns_param,
-    record_repository, BackupDir, BufferedDynamicReadAt, BufferedDynamicReader, CatalogReader,
-    DynamicIndexReader, IndexFile, Shell, CATALOG_NAME, KEYFD_SCHEMA, REPO_URL_SCHEMA,
+    complete_backup_group, complete_backup_snapshot, complete_group_or_snapshot,
+    complete_namespace, complete_pxar_archive_name, complete_repository, connect,
+    crypto_parameters, decrypt_key, dir_or_last_from_group, extract_repository_from_value,
+    format_key_source, optional_ns_param, record_repository, show_archive_excludes, BackupDir,
+    BufferedDynamicReadAt, BufferedDynamicReader, CatalogReader, DynamicIndexReader, IndexFile,
+    Shell, CATALOG_NAME, KEYFD_SCHEMA, REPO_URL_SCHEMA,
 };
 
 #[api(
@@ -46,11 +49,17 @@ use crate::{
                 schema: KEYFD_SCHEMA,
                 optional: true,
             },
+            "show-excludes": {
+                type: Boolean,
+                description: "Show the exclude patterns recorded for the file archives.",
+                optional: true,
+                default: false,
+            },
         }
    }
 )]
 /// Dump catalog.
-async fn dump_catalog(param: Value) -> Result<Value, Error> {
+async fn dump_catalog(param: Value, show_excludes: bool) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
     let backup_ns = optional_ns_param(&param)?;
@@ -87,6 +96,14 @@ async fn dump_catalog(param: Value) -> Result<Value, Error> {
     let (manifest, _) = client.download_manifest().await?;
     manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
 
+    if show_excludes {
+        for file in manifest.files() {
+            if file.filename.ends_with(".pxar.didx") {
+                show_archive_excludes(&manifest, &file.filename);
+            }
+        }
+    }
+
     let index = client
         .download_dynamic_index(&manifest, CATALOG_NAME)
         .await?;
@@ -261,6 +278,117 @@ async fn catalog_shell(param: Value) -> Result<(), Error> {
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            pattern: {
+                type: String,
+                description: "Match pattern, for example '*.conf' or '/root.pxar.didx/etc/**'.",
+            },
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Only search the snapshots of this backup group, in all namespaces \
+                    unless 'ns' is given.",
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Search the local catalog copies of a repository, without connecting to the server.
+///
+/// Only snapshots created with a local catalog are searched, see the 'local-catalog' option of
+/// the backup command.
+fn search_catalog(pattern: String, group: Option<String>, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let output_format = get_output_format(&param);
+
+    // without namespace, all namespaces are searched
+    let ns = match param.get("ns") {
+        Some(_) => Some(optional_ns_param(&param)?),
+        None => None,
+    };
+    let group: Option<BackupGroup> = group.map(|group| group.parse()).transpose()?;
+
+    let pattern = MatchEntry::parse_pattern(pattern, PatternFlag::PATH_NAME, MatchType::Include)
+        .map_err(|err| format_err!("invalid match pattern - {err}"))?;
+
+    let catalogs = list_local_catalogs(&repo, ns.as_ref(), group.as_ref())?;
+    if catalogs.is_empty() {
+        log::warn!("no local catalogs found for repository '{repo}'");
+    }
+
+    let mut list = Vec::new();
+    for catalog in catalogs {
+        let snapshot = print_ns_and_snapshot(&catalog.ns, &catalog.snapshot);
+        let mut callback = |path: &[u8]| -> Result<(), Error> {
+            let path = String::from_utf8_lossy(path);
+            if output_format == "text" {
+                println!("{snapshot}: {path}");
+            } else {
+                list.push(json!({ "snapshot": snapshot, "path": path }));
+            }
+            Ok(())
+        };
+        if let Err(err) = search_local_catalog(&catalog.path, &pattern, &mut callback) {
+            log::error!("unable to search local catalog of {snapshot} - {err}");
+        }
+    }
+
+    if output_format != "text" {
+        format_and_print_result(&Value::from(list), &output_format);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Remove the local catalog copies of snapshots which do not exist on the server anymore.
+async fn prune_local_catalog_cache(param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let client = connect(&repo)?;
+
+    let mut namespaces: Vec<BackupNamespace> = list_local_catalogs(&repo, None, None)?
+        .into_iter()
+        .map(|catalog| catalog.ns)
+        .collect();
+    namespaces.dedup(); // the list is sorted by namespace
+
+    let mut removed = 0;
+    for ns in namespaces {
+        match prune_local_catalogs(&client, &repo, &ns, None).await {
+            Ok(count) => removed += count,
+            Err(err) => log::error!("unable to prune local catalogs of namespace '{ns}' - {err}"),
+        }
+    }
+
+    log::info!("removed {removed} local catalogs");
+
+    record_repository(&repo);
+
+    Ok(())
+}
+
 pub fn catalog_mgmt_cli() -> CliCommandMap {
     let catalog_shell_cmd_def = CliCommand::new(&API_METHOD_CATALOG_SHELL)
         .arg_param(&["snapshot", "archive-name"])
@@ -275,7 +403,18 @@ pub fn catalog_mgmt_cli() -> CliCommandMap {
         .completion_cb("ns", complete_namespace)
         .completion_cb("snapshot", complete_backup_snapshot);
 
+    let catalog_search_cmd_def = CliCommand::new(&API_METHOD_SEARCH_CATALOG)
+        .arg_param(&["pattern"])
+        .completion_cb("repository", complete_repository)
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group);
+
+    let catalog_prune_cache_cmd_def = CliCommand::new(&API_METHOD_PRUNE_LOCAL_CATALOG_CACHE)
+        .completion_cb("repository", complete_repository);
+
     CliCommandMap::new()
         .insert("dump", catalog_dump_cmd_def)
         .insert("shell", catalog_shell_cmd_def)
+        .insert("search", catalog_search_cmd_def)
+        .insert("prune-cache", catalog_prune_cache_cmd_def)
 }
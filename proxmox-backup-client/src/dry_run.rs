@@ -0,0 +1,309 @@
+//! Estimate what a backup would upload, without starting a backup session on the server.
+//!
+//! The sources are read and chunked as for a real backup. Chunks are considered known if they
+//! are referenced by the same archive of the last snapshot in the backup group, or if they
+//! appeared before in the same archive, like the backup writer does. Nothing gets uploaded and
+//! no snapshot is created, only a reader session on the last snapshot is used.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{format_err, Error};
+use futures::stream::{Stream, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_router::cli::{
+    default_table_format_options, format_and_print_result_full, ColumnConfig,
+};
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType, Schema};
+
+use pbs_api_types::{BackupGroup, BackupNamespace, CryptMode, SnapshotListItem};
+use pbs_client::pxar::PxarCreateOptions;
+use pbs_client::{
+    BackupReader, ChunkStream, FixedChunkStream, HttpClient, PxarBackupStream, StreamSource,
+};
+use pbs_datastore::catalog::CatalogWriter;
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_tools::crypt_config::CryptConfig;
+
+use crate::api_datastore_list_snapshots;
+
+#[api]
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Estimated upload of a single archive.
+pub struct ArchiveEstimate {
+    /// Archive name
+    pub archive: String,
+    /// Size of the archive data
+    pub size: u64,
+    /// Data which would be uploaded, before compression
+    pub upload_size: u64,
+    /// Data of already known chunks, which would not be uploaded
+    pub reused_size: u64,
+    /// Number of regular files, only available for directory archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<u64>,
+}
+
+/// Collects upload estimates for the archives of a dry-run backup.
+pub struct DryRun {
+    reader: Option<(Arc<BackupReader>, BackupManifest)>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    crypt_mode: CryptMode,
+    estimates: Vec<ArchiveEstimate>,
+}
+
+impl DryRun {
+    /// Prepare a dry run, using the last finished snapshot of `group` to look up known chunks.
+    pub async fn start(
+        client: &HttpClient,
+        store: &str,
+        ns: &BackupNamespace,
+        group: &BackupGroup,
+        crypt_config: Option<Arc<CryptConfig>>,
+        crypt_mode: CryptMode,
+    ) -> Result<Self, Error> {
+        let list = api_datastore_list_snapshots(client, store, ns, Some(group)).await?;
+        let list: Vec<SnapshotListItem> = serde_json::from_value(list)?;
+
+        let last = list
+            .into_iter()
+            .filter(|item| item.files.iter().any(|f| f.filename == MANIFEST_BLOB_NAME))
+            .max_by_key(|item| item.backup.time);
+
+        let reader = match last {
+            Some(item) => {
+                log::info!("Looking up known chunks in snapshot {}", item.backup);
+                match Self::open_snapshot(client, store, ns, &item, crypt_config.clone()).await {
+                    Ok(reader) => Some(reader),
+                    Err(err) => {
+                        log::error!("Couldn't re-use previous snapshot - {err}");
+                        None
+                    }
+                }
+            }
+            None => {
+                log::info!("No previous snapshot available.");
+                None
+            }
+        };
+
+        Ok(Self {
+            reader,
+            crypt_config,
+            crypt_mode,
+            estimates: Vec::new(),
+        })
+    }
+
+    async fn open_snapshot(
+        client: &HttpClient,
+        store: &str,
+        ns: &BackupNamespace,
+        item: &SnapshotListItem,
+        crypt_config: Option<Arc<CryptConfig>>,
+    ) -> Result<(Arc<BackupReader>, BackupManifest), Error> {
+        let reader =
+            BackupReader::start(client, crypt_config.clone(), store, ns, &item.backup, false)
+                .await?;
+        let (manifest, _) = reader.download_manifest().await?;
+        manifest.check_fingerprint(crypt_config.as_ref().map(Arc::as_ref))?;
+        Ok((reader, manifest))
+    }
+
+    // Digests of the chunks referenced by `archive` in the previous snapshot.
+    async fn known_chunks(&self, archive: &str) -> Result<HashSet<[u8; 32]>, Error> {
+        let mut known_chunks = HashSet::new();
+
+        let (reader, manifest) = match &self.reader {
+            Some(reader) => reader,
+            None => return Ok(known_chunks),
+        };
+        if manifest.lookup_file_info(archive).is_err() {
+            return Ok(known_chunks);
+        }
+
+        let index: Box<dyn IndexFile + Send> = match archive_type(archive)? {
+            ArchiveType::DynamicIndex => {
+                Box::new(reader.download_dynamic_index(manifest, archive).await?)
+            }
+            ArchiveType::FixedIndex => {
+                Box::new(reader.download_fixed_index(manifest, archive).await?)
+            }
+            ArchiveType::Blob => return Ok(known_chunks),
+        };
+
+        for pos in 0..index.index_count() {
+            if let Some(digest) = index.index_digest(pos) {
+                known_chunks.insert(*digest);
+            }
+        }
+
+        Ok(known_chunks)
+    }
+
+    async fn estimate_chunks<S, C>(
+        &self,
+        archive: &str,
+        mut chunks: S,
+    ) -> Result<ArchiveEstimate, Error>
+    where
+        S: Stream<Item = Result<C, Error>> + Unpin,
+        C: AsRef<[u8]>,
+    {
+        let mut known_chunks = self
+            .known_chunks(archive)
+            .await
+            .map_err(|err| format_err!("unable to load previous index of {archive} - {err}"))?;
+
+        let mut estimate = ArchiveEstimate {
+            archive: archive.to_string(),
+            size: 0,
+            upload_size: 0,
+            reused_size: 0,
+            file_count: None,
+        };
+
+        while let Some(chunk) = chunks.try_next().await? {
+            let data = chunk.as_ref();
+            let size = data.len() as u64;
+
+            // signed only backups use plain chunk digests, like the backup writer
+            let digest = match (&self.crypt_config, self.crypt_mode) {
+                (Some(crypt_config), CryptMode::Encrypt) => crypt_config.compute_digest(data),
+                _ => openssl::sha::sha256(data),
+            };
+
+            estimate.size += size;
+            if known_chunks.insert(digest) {
+                estimate.upload_size += size;
+            } else {
+                estimate.reused_size += size;
+            }
+        }
+
+        Ok(estimate)
+    }
+
+    /// Estimate the upload of the directory at `path` as pxar archive `archive`.
+    pub async fn estimate_directory(
+        &mut self,
+        path: &Path,
+        archive: &str,
+        chunk_size: Option<usize>,
+        pxar_options: PxarCreateOptions,
+    ) -> Result<(), Error> {
+        let catalog = Arc::new(Mutex::new(CatalogWriter::new(std::io::sink())?));
+        let pxar_stream = PxarBackupStream::open(path, Arc::clone(&catalog), pxar_options)?;
+        let chunk_stream = ChunkStream::new(pxar_stream, chunk_size);
+
+        let mut estimate = self.estimate_chunks(archive, chunk_stream).await?;
+        estimate.file_count = Some(catalog.lock().unwrap().file_count());
+
+        self.estimates.push(estimate);
+        Ok(())
+    }
+
+    /// Estimate the upload of the image at `path` as fixed index archive `archive`.
+    pub async fn estimate_image(
+        &mut self,
+        path: &Path,
+        archive: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<(), Error> {
+        let file = tokio::fs::File::open(path).await?;
+
+        let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+            .map_err(Error::from);
+        let chunk_stream = FixedChunkStream::new(stream, chunk_size.unwrap_or(4 * 1024 * 1024));
+
+        let estimate = self.estimate_chunks(archive, chunk_stream).await?;
+
+        self.estimates.push(estimate);
+        Ok(())
+    }
+
+    /// Estimate the upload of a raw data stream as dynamic index archive `archive`.
+    ///
+    /// The source is consumed completely, so its stats are available afterwards.
+    pub async fn estimate_stream<R>(
+        &mut self,
+        source: StreamSource<R>,
+        archive: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<(), Error>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        let chunk_stream = ChunkStream::new(source, chunk_size);
+
+        let estimate = self.estimate_chunks(archive, chunk_stream).await?;
+
+        self.estimates.push(estimate);
+        Ok(())
+    }
+
+    /// Add a blob archive, blobs are always uploaded as a whole.
+    pub fn add_blob(&mut self, archive: &str, size: u64) {
+        self.estimates.push(ArchiveEstimate {
+            archive: archive.to_string(),
+            size,
+            upload_size: size,
+            reused_size: 0,
+            file_count: Some(1),
+        });
+    }
+
+    /// Print the collected estimates.
+    pub fn render(self, output_format: &str) -> Result<(), Error> {
+        if output_format == "text" {
+            let upload_size: u64 = self.estimates.iter().map(|e| e.upload_size).sum();
+            let reused_size: u64 = self.estimates.iter().map(|e| e.reused_size).sum();
+            log::info!(
+                "dry-run: estimated upload {} (reused {}), nothing was uploaded",
+                HumanByte::from(upload_size),
+                HumanByte::from(reused_size),
+            );
+        }
+
+        let mut data = serde_json::to_value(self.estimates)?;
+        let return_type = ReturnType::new(false, &ARCHIVE_ESTIMATE_LIST_SCHEMA);
+
+        let render_size = |value: &Value, _record: &Value| -> Result<String, Error> {
+            match value.as_u64() {
+                Some(size) => Ok(HumanByte::from(size).to_string()),
+                None => Ok(String::new()),
+            }
+        };
+
+        let options = default_table_format_options()
+            .column(ColumnConfig::new("archive"))
+            .column(ColumnConfig::new("size").renderer(render_size))
+            .column(
+                ColumnConfig::new("upload-size")
+                    .header("would upload (estimate)")
+                    .renderer(render_size),
+            )
+            .column(
+                ColumnConfig::new("reused-size")
+                    .header("reused (estimate)")
+                    .renderer(render_size),
+            )
+            .column(ColumnConfig::new("file-count").header("files"));
+
+        format_and_print_result_full(&mut data, &return_type, output_format, &options);
+
+        Ok(())
+    }
+}
+
+const ARCHIVE_ESTIMATE_LIST_SCHEMA: Schema = ArraySchema::new(
+    "Estimated upload per archive.",
+    &ArchiveEstimate::API_SCHEMA,
+)
+.schema();
@@ -0,0 +1,142 @@
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::{BackupGroup, BackupNamespace};
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_backup_group, complete_namespace, complete_repository, connect,
+    extract_repository_from_value, optional_ns_param, REPO_URL_SCHEMA,
+};
+
+fn group_args(ns: &BackupNamespace, group: &BackupGroup) -> Result<Value, Error> {
+    let mut args = serde_json::to_value(group)?;
+    if !ns.is_root() {
+        args["ns"] = serde_json::to_value(ns)?;
+    }
+    Ok(args)
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the notes of a backup group
+async fn show_group_notes(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let group: BackupGroup = required_string_param(&param, "group")?.parse()?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let client = connect(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/group-notes", repo.store());
+
+    let args = group_args(&backup_ns, &group)?;
+
+    let output_format = get_output_format(&param);
+
+    let mut result = client.get(&path, Some(args)).await?;
+
+    let notes = result["data"].take();
+
+    if output_format == "text" {
+        if let Some(notes) = notes.as_str() {
+            println!("{}", notes);
+        }
+    } else {
+        format_and_print_result(
+            &json!({
+                "notes": notes,
+            }),
+            &output_format,
+        );
+    }
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+            notes: {
+                type: String,
+                description: "The Notes.",
+            },
+        }
+    }
+)]
+/// Update the notes of a backup group
+async fn update_group_notes(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let group: BackupGroup = required_string_param(&param, "group")?.parse()?;
+    let notes = required_string_param(&param, "notes")?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let client = connect(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/group-notes", repo.store());
+
+    let mut args = group_args(&backup_ns, &group)?;
+    args["notes"] = Value::from(notes);
+
+    client.put(&path, Some(args)).await?;
+
+    Ok(Value::Null)
+}
+
+fn notes_cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_GROUP_NOTES)
+                .arg_param(&["group"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("group", complete_backup_group)
+                .completion_cb("repository", complete_repository),
+        )
+        .insert(
+            "update",
+            CliCommand::new(&API_METHOD_UPDATE_GROUP_NOTES)
+                .arg_param(&["group", "notes"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("group", complete_backup_group)
+                .completion_cb("repository", complete_repository),
+        )
+}
+
+pub fn group_mgmt_cli() -> CliCommandMap {
+    CliCommandMap::new().insert("notes", notes_cli())
+}
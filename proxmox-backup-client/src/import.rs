@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use futures::TryStreamExt;
+use serde_json::Value;
+
+use proxmox_human_byte::HumanByte;
+use proxmox_io::StdChannelWriter;
+use proxmox_router::cli::*;
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    print_ns_and_snapshot, BackupDir, BackupNamespace, BackupType, CryptMode, BACKUP_ID_SCHEMA,
+    BACKUP_TIME_SCHEMA,
+};
+use pbs_client::pxar::{tar_to_pxar, TarImportOptions};
+use pbs_client::tools::key_source::get_encryption_key_password;
+use pbs_client::vma::{VmaHeader, VmaReader};
+use pbs_client::{BackupStats, BackupWriter, ChunkStream, FixedChunkStream, UploadOptions};
+use pbs_datastore::catalog::BackupCatalogWriter;
+use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+use pbs_datastore::CATALOG_NAME;
+use pbs_key_config::decrypt_key;
+use pbs_tools::crypt_config::CryptConfig;
+use pbs_tools::json::required_string_param;
+
+use crate::{
+    complete_namespace, complete_repository, connect, crypto_parameters,
+    extract_repository_from_value, optional_ns_param, record_repository, spawn_catalog_upload,
+    KEYFD_SCHEMA, KEYFILE_SCHEMA, REPO_URL_SCHEMA,
+};
+
+const CT_CONFIG_PATH: &str = "etc/vzdump/pct.conf";
+const CT_FIREWALL_PATH: &str = "etc/vzdump/pct.fw";
+
+#[derive(Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    Vma,
+    Tar,
+}
+
+#[derive(Clone, Copy)]
+enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Lzo,
+}
+
+/// Properties of a vzdump archive derived from its file name, which looks like
+/// `vzdump-qemu-100-2024_01_31-12_00_00.vma.zst`.
+struct VzdumpArchiveName {
+    format: ArchiveFormat,
+    compression: Compression,
+    backup_id: Option<String>,
+    backup_time: Option<i64>,
+}
+
+/// Parses the time stamp of vzdump archive names, which uses the local time zone.
+fn parse_vzdump_time(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once('-')?;
+    let parse = |text: &str| -> Option<Vec<i32>> {
+        let values: Vec<i32> = text
+            .split('_')
+            .map(|value| value.parse().ok())
+            .collect::<Option<_>>()?;
+        (values.len() == 3).then_some(values)
+    };
+    let (date, time) = (parse(date)?, parse(time)?);
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = date[0] - 1900;
+    tm.tm_mon = date[1] - 1;
+    tm.tm_mday = date[2];
+    tm.tm_hour = time[0];
+    tm.tm_min = time[1];
+    tm.tm_sec = time[2];
+    tm.tm_isdst = -1;
+
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        epoch => Some(epoch),
+    }
+}
+
+fn parse_archive_name(file_name: &str) -> Result<VzdumpArchiveName, Error> {
+    let (name, compression) = if let Some(name) = file_name.strip_suffix(".zst") {
+        (name, Compression::Zstd)
+    } else if let Some(name) = file_name.strip_suffix(".gz") {
+        (name, Compression::Gzip)
+    } else if let Some(name) = file_name.strip_suffix(".lzo") {
+        (name, Compression::Lzo)
+    } else {
+        (file_name, Compression::None)
+    };
+
+    let (name, format, compression) = if let Some(name) = name.strip_suffix(".vma") {
+        (name, ArchiveFormat::Vma, compression)
+    } else if let Some(name) = name.strip_suffix(".tar") {
+        (name, ArchiveFormat::Tar, compression)
+    } else if let (Some(name), Compression::None) = (name.strip_suffix(".tgz"), compression) {
+        (name, ArchiveFormat::Tar, Compression::Gzip)
+    } else {
+        bail!("unknown format of archive '{file_name}', expected a (compressed) .vma or .tar file");
+    };
+
+    let mut backup_id = None;
+    let mut backup_time = None;
+    // vzdump-<guest type>-<vmid>-<time>
+    if let Some(name) = name.strip_prefix("vzdump-") {
+        let mut parts = name.splitn(3, '-');
+        if let (Some(_), Some(vmid), Some(time)) = (parts.next(), parts.next(), parts.next()) {
+            if !vmid.is_empty() && vmid.bytes().all(|b| b.is_ascii_digit()) {
+                backup_id = Some(vmid.to_string());
+                backup_time = parse_vzdump_time(time);
+            }
+        }
+    }
+
+    Ok(VzdumpArchiveName {
+        format,
+        compression,
+        backup_id,
+        backup_time,
+    })
+}
+
+/// Opens the archive for reading its decompressed data.
+///
+/// lzo compressed archives are decompressed by `lzop`, as done by vzdump itself, the returned
+/// child process has to be checked with [`finish_decompressor`] after reading all data.
+fn open_archive(
+    path: &Path,
+    compression: Compression,
+) -> Result<(Box<dyn Read + Send>, Option<Child>), Error> {
+    let file =
+        File::open(path).map_err(|err| format_err!("unable to open archive {path:?} - {err}"))?;
+
+    Ok(match compression {
+        Compression::None => (Box::new(BufReader::new(file)), None),
+        Compression::Zstd => (Box::new(zstd::stream::read::Decoder::new(file)?), None),
+        Compression::Gzip => (
+            Box::new(flate2::read::MultiGzDecoder::new(BufReader::new(file))),
+            None,
+        ),
+        Compression::Lzo => {
+            let mut child = Command::new("lzop")
+                .arg("-dc")
+                .stdin(file)
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|err| format_err!("unable to run lzop - {err}"))?;
+            let stdout = child.stdout.take().unwrap();
+            (Box::new(BufReader::new(stdout)), Some(child))
+        }
+    })
+}
+
+/// Reads the remaining decompressed data, to make sure the whole archive is intact.
+fn finish_decompressor(
+    mut reader: Box<dyn Read + Send>,
+    decompressor: Option<Child>,
+) -> Result<(), Error> {
+    std::io::copy(&mut reader, &mut std::io::sink())
+        .map_err(|err| format_err!("unable to decompress archive - {err}"))?;
+    drop(reader);
+
+    if let Some(mut child) = decompressor {
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("decompressing archive failed - lzop {status}");
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a name taken from the archive can be used as part of an archive name.
+fn check_archive_name_part(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Returns the name of the blob PVE uses for a guest configuration file.
+fn config_blob_name(name: &str) -> Result<String, Error> {
+    let name = match name {
+        "qemu-server.fw" | "pct.fw" => "fw.conf",
+        name => name,
+    };
+    if !check_archive_name_part(name) {
+        bail!("unsupported configuration file name '{name}'");
+    }
+    Ok(format!("{name}.blob"))
+}
+
+/// Disk image of a VMA archive, staged in a sparse temporary file.
+struct StagedImage {
+    archive_name: String,
+    size: u64,
+    file: File,
+}
+
+/// Reads the whole VMA archive and writes its disk images to temporary files.
+///
+/// Only non-zero blocks are written, so the files only take up the space of the data contained
+/// in the archive. Reading everything before starting the backup makes sure that incomplete or
+/// corrupt archives fail without creating a snapshot.
+fn stage_vma_images(
+    reader: &mut dyn Read,
+    tmpdir: &Path,
+) -> Result<(VmaHeader, Vec<StagedImage>), Error> {
+    let mut vma = VmaReader::new(reader)?;
+    let header = vma.header().clone();
+
+    let mut files: HashMap<u8, File> = HashMap::new();
+    for device in header.devices.iter() {
+        if !check_archive_name_part(&device.name) {
+            bail!("unsupported disk image name '{}'", device.name);
+        }
+        log::info!(
+            "found disk image '{}' ({})",
+            device.name,
+            HumanByte::from(device.size)
+        );
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .mode(0o600)
+            .custom_flags(libc::O_TMPFILE)
+            .open(tmpdir)
+            .map_err(|err| format_err!("unable to create temporary file in {tmpdir:?} - {err}"))?;
+        file.set_len(device.size)?;
+        files.insert(device.id, file);
+    }
+
+    let mut data_size = 0;
+    while vma.read_extent(|cluster| {
+        let file = &files[&cluster.dev_id];
+        for (offset, data) in cluster.data_runs() {
+            file.write_all_at(data, offset)?;
+            data_size += data.len() as u64;
+        }
+        Ok(())
+    })? {}
+    log::info!("read {} of non-zero data", HumanByte::from(data_size));
+
+    let mut images = Vec::with_capacity(header.devices.len());
+    for device in header.devices.iter() {
+        let file = files.remove(&device.id).unwrap();
+        // the last cluster is always stored as a whole
+        file.set_len(device.size)?;
+        images.push(StagedImage {
+            archive_name: format!("{}.img.fidx", device.name),
+            size: device.size,
+            file,
+        });
+    }
+
+    Ok((header, images))
+}
+
+async fn upload_image(
+    client: &BackupWriter,
+    image: StagedImage,
+    upload_options: UploadOptions,
+) -> Result<BackupStats, Error> {
+    let file = tokio::fs::File::from_std(image.file);
+
+    let stream = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
+        .map_err(Error::from);
+
+    let stream = FixedChunkStream::new(stream, 4 * 1024 * 1024);
+
+    client
+        .upload_stream(&image.archive_name, stream, upload_options)
+        .await
+}
+
+/// Converts the container archive to a pxar archive while uploading it, together with a
+/// catalog.
+///
+/// Returns the remaining input and the configuration files found in the archive.
+async fn upload_container_archive(
+    client: &Arc<BackupWriter>,
+    mut reader: Box<dyn Read + Send>,
+    crypt_mode: CryptMode,
+    manifest: &mut BackupManifest,
+) -> Result<(Box<dyn Read + Send>, HashMap<PathBuf, Vec<u8>>), Error> {
+    let encrypt = crypt_mode == CryptMode::Encrypt;
+    let archive_name = "root.pxar.didx";
+
+    let catalog_upload = spawn_catalog_upload(client.clone(), encrypt, None)?;
+    let catalog = catalog_upload.catalog_writer;
+    catalog
+        .lock()
+        .unwrap()
+        .start_directory(&CString::new(archive_name)?)?;
+
+    let (tx, rx) = std::sync::mpsc::sync_channel(10); // allow to buffer 10 writes
+    let error_tx = tx.clone();
+    let converter_catalog = catalog.clone();
+    let converter = tokio::task::spawn_blocking(move || {
+        let writer = std::io::BufWriter::with_capacity(256 * 1024, StdChannelWriter::new(tx));
+        let writer = pxar::encoder::sync::StandardWriter::new(writer);
+        let options = TarImportOptions {
+            extract_files: vec![CT_CONFIG_PATH.into(), CT_FIREWALL_PATH.into()],
+        };
+        match tar_to_pxar(&mut reader, writer, Some(converter_catalog), options) {
+            Ok(extracted) => Ok((reader, extracted)),
+            Err(err) => {
+                // make the upload fail, instead of finishing a truncated archive
+                let _ = error_tx.send(Err(format_err!("{err:#}")));
+                Err(err)
+            }
+        }
+    });
+
+    log::info!("Upload container file system as {archive_name}");
+    let stream = ChunkStream::new(proxmox_async::blocking::StdChannelStream(rx), None);
+    let upload_options = UploadOptions {
+        compress: true,
+        encrypt,
+        ..UploadOptions::default()
+    };
+    let upload_result = client
+        .upload_stream(archive_name, stream, upload_options)
+        .await;
+
+    // a failed upload also makes the converter fail, so report the upload error first
+    let converter_result = converter.await?;
+    let stats = upload_result?;
+    let (reader, extracted) = converter_result?;
+    manifest.add_file(archive_name.to_string(), stats.size, stats.csum, crypt_mode)?;
+
+    let mutex =
+        Arc::try_unwrap(catalog).map_err(|_| format_err!("unable to get catalog (still used)"))?;
+    let mut catalog = mutex.into_inner().unwrap();
+    catalog.end_directory()?;
+    catalog.finish()?;
+    drop(catalog); // close upload stream
+
+    let stats = catalog_upload.result.await??;
+    manifest.add_file(CATALOG_NAME.to_owned(), stats.size, stats.csum, crypt_mode)?;
+
+    Ok((reader, extracted))
+}
+
+#[api(
+    input: {
+        properties: {
+            archive: {
+                type: String,
+                description: "Path to the vzdump archive (.vma or .tar, optionally compressed \
+                    with zstd, gzip or lzo).",
+            },
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "backup-id": {
+                schema: BACKUP_ID_SCHEMA,
+                optional: true,
+            },
+            "backup-time": {
+                schema: BACKUP_TIME_SCHEMA,
+                optional: true,
+            },
+            keyfile: {
+                schema: KEYFILE_SCHEMA,
+                optional: true,
+            },
+            "keyfd": {
+                schema: KEYFD_SCHEMA,
+                optional: true,
+            },
+            "crypt-mode": {
+                type: CryptMode,
+                optional: true,
+            },
+            tmpdir: {
+                type: String,
+                description: "Directory for the disk images of VMA archives, which are staged \
+                    there as sparse files before uploading them.",
+                optional: true,
+                default: "/var/tmp",
+            },
+        }
+    }
+)]
+/// Import a vzdump archive as new backup snapshot.
+///
+/// Backup ID and time are taken from the archive's file name by default, falling back to the
+/// modification time of the file.
+async fn import_vzdump(param: Value) -> Result<(), Error> {
+    let archive = PathBuf::from(required_string_param(&param, "archive")?);
+    let repo = extract_repository_from_value(&param)?;
+    let backup_ns = optional_ns_param(&param)?;
+    let tmpdir = PathBuf::from(param["tmpdir"].as_str().unwrap_or("/var/tmp"));
+
+    let file_name = archive
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format_err!("invalid archive path {archive:?}"))?;
+    let archive_name = parse_archive_name(file_name)?;
+
+    let backup_id = match param["backup-id"].as_str() {
+        Some(backup_id) => backup_id.to_string(),
+        None => archive_name.backup_id.ok_or_else(|| {
+            format_err!("unable to get backup ID from archive name, please set 'backup-id'")
+        })?,
+    };
+    let backup_time = match (param["backup-time"].as_i64(), archive_name.backup_time) {
+        (Some(backup_time), _) | (None, Some(backup_time)) => backup_time,
+        (None, None) => {
+            let mtime = std::fs::metadata(&archive)?.modified()?;
+            mtime.duration_since(std::time::UNIX_EPOCH)?.as_secs() as i64
+        }
+    };
+    let backup_type = match archive_name.format {
+        ArchiveFormat::Vma => BackupType::Vm,
+        ArchiveFormat::Tar => BackupType::Ct,
+    };
+    let snapshot = BackupDir::from((backup_type, backup_id, backup_time));
+
+    let crypto = crypto_parameters(&param)?;
+    let crypt_config = match crypto.enc_key {
+        None => None,
+        Some(key) => {
+            let (key, _created, _) = decrypt_key(&key.key, &get_encryption_key_password)?;
+            Some(Arc::new(CryptConfig::new(key)?))
+        }
+    };
+    let blob_options = UploadOptions {
+        compress: true,
+        encrypt: crypto.mode == CryptMode::Encrypt,
+        ..UploadOptions::default()
+    };
+
+    let (mut reader, decompressor) = open_archive(&archive, archive_name.compression)?;
+
+    // disk images are read completely before starting the backup
+    let staged = if archive_name.format == ArchiveFormat::Vma {
+        log::info!("Reading VMA archive {archive:?}");
+        let (header, images) = tokio::task::spawn_blocking(move || {
+            let staged = stage_vma_images(&mut reader, &tmpdir)?;
+            finish_decompressor(reader, decompressor)?;
+            Ok::<_, Error>(staged)
+        })
+        .await??;
+        Some((header, images))
+    } else {
+        None
+    };
+
+    log::info!(
+        "Starting import of {archive:?} as {}",
+        print_ns_and_snapshot(&backup_ns, &snapshot)
+    );
+
+    let client = connect(&repo)?;
+    let client = BackupWriter::start(
+        client,
+        crypt_config.clone(),
+        repo.store(),
+        &backup_ns,
+        &snapshot,
+        false,
+        false,
+        false,
+        None,
+    )
+    .await?;
+
+    // the snapshot is removed again by the server if the upload fails before it is finished
+    let mut manifest = BackupManifest::new(snapshot.clone());
+    let mut configs = Vec::new();
+
+    match staged {
+        Some((header, images)) => {
+            configs = header.configs;
+            for image in images {
+                log::info!("Upload disk image as {}", image.archive_name);
+                let upload_options = UploadOptions {
+                    fixed_size: Some(image.size),
+                    ..blob_options.clone()
+                };
+                let archive_name = image.archive_name.clone();
+                let stats = upload_image(&client, image, upload_options).await?;
+                manifest.add_file(archive_name, stats.size, stats.csum, crypto.mode)?;
+            }
+        }
+        None => {
+            let (reader, mut extracted) =
+                upload_container_archive(&client, reader, crypto.mode, &mut manifest).await?;
+            tokio::task::spawn_blocking(move || finish_decompressor(reader, decompressor))
+                .await??;
+
+            for (name, path) in [("pct.conf", CT_CONFIG_PATH), ("pct.fw", CT_FIREWALL_PATH)] {
+                if let Some(data) = extracted.remove(Path::new(path)) {
+                    configs.push((name.to_string(), data));
+                }
+            }
+        }
+    }
+
+    if configs.is_empty() {
+        log::warn!("archive contains no guest configuration");
+    }
+    for (name, data) in configs {
+        let blob_name = config_blob_name(&name)?;
+        log::info!("Upload configuration file {name} as {blob_name}");
+        let stats = client
+            .upload_blob_from_data(data, &blob_name, blob_options.clone())
+            .await?;
+        manifest.add_file(blob_name, stats.size, stats.csum, crypto.mode)?;
+    }
+
+    // manifests are never encrypted, but include a signature
+    let manifest = manifest
+        .to_string(crypt_config.as_ref().map(Arc::as_ref))
+        .map_err(|err| format_err!("unable to format manifest - {err}"))?;
+    let options = UploadOptions {
+        compress: true,
+        encrypt: false,
+        ..UploadOptions::default()
+    };
+    client
+        .upload_blob_from_data(manifest.into_bytes(), MANIFEST_BLOB_NAME, options)
+        .await?;
+
+    client.finish().await?;
+
+    log::info!(
+        "Imported {archive:?} as {}",
+        print_ns_and_snapshot(&backup_ns, &snapshot)
+    );
+
+    record_repository(&repo);
+
+    Ok(())
+}
+
+pub fn import_cli() -> CliCommandMap {
+    CliCommandMap::new().insert(
+        "vzdump",
+        CliCommand::new(&API_METHOD_IMPORT_VZDUMP)
+            .arg_param(&["archive"])
+            .completion_cb("archive", complete_file_name)
+            .completion_cb("tmpdir", complete_file_name)
+            .completion_cb("ns", complete_namespace)
+            .completion_cb("repository", complete_repository)
+            .completion_cb("keyfile", complete_file_name),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_archive_name() -> Result<(), Error> {
+        let name = parse_archive_name("vzdump-qemu-100-2024_01_31-12_00_00.vma.zst")?;
+        assert!(name.format == ArchiveFormat::Vma);
+        assert!(matches!(name.compression, Compression::Zstd));
+        assert_eq!(name.backup_id.as_deref(), Some("100"));
+        assert!(name.backup_time.is_some());
+
+        let name = parse_archive_name("vzdump-lxc-12345-2024_01_31-12_00_00.tgz")?;
+        assert!(name.format == ArchiveFormat::Tar);
+        assert!(matches!(name.compression, Compression::Gzip));
+        assert_eq!(name.backup_id.as_deref(), Some("12345"));
+
+        let name = parse_archive_name("my-container.tar.lzo")?;
+        assert!(name.format == ArchiveFormat::Tar);
+        assert!(matches!(name.compression, Compression::Lzo));
+        assert!(name.backup_id.is_none());
+        assert!(name.backup_time.is_none());
+
+        assert!(parse_archive_name("vzdump-qemu-100-2024_01_31-12_00_00.log").is_err());
+        assert!(parse_archive_name("disk.tgz.zst").is_err());
+
+        Ok(())
+    }
+}
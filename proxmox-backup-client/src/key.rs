@@ -1,5 +1,5 @@
 use std::io::IsTerminal;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
@@ -13,7 +13,7 @@ use proxmox_schema::{api, ApiType, ReturnType};
 use proxmox_sys::fs::{file_get_contents, replace_file, CreateOptions};
 use proxmox_sys::linux::tty;
 
-use pbs_api_types::{Kdf, KeyInfo, PASSWORD_HINT_SCHEMA};
+use pbs_api_types::{Fingerprint, Kdf, KeyInfo, PASSWORD_HINT_SCHEMA};
 use pbs_client::tools::key_source::{
     find_default_encryption_key, find_default_master_pubkey, get_encryption_key_password,
     place_default_encryption_key, place_default_master_pubkey,
@@ -200,6 +200,46 @@ async fn import_with_master_key(
     Ok(())
 }
 
+fn default_key_path_for_update(path: Option<String>) -> Result<PathBuf, Error> {
+    match path {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => {
+            let path = find_default_encryption_key()?.ok_or_else(|| {
+                format_err!("no encryption file provided and no default file found")
+            })?;
+            log::info!("updating default key at: {:?}", path);
+            Ok(path)
+        }
+    }
+}
+
+/// Replace the key file at `path`, keeping a copy of the old file as `<path>.old`.
+///
+/// Bails out if the new key config would not contain the same key.
+fn replace_key_file(
+    path: &Path,
+    new_key_config: &KeyConfig,
+    fingerprint: &Fingerprint,
+) -> Result<(), Error> {
+    if new_key_config.fingerprint.as_ref() != Some(fingerprint) {
+        bail!("fingerprint of re-encrypted key does not match - internal error");
+    }
+
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".old");
+    let backup_path = PathBuf::from(backup_path);
+
+    std::fs::copy(path, &backup_path)
+        .map_err(|err| format_err!("unable to backup key file to {backup_path:?} - {err}"))?;
+
+    new_key_config.store(path, true)?;
+
+    log::info!("old key file saved as {:?}", backup_path);
+    log::info!("key fingerprint (unchanged): {}", fingerprint);
+
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {
@@ -215,25 +255,25 @@ async fn import_with_master_key(
                 schema: PASSWORD_HINT_SCHEMA,
                 optional: true,
             },
+            "force-encrypt": {
+                description: "Allow adding a passphrase to a currently unencrypted key.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
         },
     },
 )]
 /// Change the encryption key's password.
+///
+/// The key itself stays the same, so existing backups remain accessible.
 fn change_passphrase(
     kdf: Option<Kdf>,
     path: Option<String>,
     hint: Option<String>,
+    force_encrypt: bool,
 ) -> Result<(), Error> {
-    let path = match path {
-        Some(path) => PathBuf::from(path),
-        None => {
-            let path = find_default_encryption_key()?.ok_or_else(|| {
-                format_err!("no encryption file provided and no default file found")
-            })?;
-            log::info!("updating default key at: {:?}", path);
-            path
-        }
-    };
+    let path = default_key_path_for_update(path)?;
 
     let kdf = kdf.unwrap_or_default();
 
@@ -242,9 +282,13 @@ fn change_passphrase(
     }
 
     let key_config = KeyConfig::load(&path)?;
-    let (key, created, _fingerprint) = key_config.decrypt(&get_encryption_key_password)?;
+    if key_config.kdf.is_none() && !matches!(kdf, Kdf::None) && !force_encrypt {
+        bail!("key is not protected by a passphrase - use '--force-encrypt' to add one");
+    }
 
-    match kdf {
+    let (key, created, fingerprint) = key_config.decrypt(&get_encryption_key_password)?;
+
+    let new_key_config = match kdf {
         Kdf::None => {
             if hint.is_some() {
                 bail!("password hint not allowed for Kdf::None");
@@ -252,8 +296,7 @@ fn change_passphrase(
 
             let mut key_config = KeyConfig::without_password(key)?;
             key_config.created = created; // keep original value
-
-            key_config.store(&path, true)?;
+            key_config
         }
         Kdf::Scrypt | Kdf::PBKDF2 => {
             let password = tty::read_and_verify_password("New Password: ")?;
@@ -261,12 +304,51 @@ fn change_passphrase(
             let mut new_key_config = KeyConfig::with_key(&key, &password, kdf)?;
             new_key_config.created = created; // keep original value
             new_key_config.hint = hint;
+            new_key_config
+        }
+    };
+
+    replace_key_file(&path, &new_key_config, &fingerprint)
+}
+
+#[api(
+    input: {
+        properties: {
+            path: {
+                description: "Key file. Without this the default key will be upgraded.",
+                optional: true,
+            },
+        },
+    },
+)]
+/// Re-encrypt a key stored with legacy key derivation parameters.
+///
+/// The passphrase and the key itself stay the same, only the key derivation is upgraded to the
+/// current scrypt parameters.
+fn upgrade_kdf(path: Option<String>) -> Result<(), Error> {
+    let path = default_key_path_for_update(path)?;
 
-            new_key_config.store(&path, true)?;
+    let key_config = KeyConfig::load(&path)?;
+
+    match key_config.kdf {
+        None => bail!(
+            "key is not protected by a passphrase - use 'change-passphrase --force-encrypt' to add one"
+        ),
+        Some(ref kdf) if !kdf.is_legacy() => {
+            log::info!("key already uses current key derivation parameters, nothing to do");
+            return Ok(());
         }
+        Some(_) => {}
     }
 
-    Ok(())
+    let password = get_encryption_key_password()?;
+    let (key, created, fingerprint) = key_config.decrypt(&|| Ok(password.clone()))?;
+
+    let mut new_key_config = KeyConfig::with_key(&key, &password, Kdf::Scrypt)?;
+    new_key_config.created = created; // keep original value
+    new_key_config.hint = key_config.hint.clone(); // same passphrase, so keep its hint
+
+    replace_key_file(&path, &new_key_config, &fingerprint)
 }
 
 #[api(
@@ -503,6 +585,10 @@ pub fn cli() -> CliCommandMap {
         .arg_param(&["path"])
         .completion_cb("path", complete_file_name);
 
+    let key_upgrade_kdf_cmd_def = CliCommand::new(&API_METHOD_UPGRADE_KDF)
+        .arg_param(&["path"])
+        .completion_cb("path", complete_file_name);
+
     let key_create_master_key_cmd_def = CliCommand::new(&API_METHOD_CREATE_MASTER_KEY);
     let key_import_master_pubkey_cmd_def = CliCommand::new(&API_METHOD_IMPORT_MASTER_PUBKEY)
         .arg_param(&["path"])
@@ -525,6 +611,7 @@ pub fn cli() -> CliCommandMap {
         .insert("create-master-key", key_create_master_key_cmd_def)
         .insert("import-master-pubkey", key_import_master_pubkey_cmd_def)
         .insert("change-passphrase", key_change_passphrase_cmd_def)
+        .insert("upgrade-kdf", key_upgrade_kdf_cmd_def)
         .insert("show", key_show_cmd_def)
         .insert("show-master-pubkey", key_show_master_pubkey_cmd_def)
         .insert("paperkey", paper_key_cmd_def)
@@ -0,0 +1,327 @@
+//! Local copies of the catalogs written during backup, for searching files offline.
+//!
+//! The catalogs are stored in `~/.cache/proxmox-backup/catalogs`, below a directory per
+//! repository, using the same namespace/type/id layout as the datastore, with one
+//! `<backup-time>.pcat1` file per snapshot.
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Error};
+
+use pathpatterns::MatchEntry;
+
+use pbs_api_types::{BackupDir, BackupGroup, BackupNamespace, BackupType, SnapshotListItem};
+use pbs_client::tools::base_directories;
+use pbs_client::{BackupRepository, HttpClient};
+use pbs_datastore::catalog::CatalogReader;
+
+use crate::api_datastore_list_snapshots;
+
+/// Environment variable to enable the local catalog by default.
+pub const ENV_VAR_PBS_LOCAL_CATALOG: &str = "PBS_LOCAL_CATALOG";
+
+const CATALOG_EXT: &str = "pcat1";
+
+/// Whether the local catalog should be written, `no_local_catalog` overrides everything else.
+pub fn local_catalog_enabled(local_catalog: bool, no_local_catalog: bool) -> Result<bool, Error> {
+    if no_local_catalog {
+        return Ok(false);
+    }
+    if local_catalog {
+        return Ok(true);
+    }
+
+    match std::env::var(ENV_VAR_PBS_LOCAL_CATALOG) {
+        Ok(value) => proxmox_schema::parse_boolean(value.trim())
+            .map_err(|err| format_err!("invalid {ENV_VAR_PBS_LOCAL_CATALOG}: {err}")),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Directory containing the local catalogs of `repo`.
+fn repository_dir(repo: &BackupRepository) -> Result<PathBuf, Error> {
+    // the user is not part of the key, as it does not change the content of the datastore
+    let name = format!("{}:{}:{}", repo.host(), repo.port(), repo.store());
+
+    let mut path = base_directories()?.get_cache_home();
+    path.push("catalogs");
+    path.push(proxmox_sys::systemd::escape_unit(&name, false));
+    Ok(path)
+}
+
+fn group_dir(
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+) -> Result<PathBuf, Error> {
+    let mut path = repository_dir(repo)?;
+    if !ns.is_root() {
+        path.push(ns.path());
+    }
+    path.push(group.ty.as_str());
+    path.push(&group.id);
+    Ok(path)
+}
+
+fn catalog_file_name(backup_time: i64) -> Result<String, Error> {
+    let time = proxmox_time::epoch_to_rfc3339_utc(backup_time)?;
+    Ok(format!("{time}.{CATALOG_EXT}"))
+}
+
+fn read_dir_if_exists(path: &Path) -> Result<Option<std::fs::ReadDir>, Error> {
+    match std::fs::read_dir(path) {
+        Ok(dir) => Ok(Some(dir)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format_err!("unable to read directory {path:?} - {err}")),
+    }
+}
+
+/// The local copy of a catalog while it is being written.
+///
+/// The data is written to a temporary file, which only replaces the final file on
+/// [`commit`](LocalCatalog::commit), so that failed backups leave no catalog behind.
+pub struct LocalCatalog {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LocalCatalog {
+    pub fn create(
+        repo: &BackupRepository,
+        ns: &BackupNamespace,
+        snapshot: &BackupDir,
+    ) -> Result<Self, Error> {
+        let dir = group_dir(repo, ns, &snapshot.group)?;
+        std::fs::create_dir_all(&dir)
+            .map_err(|err| format_err!("unable to create directory {dir:?} - {err}"))?;
+
+        let path = dir.join(catalog_file_name(snapshot.time)?);
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path)
+            .map_err(|err| format_err!("unable to create {tmp_path:?} - {err}"))?;
+
+        Ok(Self {
+            path,
+            tmp_path,
+            file: Some(file),
+        })
+    }
+
+    /// Append catalog data. Errors are only logged, the local catalog is simply discarded then.
+    pub fn write_data(&mut self, data: &[u8]) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(err) = file.write_all(data) {
+                log::warn!("unable to write local catalog {:?} - {err}", self.tmp_path);
+                self.file = None;
+            }
+        }
+    }
+
+    /// Make the catalog available for searching, must be called after the backup finished.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| format_err!("local catalog {:?} is incomplete", self.path))?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&self.tmp_path, &self.path)
+            .map_err(|err| format_err!("unable to rename local catalog to {:?} - {err}", self.path))
+    }
+}
+
+impl Drop for LocalCatalog {
+    fn drop(&mut self) {
+        // no-op if the catalog was committed
+        let _ = std::fs::remove_file(&self.tmp_path);
+    }
+}
+
+/// A local catalog of a snapshot.
+pub struct CachedCatalog {
+    pub ns: BackupNamespace,
+    pub snapshot: BackupDir,
+    pub path: PathBuf,
+}
+
+fn scan_group_dir(
+    dir: &Path,
+    ns: &BackupNamespace,
+    group: &BackupGroup,
+    list: &mut Vec<CachedCatalog>,
+) -> Result<(), Error> {
+    let entries = match read_dir_if_exists(dir)? {
+        Some(entries) => entries,
+        None => return Ok(()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let time = match name
+            .to_str()
+            .and_then(|name| name.strip_suffix(CATALOG_EXT)?.strip_suffix('.'))
+            .and_then(|time| proxmox_time::parse_rfc3339(time).ok())
+        {
+            Some(time) => time,
+            None => continue, // temporary or foreign file
+        };
+
+        list.push(CachedCatalog {
+            ns: ns.clone(),
+            snapshot: BackupDir::from((group.clone(), time)),
+            path: entry.path(),
+        });
+    }
+
+    Ok(())
+}
+
+fn scan_namespace_dir(
+    dir: &Path,
+    ns: &BackupNamespace,
+    recursive: bool,
+    list: &mut Vec<CachedCatalog>,
+) -> Result<(), Error> {
+    let entries = match read_dir_if_exists(dir)? {
+        Some(entries) => entries,
+        None => return Ok(()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+
+        if name == "ns" {
+            if !recursive {
+                continue;
+            }
+            let children = match read_dir_if_exists(&entry.path())? {
+                Some(children) => children,
+                None => continue,
+            };
+            for child in children {
+                let child = child?;
+                let name = match child.file_name().into_string() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                let mut child_ns = ns.clone();
+                if child_ns.push(name).is_err() {
+                    continue;
+                }
+                scan_namespace_dir(&child.path(), &child_ns, recursive, list)?;
+            }
+            continue;
+        }
+
+        let backup_type: BackupType = match name.parse() {
+            Ok(backup_type) => backup_type,
+            Err(_) => continue,
+        };
+
+        for id_entry in std::fs::read_dir(entry.path())? {
+            let id_entry = id_entry?;
+            if let Ok(id) = id_entry.file_name().into_string() {
+                let group = BackupGroup::new(backup_type, id);
+                scan_group_dir(&id_entry.path(), ns, &group, list)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// List the local catalogs of `repo`, optionally limited to a namespace (without its
+/// sub-namespaces) and/or a group. A group without namespace is looked up in all namespaces.
+pub fn list_local_catalogs(
+    repo: &BackupRepository,
+    ns: Option<&BackupNamespace>,
+    group: Option<&BackupGroup>,
+) -> Result<Vec<CachedCatalog>, Error> {
+    let mut list = Vec::new();
+
+    match (ns, group) {
+        (Some(ns), Some(group)) => {
+            scan_group_dir(&group_dir(repo, ns, group)?, ns, group, &mut list)?;
+        }
+        (None, Some(group)) => {
+            let root = BackupNamespace::root();
+            scan_namespace_dir(&repository_dir(repo)?, &root, true, &mut list)?;
+            list.retain(|catalog| catalog.snapshot.group == *group);
+        }
+        (Some(ns), None) => {
+            let mut dir = repository_dir(repo)?;
+            if !ns.is_root() {
+                dir.push(ns.path());
+            }
+            scan_namespace_dir(&dir, ns, false, &mut list)?;
+        }
+        (None, None) => {
+            let root = BackupNamespace::root();
+            scan_namespace_dir(&repository_dir(repo)?, &root, true, &mut list)?;
+        }
+    }
+
+    list.sort_unstable_by(|a, b| (&a.ns, &a.snapshot).cmp(&(&b.ns, &b.snapshot)));
+
+    Ok(list)
+}
+
+/// Call `callback` with the path of every entry of the catalog at `path` matching `pattern`.
+pub fn search_local_catalog(
+    path: &Path,
+    pattern: &MatchEntry,
+    callback: &mut dyn FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let file = File::open(path).map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+    let mut reader = CatalogReader::new(BufReader::new(file));
+
+    let root = reader.root()?;
+    reader.find(&root, &mut Vec::new(), &[pattern], callback)
+}
+
+/// Remove the local catalogs of snapshots in `ns` (optionally limited to `group`), which do
+/// not exist on the server anymore. Returns the number of removed catalogs.
+pub async fn prune_local_catalogs(
+    client: &HttpClient,
+    repo: &BackupRepository,
+    ns: &BackupNamespace,
+    group: Option<&BackupGroup>,
+) -> Result<usize, Error> {
+    let cached = list_local_catalogs(repo, Some(ns), group)?;
+    if cached.is_empty() {
+        return Ok(0);
+    }
+
+    let list = api_datastore_list_snapshots(client, repo.store(), ns, group).await?;
+    let list: Vec<SnapshotListItem> = serde_json::from_value(list)?;
+    let existing: BTreeSet<BackupDir> = list.into_iter().map(|item| item.backup).collect();
+
+    let mut removed = 0;
+    for catalog in cached {
+        if existing.contains(&catalog.snapshot) {
+            continue;
+        }
+        match std::fs::remove_file(&catalog.path) {
+            Ok(()) => removed += 1,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => log::warn!("unable to remove local catalog {:?} - {err}", catalog.path),
+        }
+        if let Some(dir) = catalog.path.parent() {
+            let _ = std::fs::remove_dir(dir); // only succeeds if empty
+        }
+    }
+
+    Ok(removed)
+}
@@ -7,6 +7,7 @@ use std::task::Context;
 
 use anyhow::{bail, format_err, Error};
 use futures::stream::{StreamExt, TryStreamExt};
+use hex::FromHex;
 use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio::sync::mpsc;
@@ -18,16 +19,19 @@ use proxmox_async::blocking::TokioWriterAdapter;
 use proxmox_human_byte::HumanByte;
 use proxmox_io::StdChannelWriter;
 use proxmox_router::{cli::*, ApiMethod, RpcEnvironment};
-use proxmox_schema::api;
+use proxmox_schema::{api, ApiStringFormat};
 use proxmox_sys::fs::{file_get_json, image_size, replace_file, CreateOptions};
 use proxmox_time::{epoch_i64, strftime_local};
 use pxar::accessor::{MaybeReady, ReadAt, ReadAtOperation};
 
 use pbs_api_types::{
-    Authid, BackupDir, BackupGroup, BackupNamespace, BackupPart, BackupType, CryptMode,
-    Fingerprint, GroupListItem, PruneJobOptions, PruneListItem, RateLimitConfig, SnapshotListItem,
-    StorageStatus, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, TRAFFIC_CONTROL_BURST_SCHEMA, TRAFFIC_CONTROL_RATE_SCHEMA,
+    print_ns_and_snapshot, ApiErrorCode, Authid, BackupDir, BackupGroup, BackupNamespace,
+    BackupPart, BackupType, CryptMode, Fingerprint, GroupDedupStatsResult, GroupListItem,
+    PruneJobOptions, PruneListItem, RateLimitConfig, SnapshotListItem, StorageStatus,
+    WireCompression, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
+    BACKUP_TYPE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, LOCK_WAIT_SCHEMA, NS_MAX_DEPTH_SCHEMA,
+    RE_VERIFY_FAILED_SCHEMA, SHA256_HEX_REGEX, TRAFFIC_CONTROL_BURST_SCHEMA,
+    TRAFFIC_CONTROL_RATE_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
 };
 use pbs_client::catalog_shell::Shell;
 use pbs_client::pxar::ErrorHandler as PxarErrorHandler;
@@ -36,16 +40,19 @@ use pbs_client::tools::{
     complete_backup_source, complete_chunk_size, complete_group_or_snapshot,
     complete_img_archive_name, complete_namespace, complete_pxar_archive_name, complete_repository,
     connect, connect_rate_limited, extract_repository_from_value,
+    extract_upload_concurrency_from_value, extract_wire_compression_from_value,
     key_source::{
         crypto_parameters, format_key_source, get_encryption_key_password, KEYFD_SCHEMA,
         KEYFILE_SCHEMA, MASTER_PUBKEY_FD_SCHEMA, MASTER_PUBKEY_FILE_SCHEMA,
     },
-    CHUNK_SIZE_SCHEMA, REPO_URL_SCHEMA,
+    record_repository_maintenance, CHUNK_SIZE_SCHEMA, CLIENT_COMPRESSION_SCHEMA, REPO_URL_SCHEMA,
+    UPLOAD_CONCURRENCY_SCHEMA,
 };
 use pbs_client::{
-    delete_ticket_info, parse_backup_specification, view_task_result, BackupReader,
-    BackupRepository, BackupSpecificationType, BackupStats, BackupWriter, ChunkStream,
-    FixedChunkStream, HttpClient, PxarBackupStream, RemoteChunkReader, UploadOptions,
+    api_error_code, delete_ticket_info, display_task_log, follow_task_log,
+    parse_backup_specification, view_task_result, BackupReader, BackupRepository,
+    BackupSpecificationType, BackupStats, BackupWriter, ChunkStream, FixedChunkStream, HttpClient,
+    PxarBackupStream, RemoteChunkReader, StreamSource, StreamSourceStatsHandle, UploadOptions,
     BACKUP_SOURCE_SCHEMA,
 };
 use pbs_datastore::catalog::{BackupCatalogWriter, CatalogReader, CatalogWriter};
@@ -56,7 +63,6 @@ use pbs_datastore::index::IndexFile;
 use pbs_datastore::manifest::{
     archive_type, ArchiveType, BackupManifest, ENCRYPTED_KEY_BLOB_NAME, MANIFEST_BLOB_NAME,
 };
-use pbs_datastore::read_chunk::AsyncReadChunk;
 use pbs_datastore::CATALOG_NAME;
 use pbs_key_config::{decrypt_key, rsa_encrypt_key_config, KeyConfig};
 use pbs_tools::crypt_config::CryptConfig;
@@ -68,8 +74,15 @@ mod task;
 pub use task::*;
 mod catalog;
 pub use catalog::*;
+mod dry_run;
+use dry_run::DryRun;
+mod local_catalog;
+use local_catalog::LocalCatalog;
+mod group;
+pub use group::*;
 mod snapshot;
 pub use snapshot::*;
+mod import;
 pub mod key;
 pub mod namespace;
 
@@ -81,6 +94,22 @@ mod mount;
 pub use mount::*;
 
 
+/// Log the exclude patterns recorded in the manifest for `archive_name`.
+fn show_archive_excludes(manifest: &BackupManifest, archive_name: &str) {
+    match manifest.archive_excludes(archive_name) {
+        None => log::info!("{archive_name}: no exclude patterns recorded"),
+        Some(excludes) => {
+            log::info!("{archive_name}: exclude patterns");
+            for pattern in excludes.patterns {
+                log::info!("  {pattern}");
+            }
+            if excludes.truncated.unwrap_or(false) {
+                log::info!("  (list truncated)");
+            }
+        }
+    }
+}
+
 fn record_repository(repo: &BackupRepository) {
     let base = match BaseDirectories::with_prefix("proxmox-backup") {
         Ok(v) => v,
@@ -250,6 +279,90 @@ async fn backup_image<P: AsRef<Path>>(
     Ok(stats)
 }
 
+/// Reader of a raw data stream archive source.
+type StreamSourceReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
+
+/// Open the source of a raw data stream archive, `-` denotes standard input.
+async fn open_stream_source(
+    path: &str,
+    archive_name: &str,
+) -> Result<StreamSource<StreamSourceReader>, Error> {
+    let reader: StreamSourceReader = if path == "-" {
+        Box::new(tokio::io::stdin())
+    } else {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|err| format_err!("unable to open '{}' - {}", path, err))?;
+        Box::new(file)
+    };
+
+    Ok(StreamSource::new(reader).with_progress(archive_name))
+}
+
+async fn backup_stream(
+    client: &BackupWriter,
+    source: StreamSource<StreamSourceReader>,
+    archive_name: &str,
+    chunk_size: Option<usize>,
+    upload_options: UploadOptions,
+) -> Result<BackupStats, Error> {
+    let mut chunk_stream = ChunkStream::new(source, chunk_size);
+
+    let (tx, rx) = mpsc::channel(10); // allow to buffer 10 chunks
+
+    let stream = ReceiverStream::new(rx).map_err(Error::from);
+
+    // spawn chunker inside a separate task so that it can run parallel
+    tokio::spawn(async move {
+        while let Some(v) = chunk_stream.next().await {
+            let _ = tx.send(v).await;
+        }
+    });
+
+    if upload_options.fixed_size.is_some() {
+        bail!("cannot backup data stream with fixed chunk size!");
+    }
+
+    let stats = client
+        .upload_stream(archive_name, stream, upload_options)
+        .await?;
+
+    Ok(stats)
+}
+
+/// Log size and digest of a fully consumed stream source and compare against `expected_digest`.
+fn check_stream_source(
+    stats: &StreamSourceStatsHandle,
+    archive_name: &str,
+    expected_digest: Option<&[u8; 32]>,
+) -> Result<(), Error> {
+    let stats = match stats.lock().unwrap().clone() {
+        Some(stats) => stats,
+        None => bail!("{}: source was not read completely", archive_name),
+    };
+
+    let digest = hex::encode(stats.sha256);
+    log::info!(
+        "{}: read {} from source (sha256 {})",
+        archive_name,
+        HumanByte::from(stats.size),
+        digest,
+    );
+
+    if let Some(expected_digest) = expected_digest {
+        if *expected_digest != stats.sha256 {
+            bail!(
+                "{}: digest mismatch - expected {}, got {}",
+                archive_name,
+                hex::encode(expected_digest),
+                digest,
+            );
+        }
+    }
+
+    Ok(())
+}
+
 pub fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     Ok(match param.get("ns") {
         Some(Value::String(ns)) => ns.parse()?,
@@ -396,6 +509,118 @@ async fn change_backup_owner(group: String, mut param: Value) -> Result<(), Erro
     Ok(())
 }
 
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            group: {
+                type: String,
+                description: "Backup group.",
+            },
+            "ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            force: {
+                type: bool,
+                description: "Recompute the statistics even if cached ones exist.",
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// Show chunk deduplication statistics of a backup group
+async fn group_dedup_stats(group: String, force: bool, param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let ns = optional_ns_param(&param)?;
+    let output_format = get_output_format(&param);
+
+    let group: BackupGroup = group.parse()?;
+
+    let client = connect(&repo)?;
+
+    let path = format!(
+        "api2/json/admin/datastore/{}/groups/{}/{}/dedup-stats",
+        repo.store(),
+        group.ty,
+        group.id,
+    );
+
+    let mut args = json!({});
+    if !ns.is_root() {
+        args["ns"] = serde_json::to_value(&ns)?;
+    }
+
+    let mut post_args = args.clone();
+    post_args["force"] = force.into();
+    let mut result = client.post(&path, Some(post_args)).await?;
+
+    record_repository(&repo);
+
+    let mut data: GroupDedupStatsResult = serde_json::from_value(result["data"].take())?;
+
+    if let Some(upid) = data.upid.take() {
+        if output_format == "text" {
+            display_task_log(&client, &upid, true, true).await?;
+        } else if let Some(task_result) = follow_task_log(&client, &upid, true, |_| ()).await? {
+            task_result.check()?;
+        }
+        let mut result = client.get(&path, Some(args)).await?;
+        data = serde_json::from_value(result["data"].take())?;
+    }
+
+    if output_format != "text" {
+        format_and_print_result(&serde_json::to_value(&data)?, &output_format);
+        return Ok(());
+    }
+
+    let stats = match data.stats {
+        Some(stats) => stats,
+        None => bail!("no deduplication statistics available for group '{group}'"),
+    };
+
+    let percentage = |bytes: u64| {
+        if stats.chunk_count == 0 {
+            0.0
+        } else {
+            (bytes as f64) * 100.0 / ((stats.unique_bytes + stats.shared_bytes) as f64)
+        }
+    };
+
+    let age =
+        proxmox_time::TimeSpan::from(std::time::Duration::from_secs(data.age.unwrap_or(0) as u64));
+
+    println!("Group:       {group}");
+    println!(
+        "Computed:    {} ({age} ago)",
+        strftime_local("%c", stats.computed)?
+    );
+    println!("Index files: {}", stats.index_file_count);
+    println!("Referenced:  {}", HumanByte::from(stats.referenced_bytes));
+    println!(
+        "Unique:      {} ({:.2}%, {} chunks)",
+        HumanByte::from(stats.unique_bytes),
+        percentage(stats.unique_bytes),
+        stats.unique_chunks,
+    );
+    println!(
+        "Shared:      {} ({:.2}%, {} chunks)",
+        HumanByte::from(stats.shared_bytes),
+        percentage(stats.shared_bytes),
+        stats.shared_chunks,
+    );
+
+    Ok(())
+}
+
 #[api(
    input: {
         properties: {
@@ -523,6 +748,86 @@ async fn start_garbage_collection(param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            target: {
+                type: String,
+                description: "Backup group or snapshot path, verify the whole namespace if unset.",
+                optional: true,
+            },
+            "ignore-verified": {
+                schema: IGNORE_VERIFIED_BACKUPS_SCHEMA,
+                optional: true,
+            },
+            "outdated-after": {
+                schema: VERIFICATION_OUTDATED_AFTER_SCHEMA,
+                optional: true,
+            },
+            "re-verify-failed": {
+                schema: RE_VERIFY_FAILED_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Verify backups of a namespace, a backup group or a single snapshot.
+async fn verify(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let output_format = get_output_format(&param);
+
+    let mut args = json!({});
+    for name in ["max-depth", "ignore-verified", "outdated-after", "re-verify-failed"] {
+        if let Some(value) = param.get(name) {
+            args[name] = value.clone();
+        }
+    }
+
+    let ns = optional_ns_param(&param)?;
+    if !ns.is_root() {
+        args["ns"] = serde_json::to_value(&ns)?;
+    }
+
+    if let Some(target) = param["target"].as_str() {
+        let target = match target.parse::<BackupDir>() {
+            Ok(snapshot) => serde_json::to_value(snapshot)?,
+            Err(_) => serde_json::to_value(target.parse::<BackupGroup>()?)?,
+        };
+        if let (Value::Object(args), Value::Object(target)) = (&mut args, target) {
+            args.extend(target);
+        }
+    }
+
+    let client = connect(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/verify", repo.store());
+
+    let result = client.post(&path, Some(args)).await?;
+
+    record_repository(&repo);
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
 struct CatalogUploadResult {
     catalog_writer: Arc<Mutex<CatalogWriter<TokioWriterAdapter<StdChannelWriter<Error>>>>>,
     result: tokio::sync::oneshot::Receiver<Result<BackupStats, Error>>,
@@ -531,9 +836,15 @@ struct CatalogUploadResult {
 fn spawn_catalog_upload(
     client: Arc<BackupWriter>,
     encrypt: bool,
+    local_catalog: Option<Arc<Mutex<LocalCatalog>>>,
 ) -> Result<CatalogUploadResult, Error> {
     let (catalog_tx, catalog_rx) = std::sync::mpsc::sync_channel(10); // allow to buffer 10 writes
-    let catalog_stream = proxmox_async::blocking::StdChannelStream(catalog_rx);
+    let catalog_stream =
+        proxmox_async::blocking::StdChannelStream(catalog_rx).inspect_ok(move |data: &Vec<u8>| {
+            if let Some(local_catalog) = &local_catalog {
+                local_catalog.lock().unwrap().write_data(data);
+            }
+        });
     let catalog_chunk_size = 512 * 1024;
     let catalog_chunk_stream = ChunkStream::new(catalog_stream, Some(catalog_chunk_size));
 
@@ -642,6 +953,10 @@ fn spawn_catalog_upload(
                schema: CHUNK_SIZE_SCHEMA,
                optional: true,
            },
+           "upload-concurrency": {
+               schema: UPLOAD_CONCURRENCY_SCHEMA,
+               optional: true,
+           },
            rate: {
                schema: TRAFFIC_CONTROL_RATE_SCHEMA,
                optional: true,
@@ -650,6 +965,10 @@ fn spawn_catalog_upload(
                schema: TRAFFIC_CONTROL_BURST_SCHEMA,
                optional: true,
            },
+           compress: {
+               schema: CLIENT_COMPRESSION_SCHEMA,
+               optional: true,
+           },
            "exclude": {
                type: Array,
                description: "List of paths or patterns for matching files to exclude.",
@@ -667,16 +986,50 @@ fn spawn_catalog_upload(
            },
            "dry-run": {
                type: Boolean,
-               description: "Just show what backup would do, but do not upload anything.",
+               description: "Just show what backup would do and estimate the upload size, but do not upload anything.",
                optional: true,
                default: false,
            },
+           "expected-digest": {
+               type: String,
+               description: "Expected SHA-256 digest of the data read by the '.didx' stream source. The backup fails if it does not match.",
+               format: &ApiStringFormat::Pattern(&SHA256_HEX_REGEX),
+               optional: true,
+           },
            "skip-e2big-xattr": {
                type: Boolean,
                description: "Ignore the E2BIG error when retrieving xattrs. This includes the file, but discards the metadata.",
                optional: true,
                default: false,
            },
+           "ignore-quota": {
+               type: Boolean,
+               description: "Start the backup even if the namespace exceeds its quota (requires Datastore.Allocate).",
+               optional: true,
+               default: false,
+           },
+           "send-hostname": {
+               type: Boolean,
+               description: "Send the hostname of this client to the server, to be recorded in the snapshot manifest.",
+               optional: true,
+               default: false,
+           },
+           "local-catalog": {
+               type: Boolean,
+               description: "Keep a local copy of the catalog for offline searching. Can also be enabled with the PBS_LOCAL_CATALOG environment variable.",
+               optional: true,
+               default: false,
+           },
+           "no-local-catalog": {
+               type: Boolean,
+               description: "Do not keep a local copy of the catalog, even if enabled by PBS_LOCAL_CATALOG.",
+               optional: true,
+               default: false,
+           },
+           "output-format": {
+               schema: OUTPUT_FORMAT,
+               optional: true,
+           },
        }
    }
 )]
@@ -687,6 +1040,10 @@ async fn create_backup(
     skip_lost_and_found: bool,
     dry_run: bool,
     skip_e2big_xattr: bool,
+    ignore_quota: bool,
+    send_hostname: bool,
+    local_catalog: bool,
+    no_local_catalog: bool,
     _info: &ApiMethod,
     _rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
@@ -698,6 +1055,8 @@ async fn create_backup(
 
     let chunk_size_opt = param["chunk-size"].as_u64().map(|v| (v * 1024) as usize);
 
+    let upload_concurrency = extract_upload_concurrency_from_value(&param)?;
+
     if let Some(size) = chunk_size_opt {
         verify_chunk_size(size)?;
     }
@@ -713,6 +1072,8 @@ async fn create_backup(
 
     let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
 
+    let wire_compression = extract_wire_compression_from_value(&param)?;
+
     let crypto = crypto_parameters(&param)?;
 
     let backup_id = param["backup-id"]
@@ -725,6 +1086,11 @@ async fn create_backup(
 
     let include_dev = param["include-dev"].as_array();
 
+    let expected_digest = match param["expected-digest"].as_str() {
+        Some(digest) => Some(<[u8; 32]>::from_hex(digest)?),
+        None => None,
+    };
+
     let entries_max = param["entries-max"]
         .as_u64()
         .unwrap_or(pbs_client::pxar::ENCODER_MAX_ENTRIES as u64);
@@ -764,21 +1130,59 @@ async fn create_backup(
         devices = Some(set);
     }
 
-    let mut upload_list = vec![];
+    // archives are grouped by their target namespace, each namespace gets its own snapshot
+    let mut sessions: Vec<(BackupNamespace, Vec<UploadListEntry>)> = Vec::new();
     let mut target_set = HashSet::new();
+    let mut stream_count = 0;
+    let mut stdin_used = false;
 
     for backupspec in backupspec_list {
         let spec = parse_backup_specification(backupspec.as_str().unwrap())?;
         let filename = &spec.config_string;
         let target = &spec.archive_name;
+        let ns = spec.ns.clone().unwrap_or_else(|| backup_ns.clone());
 
-        if target_set.contains(target) {
+        if !target_set.insert((ns.clone(), target.to_string())) {
             bail!("got target twice: '{}'", target);
         }
-        target_set.insert(target.to_string());
+
+        let pos = sessions
+            .iter()
+            .position(|(session_ns, _)| *session_ns == ns);
+        let upload_list = match pos {
+            Some(pos) => &mut sessions[pos].1,
+            None => {
+                sessions.push((ns, Vec::new()));
+                &mut sessions.last_mut().unwrap().1
+            }
+        };
 
         use std::os::unix::fs::FileTypeExt;
 
+        if let BackupSpecificationType::STREAM = spec.spec_type {
+            if filename == "-" {
+                if stdin_used {
+                    bail!("standard input can only be used as source once");
+                }
+                stdin_used = true;
+            } else {
+                let file_type = std::fs::metadata(filename)
+                    .map_err(|err| format_err!("unable to access '{}' - {}", filename, err))?
+                    .file_type();
+                if !(file_type.is_file() || file_type.is_fifo()) {
+                    bail!("got unexpected file type (expected regular file or fifo)");
+                }
+            }
+            stream_count += 1;
+            upload_list.push((
+                BackupSpecificationType::STREAM,
+                filename.to_owned(),
+                target.to_owned(),
+                0,
+            ));
+            continue;
+        }
+
         let metadata = std::fs::metadata(filename)
             .map_err(|err| format_err!("unable to access '{}' - {}", filename, err))?;
         let file_type = metadata.file_type();
@@ -835,20 +1239,19 @@ async fn create_backup(
                     metadata.len(),
                 ));
             }
+            BackupSpecificationType::STREAM => unreachable!(), // handled above
         }
     }
 
+    if expected_digest.is_some() && stream_count != 1 {
+        bail!("option 'expected-digest' requires exactly one '.didx' stream source");
+    }
+
     let backup_time = backup_time_opt.unwrap_or_else(epoch_i64);
 
-    let client = connect_rate_limited(&repo, rate_limit)?;
     record_repository(&repo);
 
     let snapshot = BackupDir::from((backup_type, backup_id.to_owned(), backup_time));
-    if backup_ns.is_root() {
-        log::info!("Starting backup: {snapshot}");
-    } else {
-        log::info!("Starting backup: [{backup_ns}]:{snapshot}");
-    }
 
     log::info!("Client name: {}", proxmox_sys::nodename());
 
@@ -891,16 +1294,213 @@ async fn create_backup(
         }
     };
 
+    let params = BackupSessionParams {
+        repo: &repo,
+        rate_limit,
+        wire_compression,
+        crypt_config,
+        rsa_encrypted_key,
+        crypt_mode: crypto.mode,
+        chunk_size: chunk_size_opt,
+        upload_concurrency,
+        pxar_options: pbs_client::pxar::PxarCreateOptions {
+            device_set: devices,
+            patterns: pattern_list,
+            entries_max: entries_max as usize,
+            skip_lost_and_found,
+            skip_e2big_xattr,
+            exclude_log: None,
+        },
+        ignore_quota,
+        send_hostname,
+        local_catalog: local_catalog::local_catalog_enabled(local_catalog, no_local_catalog)?,
+        dry_run,
+        expected_digest,
+        output_format: get_output_format(&param),
+    };
+
+    if sessions.len() == 1 {
+        let (ns, upload_list) = sessions.pop().unwrap();
+        backup_session(&params, &ns, &snapshot, upload_list, Vec::new()).await?;
+    } else {
+        // Sessions run one after the other, so that every session can reuse the chunks of the
+        // snapshots finished before it, instead of uploading them again.
+        let mut results = Vec::with_capacity(sessions.len());
+        let mut finished = Vec::new();
+        for (ns, upload_list) in sessions {
+            let result =
+                backup_session(&params, &ns, &snapshot, upload_list, finished.clone()).await;
+            match result {
+                Ok(Some(ref manifest)) => finished.push((ns.clone(), Arc::clone(manifest))),
+                Ok(None) => (),
+                Err(ref err) => log::error!(
+                    "backup to {} failed - {err}",
+                    print_ns_and_snapshot(&ns, &snapshot)
+                ),
+            }
+            results.push((ns, result));
+        }
+
+        log::info!("Summary:");
+        let mut failed = 0;
+        for (ns, result) in results.iter() {
+            let name = print_ns_and_snapshot(ns, &snapshot);
+            match result {
+                Ok(_) => log::info!("  {name}: OK"),
+                Err(err) => {
+                    failed += 1;
+                    log::error!("  {name}: FAILED - {err}");
+                }
+            }
+        }
+        if failed > 0 {
+            bail!(
+                "backup failed for {failed} of {} namespaces (partial failure)",
+                results.len()
+            );
+        }
+    }
+
+    if dry_run {
+        return Ok(Value::Null);
+    }
+
+    let end_time = std::time::Instant::now();
+    let elapsed = end_time.duration_since(start_time);
+    log::info!("Duration: {:.2}s", elapsed.as_secs_f64());
+    log::info!("End Time: {}", strftime_local("%c", epoch_i64())?);
+    Ok(Value::Null)
+}
+
+/// An archive to upload: type, source path, target archive name and size.
+type UploadListEntry = (BackupSpecificationType, String, String, u64);
+
+/// Parameters shared by the backup sessions of a single `backup` invocation.
+struct BackupSessionParams<'a> {
+    repo: &'a BackupRepository,
+    rate_limit: RateLimitConfig,
+    wire_compression: Option<WireCompression>,
+    crypt_config: Option<Arc<CryptConfig>>,
+    rsa_encrypted_key: Option<Vec<u8>>,
+    crypt_mode: CryptMode,
+    chunk_size: Option<usize>,
+    upload_concurrency: Option<usize>,
+    pxar_options: pbs_client::pxar::PxarCreateOptions,
+    ignore_quota: bool,
+    send_hostname: bool,
+    local_catalog: bool,
+    dry_run: bool,
+    expected_digest: Option<[u8; 32]>,
+    output_format: String,
+}
+
+/// Back up `upload_list` as `snapshot` in namespace `backup_ns`, using its own backup session.
+///
+/// Chunks of the `sibling_manifests` (snapshots of this invocation in other namespaces) are
+/// reused. Returns the manifest of the new snapshot, or `None` for a dry run.
+async fn backup_session(
+    params: &BackupSessionParams<'_>,
+    backup_ns: &BackupNamespace,
+    snapshot: &BackupDir,
+    upload_list: Vec<UploadListEntry>,
+    sibling_manifests: Vec<(BackupNamespace, Arc<BackupManifest>)>,
+) -> Result<Option<Arc<BackupManifest>>, Error> {
+    let repo = params.repo;
+    let crypt_config = &params.crypt_config;
+    let crypt_mode = params.crypt_mode;
+    let chunk_size_opt = params.chunk_size;
+    let upload_concurrency = params.upload_concurrency;
+    let dry_run = params.dry_run;
+
+    let client = connect_rate_limited(repo, params.rate_limit.clone(), params.wire_compression)?;
+
+    if backup_ns.is_root() {
+        log::info!("Starting backup: {snapshot}");
+    } else {
+        log::info!("Starting backup: [{backup_ns}]:{snapshot}");
+    }
+
+    let log_file = |desc: &str, file: &str, target: &str| {
+        let what = if dry_run { "Would upload" } else { "Upload" };
+        log::info!("{} {} '{}' to '{}' as {}", what, desc, file, repo, target);
+    };
+
+    if dry_run {
+        let mut estimate = DryRun::start(
+            &client,
+            repo.store(),
+            backup_ns,
+            &snapshot.group,
+            crypt_config.clone(),
+            crypt_mode,
+        )
+        .await?;
+
+        for (backup_type, filename, target, size) in upload_list {
+            match backup_type {
+                BackupSpecificationType::CONFIG => {
+                    log_file("config file", &filename, &target);
+                    estimate.add_blob(&target, size);
+                }
+                BackupSpecificationType::LOGFILE => {
+                    log_file("log file", &filename, &target);
+                    estimate.add_blob(&target, size);
+                }
+                BackupSpecificationType::PXAR => {
+                    log_file("directory", &filename, &target);
+                    estimate
+                        .estimate_directory(
+                            Path::new(&filename),
+                            &target,
+                            chunk_size_opt,
+                            params.pxar_options.clone(),
+                        )
+                        .await?;
+                }
+                BackupSpecificationType::IMAGE => {
+                    log_file("image", &filename, &target);
+                    estimate
+                        .estimate_image(Path::new(&filename), &target, chunk_size_opt)
+                        .await?;
+                }
+                BackupSpecificationType::STREAM => {
+                    log_file("data stream", &filename, &target);
+                    let source = open_stream_source(&filename, &target).await?;
+                    let source_stats = source.stats();
+                    estimate
+                        .estimate_stream(source, &target, chunk_size_opt)
+                        .await?;
+                    check_stream_source(&source_stats, &target, params.expected_digest.as_ref())?;
+                }
+            }
+        }
+
+        estimate.render(&params.output_format)?;
+        return Ok(None);
+    }
+
     let client = BackupWriter::start(
         client,
         crypt_config.clone(),
         repo.store(),
-        &backup_ns,
-        &snapshot,
+        backup_ns,
+        snapshot,
         true,
         false,
+        params.ignore_quota,
+        params.send_hostname.then(proxmox_sys::nodename),
     )
-    .await?;
+    .await;
+    record_repository_maintenance(&repo, &client);
+    let client = client.map_err(|err| match api_error_code(&err) {
+        Some(ApiErrorCode::QuotaExceeded) => format_err!(
+            "{err}\nUse '--ignore-quota' to start the backup anyway (requires Datastore.Allocate)."
+        ),
+        Some(ApiErrorCode::LockContention) => {
+            format_err!("{err}\nAnother backup of this group is still running, try again later.")
+        }
+        _ => err,
+    })?;
 
     let download_previous_manifest = match client.previous_backup_time().await {
         Ok(Some(backup_time)) => {
@@ -940,28 +1540,18 @@ async fn create_backup(
         None
     };
 
-    let mut manifest = BackupManifest::new(snapshot);
+    let mut manifest = BackupManifest::new(snapshot.clone());
 
     let mut catalog = None;
     let mut catalog_result_rx = None;
-
-    let log_file = |desc: &str, file: &str, target: &str| {
-        let what = if dry_run { "Would upload" } else { "Upload" };
-        log::info!("{} {} '{}' to '{}' as {}", what, desc, file, repo, target);
-    };
+    let mut local_catalog = None;
 
     for (backup_type, filename, target, size) in upload_list {
-        match (backup_type, dry_run) {
-            // dry-run
-            (BackupSpecificationType::CONFIG, true) => log_file("config file", &filename, &target),
-            (BackupSpecificationType::LOGFILE, true) => log_file("log file", &filename, &target),
-            (BackupSpecificationType::PXAR, true) => log_file("directory", &filename, &target),
-            (BackupSpecificationType::IMAGE, true) => log_file("image", &filename, &target),
-            // no dry-run
-            (BackupSpecificationType::CONFIG, false) => {
+        match backup_type {
+            BackupSpecificationType::CONFIG => {
                 let upload_options = UploadOptions {
                     compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
+                    encrypt: crypt_mode == CryptMode::Encrypt,
                     ..UploadOptions::default()
                 };
 
@@ -969,13 +1559,13 @@ async fn create_backup(
                 let stats = client
                     .upload_blob_from_file(&filename, &target, upload_options)
                     .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                manifest.add_file(target, stats.size, stats.csum, crypt_mode)?;
             }
-            (BackupSpecificationType::LOGFILE, false) => {
+            BackupSpecificationType::LOGFILE => {
                 // fixme: remove - not needed anymore ?
                 let upload_options = UploadOptions {
                     compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
+                    encrypt: crypt_mode == CryptMode::Encrypt,
                     ..UploadOptions::default()
                 };
 
@@ -983,13 +1573,22 @@ async fn create_backup(
                 let stats = client
                     .upload_blob_from_file(&filename, &target, upload_options)
                     .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                manifest.add_file(target, stats.size, stats.csum, crypt_mode)?;
             }
-            (BackupSpecificationType::PXAR, false) => {
+            BackupSpecificationType::PXAR => {
                 // start catalog upload on first use
                 if catalog.is_none() {
-                    let catalog_upload_res =
-                        spawn_catalog_upload(client.clone(), crypto.mode == CryptMode::Encrypt)?;
+                    if params.local_catalog {
+                        match LocalCatalog::create(repo, backup_ns, snapshot) {
+                            Ok(file) => local_catalog = Some(Arc::new(Mutex::new(file))),
+                            Err(err) => log::warn!("unable to create local catalog - {err}"),
+                        }
+                    }
+                    let catalog_upload_res = spawn_catalog_upload(
+                        client.clone(),
+                        crypt_mode == CryptMode::Encrypt,
+                        local_catalog.clone(),
+                    )?;
                     catalog = Some(catalog_upload_res.catalog_writer);
                     catalog_result_rx = Some(catalog_upload_res.result);
                 }
@@ -1001,18 +1600,19 @@ async fn create_backup(
                     .unwrap()
                     .start_directory(std::ffi::CString::new(target.as_str())?.as_c_str())?;
 
+                let exclude_log = Arc::new(Mutex::new(Vec::new()));
+
                 let pxar_options = pbs_client::pxar::PxarCreateOptions {
-                    device_set: devices.clone(),
-                    patterns: pattern_list.clone(),
-                    entries_max: entries_max as usize,
-                    skip_lost_and_found,
-                    skip_e2big_xattr,
+                    exclude_log: Some(Arc::clone(&exclude_log)),
+                    ..params.pxar_options.clone()
                 };
 
                 let upload_options = UploadOptions {
                     previous_manifest: previous_manifest.clone(),
                     compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
+                    encrypt: crypt_mode == CryptMode::Encrypt,
+                    upload_concurrency,
+                    sibling_manifests: sibling_manifests.clone(),
                     ..UploadOptions::default()
                 };
 
@@ -1026,30 +1626,49 @@ async fn create_backup(
                     upload_options,
                 )
                 .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                let excludes = std::mem::take(&mut *exclude_log.lock().unwrap());
+                manifest.set_archive_excludes(&target, excludes)?;
+                manifest.add_file(target, stats.size, stats.csum, crypt_mode)?;
                 catalog.lock().unwrap().end_directory()?;
             }
-            (BackupSpecificationType::IMAGE, false) => {
+            BackupSpecificationType::IMAGE => {
                 log_file("image", &filename, &target);
 
                 let upload_options = UploadOptions {
                     previous_manifest: previous_manifest.clone(),
                     fixed_size: Some(size),
                     compress: true,
-                    encrypt: crypto.mode == CryptMode::Encrypt,
+                    encrypt: crypt_mode == CryptMode::Encrypt,
+                    upload_concurrency,
+                    sibling_manifests: sibling_manifests.clone(),
                 };
 
                 let stats =
                     backup_image(&client, &filename, &target, chunk_size_opt, upload_options)
                         .await?;
-                manifest.add_file(target, stats.size, stats.csum, crypto.mode)?;
+                manifest.add_file(target, stats.size, stats.csum, crypt_mode)?;
             }
-        }
-    }
+            BackupSpecificationType::STREAM => {
+                log_file("data stream", &filename, &target);
 
-    if dry_run {
-        log::info!("dry-run: no upload happened");
-        return Ok(Value::Null);
+                let upload_options = UploadOptions {
+                    previous_manifest: previous_manifest.clone(),
+                    compress: true,
+                    encrypt: crypt_mode == CryptMode::Encrypt,
+                    upload_concurrency,
+                    sibling_manifests: sibling_manifests.clone(),
+                    ..UploadOptions::default()
+                };
+
+                let source = open_stream_source(&filename, &target).await?;
+                let source_stats = source.stats();
+                let stats =
+                    backup_stream(&client, source, &target, chunk_size_opt, upload_options).await?;
+                // fails the whole snapshot, as the manifest is never uploaded
+                check_stream_source(&source_stats, &target, params.expected_digest.as_ref())?;
+                manifest.add_file(target, stats.size, stats.csum, crypt_mode)?;
+            }
+        }
     }
 
     // finalize and upload catalog
@@ -1064,11 +1683,11 @@ async fn create_backup(
 
         if let Some(catalog_result_rx) = catalog_result_rx {
             let stats = catalog_result_rx.await??;
-            manifest.add_file(CATALOG_NAME.to_owned(), stats.size, stats.csum, crypto.mode)?;
+            manifest.add_file(CATALOG_NAME.to_owned(), stats.size, stats.csum, crypt_mode)?;
         }
     }
 
-    if let Some(rsa_encrypted_key) = rsa_encrypted_key {
+    if let Some(rsa_encrypted_key) = params.rsa_encrypted_key.clone() {
         let target = ENCRYPTED_KEY_BLOB_NAME;
         log::info!("Upload RSA encoded key to '{}' as {}", repo, target);
         let options = UploadOptions {
@@ -1079,11 +1698,11 @@ async fn create_backup(
         let stats = client
             .upload_blob_from_data(rsa_encrypted_key, target, options)
             .await?;
-        manifest.add_file(target.to_string(), stats.size, stats.csum, crypto.mode)?;
+        manifest.add_file(target.to_string(), stats.size, stats.csum, crypt_mode)?;
     }
     // create manifest (index.json)
     // manifests are never encrypted, but include a signature
-    let manifest = manifest
+    let manifest_json = manifest
         .to_string(crypt_config.as_ref().map(Arc::as_ref))
         .map_err(|err| format_err!("unable to format manifest - {}", err))?;
 
@@ -1095,16 +1714,30 @@ async fn create_backup(
         ..UploadOptions::default()
     };
     client
-        .upload_blob_from_data(manifest.into_bytes(), MANIFEST_BLOB_NAME, options)
+        .upload_blob_from_data(manifest_json.into_bytes(), MANIFEST_BLOB_NAME, options)
         .await?;
 
     client.finish().await?;
 
-    let end_time = std::time::Instant::now();
-    let elapsed = end_time.duration_since(start_time);
-    log::info!("Duration: {:.2}s", elapsed.as_secs_f64());
-    log::info!("End Time: {}", strftime_local("%c", epoch_i64())?);
-    Ok(Value::Null)
+    if let Some(local_catalog) = local_catalog {
+        if let Err(err) = local_catalog.lock().unwrap().commit() {
+            log::warn!("unable to store local catalog - {err}");
+        }
+
+        // the server is reachable now, so drop the catalogs of vanished snapshots of the group
+        let group = Some(&snapshot.group);
+        let result = match connect(repo) {
+            Ok(client) => {
+                local_catalog::prune_local_catalogs(&client, repo, backup_ns, group).await
+            }
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            log::warn!("unable to prune local catalogs - {err}");
+        }
+    }
+
+    Ok(Some(Arc::new(manifest)))
 }
 
 async fn dump_image<W: Write>(
@@ -1113,6 +1746,7 @@ async fn dump_image<W: Write>(
     crypt_mode: CryptMode,
     index: FixedIndexReader,
     mut writer: W,
+    prefetch: usize,
 ) -> Result<(), Error> {
     let most_used = index.find_most_used_chunks(8);
 
@@ -1124,9 +1758,20 @@ async fn dump_image<W: Write>(
     let mut bytes = 0;
     let start_time = std::time::Instant::now();
 
+    let digests: Vec<[u8; 32]> = (0..index.index_count())
+        .map(|pos| *index.index_digest(pos).unwrap())
+        .collect();
+    let mut chunks = std::pin::pin!(pbs_client::prefetch_chunks(
+        &chunk_reader,
+        digests,
+        prefetch
+    ));
+
     for pos in 0..index.index_count() {
-        let digest = index.index_digest(pos).unwrap();
-        let raw_data = chunk_reader.read_chunk(digest).await?;
+        let raw_data = chunks
+            .try_next()
+            .await?
+            .ok_or_else(|| format_err!("chunk stream ended early"))?;
         writer.write_all(&raw_data)?;
         bytes += raw_data.len();
         let next_per = ((pos + 1) * 100) / index.index_count();
@@ -1153,6 +1798,22 @@ async fn dump_image<W: Write>(
     Ok(())
 }
 
+// The journal is placed next to the target directory, so that it is not part of the restored
+// tree. A file system root gets it inside, as there is no other place.
+fn extract_journal_path(target: &Path) -> PathBuf {
+    let journal_name = |name: &std::ffi::OsStr| {
+        let mut journal_name = std::ffi::OsString::from(".");
+        journal_name.push(name);
+        journal_name.push(".restore-journal");
+        journal_name
+    };
+
+    match (target.parent(), target.file_name()) {
+        (Some(parent), Some(name)) => parent.join(journal_name(name)),
+        _ => target.join(".restore-journal"),
+    }
+}
+
 fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     if name.ends_with(".didx") || name.ends_with(".fidx") || name.ends_with(".blob") {
         (name.into(), archive_type(name).unwrap())
@@ -1165,6 +1826,61 @@ fn parse_archive_type(name: &str) -> (String, ArchiveType) {
     }
 }
 
+#[api]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// Format for restoring a file archive as a single stream.
+pub enum RestoreFormat {
+    /// Tar archive, including extended attributes.
+    Tar,
+    /// Zip archive, without symlinks, device nodes, fifos and sockets.
+    Zip,
+}
+
+/// Writes the contents of a pxar archive in `format` to `output`.
+async fn write_archive_stream(
+    accessor: pbs_pxar_fuse::Accessor,
+    format: RestoreFormat,
+    match_list: Vec<MatchEntry>,
+    xattrs: bool,
+    mut output: std::fs::File,
+) -> Result<(), Error> {
+    let (sender, mut receiver) = mpsc::channel(100);
+    let writer = proxmox_async::io::AsyncChannelWriter::new(sender, 1024 * 1024);
+
+    let create = async move {
+        match format {
+            RestoreFormat::Tar => {
+                let options = pbs_client::pxar::TarOptions {
+                    xattrs,
+                    match_list,
+                    ..Default::default()
+                };
+                pbs_client::pxar::create_tar_with_options(writer, accessor, "/", options).await
+            }
+            RestoreFormat::Zip => {
+                let options = pbs_client::pxar::ZipOptions { match_list };
+                pbs_client::pxar::create_zip_with_options(writer, accessor, "/", options).await
+            }
+        }
+    };
+
+    let write = async move {
+        while let Some(data) = receiver.recv().await {
+            let data: Vec<u8> = data?;
+            output
+                .write_all(&data)
+                .map_err(|err| format_err!("unable to pipe data - {}", err))?;
+        }
+        output.flush()?;
+        Ok::<_, Error>(())
+    };
+
+    futures::try_join!(create, write)?;
+
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {
@@ -1188,10 +1904,23 @@ fn parse_archive_type(name: &str) -> (String, ArchiveType) {
                 type: String,
                 description: r###"Target directory path. Use '-' to write to standard output.
 
-We do not extract '.pxar' archives when writing to standard output.
+We do not extract '.pxar' archives when writing to standard output, unless 'format' is set.
 
 "###
             },
+            format: {
+                type: RestoreFormat,
+                optional: true,
+            },
+            pattern: {
+                description: "Only restore entries of file archives matching these paths or patterns.",
+                type: Array,
+                items: {
+                    type: String,
+                    description: "Path or pattern matching entries to restore.",
+                },
+                optional: true,
+            },
             rate: {
                 schema: TRAFFIC_CONTROL_RATE_SCHEMA,
                 optional: true,
@@ -1200,6 +1929,10 @@ We do not extract '.pxar' archives when writing to standard output.
                 schema: TRAFFIC_CONTROL_BURST_SCHEMA,
                 optional: true,
             },
+            compress: {
+                schema: CLIENT_COMPRESSION_SCHEMA,
+                optional: true,
+            },
             "allow-existing-dirs": {
                 type: Boolean,
                 description: "Do not fail if directories already exists.",
@@ -1268,7 +2001,29 @@ We do not extract '.pxar' archives when writing to standard output.
                 description: "ignore errors that occur during device node extraction",
                 optional: true,
                 default: false,
-            }
+            },
+            "show-excludes": {
+                type: Boolean,
+                description: "Show the exclude patterns recorded when the archive was created.",
+                optional: true,
+                default: false,
+            },
+            resume: {
+                type: Boolean,
+                description: "Resume an interrupted restore. Image data already present in the \
+                    target is not downloaded again, file archive extraction continues from its \
+                    progress journal, keeping already extracted files. Implies overwriting.",
+                optional: true,
+                default: false,
+            },
+            prefetch: {
+                type: Integer,
+                description: "Number of chunk requests kept in flight for image restores.",
+                minimum: 1,
+                maximum: 64,
+                optional: true,
+                default: pbs_client::DEFAULT_CHUNK_PREFETCH as isize,
+            },
         }
     }
 )]
@@ -1285,6 +2040,11 @@ async fn restore(
     overwrite_symlinks: bool,
     overwrite_hardlinks: bool,
     ignore_extract_device_errors: bool,
+    show_excludes: bool,
+    resume: bool,
+    prefetch: usize,
+    format: Option<RestoreFormat>,
+    pattern: Option<Vec<String>>,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
 
@@ -1301,7 +2061,9 @@ async fn restore(
 
     let rate_limit = RateLimitConfig::with_same_inout(rate, burst);
 
-    let client = connect_rate_limited(&repo, rate_limit)?;
+    let wire_compression = extract_wire_compression_from_value(&param)?;
+
+    let client = connect_rate_limited(&repo, rate_limit, wire_compression)?;
     record_repository(&repo);
 
     let ns = optional_ns_param(&param)?;
@@ -1312,6 +2074,21 @@ async fn restore(
     let target = json::required_string_param(&param, "target")?;
     let target = if target == "-" { None } else { Some(target) };
 
+    if resume && target.is_none() {
+        bail!("option 'resume' requires a target path");
+    }
+    if resume && format.is_some() {
+        bail!("option 'resume' cannot be combined with 'format'");
+    }
+
+    let mut match_list = Vec::new();
+    for entry in pattern.unwrap_or_default() {
+        match_list.push(
+            MatchEntry::parse_pattern(entry, PatternFlag::PATH_NAME, MatchType::Include)
+                .map_err(|err| format_err!("error in pattern: {}", err))?,
+        );
+    }
+
     let crypto = crypto_parameters(&param)?;
 
     let crypt_config = match crypto.enc_key {
@@ -1334,10 +2111,16 @@ async fn restore(
         &backup_dir,
         true,
     )
-    .await?;
+    .await;
+    record_repository_maintenance(&repo, &client);
+    let client = client?;
 
     let (archive_name, archive_type) = parse_archive_type(archive_name);
 
+    if (format.is_some() || !match_list.is_empty()) && !archive_name.ends_with(".pxar.didx") {
+        bail!("options 'format' and 'pattern' are only supported for file archives (.pxar)");
+    }
+
     let (manifest, backup_index_data) = client.download_manifest().await?;
 
     if archive_name == ENCRYPTED_KEY_BLOB_NAME && crypt_config.is_none() {
@@ -1370,6 +2153,10 @@ async fn restore(
 
     let file_info = manifest.lookup_file_info(&archive_name)?;
 
+    if show_excludes {
+        show_archive_excludes(&manifest, &archive_name);
+    }
+
     if archive_type == ArchiveType::Blob {
         let mut reader = client.download_blob(&manifest, &archive_name).await?;
 
@@ -1377,7 +2164,8 @@ async fn restore(
             let mut writer = std::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
-                .create_new(true)
+                .create_new(!resume)
+                .truncate(resume)
                 .open(target)
                 .map_err(|err| {
                     format_err!("unable to create target file {:?} - {}", target, err)
@@ -1405,6 +2193,33 @@ async fn restore(
 
         let mut reader = BufferedDynamicReader::new(index, chunk_reader);
 
+        if let Some(format) = format {
+            let output = if let Some(target) = target {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(target)
+                    .map_err(|err| {
+                        format_err!("unable to create target file {:?} - {}", target, err)
+                    })?
+            } else {
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open("/dev/stdout")
+                    .map_err(|err| format_err!("unable to open /dev/stdout - {}", err))?
+            };
+
+            let archive_size = reader.archive_size();
+            let reader: pbs_pxar_fuse::Reader = Arc::new(BufferedDynamicReadAt::new(reader));
+            let accessor = pbs_pxar_fuse::Accessor::new(reader, archive_size).await?;
+
+            write_archive_stream(accessor, format, match_list, !ignore_xattrs, output)
+                .await
+                .map_err(|err| format_err!("error writing archive stream - {:#}", err))?;
+
+            return Ok(Value::Null);
+        }
+
         let on_error = if ignore_extract_device_errors {
             let handler: PxarErrorHandler = Box::new(move |err: Error| {
                 use pbs_client::pxar::PxarExtractContext;
@@ -1434,12 +2249,13 @@ async fn restore(
             overwrite_flags.insert(pbs_client::pxar::OverwriteFlags::all());
         }
 
-        let options = pbs_client::pxar::PxarExtractOptions {
-            match_list: &[],
-            extract_match_default: true,
+        let mut options = pbs_client::pxar::PxarExtractOptions {
+            match_list: &match_list,
+            extract_match_default: match_list.is_empty(),
             allow_existing_dirs,
             overwrite_flags,
             on_error,
+            journal: None,
         };
 
         let mut feature_flags = pbs_client::pxar::Flags::DEFAULT;
@@ -1458,6 +2274,20 @@ async fn restore(
         }
 
         if let Some(target) = target {
+            if resume {
+                let journal = pbs_client::pxar::ExtractJournal::open(
+                    extract_journal_path(Path::new(target)),
+                    hex::encode(file_info.csum),
+                )?;
+                if journal.reached() > 0 {
+                    log::info!(
+                        "resuming extraction, checking {} already extracted entries",
+                        journal.reached()
+                    );
+                }
+                options.journal = Some(journal);
+            }
+
             pbs_client::pxar::extract_archive(
                 pxar::decoder::Decoder::from_std(reader)?,
                 Path::new(target),
@@ -1482,6 +2312,57 @@ async fn restore(
             .download_fixed_index(&manifest, &archive_name)
             .await?;
 
+        if resume {
+            // unwrap: checked above
+            let target = target.unwrap();
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(target)
+                .map_err(|err| format_err!("unable to open target file {:?} - {}", target, err))?;
+
+            let crypt_mode = file_info.chunk_crypt_mode();
+            let most_used = index.find_most_used_chunks(8);
+            let chunk_reader =
+                RemoteChunkReader::new(client.clone(), crypt_config.clone(), crypt_mode, most_used);
+            let digest_config = match crypt_mode {
+                CryptMode::Encrypt => crypt_config.as_deref(),
+                CryptMode::SignOnly | CryptMode::None => None,
+            };
+
+            let start_time = std::time::Instant::now();
+            let chunks = (0..index.index_count()).filter_map(|pos| index.chunk_info(pos));
+            let stats = pbs_client::restore_image(
+                chunks,
+                &chunk_reader,
+                &file,
+                digest_config,
+                true,
+                prefetch,
+            )
+            .await?;
+
+            if file.metadata()?.file_type().is_file() {
+                file.set_len(index.index_bytes())?;
+            }
+
+            let elapsed = start_time.elapsed().as_secs_f64();
+            log::info!(
+                "restore image complete (restored {} chunks with {}, kept {} chunks with {}, \
+                duration={:.2}s, speed={:.2}MB/s)",
+                stats.restored_chunks,
+                HumanByte::from(stats.restored_bytes),
+                stats.skipped_chunks,
+                HumanByte::from(stats.skipped_bytes),
+                elapsed,
+                stats.restored_bytes as f64 / (1024.0 * 1024.0 * elapsed),
+            );
+
+            return Ok(Value::Null);
+        }
+
         let mut writer = if let Some(target) = target {
             std::fs::OpenOptions::new()
                 .write(true)
@@ -1502,6 +2383,7 @@ async fn restore(
             file_info.chunk_crypt_mode(),
             index,
             &mut writer,
+            prefetch,
         )
         .await?;
     }
@@ -1539,6 +2421,10 @@ async fn restore(
                 schema: REPO_URL_SCHEMA,
                 optional: true,
             },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
         },
     },
 )]
@@ -1548,6 +2434,7 @@ async fn prune(
     group: String,
     prune_options: PruneJobOptions,
     quiet: bool,
+    wait: Option<u64>,
     mut param: Value,
 ) -> Result<Value, Error> {
     let repo = extract_repository_from_value(&param)?;
@@ -1564,6 +2451,9 @@ async fn prune(
     if let Some(dry_run) = dry_run {
         api_param["dry-run"] = dry_run.into();
     }
+    if let Some(wait) = wait {
+        api_param["wait"] = wait.into();
+    }
     merge_group_into(api_param.as_object_mut().unwrap(), group);
 
     let mut result = client.post(&path, Some(api_param)).await?;
@@ -1757,6 +2647,12 @@ fn main() {
     let garbage_collect_cmd_def = CliCommand::new(&API_METHOD_START_GARBAGE_COLLECTION)
         .completion_cb("repository", complete_repository);
 
+    let verify_cmd_def = CliCommand::new(&API_METHOD_VERIFY)
+        .arg_param(&["target"])
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("target", complete_group_or_snapshot)
+        .completion_cb("repository", complete_repository);
+
     let restore_cmd_def = CliCommand::new(&API_METHOD_RESTORE)
         .arg_param(&["snapshot", "archive-name", "target"])
         .completion_cb("repository", complete_repository)
@@ -1790,9 +2686,17 @@ fn main() {
         .completion_cb("new-owner", complete_auth_id)
         .completion_cb("repository", complete_repository);
 
+    let dedup_stats_cmd_def = CliCommand::new(&API_METHOD_GROUP_DEDUP_STATS)
+        .arg_param(&["group"])
+        .completion_cb("ns", complete_namespace)
+        .completion_cb("group", complete_backup_group)
+        .completion_cb("repository", complete_repository);
+
     let cmd_def = CliCommandMap::new()
         .insert("backup", backup_cmd_def)
         .insert("garbage-collect", garbage_collect_cmd_def)
+        .insert("group", group_mgmt_cli())
+        .insert("import", import::import_cli())
         .insert("list", list_cmd_def)
         .insert("login", login_cmd_def)
         .insert("logout", logout_cmd_def)
@@ -1806,6 +2710,8 @@ fn main() {
         .insert("version", version_cmd_def)
         .insert("benchmark", benchmark_cmd_def)
         .insert("change-owner", change_owner_cmd_def)
+        .insert("dedup-stats", dedup_stats_cmd_def)
+        .insert("verify", verify_cmd_def)
         .insert("namespace", namespace::cli_map())
         .alias(&["files"], &["snapshot", "files"])
         .alias(&["forget"], &["snapshot", "forget"])
@@ -1821,3 +2727,42 @@ fn main() {
         Some(|future| proxmox_async::runtime::main(future)),
     );
 }
+
+#[cfg(test)]
+mod test {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_check_stream_source_digest() -> Result<(), Error> {
+        let data = b"raw stream data".to_vec();
+        let digest = openssl::sha::sha256(&data);
+
+        let source = StreamSource::new(&data[..]);
+        let stats = source.stats();
+
+        // the stats are only available once the whole source was read
+        let err = check_stream_source(&stats, "data.img", Some(&digest)).unwrap_err();
+        assert!(err.to_string().contains("source was not read completely"));
+
+        let _: Vec<_> = block_on(source.try_collect())?;
+
+        check_stream_source(&stats, "data.img", None)?;
+        check_stream_source(&stats, "data.img", Some(&digest))?;
+
+        let mut wrong = digest;
+        wrong[31] ^= 1;
+        let err = check_stream_source(&stats, "data.img", Some(&wrong)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "data.img: digest mismatch - expected {}, got {}",
+                hex::encode(wrong),
+                hex::encode(digest),
+            ),
+        );
+
+        Ok(())
+    }
+}
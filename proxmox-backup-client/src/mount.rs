@@ -77,10 +77,29 @@ const API_METHOD_MOUNT: ApiMethod = ApiMethod::new(
 const API_METHOD_MAP: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&mount),
     &ObjectSchema::new(
-        "Map a drive image from a VM backup to a local loopback device. Use 'unmap' to undo.
+        "Map a drive image from a VM backup to a local loopback device, or to a network block \
+device if 'device' is given. Use 'unmap' to undo.
 WARNING: Only do this with *trusted* backups!",
         &sorted!([
             ("ns", true, &BackupNamespace::API_SCHEMA,),
+            (
+                "device",
+                true,
+                &StringSchema::new(
+                    "Map to the given network block device (/dev/nbdX) instead of a loop device. \
+                    Requires the 'nbd' kernel module."
+                )
+                .schema()
+            ),
+            (
+                "cache-size",
+                true,
+                &IntegerSchema::new("Number of chunks kept in the read cache (4 MiB each).")
+                    .minimum(1)
+                    .maximum(1024)
+                    .default(8)
+                    .schema()
+            ),
             (
                 "snapshot",
                 false,
@@ -112,12 +131,13 @@ WARNING: Only do this with *trusted* backups!",
 const API_METHOD_UNMAP: ApiMethod = ApiMethod::new(
     &ApiHandler::Sync(&unmap),
     &ObjectSchema::new(
-        "Unmap a loop device mapped with 'map' and release all resources.",
+        "Unmap a loop or network block device mapped with 'map' and release all resources.",
         &sorted!([(
             "name",
             true,
             &StringSchema::new(concat!(
-                "Archive name, path to loopdev (/dev/loopX) or loop device number. ",
+                "Archive name, path to loopdev (/dev/loopX), loop device number or path to ",
+                "network block device (/dev/nbdX). ",
                 "Omit to list all current mappings and force cleaning up leftover instances."
             ))
             .schema()
@@ -154,12 +174,16 @@ fn complete_mapping_names<S: BuildHasher>(
     _arg: &str,
     _param: &HashMap<String, String, S>,
 ) -> Vec<String> {
-    match pbs_fuse_loop::find_all_mappings() {
+    let mut list: Vec<String> = match pbs_fuse_loop::find_all_mappings() {
         Ok(mappings) => mappings
             .filter_map(|(name, _)| proxmox_sys::systemd::unescape_unit(&name).ok())
             .collect(),
         Err(_) => Vec::new(),
+    };
+    if let Ok(mappings) = pbs_fuse_loop::nbd::find_all_mappings() {
+        list.extend(mappings.into_iter().map(|(name, _)| name));
     }
+    list
 }
 
 fn mount(
@@ -201,6 +225,8 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
     let client = connect(&repo)?;
 
     let target = param["target"].as_str();
+    let nbd_device = param["device"].as_str();
+    let cache_size = param["cache-size"].as_u64().unwrap_or(8) as usize;
 
     record_repository(&repo);
 
@@ -321,9 +347,36 @@ async fn mount_do(param: Value, pipe: Option<OwnedFd>) -> Result<Value, Error> {
             file_info.chunk_crypt_mode(),
             HashMap::new(),
         );
-        let reader = CachedChunkReader::new(chunk_reader, index, 8).seekable();
+        let reader = CachedChunkReader::new(chunk_reader, index, cache_size).seekable();
 
         let name = &format!("{}:{}/{}", repo, path, archive_name);
+
+        if let Some(device) = nbd_device {
+            let mut session =
+                pbs_fuse_loop::nbd::NbdSession::map_nbd(device, size, reader, name).await?;
+
+            // daemonize only now to be able to print mapped device or startup errors
+            log::info!("Image '{}' mapped on {}", name, session.device_path);
+            daemonize()?;
+
+            let (mut abort_send, abort_recv) = futures::channel::mpsc::channel(1);
+            let mut session_fut = session.main(abort_recv).boxed().fuse();
+
+            // continue polling until complete or interrupted (which also happens on unmap)
+            select! {
+                res = session_fut => res?,
+                _ = interrupt => {
+                    // exit on interrupted
+                    abort_send.try_send(()).map_err(|err|
+                        format_err!("error while sending abort signal - {}", err))?;
+                    session_fut.await?;
+                }
+            }
+
+            log::info!("Image unmapped");
+            return Ok(Value::Null);
+        }
+
         let name_escaped = proxmox_sys::systemd::escape_unit(name, false);
 
         let mut session =
@@ -394,6 +447,10 @@ fn unmap(
                 );
                 any = true;
             }
+            for (name, device) in pbs_fuse_loop::nbd::find_all_mappings()? {
+                log::info!("{}:\t{}", device, name);
+                any = true;
+            }
             if !any {
                 log::info!("Nothing mapped.");
             }
@@ -408,7 +465,9 @@ fn unmap(
 
     if name.starts_with("/dev/loop") {
         pbs_fuse_loop::unmap_loopdev(name)?;
-    } else {
+    } else if name.starts_with("/dev/nbd") {
+        pbs_fuse_loop::nbd::unmap_device(name)?;
+    } else if !pbs_fuse_loop::nbd::unmap_name(&name)? {
         let name = proxmox_sys::systemd::escape_unit(&name, false);
         pbs_fuse_loop::unmap_name(name)?;
     }
@@ -1,9 +1,10 @@
 use anyhow::{bail, Error};
 use serde_json::{json, Value};
 
-use pbs_api_types::BackupNamespace;
+use pbs_api_types::{BackupNamespace, NamespaceDeleteSummary, NS_MAX_DEPTH_SCHEMA};
 use pbs_client::tools::REPO_URL_SCHEMA;
 
+use proxmox_human_byte::HumanByte;
 use proxmox_router::cli::{
     format_and_print_result, get_output_format, CliCommand, CliCommandMap, OUTPUT_FORMAT,
 };
@@ -68,11 +69,15 @@ async fn list_namespaces(param: Value, max_depth: Option<usize>) -> Result<(), E
                 continue;
             }
 
+            let mut line = entry.ns.to_string();
             if let Some(comment) = entry.comment {
-                println!("{} ({comment})", entry.ns);
-            } else {
-                println!("{}", entry.ns);
+                line.push_str(&format!(" ({comment})"));
             }
+            if let Some(quota) = entry.quota {
+                let usage = HumanByte::from(entry.usage.unwrap_or(0));
+                line.push_str(&format!(" [{usage} of {} used]", HumanByte::from(quota)));
+            }
+            println!("{line}");
         }
     } else {
         format_and_print_result(&result, &output_format);
@@ -136,11 +141,29 @@ async fn create_namespace(param: Value) -> Result<(), Error> {
                 description: "Destroys all groups in the hierarchy.",
                 optional: true,
             },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "ignore-protected": {
+                description: "Skip protected snapshots instead of refusing the deletion.",
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
         }
     },
 )]
 /// Delete an existing namespace.
-async fn delete_namespace(param: Value, delete_groups: Option<bool>) -> Result<(), Error> {
+async fn delete_namespace(
+    param: Value,
+    delete_groups: Option<bool>,
+    max_depth: Option<usize>,
+    ignore_protected: Option<bool>,
+) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
     let repo = extract_repository_from_value(&param)?;
     let backup_ns = optional_ns_param(&param)?;
 
@@ -154,13 +177,35 @@ async fn delete_namespace(param: Value, delete_groups: Option<bool>) -> Result<(
     if let Some(value) = delete_groups {
         param["delete-groups"] = serde_json::to_value(value)?;
     }
+    if let Some(value) = max_depth {
+        param["max-depth"] = value.into();
+    }
+    if let Some(value) = ignore_protected {
+        param["ignore-protected"] = value.into();
+    }
 
     let client = connect(&repo)?;
 
-    let _result = client.delete(&path, Some(param)).await?;
+    let mut result = client.delete(&path, Some(param)).await?;
 
     record_repository(&repo);
 
+    if output_format == "text" {
+        let summary: NamespaceDeleteSummary = serde_json::from_value(result["data"].take())?;
+        println!(
+            "removed {} groups, {} snapshots and {} namespaces",
+            summary.removed_groups, summary.removed_snapshots, summary.removed_namespaces,
+        );
+        if !summary.skipped_protected.is_empty() {
+            println!("kept protected snapshots:");
+            for snapshot in summary.skipped_protected {
+                println!("  {snapshot}");
+            }
+        }
+    } else {
+        format_and_print_result(&result, &output_format);
+    }
+
     Ok(())
 }
 
@@ -7,7 +7,9 @@ use proxmox_router::cli::*;
 use proxmox_schema::api;
 use proxmox_sys::fs::file_get_contents;
 
-use pbs_api_types::{BackupGroup, BackupNamespace, CryptMode, SnapshotListItem};
+use pbs_api_types::{
+    BackupGroup, BackupNamespace, CryptMode, SnapshotListItem, TrashItem, LOCK_WAIT_SCHEMA,
+};
 use pbs_client::tools::key_source::get_encryption_key_password;
 use pbs_datastore::DataBlob;
 use pbs_key_config::decrypt_key;
@@ -96,6 +98,12 @@ async fn list_snapshots(param: Value) -> Result<Value, Error> {
                 .header("snapshot"),
         )
         .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
+        .column(
+            ColumnConfig::new("upload-size")
+                .renderer(pbs_tools::format::render_bytes_human_readable)
+                .header("uploaded"),
+        )
+        .column(ColumnConfig::new("duration").renderer(pbs_tools::format::render_duration))
         .column(ColumnConfig::new("files").renderer(render_files));
 
     let return_type = &pbs_api_types::ADMIN_DATASTORE_LIST_SNAPSHOTS_RETURN_TYPE;
@@ -173,6 +181,10 @@ async fn list_snapshot_files(param: Value) -> Result<Value, Error> {
                 type: String,
                 description: "Snapshot path.",
             },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
         }
     }
 )]
@@ -188,8 +200,114 @@ async fn forget_snapshots(param: Value) -> Result<(), Error> {
 
     let path = format!("api2/json/admin/datastore/{}/snapshots", repo.store());
 
+    let mut args = snapshot_args(&backup_ns, &snapshot)?;
+    if let Some(wait) = param["wait"].as_u64() {
+        args["wait"] = wait.into();
+    }
+
+    client.delete(&path, Some(args)).await?;
+
+    record_repository(&repo);
+
+    Ok(())
+}
+
+#[api(
+   input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+   }
+)]
+/// List deleted snapshots in the trash.
+async fn list_trash(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let output_format = get_output_format(&param);
+
+    let client = connect(&repo)?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let mut args = json!({});
+    if !backup_ns.is_root() {
+        args["ns"] = serde_json::to_value(&backup_ns)?;
+    }
+
+    let path = format!("api2/json/admin/datastore/{}/trash", repo.store());
+    let mut result = client.get(&path, Some(args)).await?;
+
+    record_repository(&repo);
+
+    let render_snapshot_path = |_v: &Value, record: &Value| -> Result<String, Error> {
+        let item: TrashItem = serde_json::from_value(record.to_owned())?;
+        Ok(item.backup.to_string())
+    };
+
+    let options = default_table_format_options()
+        .sortby("backup-type", false)
+        .sortby("backup-id", false)
+        .sortby("backup-time", false)
+        .column(
+            ColumnConfig::new("backup-id")
+                .renderer(render_snapshot_path)
+                .header("snapshot"),
+        )
+        .column(ColumnConfig::new("deleted").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("purge-after").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("protected"));
+
+    let return_type = &pbs_api_types::ADMIN_DATASTORE_LIST_TRASH_RETURN_TYPE;
+
+    let mut data: Value = result["data"].take();
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+        }
+    }
+)]
+/// Restore a deleted snapshot from the trash.
+async fn restore_trash(param: Value) -> Result<(), Error> {
+    let repo = extract_repository_from_value(&param)?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let path = required_string_param(&param, "snapshot")?;
+    let snapshot: BackupDir = path.parse()?;
+
+    let client = connect(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/trash", repo.store());
+
     client
-        .delete(&path, Some(snapshot_args(&backup_ns, &snapshot)?))
+        .post(&path, Some(snapshot_args(&backup_ns, &snapshot)?))
         .await?;
 
     record_repository(&repo);
@@ -475,6 +593,59 @@ async fn update_protection(protected: bool, param: Value) -> Result<(), Error> {
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            repository: {
+                schema: REPO_URL_SCHEMA,
+                optional: true,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            snapshot: {
+                type: String,
+                description: "Snapshot path.",
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the verification history of a snapshot.
+async fn show_verify_history(param: Value) -> Result<Value, Error> {
+    let repo = extract_repository_from_value(&param)?;
+    let path = required_string_param(&param, "snapshot")?;
+
+    let backup_ns = optional_ns_param(&param)?;
+    let snapshot: BackupDir = path.parse()?;
+    let client = connect(&repo)?;
+
+    let path = format!("api2/json/admin/datastore/{}/verify-history", repo.store());
+
+    let args = snapshot_args(&backup_ns, &snapshot)?;
+
+    let output_format = get_output_format(&param);
+
+    let mut result = client.get(&path, Some(args)).await?;
+
+    let return_type = &pbs_api_types::ADMIN_DATASTORE_VERIFY_HISTORY_RETURN_TYPE;
+
+    let mut data: Value = result["data"].take();
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("time").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("state"))
+        .column(ColumnConfig::new("upid"));
+
+    format_and_print_result_full(&mut data, return_type, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
 fn protected_cli() -> CliCommandMap {
     CliCommandMap::new()
         .insert(
@@ -493,6 +664,23 @@ fn protected_cli() -> CliCommandMap {
         )
 }
 
+fn trash_cli() -> CliCommandMap {
+    CliCommandMap::new()
+        .insert(
+            "list",
+            CliCommand::new(&API_METHOD_LIST_TRASH)
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("repository", complete_repository),
+        )
+        .insert(
+            "restore",
+            CliCommand::new(&API_METHOD_RESTORE_TRASH)
+                .arg_param(&["snapshot"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("repository", complete_repository),
+        )
+}
+
 fn notes_cli() -> CliCommandMap {
     CliCommandMap::new()
         .insert(
@@ -515,6 +703,7 @@ pub fn snapshot_mgtm_cli() -> CliCommandMap {
     CliCommandMap::new()
         .insert("notes", notes_cli())
         .insert("protected", protected_cli())
+        .insert("trash", trash_cli())
         .insert(
             "list",
             CliCommand::new(&API_METHOD_LIST_SNAPSHOTS)
@@ -539,6 +728,14 @@ pub fn snapshot_mgtm_cli() -> CliCommandMap {
                 .completion_cb("repository", complete_repository)
                 .completion_cb("snapshot", complete_backup_snapshot),
         )
+        .insert(
+            "verify-history",
+            CliCommand::new(&API_METHOD_SHOW_VERIFY_HISTORY)
+                .arg_param(&["snapshot"])
+                .completion_cb("ns", complete_namespace)
+                .completion_cb("repository", complete_repository)
+                .completion_cb("snapshot", complete_backup_snapshot),
+        )
         .insert(
             "upload-log",
             CliCommand::new(&API_METHOD_UPLOAD_LOG)
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use std::future::Future;
 use std::hash::BuildHasher;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use anyhow::{bail, Error};
 use serde::{Deserialize, Serialize};
@@ -15,8 +16,10 @@ use pbs_api_types::{file_restore::FileRestoreFormat, BackupDir, BackupNamespace}
 use pbs_client::BackupRepository;
 use pbs_datastore::catalog::ArchiveEntry;
 use pbs_datastore::manifest::BackupManifest;
+use pbs_tools::crypt_config::CryptConfig;
 
 use super::block_driver_qemu::QemuBlockDriver;
+use super::block_driver_userspace::UserspaceBlockDriver;
 
 /// Contains details about a snapshot that is to be accessed by block file restore
 pub struct SnapRestoreDetails {
@@ -25,6 +28,7 @@ pub struct SnapRestoreDetails {
     pub snapshot: BackupDir,
     pub manifest: BackupManifest,
     pub keyfile: Option<String>,
+    pub crypt_config: Option<Arc<CryptConfig>>,
 }
 
 /// Return value of a BlockRestoreDriver.status() call, 'id' must be valid for .stop(id)
@@ -74,18 +78,23 @@ pub trait BlockRestoreDriver {
 pub enum BlockDriverType {
     /// Uses a small QEMU/KVM virtual machine to map images securely. Requires PVE-patched QEMU.
     Qemu,
+    /// Reads partition tables and ext4/XFS filesystems directly, without a VM. LVM, ZFS and
+    /// encrypted volumes are not supported.
+    #[serde(rename = "userspace")]
+    Userspace,
 }
 
 impl BlockDriverType {
-    fn resolve(&self) -> impl BlockRestoreDriver {
+    fn resolve(&self) -> Box<dyn BlockRestoreDriver> {
         match self {
-            BlockDriverType::Qemu => QemuBlockDriver {},
+            BlockDriverType::Qemu => Box::new(QemuBlockDriver {}),
+            BlockDriverType::Userspace => Box::new(UserspaceBlockDriver {}),
         }
     }
 }
 
 const DEFAULT_DRIVER: BlockDriverType = BlockDriverType::Qemu;
-const ALL_DRIVERS: &[BlockDriverType] = &[BlockDriverType::Qemu];
+const ALL_DRIVERS: &[BlockDriverType] = &[BlockDriverType::Qemu, BlockDriverType::Userspace];
 
 pub async fn data_list(
     driver: Option<BlockDriverType>,
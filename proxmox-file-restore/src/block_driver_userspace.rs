@@ -0,0 +1,614 @@
+//! Block file access by parsing partition tables and filesystems directly, without a restore VM
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+use bytes::Bytes;
+use futures::{FutureExt, TryStreamExt};
+use pxar::encoder::sync::{Encoder, StandardWriter};
+use pxar::encoder::SeqWrite;
+
+use proxmox_compression::zstd::ZstdEncoder;
+
+use pbs_api_types::file_restore::FileRestoreFormat;
+use pbs_client::tools::connect;
+use pbs_client::{BackupReader, RemoteChunkReader};
+use pbs_datastore::cached_chunk_reader::CachedChunkReader;
+use pbs_datastore::catalog::{ArchiveEntry, DirEntryAttribute};
+use pbs_datastore::fixed_index::FixedIndexReader;
+use pbs_datastore::index::IndexFile;
+
+use super::block_driver::*;
+use crate::image_fs::{
+    self, FileKind, FileReader, FileSystem, ImageRead, ImageSlice, Inode, Layout, Partition,
+};
+
+/// Number of chunks kept in memory for each opened image
+const CHUNK_CACHE_CAPACITY: usize = 16;
+
+/// Size of the blocks passed from the blocking encoder to the async reader
+const STREAM_BLOCK_SIZE: usize = 1024 * 1024;
+
+pub struct UserspaceBlockDriver {}
+
+/// A fixed index image, backed by the chunks of the remote snapshot
+struct IndexImage {
+    reader: CachedChunkReader<FixedIndexReader, RemoteChunkReader>,
+    size: u64,
+}
+
+impl ImageRead for IndexImage {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        let read = proxmox_async::runtime::block_on(self.reader.read_at(buf, offset))?;
+        if read != buf.len() {
+            bail!("unexpected end of image at offset {}", offset + read as u64);
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+async fn open_image(
+    details: &SnapRestoreDetails,
+    img_file: &str,
+) -> Result<Arc<dyn ImageRead>, Error> {
+    let client = connect(&details.repo)?;
+    let client = BackupReader::start(
+        &client,
+        details.crypt_config.clone(),
+        details.repo.store(),
+        &details.namespace,
+        &details.snapshot,
+        true,
+    )
+    .await?;
+
+    let file_info = details.manifest.lookup_file_info(img_file)?;
+    let index = client
+        .download_fixed_index(&details.manifest, img_file)
+        .await?;
+    let most_used = index.find_most_used_chunks(8);
+    let chunk_reader = RemoteChunkReader::new(
+        client,
+        details.crypt_config.clone(),
+        file_info.chunk_crypt_mode(),
+        most_used,
+    );
+
+    let size = index.index_bytes();
+    Ok(Arc::new(IndexImage {
+        reader: CachedChunkReader::new(chunk_reader, index, CHUNK_CACHE_CAPACITY),
+        size,
+    }))
+}
+
+/// Result of resolving a path like "part/1/etc/passwd" or "raw/etc/passwd" within an image,
+/// mirroring the bucket layout of the restore VM
+enum Resolved {
+    BucketTypes(Vec<&'static str>),
+    Partitions(Vec<Partition>),
+    Entry(Box<dyn FileSystem>, Inode),
+}
+
+fn resolve(image: Arc<dyn ImageRead>, path: &[u8]) -> Result<Resolved, Error> {
+    let mut components = path.split(|b| *b == b'/').filter(|c| !c.is_empty());
+    let layout = image_fs::detect_layout(&*image)?;
+
+    let bucket = match components.next() {
+        Some(bucket) => bucket,
+        None => {
+            let types = match layout {
+                Layout::PartitionTable => vec!["part"],
+                Layout::Ext4 | Layout::Xfs => vec!["raw"],
+                other => bail!("unsupported layout - {other}"),
+            };
+            return Ok(Resolved::BucketTypes(types));
+        }
+    };
+
+    let fs_image: Arc<dyn ImageRead> = match (bucket, layout) {
+        (b"raw", Layout::Ext4 | Layout::Xfs) => image,
+        (b"part", Layout::PartitionTable) => {
+            let partitions = image_fs::read_partitions(&*image)?;
+            let number = match components.next() {
+                Some(number) => number,
+                None => return Ok(Resolved::Partitions(partitions)),
+            };
+            let part = partitions
+                .into_iter()
+                .find(|part| part.number.to_string().as_bytes() == number)
+                .ok_or_else(|| {
+                    format_err!("partition '{}' not found", String::from_utf8_lossy(number))
+                })?;
+            Arc::new(ImageSlice::new(image, part.start, part.size)?)
+        }
+        _ => bail!("bucket '{}' not found", String::from_utf8_lossy(bucket)),
+    };
+
+    let fs = image_fs::open_filesystem(fs_image)?;
+    let path = components.collect::<Vec<&[u8]>>().join(&b'/');
+    let inode = image_fs::lookup(&*fs, &path)?;
+
+    Ok(Resolved::Entry(fs, inode))
+}
+
+fn entry_attribute(inode: &Inode) -> DirEntryAttribute {
+    match inode.kind {
+        FileKind::Directory => DirEntryAttribute::Directory { start: 0 },
+        FileKind::File => DirEntryAttribute::File {
+            size: inode.size,
+            mtime: inode.mtime,
+        },
+        FileKind::Symlink => DirEntryAttribute::Symlink,
+        FileKind::BlockDevice => DirEntryAttribute::BlockDevice,
+        FileKind::CharDevice => DirEntryAttribute::CharDevice,
+        FileKind::Fifo => DirEntryAttribute::Fifo,
+        FileKind::Socket => DirEntryAttribute::Socket,
+    }
+}
+
+fn list_entries(
+    image: Arc<dyn ImageRead>,
+    img_file: &str,
+    path: &[u8],
+) -> Result<Vec<ArchiveEntry>, Error> {
+    let base: Vec<u8> = img_file.bytes().chain(path.iter().copied()).collect();
+    let child_path = |name: &[u8]| -> Vec<u8> {
+        let mut child = base.clone();
+        child.push(b'/');
+        child.extend(name);
+        child
+    };
+
+    let mut res = Vec::new();
+    match resolve(image, path)? {
+        Resolved::BucketTypes(types) => {
+            for ty in types {
+                res.push(ArchiveEntry::new(&child_path(ty.as_bytes()), None));
+            }
+        }
+        Resolved::Partitions(partitions) => {
+            for part in partitions {
+                res.push(ArchiveEntry::new_with_size(
+                    &child_path(part.number.to_string().as_bytes()),
+                    // this marks the beginning of a filesystem, i.e. '/', so this is a Directory
+                    Some(&DirEntryAttribute::Directory { start: 0 }),
+                    Some(part.size),
+                ));
+            }
+        }
+        Resolved::Entry(fs, inode) if inode.kind == FileKind::Directory => {
+            for entry in fs.read_dir(&inode)? {
+                if let Ok(child) = fs.inode(entry.ino) {
+                    res.push(ArchiveEntry::new(
+                        &child_path(&entry.name),
+                        Some(&entry_attribute(&child)),
+                    ));
+                }
+            }
+        }
+        Resolved::Entry(_, inode) => {
+            res.push(ArchiveEntry::new(&base, Some(&entry_attribute(&inode))));
+        }
+    }
+
+    Ok(res)
+}
+
+fn pxar_metadata(inode: &Inode) -> pxar::Metadata {
+    pxar::Metadata {
+        stat: pxar::Stat {
+            mode: inode.mode as u64,
+            flags: 0,
+            uid: inode.uid,
+            gid: inode.gid,
+            mtime: pxar::format::StatxTimestamp::new(inode.mtime, inode.mtime_nsecs),
+        },
+        ..Default::default()
+    }
+}
+
+fn encode_entry<T: SeqWrite>(
+    encoder: &mut Encoder<'_, T>,
+    fs: &dyn FileSystem,
+    inode: &Inode,
+    name: &OsStr,
+    dir_stack: &mut Vec<u64>,
+) -> Result<(), Error> {
+    let metadata = pxar_metadata(inode);
+    match inode.kind {
+        FileKind::Directory => {
+            if dir_stack.contains(&inode.ino) {
+                bail!("directory loop detected at inode {}", inode.ino);
+            }
+            dir_stack.push(inode.ino);
+            let mut dir = encoder.create_directory(name, &metadata)?;
+            for entry in fs.read_dir(inode)? {
+                let child = fs.inode(entry.ino)?;
+                encode_entry(
+                    &mut dir,
+                    fs,
+                    &child,
+                    OsStr::from_bytes(&entry.name),
+                    dir_stack,
+                )?;
+            }
+            dir.finish()?;
+            dir_stack.pop();
+        }
+        FileKind::File => {
+            let mut contents = encoder.create_file(&metadata, name, inode.size)?;
+            std::io::copy(&mut FileReader::new(fs, inode)?, &mut contents)?;
+        }
+        FileKind::Symlink => {
+            let target = fs.read_link(inode)?;
+            encoder.add_symlink(&metadata, name, OsStr::from_bytes(&target))?;
+        }
+        FileKind::BlockDevice | FileKind::CharDevice => {
+            let device = pxar::format::Device {
+                major: inode.device.0,
+                minor: inode.device.1,
+            };
+            encoder.add_device(&metadata, name, device)?;
+        }
+        FileKind::Fifo => encoder.add_fifo(&metadata, name)?,
+        FileKind::Socket => encoder.add_socket(&metadata, name)?,
+    }
+    Ok(())
+}
+
+/// Encode a file or directory as pxar archive. pxar always expects a directory as its root, so
+/// the entry is added to an otherwise empty root directory, like the restore VM does.
+fn encode_pxar<W: std::io::Write>(
+    writer: W,
+    fs: &dyn FileSystem,
+    inode: &Inode,
+    name: &[u8],
+) -> Result<(), Error> {
+    let root_metadata = pxar::Metadata::dir_builder(0o0755).build();
+    let mut encoder = Encoder::new(StandardWriter::new(writer), &root_metadata)?;
+    encode_entry(
+        &mut encoder,
+        fs,
+        inode,
+        OsStr::from_bytes(name),
+        &mut Vec::new(),
+    )?;
+    encoder.finish()?;
+    Ok(())
+}
+
+type StreamSender = tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>;
+
+/// Passes the data written by the (blocking) encoder on to the async reading side
+struct ChannelWriter(StreamSender);
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::other("reader closed the stream"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stream a plain file or a pxar archive of `inode` into `sender`. Errors are logged and passed
+/// on, so that the reader sees a failed stream instead of a truncated one.
+fn stream_entry(sender: StreamSender, fs: &dyn FileSystem, inode: &Inode, name: &[u8], pxar: bool) {
+    let mut writer =
+        std::io::BufWriter::with_capacity(STREAM_BLOCK_SIZE, ChannelWriter(sender.clone()));
+    let result = if pxar {
+        encode_pxar(&mut writer, fs, inode, name)
+    } else {
+        FileReader::new(fs, inode).and_then(|mut file| {
+            std::io::copy(&mut file, &mut writer)?;
+            Ok(())
+        })
+    };
+
+    let result = result.and_then(|()| writer.flush().map_err(Error::from));
+
+    if let Err(err) = result {
+        // drop buffered data without flushing, the error has to be the last item
+        let _ = writer.into_parts();
+        log::error!("file or dir streaming task failed - {}", err);
+        let _ = sender.blocking_send(Err(std::io::Error::other(err.to_string())));
+    }
+}
+
+impl BlockRestoreDriver for UserspaceBlockDriver {
+    fn data_list(
+        &self,
+        details: SnapRestoreDetails,
+        img_file: String,
+        mut path: Vec<u8>,
+    ) -> Async<Result<Vec<ArchiveEntry>, Error>> {
+        async move {
+            if !path.is_empty() && path[0] != b'/' {
+                path.insert(0, b'/');
+            }
+            if let Some(b'/') = path.last() {
+                path.pop();
+            }
+            let image = open_image(&details, &img_file).await?;
+            tokio::task::spawn_blocking(move || list_entries(image, &img_file, &path)).await?
+        }
+        .boxed()
+    }
+
+    fn data_extract(
+        &self,
+        details: SnapRestoreDetails,
+        img_file: String,
+        path: Vec<u8>,
+        format: Option<FileRestoreFormat>,
+        zstd: bool,
+    ) -> Async<Result<Box<dyn tokio::io::AsyncRead + Unpin + Send>, Error>> {
+        async move {
+            let image = open_image(&details, &img_file).await?;
+            let (fs, inode, name) = tokio::task::spawn_blocking(move || {
+                let name = path
+                    .split(|b| *b == b'/')
+                    .filter(|c| !c.is_empty())
+                    .last()
+                    .unwrap_or(b"/")
+                    .to_vec();
+                match resolve(image, &path)? {
+                    Resolved::Entry(fs, inode) => Ok((fs, inode, name)),
+                    _ => bail!(
+                        "invalid path, cannot restore meta-directory: {:?}",
+                        String::from_utf8_lossy(&path)
+                    ),
+                }
+            })
+            .await??;
+
+            let pxar = match format {
+                Some(FileRestoreFormat::Pxar) => true,
+                Some(FileRestoreFormat::Plain) | None if inode.kind == FileKind::File => false,
+                Some(FileRestoreFormat::Plain) => {
+                    bail!("plain file not supported for non-regular files")
+                }
+                _ => bail!("the userspace driver only supports 'pxar' archives for directories"),
+            };
+
+            let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+            tokio::task::spawn_blocking(move || stream_entry(sender, &*fs, &inode, &name, pxar));
+            let stream = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx));
+
+            let reader: Box<dyn tokio::io::AsyncRead + Unpin + Send> = if zstd {
+                let stream = ZstdEncoder::new(stream)?.map_err(std::io::Error::other);
+                Box::new(tokio_util::io::StreamReader::new(Box::pin(stream)))
+            } else {
+                Box::new(tokio_util::io::StreamReader::new(stream))
+            };
+            Ok(reader)
+        }
+        .boxed()
+    }
+
+    fn status(&self) -> Async<Result<Vec<DriverStatus>, Error>> {
+        // nothing is kept running between invocations
+        async move { Ok(Vec::new()) }.boxed()
+    }
+
+    fn stop(&self, id: String) -> Async<Result<(), Error>> {
+        async move { bail!("no mapping with name '{id}' found") }.boxed()
+    }
+
+    fn list(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use pxar::EntryKind;
+
+    use super::*;
+    use crate::image_fs::test::{make_ext4_image, write_mbr_entry, ChunkedImage};
+    use crate::image_fs::{DirEntry, Extent};
+
+    const IMG_FILE: &str = "drive-scsi0.img.fidx";
+
+    /// Path, type, leaf and size of a listed entry
+    type Listed = (String, String, bool, Option<u64>);
+
+    fn list(image: &Arc<dyn ImageRead>, path: &[u8]) -> Result<Vec<Listed>, Error> {
+        let mut entries: Vec<_> = list_entries(image.clone(), IMG_FILE, path)?
+            .into_iter()
+            .map(|entry| {
+                let path = String::from_utf8(base64::decode(entry.filepath).unwrap()).unwrap();
+                (path, entry.entry_type, entry.leaf, entry.size)
+            })
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn entry(path: &str, ty: &str, leaf: bool, size: Option<u64>) -> Listed {
+        (format!("{IMG_FILE}{path}"), ty.to_string(), leaf, size)
+    }
+
+    #[test]
+    fn test_list_entries_raw() {
+        let data = make_ext4_image(&["-q", "-F"], 16 * 1024 * 1024, "list-raw");
+        let image: Arc<dyn ImageRead> = Arc::new(ChunkedImage::new(&data, 4096));
+
+        assert_eq!(
+            list(&image, b"").unwrap(),
+            vec![entry("/raw", "v", false, None)]
+        );
+
+        let root = list(&image, b"/raw").unwrap();
+        for expected in [
+            entry("/raw/large.bin", "f", true, Some(300 * 1024 + 17)),
+            entry("/raw/many", "d", false, None),
+            entry("/raw/short-link", "l", true, None),
+            entry("/raw/small.txt", "f", true, Some(12)),
+        ] {
+            assert!(root.contains(&expected), "{expected:?} not in {root:?}");
+        }
+
+        assert_eq!(list(&image, b"/raw/many").unwrap().len(), 300);
+        assert_eq!(
+            list(&image, b"/raw/small.txt").unwrap(),
+            vec![entry("/raw/small.txt", "f", true, Some(12))]
+        );
+
+        assert!(list(&image, b"/raw/missing").is_err());
+        let err = list(&image, b"/part").unwrap_err();
+        assert_eq!(err.to_string(), "bucket 'part' not found");
+    }
+
+    #[test]
+    fn test_list_entries_partitions() {
+        let fs = make_ext4_image(&["-q", "-F"], 16 * 1024 * 1024, "list-part");
+        let mut data = vec![0u8; 2048 * 512 + fs.len()];
+        write_mbr_entry(&mut data, 0, 0, 0x83, 2048, (fs.len() / 512) as u32);
+        data[2048 * 512..].copy_from_slice(&fs);
+        let image: Arc<dyn ImageRead> = Arc::new(ChunkedImage::new(&data, 4096));
+
+        assert_eq!(
+            list(&image, b"").unwrap(),
+            vec![entry("/part", "v", false, None)]
+        );
+        assert_eq!(
+            list(&image, b"/part").unwrap(),
+            vec![entry("/part/1", "d", false, Some(fs.len() as u64))]
+        );
+        let root = list(&image, b"/part/1").unwrap();
+        assert!(root.contains(&entry("/part/1/small.txt", "f", true, Some(12))));
+        assert_eq!(list(&image, b"/part/1/many").unwrap().len(), 300);
+
+        let err = list(&image, b"/part/2").unwrap_err();
+        assert_eq!(err.to_string(), "partition '2' not found");
+        assert!(list(&image, b"/raw").is_err());
+    }
+
+    /// Run `stream_entry` and collect the data passed to the reader
+    fn extract(fs: &dyn FileSystem, path: &[u8], pxar: bool) -> Result<Vec<u8>, std::io::Error> {
+        let inode = image_fs::lookup(fs, path).unwrap();
+        let name = path.rsplit(|b| *b == b'/').next().unwrap().to_vec();
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| stream_entry(sender, fs, &inode, &name, pxar));
+            let mut data = Vec::new();
+            while let Some(block) = receiver.blocking_recv() {
+                data.extend_from_slice(&block?);
+            }
+            Ok(data)
+        })
+    }
+
+    #[test]
+    fn test_extract() -> Result<(), Error> {
+        let data = make_ext4_image(&["-q", "-F"], 16 * 1024 * 1024, "extract");
+        let fs = image_fs::open_filesystem(Arc::new(ChunkedImage::new(&data, 4096)))?;
+
+        assert_eq!(extract(&*fs, b"/small.txt", false)?, b"hello world\n");
+        let large = extract(&*fs, b"/large.bin", false)?;
+        assert_eq!(large.len(), 300 * 1024 + 17);
+
+        let archive = extract(&*fs, b"/many", true)?;
+        let mut decoder = pxar::decoder::sync::Decoder::from_std(&archive[..])?;
+        let mut files = 0;
+        while let Some(entry) = decoder.next() {
+            let entry = entry?;
+            let path = entry.path().to_string_lossy().into_owned();
+            match entry.kind() {
+                EntryKind::Directory => assert!(path == "/" || path == "/many", "{path}"),
+                EntryKind::File { .. } => {
+                    let number: usize = path.strip_prefix("/many/file-").unwrap().parse()?;
+                    let mut content = String::new();
+                    decoder.contents().unwrap().read_to_string(&mut content)?;
+                    assert_eq!(content, number.to_string());
+                    files += 1;
+                }
+                _ => panic!("unexpected entry {path}"),
+            }
+        }
+        assert_eq!(files, 300);
+
+        let archive = extract(&*fs, b"/short-link", true)?;
+        let mut decoder = pxar::decoder::sync::Decoder::from_std(&archive[..])?;
+        decoder.next().unwrap()?; // root directory
+        let entry = decoder.next().unwrap()?;
+        match entry.kind() {
+            EntryKind::Symlink(link) => assert_eq!(link.as_os_str(), "small.txt"),
+            _ => panic!("expected a symlink"),
+        }
+
+        Ok(())
+    }
+
+    /// A single file whose data runs past the end of the image
+    struct TruncatedFs(ChunkedImage);
+
+    impl FileSystem for TruncatedFs {
+        fn image(&self) -> &dyn ImageRead {
+            &self.0
+        }
+
+        fn root(&self) -> Result<Inode, Error> {
+            self.inode(1)
+        }
+
+        fn inode(&self, ino: u64) -> Result<Inode, Error> {
+            let mode = if ino == 1 { 0o040755 } else { 0o100644 };
+            Ok(Inode {
+                ino,
+                kind: FileKind::from_mode(mode)?,
+                mode,
+                uid: 0,
+                gid: 0,
+                size: 2 * self.0.size(),
+                mtime: 0,
+                mtime_nsecs: 0,
+                device: (0, 0),
+            })
+        }
+
+        fn read_dir(&self, _dir: &Inode) -> Result<Vec<DirEntry>, Error> {
+            Ok(vec![DirEntry {
+                name: b"file".to_vec(),
+                ino: 2,
+            }])
+        }
+
+        fn extents(&self, inode: &Inode) -> Result<Vec<Extent>, Error> {
+            Ok(vec![Extent {
+                logical: 0,
+                physical: 0,
+                length: inode.size,
+            }])
+        }
+
+        fn read_link(&self, inode: &Inode) -> Result<Vec<u8>, Error> {
+            bail!("inode {} is not a symlink", inode.ino);
+        }
+    }
+
+    #[test]
+    fn test_extract_error() {
+        // larger than a stream block, so that data is passed on before the error
+        let data = vec![0x55u8; STREAM_BLOCK_SIZE + 4096];
+        let fs = TruncatedFs(ChunkedImage::new(&data, 4096));
+
+        for pxar in [false, true] {
+            let err = extract(&fs, b"/file", pxar).unwrap_err();
+            assert!(err.to_string().contains("beyond end of image"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! Read-only ext2/3/4 support
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use super::{
+    le16, le32, read_all, read_vec, DirEntry, Extent, FileKind, FileSystem, ImageRead, Inode,
+};
+
+pub(crate) const EXT4_MAGIC: u16 = 0xef53;
+
+const ROOT_INO: u64 = 2;
+
+const INCOMPAT_COMPRESSION: u32 = 0x1;
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_JOURNAL_DEV: u32 = 0x8;
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+const INCOMPAT_DIRDATA: u32 = 0x1000;
+const INCOMPAT_INLINE_DATA: u32 = 0x8000;
+const INCOMPAT_ENCRYPT: u32 = 0x10000;
+
+const UNSUPPORTED_INCOMPAT: &[(u32, &str)] = &[
+    (INCOMPAT_COMPRESSION, "compression"),
+    (INCOMPAT_JOURNAL_DEV, "external journal device"),
+    (INCOMPAT_META_BG, "meta_bg"),
+    (INCOMPAT_DIRDATA, "dirdata"),
+    (INCOMPAT_INLINE_DATA, "inline_data"),
+    (INCOMPAT_ENCRYPT, "encryption"),
+];
+
+const INODE_FLAG_ENCRYPT: u32 = 0x800;
+const INODE_FLAG_EXTENTS: u32 = 0x80000;
+const INODE_FLAG_INLINE_DATA: u32 = 0x10000000;
+
+const EXTENT_MAGIC: u16 = 0xf30a;
+const EXTENT_MAX_DEPTH: u16 = 5;
+const EXTENT_INIT_MAX_LEN: u16 = 32768;
+
+pub struct Ext4 {
+    image: Arc<dyn ImageRead>,
+    block_size: u64,
+    inodes_count: u64,
+    inodes_per_group: u64,
+    inode_size: u64,
+    desc_size: u64,
+    gdt_offset: u64,
+    incompat: u32,
+}
+
+impl Ext4 {
+    pub fn open(image: Arc<dyn ImageRead>) -> Result<Self, Error> {
+        let sb = read_vec(&*image, 1024, 1024)?;
+        if le16(&sb, 0x38) != EXT4_MAGIC {
+            bail!("no ext2/3/4 superblock found");
+        }
+
+        let incompat = le32(&sb, 0x60);
+        for (flag, name) in UNSUPPORTED_INCOMPAT {
+            if incompat & flag != 0 {
+                bail!("unsupported layout - ext4 feature '{name}' is not supported");
+            }
+        }
+
+        let log_block_size = le32(&sb, 0x18);
+        if log_block_size > 6 {
+            bail!("invalid ext4 block size");
+        }
+        let block_size = 1024u64 << log_block_size;
+
+        let inode_size = match le32(&sb, 0x4c) {
+            0 => 128,
+            _ => le16(&sb, 0x58) as u64,
+        };
+        if inode_size < 128 || inode_size > block_size {
+            bail!("invalid ext4 inode size {inode_size}");
+        }
+
+        let desc_size = if incompat & INCOMPAT_64BIT != 0 {
+            (le16(&sb, 0xfe) as u64).max(32)
+        } else {
+            32
+        };
+
+        let inodes_per_group = le32(&sb, 0x28) as u64;
+        if inodes_per_group == 0 {
+            bail!("invalid ext4 inodes per group");
+        }
+
+        let first_data_block = le32(&sb, 0x14) as u64;
+
+        Ok(Self {
+            image,
+            block_size,
+            inodes_count: le32(&sb, 0x0) as u64,
+            inodes_per_group,
+            inode_size,
+            desc_size,
+            gdt_offset: (first_data_block + 1) * block_size,
+            incompat,
+        })
+    }
+
+    fn raw_inode(&self, ino: u64) -> Result<Vec<u8>, Error> {
+        if ino == 0 || ino > self.inodes_count {
+            bail!("invalid inode number {ino}");
+        }
+        let group = (ino - 1) / self.inodes_per_group;
+        let index = (ino - 1) % self.inodes_per_group;
+
+        let desc = read_vec(
+            &*self.image,
+            self.gdt_offset + group * self.desc_size,
+            self.desc_size as usize,
+        )?;
+        let mut table = le32(&desc, 0x8) as u64;
+        if self.desc_size >= 64 {
+            table |= (le32(&desc, 0x28) as u64) << 32;
+        }
+
+        read_vec(
+            &*self.image,
+            table * self.block_size + index * self.inode_size,
+            self.inode_size as usize,
+        )
+    }
+
+    fn read_block(&self, block: u64) -> Result<Vec<u8>, Error> {
+        read_vec(
+            &*self.image,
+            block * self.block_size,
+            self.block_size as usize,
+        )
+    }
+
+    fn walk_extent_node(
+        &self,
+        node: &[u8],
+        level: u16,
+        out: &mut Vec<Extent>,
+    ) -> Result<(), Error> {
+        if le16(node, 0) != EXTENT_MAGIC {
+            bail!("invalid extent header");
+        }
+        let entries = le16(node, 2) as usize;
+        let depth = le16(node, 6);
+        if depth > EXTENT_MAX_DEPTH || level > EXTENT_MAX_DEPTH || 12 + entries * 12 > node.len() {
+            bail!("corrupt extent tree");
+        }
+
+        for entry in node[12..12 + entries * 12].chunks_exact(12) {
+            if depth == 0 {
+                let len = le16(entry, 4);
+                if len > EXTENT_INIT_MAX_LEN {
+                    // unwritten extent, reads as zeroes
+                    continue;
+                }
+                let start = (le16(entry, 6) as u64) << 32 | le32(entry, 8) as u64;
+                out.push(Extent {
+                    logical: le32(entry, 0) as u64 * self.block_size,
+                    physical: start * self.block_size,
+                    length: len as u64 * self.block_size,
+                });
+            } else {
+                let leaf = (le16(entry, 8) as u64) << 32 | le32(entry, 4) as u64;
+                let child = self.read_block(leaf)?;
+                self.walk_extent_node(&child, level + 1, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Map legacy direct/indirect block pointers, `level` is the indirection level of `ptrs`
+    fn map_blocks(
+        &self,
+        ptrs: &[u32],
+        level: u32,
+        logical: &mut u64,
+        end: u64,
+        out: &mut Vec<Extent>,
+    ) -> Result<(), Error> {
+        let span = (self.block_size / 4).pow(level);
+        for ptr in ptrs {
+            if *logical >= end {
+                break;
+            }
+            if *ptr == 0 {
+                *logical += span;
+                continue;
+            }
+            if level == 0 {
+                let logical_bytes = *logical * self.block_size;
+                let physical = *ptr as u64 * self.block_size;
+                match out.last_mut() {
+                    Some(last)
+                        if last.logical + last.length == logical_bytes
+                            && last.physical + last.length == physical =>
+                    {
+                        last.length += self.block_size;
+                    }
+                    _ => out.push(Extent {
+                        logical: logical_bytes,
+                        physical,
+                        length: self.block_size,
+                    }),
+                }
+                *logical += 1;
+            } else {
+                let block = self.read_block(*ptr as u64)?;
+                let child: Vec<u32> = block.chunks_exact(4).map(|b| le32(b, 0)).collect();
+                self.map_blocks(&child, level - 1, logical, end, out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for Ext4 {
+    fn image(&self) -> &dyn ImageRead {
+        &*self.image
+    }
+
+    fn root(&self) -> Result<Inode, Error> {
+        self.inode(ROOT_INO)
+    }
+
+    fn inode(&self, ino: u64) -> Result<Inode, Error> {
+        let raw = self.raw_inode(ino)?;
+
+        let mode = le16(&raw, 0x0) as u32;
+        let kind = FileKind::from_mode(mode)?;
+
+        let mut mtime = le32(&raw, 0x10) as i32 as i64;
+        let mut mtime_nsecs = 0;
+        if raw.len() > 128 && le16(&raw, 0x80) >= 12 {
+            let extra = le32(&raw, 0x88);
+            mtime += ((extra & 3) as i64) << 32;
+            mtime_nsecs = extra >> 2;
+        }
+
+        let device = match kind {
+            FileKind::BlockDevice | FileKind::CharDevice => {
+                let old = le32(&raw, 0x28) as u64;
+                let new = le32(&raw, 0x2c) as u64;
+                if old != 0 {
+                    ((old >> 8) & 0xff, old & 0xff)
+                } else {
+                    ((new & 0xfff00) >> 8, (new & 0xff) | ((new >> 12) & 0xfff00))
+                }
+            }
+            _ => (0, 0),
+        };
+
+        Ok(Inode {
+            ino,
+            kind,
+            mode,
+            uid: le16(&raw, 0x2) as u32 | (le16(&raw, 0x78) as u32) << 16,
+            gid: le16(&raw, 0x18) as u32 | (le16(&raw, 0x7a) as u32) << 16,
+            size: le32(&raw, 0x4) as u64 | (le32(&raw, 0x6c) as u64) << 32,
+            mtime,
+            mtime_nsecs,
+            device,
+        })
+    }
+
+    fn read_dir(&self, dir: &Inode) -> Result<Vec<DirEntry>, Error> {
+        if dir.kind != FileKind::Directory {
+            bail!("inode {} is not a directory", dir.ino);
+        }
+
+        let data = read_all(self, dir)?;
+        let block_size = self.block_size as usize;
+        let mut entries = Vec::new();
+
+        // hashed directories are also valid linear directories, index blocks look like empty
+        // entries spanning the whole block
+        for block in data.chunks(block_size) {
+            let mut pos = 0;
+            while pos + 8 <= block.len() {
+                let ino = le32(block, pos) as u64;
+                let rec_len = match le16(block, pos + 4) as usize {
+                    0 | 65535 => block.len() - pos,
+                    len => len,
+                };
+                let name_len = if self.incompat & INCOMPAT_FILETYPE != 0 {
+                    block[pos + 6] as usize
+                } else {
+                    le16(block, pos + 6) as usize
+                };
+                if rec_len < 8 || pos + rec_len > block.len() || 8 + name_len > rec_len {
+                    bail!("corrupt directory entry in inode {}", dir.ino);
+                }
+
+                let name = &block[pos + 8..pos + 8 + name_len];
+                if ino != 0 && !name.is_empty() && name != b"." && name != b".." {
+                    entries.push(DirEntry {
+                        name: name.to_vec(),
+                        ino,
+                    });
+                }
+                pos += rec_len;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn extents(&self, inode: &Inode) -> Result<Vec<Extent>, Error> {
+        let raw = self.raw_inode(inode.ino)?;
+        let flags = le32(&raw, 0x20);
+        if flags & INODE_FLAG_ENCRYPT != 0 {
+            bail!("unsupported layout - inode {} is encrypted", inode.ino);
+        }
+        if flags & INODE_FLAG_INLINE_DATA != 0 {
+            bail!("unsupported layout - inode {} uses inline data", inode.ino);
+        }
+
+        let i_block = &raw[0x28..0x28 + 60];
+        let mut extents = Vec::new();
+        if flags & INODE_FLAG_EXTENTS != 0 {
+            self.walk_extent_node(i_block, 0, &mut extents)?;
+        } else {
+            let ptrs: Vec<u32> = i_block.chunks_exact(4).map(|b| le32(b, 0)).collect();
+            let end = inode.size.div_ceil(self.block_size);
+            let mut logical = 0;
+            self.map_blocks(&ptrs[0..12], 0, &mut logical, end, &mut extents)?;
+            for (level, ptr) in ptrs[12..15].iter().enumerate() {
+                self.map_blocks(&[*ptr], level as u32 + 1, &mut logical, end, &mut extents)?;
+            }
+        }
+        extents.sort_by_key(|extent| extent.logical);
+
+        Ok(extents)
+    }
+
+    fn read_link(&self, inode: &Inode) -> Result<Vec<u8>, Error> {
+        if inode.kind != FileKind::Symlink {
+            bail!("inode {} is not a symlink", inode.ino);
+        }
+        let raw = self.raw_inode(inode.ino)?;
+        let flags = le32(&raw, 0x20);
+        if flags & (INODE_FLAG_EXTENTS | INODE_FLAG_INLINE_DATA) == 0 && inode.size < 60 {
+            // fast symlink, the target is stored in place of the block pointers
+            return Ok(raw[0x28..0x28 + inode.size as usize].to_vec());
+        }
+        read_all(self, inode)
+    }
+}
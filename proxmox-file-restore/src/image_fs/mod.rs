@@ -0,0 +1,339 @@
+//! Read-only access to filesystems contained in raw disk images
+//!
+//! This is used by the userspace block driver to list and extract files without starting a
+//! restore VM. Only a limited set of on-disk formats is understood: MBR and GPT partition tables,
+//! ext2/3/4 and XFS. Everything else (LVM, LUKS, ZFS, ...) is reported as unsupported layout.
+use std::io::Read;
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+mod ext4;
+mod partition;
+mod xfs;
+
+pub use partition::{read_partitions, Partition};
+
+/// Random access to the contents of a disk image
+pub trait ImageRead: Send + Sync {
+    /// Fill `buf` with data starting at `offset`, short reads are an error
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error>;
+
+    /// Total size of the image in bytes
+    fn size(&self) -> u64;
+}
+
+impl ImageRead for std::fs::File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+            .map_err(|err| format_err!("reading {} bytes at {offset} failed - {err}", buf.len()))
+    }
+
+    fn size(&self) -> u64 {
+        self.metadata().map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+/// A window into another image, e.g. a single partition
+pub struct ImageSlice {
+    image: Arc<dyn ImageRead>,
+    start: u64,
+    size: u64,
+}
+
+impl ImageSlice {
+    pub fn new(image: Arc<dyn ImageRead>, start: u64, size: u64) -> Result<Self, Error> {
+        match start.checked_add(size) {
+            Some(end) if end <= image.size() => Ok(Self { image, start, size }),
+            _ => bail!("region {start}+{size} exceeds image size {}", image.size()),
+        }
+    }
+}
+
+impl ImageRead for ImageSlice {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        match offset.checked_add(buf.len() as u64) {
+            Some(end) if end <= self.size => self.image.read_exact_at(buf, self.start + offset),
+            _ => bail!(
+                "read of {} bytes at {offset} beyond end of region",
+                buf.len()
+            ),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+pub(crate) fn read_vec(image: &dyn ImageRead, offset: u64, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0u8; len];
+    image.read_exact_at(&mut buf, offset)?;
+    Ok(buf)
+}
+
+pub(crate) fn le16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn le32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn le64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+pub(crate) fn be16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub(crate) fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn be64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// What was found at the start of an image or partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    PartitionTable,
+    Ext4,
+    Xfs,
+    Lvm,
+    Luks,
+    Unknown,
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Layout::PartitionTable => "partition table",
+            Layout::Ext4 => "ext2/3/4 filesystem",
+            Layout::Xfs => "XFS filesystem",
+            Layout::Lvm => "LVM physical volume",
+            Layout::Luks => "LUKS encrypted volume",
+            Layout::Unknown => "unknown content",
+        })
+    }
+}
+
+/// Detect the content of an image by looking at well known magic values
+pub fn detect_layout(image: &dyn ImageRead) -> Result<Layout, Error> {
+    let len = image.size().min(4096) as usize;
+    let buf = read_vec(image, 0, len)?;
+
+    if buf.starts_with(b"LUKS\xba\xbe") {
+        return Ok(Layout::Luks);
+    }
+    if buf.starts_with(b"XFSB") {
+        return Ok(Layout::Xfs);
+    }
+    if len >= 1082 && le16(&buf, 1080) == ext4::EXT4_MAGIC {
+        return Ok(Layout::Ext4);
+    }
+    // the LVM label can be in any of the first four sectors
+    for sector in 0..4 {
+        let offset = sector * 512;
+        if len >= offset + 8 && &buf[offset..offset + 8] == b"LABELONE" {
+            return Ok(Layout::Lvm);
+        }
+    }
+    if len >= 512 && buf[510..512] == [0x55, 0xaa] {
+        return Ok(Layout::PartitionTable);
+    }
+
+    Ok(Layout::Unknown)
+}
+
+/// Open the filesystem contained directly in `image`
+pub fn open_filesystem(image: Arc<dyn ImageRead>) -> Result<Box<dyn FileSystem>, Error> {
+    match detect_layout(&*image)? {
+        Layout::Ext4 => Ok(Box::new(ext4::Ext4::open(image)?)),
+        Layout::Xfs => Ok(Box::new(xfs::Xfs::open(image)?)),
+        other => bail!("unsupported layout - {other}"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Directory,
+    File,
+    Symlink,
+    BlockDevice,
+    CharDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileKind {
+    pub(crate) fn from_mode(mode: u32) -> Result<Self, Error> {
+        Ok(match mode & 0o170000 {
+            0o040000 => FileKind::Directory,
+            0o100000 => FileKind::File,
+            0o120000 => FileKind::Symlink,
+            0o060000 => FileKind::BlockDevice,
+            0o020000 => FileKind::CharDevice,
+            0o010000 => FileKind::Fifo,
+            0o140000 => FileKind::Socket,
+            _ => bail!("invalid file mode {mode:o}"),
+        })
+    }
+}
+
+/// File attributes, independent of the underlying filesystem
+#[derive(Debug, Clone)]
+pub struct Inode {
+    pub ino: u64,
+    pub kind: FileKind,
+    /// Full mode including the file type bits
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsecs: u32,
+    /// Major and minor number for device nodes
+    pub device: (u64, u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: Vec<u8>,
+    pub ino: u64,
+}
+
+/// Maps a range of file data to image bytes, all values are in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub logical: u64,
+    pub physical: u64,
+    pub length: u64,
+}
+
+/// Minimal read-only filesystem interface
+pub trait FileSystem: Send + Sync {
+    /// The image (or partition) the filesystem lives on
+    fn image(&self) -> &dyn ImageRead;
+
+    fn root(&self) -> Result<Inode, Error>;
+
+    fn inode(&self, ino: u64) -> Result<Inode, Error>;
+
+    /// Return the entries of a directory, without '.' and '..'
+    fn read_dir(&self, dir: &Inode) -> Result<Vec<DirEntry>, Error>;
+
+    /// Return the allocated, initialized data extents of a file sorted by logical offset
+    fn extents(&self, inode: &Inode) -> Result<Vec<Extent>, Error>;
+
+    fn read_link(&self, inode: &Inode) -> Result<Vec<u8>, Error>;
+}
+
+/// Resolve `path` relative to the filesystem root, symlinks are not followed
+pub fn lookup(fs: &dyn FileSystem, path: &[u8]) -> Result<Inode, Error> {
+    let mut stack = vec![fs.root()?];
+    for component in path.split(|b| *b == b'/') {
+        match component {
+            b"" | b"." => continue,
+            b".." => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let dir = stack.last().unwrap();
+        if dir.kind != FileKind::Directory {
+            bail!("not a directory: '{}'", String::from_utf8_lossy(path));
+        }
+        let entry = fs
+            .read_dir(dir)?
+            .into_iter()
+            .find(|entry| entry.name == component)
+            .ok_or_else(|| {
+                format_err!(
+                    "no such file or directory: '{}'",
+                    String::from_utf8_lossy(path)
+                )
+            })?;
+        stack.push(fs.inode(entry.ino)?);
+    }
+
+    Ok(stack.pop().unwrap())
+}
+
+/// Sequential reader for file contents, holes and unwritten extents read as zeroes
+pub struct FileReader<'a> {
+    fs: &'a dyn FileSystem,
+    extents: Vec<Extent>,
+    next: usize,
+    size: u64,
+    pos: u64,
+}
+
+impl<'a> FileReader<'a> {
+    pub fn new(fs: &'a dyn FileSystem, inode: &Inode) -> Result<Self, Error> {
+        Ok(Self {
+            fs,
+            extents: fs.extents(inode)?,
+            next: 0,
+            size: inode.size,
+            pos: 0,
+        })
+    }
+}
+
+impl<'a> Read for FileReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = (buf.len() as u64).min(self.size - self.pos);
+
+        while let Some(extent) = self.extents.get(self.next) {
+            if extent.logical + extent.length > self.pos {
+                break;
+            }
+            self.next += 1;
+        }
+
+        let len = match self.extents.get(self.next) {
+            Some(extent) if extent.logical <= self.pos => {
+                let offset = self.pos - extent.logical;
+                let len = max.min(extent.length - offset) as usize;
+                self.fs
+                    .image()
+                    .read_exact_at(&mut buf[..len], extent.physical + offset)
+                    .map_err(std::io::Error::other)?;
+                len
+            }
+            Some(extent) => {
+                let len = max.min(extent.logical - self.pos) as usize;
+                buf[..len].fill(0);
+                len
+            }
+            None => {
+                let len = max as usize;
+                buf[..len].fill(0);
+                len
+            }
+        };
+
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+/// Read the complete contents of a (small) file, e.g. a directory
+pub(crate) fn read_all(fs: &dyn FileSystem, inode: &Inode) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(inode.size.min(16 * 1024 * 1024) as usize);
+    FileReader::new(fs, inode)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+pub(crate) mod test;
@@ -0,0 +1,160 @@
+//! MBR and GPT partition table parsing
+use anyhow::{bail, format_err, Error};
+
+use super::{le32, le64, read_vec, ImageRead};
+
+const MBR_PROTECTIVE_GPT: u8 = 0xee;
+const MBR_EXTENDED_TYPES: &[u8] = &[0x05, 0x0f, 0x85];
+const MAX_LOGICAL_PARTITIONS: u32 = 128;
+const MAX_GPT_ENTRIES: u64 = 1024;
+const MAX_GPT_ENTRY_SIZE: u64 = 4096;
+
+/// A partition of a disk image, offsets in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Partition number as the Linux kernel would assign it
+    pub number: u32,
+    pub start: u64,
+    pub size: u64,
+}
+
+/// Read the partition table of an image, returns all partitions sorted by number
+pub fn read_partitions(image: &dyn ImageRead) -> Result<Vec<Partition>, Error> {
+    let mbr = read_vec(image, 0, 512)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        bail!("no partition table found");
+    }
+
+    let mut partitions = if mbr_entries(&mbr).any(|(ty, _, _)| ty == MBR_PROTECTIVE_GPT) {
+        read_gpt(image)?
+    } else {
+        read_mbr(image, &mbr)?
+    };
+
+    for part in partitions.iter() {
+        if part.size == 0 || part.start.saturating_add(part.size) > image.size() {
+            bail!("partition {} exceeds the size of the image", part.number);
+        }
+    }
+    partitions.sort_by_key(|part| part.number);
+
+    Ok(partitions)
+}
+
+/// Iterate over the (type, start lba, sector count) of the four MBR/EBR entries
+fn mbr_entries(sector: &[u8]) -> impl Iterator<Item = (u8, u64, u64)> + '_ {
+    (0..4).map(move |i| {
+        let entry = &sector[446 + i * 16..446 + (i + 1) * 16];
+        (entry[4], le32(entry, 8) as u64, le32(entry, 12) as u64)
+    })
+}
+
+fn read_mbr(image: &dyn ImageRead, mbr: &[u8]) -> Result<Vec<Partition>, Error> {
+    let mut partitions = Vec::new();
+    let mut extended = None;
+
+    for (i, (ty, start, count)) in mbr_entries(mbr).enumerate() {
+        if ty == 0 || count == 0 {
+            continue;
+        }
+        if MBR_EXTENDED_TYPES.contains(&ty) {
+            extended = Some(start);
+            continue;
+        }
+        partitions.push(Partition {
+            number: i as u32 + 1,
+            start: start * 512,
+            size: count * 512,
+        });
+    }
+
+    // logical partitions are a linked list of EBRs, each relative to the extended partition
+    if let Some(ext_start) = extended {
+        let mut ebr_lba = ext_start;
+        let mut number = 5;
+        loop {
+            if number >= 5 + MAX_LOGICAL_PARTITIONS {
+                bail!("too many logical partitions");
+            }
+            let ebr = read_vec(image, ebr_lba * 512, 512)?;
+            if ebr[510..512] != [0x55, 0xaa] {
+                bail!("invalid extended boot record at sector {ebr_lba}");
+            }
+            let mut entries = mbr_entries(&ebr);
+            let (ty, start, count) = entries.next().unwrap();
+            if ty != 0 && count != 0 {
+                partitions.push(Partition {
+                    number,
+                    start: (ebr_lba + start) * 512,
+                    size: count * 512,
+                });
+                number += 1;
+            }
+            match entries.next().unwrap() {
+                (ty, next, _) if MBR_EXTENDED_TYPES.contains(&ty) && next != 0 => {
+                    ebr_lba = ext_start + next;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    Ok(partitions)
+}
+
+fn read_gpt(image: &dyn ImageRead) -> Result<Vec<Partition>, Error> {
+    // the header is in the second logical block, try both common sector sizes
+    for sector_size in [512u64, 4096] {
+        if image.size() < sector_size * 2 {
+            continue;
+        }
+        let header = read_vec(image, sector_size, 92)?;
+        if &header[0..8] != b"EFI PART" {
+            continue;
+        }
+
+        let entries_lba = le64(&header, 72);
+        let entry_count = le32(&header, 80) as u64;
+        let entry_size = le32(&header, 84) as u64;
+        // the entry size must be 128 multiplied by a power of two
+        if entry_count > MAX_GPT_ENTRIES
+            || !(128..=MAX_GPT_ENTRY_SIZE).contains(&entry_size)
+            || !entry_size.is_power_of_two()
+        {
+            bail!("invalid GPT header");
+        }
+        let table_offset = entries_lba
+            .checked_mul(sector_size)
+            .ok_or_else(|| format_err!("invalid GPT partition entry location"))?;
+
+        let table = read_vec(image, table_offset, (entry_count * entry_size) as usize)?;
+
+        let mut partitions = Vec::new();
+        for i in 0..entry_count {
+            let entry = &table[(i * entry_size) as usize..((i + 1) * entry_size) as usize];
+            if entry[0..16].iter().all(|b| *b == 0) {
+                continue;
+            }
+            let first = le64(entry, 32);
+            let last = le64(entry, 40);
+            if last < first {
+                bail!("invalid GPT entry {}", i + 1);
+            }
+            let (start, size) = match (
+                first.checked_mul(sector_size),
+                (last - first + 1).checked_mul(sector_size),
+            ) {
+                (Some(start), Some(size)) => (start, size),
+                _ => bail!("invalid GPT entry {}", i + 1),
+            };
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                start,
+                size,
+            });
+        }
+        return Ok(partitions);
+    }
+
+    bail!("protective MBR found, but no valid GPT header");
+}
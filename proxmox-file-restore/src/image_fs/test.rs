@@ -0,0 +1,689 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use anyhow::{bail, Error};
+
+use super::*;
+
+/// Image split into fixed size chunks like a fixed index, so that reads cross chunk boundaries
+pub(crate) struct ChunkedImage {
+    chunks: Vec<Vec<u8>>,
+    chunk_size: usize,
+    size: u64,
+}
+
+impl ChunkedImage {
+    pub(crate) fn new(data: &[u8], chunk_size: usize) -> Self {
+        Self {
+            chunks: data.chunks(chunk_size).map(|c| c.to_vec()).collect(),
+            chunk_size,
+            size: data.len() as u64,
+        }
+    }
+}
+
+impl ImageRead for ChunkedImage {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+        if offset + buf.len() as u64 > self.size {
+            bail!("read beyond end of image");
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset as usize + done;
+            let chunk = &self.chunks[pos / self.chunk_size];
+            let start = pos % self.chunk_size;
+            let count = (buf.len() - done).min(chunk.len() - start);
+            buf[done..done + count].copy_from_slice(&chunk[start..start + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("file-restore-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+const LONG_LINK: &str = "some/rather/long/symlink/target/which/does/not/fit/into/the/inode/itself";
+
+/// The sparse test file has this many data regions, enough for an extent tree with index nodes
+const SPARSE_REGIONS: usize = 40;
+const SPARSE_REGION_SIZE: usize = 4096;
+const SPARSE_DISTANCE: usize = 64 * 1024;
+
+fn sparse_content() -> Vec<u8> {
+    let mut data = vec![0u8; SPARSE_REGIONS * SPARSE_DISTANCE];
+    for i in 0..SPARSE_REGIONS {
+        let start = i * SPARSE_DISTANCE + SPARSE_DISTANCE / 2;
+        data[start..start + SPARSE_REGION_SIZE]
+            .copy_from_slice(&pattern(SPARSE_REGION_SIZE, i as u32 + 2));
+    }
+    data
+}
+
+fn populate(dir: &Path) {
+    std::fs::write(dir.join("small.txt"), b"hello world\n").unwrap();
+    std::fs::write(dir.join("large.bin"), pattern(300 * 1024 + 17, 1)).unwrap();
+    std::fs::create_dir(dir.join("many")).unwrap();
+    for i in 0..300 {
+        std::fs::write(dir.join("many").join(format!("file-{i:04}")), i.to_string()).unwrap();
+    }
+    std::os::unix::fs::symlink("small.txt", dir.join("short-link")).unwrap();
+    std::os::unix::fs::symlink(LONG_LINK, dir.join("long-link")).unwrap();
+
+    // only write the data regions, so that the file is sparse on the source filesystem
+    let sparse = std::fs::File::create(dir.join("sparse.bin")).unwrap();
+    sparse
+        .set_len((SPARSE_REGIONS * SPARSE_DISTANCE) as u64)
+        .unwrap();
+    let data = sparse_content();
+    for i in 0..SPARSE_REGIONS {
+        let start = i * SPARSE_DISTANCE + SPARSE_DISTANCE / 2;
+        std::os::unix::fs::FileExt::write_all_at(
+            &sparse,
+            &data[start..start + SPARSE_REGION_SIZE],
+            start as u64,
+        )
+        .unwrap();
+    }
+}
+
+/// Run a mkfs tool, a missing tool fails the test instead of silently skipping it
+fn mkfs(tool: &str, args: &[&str], image: &Path) {
+    match Command::new(tool).args(args).arg(image).status() {
+        Ok(status) if status.success() => (),
+        Ok(status) => panic!("{tool} failed - {status}"),
+        Err(err) => panic!("unable to run {tool} - {err}"),
+    }
+}
+
+fn check_filesystem(image: Arc<dyn ImageRead>) {
+    let fs = open_filesystem(image).unwrap();
+
+    let mut names: Vec<Vec<u8>> = fs
+        .read_dir(&fs.root().unwrap())
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    for name in [
+        "large.bin",
+        "long-link",
+        "many",
+        "short-link",
+        "small.txt",
+        "sparse.bin",
+    ] {
+        assert!(names.contains(&name.as_bytes().to_vec()), "missing {name}");
+    }
+
+    let small = lookup(&*fs, b"/small.txt").unwrap();
+    assert_eq!(small.kind, FileKind::File);
+    assert_eq!(read_all(&*fs, &small).unwrap(), b"hello world\n");
+
+    let large = lookup(&*fs, b"large.bin").unwrap();
+    let mut data = Vec::new();
+    FileReader::new(&*fs, &large)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, pattern(300 * 1024 + 17, 1));
+
+    let many = lookup(&*fs, b"/many/").unwrap();
+    assert_eq!(many.kind, FileKind::Directory);
+    assert_eq!(fs.read_dir(&many).unwrap().len(), 300);
+    let file = lookup(&*fs, b"/many/../many/./file-0123").unwrap();
+    assert_eq!(read_all(&*fs, &file).unwrap(), b"123");
+
+    let link = lookup(&*fs, b"/short-link").unwrap();
+    assert_eq!(link.kind, FileKind::Symlink);
+    assert_eq!(fs.read_link(&link).unwrap(), b"small.txt");
+    let link = lookup(&*fs, b"/long-link").unwrap();
+    assert_eq!(fs.read_link(&link).unwrap(), LONG_LINK.as_bytes());
+
+    let sparse = lookup(&*fs, b"/sparse.bin").unwrap();
+    assert_eq!(read_all(&*fs, &sparse).unwrap(), sparse_content());
+
+    assert!(lookup(&*fs, b"/does/not/exist").is_err());
+    assert!(lookup(&*fs, b"/small.txt/foo").is_err());
+}
+
+pub(crate) fn make_ext4_image(args: &[&str], size: usize, name: &str) -> Vec<u8> {
+    let tmp = TempDir::new(name);
+    let source = tmp.0.join("source");
+    std::fs::create_dir(&source).unwrap();
+    populate(&source);
+
+    let image = tmp.0.join("image.raw");
+    std::fs::File::create(&image)
+        .unwrap()
+        .set_len(size as u64)
+        .unwrap();
+
+    let mut args = args.to_vec();
+    args.extend(["-d", source.to_str().unwrap()]);
+    mkfs("mkfs.ext4", &args, &image);
+
+    std::fs::read(&image).unwrap()
+}
+
+#[test]
+fn test_ext4_raw() {
+    for (name, args) in [
+        ("ext4", &["-q", "-F"][..]),
+        ("ext4-1k", &["-q", "-F", "-b", "1024"][..]),
+        (
+            "ext4-blockmap",
+            &["-q", "-F", "-O", "^extent,^64bit,^flex_bg"][..],
+        ),
+    ] {
+        let data = make_ext4_image(args, 16 * 1024 * 1024, name);
+        assert_eq!(
+            detect_layout(&ChunkedImage::new(&data, 4096)).unwrap(),
+            Layout::Ext4
+        );
+        // odd chunk size so that blocks and inodes straddle chunk boundaries
+        check_filesystem(Arc::new(ChunkedImage::new(&data, 3000)));
+
+        // mkfs keeps the holes, more extents than fit into the inode need index nodes
+        let fs = open_filesystem(Arc::new(ChunkedImage::new(&data, 4096))).unwrap();
+        let sparse = lookup(&*fs, b"/sparse.bin").unwrap();
+        let extents = fs.extents(&sparse).unwrap();
+        assert!(extents.len() >= SPARSE_REGIONS, "{name}");
+        // a region may be split, if the allocator placed tree blocks in between
+        for extent in extents.iter() {
+            let offset = extent.logical as usize % SPARSE_DISTANCE;
+            assert!(offset >= SPARSE_DISTANCE / 2, "{name} {extent:?}");
+            assert!(offset + extent.length as usize <= SPARSE_DISTANCE / 2 + SPARSE_REGION_SIZE);
+        }
+        let mapped: u64 = extents.iter().map(|extent| extent.length).sum();
+        assert_eq!(
+            mapped,
+            (SPARSE_REGIONS * SPARSE_REGION_SIZE) as u64,
+            "{name}"
+        );
+    }
+}
+
+/// Protofile for mkfs.xfs, describing the same tree as `populate`
+fn xfs_protofile(source: &Path) -> String {
+    let source = source.to_str().unwrap();
+    let mut proto = String::from("/dev/null\n0 0\nd--755 0 0\n");
+    for name in ["small.txt", "large.bin", "sparse.bin"] {
+        proto += &format!("{name} ---644 0 0 {source}/{name}\n");
+    }
+    proto += "many d--755 0 0\n";
+    for i in 0..300 {
+        proto += &format!("file-{i:04} ---644 0 0 {source}/many/file-{i:04}\n");
+    }
+    proto += "$\n";
+    proto += "short-link l--777 0 0 small.txt\n";
+    proto += &format!("long-link l--777 0 0 {LONG_LINK}\n");
+    proto += "$\n";
+    proto
+}
+
+#[test]
+#[ignore = "requires mkfs.xfs"]
+fn test_xfs_raw() {
+    for (name, args) in [
+        ("xfs", &["-q", "-f"][..]),
+        ("xfs-v4", &["-q", "-f", "-m", "crc=0"][..]),
+    ] {
+        let tmp = TempDir::new(name);
+        let source = tmp.0.join("source");
+        std::fs::create_dir(&source).unwrap();
+        populate(&source);
+        let proto = tmp.0.join("proto");
+        std::fs::write(&proto, xfs_protofile(&source)).unwrap();
+
+        let image = tmp.0.join("image.raw");
+        std::fs::File::create(&image)
+            .unwrap()
+            .set_len(300 * 1024 * 1024)
+            .unwrap();
+        let mut args = args.to_vec();
+        args.extend(["-p", proto.to_str().unwrap()]);
+        mkfs("mkfs.xfs", &args, &image);
+
+        let data = std::fs::read(&image).unwrap();
+        assert_eq!(
+            detect_layout(&ChunkedImage::new(&data, 4096)).unwrap(),
+            Layout::Xfs
+        );
+        check_filesystem(Arc::new(ChunkedImage::new(&data, 3000)));
+    }
+}
+
+const XFS_BLOCK_SIZE: usize = 4096;
+const XFS_ROOT_INO: u64 = 16;
+const XFS_SPARSE_INO: u64 = 17;
+const XFS_DIR_INO: u64 = 18;
+const XFS_LINK_INO: u64 = 19;
+const XFS_REMOTE_LINK_INO: u64 = 20;
+const XFS_FILE_INO: u64 = 21;
+/// Extent btree leaf of the sparse file
+const XFS_BMBT_BLOCK: u64 = 3;
+/// Physical block of the first data block of the sparse file
+const XFS_SPARSE_DATA: u64 = 10;
+const XFS_SPARSE_EXTENTS: u64 = 30;
+
+fn put(buf: &mut [u8], offset: usize, data: &[u8]) {
+    buf[offset..offset + data.len()].copy_from_slice(data);
+}
+
+fn xfs_extent(logical: u64, physical: u64, count: u64, unwritten: bool) -> [u8; 16] {
+    let l0 = (u64::from(unwritten) << 63) | (logical << 9) | (physical >> 43);
+    let l1 = (physical << 21) | count;
+    let mut rec = [0u8; 16];
+    put(&mut rec, 0, &l0.to_be_bytes());
+    put(&mut rec, 8, &l1.to_be_bytes());
+    rec
+}
+
+/// Write a v2 inode into the inode block 1 (16 inodes per block, all inodes live there)
+fn xfs_inode(image: &mut [u8], ino: u64, mode: u16, format: u8, size: u64, fork: &[u8]) {
+    let offset = XFS_BLOCK_SIZE + (ino as usize - 16) * 256;
+    let inode = &mut image[offset..offset + 256];
+    put(inode, 0, b"IN");
+    put(inode, 2, &mode.to_be_bytes());
+    inode[4] = 2;
+    inode[5] = format;
+    put(inode, 40, &1_700_000_000u32.to_be_bytes());
+    put(inode, 56, &size.to_be_bytes());
+    let nextents = if format == 2 { fork.len() / 16 } else { 0 };
+    put(inode, 76, &(nextents as u32).to_be_bytes());
+    put(inode, 100, fork);
+}
+
+/// Write a directory entry into a data block, returns the offset of the next entry
+fn xfs_dir_entry(block: &mut [u8], pos: usize, name: &[u8], ino: u64) -> usize {
+    put(block, pos, &ino.to_be_bytes());
+    block[pos + 8] = name.len() as u8;
+    put(block, pos + 9, name);
+    pos + (9 + name.len() + 1 + 2).next_multiple_of(8)
+}
+
+fn xfs_remote_link_target() -> Vec<u8> {
+    "long/".repeat(60).into_bytes()
+}
+
+/// Build a tiny v4 XFS image by hand, to cover formats mkfs does not create for small trees:
+/// an extent btree with holes and an unwritten extent, a single block directory, and local
+/// and remote symlinks.
+fn make_xfs_image() -> Vec<u8> {
+    let mut image = vec![0u8; 64 * XFS_BLOCK_SIZE];
+
+    // superblock: 4k blocks, 256 byte inodes, a single AG of 64 blocks, ftype enabled
+    put(&mut image, 0, b"XFSB");
+    put(&mut image, 4, &(XFS_BLOCK_SIZE as u32).to_be_bytes());
+    put(&mut image, 56, &XFS_ROOT_INO.to_be_bytes());
+    put(&mut image, 84, &64u32.to_be_bytes());
+    put(&mut image, 100, &4u16.to_be_bytes());
+    put(&mut image, 104, &256u16.to_be_bytes());
+    image[120] = 12;
+    image[123] = 4;
+    image[124] = 6;
+    put(&mut image, 200, &0x200u32.to_be_bytes());
+
+    // short form root directory
+    let mut fork = vec![4, 0];
+    fork.extend((XFS_ROOT_INO as u32).to_be_bytes());
+    for (name, ino) in [
+        (&b"sparse"[..], XFS_SPARSE_INO),
+        (b"dir", XFS_DIR_INO),
+        (b"link", XFS_LINK_INO),
+        (b"long-link", XFS_REMOTE_LINK_INO),
+    ] {
+        fork.push(name.len() as u8);
+        fork.extend([0, 0]);
+        fork.extend(name);
+        fork.push(0);
+        fork.extend((ino as u32).to_be_bytes());
+    }
+    xfs_inode(
+        &mut image,
+        XFS_ROOT_INO,
+        0o40755,
+        1,
+        fork.len() as u64,
+        &fork,
+    );
+
+    // sparse file: every other block is a hole, the last extent is unwritten
+    let mut fork = vec![0u8; 156];
+    put(&mut fork, 0, &1u16.to_be_bytes());
+    put(&mut fork, 2, &1u16.to_be_bytes());
+    put(&mut fork, 4 + 9 * 8, &XFS_BMBT_BLOCK.to_be_bytes());
+    let size = (2 * XFS_SPARSE_EXTENTS + 1) * XFS_BLOCK_SIZE as u64;
+    xfs_inode(&mut image, XFS_SPARSE_INO, 0o100644, 3, size, &fork);
+
+    let leaf = XFS_BMBT_BLOCK as usize * XFS_BLOCK_SIZE;
+    put(&mut image, leaf, b"BMAP");
+    put(
+        &mut image,
+        leaf + 6,
+        &(XFS_SPARSE_EXTENTS as u16 + 1).to_be_bytes(),
+    );
+    for i in 0..XFS_SPARSE_EXTENTS {
+        let rec = xfs_extent(2 * i, XFS_SPARSE_DATA + i, 1, false);
+        put(&mut image, leaf + 24 + i as usize * 16, &rec);
+        let data = (XFS_SPARSE_DATA + i) as usize * XFS_BLOCK_SIZE;
+        put(&mut image, data, &pattern(XFS_BLOCK_SIZE, i as u32));
+    }
+    let unwritten = XFS_SPARSE_DATA + XFS_SPARSE_EXTENTS;
+    let rec = xfs_extent(2 * XFS_SPARSE_EXTENTS, unwritten, 1, true);
+    put(
+        &mut image,
+        leaf + 24 + XFS_SPARSE_EXTENTS as usize * 16,
+        &rec,
+    );
+    let data = unwritten as usize * XFS_BLOCK_SIZE;
+    image[data..data + XFS_BLOCK_SIZE].fill(0xaa);
+
+    // single block directory in block 4, containing a file with its data in block 5
+    xfs_inode(
+        &mut image,
+        XFS_DIR_INO,
+        0o40755,
+        2,
+        XFS_BLOCK_SIZE as u64,
+        &xfs_extent(0, 4, 1, false),
+    );
+    let block = &mut image[4 * XFS_BLOCK_SIZE..5 * XFS_BLOCK_SIZE];
+    put(block, 0, b"XD2B");
+    let mut pos = 16;
+    pos = xfs_dir_entry(block, pos, b".", XFS_DIR_INO);
+    pos = xfs_dir_entry(block, pos, b"..", XFS_ROOT_INO);
+    pos = xfs_dir_entry(block, pos, b"hello.txt", XFS_FILE_INO);
+    let end = XFS_BLOCK_SIZE - 8 - 3 * 8;
+    put(block, pos, &0xffffu16.to_be_bytes());
+    put(block, pos + 2, &((end - pos) as u16).to_be_bytes());
+    put(block, XFS_BLOCK_SIZE - 8, &3u32.to_be_bytes());
+
+    xfs_inode(
+        &mut image,
+        XFS_FILE_INO,
+        0o100644,
+        2,
+        12,
+        &xfs_extent(0, 5, 1, false),
+    );
+    put(&mut image, 5 * XFS_BLOCK_SIZE, b"hello world\n");
+
+    // symlinks, stored in the inode and in block 6
+    let target = b"dir/hello.txt";
+    xfs_inode(
+        &mut image,
+        XFS_LINK_INO,
+        0o120777,
+        1,
+        target.len() as u64,
+        target,
+    );
+    let target = xfs_remote_link_target();
+    xfs_inode(
+        &mut image,
+        XFS_REMOTE_LINK_INO,
+        0o120777,
+        2,
+        target.len() as u64,
+        &xfs_extent(0, 6, 1, false),
+    );
+    put(&mut image, 6 * XFS_BLOCK_SIZE, &target);
+
+    image
+}
+
+#[test]
+fn test_xfs_formats() {
+    let image = make_xfs_image();
+    assert_eq!(
+        detect_layout(&ChunkedImage::new(&image, 4096)).unwrap(),
+        Layout::Xfs
+    );
+    let fs = open_filesystem(Arc::new(ChunkedImage::new(&image, 3000))).unwrap();
+
+    let root = fs.root().unwrap();
+    assert_eq!(root.kind, FileKind::Directory);
+    let names: Vec<Vec<u8>> = fs
+        .read_dir(&root)
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(
+        names,
+        [&b"sparse"[..], b"dir", b"link", b"long-link"].map(|name| name.to_vec())
+    );
+
+    let sparse = lookup(&*fs, b"/sparse").unwrap();
+    assert_eq!(sparse.kind, FileKind::File);
+    assert_eq!(sparse.mtime, 1_700_000_000);
+    let extents = fs.extents(&sparse).unwrap();
+    assert_eq!(extents.len(), XFS_SPARSE_EXTENTS as usize);
+    assert_eq!(
+        extents[1],
+        Extent {
+            logical: 2 * XFS_BLOCK_SIZE as u64,
+            physical: (XFS_SPARSE_DATA + 1) * XFS_BLOCK_SIZE as u64,
+            length: XFS_BLOCK_SIZE as u64,
+        }
+    );
+    let mut expected = Vec::new();
+    for i in 0..XFS_SPARSE_EXTENTS {
+        expected.extend(pattern(XFS_BLOCK_SIZE, i as u32));
+        expected.extend([0u8; XFS_BLOCK_SIZE]);
+    }
+    // the unwritten extent reads as zeroes
+    expected.resize((2 * XFS_SPARSE_EXTENTS as usize + 1) * XFS_BLOCK_SIZE, 0);
+    assert!(read_all(&*fs, &sparse).unwrap() == expected);
+
+    let dir = lookup(&*fs, b"/dir").unwrap();
+    let entries = fs.read_dir(&dir).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].name, b"hello.txt");
+    assert_eq!(entries[0].ino, XFS_FILE_INO);
+    let file = lookup(&*fs, b"/dir/hello.txt").unwrap();
+    assert_eq!(read_all(&*fs, &file).unwrap(), b"hello world\n");
+
+    let link = lookup(&*fs, b"/link").unwrap();
+    assert_eq!(link.kind, FileKind::Symlink);
+    assert_eq!(fs.read_link(&link).unwrap(), b"dir/hello.txt");
+    let link = lookup(&*fs, b"/long-link").unwrap();
+    assert_eq!(fs.read_link(&link).unwrap(), xfs_remote_link_target());
+    assert!(fs.read_link(&file).is_err());
+}
+
+#[test]
+fn test_xfs_corrupt_btree() {
+    let mut image = make_xfs_image();
+    let leaf = XFS_BMBT_BLOCK as usize * XFS_BLOCK_SIZE;
+
+    // wrong level
+    put(&mut image, leaf + 4, &1u16.to_be_bytes());
+    let fs = open_filesystem(Arc::new(ChunkedImage::new(&image, 4096))).unwrap();
+    let sparse = lookup(&*fs, b"/sparse").unwrap();
+    let err = fs.extents(&sparse).unwrap_err();
+    assert!(err.to_string().contains("unexpected level"), "{err}");
+
+    // more records than fit into the block
+    put(&mut image, leaf + 4, &0u16.to_be_bytes());
+    put(&mut image, leaf + 6, &1000u16.to_be_bytes());
+    let fs = open_filesystem(Arc::new(ChunkedImage::new(&image, 4096))).unwrap();
+    let err = fs.extents(&sparse).unwrap_err();
+    assert!(err.to_string().contains("corrupt extent btree"), "{err}");
+
+    // not a btree block at all
+    put(&mut image, leaf, b"XXXX");
+    let fs = open_filesystem(Arc::new(ChunkedImage::new(&image, 4096))).unwrap();
+    let err = fs.extents(&sparse).unwrap_err();
+    assert!(
+        err.to_string().contains("invalid extent btree block"),
+        "{err}"
+    );
+}
+
+pub(crate) fn write_mbr_entry(
+    image: &mut [u8],
+    sector: usize,
+    index: usize,
+    ty: u8,
+    start: u32,
+    count: u32,
+) {
+    let entry = sector * 512 + 446 + index * 16;
+    image[entry + 4] = ty;
+    image[entry + 8..entry + 12].copy_from_slice(&start.to_le_bytes());
+    image[entry + 12..entry + 16].copy_from_slice(&count.to_le_bytes());
+    image[sector * 512 + 510] = 0x55;
+    image[sector * 512 + 511] = 0xaa;
+}
+
+#[test]
+fn test_mbr_partitions() {
+    let mut image = vec![0u8; 64 * 1024 * 1024];
+    write_mbr_entry(&mut image, 0, 0, 0x83, 2048, 2048);
+    write_mbr_entry(&mut image, 0, 1, 0x05, 8192, 20480);
+    // two logical partitions, each EBR relative to the extended partition start
+    write_mbr_entry(&mut image, 8192, 0, 0x83, 2048, 4096);
+    write_mbr_entry(&mut image, 8192, 1, 0x05, 8192, 8192);
+    write_mbr_entry(&mut image, 16384, 0, 0x83, 2048, 2048);
+
+    let image = ChunkedImage::new(&image, 65536);
+    assert_eq!(detect_layout(&image).unwrap(), Layout::PartitionTable);
+    let parts = read_partitions(&image).unwrap();
+    assert_eq!(
+        parts,
+        vec![
+            Partition {
+                number: 1,
+                start: 2048 * 512,
+                size: 2048 * 512,
+            },
+            Partition {
+                number: 5,
+                start: (8192 + 2048) * 512,
+                size: 4096 * 512,
+            },
+            Partition {
+                number: 6,
+                start: (16384 + 2048) * 512,
+                size: 2048 * 512,
+            },
+        ]
+    );
+}
+
+fn write_gpt_header(image: &mut [u8], entries_lba: u64, count: u32, entry_size: u32) {
+    write_mbr_entry(image, 0, 0, 0xee, 1, 0xffffffff);
+    image[512..520].copy_from_slice(b"EFI PART");
+    image[512 + 72..512 + 80].copy_from_slice(&entries_lba.to_le_bytes());
+    image[512 + 80..512 + 84].copy_from_slice(&count.to_le_bytes());
+    image[512 + 84..512 + 88].copy_from_slice(&entry_size.to_le_bytes());
+}
+
+#[test]
+fn test_gpt_partition_with_ext4() {
+    let fs = make_ext4_image(&["-q", "-F"], 16 * 1024 * 1024, "gpt");
+
+    let mut image = vec![0u8; 2048 * 512 + fs.len() + 34 * 512];
+    write_gpt_header(&mut image, 2, 128, 128);
+    // second entry, the first one stays unused
+    let entry = 2 * 512 + 128;
+    image[entry..entry + 16].copy_from_slice(&[0xaf; 16]);
+    image[entry + 32..entry + 40].copy_from_slice(&2048u64.to_le_bytes());
+    let last = 2048 + (fs.len() / 512) as u64 - 1;
+    image[entry + 40..entry + 48].copy_from_slice(&last.to_le_bytes());
+    image[2048 * 512..2048 * 512 + fs.len()].copy_from_slice(&fs);
+
+    let image: Arc<dyn ImageRead> = Arc::new(ChunkedImage::new(&image, 3000));
+    let parts = read_partitions(&*image).unwrap();
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].number, 2);
+    assert_eq!(parts[0].start, 2048 * 512);
+    assert_eq!(parts[0].size, fs.len() as u64);
+
+    let part = ImageSlice::new(image, parts[0].start, parts[0].size).unwrap();
+    check_filesystem(Arc::new(part));
+}
+
+#[test]
+fn test_invalid_gpt() {
+    let mut image = vec![0u8; 1024 * 1024];
+
+    for (entries_lba, count, entry_size) in [
+        (2, 128, 0x8000_0000),
+        (2, 128, 4096 * 2),
+        (2, 128, 192),
+        (2, 128, 64),
+        (2, 4096, 128),
+        (u64::MAX / 256, 128, 128),
+    ] {
+        write_gpt_header(&mut image, entries_lba, count, entry_size);
+        let image = ChunkedImage::new(&image, 65536);
+        assert!(
+            read_partitions(&image).is_err(),
+            "{entries_lba} {count} {entry_size}"
+        );
+    }
+
+    // entry beyond the end of the image
+    write_gpt_header(&mut image, 2, 128, 128);
+    let entry = 2 * 512;
+    image[entry..entry + 16].copy_from_slice(&[0xaf; 16]);
+    image[entry + 32..entry + 40].copy_from_slice(&(u64::MAX / 256).to_le_bytes());
+    image[entry + 40..entry + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(read_partitions(&ChunkedImage::new(&image, 65536)).is_err());
+}
+
+#[test]
+fn test_unsupported_layouts() {
+    let mut lvm = vec![0u8; 1024 * 1024];
+    lvm[512..520].copy_from_slice(b"LABELONE");
+    let err = open_filesystem(Arc::new(ChunkedImage::new(&lvm, 4096)))
+        .err()
+        .unwrap();
+    assert!(err.to_string().starts_with("unsupported layout"), "{err}");
+
+    let mut luks = vec![0u8; 1024 * 1024];
+    luks[0..6].copy_from_slice(b"LUKS\xba\xbe");
+    let err = open_filesystem(Arc::new(ChunkedImage::new(&luks, 4096)))
+        .err()
+        .unwrap();
+    assert!(err.to_string().starts_with("unsupported layout"), "{err}");
+
+    let zero = vec![0u8; 1024 * 1024];
+    assert!(open_filesystem(Arc::new(ChunkedImage::new(&zero, 4096))).is_err());
+}
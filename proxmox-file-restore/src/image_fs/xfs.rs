@@ -0,0 +1,485 @@
+//! Read-only XFS (v4 and v5) support
+use std::sync::Arc;
+
+use anyhow::{bail, format_err, Error};
+
+use super::{be16, be32, be64, read_vec, DirEntry, Extent, FileKind, FileSystem, ImageRead, Inode};
+
+const INODE_MAGIC: u16 = 0x494e; // "IN"
+
+const FORMAT_DEV: u8 = 0;
+const FORMAT_LOCAL: u8 = 1;
+const FORMAT_EXTENTS: u8 = 2;
+const FORMAT_BTREE: u8 = 3;
+
+const INCOMPAT_FTYPE: u32 = 0x1;
+const INCOMPAT_KNOWN: u32 = 0x1 | 0x2 | 0x4 | 0x8 | 0x20 | 0x40 | 0x80;
+const FEATURES2_FTYPE: u32 = 0x200;
+
+const DIFLAG_REALTIME: u16 = 0x1;
+const DIFLAG2_BIGTIME: u64 = 1 << 3;
+const DIFLAG2_NREXT64: u64 = 1 << 4;
+const BIGTIME_EPOCH_OFFSET: i64 = 1 << 31;
+
+const BMAP_MAGIC: u32 = 0x424d4150; // "BMAP"
+const BMAP_CRC_MAGIC: u32 = 0x424d4133; // "BMA3"
+const BMBT_MAX_LEVEL: u16 = 9;
+
+const DIR_BLOCK_MAGIC: u32 = 0x58443242; // "XD2B"
+const DIR_DATA_MAGIC: u32 = 0x58443244; // "XD2D"
+const DIR_BLOCK_CRC_MAGIC: u32 = 0x58444233; // "XDB3"
+const DIR_DATA_CRC_MAGIC: u32 = 0x58444433; // "XDD3"
+/// Directory data blocks live below this logical offset, leaf and free index blocks above
+const DIR_LEAF_OFFSET: u64 = 32 * 1024 * 1024 * 1024;
+
+const SYMLINK_CRC_MAGIC: u32 = 0x58534c4d; // "XSLM"
+const SYMLINK_CRC_HEADER_SIZE: usize = 56;
+
+pub struct Xfs {
+    image: Arc<dyn ImageRead>,
+    block_size: u64,
+    ag_blocks: u64,
+    ag_block_log: u32,
+    inodes_per_block_log: u32,
+    inode_size: u64,
+    dir_block_size: u64,
+    crc: bool,
+    ftype: bool,
+    root_ino: u64,
+}
+
+/// The parts of an on-disk inode we care about
+struct RawInode {
+    data: Vec<u8>,
+    format: u8,
+    flags: u16,
+    flags2: u64,
+    nextents: u64,
+    fork_start: usize,
+    fork_end: usize,
+}
+
+impl RawInode {
+    fn data_fork(&self) -> &[u8] {
+        &self.data[self.fork_start..self.fork_end]
+    }
+}
+
+impl Xfs {
+    pub fn open(image: Arc<dyn ImageRead>) -> Result<Self, Error> {
+        let sb = read_vec(&*image, 0, 512)?;
+        if &sb[0..4] != b"XFSB" {
+            bail!("no XFS superblock found");
+        }
+
+        let crc = match be16(&sb, 100) & 0xf {
+            4 => false,
+            5 => true,
+            version => bail!("unsupported XFS version {version}"),
+        };
+
+        let incompat = if crc { be32(&sb, 216) } else { 0 };
+        if incompat & !INCOMPAT_KNOWN != 0 {
+            bail!("unsupported layout - unknown XFS features {incompat:#x}");
+        }
+        let ftype = if crc {
+            incompat & INCOMPAT_FTYPE != 0
+        } else {
+            be32(&sb, 200) & FEATURES2_FTYPE != 0
+        };
+
+        let block_size = be32(&sb, 4) as u64;
+        let block_log = sb[120] as u32;
+        if !(9..=16).contains(&block_log) || block_size != 1 << block_log {
+            bail!("invalid XFS block size");
+        }
+
+        let inode_size = be16(&sb, 104) as u64;
+        if !(256..=2048).contains(&inode_size) {
+            bail!("invalid XFS inode size {inode_size}");
+        }
+
+        let dir_block_log = sb[192] as u32;
+        if block_log + dir_block_log > 16 {
+            bail!("invalid XFS directory block size");
+        }
+
+        Ok(Self {
+            image,
+            block_size,
+            ag_blocks: be32(&sb, 84) as u64,
+            ag_block_log: sb[124] as u32,
+            inodes_per_block_log: sb[123] as u32,
+            inode_size,
+            dir_block_size: block_size << dir_block_log,
+            crc,
+            ftype,
+            root_ino: be64(&sb, 56),
+        })
+    }
+
+    /// Convert a filesystem block number (AG number and AG relative block) to a byte offset
+    fn fsb_to_offset(&self, fsb: u64) -> u64 {
+        let agno = fsb >> self.ag_block_log;
+        let agbno = fsb & ((1 << self.ag_block_log) - 1);
+        (agno * self.ag_blocks + agbno) * self.block_size
+    }
+
+    fn raw_inode(&self, ino: u64) -> Result<RawInode, Error> {
+        let index = ino & ((1 << self.inodes_per_block_log) - 1);
+        let block = ino >> self.inodes_per_block_log;
+        let offset = self.fsb_to_offset(block) + index * self.inode_size;
+
+        let data = read_vec(&*self.image, offset, self.inode_size as usize)?;
+        if be16(&data, 0) != INODE_MAGIC {
+            bail!("invalid inode {ino}");
+        }
+
+        let version = data[4];
+        let (literal, flags2) = match version {
+            1 | 2 if !self.crc => (100, 0),
+            3 if self.crc => (176, be64(&data, 120)),
+            _ => bail!("unexpected version {version} of inode {ino}"),
+        };
+
+        let fork_end = match data[82] {
+            0 => data.len(),
+            forkoff => (literal + forkoff as usize * 8).min(data.len()),
+        };
+
+        let nextents = if flags2 & DIFLAG2_NREXT64 != 0 {
+            be64(&data, 24)
+        } else {
+            be32(&data, 76) as u64
+        };
+
+        Ok(RawInode {
+            format: data[5],
+            flags: be16(&data, 90),
+            flags2,
+            nextents,
+            fork_start: literal,
+            fork_end,
+            data,
+        })
+    }
+
+    fn decode_extent(&self, rec: &[u8], out: &mut Vec<Extent>) {
+        let l0 = be64(rec, 0);
+        let l1 = be64(rec, 8);
+        if l0 >> 63 != 0 {
+            // unwritten extent, reads as zeroes
+            return;
+        }
+        let start_offset = (l0 & ((1 << 63) - 1)) >> 9;
+        let start_block = ((l0 & 0x1ff) << 43) | (l1 >> 21);
+        let count = l1 & ((1 << 21) - 1);
+        out.push(Extent {
+            logical: start_offset * self.block_size,
+            physical: self.fsb_to_offset(start_block),
+            length: count * self.block_size,
+        });
+    }
+
+    fn walk_bmbt(&self, fsb: u64, level: u16, out: &mut Vec<Extent>) -> Result<(), Error> {
+        let block = read_vec(
+            &*self.image,
+            self.fsb_to_offset(fsb),
+            self.block_size as usize,
+        )?;
+        let header = match be32(&block, 0) {
+            BMAP_MAGIC if !self.crc => 24,
+            BMAP_CRC_MAGIC if self.crc => 72,
+            _ => bail!("invalid extent btree block {fsb}"),
+        };
+        if be16(&block, 4) != level {
+            bail!("unexpected level in extent btree block {fsb}");
+        }
+        let count = be16(&block, 6) as usize;
+
+        if level == 0 {
+            if header + count * 16 > block.len() {
+                bail!("corrupt extent btree block {fsb}");
+            }
+            for rec in block[header..header + count * 16].chunks_exact(16) {
+                self.decode_extent(rec, out);
+            }
+        } else {
+            let max_records = (block.len() - header) / 16;
+            if count > max_records {
+                bail!("corrupt extent btree block {fsb}");
+            }
+            let ptrs = header + max_records * 8;
+            for i in 0..count {
+                self.walk_bmbt(be64(&block, ptrs + i * 8), level - 1, out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn data_extents(&self, ino: u64, raw: &RawInode) -> Result<Vec<Extent>, Error> {
+        if raw.flags & DIFLAG_REALTIME != 0 {
+            bail!("unsupported layout - inode {ino} is stored on the realtime device");
+        }
+
+        let fork = raw.data_fork();
+        let mut extents = Vec::new();
+        match raw.format {
+            FORMAT_DEV => (),
+            FORMAT_EXTENTS => {
+                let count = raw.nextents as usize;
+                if count * 16 > fork.len() {
+                    bail!("corrupt extent list in inode {ino}");
+                }
+                for rec in fork[..count * 16].chunks_exact(16) {
+                    self.decode_extent(rec, &mut extents);
+                }
+            }
+            FORMAT_BTREE => {
+                let level = be16(fork, 0);
+                let count = be16(fork, 2) as usize;
+                let max_records = (fork.len() - 4) / 16;
+                if level == 0 || level > BMBT_MAX_LEVEL || count > max_records {
+                    bail!("corrupt extent btree root in inode {ino}");
+                }
+                let ptrs = 4 + max_records * 8;
+                for i in 0..count {
+                    self.walk_bmbt(be64(fork, ptrs + i * 8), level - 1, &mut extents)?;
+                }
+            }
+            format => bail!("unexpected data fork format {format} in inode {ino}"),
+        }
+        extents.sort_by_key(|extent| extent.logical);
+
+        Ok(extents)
+    }
+
+    /// Read `len` bytes at a logical offset of a file whose data must not contain holes
+    fn read_mapped(&self, extents: &[Extent], logical: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; len];
+        let mut done = 0;
+        while done < len {
+            let pos = logical + done as u64;
+            let extent = extents
+                .iter()
+                .find(|e| e.logical <= pos && pos < e.logical + e.length)
+                .ok_or_else(|| format_err!("unexpected hole at offset {pos}"))?;
+            let count = (len - done).min((extent.logical + extent.length - pos) as usize);
+            self.image.read_exact_at(
+                &mut buf[done..done + count],
+                extent.physical + pos - extent.logical,
+            )?;
+            done += count;
+        }
+        Ok(buf)
+    }
+
+    fn read_shortform_dir(&self, ino: u64, fork: &[u8]) -> Result<Vec<DirEntry>, Error> {
+        let corrupt = || format_err!("corrupt short form directory in inode {ino}");
+
+        let count = *fork.first().ok_or_else(corrupt)? as usize;
+        let ino_size = if *fork.get(1).ok_or_else(corrupt)? > 0 {
+            8
+        } else {
+            4
+        };
+        let ftype = usize::from(self.ftype);
+
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = 2 + ino_size;
+        for _ in 0..count {
+            let name_len = *fork.get(pos).ok_or_else(corrupt)? as usize;
+            let name_start = pos + 3;
+            let ino_start = name_start + name_len + ftype;
+            if ino_start + ino_size > fork.len() {
+                return Err(corrupt());
+            }
+            let entry_ino = if ino_size == 8 {
+                be64(fork, ino_start)
+            } else {
+                be32(fork, ino_start) as u64
+            };
+            entries.push(DirEntry {
+                name: fork[name_start..name_start + name_len].to_vec(),
+                ino: entry_ino,
+            });
+            pos = ino_start + ino_size;
+        }
+
+        Ok(entries)
+    }
+
+    fn read_dir_block(&self, ino: u64, block: &[u8], out: &mut Vec<DirEntry>) -> Result<(), Error> {
+        let corrupt = || format_err!("corrupt directory block in inode {ino}");
+
+        let (header, is_block) = match be32(block, 0) {
+            DIR_BLOCK_MAGIC if !self.crc => (16, true),
+            DIR_DATA_MAGIC if !self.crc => (16, false),
+            DIR_BLOCK_CRC_MAGIC if self.crc => (64, true),
+            DIR_DATA_CRC_MAGIC if self.crc => (64, false),
+            _ => return Err(corrupt()),
+        };
+
+        // single block directories have the leaf entries and a tail at the end of the block
+        let end = if is_block {
+            let leaf_count = be32(block, block.len() - 8) as usize;
+            block
+                .len()
+                .checked_sub(8 + leaf_count * 8)
+                .filter(|end| *end >= header)
+                .ok_or_else(corrupt)?
+        } else {
+            block.len()
+        };
+
+        let mut pos = header;
+        while pos + 8 <= end {
+            if be16(block, pos) == 0xffff {
+                let len = be16(block, pos + 2) as usize;
+                if len < 8 || pos + len > end {
+                    return Err(corrupt());
+                }
+                pos += len;
+                continue;
+            }
+
+            let name_len = block[pos + 8] as usize;
+            let entry_size = (9 + name_len + usize::from(self.ftype) + 2).next_multiple_of(8);
+            if pos + entry_size > end {
+                return Err(corrupt());
+            }
+            let name = &block[pos + 9..pos + 9 + name_len];
+            if name != b"." && name != b".." {
+                out.push(DirEntry {
+                    name: name.to_vec(),
+                    ino: be64(block, pos),
+                });
+            }
+            pos += entry_size;
+        }
+
+        Ok(())
+    }
+}
+
+impl FileSystem for Xfs {
+    fn image(&self) -> &dyn ImageRead {
+        &*self.image
+    }
+
+    fn root(&self) -> Result<Inode, Error> {
+        self.inode(self.root_ino)
+    }
+
+    fn inode(&self, ino: u64) -> Result<Inode, Error> {
+        let raw = self.raw_inode(ino)?;
+        let data = &raw.data;
+
+        let mode = be16(data, 2) as u32;
+        let kind = FileKind::from_mode(mode)?;
+
+        let (mtime, mtime_nsecs) = if raw.flags2 & DIFLAG2_BIGTIME != 0 {
+            let nsecs = be64(data, 40);
+            (
+                (nsecs / 1_000_000_000) as i64 - BIGTIME_EPOCH_OFFSET,
+                (nsecs % 1_000_000_000) as u32,
+            )
+        } else {
+            (be32(data, 40) as i32 as i64, be32(data, 44))
+        };
+
+        let device = if raw.format == FORMAT_DEV {
+            let dev = be32(raw.data_fork(), 0) as u64;
+            (dev >> 18, dev & 0x3ffff)
+        } else {
+            (0, 0)
+        };
+
+        Ok(Inode {
+            ino,
+            kind,
+            mode,
+            uid: be32(data, 8),
+            gid: be32(data, 12),
+            size: be64(data, 56),
+            mtime,
+            mtime_nsecs,
+            device,
+        })
+    }
+
+    fn read_dir(&self, dir: &Inode) -> Result<Vec<DirEntry>, Error> {
+        if dir.kind != FileKind::Directory {
+            bail!("inode {} is not a directory", dir.ino);
+        }
+        let raw = self.raw_inode(dir.ino)?;
+        if raw.format == FORMAT_LOCAL {
+            return self.read_shortform_dir(dir.ino, raw.data_fork());
+        }
+
+        let extents = self.data_extents(dir.ino, &raw)?;
+        let mut entries = Vec::new();
+        for extent in extents.iter() {
+            let end = (extent.logical + extent.length).min(DIR_LEAF_OFFSET);
+            let mut logical = extent.logical.next_multiple_of(self.dir_block_size);
+            while logical < end {
+                let block = self.read_mapped(&extents, logical, self.dir_block_size as usize)?;
+                self.read_dir_block(dir.ino, &block, &mut entries)?;
+                logical += self.dir_block_size;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn extents(&self, inode: &Inode) -> Result<Vec<Extent>, Error> {
+        let raw = self.raw_inode(inode.ino)?;
+        self.data_extents(inode.ino, &raw)
+    }
+
+    fn read_link(&self, inode: &Inode) -> Result<Vec<u8>, Error> {
+        if inode.kind != FileKind::Symlink {
+            bail!("inode {} is not a symlink", inode.ino);
+        }
+        let raw = self.raw_inode(inode.ino)?;
+        let size = inode.size as usize;
+
+        if raw.format == FORMAT_LOCAL {
+            let fork = raw.data_fork();
+            if size > fork.len() {
+                bail!("corrupt symlink inode {}", inode.ino);
+            }
+            return Ok(fork[..size].to_vec());
+        }
+
+        let mut target = Vec::with_capacity(size);
+        for extent in self.data_extents(inode.ino, &raw)? {
+            for offset in (0..extent.length).step_by(self.block_size as usize) {
+                let block = read_vec(
+                    &*self.image,
+                    extent.physical + offset,
+                    self.block_size as usize,
+                )?;
+                let data = if self.crc {
+                    let bytes = be32(&block, 8) as usize;
+                    if be32(&block, 0) != SYMLINK_CRC_MAGIC
+                        || SYMLINK_CRC_HEADER_SIZE + bytes > block.len()
+                    {
+                        bail!("corrupt symlink block in inode {}", inode.ino);
+                    }
+                    &block[SYMLINK_CRC_HEADER_SIZE..SYMLINK_CRC_HEADER_SIZE + bytes]
+                } else {
+                    &block[..block.len().min(size - target.len())]
+                };
+                target.extend_from_slice(data);
+                if target.len() >= size {
+                    target.truncate(size);
+                    return Ok(target);
+                }
+            }
+        }
+
+        bail!("symlink target of inode {} is truncated", inode.ino);
+    }
+}
@@ -30,7 +30,7 @@ use pbs_client::tools::{
     },
     REPO_URL_SCHEMA,
 };
-use pbs_client::{BackupReader, BackupRepository, RemoteChunkReader};
+use pbs_client::{ApiResponseError, BackupReader, BackupRepository, RemoteChunkReader};
 use pbs_datastore::catalog::{ArchiveEntry, CatalogReader, DirEntryAttribute};
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, LocalDynamicReadAt};
 use pbs_datastore::index::IndexFile;
@@ -44,6 +44,8 @@ pub use block_driver::*;
 pub mod cpio;
 
 mod block_driver_qemu;
+mod block_driver_userspace;
+mod image_fs;
 mod qemu_helper;
 
 enum ExtractPath {
@@ -169,6 +171,7 @@ async fn list_files(
                 namespace,
                 snapshot,
                 keyfile,
+                crypt_config,
             };
             data_list(driver, details, file, path).await
         }
@@ -290,8 +293,11 @@ async fn list(
             return Err(err);
         }
         let (msg, code) = match err.downcast_ref::<HttpError>() {
-            Some(HttpError { code, message }) => (message.clone(), Some(code)),
-            None => (err.to_string(), None),
+            Some(HttpError { code, message }) => (message.clone(), Some(*code)),
+            None => match err.downcast_ref::<ApiResponseError>() {
+                Some(err) => (err.message.clone(), Some(err.status)),
+                None => (err.to_string(), None),
+            },
         };
         let mut json_err = json!({
             "message": msg,
@@ -467,6 +473,7 @@ async fn extract(
                 namespace,
                 snapshot,
                 keyfile,
+                crypt_config,
             };
             let driver: Option<BlockDriverType> = match param.get("driver") {
                 Some(drv) => Some(serde::Deserialize::deserialize(drv)?),
@@ -489,9 +496,14 @@ async fn extract(
                 // we extracted a .pxarexclude-cli file auto-generated by the VM when encoding the
                 // archive, this file is of no use for the user, so try to remove it
                 target.push(".pxarexclude-cli");
-                std::fs::remove_file(target).map_err(|err| {
-                    format_err!("unable to remove temporary .pxarexclude-cli file - {err}")
-                })?;
+                match std::fs::remove_file(target) {
+                    Ok(()) => (),
+                    // the userspace driver does not generate one
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                    Err(err) => {
+                        bail!("unable to remove temporary .pxarexclude-cli file - {err}")
+                    }
+                }
             } else {
                 let mut reader =
                     data_extract(driver, details, file, path.clone(), format, zstd).await?;
@@ -353,6 +353,7 @@ fn extract(
                         patterns,
                         skip_lost_and_found: false,
                         skip_e2big_xattr: false,
+                        exclude_log: None,
                     };
 
                     let pxar_writer = TokioWriter::new(writer);
@@ -214,6 +214,7 @@ fn extract_archive(
         overwrite_flags,
         extract_match_default,
         on_error,
+        journal: None,
     };
 
     if archive == "-" {
@@ -336,6 +337,7 @@ async fn create_archive(
         patterns,
         skip_lost_and_found: false,
         skip_e2big_xattr: false,
+        exclude_log: None,
     };
 
     let source = PathBuf::from(source);
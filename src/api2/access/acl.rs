@@ -2,19 +2,25 @@
 
 use anyhow::{bail, Error};
 use hex::FromHex;
+use serde_json::json;
 
-use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_router::{Permission, Router, RpcEnvironment, SubdirMap};
 use proxmox_schema::api;
+use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    AclListItem, Authid, Role, ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA, PRIV_PERMISSIONS_MODIFY,
-    PRIV_SYS_AUDIT, PROXMOX_CONFIG_DIGEST_SCHEMA, PROXMOX_GROUP_ID_SCHEMA,
+    AclListItem, Authid, EffectivePermissions, ACL_PATH_SCHEMA, ACL_PROPAGATE_SCHEMA,
+    PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT, PROXMOX_CONFIG_DIGEST_SCHEMA, PROXMOX_GROUP_ID_SCHEMA,
+    ROLE_ID_SCHEMA,
 };
 
 use pbs_config::acl::AclTreeNode;
 
 use pbs_config::CachedUserInfo;
 
+use crate::api2::helpers::api_bail;
+use crate::server::audit_log::audit_config_change;
+
 fn extract_acl_node_data(
     node: &AclTreeNode,
     path: &str,
@@ -146,7 +152,7 @@ pub fn read_acl(
                 schema: ACL_PATH_SCHEMA,
             },
 	    role: {
-                type: Role,
+                schema: ROLE_ID_SCHEMA,
             },
             propagate: {
                 optional: true,
@@ -195,21 +201,36 @@ pub fn update_acl(
     let top_level_privs = user_info.lookup_privs(&current_auth_id, &["access", "acl"]);
     if top_level_privs & PRIV_PERMISSIONS_MODIFY == 0 {
         if group.is_some() {
-            bail!("Unprivileged users are not allowed to create group ACL item.");
+            api_bail!(
+                PermissionDenied,
+                "Unprivileged users are not allowed to create group ACL item."
+            );
         }
 
         match &auth_id {
             Some(auth_id) => {
                 if current_auth_id.is_token() {
-                    bail!("Unprivileged API tokens can't set ACL items.");
+                    api_bail!(
+                        PermissionDenied,
+                        "Unprivileged API tokens can't set ACL items."
+                    );
                 } else if !auth_id.is_token() {
-                    bail!("Unprivileged users can only set ACL items for API tokens.");
+                    api_bail!(
+                        PermissionDenied,
+                        "Unprivileged users can only set ACL items for API tokens."
+                    );
                 } else if auth_id.user() != current_auth_id.user() {
-                    bail!("Unprivileged users can only set ACL items for their own API tokens.");
+                    api_bail!(
+                        PermissionDenied,
+                        "Unprivileged users can only set ACL items for their own API tokens."
+                    );
                 }
             }
             None => {
-                bail!("Unprivileged user needs to provide auth_id to update ACL item.");
+                api_bail!(
+                    PermissionDenied,
+                    "Unprivileged user needs to provide auth_id to update ACL item."
+                );
             }
         };
     }
@@ -234,14 +255,15 @@ pub fn update_acl(
             // Note: we allow to delete non-existent users
             let user_cfg = pbs_config::user::cached_config()?;
             if user_cfg.sections.get(&auth_id.to_string()).is_none() {
-                bail!(format!(
+                api_bail!(
+                    NotFound,
                     "no such {}.",
                     if auth_id.is_token() {
                         "API token"
                     } else {
                         "user"
                     }
-                ));
+                );
             }
         }
     } else {
@@ -251,8 +273,27 @@ pub fn update_acl(
     if !delete {
         // Note: we allow to delete entries with invalid path
         pbs_config::acl::check_acl_path(&path)?;
+
+        // Note: we allow to delete entries referencing unknown roles
+        let (roles, _) = pbs_config::roles::config()?;
+        if !pbs_config::acl::ROLE_NAMES.contains_key(role.as_str())
+            && !roles.sections.contains_key(&role)
+        {
+            api_bail!(NotFound, "no such role '{}'.", role);
+        }
     }
 
+    let ugid = auth_id
+        .as_ref()
+        .map(|auth_id| auth_id.to_string())
+        .or_else(|| group.clone());
+    let audit_entry = json!({
+        "path": path,
+        "ugid": ugid,
+        "roleid": role,
+        "propagate": propagate,
+    });
+
     if let Some(auth_id) = auth_id {
         if delete {
             tree.delete_user_role(&path, &auth_id, &role);
@@ -269,9 +310,103 @@ pub fn update_acl(
 
     pbs_config::acl::save_config(&tree)?;
 
+    let (before, after) = if delete {
+        (Some(audit_entry), None)
+    } else {
+        (None, Some(audit_entry))
+    };
+    audit_config_change(
+        &current_auth_id,
+        "/access/acl",
+        before,
+        after,
+        pbs_config::acl::ACL_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            path: {
+                schema: ACL_PATH_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Effective permissions of all users and API tokens with privileges on the path.",
+        type: Array,
+        items: {
+            type: EffectivePermissions,
+        }
+    },
+    access: {
+        permission: &Permission::Privilege(&["access"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Show which users and API tokens can do what on an ACL path.
+///
+/// Disabled or expired users and tokens, and those without any privileges on the path, are
+/// omitted.
+pub fn effective_permissions(path: String) -> Result<Vec<EffectivePermissions>, Error> {
+    let user_info = CachedUserInfo::new()?;
+    let (user_cfg, _digest) = pbs_config::user::config()?;
+
+    let split_path = pbs_config::acl::split_acl_path(path.as_str());
+    let path = format!("/{}", split_path.join("/"));
+
+    let mut auth_ids: Vec<Authid> = user_cfg
+        .sections
+        .keys()
+        .filter_map(|id| id.parse().ok())
+        .collect();
+    let root = Authid::root_auth_id();
+    if !auth_ids.contains(root) {
+        auth_ids.push(root.clone());
+    }
+    auth_ids.sort_by_key(|auth_id| auth_id.to_string());
+
+    let mut list = Vec::new();
+    for auth_id in auth_ids {
+        if !user_info.is_active_auth_id(&auth_id) {
+            continue;
+        }
+
+        let privs = user_info.lookup_privs(&auth_id, &split_path);
+        if privs == 0 {
+            continue;
+        }
+
+        let superuser = user_info.is_superuser(&auth_id);
+        let mut entry = EffectivePermissions::from_privs(auth_id, superuser, privs);
+
+        if !superuser {
+            // for tokens, the privileges are limited by the owning user, but only the token's
+            // own ACLs can grant them
+            if let Some((origin, _)) = user_info
+                .lookup_role_origins(&entry.auth_id, &split_path)
+                .pop()
+            {
+                entry.inherited = origin != path;
+                entry.origin = Some(origin);
+            }
+        }
+
+        list.push(entry);
+    }
+
+    Ok(list)
+}
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([(
+    "effective",
+    &Router::new().get(&API_METHOD_EFFECTIVE_PERMISSIONS)
+)]);
+
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_READ_ACL)
-    .put(&API_METHOD_UPDATE_ACL);
+    .put(&API_METHOD_UPDATE_ACL)
+    .subdirs(SUBDIRS);
@@ -44,6 +44,10 @@ fn list_domains(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<BasicRealmInfo>,
     let (config, digest) = pbs_config::domains::config()?;
 
     for (_, (section_type, v)) in config.sections.iter() {
+        // the built-in realms are already listed
+        if section_type == "pam" || section_type == "pbs" {
+            continue;
+        }
         let mut entry = v.clone();
         entry["type"] = Value::from(section_type.clone());
         list.push(serde_json::from_value(entry)?);
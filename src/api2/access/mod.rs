@@ -2,17 +2,20 @@
 
 use anyhow::{bail, format_err, Error};
 
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-use proxmox_router::{list_subdirs_api_method, Permission, Router, RpcEnvironment, SubdirMap};
+use proxmox_router::{
+    list_subdirs_api_method, ApiFuture, ApiHandler, ApiMethod, Permission, Router, RpcEnvironment,
+    SubdirMap,
+};
 use proxmox_schema::api;
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, Userid, ACL_PATH_SCHEMA, PASSWORD_SCHEMA, PRIVILEGES, PRIV_PERMISSIONS_MODIFY,
-    PRIV_SYS_AUDIT,
+    privs_to_priv_names, Authid, PermissionDetails, PermissionRoleOrigin, Userid, ACL_PATH_SCHEMA,
+    PASSWORD_SCHEMA, PRIVILEGES, PRIV_PERMISSIONS_MODIFY, PRIV_SYS_AUDIT,
 };
 use pbs_config::acl::AclTreeNode;
 use pbs_config::CachedUserInfo;
@@ -85,6 +88,32 @@ pub fn change_password(
     Ok(Value::Null)
 }
 
+/// Returns the auth id whose permissions `current_auth_id` is allowed to query.
+///
+/// Users may query their own tokens, anything else requires Sys.Audit on '/access'.
+fn permission_auth_id(
+    user_info: &CachedUserInfo,
+    current_auth_id: Authid,
+    auth_id: Option<Authid>,
+) -> Result<Authid, Error> {
+    match auth_id {
+        Some(auth_id) if auth_id == current_auth_id => Ok(current_auth_id),
+        Some(auth_id) => {
+            let user_privs = user_info.lookup_privs(&current_auth_id, &["access"]);
+            if user_privs & PRIV_SYS_AUDIT != 0
+                || (auth_id.is_token()
+                    && !current_auth_id.is_token()
+                    && auth_id.user() == current_auth_id.user())
+            {
+                Ok(auth_id)
+            } else {
+                bail!("not allowed to list permissions of {}", auth_id);
+            }
+        }
+        None => Ok(current_auth_id),
+    }
+}
+
 #[api(
     input: {
         properties: {
@@ -120,23 +149,7 @@ pub fn list_permissions(
     let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     let user_info = CachedUserInfo::new()?;
-    let user_privs = user_info.lookup_privs(&current_auth_id, &["access"]);
-
-    let auth_id = match auth_id {
-        Some(auth_id) if auth_id == current_auth_id => current_auth_id,
-        Some(auth_id) => {
-            if user_privs & PRIV_SYS_AUDIT != 0
-                || (auth_id.is_token()
-                    && !current_auth_id.is_token()
-                    && auth_id.user() == current_auth_id.user())
-            {
-                auth_id
-            } else {
-                bail!("not allowed to list permissions of {}", auth_id);
-            }
-        }
-        None => current_auth_id,
-    };
+    let auth_id = permission_auth_id(&user_info, current_auth_id, auth_id)?;
 
     fn populate_acl_paths(
         mut paths: HashSet<String>,
@@ -204,17 +217,170 @@ pub fn list_permissions(
     Ok(map)
 }
 
+#[api(
+    input: {
+        properties: {
+            "auth-id": {
+                type: Authid,
+                optional: true,
+            },
+            path: {
+                schema: ACL_PATH_SCHEMA,
+            },
+            chain: {
+                type: bool,
+                optional: true,
+                default: false,
+                description: "Also include roles which are overridden by more specific ACLs.",
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Sys.Audit on '/access', limited to own privileges otherwise.",
+    },
+    returns: {
+        type: PermissionDetails,
+    },
+)]
+/// Show the privileges of the given or currently authenticated user / API token on a path,
+/// together with the role assignments they result from.
+pub fn permission_details(
+    auth_id: Option<Authid>,
+    path: String,
+    chain: bool,
+    rpcenv: &dyn RpcEnvironment,
+) -> Result<PermissionDetails, Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let user_info = CachedUserInfo::new()?;
+    let auth_id = permission_auth_id(&user_info, current_auth_id, auth_id)?;
+
+    let split_path = pbs_config::acl::split_acl_path(path.as_str());
+    let (privs, propagated_privs) = user_info.lookup_privs_details(&auth_id, &split_path);
+    let path = format!("/{}", split_path.join("/"));
+
+    let mut roles = Vec::new();
+
+    let mut add_origins = |origin_auth_id: &Authid| {
+        let origins = user_info.lookup_role_origins(origin_auth_id, &split_path);
+        let count = origins.len();
+        for (pos, (origin_path, origin_roles)) in origins.into_iter().enumerate() {
+            let effective = pos + 1 == count;
+            if !effective && !chain {
+                continue;
+            }
+            let inherited = origin_path != path;
+            let mut origin_roles: Vec<_> = origin_roles.into_iter().collect();
+            origin_roles.sort();
+            for (roleid, propagate) in origin_roles {
+                roles.push(PermissionRoleOrigin {
+                    path: origin_path.clone(),
+                    auth_id: origin_auth_id.clone(),
+                    roleid,
+                    propagate,
+                    inherited,
+                    effective,
+                });
+            }
+        }
+    };
+
+    add_origins(&auth_id);
+    if auth_id.is_token() {
+        // token privileges are limited to the ones of the owning user
+        add_origins(&Authid::from(auth_id.user().clone()));
+    }
+
+    let priv_names = |privs: u64| -> Vec<String> {
+        privs_to_priv_names(privs)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    };
+
+    Ok(PermissionDetails {
+        superuser: user_info.is_superuser(&auth_id),
+        path,
+        auth_id,
+        privs: priv_names(privs),
+        propagated_privs: priv_names(propagated_privs),
+        roles,
+    })
+}
+
+#[sortable]
+const PERMISSIONS_SUBDIRS: SubdirMap = &sorted!([(
+    "details",
+    &Router::new().get(&API_METHOD_PERMISSION_DETAILS)
+)]);
+
+/// Ticket creation, additionally enforcing the two-factor authentication requirement of the
+/// realm or node configuration.
+pub const API_METHOD_CREATE_TICKET: ApiMethod = ApiMethod {
+    handler: &ApiHandler::Async(&create_ticket),
+    ..proxmox_auth_api::api::API_METHOD_CREATE_TICKET
+};
+
+fn create_ticket<'a>(
+    param: Value,
+    info: &'static ApiMethod,
+    rpcenv: &'a mut dyn RpcEnvironment,
+) -> ApiFuture<'a> {
+    Box::pin(async move {
+        let username = param["username"].as_str().map(String::from);
+        // checking path based (terminal) tickets is no login
+        let is_login = param["path"].is_null();
+
+        let result = match proxmox_auth_api::api::API_METHOD_CREATE_TICKET.handler {
+            ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+            _ => unreachable!(),
+        };
+
+        let ticket = result["ticket"].as_str().unwrap_or_default();
+        // users with a second factor get a partial ticket and the TFA challenge
+        if !is_login || ticket.starts_with("PBS:!tfa!") {
+            return Ok(result);
+        }
+
+        let userid: Userid = match username {
+            Some(username) => username.parse()?,
+            None => return Ok(result),
+        };
+
+        if !crate::auth::tfa_setup_required(&userid)? {
+            return Ok(result);
+        }
+
+        let tfa_setup_ticket =
+            crate::auth::assemble_tfa_setup_ticket(crate::auth::private_auth_keyring(), &userid)?;
+
+        Ok(json!({
+            "username": userid,
+            "tfa-setup-ticket": tfa_setup_ticket,
+            "message": format!(
+                "two-factor authentication is required for realm '{}', please add a second \
+                factor (for example TOTP or recovery keys) using the TFA setup ticket",
+                userid.realm(),
+            ),
+        }))
+    })
+}
+
 #[sortable]
 const SUBDIRS: SubdirMap = &sorted!([
     ("acl", &acl::ROUTER),
     ("password", &Router::new().put(&API_METHOD_CHANGE_PASSWORD)),
     (
         "permissions",
-        &Router::new().get(&API_METHOD_LIST_PERMISSIONS)
+        &Router::new()
+            .get(&API_METHOD_LIST_PERMISSIONS)
+            .subdirs(PERMISSIONS_SUBDIRS)
     ),
+    ("ticket", &Router::new().post(&API_METHOD_CREATE_TICKET)),
     (
-        "ticket",
-        &Router::new().post(&proxmox_auth_api::api::API_METHOD_CREATE_TICKET)
+        "tfa-setup",
+        &Router::new().post(&tfa::API_METHOD_ADD_TFA_ENTRY_WITH_SETUP_TICKET)
     ),
     ("openid", &openid::ROUTER),
     ("domains", &domain::ROUTER),
@@ -1,59 +1,372 @@
 //! Manage Roles with privileges
 
+use ::serde::{Deserialize, Serialize};
 use anyhow::Error;
+use hex::FromHex;
+use serde_json::json;
 
-use serde_json::{json, Value};
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::{api, param_bail};
 
-use proxmox_router::{Permission, Router};
-use proxmox_schema::api;
-
-use pbs_api_types::{Role, PRIVILEGES, SINGLE_LINE_COMMENT_SCHEMA};
+use pbs_api_types::{
+    priv_names_to_privs, privs_to_priv_names, Authid, CustomRole, CustomRoleUpdater, RoleListItem,
+    PRIV_PERMISSIONS_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, ROLE_ID_SCHEMA,
+};
 use pbs_config::acl::ROLE_NAMES;
 
+use crate::api2::helpers::api_bail;
+use crate::server::audit_log::audit_config_change;
+
+/// Returns the privilege names of `privs`, sorted and without duplicates.
+fn normalize_privs(privs: &[String]) -> Result<Vec<String>, Error> {
+    let privs = priv_names_to_privs(privs)?;
+    Ok(privs_to_priv_names(privs)
+        .into_iter()
+        .map(String::from)
+        .collect())
+}
+
 #[api(
     returns: {
-        description: "List of roles.",
+        description: "List of built-in and custom roles.",
         type: Array,
-        items: {
-            type: Object,
-            description: "Role with description and privileges.",
-            properties: {
-                roleid: {
-                    type: Role,
-                },
-                privs: {
-                    type: Array,
-                    description: "List of Privileges",
-                    items: {
-                        type: String,
-                        description: "A Privilege",
-                    },
-                },
-                comment: {
-                    schema: SINGLE_LINE_COMMENT_SCHEMA,
-                    optional: true,
-                },
-            },
-        }
+        items: { type: RoleListItem },
     },
     access: {
         permission: &Permission::Anybody,
     }
 )]
 /// Role list
-fn list_roles() -> Result<Value, Error> {
+fn list_roles(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<RoleListItem>, Error> {
     let mut list = Vec::new();
 
     for (role, (privs, comment)) in ROLE_NAMES.iter() {
-        let mut priv_list = Vec::new();
-        for (name, privilege) in PRIVILEGES.iter() {
-            if privs & privilege > 0 {
-                priv_list.push(name);
+        list.push(RoleListItem {
+            roleid: role.to_string(),
+            comment: Some(comment.to_string()),
+            privs: privs_to_priv_names(*privs)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            builtin: true,
+        });
+    }
+
+    let (config, digest) = pbs_config::roles::config()?;
+    let custom: Vec<CustomRole> = config.convert_to_typed_array("role")?;
+
+    for role in custom {
+        list.push(RoleListItem {
+            roleid: role.roleid,
+            comment: role.comment,
+            privs: role.privs,
+            builtin: false,
+        });
+    }
+
+    list.sort_by(|a, b| a.roleid.cmp(&b.roleid));
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(list)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            config: {
+                type: CustomRole,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Create a new custom role.
+pub fn create_role(mut config: CustomRole, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let _lock = pbs_config::roles::lock_config()?;
+
+    let (mut section_config, digest) = pbs_config::roles::config()?;
+
+    if ROLE_NAMES.contains_key(config.roleid.as_str()) {
+        param_bail!("roleid", "'{}' is a built-in role.", config.roleid);
+    }
+
+    if section_config.sections.get(&config.roleid).is_some() {
+        param_bail!("roleid", "role '{}' already exists.", config.roleid);
+    }
+
+    config.privs = normalize_privs(&config.privs)?;
+
+    section_config.set_data(&config.roleid, "role", &config)?;
+
+    pbs_config::roles::save_config(&section_config)?;
+
+    audit_config_change(
+        &auth_id,
+        &format!("/access/roles/{}", config.roleid),
+        None,
+        Some(serde_json::to_value(&config)?),
+        pbs_config::roles::ROLES_CFG_FILENAME,
+        Some(&digest),
+    );
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            roleid: {
+                schema: ROLE_ID_SCHEMA,
+            },
+        },
+    },
+    returns: { type: RoleListItem },
+    access: {
+        permission: &Permission::Anybody,
+    },
+)]
+/// Read a built-in or custom role.
+pub fn read_role(roleid: String, rpcenv: &mut dyn RpcEnvironment) -> Result<RoleListItem, Error> {
+    if let Some((privs, comment)) = ROLE_NAMES.get(roleid.as_str()) {
+        return Ok(RoleListItem {
+            roleid,
+            comment: Some(comment.to_string()),
+            privs: privs_to_priv_names(*privs)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            builtin: true,
+        });
+    }
+
+    let (config, digest) = pbs_config::roles::config()?;
+    let role: CustomRole = match config.lookup("role", &roleid) {
+        Ok(role) => role,
+        Err(_) => api_bail!(NotFound, "no such role '{}'.", roleid),
+    };
+
+    rpcenv["digest"] = hex::encode(digest).into();
+
+    Ok(RoleListItem {
+        roleid: role.roleid,
+        comment: role.comment,
+        privs: role.privs,
+        builtin: false,
+    })
+}
+
+#[api()]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// Deletable property name
+pub enum DeletableProperty {
+    /// Delete the comment property.
+    Comment,
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            roleid: {
+                schema: ROLE_ID_SCHEMA,
+            },
+            update: {
+                type: CustomRoleUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Update a custom role.
+pub fn update_role(
+    roleid: String,
+    update: CustomRoleUpdater,
+    delete: Option<Vec<DeletableProperty>>,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    if ROLE_NAMES.contains_key(roleid.as_str()) {
+        param_bail!("roleid", "built-in role '{}' cannot be modified.", roleid);
+    }
+
+    let _lock = pbs_config::roles::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::roles::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let mut data: CustomRole = match config.lookup("role", &roleid) {
+        Ok(role) => role,
+        Err(_) => api_bail!(NotFound, "no such role '{}'.", roleid),
+    };
+    let audit_before = serde_json::to_value(&data)?;
+
+    if let Some(delete) = delete {
+        for delete_prop in delete {
+            match delete_prop {
+                DeletableProperty::Comment => {
+                    data.comment = None;
+                }
             }
         }
-        list.push(json!({ "roleid": role, "privs": priv_list, "comment": comment }));
     }
-    Ok(list.into())
+
+    if let Some(comment) = update.comment {
+        let comment = comment.trim().to_string();
+        if comment.is_empty() {
+            data.comment = None;
+        } else {
+            data.comment = Some(comment);
+        }
+    }
+
+    if let Some(privs) = update.privs {
+        data.privs = normalize_privs(&privs)?;
+    }
+
+    config.set_data(&roleid, "role", &data)?;
+
+    pbs_config::roles::save_config(&config)?;
+
+    audit_config_change(
+        &auth_id,
+        &format!("/access/roles/{roleid}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::roles::ROLES_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            roleid: {
+                schema: ROLE_ID_SCHEMA,
+            },
+            force: {
+                description: "Also remove all ACL entries referencing the role.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["access", "acl"], PRIV_PERMISSIONS_MODIFY, false),
+    },
+)]
+/// Remove a custom role.
+///
+/// Roles which are still referenced by ACL entries can only be removed with `force`, which purges
+/// those entries as well.
+pub fn delete_role(
+    roleid: String,
+    force: bool,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    if ROLE_NAMES.contains_key(roleid.as_str()) {
+        param_bail!("roleid", "built-in role '{}' cannot be removed.", roleid);
+    }
+
+    // lock order: ACLs first, so that no new references can be added in between
+    let _acl_lock = pbs_config::acl::lock_config()?;
+    let _lock = pbs_config::roles::lock_config()?;
+
+    let (mut config, expected_digest) = pbs_config::roles::config()?;
+
+    if let Some(ref digest) = digest {
+        let digest = <[u8; 32]>::from_hex(digest)?;
+        crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
+    }
+
+    let audit_before = match config.sections.get(&roleid) {
+        Some((_, data)) => data.clone(),
+        None => api_bail!(NotFound, "no such role '{}'.", roleid),
+    };
+
+    let (mut tree, acl_digest) = pbs_config::acl::config()?;
+    if tree.role_in_use(&roleid) {
+        if !force {
+            param_bail!(
+                "roleid",
+                "role '{}' is still used in ACL entries (use 'force' to remove them as well).",
+                roleid
+            );
+        }
+        tree.delete_role(&roleid);
+        pbs_config::acl::save_config(&tree)?;
+
+        audit_config_change(
+            &auth_id,
+            "/access/acl",
+            Some(json!({ "roleid": roleid })),
+            None,
+            pbs_config::acl::ACL_CFG_FILENAME,
+            Some(&acl_digest),
+        );
+    }
+
+    config.sections.remove(&roleid);
+
+    pbs_config::roles::save_config(&config)?;
+
+    audit_config_change(
+        &auth_id,
+        &format!("/access/roles/{roleid}"),
+        Some(audit_before),
+        None,
+        pbs_config::roles::ROLES_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
+    Ok(())
 }
 
-pub const ROUTER: Router = Router::new().get(&API_METHOD_LIST_ROLES);
+const ITEM_ROUTER: Router = Router::new()
+    .get(&API_METHOD_READ_ROLE)
+    .put(&API_METHOD_UPDATE_ROLE)
+    .delete(&API_METHOD_DELETE_ROLE);
+
+pub const ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_ROLES)
+    .post(&API_METHOD_CREATE_ROLE)
+    .match_all("roleid", &ITEM_ROUTER);
@@ -245,6 +245,88 @@ async fn add_tfa_entry(
     Ok(out)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            "tfa-setup-ticket": {
+                description: "The limited ticket issued at login to users who are required to \
+                    use two-factor authentication, but have no second factor yet.",
+                type: String,
+            },
+            description: {
+                description: "A description to distinguish multiple entries from one another",
+                type: String,
+                max_length: 255,
+                optional: true,
+            },
+            "type": { type: methods::TfaType },
+            totp: {
+                description: "A totp URI.",
+                optional: true,
+            },
+            value: {
+                description:
+            "The current value for the provided totp URI, or a Webauthn/U2F challenge response",
+                optional: true,
+            },
+            challenge: {
+                description: "When responding to a u2f challenge: the original challenge string",
+                optional: true,
+            },
+        },
+    },
+    returns: { type: methods::TfaUpdateInfo },
+    access: {
+        description: "Anybody holding a valid TFA setup ticket, for the user it was issued to.",
+        permission: &Permission::World,
+    },
+)]
+/// Add the first TFA entry of a user required to use two-factor authentication.
+pub async fn add_tfa_entry_with_setup_ticket(
+    tfa_setup_ticket: String,
+    description: Option<String>,
+    totp: Option<String>,
+    value: Option<String>,
+    challenge: Option<String>,
+    r#type: methods::TfaType,
+) -> Result<methods::TfaUpdateInfo, Error> {
+    let userid =
+        crate::auth::verify_tfa_setup_ticket(crate::auth::public_auth_keyring(), &tfa_setup_ticket)
+            .map_err(|err| http_err!(UNAUTHORIZED, "invalid TFA setup ticket - {}", err))?;
+
+    if !CachedUserInfo::new()?.is_active_auth_id(&Authid::from(userid.clone())) {
+        http_bail!(UNAUTHORIZED, "user '{}' is disabled or expired", userid);
+    }
+
+    let _lock = crate::config::tfa::write_lock()?;
+
+    let mut data = crate::config::tfa::read()?;
+
+    // once a second factor exists, further changes require a full login
+    if crate::auth::user_has_tfa(&data, &userid) {
+        http_bail!(
+            UNAUTHORIZED,
+            "user '{}' already has a second factor, please log in again",
+            userid
+        );
+    }
+
+    let out = methods::add_tfa_entry(
+        &mut data,
+        &UserAccess,
+        userid.as_str(),
+        description,
+        totp,
+        value,
+        challenge,
+        r#type,
+        None,
+    )?;
+    crate::config::tfa::write(&data)?;
+    Ok(out)
+}
+
 #[api(
     protected: true,
     input: {
@@ -19,6 +19,8 @@ use pbs_config::token_shadow;
 
 use pbs_config::CachedUserInfo;
 
+use crate::server::audit_log::audit_config_change;
+
 fn new_user_with_tokens(user: User, tfa: &TfaConfig) -> UserWithTokens {
     UserWithTokens {
         totp_locked: tfa
@@ -143,9 +145,11 @@ pub fn create_user(
     config: User,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
 
-    let (mut section_config, _digest) = pbs_config::user::config()?;
+    let (mut section_config, digest) = pbs_config::user::config()?;
 
     if section_config
         .sections
@@ -164,16 +168,27 @@ pub fn create_user(
 
     pbs_config::user::save_config(&section_config)?;
 
+    let mut audit_after = serde_json::to_value(&config)?;
+
     if let Some(password) = password {
         let user_info = CachedUserInfo::new()?;
-        let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
         if realm == "pam" && !user_info.is_superuser(&current_auth_id) {
             bail!("only superuser can edit pam credentials!");
         }
         let client_ip = rpcenv.get_client_ip().map(|sa| sa.ip());
         authenticator.store_password(config.userid.name(), &password, client_ip.as_ref())?;
+        audit_after["password"] = "set".into();
     }
 
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{}", config.userid),
+        None,
+        Some(audit_after),
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&digest),
+    );
+
     Ok(())
 }
 
@@ -261,6 +276,8 @@ pub fn update_user(
     digest: Option<String>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
 
     let (mut config, expected_digest) = pbs_config::user::config()?;
@@ -271,6 +288,8 @@ pub fn update_user(
     }
 
     let mut data: User = config.lookup("user", userid.as_str())?;
+    let audit_before = serde_json::to_value(&data)?;
+    let password_changed = password.is_some();
 
     if let Some(delete) = delete {
         for delete_prop in delete {
@@ -302,7 +321,6 @@ pub fn update_user(
 
     if let Some(password) = password {
         let user_info = CachedUserInfo::new()?;
-        let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
         let self_service = current_auth_id.user() == &userid;
         let target_realm = userid.realm();
         if !self_service && target_realm == "pam" && !user_info.is_superuser(&current_auth_id) {
@@ -336,6 +354,19 @@ pub fn update_user(
 
     pbs_config::user::save_config(&config)?;
 
+    let mut audit_after = serde_json::to_value(&data)?;
+    if password_changed {
+        audit_after["password"] = "changed".into();
+    }
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{userid}"),
+        Some(audit_before),
+        Some(audit_after),
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     Ok(())
 }
 
@@ -360,7 +391,13 @@ pub fn update_user(
     },
 )]
 /// Remove a user from the configuration file.
-pub fn delete_user(userid: Userid, digest: Option<String>) -> Result<(), Error> {
+pub fn delete_user(
+    userid: Userid,
+    digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
     let _tfa_lock = crate::config::tfa::write_lock()?;
 
@@ -371,15 +408,22 @@ pub fn delete_user(userid: Userid, digest: Option<String>) -> Result<(), Error>
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    match config.sections.get(userid.as_str()) {
-        Some(_) => {
-            config.sections.remove(userid.as_str());
-        }
+    let audit_before = match config.sections.remove(userid.as_str()) {
+        Some((_, data)) => data,
         None => bail!("user '{}' does not exist.", userid),
-    }
+    };
 
     pbs_config::user::save_config(&config)?;
 
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{userid}"),
+        Some(audit_before),
+        None,
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     let authenticator = crate::auth::lookup_authenticator(userid.realm())?;
     match authenticator.remove_password(userid.name()) {
         Ok(()) => {}
@@ -498,7 +542,10 @@ pub fn generate_token(
     enable: Option<bool>,
     expire: Option<i64>,
     digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
 
     let (mut config, expected_digest) = pbs_config::user::config()?;
@@ -533,6 +580,15 @@ pub fn generate_token(
 
     pbs_config::user::save_config(&config)?;
 
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{userid}/token/{}", token_name.as_str()),
+        None,
+        Some(serde_json::to_value(&token)?),
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     Ok(json!({
         "tokenid": tokenid_string,
         "value": secret
@@ -582,7 +638,10 @@ pub fn update_token(
     enable: Option<bool>,
     expire: Option<i64>,
     digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
 
     let (mut config, expected_digest) = pbs_config::user::config()?;
@@ -592,10 +651,11 @@ pub fn update_token(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    let tokenid = Authid::from((userid, Some(token_name)));
+    let tokenid = Authid::from((userid.clone(), Some(token_name.clone())));
     let tokenid_string = tokenid.to_string();
 
     let mut data: ApiToken = config.lookup("token", &tokenid_string)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     if let Some(comment) = comment {
         let comment = comment.trim().to_string();
@@ -618,6 +678,15 @@ pub fn update_token(
 
     pbs_config::user::save_config(&config)?;
 
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{userid}/token/{}", token_name.as_str()),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     Ok(())
 }
 
@@ -649,7 +718,10 @@ pub fn delete_token(
     userid: Userid,
     token_name: Tokenname,
     digest: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let current_auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::user::lock_config()?;
 
     let (mut config, expected_digest) = pbs_config::user::config()?;
@@ -662,21 +734,28 @@ pub fn delete_token(
     let tokenid = Authid::from((userid.clone(), Some(token_name.clone())));
     let tokenid_string = tokenid.to_string();
 
-    match config.sections.get(&tokenid_string) {
-        Some(_) => {
-            config.sections.remove(&tokenid_string);
-        }
+    let audit_before = match config.sections.remove(&tokenid_string) {
+        Some((_, data)) => data,
         None => bail!(
             "token '{}' of user '{}' does not exist.",
             token_name.as_str(),
             userid
         ),
-    }
+    };
 
     token_shadow::delete_secret(&tokenid)?;
 
     pbs_config::user::save_config(&config)?;
 
+    audit_config_change(
+        &current_auth_id,
+        &format!("/access/users/{userid}/token/{}", token_name.as_str()),
+        Some(audit_before),
+        None,
+        pbs_config::user::USER_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     Ok(())
 }
 
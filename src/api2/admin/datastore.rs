@@ -1,10 +1,11 @@
 //! Datastore Management
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsStr;
 use std::os::unix::ffi::OsStrExt;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, format_err, Error};
 use futures::*;
@@ -12,46 +13,54 @@ use hyper::http::request::Parts;
 use hyper::{header, Body, Response, StatusCode};
 use serde::Deserialize;
 use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_stream::wrappers::ReceiverStream;
 
-use proxmox_async::blocking::WrappedReaderStream;
 use proxmox_async::{io::AsyncChannelWriter, stream::AsyncReaderStream};
 use proxmox_compression::zstd::ZstdEncoder;
+use proxmox_human_byte::HumanByte;
 use proxmox_router::{
-    http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture, Permission,
-    Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+    http_bail, http_err, list_subdirs_api_method, ApiHandler, ApiMethod, ApiResponseFuture,
+    Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
 };
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
-use proxmox_sys::fs::{
-    file_read_firstline, file_read_optional_string, replace_file, CreateOptions,
-};
-use proxmox_sys::{task_log, task_warn};
+use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
+use proxmox_time::CalendarEvent;
 
 use pxar::accessor::aio::Accessor;
 use pxar::EntryKind;
 
 use pbs_api_types::{
-    print_ns_and_snapshot, print_store_and_ns, Authid, BackupContent, BackupNamespace, BackupType,
-    Counts, CryptMode, DataStoreListItem, DataStoreStatus, GarbageCollectionStatus, GroupListItem,
-    KeepOptions, Operation, PruneJobOptions, RRDMode, RRDTimeFrame, SnapshotListItem,
-    SnapshotVerifyState, BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA,
-    BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA, DATASTORE_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA,
-    MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY,
-    UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    print_ns_and_snapshot, print_store_and_ns, ActiveOperationCounts, Authid, BackupContent,
+    BackupNamespace, BackupType, Counts, CryptMode, DataStoreConfig, DataStoreHealth,
+    DataStoreListItem, DataStoreStatus, DataStoreUsageEstimate, GarbageCollectionStatus,
+    GroupDedupStatsResult, GroupFilter, GroupListItem, GroupOwnerChangeResult, KeepOptions,
+    MaintenanceMode, MaintenanceType, NamespaceCounts, Operation, OwnerReportResult,
+    ProtectionBulkResult, PruneJobConfig, PruneJobOptions, PruneListItem, RRDMode, RRDTimeFrame,
+    ScrubMetaEntry, SnapshotListItem, SnapshotListRecord, SnapshotUploadInfo, SnapshotVerifyState,
+    TrashItem, VerifyBadChunkReference, VerifyTaskStatus, BACKUP_ARCHIVE_NAME_SCHEMA,
+    BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA, BACKUP_TYPE_SCHEMA,
+    DATASTORE_SCHEMA, GROUP_FILTER_LIST_SCHEMA, GROUP_FILTER_SCHEMA, GROUP_HEALTH_MAX_AGE_DEFAULT,
+    IGNORE_VERIFIED_BACKUPS_SCHEMA, LOCK_WAIT_SCHEMA, MAX_NAMESPACE_DEPTH, NS_MAX_DEPTH_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    PRIV_DATASTORE_READ, PRIV_DATASTORE_VERIFY, RE_VERIFY_FAILED_SCHEMA, UPID, UPID_SCHEMA,
+    VERIFICATION_OUTDATED_AFTER_SCHEMA, VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA,
 };
-use pbs_client::pxar::{create_tar, create_zip};
+use pbs_client::pxar::{create_tar, create_tar_with_options, create_zip, TarOptions};
 use pbs_config::CachedUserInfo;
-use pbs_datastore::backup_info::BackupInfo;
+use pbs_datastore::backup_info::{BackupInfo, GROUP_NOTES_FILE_NAME};
 use pbs_datastore::cached_chunk_reader::CachedChunkReader;
 use pbs_datastore::catalog::{ArchiveEntry, CatalogReader};
 use pbs_datastore::data_blob::DataBlob;
-use pbs_datastore::data_blob_reader::DataBlobReader;
 use pbs_datastore::dynamic_index::{BufferedDynamicReader, DynamicIndexReader, LocalDynamicReadAt};
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
-use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
+use pbs_datastore::lock_tracking::{retry_on_lock_contention, with_lock_owner};
+use pbs_datastore::manifest::{
+    archive_type, ArchiveType, BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
+};
 use pbs_datastore::prune::compute_prune_info;
 use pbs_datastore::{
     check_backup_owner, task_tracking, BackupDir, BackupGroup, DataStore, LocalChunkReader,
@@ -61,15 +70,17 @@ use pbs_tools::json::required_string_param;
 use proxmox_rest_server::{formatter, WorkerTask};
 
 use crate::api2::backup::optional_ns_param;
+use crate::api2::helpers;
 use crate::api2::node::rrd::create_value_from_rrd;
+use crate::api2::node::tasks::{check_job_store, check_task_access};
 use crate::backup::{
-    check_ns_privs_full, verify_all_backups, verify_backup_dir, verify_backup_group, verify_filter,
-    ListAccessibleBackupGroups, NS_PRIVS_OK,
+    check_group_health, check_ns_privs, check_ns_privs_full, generate_owner_report,
+    list_report_groups, load_owner_report, store_owner_report, verify_all_backups,
+    verify_backup_dir, verify_backup_group, ListAccessibleBackupGroups, VerifySkipStats,
+    NS_PRIVS_OK, OWNER_REPORT_SYNC_LIMIT_DEFAULT,
 };
 
-use crate::server::jobstate::Job;
-
-const GROUP_NOTES_FILE_NAME: &str = "notes";
+use crate::server::jobstate::{self, Job};
 
 fn get_group_note_path(
     store: &DataStore,
@@ -117,6 +128,7 @@ fn read_backup_index(
             filename: item.filename.clone(),
             crypt_mode: Some(item.crypt_mode),
             size: Some(item.size),
+            excludes: None,
         });
     }
 
@@ -127,6 +139,7 @@ fn read_backup_index(
             None => Some(CryptMode::None),
         },
         size: Some(index_size),
+        excludes: None,
     });
 
     Ok((manifest, result))
@@ -150,6 +163,7 @@ fn get_all_snapshot_files(
             filename: file.to_string(),
             size: None,
             crypt_mode: None,
+            excludes: None,
         });
     }
 
@@ -237,7 +251,10 @@ pub fn list_groups(
                 .to_owned();
 
             let note_path = get_group_note_path(&datastore, &ns, group.as_ref());
-            let comment = file_read_firstline(note_path).ok();
+            let notes = file_read_optional_string(note_path).ok().flatten();
+            let mut note_lines = notes.iter().flat_map(|notes| notes.lines());
+            let comment = note_lines.next().map(String::from);
+            let notes_truncated = note_lines.any(|line| !line.trim().is_empty());
 
             group_info.push(GroupListItem {
                 backup: group.into(),
@@ -246,6 +263,7 @@ pub fn list_groups(
                 backup_count,
                 files: last_backup.files,
                 comment,
+                notes_truncated,
             });
 
             Ok(group_info)
@@ -264,6 +282,10 @@ pub fn list_groups(
                 type: pbs_api_types::BackupGroup,
                 flatten: true,
             },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -277,6 +299,7 @@ pub async fn delete_group(
     store: String,
     ns: Option<BackupNamespace>,
     group: pbs_api_types::BackupGroup,
+    wait: Option<u64>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -294,7 +317,12 @@ pub async fn delete_group(
             &group,
         )?;
 
-        if !datastore.remove_backup_group(&ns, &group)? {
+        let removed_all = with_lock_owner(None, "delete group", || {
+            retry_on_lock_contention(wait.map(Duration::from_secs), || {
+                datastore.remove_backup_group(&ns, &group)
+            })
+        })?;
+        if !removed_all {
             bail!("group only partially deleted due to protected snapshots");
         }
 
@@ -303,6 +331,75 @@ pub async fn delete_group(
     .await?
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "target-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_PRUNE and being the owner of the group, and on \
+            /datastore/{store}[/{target-ns}] either DATASTORE_MODIFY or DATASTORE_BACKUP and \
+            being the owner of the group",
+    },
+)]
+/// Move a backup group including all snapshots to another namespace.
+pub async fn move_group(
+    store: String,
+    ns: Option<BackupNamespace>,
+    target_ns: Option<BackupNamespace>,
+    group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+        let target_ns = target_ns.unwrap_or_default();
+
+        let datastore = check_privs_and_load_store(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_PRUNE,
+            Some(Operation::Write),
+            &group,
+        )?;
+
+        let limited = check_ns_privs_full(
+            &store,
+            &target_ns,
+            &auth_id,
+            PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_BACKUP,
+        )?;
+        if limited {
+            let owner = datastore.get_owner(&ns, &group)?;
+            check_backup_owner(&owner, &auth_id)?;
+        }
+
+        datastore.move_backup_group(&ns, &target_ns, &group)?;
+
+        Ok(Value::Null)
+    })
+    .await?
+}
+
 #[api(
     input: {
         properties: {
@@ -351,7 +448,12 @@ pub async fn list_snapshot_files(
 
         let info = BackupInfo::new(snapshot)?;
 
-        let (_manifest, files) = get_all_snapshot_files(&info)?;
+        let (manifest, mut files) = get_all_snapshot_files(&info)?;
+
+        // only included here, to keep snapshot lists small
+        for file in files.iter_mut() {
+            file.excludes = manifest.archive_excludes(&file.filename);
+        }
 
         Ok(files)
     })
@@ -370,6 +472,10 @@ pub async fn list_snapshot_files(
                 type: pbs_api_types::BackupDir,
                 flatten: true,
             },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -383,6 +489,7 @@ pub async fn delete_snapshot(
     store: String,
     ns: Option<BackupNamespace>,
     backup_dir: pbs_api_types::BackupDir,
+    wait: Option<u64>,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
@@ -403,7 +510,9 @@ pub async fn delete_snapshot(
 
         let snapshot = datastore.backup_dir(ns, backup_dir)?;
 
-        snapshot.destroy(false)?;
+        with_lock_owner(None, "delete snapshot", || {
+            retry_on_lock_contention(wait.map(Duration::from_secs), || snapshot.destroy(false))
+        })?;
 
         Ok(Value::Null)
     })
@@ -465,15 +574,38 @@ unsafe fn list_snapshots_blocking(
 ) -> Result<Vec<SnapshotListItem>, Error> {
     let ns = ns.unwrap_or_default();
 
+    let (groups, list_all) =
+        list_snapshot_groups_blocking(&store, &ns, backup_type, backup_id, &auth_id)?;
+
+    let mut snapshots = Vec::new();
+    for_each_snapshot_blocking(&store, &ns, &groups, list_all, &auth_id, |item| {
+        snapshots.push(item);
+        Ok(())
+    })?;
+
+    Ok(snapshots)
+}
+
+/// Check the privileges for listing snapshots and return the groups to list, together with
+/// whether groups owned by other users may be included.
+///
+/// This must not run in a main worker thread as it potentially does tons of I/O.
+unsafe fn list_snapshot_groups_blocking(
+    store: &str,
+    ns: &BackupNamespace,
+    backup_type: Option<BackupType>,
+    backup_id: Option<String>,
+    auth_id: &Authid,
+) -> Result<(Vec<BackupGroup>, bool), Error> {
     let list_all = !check_ns_privs_full(
-        &store,
-        &ns,
-        &auth_id,
+        store,
+        ns,
+        auth_id,
         PRIV_DATASTORE_AUDIT,
         PRIV_DATASTORE_BACKUP,
     )?;
 
-    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
 
     // FIXME: filter also owner before collecting, for doing that nicely the owner should move into
     // backup group and provide an error free (Err -> None) accessor
@@ -497,117 +629,253 @@ unsafe fn list_snapshots_blocking(
         (None, None) => datastore.list_backup_groups(ns.clone())?,
     };
 
-    let info_to_snapshot_list_item = |group: &BackupGroup, owner, info: BackupInfo| {
-        let backup = pbs_api_types::BackupDir {
-            group: group.into(),
-            time: info.backup_dir.backup_time(),
+    Ok((groups, list_all))
+}
+
+/// Pass the list item of every snapshot in `groups` visible to `auth_id` to `callback`, one at a
+/// time.
+///
+/// This must not run in a main worker thread as it potentially does tons of I/O.
+unsafe fn for_each_snapshot_blocking<F>(
+    store: &str,
+    ns: &BackupNamespace,
+    groups: &[BackupGroup],
+    list_all: bool,
+    auth_id: &Authid,
+    mut callback: F,
+) -> Result<(), Error>
+where
+    F: FnMut(SnapshotListItem) -> Result<(), Error>,
+{
+    for group in groups {
+        let owner = match group.get_owner() {
+            Ok(auth_id) => auth_id,
+            Err(err) => {
+                eprintln!(
+                    "Failed to get owner of group '{}' in {} - {}",
+                    group.group(),
+                    print_store_and_ns(store, ns),
+                    err
+                );
+                continue;
+            }
         };
-        let protected = info.backup_dir.is_protected();
-
-        match get_all_snapshot_files(&info) {
-            Ok((manifest, files)) => {
-                // extract the first line from notes
-                let comment: Option<String> = manifest.unprotected["notes"]
-                    .as_str()
-                    .and_then(|notes| notes.lines().next())
-                    .map(String::from);
-
-                let fingerprint = match manifest.fingerprint() {
-                    Ok(fp) => fp,
+
+        if !list_all && check_backup_owner(&owner, auth_id).is_err() {
+            continue;
+        }
+
+        for info in group.list_backups()? {
+            callback(snapshot_list_item(group, Some(owner.clone()), info))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn snapshot_list_item(
+    group: &BackupGroup,
+    owner: Option<Authid>,
+    info: BackupInfo,
+) -> SnapshotListItem {
+    let backup = pbs_api_types::BackupDir {
+        group: group.into(),
+        time: info.backup_dir.backup_time(),
+    };
+    let protected = info.backup_dir.is_protected();
+
+    match get_all_snapshot_files(&info) {
+        Ok((manifest, files)) => {
+            // extract the first line from notes
+            let notes = manifest.unprotected["notes"].as_str();
+            let comment: Option<String> = notes
+                .and_then(|notes| notes.lines().next())
+                .map(String::from);
+            let notes_digest = notes
+                .filter(|notes| !notes.is_empty())
+                .map(|notes| hex::encode(openssl::sha::sha256(notes.as_bytes())));
+
+            let fingerprint = match manifest.fingerprint() {
+                Ok(fp) => fp,
+                Err(err) => {
+                    eprintln!("error parsing fingerprint: '{}'", err);
+                    None
+                }
+            };
+
+            let verification = manifest.unprotected["verify_state"].clone();
+            let verification: Option<SnapshotVerifyState> =
+                match serde_json::from_value(verification) {
+                    Ok(verify) => verify,
                     Err(err) => {
-                        eprintln!("error parsing fingerprint: '{}'", err);
+                        eprintln!("error parsing verification state : '{}'", err);
                         None
                     }
                 };
 
-                let verification = manifest.unprotected["verify_state"].clone();
-                let verification: Option<SnapshotVerifyState> =
-                    match serde_json::from_value(verification) {
-                        Ok(verify) => verify,
-                        Err(err) => {
-                            eprintln!("error parsing verification state : '{}'", err);
-                            None
-                        }
-                    };
-
-                let size = Some(files.iter().map(|x| x.size.unwrap_or(0)).sum());
-
-                SnapshotListItem {
-                    backup,
-                    comment,
-                    verification,
-                    fingerprint,
-                    files,
-                    size,
-                    owner,
-                    protected,
-                }
+            let size = Some(files.iter().map(|x| x.size.unwrap_or(0)).sum());
+
+            // not recorded for snapshots created by older versions
+            let upload_info: SnapshotUploadInfo =
+                serde_json::from_value(manifest.unprotected["upload_info"].clone())
+                    .unwrap_or_default();
+
+            SnapshotListItem {
+                backup,
+                comment,
+                notes_digest,
+                verification,
+                fingerprint,
+                files,
+                size,
+                owner,
+                protected,
+                upload_info,
             }
-            Err(err) => {
-                eprintln!("error during snapshot file listing: '{}'", err);
-                let files = info
-                    .files
-                    .into_iter()
-                    .map(|filename| BackupContent {
-                        filename,
-                        size: None,
-                        crypt_mode: None,
-                    })
-                    .collect();
-
-                SnapshotListItem {
-                    backup,
-                    comment: None,
-                    verification: None,
-                    fingerprint: None,
-                    files,
+        }
+        Err(err) => {
+            eprintln!("error during snapshot file listing: '{}'", err);
+            let files = info
+                .files
+                .into_iter()
+                .map(|filename| BackupContent {
+                    filename,
                     size: None,
-                    owner,
-                    protected,
-                }
+                    crypt_mode: None,
+                    excludes: None,
+                })
+                .collect();
+
+            SnapshotListItem {
+                backup,
+                comment: None,
+                notes_digest: None,
+                verification: None,
+                fingerprint: None,
+                files,
+                size: None,
+                owner,
+                protected,
+                upload_info: Default::default(),
             }
         }
-    };
+    }
+}
 
-    groups.iter().try_fold(Vec::new(), |mut snapshots, group| {
-        let owner = match group.get_owner() {
-            Ok(auth_id) => auth_id,
-            Err(err) => {
+#[sortable]
+pub const API_METHOD_STREAM_SNAPSHOTS: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&stream_snapshots),
+    &ObjectSchema::new(
+        "List backup snapshots as a stream of newline delimited JSON records. Each line is \
+        either '{\"data\": SNAPSHOT}' or, if listing failed after the response was started, a \
+        final '{\"error\": MESSAGE}'.",
+        &sorted!([
+            ("store", false, &DATASTORE_SCHEMA),
+            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
+            ("backup-type", true, &BACKUP_TYPE_SCHEMA),
+            ("backup-id", true, &BACKUP_ID_SCHEMA),
+        ]),
+    ),
+)
+.access(
+    Some(
+        "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+        or DATASTORE_BACKUP and being the owner of the group",
+    ),
+    &Permission::Anybody,
+);
+
+pub fn stream_snapshots(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        let store = required_string_param(&param, "store")?.to_owned();
+        let ns = optional_ns_param(&param)?;
+        let backup_type: Option<BackupType> = match param["backup-type"].as_str() {
+            Some(backup_type) => Some(backup_type.parse()?),
+            None => None,
+        };
+        let backup_id = param["backup-id"].as_str().map(String::from);
+
+        // check privileges and collect the groups up front, so that such errors are still
+        // reported with a proper HTTP status
+        let (store, ns, auth_id, groups, list_all) = tokio::task::spawn_blocking(move || {
+            let (groups, list_all) = unsafe {
+                list_snapshot_groups_blocking(&store, &ns, backup_type, backup_id, &auth_id)?
+            };
+            Ok::<_, Error>((store, ns, auth_id, groups, list_all))
+        })
+        .await
+        .map_err(|err| format_err!("failed to await blocking task: {err}"))??;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Vec<u8>, Error>>(64);
+
+        tokio::task::spawn_blocking(move || {
+            let result = unsafe {
+                for_each_snapshot_blocking(&store, &ns, &groups, list_all, &auth_id, |item| {
+                    let mut line = serde_json::to_vec(&SnapshotListRecord::Data(item))?;
+                    line.push(b'\n');
+                    sender
+                        .blocking_send(Ok(line))
+                        .map_err(|_| format_err!("receiver of snapshot list stream is gone"))
+                })
+            };
+
+            if let Err(err) = result {
                 eprintln!(
-                    "Failed to get owner of group '{}' in {} - {}",
-                    group.group(),
+                    "error during streaming of snapshot list of {} - {}",
                     print_store_and_ns(&store, &ns),
                     err
                 );
-                return Ok(snapshots);
+                // the response already started, so report the error as final record
+                let record = SnapshotListRecord::Error(err.to_string());
+                if let Ok(mut line) = serde_json::to_vec(&record) {
+                    line.push(b'\n');
+                    let _ = sender.blocking_send(Ok(line));
+                }
             }
-        };
-
-        if !list_all && check_backup_owner(&owner, &auth_id).is_err() {
-            return Ok(snapshots);
-        }
+        });
 
-        let group_backups = group.list_backups()?;
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/x-ndjson")
+            .body(Body::wrap_stream(ReceiverStream::new(receiver)))
+            .unwrap())
+    }
+    .boxed()
+}
 
-        snapshots.extend(
-            group_backups
-                .into_iter()
-                .map(|info| info_to_snapshot_list_item(group, Some(owner.clone()), info)),
-        );
+fn add_group_count(counts: &mut Counts, backup_type: BackupType, snapshot_count: u64) {
+    let type_count = match backup_type {
+        BackupType::Ct => counts.ct.get_or_insert(Default::default()),
+        BackupType::Vm => counts.vm.get_or_insert(Default::default()),
+        BackupType::Host => counts.host.get_or_insert(Default::default()),
+    };
 
-        Ok(snapshots)
-    })
+    type_count.groups += 1;
+    type_count.snapshots += snapshot_count;
 }
 
+/// Count groups and snapshots, optionally also per namespace.
+///
+/// Both are collected in a single walk over all accessible groups.
 async fn get_snapshots_count(
     store: &Arc<DataStore>,
     owner: Option<&Authid>,
-) -> Result<Counts, Error> {
+    per_ns: bool,
+) -> Result<(Counts, Option<Vec<NamespaceCounts>>), Error> {
     let store = Arc::clone(store);
     let owner = owner.cloned();
     tokio::task::spawn_blocking(move || {
         let root_ns = Default::default();
-        ListAccessibleBackupGroups::new_with_privs(
+        let mut ns_counts: BTreeMap<BackupNamespace, Counts> = BTreeMap::new();
+
+        let counts = ListAccessibleBackupGroups::new_with_privs(
             &store,
             root_ns,
             MAX_NAMESPACE_DEPTH,
@@ -618,28 +886,62 @@ async fn get_snapshots_count(
         .try_fold(Counts::default(), |mut counts, group| {
             let group = match group {
                 Ok(group) => group,
-                Err(_) => return Ok(counts), // TODO: add this as error counts?
+                Err(_) => return Ok::<_, Error>(counts), // TODO: add this as error counts?
             };
             let snapshot_count = group.list_backups()?.len() as u64;
 
             // only include groups with snapshots, counting/displaying empty groups can confuse
             if snapshot_count > 0 {
-                let type_count = match group.backup_type() {
-                    BackupType::Ct => counts.ct.get_or_insert(Default::default()),
-                    BackupType::Vm => counts.vm.get_or_insert(Default::default()),
-                    BackupType::Host => counts.host.get_or_insert(Default::default()),
-                };
-
-                type_count.groups += 1;
-                type_count.snapshots += snapshot_count;
+                add_group_count(&mut counts, group.backup_type(), snapshot_count);
+                if per_ns {
+                    let ns_count = ns_counts.entry(group.backup_ns().clone()).or_default();
+                    add_group_count(ns_count, group.backup_type(), snapshot_count);
+                }
             }
 
             Ok(counts)
-        })
+        })?;
+
+        let ns_counts = per_ns.then(|| {
+            ns_counts
+                .into_iter()
+                .map(|(ns, counts)| NamespaceCounts { ns, counts })
+                .collect()
+        });
+
+        Ok((counts, ns_counts))
     })
     .await?
 }
 
+/// Computes the next scheduled garbage collection and prune job run of a datastore.
+///
+/// Disabled prune jobs and unparsable schedules are ignored.
+fn next_scheduled_runs(store: &str) -> Result<(Option<i64>, Option<i64>), Error> {
+    let next_event = |schedule: &str, jobtype: &str, jobname: &str| -> Option<i64> {
+        let event: CalendarEvent = schedule.parse().ok()?;
+        let last = jobstate::last_run_time(jobtype, jobname).ok()?;
+        event.compute_next_event(last).ok().flatten()
+    };
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let store_config: DataStoreConfig = config.lookup("datastore", store)?;
+    let next_gc_run = store_config
+        .gc_schedule
+        .as_deref()
+        .and_then(|schedule| next_event(schedule, "garbage_collection", store));
+
+    let (config, _digest) = pbs_config::prune::config()?;
+    let next_prune_run = config
+        .convert_to_typed_array::<PruneJobConfig>("prune")?
+        .into_iter()
+        .filter(|job| job.store == store && !job.disable)
+        .filter_map(|job| next_event(&job.schedule, "prunejob", &job.id))
+        .min();
+
+    Ok((next_gc_run, next_prune_run))
+}
+
 #[api(
     input: {
         properties: {
@@ -652,6 +954,12 @@ async fn get_snapshots_count(
                 optional: true,
                 description: "Include additional information like snapshot counts and GC status.",
             },
+            "per-ns": {
+                type: bool,
+                default: false,
+                optional: true,
+                description: "Include group and snapshot counts for each namespace.",
+            },
         },
 
     },
@@ -668,6 +976,7 @@ async fn get_snapshots_count(
 pub async fn status(
     store: String,
     verbose: bool,
+    per_ns: bool,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<DataStoreStatus, Error> {
@@ -690,33 +999,44 @@ pub async fn status(
     };
     let datastore = datastore?; // only unwrap no to avoid leaking existence info
 
-    let (counts, gc_status) = if verbose {
+    let (counts, namespace_counts) = if verbose || per_ns {
         let filter_owner = if store_privs & PRIV_DATASTORE_AUDIT != 0 {
             None
         } else {
             Some(&auth_id)
         };
 
-        let counts = Some(get_snapshots_count(&datastore, filter_owner).await?);
-        let gc_status = if store_stats {
-            Some(datastore.last_gc_status())
-        } else {
-            None
-        };
+        let (counts, namespace_counts) =
+            get_snapshots_count(&datastore, filter_owner, per_ns).await?;
 
-        (counts, gc_status)
+        (verbose.then_some(counts), namespace_counts)
     } else {
         (None, None)
     };
 
+    let gc_status = if verbose && store_stats {
+        Some(datastore.last_gc_status())
+    } else {
+        None
+    };
+
     Ok(if store_stats {
-        let storage = crate::tools::fs::fs_info(datastore.base_path()).await?;
+        let storage = crate::tools::fs::datastore_fs_info(&datastore).await?;
+        let (next_gc_run, next_prune_run) = next_scheduled_runs(&store)?;
+        let active_operations = task_tracking::get_active_operations(&store)?;
         DataStoreStatus {
             total: storage.total,
             used: storage.used,
             avail: storage.available,
             gc_status,
             counts,
+            namespace_counts,
+            next_gc_run,
+            next_prune_run,
+            active_operations: Some(ActiveOperationCounts {
+                read: active_operations.read,
+                write: active_operations.write,
+            }),
         }
     } else {
         DataStoreStatus {
@@ -725,6 +1045,10 @@ pub async fn status(
             avail: 0,
             gc_status,
             counts,
+            namespace_counts,
+            next_gc_run: None,
+            next_prune_run: None,
+            active_operations: None,
         }
     })
 }
@@ -755,6 +1079,10 @@ pub async fn status(
                 schema: VERIFICATION_OUTDATED_AFTER_SCHEMA,
                 optional: true,
             },
+            "re-verify-failed": {
+                schema: RE_VERIFY_FAILED_SCHEMA,
+                optional: true,
+            },
             "backup-time": {
                 schema: BACKUP_TIME_SCHEMA,
                 optional: true,
@@ -763,6 +1091,10 @@ pub async fn status(
                 schema: NS_MAX_DEPTH_SCHEMA,
                 optional: true,
             },
+            "skip-bad-chunk-report": {
+                schema: VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: {
@@ -777,7 +1109,12 @@ pub async fn status(
 /// Verify backups.
 ///
 /// This function can verify a single backup snapshot, all backup from a backup group,
-/// or all backups in the datastore.
+/// or all backups in the datastore, optionally limited to a namespace and its children up to
+/// `max-depth`.
+///
+/// When verifying the datastore, all index files are searched for chunks found to be bad at
+/// the end, unless `skip-bad-chunk-report` is set. This requires DATASTORE_VERIFY on the whole
+/// datastore, as the report may include any snapshot.
 #[allow(clippy::too_many_arguments)]
 pub fn verify(
     store: String,
@@ -787,12 +1124,19 @@ pub fn verify(
     backup_time: Option<i64>,
     ignore_verified: Option<bool>,
     outdated_after: Option<i64>,
+    re_verify_failed: Option<bool>,
     max_depth: Option<usize>,
+    skip_bad_chunk_report: Option<bool>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let ns = ns.unwrap_or_default();
 
+    let bad_chunk_report = !skip_bad_chunk_report.unwrap_or(false)
+        && CachedUserInfo::new()?.lookup_privs(&auth_id, &ns.acl_path(&store))
+            & PRIV_DATASTORE_VERIFY
+            != 0;
+
     let owner_check_required = check_ns_privs_full(
         &store,
         &ns,
@@ -803,6 +1147,7 @@ pub fn verify(
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
     let ignore_verified = ignore_verified.unwrap_or(true);
+    let re_verify_failed = re_verify_failed.unwrap_or(true);
 
     let worker_id;
 
@@ -867,20 +1212,30 @@ pub fn verify(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
+            datastore.set_operation_owner(&worker.upid().to_string());
             let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
+            let skip_stats = VerifySkipStats::default();
+            let filter = |manifest: &BackupManifest| {
+                skip_stats.filter(ignore_verified, outdated_after, re_verify_failed, manifest)
+            };
             let failed_dirs = if let Some(backup_dir) = backup_dir {
                 let mut res = Vec::new();
+                verify_worker.add_snapshots_total(1);
                 if !verify_backup_dir(
                     &verify_worker,
                     &backup_dir,
                     worker.upid().clone(),
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
+                    Some(&filter),
                 )? {
                     res.push(print_ns_and_snapshot(
                         backup_dir.backup_ns(),
                         backup_dir.as_ref(),
                     ));
                 }
+                let mut progress = StoreProgress::new(1);
+                progress.done_groups = 1;
+                verify_worker.update_percentage(&progress);
                 res
             } else if let Some(backup_group) = backup_group {
                 verify_backup_group(
@@ -888,7 +1243,7 @@ pub fn verify(
                     &backup_group,
                     &mut StoreProgress::new(1),
                     worker.upid(),
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
+                    Some(&filter),
                 )?
             } else {
                 let owner = if owner_check_required {
@@ -897,15 +1252,20 @@ pub fn verify(
                     None
                 };
 
-                verify_all_backups(
+                let failed_dirs = verify_all_backups(
                     &verify_worker,
                     worker.upid(),
                     ns,
                     max_depth,
                     owner,
-                    Some(&move |manifest| verify_filter(ignore_verified, outdated_after, manifest)),
-                )?
+                    Some(&filter),
+                )?;
+                if bad_chunk_report {
+                    verify_worker.report_bad_chunks()?;
+                }
+                failed_dirs
             };
+            skip_stats.log(&*worker);
             if !failed_dirs.is_empty() {
                 task_log!(worker, "Failed to verify the following snapshots/groups:");
                 for dir in failed_dirs {
@@ -944,6 +1304,10 @@ pub fn verify(
                 type: BackupNamespace,
                 optional: true,
             },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
         },
     },
     returns: pbs_api_types::ADMIN_DATASTORE_PRUNE_RETURN_TYPE,
@@ -954,12 +1318,14 @@ pub fn verify(
     },
 )]
 /// Prune a group on the datastore
+#[allow(clippy::too_many_arguments)]
 pub fn prune(
     group: pbs_api_types::BackupGroup,
     dry_run: bool,
     keep_options: KeepOptions,
     store: String,
     ns: Option<BackupNamespace>,
+    wait: Option<u64>,
     _param: Value,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
@@ -1010,6 +1376,7 @@ pub fn prune(
 
     // We use a WorkerTask just to have a task log, but run synchrounously
     let worker = WorkerTask::new("prune", Some(worker_id), auth_id.to_string(), true)?;
+    let upid = worker.upid().to_string();
 
     if keep_all {
         task_log!(worker, "No prune selection - keeping all files.");
@@ -1049,7 +1416,14 @@ pub fn prune(
         }));
 
         if !(dry_run || keep) {
-            if let Err(err) = info.backup_dir.destroy(false) {
+            let result = proxmox_async::runtime::block_in_place(|| {
+                with_lock_owner(Some(&upid), "prune", || {
+                    retry_on_lock_contention(wait.map(Duration::from_secs), || {
+                        info.backup_dir.destroy(false)
+                    })
+                })
+            });
+            if let Err(err) = result {
                 task_warn!(
                     worker,
                     "failed to remove dir {:?}: {}",
@@ -1068,42 +1442,129 @@ pub fn prune(
 #[api(
     input: {
         properties: {
-            "dry-run": {
-                optional: true,
-                type: bool,
-                default: false,
-                description: "Just show what prune would do, but do not delete anything.",
+            group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
             },
-            "prune-options": {
-                type: PruneJobOptions,
+            "keep-options": {
+                type: KeepOptions,
                 flatten: true,
             },
             store: {
                 schema: DATASTORE_SCHEMA,
             },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
         },
     },
-    returns: {
-        schema: UPID_SCHEMA,
-    },
+    returns: pbs_api_types::ADMIN_DATASTORE_PRUNE_RETURN_TYPE,
     access: {
         permission: &Permission::Anybody,
-        description: "Requires Datastore.Modify or Datastore.Prune on the datastore/namespace.",
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT or \
+            DATASTORE_READ for any or DATASTORE_BACKUP and being the owner of the group",
     },
 )]
-/// Prune the datastore
-pub fn prune_datastore(
-    dry_run: bool,
-    prune_options: PruneJobOptions,
+/// Simulate a prune run on a group without modifying anything.
+///
+/// In contrast to a dry-run prune this only requires read access and never locks the group,
+/// snapshots which are still in progress are ignored.
+pub async fn prune_simulate(
+    group: pbs_api_types::BackupGroup,
+    keep_options: KeepOptions,
     store: String,
-    _param: Value,
+    ns: Option<BackupNamespace>,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<String, Error> {
-    let user_info = CachedUserInfo::new()?;
-
+) -> Result<Vec<PruneListItem>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
-    user_info.check_privs(
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+
+        let datastore = check_privs_and_load_store(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_READ,
+            PRIV_DATASTORE_BACKUP,
+            Some(Operation::Read),
+            &group,
+        )?;
+
+        let group = datastore.backup_group(ns, group);
+
+        let list: Vec<BackupInfo> = group
+            .list_backups()?
+            .into_iter()
+            .filter(|info| info.is_finished())
+            .collect();
+
+        let mut prune_info = compute_prune_info(list, &keep_options)?;
+
+        prune_info.reverse(); // same order as a real prune run
+
+        let keep_all = !keep_options.keeps_something();
+
+        let result = prune_info
+            .into_iter()
+            .map(|(info, mark)| PruneListItem {
+                keep: keep_all || mark.keep(),
+                protected: Some(mark.protected()),
+                reason: mark.protected().then(|| mark.to_string()),
+                backup: info.backup_dir.dir().clone(),
+            })
+            .collect();
+
+        Ok(result)
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            "dry-run": {
+                optional: true,
+                type: bool,
+                default: false,
+                description: "Just show what prune would do, but do not delete anything.",
+            },
+            "prune-options": {
+                type: PruneJobOptions,
+                flatten: true,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            wait: {
+                schema: LOCK_WAIT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires Datastore.Modify or Datastore.Prune on the datastore/namespace.",
+    },
+)]
+/// Prune the datastore
+pub fn prune_datastore(
+    dry_run: bool,
+    prune_options: PruneJobOptions,
+    store: String,
+    wait: Option<u64>,
+    _param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let user_info = CachedUserInfo::new()?;
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    user_info.check_privs(
         &auth_id,
         &prune_options.acl_path(&store),
         PRIV_DATASTORE_MODIFY | PRIV_DATASTORE_PRUNE,
@@ -1122,7 +1583,15 @@ pub fn prune_datastore(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            crate::server::prune_datastore(worker, auth_id, prune_options, datastore, dry_run)
+            datastore.set_operation_owner(&worker.upid().to_string());
+            crate::server::prune_datastore(
+                worker,
+                auth_id,
+                prune_options,
+                datastore,
+                dry_run,
+                wait.map(Duration::from_secs),
+            )
         },
     )?;
 
@@ -1194,7 +1663,12 @@ pub fn garbage_collection_status(
 ) -> Result<GarbageCollectionStatus, Error> {
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
 
-    let status = datastore.last_gc_status();
+    let mut status = datastore.last_gc_status();
+
+    if let Some(progress) = datastore.garbage_collection_progress() {
+        status.running = true;
+        status.progress = Some(progress);
+    }
 
     Ok(status)
 }
@@ -1235,6 +1709,13 @@ pub fn get_datastore_list(
         }
 
         if allowed || allow_id {
+            let maintenance = data["maintenance-mode"].as_str().map(String::from);
+            let maintenance_type = maintenance
+                .as_deref()
+                .and_then(|mode| MaintenanceMode::API_SCHEMA.parse_property_string(mode).ok())
+                .and_then(|value| MaintenanceMode::deserialize(value).ok())
+                .map(|mode| mode.maintenance_type());
+
             list.push(DataStoreListItem {
                 store: store.clone(),
                 comment: if !allowed {
@@ -1242,7 +1723,8 @@ pub fn get_datastore_list(
                 } else {
                     data["comment"].as_str().map(String::from)
                 },
-                maintenance: data["maintenance-mode"].as_str().map(String::from),
+                maintenance,
+                maintenance_type,
             });
         }
     }
@@ -1254,7 +1736,7 @@ pub fn get_datastore_list(
 pub const API_METHOD_DOWNLOAD_FILE: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&download_file),
     &ObjectSchema::new(
-        "Download single raw file from backup snapshot.",
+        "Download single raw file from backup snapshot. Supports single byte range requests.",
         &sorted!([
             ("store", false, &DATASTORE_SCHEMA),
             ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
@@ -1273,8 +1755,71 @@ pub const API_METHOD_DOWNLOAD_FILE: ApiMethod = ApiMethod::new(
     &Permission::Anybody,
 );
 
+/// Returns the SHA-256 digest of a file for raw downloads, if it is covered by the manifest.
+///
+/// For blobs, the manifest contains the digest of the file itself. Index files are hashed if their
+/// checksum matches the manifest.
+fn raw_download_digest(
+    backup_dir: &BackupDir,
+    file_name: &str,
+    path: &std::path::Path,
+) -> Result<Option<[u8; 32]>, Error> {
+    if file_name == MANIFEST_BLOB_NAME {
+        return Ok(None);
+    }
+    let manifest = match backup_dir.load_manifest() {
+        Ok((manifest, _)) => manifest,
+        Err(_) => return Ok(None),
+    };
+    let info = match manifest.lookup_file_info(file_name) {
+        Ok(info) => info,
+        Err(_) => return Ok(None),
+    };
+
+    let (csum, size) = match archive_type(file_name)? {
+        ArchiveType::Blob => return Ok(Some(info.csum)),
+        ArchiveType::FixedIndex => FixedIndexReader::open(path)?.compute_csum(),
+        ArchiveType::DynamicIndex => DynamicIndexReader::open(path)?.compute_csum(),
+    };
+    if let Err(err) = manifest.verify_file(file_name, &csum, size) {
+        eprintln!("not sending digest of {path:?} - {err}");
+        return Ok(None);
+    }
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = openssl::sha::Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = std::io::Read::read(&mut file, &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(Some(hasher.finish()))
+}
+
+/// Load a blob for decoded downloads, returning its decoded data and their SHA-256 digest.
+///
+/// The blob is checked against the manifest, as done for index files.
+fn decode_blob_verified(
+    manifest: &BackupManifest,
+    file_name: &str,
+    path: &std::path::Path,
+) -> Result<(Vec<u8>, [u8; 32]), Error> {
+    let raw_data =
+        std::fs::read(path).map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
+    let csum = openssl::sha::sha256(&raw_data);
+    manifest.verify_file(file_name, &csum, raw_data.len() as u64)?;
+
+    let data = DataBlob::load_from_reader(&mut &raw_data[..])?.decode(None, None)?;
+    let sha256 = openssl::sha::sha256(&data);
+    Ok((data, sha256))
+}
+
 pub fn download_file(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1312,25 +1857,42 @@ pub fn download_file(
         path.push(backup_dir.relative_path());
         path.push(&file_name);
 
-        let file = tokio::fs::File::open(&path)
+        let mut file = tokio::fs::File::open(&path)
             .await
             .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
+        let size = file.metadata().await?.len();
+
+        let range = helpers::parse_range_header(&parts.headers, size)?;
+        // hashing the file is blocking and may take a while
+        let sha256 = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || raw_download_digest(&backup_dir, &file_name, &path))
+                .await??
+        };
 
-        let payload =
-            tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
-                .map_ok(|bytes| bytes.freeze())
-                .map_err(move |err| {
-                    eprintln!("error during streaming of '{:?}' - {}", &path, err);
-                    err
-                });
+        let (start, len) = match &range {
+            Some(range) => (range.start, range.end - range.start),
+            None => (0, size),
+        };
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+
+        let payload = tokio_util::codec::FramedRead::new(
+            file.take(len),
+            tokio_util::codec::BytesCodec::new(),
+        )
+        .map_ok(|bytes| bytes.freeze())
+        .map_err(move |err| {
+            eprintln!("error during streaming of '{:?}' - {}", &path, err);
+            err
+        });
         let body = Body::wrap_stream(payload);
 
-        // fixme: set other headers ?
-        Ok(Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/octet-stream")
-            .body(body)
-            .unwrap())
+        Ok(helpers::create_ranged_download_response(
+            body,
+            size,
+            range,
+            sha256.as_ref(),
+        ))
     }
     .boxed()
 }
@@ -1339,7 +1901,8 @@ pub fn download_file(
 pub const API_METHOD_DOWNLOAD_FILE_DECODED: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&download_file_decoded),
     &ObjectSchema::new(
-        "Download single decoded file from backup snapshot. Only works if it's not encrypted.",
+        "Download single decoded file from backup snapshot. Only works if it's not encrypted. \
+        Supports single byte range requests.",
         &sorted!([
             ("store", false, &DATASTORE_SCHEMA),
             ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
@@ -1359,7 +1922,7 @@ pub const API_METHOD_DOWNLOAD_FILE_DECODED: ApiMethod = ApiMethod::new(
 );
 
 pub fn download_file_decoded(
-    _parts: Parts,
+    parts: Parts,
     _req_body: Body,
     param: Value,
     _info: &ApiMethod,
@@ -1405,6 +1968,7 @@ pub fn download_file_decoded(
 
         let (_, extension) = file_name.rsplit_once('.').unwrap();
 
+        // index contents can be downloaded partially, by seeking in the chunk reader
         let body = match extension {
             "didx" => {
                 let index = DynamicIndexReader::open(&path).map_err(|err| {
@@ -1413,12 +1977,18 @@ pub fn download_file_decoded(
                 let (csum, size) = index.compute_csum();
                 manifest.verify_file(&file_name, &csum, size)?;
 
+                let range = helpers::parse_range_header(&parts.headers, size)?;
                 let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
-                let reader = CachedChunkReader::new(chunk_reader, index, 1).seekable();
-                Body::wrap_stream(AsyncReaderStream::new(reader).map_err(move |err| {
+                let reader = CachedChunkReader::new(chunk_reader, index, 1);
+                let reader =
+                    helpers::index_range_reader(reader, range.clone().unwrap_or(0..size)).await?;
+                let body = Body::wrap_stream(AsyncReaderStream::new(reader).map_err(move |err| {
                     eprintln!("error during streaming of '{:?}' - {}", path, err);
                     err
-                }))
+                }));
+                return Ok(helpers::create_ranged_download_response(
+                    body, size, range, None,
+                ));
             }
             "fidx" => {
                 let index = FixedIndexReader::open(&path).map_err(|err| {
@@ -1428,31 +1998,42 @@ pub fn download_file_decoded(
                 let (csum, size) = index.compute_csum();
                 manifest.verify_file(&file_name, &csum, size)?;
 
+                let range = helpers::parse_range_header(&parts.headers, size)?;
                 let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
-                let reader = CachedChunkReader::new(chunk_reader, index, 1).seekable();
-                Body::wrap_stream(
+                let reader = CachedChunkReader::new(chunk_reader, index, 1);
+                let reader =
+                    helpers::index_range_reader(reader, range.clone().unwrap_or(0..size)).await?;
+                let body = Body::wrap_stream(
                     AsyncReaderStream::with_buffer_size(reader, 4 * 1024 * 1024).map_err(
                         move |err| {
                             eprintln!("error during streaming of '{:?}' - {}", path, err);
                             err
                         },
                     ),
-                )
+                );
+                return Ok(helpers::create_ranged_download_response(
+                    body, size, range, None,
+                ));
             }
             "blob" => {
-                let file = std::fs::File::open(&path)
-                    .map_err(|err| http_err!(BAD_REQUEST, "File open failed: {}", err))?;
-
-                // FIXME: load full blob to verify index checksum?
+                let file_name = file_name.clone();
+                let (mut data, sha256) = tokio::task::spawn_blocking(move || {
+                    decode_blob_verified(&manifest, &file_name, &path)
+                })
+                .await??;
 
-                Body::wrap_stream(
-                    WrappedReaderStream::new(DataBlobReader::new(file, None)?).map_err(
-                        move |err| {
-                            eprintln!("error during streaming of '{:?}' - {}", path, err);
-                            err
-                        },
-                    ),
-                )
+                let size = data.len() as u64;
+                let range = helpers::parse_range_header(&parts.headers, size)?;
+                if let Some(ref range) = range {
+                    data.truncate(range.end as usize);
+                    data.drain(..range.start as usize);
+                }
+                return Ok(helpers::create_ranged_download_response(
+                    Body::from(data),
+                    size,
+                    range,
+                    Some(&sha256),
+                ));
             }
             extension => {
                 bail!("cannot download '{}' files", extension);
@@ -1630,6 +2211,52 @@ pub async fn catalog(
     .await?
 }
 
+/// Split a base64 encoded `filepath` parameter into the pxar archive name and the path inside
+/// of the archive.
+fn decode_pxar_file_path(filepath: &str) -> Result<(String, Vec<u8>), Error> {
+    let mut components = base64::decode(filepath)?;
+    if !components.is_empty() && components[0] == b'/' {
+        components.remove(0);
+    }
+
+    let mut split = components.splitn(2, |c| *c == b'/');
+    let pxar_name = std::str::from_utf8(split.next().unwrap())?.to_string();
+    let file_path = split.next().unwrap_or(b"/").to_vec();
+
+    Ok((pxar_name, file_path))
+}
+
+/// Open the (unencrypted) pxar archive `pxar_name` of a snapshot for random access.
+async fn open_pxar_archive(
+    datastore: Arc<DataStore>,
+    backup_dir: &BackupDir,
+    pxar_name: &str,
+) -> Result<Accessor<LocalDynamicReadAt<LocalChunkReader>>, Error> {
+    let (manifest, files) = read_backup_index(backup_dir)?;
+    for file in files {
+        if file.filename == pxar_name && file.crypt_mode == Some(CryptMode::Encrypt) {
+            bail!("cannot decode '{}' - is encrypted", pxar_name);
+        }
+    }
+
+    let mut path = datastore.base_path();
+    path.push(backup_dir.relative_path());
+    path.push(pxar_name);
+
+    let index = DynamicIndexReader::open(&path)
+        .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
+
+    let (csum, size) = index.compute_csum();
+    manifest.verify_file(pxar_name, &csum, size)?;
+
+    let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
+    let reader = BufferedDynamicReader::new(index, chunk_reader);
+    let archive_size = reader.archive_size();
+    let reader = LocalDynamicReadAt::new(reader);
+
+    Ok(Accessor::new(reader, archive_size).await?)
+}
+
 #[sortable]
 pub const API_METHOD_PXAR_FILE_DOWNLOAD: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&pxar_file_download),
@@ -1682,39 +2309,10 @@ pub fn pxar_file_download(
 
         let tar = param["tar"].as_bool().unwrap_or(false);
 
-        let mut components = base64::decode(&filepath)?;
-        if !components.is_empty() && components[0] == b'/' {
-            components.remove(0);
-        }
-
-        let mut split = components.splitn(2, |c| *c == b'/');
-        let pxar_name = std::str::from_utf8(split.next().unwrap())?;
-        let file_path = split.next().unwrap_or(b"/");
-        let (manifest, files) = read_backup_index(&backup_dir)?;
-        for file in files {
-            if file.filename == pxar_name && file.crypt_mode == Some(CryptMode::Encrypt) {
-                bail!("cannot decode '{}' - is encrypted", pxar_name);
-            }
-        }
-
-        let mut path = datastore.base_path();
-        path.push(backup_dir.relative_path());
-        path.push(pxar_name);
-
-        let index = DynamicIndexReader::open(&path)
-            .map_err(|err| format_err!("unable to read dynamic index '{:?}' - {}", &path, err))?;
-
-        let (csum, size) = index.compute_csum();
-        manifest.verify_file(pxar_name, &csum, size)?;
-
-        let chunk_reader = LocalChunkReader::new(datastore, None, CryptMode::None);
-        let reader = BufferedDynamicReader::new(index, chunk_reader);
-        let archive_size = reader.archive_size();
-        let reader = LocalDynamicReadAt::new(reader);
-
-        let decoder = Accessor::new(reader, archive_size).await?;
+        let (pxar_name, file_path) = decode_pxar_file_path(&filepath)?;
+        let decoder = open_pxar_archive(datastore, &backup_dir, &pxar_name).await?;
         let root = decoder.open_root().await?;
-        let path = OsStr::from_bytes(file_path).to_os_string();
+        let path = OsStr::from_bytes(&file_path).to_os_string();
         let file = root
             .lookup(&path)
             .await?
@@ -1773,6 +2371,142 @@ pub fn pxar_file_download(
     .boxed()
 }
 
+/// Default size limit of the tar download of a pxar directory, in bytes.
+const PXAR_TAR_DOWNLOAD_DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+#[sortable]
+pub const API_METHOD_PXAR_TAR_DOWNLOAD: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&pxar_tar_download),
+    &ObjectSchema::new(
+        "Download a directory from a pxar file of a backup snapshot as tar archive, skipping \
+        device nodes. Only works if it's not encrypted.",
+        &sorted!([
+            ("store", false, &DATASTORE_SCHEMA),
+            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
+            ("backup-type", false, &BACKUP_TYPE_SCHEMA),
+            ("backup-id", false, &BACKUP_ID_SCHEMA),
+            ("backup-time", false, &BACKUP_TIME_SCHEMA),
+            (
+                "filepath",
+                false,
+                &StringSchema::new("Base64 encoded path").schema()
+            ),
+            (
+                "max-size",
+                true,
+                &IntegerSchema::new(
+                    "Abort the download once the file contents exceed this size (bytes)."
+                )
+                .minimum(1)
+                .default(PXAR_TAR_DOWNLOAD_DEFAULT_MAX_SIZE as isize)
+                .schema()
+            ),
+            (
+                "zstd",
+                true,
+                &BooleanSchema::new("Compress the tar archive with zstd.")
+                    .default(false)
+                    .schema()
+            ),
+        ]),
+    ),
+)
+.access(
+    Some(
+        "Requires on /datastore/{store}[/{namespace}] either DATASTORE_READ for any or \
+        DATASTORE_BACKUP and being the owner of the group",
+    ),
+    &Permission::Anybody,
+);
+
+pub fn pxar_tar_download(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        let store = required_string_param(&param, "store")?;
+        let ns = optional_ns_param(&param)?;
+
+        let backup_dir: pbs_api_types::BackupDir = Deserialize::deserialize(&param)?;
+        let datastore = check_privs_and_load_store(
+            store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_READ,
+            PRIV_DATASTORE_BACKUP,
+            Some(Operation::Read),
+            &backup_dir.group,
+        )?;
+
+        let backup_dir = datastore.backup_dir(ns, backup_dir)?;
+
+        let filepath = required_string_param(&param, "filepath")?;
+        let max_size = param["max-size"]
+            .as_u64()
+            .unwrap_or(PXAR_TAR_DOWNLOAD_DEFAULT_MAX_SIZE);
+        let zstd = param["zstd"].as_bool().unwrap_or(false);
+
+        let (pxar_name, file_path) = decode_pxar_file_path(filepath)?;
+        let decoder = open_pxar_archive(datastore, &backup_dir, &pxar_name).await?;
+        let root = decoder.open_root().await?;
+        let path = OsStr::from_bytes(&file_path).to_os_string();
+        let file = root
+            .lookup(&path)
+            .await?
+            .ok_or_else(|| format_err!("error opening '{:?}'", path))?;
+
+        if !matches!(file.kind(), EntryKind::Directory) {
+            bail!("'{:?}' is not a directory", path);
+        }
+
+        let options = TarOptions {
+            skip_devices: true,
+            max_size: Some(max_size),
+            ..Default::default()
+        };
+
+        let (sender, receiver) = tokio::sync::mpsc::channel::<Result<_, Error>>(100);
+        let error_sender = sender.clone();
+        let channelwriter = AsyncChannelWriter::new(sender, 1024 * 1024);
+        let tar_path = path.clone();
+        proxmox_rest_server::spawn_internal_task(async move {
+            if let Err(err) =
+                create_tar_with_options(channelwriter, decoder, tar_path, options).await
+            {
+                // abort the response, so that the client does not take a truncated archive
+                // for a complete one
+                let _ = error_sender.send(Err(err)).await;
+            }
+        });
+
+        let (content_type, body) = if zstd {
+            let zstdstream = ZstdEncoder::new(ReceiverStream::new(receiver))?;
+            let body = Body::wrap_stream(zstdstream.map_err(move |err| {
+                log::error!("error during streaming of tar.zst '{:?}' - {}", path, err);
+                err
+            }));
+            ("application/zstd", body)
+        } else {
+            let body = Body::wrap_stream(ReceiverStream::new(receiver).map_err(move |err| {
+                log::error!("error during streaming of tar '{:?}' - {}", path, err);
+                err
+            }));
+            ("application/x-tar", body)
+        };
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .unwrap())
+    }
+    .boxed()
+}
+
 #[api(
     input: {
         properties: {
@@ -1785,6 +2519,12 @@ pub fn pxar_file_download(
             cf: {
                 type: RRDMode,
             },
+            "chunk-traffic": {
+                description: "Include the chunk read/write traffic served by the datastore.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
         },
     },
     access: {
@@ -1797,6 +2537,7 @@ pub fn get_rrd_stats(
     store: String,
     timeframe: RRDTimeFrame,
     cf: RRDMode,
+    chunk_traffic: bool,
     _param: Value,
 ) -> Result<Value, Error> {
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
@@ -1818,6 +2559,15 @@ pub fn get_rrd_stats(
         _ => rrd_fields.push("io_ticks"),
     };
 
+    if chunk_traffic {
+        rrd_fields.extend([
+            "chunk_read_bytes",
+            "chunk_read_count",
+            "chunk_write_bytes",
+            "chunk_write_count",
+        ]);
+    }
+
     create_value_from_rrd(&format!("datastore/{}", store), &rrd_fields, timeframe, cf)
 }
 
@@ -1829,17 +2579,20 @@ pub fn get_rrd_stats(
             },
         },
     },
+    returns: {
+        type: DataStoreUsageEstimate,
+    },
     access: {
-        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, true),
+        permission: &Permission::Privilege(
+            &["datastore", "{store}"], PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP, true),
     },
 )]
-/// Read datastore stats
-pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Error> {
-    let active_operations = task_tracking::get_active_operations(&store)?;
-    Ok(json!({
-        "read": active_operations.read,
-        "write": active_operations.write,
-    }))
+/// Estimate when the datastore is full, including the quality of the estimation.
+///
+/// The lookback window and the damping of steep usage changes can be configured with the
+/// 'estimation-window' and 'estimation-damping' tuning options of the datastore.
+pub fn get_usage_estimate(store: String) -> Result<DataStoreUsageEstimate, Error> {
+    crate::api2::status::datastore_usage_estimate(&store)
 }
 
 #[api(
@@ -1850,40 +2603,472 @@ pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Erro
                 type: BackupNamespace,
                 optional: true,
             },
-            backup_group: {
-                type: pbs_api_types::BackupGroup,
-                flatten: true,
-            },
         },
     },
+    returns: pbs_api_types::ADMIN_DATASTORE_LIST_TRASH_RETURN_TYPE,
     access: {
         permission: &Permission::Anybody,
-        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any\
             or DATASTORE_BACKUP and being the owner of the group",
     },
 )]
-/// Get "notes" for a backup group
-pub fn get_group_notes(
+/// List the snapshots in the trash, optionally only those of a namespace.
+pub async fn list_trash(
     store: String,
     ns: Option<BackupNamespace>,
-    backup_group: pbs_api_types::BackupGroup,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<String, Error> {
+) -> Result<Vec<TrashItem>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
-    let ns = ns.unwrap_or_default();
 
-    let datastore = check_privs_and_load_store(
-        &store,
-        &ns,
-        &auth_id,
-        PRIV_DATASTORE_AUDIT,
-        PRIV_DATASTORE_BACKUP,
-        Some(Operation::Read),
-        &backup_group,
-    )?;
+    tokio::task::spawn_blocking(move || {
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+        let user_info = CachedUserInfo::new()?;
+
+        let mut list = pbs_datastore::trash::list_trash(&datastore)?;
+        list.retain(|item| {
+            if matches!(&ns, Some(ns) if *ns != item.ns) {
+                return false;
+            }
+            let limited = match crate::backup::check_ns_privs_with_user_info(
+                &user_info,
+                &store,
+                &item.ns,
+                &auth_id,
+                PRIV_DATASTORE_AUDIT,
+                PRIV_DATASTORE_BACKUP,
+            ) {
+                Ok(limited) => limited,
+                Err(_) => return false,
+            };
+            !limited
+                || matches!(&item.owner, Some(owner) if check_backup_owner(owner, &auth_id).is_ok())
+        });
+
+        Ok(list)
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: pbs_api_types::BackupDir,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any\
+            or DATASTORE_PRUNE and being the owner of the deleted snapshot",
+    },
+)]
+/// Restore a deleted snapshot from the trash.
+pub async fn restore_trash_snapshot(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_dir: pbs_api_types::BackupDir,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+
+        let limited = check_ns_privs_full(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_PRUNE,
+        )?;
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+        if limited {
+            let item = pbs_datastore::trash::list_trash(&datastore)?
+                .into_iter()
+                .find(|item| item.ns == ns && item.backup == backup_dir)
+                .ok_or_else(|| format_err!("snapshot {backup_dir} not found in trash"))?;
+            match &item.owner {
+                Some(owner) => check_backup_owner(owner, &auth_id)?,
+                None => bail!("owner of deleted snapshot {backup_dir} is unknown"),
+            }
+        }
+
+        pbs_datastore::trash::restore_from_trash(&datastore, &ns, &backup_dir)
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            all: {
+                description: "Purge all snapshots in the trash, not only the expired ones.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Purge the expired snapshots from the trash, or all of them.
+pub fn purge_trash(
+    store: String,
+    all: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "trash-purge",
+        Some(store),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.set_operation_owner(&worker.upid().to_string());
+            let purged = pbs_datastore::trash::purge_trash(&datastore, all)?;
+            task_log!(worker, "purged {purged} snapshots from the trash");
+            Ok(())
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_AUDIT, true),
+    },
+)]
+/// Read datastore stats
+pub fn get_active_operations(store: String, _param: Value) -> Result<Value, Error> {
+    let active_operations = task_tracking::get_active_operations(&store)?;
+    Ok(json!({
+        "read": active_operations.read,
+        "write": active_operations.write,
+    }))
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "maintenance-mode": {
+                description: "The maintenance mode to set, the datastore leaves maintenance mode \
+                    if not set.",
+                optional: true,
+                format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
+                type: String,
+            },
+            timeout: {
+                description: "Wait up to this many seconds for operations which are not allowed \
+                    in the new mode to finish.",
+                type: Integer,
+                minimum: 0,
+                maximum: 3600,
+                optional: true,
+                default: 0,
+            },
+        },
+    },
+    returns: {
+        schema: UPID_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Set or clear the maintenance mode of a datastore.
+///
+/// Operations already running when the mode is set are not interrupted. With a timeout, the task
+/// waits for those not allowed in the new mode to finish, and fails listing the tasks still
+/// running otherwise.
+pub fn set_maintenance_mode(
+    store: String,
+    maintenance_mode: Option<String>,
+    timeout: u64,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let mode = {
+        let _lock = pbs_config::datastore::lock_config()?;
+
+        let (mut config, digest) = pbs_config::datastore::config()?;
+        let mut data: DataStoreConfig = config.lookup("datastore", &store)?;
+
+        if let Some(MaintenanceType::Delete) = data
+            .get_maintenance_mode()
+            .map(|mode| mode.maintenance_type())
+        {
+            http_bail!(CONFLICT, "datastore '{store}' is being deleted");
+        }
+
+        let audit_before = serde_json::to_value(&data)?;
+        data.maintenance_mode = maintenance_mode.clone();
+
+        let mode = data.get_maintenance_mode();
+        if let Some(MaintenanceType::Delete) = mode.as_ref().map(|mode| mode.maintenance_type()) {
+            param_bail!(
+                "maintenance-mode",
+                "the 'delete' maintenance mode is reserved for removing datastores"
+            );
+        }
+
+        config.set_data(&store, "datastore", &data)?;
+        pbs_config::datastore::save_config(&config)?;
+
+        crate::server::audit_log::audit_config_change(
+            &auth_id,
+            &format!("/config/datastore/{store}"),
+            Some(audit_before),
+            Some(serde_json::to_value(&data)?),
+            pbs_config::datastore::DATASTORE_CFG_FILENAME,
+            Some(&digest),
+        );
+
+        mode
+    };
+
+    let upid_str = WorkerTask::new_thread(
+        "maintenance-mode",
+        Some(store.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            match maintenance_mode {
+                Some(mode) => task_log!(worker, "set maintenance mode '{mode}'"),
+                None => task_log!(worker, "cleared maintenance mode"),
+            }
+
+            let deadline = std::time::Instant::now() + Duration::from_secs(timeout);
+            loop {
+                let operations = task_tracking::get_active_operations(&store)?;
+                if !must_drain(mode.as_ref(), &operations) {
+                    task_log!(
+                        worker,
+                        "no operations conflicting with the new mode are active"
+                    );
+                    return Ok(());
+                }
+                if std::time::Instant::now() >= deadline {
+                    for upid in task_tracking::get_active_tasks(&store)? {
+                        task_log!(worker, "still active: {upid}");
+                    }
+                    bail!(
+                        "{} read and {} write operations still active after {timeout} seconds",
+                        operations.read,
+                        operations.write,
+                    );
+                }
+                worker.check_abort()?;
+                std::thread::sleep(Duration::from_millis(500));
+            }
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+/// Whether operations not allowed in the maintenance `mode` are still active.
+///
+/// Operations already running are only counted, so this waits for all kinds the mode rejects.
+fn must_drain(
+    mode: Option<&MaintenanceMode>,
+    operations: &task_tracking::ActiveOperationStats,
+) -> bool {
+    match mode {
+        Some(mode) => {
+            (operations.read > 0 && mode.check(Some(Operation::Read)).is_err())
+                || (operations.write > 0 && mode.check(Some(Operation::Write)).is_err())
+        }
+        None => false,
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get "notes" for a backup group
+pub fn get_group_notes(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<String, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_group,
+    )?;
+
+    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
+    Ok(file_read_optional_string(note_path)?.unwrap_or_else(|| "".to_owned()))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+            notes: {
+                description: "A multiline text.",
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Set "notes" for a backup group
+pub fn set_group_notes(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    notes: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_MODIFY,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Write),
+        &backup_group,
+    )?;
+
+    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
+    replace_file(note_path, notes.as_bytes(), CreateOptions::new(), false)?;
+
+    Ok(())
+}
+
+fn cached_group_dedup_stats(group: &BackupGroup) -> Result<GroupDedupStatsResult, Error> {
+    let stats = group.cached_dedup_stats()?;
+    let age = stats
+        .as_ref()
+        .map(|stats| (proxmox_time::epoch_i64() - stats.computed).max(0));
+
+    Ok(GroupDedupStatsResult {
+        upid: None,
+        stats,
+        age,
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_group: {
+                type: pbs_api_types::BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+    returns: {
+        type: GroupDedupStatsResult,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get the cached chunk deduplication statistics of a backup group.
+pub fn get_group_dedup_stats(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_group: pbs_api_types::BackupGroup,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<GroupDedupStatsResult, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
 
-    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
-    Ok(file_read_optional_string(note_path)?.unwrap_or_else(|| "".to_owned()))
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_group,
+    )?;
+
+    let group = datastore.backup_group(ns, backup_group);
+    if !group.exists() {
+        http_bail!(NOT_FOUND, "backup group '{}' does not exist", group.group());
+    }
+
+    cached_group_dedup_stats(&group)
 }
 
 #[api(
@@ -1898,25 +3083,35 @@ pub fn get_group_notes(
                 type: pbs_api_types::BackupGroup,
                 flatten: true,
             },
-            notes: {
-                description: "A multiline text.",
+            force: {
+                description: "Recompute the statistics even if cached ones exist.",
+                type: bool,
+                optional: true,
+                default: false,
             },
         },
     },
+    returns: {
+        type: GroupDedupStatsResult,
+    },
     access: {
         permission: &Permission::Anybody,
-        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
-            or DATASTORE_BACKUP and being the owner of the group",
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group. Computing new statistics scans \
+            the whole datastore and requires DATASTORE_AUDIT on /datastore/{store}.",
     },
 )]
-/// Set "notes" for a backup group
-pub fn set_group_notes(
+/// Compute the chunk deduplication statistics of a backup group.
+///
+/// Returns the cached statistics if available, unless `force` is set. Otherwise a worker task
+/// is started to compute them, which has to read all index files of the datastore.
+pub fn update_group_dedup_stats(
     store: String,
     ns: Option<BackupNamespace>,
     backup_group: pbs_api_types::BackupGroup,
-    notes: String,
+    force: bool,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<(), Error> {
+) -> Result<GroupDedupStatsResult, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let ns = ns.unwrap_or_default();
 
@@ -1924,16 +3119,299 @@ pub fn set_group_notes(
         &store,
         &ns,
         &auth_id,
-        PRIV_DATASTORE_MODIFY,
+        PRIV_DATASTORE_AUDIT,
         PRIV_DATASTORE_BACKUP,
-        Some(Operation::Write),
+        Some(Operation::Read),
         &backup_group,
     )?;
 
-    let note_path = get_group_note_path(&datastore, &ns, &backup_group);
-    replace_file(note_path, notes.as_bytes(), CreateOptions::new(), false)?;
+    let worker_id = format!(
+        "{}:{}/{}/{}",
+        store,
+        ns.display_as_path(),
+        backup_group.ty,
+        backup_group.id,
+    );
+
+    let group = datastore.backup_group(ns, backup_group);
+    if !group.exists() {
+        http_bail!(NOT_FOUND, "backup group '{}' does not exist", group.group());
+    }
 
-    Ok(())
+    if !force {
+        let cached = cached_group_dedup_stats(&group)?;
+        if cached.stats.is_some() {
+            return Ok(cached);
+        }
+    }
+
+    // the computation reads the index files of all groups of the datastore
+    let user_info = CachedUserInfo::new()?;
+    user_info.check_privs(
+        &auth_id,
+        &["datastore", &store],
+        PRIV_DATASTORE_AUDIT,
+        false,
+    )?;
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "dedup-stats",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.set_operation_owner(&worker.upid().to_string());
+            let stats = group.update_dedup_stats(&*worker)?;
+            task_log!(
+                worker,
+                "referenced: {}, unique: {} ({} chunks), shared: {} ({} chunks)",
+                HumanByte::from(stats.referenced_bytes),
+                HumanByte::from(stats.unique_bytes),
+                stats.unique_chunks,
+                HumanByte::from(stats.shared_bytes),
+                stats.shared_chunks,
+            );
+            Ok(())
+        },
+    )?;
+
+    Ok(GroupDedupStatsResult {
+        upid: Some(upid_str),
+        ..Default::default()
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: OwnerReportResult,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT or \
+            DATASTORE_BACKUP. Reports are stored per user.",
+    },
+)]
+/// Get the last owner report generated by the current user for a namespace subtree.
+pub fn get_owner_report(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<OwnerReportResult, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+    let max_depth = max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+
+    check_ns_privs_full(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+    )?;
+
+    Ok(OwnerReportResult {
+        report: load_owner_report(&store, &auth_id, &ns, max_depth)?,
+        upid: None,
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: OwnerReportResult,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT or \
+            DATASTORE_BACKUP. Only namespaces with DATASTORE_AUDIT are fully included, otherwise \
+            only owned groups in namespaces with DATASTORE_BACKUP.",
+    },
+)]
+/// Generate a report of the groups, snapshots and logical size per backup owner.
+///
+/// Small reports are returned directly, for more groups than the node's
+/// 'owner-report-sync-limit' a worker task is started. Its report can be retrieved afterwards
+/// with a GET request.
+pub fn create_owner_report(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<OwnerReportResult, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+    let max_depth = max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+    ns.check_max_depth(max_depth)?;
+
+    check_ns_privs_full(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+    )?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    let groups = list_report_groups(&datastore, &ns, max_depth, &auth_id)?;
+
+    let (node_config, _digest) = crate::config::node::config()?;
+    let sync_limit = node_config
+        .owner_report_sync_limit
+        .unwrap_or(OWNER_REPORT_SYNC_LIMIT_DEFAULT);
+
+    if groups.len() <= sync_limit {
+        let report = generate_owner_report(ns, max_depth, groups, None)?;
+        store_owner_report(&store, &auth_id, &report)?;
+        return Ok(OwnerReportResult {
+            report: Some(report),
+            upid: None,
+        });
+    }
+
+    let worker_id = format!("{}:{}", store, ns.display_as_path());
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid_str = WorkerTask::new_thread(
+        "owner-report",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| {
+            datastore.set_operation_owner(&worker.upid().to_string());
+            task_log!(
+                worker,
+                "generating owner report for {} groups",
+                groups.len()
+            );
+            let report = generate_owner_report(ns, max_depth, groups, Some(&*worker))?;
+            for usage in report.owners.iter() {
+                task_log!(
+                    worker,
+                    "{}: {} groups, {} snapshots, {}",
+                    usage.owner,
+                    usage.groups,
+                    usage.snapshots,
+                    HumanByte::from(usage.size),
+                );
+            }
+            store_owner_report(&store, &auth_id, &report)
+        },
+    )?;
+
+    Ok(OwnerReportResult {
+        report: None,
+        upid: Some(upid_str),
+    })
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "max-age": {
+                description: "Maximum age of the newest finished snapshot of a group, in seconds.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+                default: GROUP_HEALTH_MAX_AGE_DEFAULT as isize,
+            },
+            ignore: {
+                description: "Groups to skip, for example of decommissioned guests.",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: GROUP_FILTER_SCHEMA,
+                },
+            },
+        },
+    },
+    returns: {
+        type: DataStoreHealth,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT or \
+            DATASTORE_BACKUP. Only namespaces with DATASTORE_AUDIT are fully included, otherwise \
+            only owned groups in namespaces with DATASTORE_BACKUP.",
+    },
+)]
+/// List the groups without a recent, successful backup.
+///
+/// Groups are reported if their newest finished snapshot is older than 'max-age', or if its
+/// verification failed.
+pub fn get_health(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    max_age: Option<i64>,
+    ignore: Option<Vec<GroupFilter>>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<DataStoreHealth, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+    let max_depth = max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+    ns.check_max_depth(max_depth)?;
+
+    let ignore = ignore.unwrap_or_default();
+    if let Some(filter) = ignore.iter().find(|filter| filter.is_exclude) {
+        param_bail!("ignore", "exclude filters are not supported ('{filter}')");
+    }
+
+    check_ns_privs_full(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+    )?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    check_group_health(
+        &datastore,
+        &ns,
+        max_depth,
+        &auth_id,
+        max_age.unwrap_or(GROUP_HEALTH_MAX_AGE_DEFAULT),
+        &ignore,
+    )
 }
 
 #[api(
@@ -2037,7 +3515,55 @@ pub fn set_notes(
         })
         .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
-    Ok(())
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: pbs_api_types::BackupDir,
+                flatten: true,
+            },
+        },
+    },
+    returns: pbs_api_types::ADMIN_DATASTORE_VERIFY_HISTORY_RETURN_TYPE,
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_AUDIT for any \
+            or DATASTORE_BACKUP and being the owner of the group",
+    },
+)]
+/// Get the verification history of a specific backup
+pub fn get_verify_history(
+    store: String,
+    ns: Option<BackupNamespace>,
+    backup_dir: pbs_api_types::BackupDir,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<SnapshotVerifyState>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    let datastore = check_privs_and_load_store(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT,
+        PRIV_DATASTORE_BACKUP,
+        Some(Operation::Read),
+        &backup_dir.group,
+    )?;
+
+    let backup_dir = datastore.backup_dir(ns, backup_dir)?;
+
+    let (manifest, _) = backup_dir.load_manifest()?;
+
+    Ok(manifest.verify_history())
 }
 
 #[api(
@@ -2136,6 +3662,178 @@ pub async fn set_protection(
     .await?
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "newer-than": {
+                description: "Only include snapshots with a backup time at or after this epoch.",
+                type: Integer,
+                optional: true,
+            },
+            "older-than": {
+                description: "Only include snapshots with a backup time before this epoch.",
+                type: Integer,
+                optional: true,
+            },
+            protected: {
+                description: "Enable/disable protection.",
+            },
+        },
+    },
+    returns: {
+        type: ProtectionBulkResult,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{namespace}] either DATASTORE_MODIFY for any \
+            or DATASTORE_BACKUP and being the owner of the group. Other groups are skipped.",
+    },
+)]
+/// En- or disable protection for all snapshots matching the given filters.
+pub async fn set_protection_bulk(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    group_filter: Option<Vec<GroupFilter>>,
+    newer_than: Option<i64>,
+    older_than: Option<i64>,
+    protected: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ProtectionBulkResult, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+        let max_depth = max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+        ns.check_max_depth(max_depth)?;
+
+        check_ns_privs_full(
+            &store,
+            &ns,
+            &auth_id,
+            PRIV_DATASTORE_MODIFY,
+            PRIV_DATASTORE_BACKUP,
+        )?;
+
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+        let mut result = ProtectionBulkResult::default();
+
+        for group in ListAccessibleBackupGroups::new_with_privs(
+            &datastore,
+            ns,
+            max_depth,
+            Some(PRIV_DATASTORE_MODIFY),
+            Some(PRIV_DATASTORE_BACKUP),
+            Some(&auth_id),
+        )? {
+            let group = group?;
+            if let Some(filters) = &group_filter {
+                if !group.group().apply_filters(filters) {
+                    continue;
+                }
+            }
+
+            for info in group.list_backups()? {
+                let backup_time = info.backup_dir.backup_time();
+                if newer_than.map(|time| backup_time < time).unwrap_or(false)
+                    || older_than.map(|time| backup_time >= time).unwrap_or(false)
+                {
+                    continue;
+                }
+
+                if !info.is_finished() {
+                    result.skipped += 1;
+                    continue;
+                }
+
+                if info.backup_dir.is_protected() == protected {
+                    result.unchanged += 1;
+                    continue;
+                }
+
+                match datastore.update_protection(&info.backup_dir, protected) {
+                    Ok(()) => result.changed += 1,
+                    Err(err) => {
+                        log::warn!(
+                            "could not update protection of {}: {err}",
+                            print_ns_and_snapshot(
+                                info.backup_dir.backup_ns(),
+                                info.backup_dir.as_ref()
+                            ),
+                        );
+                        result.skipped += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            repair: {
+                description: "Repair the inconsistencies which can be fixed safely.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "default-owner": {
+                type: Authid,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "List of inconsistencies found.",
+        type: Array,
+        items: { type: ScrubMetaEntry },
+    },
+    access: {
+        permission: &Permission::Privilege(&["datastore", "{store}"], PRIV_DATASTORE_MODIFY, false),
+    },
+)]
+/// Check the directory structure of a datastore for orphaned or incomplete groups, snapshots
+/// and namespaces.
+///
+/// With `repair`, empty group directories are removed and missing owner files are recreated
+/// using `default-owner`. Snapshots without manifest and too deep namespaces are only reported.
+pub async fn scrub_meta(
+    store: String,
+    repair: bool,
+    default_owner: Option<Authid>,
+) -> Result<Vec<ScrubMetaEntry>, Error> {
+    tokio::task::spawn_blocking(move || {
+        let operation = if repair {
+            Operation::Write
+        } else {
+            Operation::Read
+        };
+        let datastore = DataStore::lookup_datastore(&store, Some(operation))?;
+
+        datastore.scrub_metadata(repair, default_owner.as_ref())
+    })
+    .await?
+}
+
 #[api(
     input: {
         properties: {
@@ -2239,6 +3937,204 @@ pub async fn set_backup_owner(
     .await?
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "new-owner": {
+                type: Authid,
+            },
+            "expected-owner": {
+                type: Authid,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        description: "Per-group result of the owner change.",
+        type: Array,
+        items: { type: GroupOwnerChangeResult },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Datastore.Modify on the namespaces of the affected groups. Groups in \
+            namespaces without that privilege are skipped.",
+    },
+)]
+/// Change the owner of all backup groups matching the given filters.
+///
+/// Groups whose current owner does not match `expected-owner` (if given) are left untouched.
+pub async fn set_backup_owner_bulk(
+    store: String,
+    ns: Option<BackupNamespace>,
+    max_depth: Option<usize>,
+    group_filter: Option<Vec<GroupFilter>>,
+    new_owner: Authid,
+    expected_owner: Option<Authid>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<GroupOwnerChangeResult>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    tokio::task::spawn_blocking(move || {
+        let ns = ns.unwrap_or_default();
+        let max_depth = max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+        ns.check_max_depth(max_depth)?;
+
+        check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_MODIFY)?;
+
+        let user_info = CachedUserInfo::new()?;
+        if !user_info.is_active_auth_id(&new_owner) {
+            bail!(
+                "{} '{}' is inactive or non-existent",
+                if new_owner.is_token() {
+                    "API token"
+                } else {
+                    "user"
+                },
+                new_owner
+            );
+        }
+
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+        let mut result = Vec::new();
+        for group in ListAccessibleBackupGroups::new_with_privs(
+            &datastore,
+            ns,
+            max_depth,
+            Some(PRIV_DATASTORE_MODIFY),
+            Some(PRIV_DATASTORE_MODIFY), // never allow owner-only access
+            Some(&auth_id),
+        )? {
+            let group = group?;
+            if let Some(filters) = &group_filter {
+                if !group.group().apply_filters(filters) {
+                    continue;
+                }
+            }
+
+            let mut item = GroupOwnerChangeResult {
+                backup: group.group().clone(),
+                ns: Some(group.backup_ns().clone()).filter(|ns| !ns.is_root()),
+                owner: None,
+                success: false,
+                error: None,
+            };
+
+            match group.change_owner(&new_owner, expected_owner.as_ref()) {
+                Ok(owner) => {
+                    item.owner = Some(owner);
+                    item.success = true;
+                }
+                Err(err) => {
+                    item.owner = group.get_owner().ok();
+                    item.error = Some(err.to_string());
+                }
+            }
+            result.push(item);
+        }
+
+        Ok(result)
+    })
+    .await?
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            upid: { schema: UPID_SCHEMA },
+        },
+    },
+    returns: {
+        type: VerifyTaskStatus,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Users can access their own tasks, or need Sys.Audit on /system/tasks.",
+    },
+)]
+/// Get the progress of a running verify task, or the per-snapshot results of a finished one.
+///
+/// Results of finished tasks are only kept for a limited number of tasks and until the next
+/// restart of the proxy.
+pub fn get_verify_status(
+    store: String,
+    upid: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<VerifyTaskStatus, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let upid_parsed: UPID = upid.parse()?;
+
+    check_task_access(&auth_id, &upid_parsed)?;
+
+    if !check_job_store(&upid_parsed, &store) {
+        bail!("task '{upid}' does not belong to datastore '{store}'");
+    }
+
+    crate::backup::verify_task_status(&upid)
+        .ok_or_else(|| http_err!(NOT_FOUND, "no verify status available for task '{upid}'"))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            upid: { schema: UPID_SCHEMA },
+        },
+    },
+    returns: {
+        description: "Snapshots referencing chunks which were found to be bad.",
+        type: Array,
+        items: {
+            type: VerifyBadChunkReference,
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Users can access their own tasks, or need Sys.Audit on /system/tasks.",
+    },
+)]
+/// Get the snapshots referencing chunks which a finished datastore verify task found to be bad.
+///
+/// The report is only available if the task searched the whole datastore for bad chunk
+/// references, and is kept as long as the task's verify status.
+pub fn get_verify_bad_chunks(
+    store: String,
+    upid: String,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<VerifyBadChunkReference>, Error> {
+    let status = get_verify_status(store, upid.clone(), rpcenv)?;
+
+    if status.running {
+        http_bail!(BAD_REQUEST, "verify task '{upid}' is still running");
+    }
+
+    status
+        .bad_chunk_report
+        .ok_or_else(|| http_err!(NOT_FOUND, "no bad chunk report available for task '{upid}'"))
+}
+
+#[sortable]
+const GROUP_SUBDIRS: SubdirMap = &[(
+    "dedup-stats",
+    &Router::new()
+        .get(&API_METHOD_GET_GROUP_DEDUP_STATS)
+        .post(&API_METHOD_UPDATE_GROUP_DEDUP_STATS),
+)];
+
 #[sortable]
 const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
     (
@@ -2250,6 +4146,10 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "change-owner",
         &Router::new().post(&API_METHOD_SET_BACKUP_OWNER),
     ),
+    (
+        "change-owner-bulk",
+        &Router::new().post(&API_METHOD_SET_BACKUP_OWNER_BULK),
+    ),
     (
         "download",
         &Router::new().download(&API_METHOD_DOWNLOAD_FILE),
@@ -2275,8 +4175,18 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
         "groups",
         &Router::new()
             .get(&API_METHOD_LIST_GROUPS)
-            .delete(&API_METHOD_DELETE_GROUP),
+            .delete(&API_METHOD_DELETE_GROUP)
+            .match_all(
+                "backup-type",
+                &Router::new().match_all("backup-id", &Router::new().subdirs(GROUP_SUBDIRS)),
+            ),
+    ),
+    ("health", &Router::new().get(&API_METHOD_GET_HEALTH)),
+    (
+        "maintenance",
+        &Router::new().put(&API_METHOD_SET_MAINTENANCE_MODE),
     ),
+    ("move-group", &Router::new().post(&API_METHOD_MOVE_GROUP)),
     (
         "namespace",
         // FIXME: move into datastore:: sub-module?!
@@ -2288,34 +4198,83 @@ const DATASTORE_INFO_SUBDIRS: SubdirMap = &[
             .get(&API_METHOD_GET_NOTES)
             .put(&API_METHOD_SET_NOTES),
     ),
+    (
+        "owner-report",
+        &Router::new()
+            .get(&API_METHOD_GET_OWNER_REPORT)
+            .post(&API_METHOD_CREATE_OWNER_REPORT),
+    ),
     (
         "protected",
         &Router::new()
             .get(&API_METHOD_GET_PROTECTION)
             .put(&API_METHOD_SET_PROTECTION),
     ),
+    (
+        "protect-bulk",
+        &Router::new().post(&API_METHOD_SET_PROTECTION_BULK),
+    ),
     ("prune", &Router::new().post(&API_METHOD_PRUNE)),
     (
         "prune-datastore",
         &Router::new().post(&API_METHOD_PRUNE_DATASTORE),
     ),
+    (
+        "prune-simulate",
+        &Router::new().get(&API_METHOD_PRUNE_SIMULATE),
+    ),
     (
         "pxar-file-download",
         &Router::new().download(&API_METHOD_PXAR_FILE_DOWNLOAD),
     ),
+    (
+        "pxar-tar-download",
+        &Router::new().download(&API_METHOD_PXAR_TAR_DOWNLOAD),
+    ),
     ("rrd", &Router::new().get(&API_METHOD_GET_RRD_STATS)),
+    ("scrub-meta", &Router::new().post(&API_METHOD_SCRUB_META)),
     (
         "snapshots",
         &Router::new()
             .get(&API_METHOD_LIST_SNAPSHOTS)
             .delete(&API_METHOD_DELETE_SNAPSHOT),
     ),
+    (
+        "snapshots-stream",
+        &Router::new().download(&API_METHOD_STREAM_SNAPSHOTS),
+    ),
     ("status", &Router::new().get(&API_METHOD_STATUS)),
+    (
+        "trash",
+        &Router::new()
+            .get(&API_METHOD_LIST_TRASH)
+            .post(&API_METHOD_RESTORE_TRASH_SNAPSHOT)
+            .delete(&API_METHOD_PURGE_TRASH),
+    ),
     (
         "upload-backup-log",
         &Router::new().upload(&API_METHOD_UPLOAD_BACKUP_LOG),
     ),
+    (
+        "usage-estimate",
+        &Router::new().get(&API_METHOD_GET_USAGE_ESTIMATE),
+    ),
     ("verify", &Router::new().post(&API_METHOD_VERIFY)),
+    (
+        "verify-bad-chunks",
+        &Router::new().match_all(
+            "upid",
+            &Router::new().get(&API_METHOD_GET_VERIFY_BAD_CHUNKS),
+        ),
+    ),
+    (
+        "verify-history",
+        &Router::new().get(&API_METHOD_GET_VERIFY_HISTORY),
+    ),
+    (
+        "verify-status",
+        &Router::new().match_all("upid", &Router::new().get(&API_METHOD_GET_VERIFY_STATUS)),
+    ),
 ];
 
 const DATASTORE_INFO_ROUTER: Router = Router::new()
@@ -2325,3 +4284,31 @@ const DATASTORE_INFO_ROUTER: Router = Router::new()
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_GET_DATASTORE_LIST)
     .match_all("store", &DATASTORE_INFO_ROUTER);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mode(ty: &str) -> MaintenanceMode {
+        serde_json::from_value(json!({ "type": ty })).unwrap()
+    }
+
+    fn operations(read: i64, write: i64) -> task_tracking::ActiveOperationStats {
+        task_tracking::ActiveOperationStats { read, write }
+    }
+
+    #[test]
+    fn test_must_drain() {
+        assert!(!must_drain(None, &operations(3, 2)));
+
+        let read_only = mode("read-only");
+        assert!(!must_drain(Some(&read_only), &operations(0, 0)));
+        assert!(!must_drain(Some(&read_only), &operations(2, 0)));
+        assert!(must_drain(Some(&read_only), &operations(0, 1)));
+
+        let offline = mode("offline");
+        assert!(!must_drain(Some(&offline), &operations(0, 0)));
+        assert!(must_drain(Some(&offline), &operations(1, 0)));
+        assert!(must_drain(Some(&offline), &operations(0, 1)));
+    }
+}
@@ -1,13 +1,24 @@
-use anyhow::{bail, Error};
+use std::collections::HashMap;
+
+use anyhow::{bail, format_err, Error};
 use serde_json::Value;
 
+use proxmox_human_byte::HumanByte;
+
 use pbs_config::CachedUserInfo;
-use proxmox_router::{http_bail, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_rest_server::WorkerTask;
+use proxmox_router::{
+    http_bail, ApiMethod, Permission, Router, RpcEnvironment, RpcEnvironmentType, SubdirMap,
+};
 use proxmox_schema::*;
+use proxmox_sortable_macro::sortable;
+use proxmox_sys::{task_log, WorkerTaskContext};
 
 use pbs_api_types::{
-    Authid, BackupNamespace, NamespaceListItem, Operation, DATASTORE_SCHEMA, NS_MAX_DEPTH_SCHEMA,
-    PROXMOX_SAFE_ID_FORMAT,
+    print_ns_and_snapshot, print_store_and_ns, Authid, BackupNamespace, KeepOptions,
+    NamespaceDeleteSummary, NamespaceListItem, NamespaceQuotaStatus, NamespaceVerifyNewStatus,
+    Operation, DATASTORE_SCHEMA, NS_MAX_DEPTH_SCHEMA, PRIV_DATASTORE_ALLOCATE,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_SAFE_ID_FORMAT,
 };
 
 use pbs_datastore::DataStore;
@@ -107,8 +118,24 @@ pub fn list_namespaces(
         Err(err) => return Err(err),
     };
 
-    let ns_to_item =
-        |ns: BackupNamespace| -> NamespaceListItem { NamespaceListItem { ns, comment: None } };
+    let recorded_usage = datastore.recorded_namespace_usage().unwrap_or_else(|err| {
+        log::warn!("unable to load namespace usage of datastore '{store}' - {err}");
+        HashMap::new()
+    });
+
+    let ns_to_item = |ns: BackupNamespace| -> NamespaceListItem {
+        let quota = datastore.namespace_quota(&ns).unwrap_or_else(|err| {
+            log::warn!("{err}");
+            None
+        });
+        let usage = recorded_usage.get(&ns.to_string()).copied();
+        NamespaceListItem {
+            ns,
+            comment: None,
+            quota,
+            usage,
+        }
+    };
 
     let namespace_list: Vec<NamespaceListItem> = iter
         .filter(|ns| {
@@ -134,42 +161,532 @@ pub fn list_namespaces(
             "delete-groups": {
                 type: bool,
                 description: "If set, all groups will be destroyed in the whole hierarchy below and\
-                    including `ns`. If not set, only empty namespaces will be pruned.",
+                    including `ns`. If not set, the deletion is refused if any group exists in\
+                    that hierarchy.",
+                optional: true,
+                default: false,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "ignore-protected": {
+                type: bool,
+                description: "Skip protected snapshots instead of refusing the deletion. Their \
+                    groups and namespaces are kept.",
                 optional: true,
                 default: false,
             },
         },
     },
+    returns: {
+        type: NamespaceDeleteSummary,
+    },
     access: {
         permission: &Permission::Anybody,
     },
 )]
 /// Delete a backup namespace including all snapshots.
+///
+/// Namespaces are processed depth first, with `max-depth` limiting how deep below `ns` groups and
+/// namespaces get removed. A namespace is only removed if nothing is left in it.
 pub fn delete_namespace(
     store: String,
     ns: BackupNamespace,
     delete_groups: bool,
+    max_depth: Option<usize>,
+    ignore_protected: bool,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Value, Error> {
+) -> Result<NamespaceDeleteSummary, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
     check_ns_modification_privs(&store, &ns, &auth_id)?;
 
     let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
 
-    if !datastore.remove_namespace_recursive(&ns, delete_groups)? {
-        if delete_groups {
-            bail!("group only partially deleted due to protected snapshots");
-        } else {
-            bail!("only partially deleted due to existing groups but `delete-groups` not true ");
+    let mut namespaces: Vec<BackupNamespace> = datastore
+        .recursive_iter_backup_ns(ns.clone())?
+        .filter(|child| match (child, max_depth) {
+            (Ok(child), Some(max_depth)) => child.depth() - ns.depth() <= max_depth,
+            _ => true,
+        })
+        .collect::<Result<_, Error>>()?;
+    namespaces.sort_by_key(|ns| std::cmp::Reverse(ns.depth()));
+
+    let mut groups = Vec::new();
+    for child in namespaces.iter() {
+        for group in datastore.iter_backup_groups(child.clone())? {
+            groups.push(group?);
+        }
+    }
+
+    if !delete_groups && !groups.is_empty() {
+        bail!(
+            "namespace '{ns}' contains {} backup groups in its hierarchy, set 'delete-groups' \
+            to remove them",
+            groups.len(),
+        );
+    }
+
+    let mut protected = Vec::new();
+    for group in groups.iter() {
+        for snapshot in group.iter_snapshots()? {
+            let snapshot = snapshot?;
+            if snapshot.is_protected() {
+                protected.push(print_ns_and_snapshot(
+                    snapshot.backup_ns(),
+                    snapshot.as_ref(),
+                ));
+            }
         }
     }
 
-    Ok(Value::Null)
+    if !protected.is_empty() && !ignore_protected {
+        bail!(
+            "namespace '{ns}' contains {} protected snapshots, set 'ignore-protected' to keep \
+            them and delete everything else: {}",
+            protected.len(),
+            protected.join(", "),
+        );
+    }
+
+    let worker_id = format!("{store}:{ns}");
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    // We use a WorkerTask just to have a task log, but run synchrounously
+    let worker = WorkerTask::new(
+        "delete-namespace",
+        Some(worker_id),
+        auth_id.to_string(),
+        to_stdout,
+    )?;
+
+    let result = proxmox_lang::try_block!({
+        let mut summary = NamespaceDeleteSummary {
+            skipped_protected: protected,
+            ..Default::default()
+        };
+
+        task_log!(
+            worker,
+            "deleting {} namespaces and {} backup groups below {}",
+            namespaces.len(),
+            groups.len(),
+            print_store_and_ns(&store, &ns),
+        );
+
+        for child in namespaces.iter() {
+            for group in groups.iter().filter(|group| group.backup_ns() == child) {
+                worker.check_abort()?;
+
+                let snapshots = group.list_backups()?;
+                let kept = snapshots.iter().filter(|info| info.protected).count();
+
+                task_log!(
+                    worker,
+                    "removing group {child}:{} ({} snapshots)",
+                    group.group(),
+                    snapshots.len() - kept,
+                );
+                if group.destroy()? {
+                    summary.removed_groups += 1;
+                }
+                summary.removed_snapshots += (snapshots.len() - kept) as u64;
+            }
+
+            if datastore.remove_empty_namespace(child)? {
+                task_log!(worker, "removed namespace '{child}'");
+                summary.removed_namespaces += 1;
+            } else if !child.is_root() {
+                task_log!(worker, "kept non-empty namespace '{child}'");
+            }
+        }
+
+        task_log!(
+            worker,
+            "removed {} groups, {} snapshots and {} namespaces, skipped {} protected snapshots",
+            summary.removed_groups,
+            summary.removed_snapshots,
+            summary.removed_namespaces,
+            summary.skipped_protected.len(),
+        );
+
+        Ok::<_, Error>(summary)
+    });
+
+    worker.log_result(
+        &result
+            .as_ref()
+            .map(|_| ())
+            .map_err(|err| format_err!("{err}")),
+    );
+
+    result
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        type: KeepOptions,
+        optional: true,
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_AUDIT or DATASTORE_MODIFY",
+    },
+)]
+/// Get the prune options stored on a namespace.
+pub fn get_prune_options(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<KeepOptions>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY,
+    )?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    if !datastore.namespace_exists(&ns) {
+        http_bail!(NOT_FOUND, "namespace '{ns}' does not exist");
+    }
+
+    datastore.namespace_prune_options(&ns)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "keep-options": {
+                type: KeepOptions,
+                flatten: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_MODIFY",
+    },
+)]
+/// Set the prune options of a namespace.
+///
+/// Prune jobs use the options of the closest namespace up the hierarchy which has options set,
+/// falling back to the options of the job itself.
+pub fn set_prune_options(
+    store: String,
+    ns: Option<BackupNamespace>,
+    keep_options: KeepOptions,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_MODIFY)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_prune_options(&ns, Some(&keep_options))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_MODIFY",
+    },
+)]
+/// Remove the prune options of a namespace.
+pub fn delete_prune_options(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_MODIFY)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_prune_options(&ns, None)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    returns: { type: NamespaceQuotaStatus },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires DATASTORE_AUDIT, DATASTORE_MODIFY or DATASTORE_BACKUP on \
+            /datastore/{store}[/{ns}]",
+    },
+)]
+/// Get the quota and the recorded logical usage of a namespace.
+pub fn get_quota(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<NamespaceQuotaStatus, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, NS_PRIVS_OK)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    if !datastore.namespace_exists(&ns) {
+        http_bail!(NOT_FOUND, "namespace '{ns}' does not exist");
+    }
+
+    datastore.namespace_quota_status(&ns)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            quota: {
+                type: HumanByte,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_ALLOCATE",
+    },
+)]
+/// Set the quota of a namespace.
+///
+/// New backups into the namespace or any of its children are refused once the logical usage
+/// recorded by the last garbage collection exceeds the quota.
+pub fn set_quota(
+    store: String,
+    ns: Option<BackupNamespace>,
+    quota: HumanByte,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_ALLOCATE)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_quota(&ns, Some(quota.as_u64()))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_ALLOCATE",
+    },
+)]
+/// Remove the quota of a namespace.
+pub fn delete_quota(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_ALLOCATE)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_quota(&ns, None)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    returns: { type: NamespaceVerifyNewStatus },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_AUDIT or DATASTORE_MODIFY",
+    },
+)]
+/// Get the verify-new setting of a namespace and the effective setting for new snapshots in it.
+pub fn get_verify_new(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<NamespaceVerifyNewStatus, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(
+        &store,
+        &ns,
+        &auth_id,
+        PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY,
+    )?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+
+    if !datastore.namespace_exists(&ns) {
+        http_bail!(NOT_FOUND, "namespace '{ns}' does not exist");
+    }
+
+    let (effective, source) = datastore.lookup_namespace_verify_new(&ns)?;
+
+    Ok(NamespaceVerifyNewStatus {
+        verify_new: datastore.namespace_verify_new(&ns)?,
+        effective,
+        source,
+    })
 }
 
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "verify-new": {
+                description: "Verify new snapshots in the namespace and its children right after \
+                    the backup finished.",
+                type: bool,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_MODIFY",
+    },
+)]
+/// Set the verify-new setting of a namespace.
+///
+/// Overrides the setting of the parent namespaces and the datastore for new snapshots in the
+/// namespace and all its children, unless they have their own setting.
+pub fn set_verify_new(
+    store: String,
+    ns: Option<BackupNamespace>,
+    verify_new: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_MODIFY)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_verify_new(&ns, Some(verify_new))
+}
+
+#[api(
+    input: {
+        properties: {
+            store: { schema: DATASTORE_SCHEMA },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Requires on /datastore/{store}[/{ns}] DATASTORE_MODIFY",
+    },
+)]
+/// Remove the verify-new setting of a namespace, so that it is inherited again.
+pub fn delete_verify_new(
+    store: String,
+    ns: Option<BackupNamespace>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let ns = ns.unwrap_or_default();
+
+    check_ns_privs(&store, &ns, &auth_id, PRIV_DATASTORE_MODIFY)?;
+
+    let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+
+    datastore.set_namespace_verify_new(&ns, None)
+}
+
+const PRUNE_OPTIONS_ROUTER: Router = Router::new()
+    .get(&API_METHOD_GET_PRUNE_OPTIONS)
+    .put(&API_METHOD_SET_PRUNE_OPTIONS)
+    .delete(&API_METHOD_DELETE_PRUNE_OPTIONS);
+
+const QUOTA_ROUTER: Router = Router::new()
+    .get(&API_METHOD_GET_QUOTA)
+    .put(&API_METHOD_SET_QUOTA)
+    .delete(&API_METHOD_DELETE_QUOTA);
+
+const VERIFY_NEW_ROUTER: Router = Router::new()
+    .get(&API_METHOD_GET_VERIFY_NEW)
+    .put(&API_METHOD_SET_VERIFY_NEW)
+    .delete(&API_METHOD_DELETE_VERIFY_NEW);
+
+#[sortable]
+const SUBDIRS: SubdirMap = &sorted!([
+    ("prune-options", &PRUNE_OPTIONS_ROUTER),
+    ("quota", &QUOTA_ROUTER),
+    ("verify-new", &VERIFY_NEW_ROUTER),
+]);
+
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_NAMESPACES)
     .post(&API_METHOD_CREATE_NAMESPACE)
-    .delete(&API_METHOD_DELETE_NAMESPACE);
+    .delete(&API_METHOD_DELETE_NAMESPACE)
+    .subdirs(SUBDIRS);
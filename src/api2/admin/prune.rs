@@ -18,7 +18,7 @@ use pbs_config::CachedUserInfo;
 
 use crate::server::{
     do_prune_job,
-    jobstate::{compute_schedule_status, Job, JobState},
+    jobstate::{compute_schedule_status, waiting_for_group, Job, JobState},
 };
 
 #[api(
@@ -77,6 +77,7 @@ pub fn list_prune_jobs(
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
         let mut status = compute_schedule_status(&last_state, Some(&job.schedule))?;
+        status.waiting_for_group = waiting_for_group("prunejob", &job.id);
         if job.disable {
             status.next_run = None;
         }
@@ -19,7 +19,7 @@ use pbs_config::CachedUserInfo;
 
 use crate::server::{
     do_verification_job,
-    jobstate::{compute_schedule_status, Job, JobState},
+    jobstate::{compute_schedule_status, waiting_for_group, Job, JobState},
 };
 
 #[api(
@@ -76,7 +76,8 @@ pub fn list_verification_jobs(
         let last_state = JobState::load("verificationjob", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        status.waiting_for_group = waiting_for_group("verificationjob", &job.id);
 
         list.push(VerificationJobStatus {
             config: job,
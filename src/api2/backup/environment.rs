@@ -1,5 +1,4 @@
 use anyhow::{bail, format_err, Error};
-use nix::dir::Dir;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -7,13 +6,14 @@ use ::serde::Serialize;
 use serde_json::{json, Value};
 
 use proxmox_router::{RpcEnvironment, RpcEnvironmentType};
-use proxmox_sys::fs::{lock_dir_noblock_shared, replace_file, CreateOptions};
+use proxmox_sys::fs::{replace_file, CreateOptions};
 
-use pbs_api_types::Authid;
+use pbs_api_types::{Authid, DatastoreFSyncLevel, SnapshotUploadInfo, WireCompression};
 use pbs_datastore::backup_info::{BackupDir, BackupInfo};
 use pbs_datastore::dynamic_index::DynamicIndexWriter;
 use pbs_datastore::fixed_index::FixedIndexWriter;
-use pbs_datastore::{DataBlob, DataStore};
+use pbs_datastore::lock_tracking::{lock_dir_noblock_shared, DirLockGuard};
+use pbs_datastore::{task_io_stats, DataBlob, DataStore};
 use proxmox_rest_server::{formatter::*, WorkerTask};
 
 use crate::backup::verify_backup_dir_with_lock;
@@ -83,6 +83,7 @@ struct SharedBackupState {
     known_chunks: KnownChunksMap,
     backup_size: u64, // sums up size of all files
     backup_stat: UploadStatistic,
+    reused_size: u64, // sums up size of index data not uploaded
 }
 
 impl SharedBackupState {
@@ -113,6 +114,10 @@ pub struct BackupEnvironment {
     pub datastore: Arc<DataStore>,
     pub backup_dir: BackupDir,
     pub last_backup: Option<BackupInfo>,
+    pub client_version: Option<String>,
+    pub client_hostname: Option<String>,
+    pub wire_compression: WireCompression,
+    start_time: i64,
     state: Arc<Mutex<SharedBackupState>>,
 }
 
@@ -133,6 +138,7 @@ impl BackupEnvironment {
             known_chunks: HashMap::new(),
             backup_size: 0,
             backup_stat: UploadStatistic::new(),
+            reused_size: 0,
         };
 
         Self {
@@ -145,6 +151,10 @@ impl BackupEnvironment {
             formatter: JSON_FORMATTER,
             backup_dir,
             last_backup: None,
+            client_version: None,
+            client_hostname: None,
+            wire_compression: WireCompression::None,
+            start_time: proxmox_time::epoch_i64(),
             state: Arc::new(Mutex::new(state)),
         }
     }
@@ -484,6 +494,7 @@ impl BackupEnvironment {
 
         state.file_counter += 1;
         state.backup_size += size;
+        state.reused_size += size.saturating_sub(data.upload_stat.size);
         state.backup_stat = state.backup_stat + data.upload_stat;
 
         Ok(())
@@ -558,6 +569,7 @@ impl BackupEnvironment {
 
         state.file_counter += 1;
         state.backup_size += size;
+        state.reused_size += size.saturating_sub(data.upload_stat.size);
         state.backup_stat = state.backup_stat + data.upload_stat;
 
         Ok(())
@@ -575,7 +587,9 @@ impl BackupEnvironment {
         let blob = DataBlob::load_from_reader(&mut &data[..])?;
 
         let raw_data = blob.raw_data();
-        replace_file(&path, raw_data, CreateOptions::new(), false)?;
+        let fsync = self.datastore.sync_level() == DatastoreFSyncLevel::File;
+        replace_file(&path, raw_data, CreateOptions::new(), fsync)?;
+        task_io_stats::account_write(raw_data.len() as u64);
 
         self.log(format!(
             "add blob {:?} ({} bytes, comp: {})",
@@ -607,9 +621,17 @@ impl BackupEnvironment {
 
         // check for valid manifest and store stats
         let stats = serde_json::to_value(state.backup_stat)?;
+        let upload_info = serde_json::to_value(SnapshotUploadInfo {
+            client_version: self.client_version.clone(),
+            client_hostname: self.client_hostname.clone(),
+            duration: Some(proxmox_time::epoch_i64() - self.start_time),
+            upload_size: Some(state.backup_stat.size),
+            reused_size: Some(state.reused_size),
+        })?;
         self.backup_dir
             .update_manifest(|manifest| {
                 manifest.unprotected["chunk_upload_stats"] = stats;
+                manifest.unprotected["upload_info"] = upload_info;
             })
             .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
@@ -623,7 +645,10 @@ impl BackupEnvironment {
             }
         }
 
-        self.datastore.try_ensure_sync_level()?;
+        // the snapshot must only count as finished once it reached the configured durability
+        let sync_level = self.datastore.sync_level();
+        self.datastore.sync_snapshot(&self.backup_dir)?;
+        self.log(format!("effective sync level: {sync_level}"));
 
         // marks the backup as successful
         state.finished = true;
@@ -634,13 +659,34 @@ impl BackupEnvironment {
     /// If verify-new is set on the datastore, this will run a new verify task
     /// for the backup. If not, this will return and also drop the passed lock
     /// immediately.
-    pub fn verify_after_complete(&self, excl_snap_lock: Dir) -> Result<(), Error> {
+    pub fn verify_after_complete(&self, excl_snap_lock: DirLockGuard) -> Result<(), Error> {
         self.ensure_finished()?;
 
-        if !self.datastore.verify_new() {
-            // no verify requested, do nothing
+        let backup_ns = self.backup_dir.backup_ns();
+        let (verify_new, source) = match self.datastore.lookup_namespace_verify_new(backup_ns) {
+            Ok(result) => result,
+            Err(err) => {
+                self.log(format!(
+                    "could not read verify-new setting of namespace, using datastore setting - {err}"
+                ));
+                (self.datastore.verify_new(), None)
+            }
+        };
+        let level = match source {
+            Some(ns) if ns.is_root() => "root namespace".to_string(),
+            Some(ns) => format!("namespace '{ns}'"),
+            None => "datastore configuration".to_string(),
+        };
+
+        if !verify_new {
+            self.log(format!(
+                "verify-new disabled by {level}, skipping verification"
+            ));
             return Ok(());
         }
+        self.log(format!(
+            "verify-new enabled by {level}, starting verification"
+        ));
 
         // Downgrade to shared lock, the backup itself is finished
         drop(excl_snap_lock);
@@ -667,6 +713,7 @@ impl BackupEnvironment {
             self.auth_id.to_string(),
             false,
             move |worker| {
+                snap_lock.set_owner(Some(&worker.upid().to_string()), "verify");
                 worker.log_message("Automatically verifying newly added snapshot");
 
                 let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
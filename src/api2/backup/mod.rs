@@ -1,5 +1,7 @@
 //! Backup protocol (HTTP2 upgrade)
 
+use std::path::Path;
+
 use anyhow::{bail, format_err, Error};
 use futures::*;
 use hex::FromHex;
@@ -9,7 +11,7 @@ use hyper::{Body, Request, Response, StatusCode};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
-use proxmox_router::{http_err, list_subdirs_api_method};
+use proxmox_router::list_subdirs_api_method;
 use proxmox_router::{
     ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment, SubdirMap,
 };
@@ -19,15 +21,24 @@ use proxmox_sortable_macro::sortable;
 use pbs_api_types::{
     Authid, BackupNamespace, BackupType, Operation, SnapshotVerifyState, VerifyState,
     BACKUP_ARCHIVE_NAME_SCHEMA, BACKUP_ID_SCHEMA, BACKUP_NAMESPACE_SCHEMA, BACKUP_TIME_SCHEMA,
-    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, PRIV_DATASTORE_BACKUP,
+    BACKUP_TYPE_SCHEMA, CHUNK_DIGEST_SCHEMA, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
+    PRIV_DATASTORE_BACKUP,
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::index::IndexFile;
-use pbs_datastore::manifest::{archive_type, ArchiveType};
-use pbs_datastore::{DataStore, PROXMOX_BACKUP_PROTOCOL_ID_V1};
+use pbs_datastore::lock_tracking::{
+    is_lock_contention, list_lock_holders, lock_dir_noblock_shared, LockHolder,
+};
+use pbs_datastore::manifest::{archive_type, ArchiveType, MANIFEST_BLOB_NAME};
+use pbs_datastore::task_io_stats::track_task_io;
+use pbs_datastore::{
+    check_backup_owner, wire_compression, DataStore, BACKUP_CLIENT_HOSTNAME_HEADER,
+    BACKUP_CLIENT_VERSION_HEADER, BACKUP_WIRE_COMPRESSION_HEADER, PROXMOX_BACKUP_PROTOCOL_ID_V1,
+};
 use pbs_tools::json::{required_array_param, required_integer_param, required_string_param};
 use proxmox_rest_server::{H2Service, WorkerTask};
-use proxmox_sys::fs::lock_dir_noblock_shared;
+
+use crate::api2::helpers::{api_bail, api_err, TaskIoExecutor};
 
 mod environment;
 use environment::*;
@@ -50,6 +61,7 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
             ("backup-time", false, &BACKUP_TIME_SCHEMA),
             ("debug", true, &BooleanSchema::new("Enable verbose debug logging.").schema()),
             ("benchmark", true, &BooleanSchema::new("Job is a benchmark (do not keep data).").schema()),
+            ("ignore-quota", true, &BooleanSchema::new("Start the backup even if the namespace exceeds its quota (requires Datastore.Allocate).").schema()),
         ]),
     )
 ).access(
@@ -58,6 +70,32 @@ pub const API_METHOD_UPGRADE_BACKUP: ApiMethod = ApiMethod::new(
     &Permission::Anybody
 );
 
+/// Tell a duplicate start of a snapshot, which another backup session is still writing, apart
+/// from other lock failures, which keep their lock contention error.
+fn map_backup_lock_error(
+    snapshot_path: &Path,
+    snapshot: &pbs_api_types::BackupDir,
+    err: Error,
+) -> Error {
+    if is_lock_contention(&err) {
+        let holders = list_lock_holders(Some(snapshot_path)).unwrap_or_default();
+        if written_by_backup_session(&holders) {
+            return api_err!(
+                BackupInProgress,
+                "snapshot {snapshot} is already being written by another backup session - {err}"
+            );
+        }
+    }
+    crate::api2::helpers::map_api_error(err)
+}
+
+/// Returns true if one of the snapshot lock `holders` is a backup session.
+fn written_by_backup_session(holders: &[LockHolder]) -> bool {
+    holders
+        .iter()
+        .any(|holder| holder.exclusive && holder.operation.as_deref() == Some("backup"))
+}
+
 pub(crate) fn optional_ns_param(param: &Value) -> Result<BackupNamespace, Error> {
     match param.get("ns") {
         Some(Value::String(ns)) => ns.parse(),
@@ -76,6 +114,7 @@ fn upgrade_to_backup_protocol(
     async move {
         let debug = param["debug"].as_bool().unwrap_or(false);
         let benchmark = param["benchmark"].as_bool().unwrap_or(false);
+        let ignore_quota = param["ignore-quota"].as_bool().unwrap_or(false);
 
         let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
@@ -92,9 +131,10 @@ fn upgrade_to_backup_protocol(
                 PRIV_DATASTORE_BACKUP,
                 false,
             )
-            .map_err(|err| http_err!(FORBIDDEN, "{err}"))?;
+            .map_err(|err| api_err!(PermissionDenied, "{err}"))?;
 
-        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))?;
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Write))
+            .map_err(crate::api2::helpers::map_api_error)?;
 
         let protocols = parts
             .headers
@@ -106,6 +146,20 @@ fn upgrade_to_backup_protocol(
             bail!("invalid protocol name");
         }
 
+        // only sent by newer clients, recorded in the manifest for informational purposes
+        let client_header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty() && value.len() <= 256)
+                .map(String::from)
+        };
+        let client_version = client_header(BACKUP_CLIENT_VERSION_HEADER);
+        let client_hostname = client_header(BACKUP_CLIENT_HOSTNAME_HEADER);
+        let wire_compression = client_header(BACKUP_WIRE_COMPRESSION_HEADER)
+            .map(|offer| wire_compression::select_codec(&offer));
+
         if parts.version >= http::version::Version::HTTP_2 {
             bail!(
                 "unexpected http version '{:?}' (expected version < 2)",
@@ -114,7 +168,22 @@ fn upgrade_to_backup_protocol(
         }
 
         if !datastore.namespace_path(&backup_ns).exists() {
-            proxmox_router::http_bail!(NOT_FOUND, "namespace not found");
+            api_bail!(NotFound, "namespace not found");
+        }
+
+        if ignore_quota {
+            user_info
+                .check_privs(
+                    &auth_id,
+                    &backup_ns.acl_path(&store),
+                    PRIV_DATASTORE_ALLOCATE,
+                    false,
+                )
+                .map_err(|err| api_err!(PermissionDenied, "ignoring the quota not allowed - {err}"))?;
+        } else if !benchmark {
+            datastore
+                .check_namespace_quota(&backup_ns)
+                .map_err(|err| api_err!(QuotaExceeded, "{err} - refusing to start backup"))?;
         }
 
         // FIXME: include namespace here?
@@ -139,18 +208,30 @@ fn upgrade_to_backup_protocol(
         };
 
         // lock backup group to only allow one backup per group at a time
-        let (owner, _group_guard) = datastore.create_locked_backup_group(
+        let (owner, _group_guard) = match datastore.create_locked_backup_group(
             backup_group.backup_ns(),
             backup_group.as_ref(),
             &auth_id,
-        )?;
+        ) {
+            Ok(res) => res,
+            Err(err) => {
+                let snapshot_path =
+                    datastore.snapshot_path(backup_group.backup_ns(), &backup_dir_arg);
+                return Err(map_backup_lock_error(&snapshot_path, &backup_dir_arg, err));
+            }
+        };
 
         // permission check
         let correct_owner =
             owner == auth_id || (owner.is_token() && Authid::from(owner.user().clone()) == auth_id);
         if !correct_owner && worker_type != "benchmark" {
             // only the owner is allowed to create additional snapshots
-            bail!("backup owner check failed ({} != {})", auth_id, owner);
+            api_bail!(
+                PermissionDenied,
+                "backup owner check failed ({} != {})",
+                auth_id,
+                owner
+            );
         }
 
         let last_backup = {
@@ -192,9 +273,22 @@ fn upgrade_to_backup_protocol(
         };
 
         let (path, is_new, snap_guard) =
-            datastore.create_locked_backup_dir(backup_dir.backup_ns(), backup_dir.as_ref())?;
+            match datastore.create_locked_backup_dir(backup_dir.backup_ns(), backup_dir.as_ref()) {
+                Ok(res) => res,
+                Err(err) => {
+                    let snapshot_path = backup_dir.full_path();
+                    return Err(map_backup_lock_error(&snapshot_path, backup_dir.dir(), err));
+                }
+            };
+
+        let mut removed_partial_files = Vec::new();
         if !is_new {
-            bail!("backup directory already exists.");
+            if backup_dir.full_path().join(MANIFEST_BLOB_NAME).exists() {
+                api_bail!(SnapshotExists, "backup directory already exists.");
+            }
+            // we got the snapshot lock, so the session which left the files behind is gone
+            removed_partial_files =
+                datastore.remove_partial_backup_files(backup_dir.backup_ns(), backup_dir.as_ref())?;
         }
 
         WorkerTask::spawn(
@@ -203,6 +297,15 @@ fn upgrade_to_backup_protocol(
             auth_id.to_string(),
             true,
             move |worker| {
+                // the locks were acquired before the task was started
+                let upid = worker.upid().to_string();
+                _group_guard.set_owner(Some(&upid), worker_type);
+                snap_guard.set_owner(Some(&upid), worker_type);
+                if let Some(ref guard) = _last_guard {
+                    guard.set_owner(Some(&upid), worker_type);
+                }
+                datastore.set_operation_owner(&upid);
+
                 let mut env = BackupEnvironment::new(
                     env_type,
                     auth_id,
@@ -213,6 +316,9 @@ fn upgrade_to_backup_protocol(
 
                 env.debug = debug;
                 env.last_backup = last_backup;
+                env.client_version = client_version;
+                env.client_hostname = client_hostname;
+                env.wire_compression = wire_compression.unwrap_or_default();
 
                 let origin = match rpcenv.get_client_ip().map(|addr| addr.ip()) {
                     Some(ip) => format!(" from {ip}"),
@@ -221,6 +327,15 @@ fn upgrade_to_backup_protocol(
                 env.log(format!(
                     "starting new {worker_type} on datastore '{store}'{origin}: {path:?}",
                 ));
+                if !removed_partial_files.is_empty() {
+                    env.log(format!(
+                        "removed partial files of an unfinished backup session: {}",
+                        removed_partial_files.join(", "),
+                    ));
+                }
+                if wire_compression.is_some() {
+                    env.log(format!("wire compression: {}", env.wire_compression));
+                }
 
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &BACKUP_API_ROUTER, debug);
@@ -234,7 +349,8 @@ fn upgrade_to_backup_protocol(
                     .and_then(move |conn| {
                         env2.debug("protocol upgrade done");
 
-                        let mut http = hyper::server::conn::Http::new();
+                        let mut http = hyper::server::conn::Http::new()
+                            .with_executor(TaskIoExecutor);
                         http.http2_only(true);
                         // increase window size: todo - find optiomal size
                         let window_size = 32 * 1024 * 1024; // max = (1 << 31) - 2
@@ -262,7 +378,7 @@ fn upgrade_to_backup_protocol(
                     });
                 let mut abort_future = abort_future.map(|_| Err(format_err!("task aborted")));
 
-                async move {
+                track_task_io(upid, async move {
                     // keep flock until task ends
                     let _group_guard = _group_guard;
                     let snap_guard = snap_guard;
@@ -312,18 +428,25 @@ fn upgrade_to_backup_protocol(
                             Err(err)
                         }
                     }
-                }
+                })
             },
         )?;
 
-        let response = Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::SWITCHING_PROTOCOLS)
             .header(CONNECTION, HeaderValue::from_static("upgrade"))
             .header(
                 UPGRADE,
                 HeaderValue::from_static(PROXMOX_BACKUP_PROTOCOL_ID_V1!()),
-            )
-            .body(Body::empty())?;
+            );
+        // only answer clients which asked for it
+        if let Some(wire_compression) = wire_compression {
+            response = response.header(
+                BACKUP_WIRE_COMPRESSION_HEADER,
+                HeaderValue::from_str(&wire_compression.to_string())?,
+            );
+        }
+        let response = response.body(Body::empty())?;
 
         Ok(response)
     }
@@ -813,8 +936,12 @@ fn get_previous_backup_time(
 pub const API_METHOD_DOWNLOAD_PREVIOUS: ApiMethod = ApiMethod::new(
     &ApiHandler::AsyncHttp(&download_previous),
     &ObjectSchema::new(
-        "Download archive from previous backup.",
-        &sorted!([("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA)]),
+        "Download archive from previous backup, or from the finished snapshot with the same \
+        group and time in namespace 'ns'.",
+        &sorted!([
+            ("archive-name", false, &BACKUP_ARCHIVE_NAME_SCHEMA),
+            ("ns", true, &BACKUP_NAMESPACE_SCHEMA),
+        ]),
     ),
 );
 
@@ -830,12 +957,13 @@ fn download_previous(
 
         let archive_name = required_string_param(&param, "archive-name")?.to_owned();
 
-        let last_backup = match &env.last_backup {
-            Some(info) => info,
-            None => bail!("no valid previous backup"),
+        let mut path = match param.get("ns") {
+            Some(_) => sibling_snapshot_path(env, &optional_ns_param(&param)?)?,
+            None => match &env.last_backup {
+                Some(info) => info.backup_dir.full_path(),
+                None => bail!("no valid previous backup"),
+            },
         };
-
-        let mut path = last_backup.backup_dir.full_path();
         path.push(&archive_name);
 
         {
@@ -869,3 +997,69 @@ fn download_previous(
     }
     .boxed()
 }
+
+/// Path of the snapshot with the same group and time as the current one in namespace `ns`.
+///
+/// Only finished snapshots of groups owned by the client are accepted, so that no chunks can
+/// be registered which the client could not read anyway.
+fn sibling_snapshot_path(
+    env: &BackupEnvironment,
+    ns: &BackupNamespace,
+) -> Result<std::path::PathBuf, Error> {
+    let auth_id: Authid = env.get_auth_id().unwrap().parse()?;
+    let store = env.datastore.name();
+
+    CachedUserInfo::new()?
+        .check_privs(&auth_id, &ns.acl_path(store), PRIV_DATASTORE_BACKUP, false)
+        .map_err(|err| api_err!(PermissionDenied, "{err}"))?;
+
+    let snapshot = env
+        .datastore
+        .backup_dir(ns.clone(), env.backup_dir.dir().clone())?;
+
+    let owner = env.datastore.get_owner(ns, snapshot.group())?;
+    check_backup_owner(&owner, &auth_id)?;
+
+    snapshot
+        .load_manifest()
+        .map_err(|err| format_err!("snapshot {} is not finished - {err}", snapshot.dir()))?;
+
+    Ok(snapshot.full_path())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn holder(exclusive: bool, operation: Option<&str>) -> LockHolder {
+        LockHolder {
+            path: "/datastore/host/test/2024-01-01T00:00:00Z".into(),
+            what: "snapshot".to_string(),
+            exclusive,
+            pid: 1,
+            starttime: 0,
+            since: 0,
+            upid: None,
+            operation: operation.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_written_by_backup_session() {
+        assert!(!written_by_backup_session(&[]));
+        assert!(written_by_backup_session(&[holder(true, Some("backup"))]));
+        assert!(written_by_backup_session(&[
+            holder(false, Some("reader")),
+            holder(true, Some("backup")),
+        ]));
+
+        // a snapshot locked by other operations is no duplicate backup session
+        assert!(!written_by_backup_session(&[holder(true, Some("verify"))]));
+        assert!(!written_by_backup_session(&[holder(
+            true,
+            Some("benchmark")
+        )]));
+        assert!(!written_by_backup_session(&[holder(false, Some("backup"))]));
+        assert!(!written_by_backup_session(&[holder(true, None)]));
+    }
+}
@@ -13,9 +13,9 @@ use proxmox_router::{ApiHandler, ApiMethod, ApiResponseFuture, RpcEnvironment};
 use proxmox_schema::*;
 use proxmox_sortable_macro::sortable;
 
-use pbs_api_types::{BACKUP_ARCHIVE_NAME_SCHEMA, CHUNK_DIGEST_SCHEMA};
+use pbs_api_types::{WireCompression, BACKUP_ARCHIVE_NAME_SCHEMA, CHUNK_DIGEST_SCHEMA};
 use pbs_datastore::file_formats::{DataBlobHeader, EncryptedDataBlobHeader};
-use pbs_datastore::{DataBlob, DataStore};
+use pbs_datastore::{wire_compression, DataBlob, DataStore};
 use pbs_tools::json::{required_integer_param, required_string_param};
 
 use super::environment::*;
@@ -26,6 +26,7 @@ pub struct UploadChunk {
     digest: [u8; 32],
     size: u32,
     encoded_size: u32,
+    wire_compression: WireCompression,
     raw_data: Option<Vec<u8>>,
 }
 
@@ -36,12 +37,14 @@ impl UploadChunk {
         digest: [u8; 32],
         size: u32,
         encoded_size: u32,
+        wire_compression: WireCompression,
     ) -> Self {
         Self {
             stream,
             store,
             size,
             encoded_size,
+            wire_compression,
             raw_data: Some(vec![]),
             digest,
         }
@@ -59,7 +62,11 @@ impl Future for UploadChunk {
                 Some(Err(err)) => return Poll::Ready(Err(Error::from(err))),
                 Some(Ok(input)) => {
                     if let Some(ref mut raw_data) = this.raw_data {
-                        if (raw_data.len() + input.len()) > (this.encoded_size as usize) {
+                        let max_size = wire_compression::max_encoded_size(
+                            this.wire_compression,
+                            this.encoded_size as usize,
+                        );
+                        if (raw_data.len() + input.len()) > max_size {
                             break format_err!("uploaded chunk is larger than announced.");
                         }
                         raw_data.extend_from_slice(&input);
@@ -69,6 +76,16 @@ impl Future for UploadChunk {
                 }
                 None => {
                     if let Some(raw_data) = this.raw_data.take() {
+                        let raw_data = match wire_compression::decode(
+                            this.wire_compression,
+                            raw_data,
+                            this.encoded_size as usize,
+                        ) {
+                            Ok(raw_data) => raw_data,
+                            Err(err) => {
+                                break format_err!("unable to decode uploaded chunk - {err}")
+                            }
+                        };
                         if raw_data.len() != (this.encoded_size as usize) {
                             break format_err!("uploaded chunk has unexpected size.");
                         }
@@ -161,8 +178,15 @@ fn upload_fixed_chunk(
 
         let env: &BackupEnvironment = rpcenv.as_ref();
 
-        let (digest, size, compressed_size, is_duplicate) =
-            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
+        let (digest, size, compressed_size, is_duplicate) = UploadChunk::new(
+            req_body,
+            env.datastore.clone(),
+            digest,
+            size,
+            encoded_size,
+            env.wire_compression,
+        )
+        .await?;
 
         env.register_fixed_chunk(wid, digest, size, compressed_size, is_duplicate)?;
         let digest_str = hex::encode(digest);
@@ -230,8 +254,15 @@ fn upload_dynamic_chunk(
 
         let env: &BackupEnvironment = rpcenv.as_ref();
 
-        let (digest, size, compressed_size, is_duplicate) =
-            UploadChunk::new(req_body, env.datastore.clone(), digest, size, encoded_size).await?;
+        let (digest, size, compressed_size, is_duplicate) = UploadChunk::new(
+            req_body,
+            env.datastore.clone(),
+            digest,
+            size,
+            encoded_size,
+            env.wire_compression,
+        )
+        .await?;
 
         env.register_dynamic_chunk(wid, digest, size, compressed_size, is_duplicate)?;
         let digest_str = hex::encode(digest);
@@ -187,6 +187,8 @@ pub enum DeletableProperty {
     SyncAttributes,
     /// User classes
     UserClasses,
+    /// Require a second factor
+    TfaRequired,
 }
 
 #[api(
@@ -279,6 +281,9 @@ pub fn update_ldap_realm(
                 DeletableProperty::UserClasses => {
                     config.user_classes = None;
                 }
+                DeletableProperty::TfaRequired => {
+                    config.tfa_required = None;
+                }
             }
         }
     }
@@ -336,6 +341,9 @@ pub fn update_ldap_realm(
     if let Some(user_classes) = update.user_classes {
         config.user_classes = Some(user_classes);
     }
+    if let Some(tfa_required) = update.tfa_required {
+        config.tfa_required = Some(tfa_required);
+    }
 
     let ldap_config = if password.is_some() {
         LdapAuthenticator::api_type_to_config_with_password(&config, password.clone())?
@@ -4,12 +4,16 @@ use proxmox_sortable_macro::sortable;
 
 pub mod ldap;
 pub mod openid;
+pub mod pam;
+pub mod pbs;
 pub mod tfa;
 
 #[sortable]
 const SUBDIRS: SubdirMap = &sorted!([
     ("ldap", &ldap::ROUTER),
     ("openid", &openid::ROUTER),
+    ("pam", &pam::ROUTER),
+    ("pbs", &pbs::ROUTER),
     ("tfa", &tfa::ROUTER),
 ]);
 
@@ -1,23 +1,26 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use ::serde::{Deserialize, Serialize};
-use anyhow::Error;
+use anyhow::{bail, format_err, Error};
 use hex::FromHex;
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use proxmox_router::{http_bail, Permission, Router, RpcEnvironment, RpcEnvironmentType};
 use proxmox_schema::{api, param_bail, ApiType};
 use proxmox_section_config::SectionConfigData;
-use proxmox_sys::{task_warn, WorkerTaskContext};
+use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, DataStoreConfigUpdater, DatastoreNotify, DatastoreTuning, KeepOptions,
-    PruneJobConfig, PruneJobOptions, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
+    Authid, BackupNamespace, DataStoreConfig, DataStoreConfigUpdater, DatastoreBackendConfig,
+    DatastoreBackendType, DatastoreNotify, DatastoreNotifyTarget, DatastoreTuning, KeepOptions,
+    Operation, PruneJobConfig, PruneJobOptions, DATASTORE_SCHEMA, PRIV_DATASTORE_ALLOCATE,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    REUSE_DATASTORE_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::BackupLockGuard;
-use pbs_datastore::chunk_store::ChunkStore;
+use pbs_datastore::chunk_store::{ChunkBackend, ChunkStore};
+use pbs_datastore::DataStore;
 
 use crate::api2::admin::{
     prune::list_prune_jobs, sync::list_sync_jobs, verify::list_verification_jobs,
@@ -30,6 +33,7 @@ use pbs_config::CachedUserInfo;
 
 use proxmox_rest_server::WorkerTask;
 
+use crate::server::audit_log::audit_config_change;
 use crate::server::jobstate;
 
 #[api(
@@ -67,9 +71,10 @@ pub fn list_datastores(
 }
 
 pub(crate) fn do_create_datastore(
-    _lock: BackupLockGuard,
+    lock: BackupLockGuard,
     mut config: SectionConfigData,
     datastore: DataStoreConfig,
+    reuse_existing: bool,
     worker: Option<&dyn WorkerTaskContext>,
 ) -> Result<(), Error> {
     let path: PathBuf = datastore.path.clone().into();
@@ -79,20 +84,138 @@ pub(crate) fn do_create_datastore(
             .parse_property_string(datastore.tuning.as_deref().unwrap_or(""))?,
     )?;
     let backup_user = pbs_config::backup_user()?;
-    let _store = ChunkStore::create(
-        &datastore.name,
-        path,
-        backup_user.uid,
-        backup_user.gid,
-        worker,
-        tuning.sync_level.unwrap_or_default(),
-    )?;
+
+    if let Some(chunk_dirs) = &datastore.extra_chunk_dir {
+        check_extra_chunk_dirs(&path, chunk_dirs)?;
+    }
+
+    if let ChunkBackend::S3(client) = ChunkBackend::from_config(datastore.backend.as_deref())? {
+        check_no_extra_chunk_dirs_on_s3(datastore.extra_chunk_dir.as_deref().unwrap_or_default())?;
+        proxmox_async::runtime::block_on(client.check_bucket())
+            .map_err(|err| format_err!("unable to access the S3 bucket - {err}"))?;
+    }
+
+    if reuse_existing {
+        ChunkStore::verify_existing(
+            &datastore.name,
+            path,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+        )?;
+    } else {
+        if !is_empty_directory(&path)? {
+            bail!(
+                "datastore path {path:?} is not empty, use 'reuse-existing' to adopt an existing \
+                chunk store"
+            );
+        }
+        let _store = ChunkStore::create(
+            &datastore.name,
+            path,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+            tuning.sync_level.unwrap_or_default(),
+        )?;
+    }
+
+    for chunk_dir in datastore.extra_chunk_dir.iter().flatten() {
+        let chunk_dir = Path::new(chunk_dir);
+        // an existing chunk store may already be sharded
+        if reuse_existing && !is_empty_directory(chunk_dir)? {
+            continue;
+        }
+        ChunkStore::create_extra_chunk_dir(
+            &datastore.name,
+            chunk_dir,
+            backup_user.uid,
+            backup_user.gid,
+            worker,
+        )?;
+    }
 
     config.set_data(&datastore.name, "datastore", &datastore)?;
 
     pbs_config::datastore::save_config(&config)?;
 
-    jobstate::create_state_file("garbage_collection", &datastore.name)
+    jobstate::create_state_file("garbage_collection", &datastore.name)?;
+
+    if reuse_existing {
+        // looking up the datastore needs the config lock
+        drop(lock);
+        check_group_owners(&datastore.name, worker)?;
+    }
+
+    Ok(())
+}
+
+/// Fill in the node-wide defaults for the tuning options and schedules of a new datastore.
+///
+/// Only options which are not set are filled in, and the resulting values are stored in the
+/// datastore config itself, so later changes to the defaults do not affect existing datastores.
+/// The default prune schedule is only used if keep options are set.
+pub(crate) fn apply_node_defaults(datastore: &mut DataStoreConfig) -> Result<(), Error> {
+    let (node_config, _digest) = crate::config::node::config()?;
+
+    if datastore.tuning.is_none() {
+        datastore.tuning = node_config.default_datastore_tuning;
+    }
+    if datastore.gc_schedule.is_none() {
+        datastore.gc_schedule = node_config.default_gc_schedule;
+    }
+    if datastore.prune_schedule.is_none() && datastore.keep.keeps_something() {
+        datastore.prune_schedule = node_config.default_prune_schedule;
+    }
+
+    Ok(())
+}
+
+// A missing path counts as empty, it gets created. File systems like ext4 add a 'lost+found'
+// directory on creation, so ignore that one for datastores placed directly on a mount point.
+fn is_empty_directory(path: &Path) -> Result<bool, Error> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(err) => bail!("unable to read datastore path {path:?} - {err}"),
+    };
+    for entry in entries {
+        let entry = entry.map_err(|err| format_err!("unable to read {path:?} - {err}"))?;
+        if entry.file_name() != "lost+found" {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Warn about backup groups of an adopted chunk store whose owner cannot be read, the datastore
+// itself is already configured at this point.
+fn check_group_owners(store: &str, worker: Option<&dyn WorkerTaskContext>) -> Result<(), Error> {
+    let datastore = DataStore::lookup_datastore(store, Some(Operation::Read))?;
+
+    let mut groups = 0;
+    for ns in datastore.recursive_iter_backup_ns_ok(BackupNamespace::root(), None)? {
+        for group in datastore.iter_backup_groups_ok(ns)? {
+            groups += 1;
+            if let Err(err) = group.get_owner() {
+                if let Some(worker) = worker {
+                    task_warn!(
+                        worker,
+                        "unable to read owner of group '{}' - {err}",
+                        group.group()
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(worker) = worker {
+        task_log!(
+            worker,
+            "adopted existing chunk store with {groups} backup groups"
+        );
+    }
+    Ok(())
 }
 
 #[api(
@@ -103,6 +226,10 @@ pub(crate) fn do_create_datastore(
                 type: DataStoreConfig,
                 flatten: true,
             },
+            "reuse-existing": {
+                schema: REUSE_DATASTORE_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -110,18 +237,27 @@ pub(crate) fn do_create_datastore(
     },
 )]
 /// Create new datastore config.
+///
+/// Tuning options and schedules which are not set are taken from the node configuration.
 pub fn create_datastore(
-    config: DataStoreConfig,
+    mut config: DataStoreConfig,
+    reuse_existing: Option<bool>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let lock = pbs_config::datastore::lock_config()?;
 
-    let (section_config, _digest) = pbs_config::datastore::config()?;
+    let (section_config, digest) = pbs_config::datastore::config()?;
 
     if section_config.sections.get(&config.name).is_some() {
         param_bail!("name", "datastore '{}' already exists.", config.name);
     }
 
+    if let Some(targets) = &config.notify_target {
+        check_notify_targets(targets)?;
+    }
+
+    apply_node_defaults(&mut config)?;
+
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
@@ -140,6 +276,7 @@ pub fn create_datastore(
                 max_depth: None,
                 ns: None,
             },
+            concurrency_group: None,
         }
     });
 
@@ -150,16 +287,34 @@ pub fn create_datastore(
         ..config
     };
 
+    let audit_path = format!("/config/datastore/{}", config.name);
+    let audit_data = serde_json::to_value(&config)?;
+
     WorkerTask::new_thread(
         "create-datastore",
         Some(config.name.to_string()),
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            do_create_datastore(lock, section_config, config, Some(&worker))?;
+            do_create_datastore(
+                lock,
+                section_config,
+                config,
+                reuse_existing.unwrap_or(false),
+                Some(&worker),
+            )?;
+
+            audit_config_change(
+                &auth_id,
+                &audit_path,
+                None,
+                Some(audit_data),
+                pbs_config::datastore::DATASTORE_CFG_FILENAME,
+                Some(&digest),
+            );
 
             if let Some(prune_job_config) = prune_job_config {
-                do_create_prune_job(prune_job_config, Some(&worker))
+                do_create_prune_job(prune_job_config, &auth_id, Some(&worker))
             } else {
                 Ok(())
             }
@@ -167,6 +322,154 @@ pub fn create_datastore(
     )
 }
 
+// Additional chunk directories must be absolute, distinct and outside of the datastore path.
+fn check_extra_chunk_dirs(datastore_path: &Path, chunk_dirs: &[String]) -> Result<(), Error> {
+    let mut seen = std::collections::HashSet::new();
+    for chunk_dir in chunk_dirs {
+        let path = Path::new(chunk_dir);
+        if !path.is_absolute() {
+            param_bail!(
+                "extra-chunk-dir",
+                "expected absolute path - got {chunk_dir:?}"
+            );
+        }
+        if path.starts_with(datastore_path) || datastore_path.starts_with(path) {
+            param_bail!(
+                "extra-chunk-dir",
+                "chunk directory {chunk_dir:?} overlaps with the datastore path {datastore_path:?}"
+            );
+        }
+        if !seen.insert(path) {
+            param_bail!("extra-chunk-dir", "duplicate chunk directory {chunk_dir:?}");
+        }
+    }
+    Ok(())
+}
+
+// The chunk directories of a datastore with the S3 backend only hold the chunk markers, sharding
+// them is pointless.
+fn check_no_extra_chunk_dirs_on_s3(chunk_dirs: &[String]) -> Result<(), Error> {
+    if !chunk_dirs.is_empty() {
+        param_bail!(
+            "extra-chunk-dir",
+            "additional chunk directories are not supported with the 's3' backend"
+        );
+    }
+    Ok(())
+}
+
+// Apply a change of the additional chunk directories of a datastore. Removed directories must not
+// contain any chunks anymore. New directories are left out of `data` and returned, they need to be
+// initialized first, see `add_extra_chunk_dirs`.
+fn update_extra_chunk_dirs(
+    data: &mut DataStoreConfig,
+    chunk_dirs: Vec<String>,
+) -> Result<Vec<String>, Error> {
+    let path = Path::new(&data.path);
+    check_extra_chunk_dirs(path, &chunk_dirs)?;
+    let backend: DatastoreBackendConfig = serde_json::from_value(
+        DatastoreBackendConfig::API_SCHEMA
+            .parse_property_string(data.backend.as_deref().unwrap_or(""))?,
+    )?;
+    if backend.ty == Some(DatastoreBackendType::S3) {
+        check_no_extra_chunk_dirs_on_s3(&chunk_dirs)?;
+    }
+
+    let old_dirs = data.extra_chunk_dir.take().unwrap_or_default();
+
+    for chunk_dir in old_dirs.iter().filter(|dir| !chunk_dirs.contains(dir)) {
+        if ChunkStore::chunk_dir_has_chunks(chunk_dir)? {
+            param_bail!(
+                "extra-chunk-dir",
+                "chunk directory {chunk_dir:?} still contains chunks, move them to the other \
+                chunk directories of the datastore before removing it"
+            );
+        }
+    }
+
+    let (kept_dirs, new_dirs): (Vec<String>, Vec<String>) = chunk_dirs
+        .into_iter()
+        .partition(|dir| old_dirs.contains(dir));
+
+    data.extra_chunk_dir = if kept_dirs.is_empty() {
+        None
+    } else {
+        Some(kept_dirs)
+    };
+
+    Ok(new_dirs)
+}
+
+// Initialize new additional chunk directories without holding the datastore config lock, this
+// creates 64k subdirectories each. Only then the config is switched to `chunk_dirs`, which must
+// still be based on the chunk directories the update left in the config.
+fn add_extra_chunk_dirs(
+    name: &str,
+    chunk_dirs: Vec<String>,
+    new_dirs: &[String],
+    auth_id: &Authid,
+    worker: &dyn WorkerTaskContext,
+) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    for chunk_dir in new_dirs {
+        task_log!(worker, "creating chunk directory {chunk_dir:?}");
+        ChunkStore::create_extra_chunk_dir(
+            name,
+            chunk_dir,
+            backup_user.uid,
+            backup_user.gid,
+            Some(worker),
+        )?;
+    }
+
+    let _lock = pbs_config::datastore::lock_config()?;
+    let (mut config, digest) = pbs_config::datastore::config()?;
+
+    let mut data: DataStoreConfig = config.lookup("datastore", name)?;
+    let audit_before = serde_json::to_value(&data)?;
+
+    let expected_dirs: Vec<&String> = chunk_dirs
+        .iter()
+        .filter(|dir| !new_dirs.contains(dir))
+        .collect();
+    let current_dirs: Vec<&String> = data.extra_chunk_dir.iter().flatten().collect();
+    if current_dirs != expected_dirs {
+        bail!("chunk directories of datastore '{name}' were changed concurrently");
+    }
+    check_extra_chunk_dirs(Path::new(&data.path), &chunk_dirs)?;
+
+    data.extra_chunk_dir = Some(chunk_dirs);
+
+    config.set_data(name, "datastore", &data)?;
+    pbs_config::datastore::save_config(&config)?;
+
+    audit_config_change(
+        auth_id,
+        &format!("/config/datastore/{name}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::datastore::DATASTORE_CFG_FILENAME,
+        Some(&digest),
+    );
+
+    Ok(())
+}
+
+// The config schema accepts unknown event types for compatibility, but new entries must be valid.
+fn check_notify_targets(targets: &[String]) -> Result<(), Error> {
+    for target in targets {
+        let target: DatastoreNotifyTarget = target.parse()?;
+        if target.event_type().is_none() {
+            param_bail!(
+                "notify-target",
+                "unknown notification event type '{}'",
+                target.event
+            );
+        }
+    }
+    Ok(())
+}
+
 #[api(
    input: {
         properties: {
@@ -218,10 +521,16 @@ pub enum DeletableProperty {
     KeepYearly,
     /// Delete the verify-new property
     VerifyNew,
+    /// Delete the trash-retention property
+    TrashRetention,
     /// Delete the notify-user property
     NotifyUser,
     /// Delete the notify property
     Notify,
+    /// Delete the notify-target property
+    NotifyTarget,
+    /// Delete the extra-chunk-dir property
+    ExtraChunkDir,
     /// Delete the tuning property
     Tuning,
     /// Delete the maintenance-mode property
@@ -256,15 +565,25 @@ pub enum DeletableProperty {
     access: {
         permission: &Permission::Privilege(&["datastore", "{name}"], PRIV_DATASTORE_MODIFY, false),
     },
+    returns: {
+        schema: UPID_SCHEMA,
+        optional: true,
+    },
 )]
 /// Update datastore config.
+///
+/// New additional chunk directories are initialized by a worker task, which only adds them to the
+/// config once done. Its UPID is returned in that case.
 pub fn update_datastore(
     update: DataStoreConfigUpdater,
     name: String,
     delete: Option<Vec<DeletableProperty>>,
     digest: Option<String>,
-) -> Result<(), Error> {
-    let _lock = pbs_config::datastore::lock_config()?;
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Option<String>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let lock = pbs_config::datastore::lock_config()?;
 
     // pass/compare digest
     let (mut config, expected_digest) = pbs_config::datastore::config()?;
@@ -275,6 +594,7 @@ pub fn update_datastore(
     }
 
     let mut data: DataStoreConfig = config.lookup("datastore", &name)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     if let Some(delete) = delete {
         for delete_prop in delete {
@@ -309,12 +629,21 @@ pub fn update_datastore(
                 DeletableProperty::VerifyNew => {
                     data.verify_new = None;
                 }
+                DeletableProperty::TrashRetention => {
+                    data.trash_retention = None;
+                }
                 DeletableProperty::Notify => {
                     data.notify = None;
                 }
                 DeletableProperty::NotifyUser => {
                     data.notify_user = None;
                 }
+                DeletableProperty::NotifyTarget => {
+                    data.notify_target = None;
+                }
+                DeletableProperty::ExtraChunkDir => {
+                    update_extra_chunk_dirs(&mut data, Vec::new())?;
+                }
                 DeletableProperty::Tuning => {
                     data.tuning = None;
                 }
@@ -381,10 +710,31 @@ pub fn update_datastore(
         data.verify_new = update.verify_new;
     }
 
+    if update.trash_retention.is_some() {
+        data.trash_retention = update.trash_retention;
+    }
+
     if update.notify_user.is_some() {
         data.notify_user = update.notify_user;
     }
 
+    if let Some(targets) = update.notify_target {
+        check_notify_targets(&targets)?;
+        data.notify_target = if targets.is_empty() {
+            None
+        } else {
+            Some(targets)
+        };
+    }
+
+    let mut new_chunk_dirs = None;
+    if let Some(chunk_dirs) = update.extra_chunk_dir {
+        let new_dirs = update_extra_chunk_dirs(&mut data, chunk_dirs.clone())?;
+        if !new_dirs.is_empty() {
+            new_chunk_dirs = Some((chunk_dirs, new_dirs));
+        }
+    }
+
     if update.tuning.is_some() {
         data.tuning = update.tuning;
     }
@@ -397,13 +747,38 @@ pub fn update_datastore(
 
     pbs_config::datastore::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/datastore/{name}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::datastore::DATASTORE_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     // we want to reset the statefiles, to avoid an immediate action in some cases
     // (e.g. going from monthly to weekly in the second week of the month)
     if gc_schedule_changed {
         jobstate::update_job_last_run_time("garbage_collection", &name)?;
     }
 
-    Ok(())
+    let Some((chunk_dirs, new_dirs)) = new_chunk_dirs else {
+        return Ok(None);
+    };
+
+    drop(lock);
+
+    let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
+
+    let upid = WorkerTask::new_thread(
+        "extend-datastore",
+        Some(name.clone()),
+        auth_id.to_string(),
+        to_stdout,
+        move |worker| add_extra_chunk_dirs(&name, chunk_dirs, &new_dirs, &auth_id, &worker),
+    )?;
+
+    Ok(Some(upid))
 }
 
 #[api(
@@ -420,7 +795,8 @@ pub fn update_datastore(
                 default: false,
             },
             "destroy-data": {
-                description: "Delete the datastore's underlying contents",
+                description: "Delete the datastore's underlying contents. Requires the datastore \
+                    to be in offline maintenance mode.",
                 optional: true,
                 type: bool,
                 default: false,
@@ -455,8 +831,15 @@ pub async fn delete_datastore(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    if !config.sections.contains_key(&name) {
-        http_bail!(NOT_FOUND, "datastore '{}' does not exist.", name);
+    let audit_before = match config.sections.get(&name) {
+        Some((_, data)) => data.clone(),
+        None => http_bail!(NOT_FOUND, "datastore '{}' does not exist.", name),
+    };
+
+    if destroy_data {
+        // checked again by the worker, but fail early before touching any job configs
+        let store_config: DataStoreConfig = config.lookup("datastore", &name)?;
+        pbs_datastore::check_destroy_maintenance_mode(&store_config)?;
     }
 
     if !keep_job_configs {
@@ -470,10 +853,20 @@ pub async fn delete_datastore(
             delete_prune_job(job.config.id, None, rpcenv)?
         }
 
-        let (mut tree, _digest) = pbs_config::acl::config()?;
+        let (mut tree, acl_digest) = pbs_config::acl::config()?;
         tree.delete_node(&format!("/datastore/{}", name));
         pbs_config::acl::save_config(&tree)?;
 
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        audit_config_change(
+            &auth_id,
+            "/access/acl",
+            Some(json!({ "path": format!("/datastore/{name}") })),
+            None,
+            pbs_config::acl::ACL_CFG_FILENAME,
+            Some(&acl_digest),
+        );
+
         let tape_jobs = list_tape_backup_jobs(Value::Null, rpcenv)?;
         for job_config in tape_jobs
             .into_iter()
@@ -494,6 +887,15 @@ pub async fn delete_datastore(
         move |worker| {
             pbs_datastore::DataStore::destroy(&name, destroy_data, &worker)?;
 
+            audit_config_change(
+                &auth_id,
+                &format!("/config/datastore/{name}"),
+                Some(audit_before),
+                None,
+                pbs_config::datastore::DATASTORE_CFG_FILENAME,
+                Some(&expected_digest),
+            );
+
             // ignore errors
             let _ = jobstate::remove_state_file("prune", &name);
             let _ = jobstate::remove_state_file("garbage_collection", &name);
@@ -1,16 +1,21 @@
 use ::serde::{Deserialize, Serialize};
-use anyhow::Error;
+use anyhow::{format_err, Error};
 
-use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
+use proxmox_router::{http_bail, Permission, Router, RpcEnvironment, SubdirMap};
 use proxmox_schema::{api, param_bail};
+use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, MediaPoolConfig, MediaPoolConfigUpdater, MEDIA_POOL_NAME_SCHEMA, PRIV_TAPE_AUDIT,
-    PRIV_TAPE_MODIFY,
+    Authid, Fingerprint, Kdf, MediaPoolConfig, MediaPoolConfigUpdater, MEDIA_POOL_NAME_SCHEMA,
+    PASSWORD_HINT_SCHEMA, PRIV_TAPE_AUDIT, PRIV_TAPE_MODIFY,
+    TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
 
+use crate::server::audit_log::audit_config_change;
+use crate::tape::encryption_keys::load_key_configs;
+
 #[api(
     protected: true,
     input: {
@@ -26,10 +31,12 @@ use pbs_config::CachedUserInfo;
     },
 )]
 /// Create a new media pool
-pub fn create_pool(config: MediaPoolConfig) -> Result<(), Error> {
+pub fn create_pool(config: MediaPoolConfig, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::media_pool::lock()?;
 
-    let (mut section_config, _digest) = pbs_config::media_pool::config()?;
+    let (mut section_config, digest) = pbs_config::media_pool::config()?;
 
     if section_config.sections.get(&config.name).is_some() {
         param_bail!("name", "Media pool '{}' already exists", config.name);
@@ -39,6 +46,15 @@ pub fn create_pool(config: MediaPoolConfig) -> Result<(), Error> {
 
     pbs_config::media_pool::save_config(&section_config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/media-pool/{}", config.name),
+        None,
+        Some(serde_json::to_value(&config)?),
+        pbs_config::media_pool::MEDIA_POOL_CFG_FILENAME,
+        Some(&digest),
+    );
+
     Ok(())
 }
 
@@ -148,12 +164,16 @@ pub fn update_pool(
     name: String,
     update: MediaPoolConfigUpdater,
     delete: Option<Vec<DeletableProperty>>,
+    rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::media_pool::lock()?;
 
-    let (mut config, _digest) = pbs_config::media_pool::config()?;
+    let (mut config, digest) = pbs_config::media_pool::config()?;
 
     let mut data: MediaPoolConfig = config.lookup("pool", &name)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     if let Some(delete) = delete {
         for delete_prop in delete {
@@ -168,7 +188,7 @@ pub fn update_pool(
                     data.template = None;
                 }
                 DeletableProperty::Encrypt => {
-                    data.encrypt = None;
+                    data.rotate_encryption_key(None);
                 }
                 DeletableProperty::Comment => {
                     data.comment = None;
@@ -187,7 +207,7 @@ pub fn update_pool(
         data.template = update.template;
     }
     if update.encrypt.is_some() {
-        data.encrypt = update.encrypt;
+        data.rotate_encryption_key(update.encrypt);
     }
 
     if let Some(comment) = update.comment {
@@ -203,6 +223,15 @@ pub fn update_pool(
 
     pbs_config::media_pool::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/media-pool/{name}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::media_pool::MEDIA_POOL_CFG_FILENAME,
+        Some(&digest),
+    );
+
     Ok(())
 }
 
@@ -220,27 +249,144 @@ pub fn update_pool(
     },
 )]
 /// Delete a media pool configuration
-pub fn delete_pool(name: String) -> Result<(), Error> {
+pub fn delete_pool(name: String, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
     let _lock = pbs_config::media_pool::lock()?;
 
-    let (mut config, _digest) = pbs_config::media_pool::config()?;
+    let (mut config, digest) = pbs_config::media_pool::config()?;
 
-    match config.sections.get(&name) {
-        Some(_) => {
-            config.sections.remove(&name);
-        }
+    let audit_before = match config.sections.remove(&name) {
+        Some((_, data)) => data,
         None => http_bail!(NOT_FOUND, "delete pool '{}' failed - no such pool", name),
-    }
+    };
 
     pbs_config::media_pool::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/media-pool/{name}"),
+        Some(audit_before),
+        None,
+        pbs_config::media_pool::MEDIA_POOL_CFG_FILENAME,
+        Some(&digest),
+    );
+
     Ok(())
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: MEDIA_POOL_NAME_SCHEMA,
+            },
+            fingerprint: {
+                schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+                optional: true,
+            },
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            password: {
+                description: "Password for the newly created key.",
+                min_length: 5,
+                optional: true,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+    returns: {
+        schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+    },
+    access: {
+        permission: &Permission::Privilege(&["tape", "pool", "{name}"], PRIV_TAPE_MODIFY, false),
+        description: "Creating a new key additionally requires Tape.Modify on '/tape/pool'.",
+    },
+)]
+/// Rotate the encryption key of a media pool
+///
+/// Uses the existing key 'fingerprint', or creates a new one if only a password is given. The
+/// previous key is kept in the key history of the pool, so that older media sets can still be
+/// restored. The new key is only used for newly allocated media sets.
+pub fn rotate_key(
+    name: String,
+    fingerprint: Option<Fingerprint>,
+    kdf: Option<Kdf>,
+    password: Option<String>,
+    hint: Option<String>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Fingerprint, Error> {
+    let _lock = pbs_config::media_pool::lock()?;
+
+    let (mut config, digest) = pbs_config::media_pool::config()?;
+
+    // fail early for unknown pools, before creating any key
+    let mut data: MediaPoolConfig = config.lookup("pool", &name)?;
+
+    let fingerprint = match (fingerprint, password) {
+        (Some(_), Some(_)) => param_bail!(
+            "password",
+            format_err!("cannot create a new key when 'fingerprint' is given")
+        ),
+        (Some(fingerprint), None) => {
+            let (key_map, _digest) = load_key_configs()?;
+            if !key_map.contains_key(&fingerprint) {
+                param_bail!(
+                    "fingerprint",
+                    format_err!("tape encryption key '{fingerprint}' does not exist")
+                );
+            }
+            fingerprint
+        }
+        (None, Some(password)) => {
+            let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+            let user_info = CachedUserInfo::new()?;
+            user_info.check_privs(&auth_id, &["tape", "pool"], PRIV_TAPE_MODIFY, false)?;
+
+            super::tape_encryption_keys::create_key(kdf, password, hint, None, rpcenv)?
+        }
+        (None, None) => param_bail!(
+            "fingerprint",
+            format_err!("either 'fingerprint' or 'password' is required")
+        ),
+    };
+
+    let audit_before = serde_json::to_value(&data)?;
+
+    data.rotate_encryption_key(Some(fingerprint.signature()));
+
+    config.set_data(&name, "pool", &data)?;
+
+    pbs_config::media_pool::save_config(&config)?;
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    audit_config_change(
+        &auth_id,
+        &format!("/config/media-pool/{name}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        pbs_config::media_pool::MEDIA_POOL_CFG_FILENAME,
+        Some(&digest),
+    );
+
+    Ok(fingerprint)
+}
+
+#[sortable]
+const ITEM_SUBDIRS: SubdirMap =
+    &sorted!([("rotate-key", &Router::new().post(&API_METHOD_ROTATE_KEY))]);
+
 const ITEM_ROUTER: Router = Router::new()
     .get(&API_METHOD_GET_CONFIG)
     .put(&API_METHOD_UPDATE_POOL)
-    .delete(&API_METHOD_DELETE_POOL);
+    .delete(&API_METHOD_DELETE_POOL)
+    .subdirs(ITEM_SUBDIRS);
 
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_POOLS)
@@ -16,6 +16,8 @@ use pbs_config::prune;
 
 use pbs_config::CachedUserInfo;
 
+use crate::server::audit_log::audit_config_change;
+
 #[api(
     input: {
         properties: {},
@@ -60,11 +62,12 @@ pub fn list_prune_jobs(
 
 pub fn do_create_prune_job(
     config: PruneJobConfig,
+    auth_id: &Authid,
     worker: Option<&dyn WorkerTaskContext>,
 ) -> Result<(), Error> {
     let _lock = prune::lock_config()?;
 
-    let (mut section_config, _digest) = prune::config()?;
+    let (mut section_config, digest) = prune::config()?;
 
     if section_config.sections.get(&config.id).is_some() {
         param_bail!("id", "job '{}' already exists.", config.id);
@@ -74,6 +77,15 @@ pub fn do_create_prune_job(
 
     prune::save_config(&section_config)?;
 
+    audit_config_change(
+        auth_id,
+        &format!("/config/prune/{}", config.id),
+        None,
+        Some(serde_json::to_value(&config)?),
+        prune::PRUNE_CFG_FILENAME,
+        Some(&digest),
+    );
+
     crate::server::jobstate::create_state_file("prunejob", &config.id)?;
 
     if let Some(worker) = worker {
@@ -108,7 +120,7 @@ pub fn create_prune_job(
 
     user_info.check_privs(&auth_id, &config.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
 
-    do_create_prune_job(config, None)
+    do_create_prune_job(config, &auth_id, None)
 }
 
 #[api(
@@ -170,6 +182,8 @@ pub enum DeletableProperty {
     KeepMonthly,
     /// Delete number of yearly backups to keep.
     KeepYearly,
+    /// Reset the concurrency group to the datastore name.
+    ConcurrencyGroup,
 }
 
 #[api(
@@ -225,6 +239,7 @@ pub fn update_prune_job(
     }
 
     let mut data: PruneJobConfig = config.lookup("prune", &id)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_MODIFY, true)?;
 
@@ -261,6 +276,9 @@ pub fn update_prune_job(
                 DeletableProperty::KeepYearly => {
                     data.options.keep.keep_yearly = None;
                 }
+                DeletableProperty::ConcurrencyGroup => {
+                    data.concurrency_group = None;
+                }
             }
         }
     }
@@ -299,6 +317,9 @@ pub fn update_prune_job(
     if let Some(value) = update.comment {
         data.comment = Some(value);
     }
+    if let Some(value) = update.concurrency_group {
+        data.concurrency_group = Some(value);
+    }
     if let Some(value) = update.options.keep.keep_last {
         data.options.keep.keep_last = Some(value);
     }
@@ -322,6 +343,15 @@ pub fn update_prune_job(
 
     prune::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/prune/{id}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        prune::PRUNE_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     if schedule_changed {
         crate::server::jobstate::update_job_last_run_time("prunejob", &id)?;
     }
@@ -369,12 +399,22 @@ pub fn delete_prune_job(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    if config.sections.remove(&id).is_none() {
-        http_bail!(NOT_FOUND, "job '{}' does not exist.", id);
-    }
+    let audit_before = match config.sections.remove(&id) {
+        Some((_, data)) => data,
+        None => http_bail!(NOT_FOUND, "job '{}' does not exist.", id),
+    };
 
     prune::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/prune/{id}"),
+        Some(audit_before),
+        None,
+        prune::PRUNE_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     crate::server::jobstate::remove_state_file("prunejob", &id)?;
 
     Ok(())
@@ -1,6 +1,11 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
 use ::serde::{Deserialize, Serialize};
 use anyhow::{bail, format_err, Error};
 use hex::FromHex;
+use hyper::StatusCode;
+use lazy_static::lazy_static;
 use pbs_api_types::BackupNamespace;
 use pbs_api_types::NamespaceListItem;
 use proxmox_router::list_subdirs_api_method;
@@ -8,20 +13,26 @@ use proxmox_router::SubdirMap;
 use proxmox_sortable_macro::sortable;
 use serde_json::Value;
 
-use proxmox_router::{http_bail, http_err, ApiMethod, Permission, Router, RpcEnvironment};
+use proxmox_router::{
+    http_bail, http_err, ApiMethod, HttpError, Permission, Router, RpcEnvironment,
+};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, DataStoreListItem, GroupListItem, RateLimitConfig, Remote, RemoteConfig,
-    RemoteConfigUpdater, RemoteWithoutPassword, SyncJobConfig, DATASTORE_SCHEMA, PRIV_REMOTE_AUDIT,
-    PRIV_REMOTE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA, REMOTE_ID_SCHEMA, REMOTE_PASSWORD_SCHEMA,
+    Authid, DataStoreListItem, GroupListItem, RateLimitConfig, Remote, RemoteCheckResult,
+    RemoteConfig, RemoteConfigUpdater, RemoteListItem, RemoteWithoutPassword, SyncJobConfig,
+    DATASTORE_SCHEMA, PRIV_REMOTE_AUDIT, PRIV_REMOTE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    REMOTE_ID_SCHEMA, REMOTE_PASSWORD_SCHEMA,
 };
-use pbs_client::{HttpClient, HttpClientOptions};
+use pbs_client::{ApiResponseError, HttpClient, HttpClientOptions};
 use pbs_config::sync;
 
 use pbs_config::CachedUserInfo;
 use serde_json::json;
 
+use crate::api2::helpers::map_api_error;
+use crate::server::jobstate;
+
 #[api(
     input: {
         properties: {},
@@ -29,19 +40,19 @@ use serde_json::json;
     returns: {
         description: "The list of configured remotes (with config digest).",
         type: Array,
-        items: { type: RemoteWithoutPassword },
+        items: { type: RemoteListItem },
     },
     access: {
         description: "List configured remotes filtered by Remote.Audit privileges",
         permission: &Permission::Anybody,
     },
 )]
-/// List all remotes
+/// List all remotes, with the result of their last health check
 pub fn list_remotes(
     _param: Value,
     _info: &ApiMethod,
     rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Vec<RemoteWithoutPassword>, Error> {
+) -> Result<Vec<RemoteListItem>, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let user_info = CachedUserInfo::new()?;
 
@@ -56,6 +67,21 @@ pub fn list_remotes(
             let privs = user_info.lookup_privs(&auth_id, &["remote", &remote.name]);
             privs & PRIV_REMOTE_AUDIT != 0
         })
+        .map(|remote| {
+            let last_check =
+                crate::server::read_remote_check_result(&remote.name).unwrap_or_else(|err| {
+                    log::error!(
+                        "unable to read check result of remote '{}' - {err}",
+                        remote.name
+                    );
+                    None
+                });
+            RemoteListItem {
+                name: remote.name,
+                config: remote.config,
+                last_check,
+            }
+        })
         .collect();
 
     rpcenv["digest"] = hex::encode(digest).into();
@@ -103,6 +129,8 @@ pub fn create_remote(name: String, config: RemoteConfig, password: String) -> Re
 
     pbs_config::remote::save_config(&section_config)?;
 
+    jobstate::create_state_file("remote-check", &name)?;
+
     Ok(())
 }
 
@@ -142,6 +170,8 @@ pub enum DeletableProperty {
     Fingerprint,
     /// Delete the port property.
     Port,
+    /// Delete the check-schedule property.
+    CheckSchedule,
 }
 
 #[api(
@@ -209,6 +239,9 @@ pub fn update_remote(
                 DeletableProperty::Port => {
                     data.config.port = None;
                 }
+                DeletableProperty::CheckSchedule => {
+                    data.config.check_schedule = None;
+                }
             }
         }
     }
@@ -237,6 +270,9 @@ pub fn update_remote(
     if update.fingerprint.is_some() {
         data.config.fingerprint = update.fingerprint;
     }
+    if update.check_schedule.is_some() {
+        data.config.check_schedule = update.check_schedule;
+    }
 
     config.set_data(&name, "remote", &data)?;
 
@@ -297,6 +333,11 @@ pub fn delete_remote(name: String, digest: Option<String>) -> Result<(), Error>
 
     pbs_config::remote::save_config(&config)?;
 
+    let _ = jobstate::remove_state_file("remote-check", &name);
+    if let Err(err) = crate::server::remove_remote_check_result(&name) {
+        log::warn!("{err}");
+    }
+
     Ok(())
 }
 
@@ -344,6 +385,38 @@ pub async fn remote_client(
     Ok(client)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: REMOTE_ID_SCHEMA,
+            },
+        },
+    },
+    access: {
+        permission: &Permission::Privilege(&["remote", "{name}"], PRIV_REMOTE_AUDIT, false),
+    },
+    returns: {
+        type: RemoteCheckResult,
+    },
+)]
+/// Check the connection to a remote with the stored credentials and record the result.
+///
+/// The check is limited to 30 seconds, failures are reported in the result.
+pub async fn check_remote(name: String) -> Result<RemoteCheckResult, Error> {
+    let (remote_config, _digest) = pbs_config::remote::config()?;
+    let remote: Remote = remote_config.lookup("remote", &name)?;
+
+    let result = crate::server::check_remote(&remote).await;
+
+    if let Err(err) = crate::server::save_remote_check_result(&name, &result) {
+        log::error!("unable to save check result of remote '{name}' - {err}");
+    }
+
+    Ok(result)
+}
+
 #[api(
     input: {
         properties: {
@@ -362,33 +435,13 @@ pub async fn remote_client(
     },
 )]
 /// List datastores of a remote.cfg entry
+///
+/// Results are cached for a short time. Connection failures carry an error code telling whether
+/// the remote is unreachable, its certificate does not match or the login failed.
 pub async fn scan_remote_datastores(name: String) -> Result<Vec<DataStoreListItem>, Error> {
-    let (remote_config, _digest) = pbs_config::remote::config()?;
-    let remote: Remote = remote_config.lookup("remote", &name)?;
-
-    let map_remote_err = |api_err| {
-        http_err!(
-            INTERNAL_SERVER_ERROR,
-            "failed to scan remote '{}' - {}",
-            &name,
-            api_err
-        )
-    };
+    let data = scan_remote(&name, "api2/json/admin/datastore".to_string(), None).await?;
 
-    let client = remote_client(&remote, None).await.map_err(map_remote_err)?;
-    let api_res = client
-        .get("api2/json/admin/datastore", None)
-        .await
-        .map_err(map_remote_err)?;
-    let parse_res = match api_res.get("data") {
-        Some(data) => serde_json::from_value::<Vec<DataStoreListItem>>(data.to_owned()),
-        None => bail!("remote {} did not return any datastore list data", &name),
-    };
-
-    match parse_res {
-        Ok(parsed) => Ok(parsed),
-        Err(_) => bail!("Failed to parse remote scan api result."),
-    }
+    serde_json::from_value(data).map_err(|_| format_err!("Failed to parse remote scan api result."))
 }
 
 #[api(
@@ -416,35 +469,10 @@ pub async fn scan_remote_namespaces(
     name: String,
     store: String,
 ) -> Result<Vec<NamespaceListItem>, Error> {
-    let (remote_config, _digest) = pbs_config::remote::config()?;
-    let remote: Remote = remote_config.lookup("remote", &name)?;
-
-    let map_remote_err = |api_err| {
-        http_err!(
-            INTERNAL_SERVER_ERROR,
-            "failed to scan remote '{}' - {}",
-            &name,
-            api_err
-        )
-    };
-
-    let client = remote_client(&remote, None).await.map_err(map_remote_err)?;
-    let api_res = client
-        .get(
-            &format!("api2/json/admin/datastore/{}/namespace", store),
-            None,
-        )
-        .await
-        .map_err(map_remote_err)?;
-    let parse_res = match api_res.get("data") {
-        Some(data) => serde_json::from_value::<Vec<NamespaceListItem>>(data.to_owned()),
-        None => bail!("remote {} did not return any datastore list data", &name),
-    };
+    let path = format!("api2/json/admin/datastore/{}/namespace", store);
+    let data = scan_remote(&name, path, None).await?;
 
-    match parse_res {
-        Ok(parsed) => Ok(parsed),
-        Err(_) => bail!("Failed to parse remote scan api result."),
-    }
+    serde_json::from_value(data).map_err(|_| format_err!("Failed to parse remote scan api result."))
 }
 
 #[api(
@@ -477,35 +505,89 @@ pub async fn scan_remote_groups(
     store: String,
     namespace: Option<BackupNamespace>,
 ) -> Result<Vec<GroupListItem>, Error> {
-    let (remote_config, _digest) = pbs_config::remote::config()?;
-    let remote: Remote = remote_config.lookup("remote", &name)?;
+    let path = format!("api2/json/admin/datastore/{}/groups", store);
+    let args = namespace.map(|ns| json!({ "ns": ns }));
+    let data = scan_remote(&name, path, args).await?;
 
-    let map_remote_err = |api_err| {
-        http_err!(
-            INTERNAL_SERVER_ERROR,
-            "failed to scan remote '{}' - {}",
-            &name,
-            api_err
-        )
-    };
+    serde_json::from_value(data).map_err(|_| format_err!("Failed to parse remote scan api result."))
+}
 
-    let client = remote_client(&remote, None).await.map_err(map_remote_err)?;
+/// Seconds for which the results of remote scans are cached, so that auto-completion in the
+/// GUI and CLI does not connect to the remote on every request.
+const SCAN_CACHE_TIME: i64 = 30;
 
-    let args = namespace.map(|ns| json!({ "ns": ns }));
+struct ScanCacheEntry {
+    time: i64,
+    config_digest: [u8; 32],
+    data: Value,
+}
 
-    let api_res = client
-        .get(&format!("api2/json/admin/datastore/{}/groups", store), args)
-        .await
-        .map_err(map_remote_err)?;
-    let parse_res = match api_res.get("data") {
-        Some(data) => serde_json::from_value::<Vec<GroupListItem>>(data.to_owned()),
-        None => bail!("remote {} did not return any group list data", &name),
-    };
+lazy_static! {
+    static ref SCAN_CACHE: Mutex<HashMap<String, ScanCacheEntry>> = Mutex::new(HashMap::new());
+}
 
-    match parse_res {
-        Ok(parsed) => Ok(parsed),
-        Err(_) => bail!("Failed to parse remote scan api result."),
+/// Query `path` on the remote `name` and return the `data` of the result.
+///
+/// Results are cached for [`SCAN_CACHE_TIME`] seconds, any change of the remote configuration
+/// invalidates them.
+async fn scan_remote(name: &str, path: String, args: Option<Value>) -> Result<Value, Error> {
+    let (remote_config, config_digest) = pbs_config::remote::config()?;
+    let remote: Remote = remote_config.lookup("remote", name)?;
+
+    let key = format!("{name}/{path}?{}", args.as_ref().unwrap_or(&Value::Null));
+    let now = proxmox_time::epoch_i64();
+
+    if let Some(entry) = SCAN_CACHE.lock().unwrap().get(&key) {
+        let age = now - entry.time;
+        if entry.config_digest == config_digest && (0..SCAN_CACHE_TIME).contains(&age) {
+            return Ok(entry.data.clone());
+        }
     }
+
+    let client = match remote_client(&remote, None).await {
+        Ok(client) => client,
+        Err(err) => {
+            let err = crate::server::classify_remote_error(&remote, err);
+            return Err(map_api_error(err));
+        }
+    };
+
+    let mut api_res = client.get(&path, args).await.map_err(|err| {
+        match err.downcast_ref::<ApiResponseError>() {
+            // a 401 would make the GUI ask for a new login on this server
+            Some(ApiResponseError {
+                status, message, ..
+            }) if *status != StatusCode::UNAUTHORIZED => HttpError::new(
+                *status,
+                format!("failed to scan remote '{name}' - {message}"),
+            )
+            .into(),
+            _ => http_err!(
+                INTERNAL_SERVER_ERROR,
+                "failed to scan remote '{}' - {}",
+                name,
+                err
+            ),
+        }
+    })?;
+
+    let data = match api_res.get_mut("data") {
+        Some(data) if !data.is_null() => data.take(),
+        _ => bail!("remote {} did not return any data for {}", name, path),
+    };
+
+    let mut cache = SCAN_CACHE.lock().unwrap();
+    cache.retain(|_, entry| now - entry.time < SCAN_CACHE_TIME);
+    cache.insert(
+        key,
+        ScanCacheEntry {
+            time: now,
+            config_digest,
+            data: data.clone(),
+        },
+    );
+
+    Ok(data)
 }
 
 #[sortable]
@@ -529,7 +611,10 @@ const ITEM_ROUTER: Router = Router::new()
     .get(&API_METHOD_READ_REMOTE)
     .put(&API_METHOD_UPDATE_REMOTE)
     .delete(&API_METHOD_DELETE_REMOTE)
-    .subdirs(&[("scan", &SCAN_ROUTER)]);
+    .subdirs(&[
+        ("check", &Router::new().post(&API_METHOD_CHECK_REMOTE)),
+        ("scan", &SCAN_ROUTER),
+    ]);
 
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_REMOTES)
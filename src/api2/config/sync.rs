@@ -7,14 +7,16 @@ use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, SyncJobConfig, SyncJobConfigUpdater, JOB_ID_SCHEMA, PRIV_DATASTORE_AUDIT,
-    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE, PRIV_REMOTE_AUDIT,
-    PRIV_REMOTE_READ, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    Authid, SyncDirection, SyncJobConfig, SyncJobConfigUpdater, JOB_ID_SCHEMA,
+    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    PRIV_REMOTE_AUDIT, PRIV_REMOTE_MODIFY, PRIV_REMOTE_READ, PROXMOX_CONFIG_DIGEST_SCHEMA,
 };
 use pbs_config::sync;
 
 use pbs_config::CachedUserInfo;
 
+use crate::server::audit_log::audit_config_change;
+
 pub fn check_sync_job_read_access(
     user_info: &CachedUserInfo,
     auth_id: &Authid,
@@ -34,10 +36,11 @@ pub fn check_sync_job_read_access(
     }
 }
 
-/// checks whether user can run the corresponding pull job
+/// checks whether user can run the corresponding pull or push job
 ///
 /// namespace creation/deletion ACL and backup group ownership checks happen in the pull code directly.
 /// remote side checks/filters remote datastore/namespace/group access.
+/// pushing requires Remote.Modify instead of Remote.Read, as it writes to the remote.
 pub fn check_sync_job_modify_access(
     user_info: &CachedUserInfo,
     auth_id: &Authid,
@@ -68,13 +71,46 @@ pub fn check_sync_job_modify_access(
         return false;
     }
 
+    // both can set owners other than the job's owner
+    if (job.preserve_owner.unwrap_or(false) || job.force_owner.unwrap_or(false))
+        && ns_anchor_privs & PRIV_DATASTORE_MODIFY == 0
+    {
+        return false;
+    }
+
     if let Some(remote) = &job.remote {
         let remote_privs = user_info.lookup_privs(auth_id, &["remote", remote, &job.remote_store]);
-        return remote_privs & PRIV_REMOTE_READ != 0;
+        let required = match job.sync_direction() {
+            SyncDirection::Pull => PRIV_REMOTE_READ,
+            SyncDirection::Push => PRIV_REMOTE_MODIFY,
+        };
+        return remote_privs & required != 0;
     }
     true
 }
 
+/// Push jobs need a remote and can't remove anything on it.
+fn check_sync_direction(job: &SyncJobConfig) -> Result<(), Error> {
+    if job.sync_direction() == SyncDirection::Push {
+        if job.remote.is_none() {
+            param_bail!("remote", "push sync jobs require a remote");
+        }
+        if job.remove_vanished.unwrap_or(false) {
+            param_bail!(
+                "remove-vanished",
+                "removing vanished snapshots is not supported for push sync jobs"
+            );
+        }
+        if job.preserve_owner.unwrap_or(false) || job.force_owner.unwrap_or(false) {
+            param_bail!(
+                "preserve-owner",
+                "the owner of pushed groups is set by the remote, not by the job"
+            );
+        }
+    }
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {},
@@ -121,7 +157,7 @@ pub fn list_sync_jobs(
         },
     },
     access: {
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote (Remote.Modify for push jobs). Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves, preserve-owner or force-owner requires Datastore.Modify",
         permission: &Permission::Anybody,
     },
 )]
@@ -143,6 +179,8 @@ pub fn create_sync_job(
         bail!("source and target datastore can't be the same");
     }
 
+    check_sync_direction(&config)?;
+
     if let Some(max_depth) = config.max_depth {
         if let Some(ref ns) = config.ns {
             ns.check_max_depth(max_depth)?;
@@ -152,7 +190,7 @@ pub fn create_sync_job(
         }
     }
 
-    let (mut section_config, _digest) = sync::config()?;
+    let (mut section_config, digest) = sync::config()?;
 
     if section_config.sections.get(&config.id).is_some() {
         param_bail!("id", "job '{}' already exists.", config.id);
@@ -162,6 +200,15 @@ pub fn create_sync_job(
 
     sync::save_config(&section_config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/sync/{}", config.id),
+        None,
+        Some(serde_json::to_value(&config)?),
+        sync::SYNC_CFG_FILENAME,
+        Some(&digest),
+    );
+
     crate::server::jobstate::create_state_file("syncjob", &config.id)?;
 
     Ok(())
@@ -207,6 +254,10 @@ pub enum DeletableProperty {
     Remote,
     /// Delete the owner property.
     Owner,
+    /// Delete the preserve-owner flag.
+    PreserveOwner,
+    /// Delete the force-owner flag.
+    ForceOwner,
     /// Delete the comment property.
     Comment,
     /// Delete the job schedule.
@@ -231,6 +282,18 @@ pub enum DeletableProperty {
     MaxDepth,
     /// Delete the transfer_last property,
     TransferLast,
+    /// Delete the verify_downloads property,
+    VerifyDownloads,
+    /// Delete the sync_metadata property,
+    SyncMetadata,
+    /// Delete the encrypted_only property,
+    EncryptedOnly,
+    /// Delete the verified_only property,
+    VerifiedOnly,
+    /// Delete the sync_direction property (-> meaning pull),
+    SyncDirection,
+    /// Delete the concurrency_group property (-> meaning local datastore name),
+    ConcurrencyGroup,
 }
 
 #[api(
@@ -260,7 +323,7 @@ pub enum DeletableProperty {
     },
     access: {
         permission: &Permission::Anybody,
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote (Remote.Modify for push jobs). Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves, preserve-owner or force-owner requires Datastore.Modify",
     },
 )]
 /// Update sync job config.
@@ -285,6 +348,7 @@ pub fn update_sync_job(
     }
 
     let mut data: SyncJobConfig = config.lookup("sync", &id)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     if let Some(delete) = delete {
         for delete_prop in delete {
@@ -295,6 +359,12 @@ pub fn update_sync_job(
                 DeletableProperty::Owner => {
                     data.owner = None;
                 }
+                DeletableProperty::PreserveOwner => {
+                    data.preserve_owner = None;
+                }
+                DeletableProperty::ForceOwner => {
+                    data.force_owner = None;
+                }
                 DeletableProperty::Comment => {
                     data.comment = None;
                 }
@@ -331,6 +401,24 @@ pub fn update_sync_job(
                 DeletableProperty::TransferLast => {
                     data.transfer_last = None;
                 }
+                DeletableProperty::VerifyDownloads => {
+                    data.verify_downloads = None;
+                }
+                DeletableProperty::SyncMetadata => {
+                    data.sync_metadata = None;
+                }
+                DeletableProperty::EncryptedOnly => {
+                    data.encrypted_only = None;
+                }
+                DeletableProperty::VerifiedOnly => {
+                    data.verified_only = None;
+                }
+                DeletableProperty::SyncDirection => {
+                    data.sync_direction = None;
+                }
+                DeletableProperty::ConcurrencyGroup => {
+                    data.concurrency_group = None;
+                }
             }
         }
     }
@@ -362,12 +450,36 @@ pub fn update_sync_job(
     if let Some(owner) = update.owner {
         data.owner = Some(owner);
     }
+    if let Some(preserve_owner) = update.preserve_owner {
+        data.preserve_owner = Some(preserve_owner);
+    }
+    if let Some(force_owner) = update.force_owner {
+        data.force_owner = Some(force_owner);
+    }
     if let Some(group_filter) = update.group_filter {
         data.group_filter = Some(group_filter);
     }
     if let Some(transfer_last) = update.transfer_last {
         data.transfer_last = Some(transfer_last);
     }
+    if let Some(verify_downloads) = update.verify_downloads {
+        data.verify_downloads = Some(verify_downloads);
+    }
+    if let Some(sync_metadata) = update.sync_metadata {
+        data.sync_metadata = Some(sync_metadata);
+    }
+    if let Some(encrypted_only) = update.encrypted_only {
+        data.encrypted_only = Some(encrypted_only);
+    }
+    if let Some(verified_only) = update.verified_only {
+        data.verified_only = Some(verified_only);
+    }
+    if let Some(sync_direction) = update.sync_direction {
+        data.sync_direction = Some(sync_direction);
+    }
+    if let Some(concurrency_group) = update.concurrency_group {
+        data.concurrency_group = Some(concurrency_group);
+    }
 
     if update.limit.rate_in.is_some() {
         data.limit.rate_in = update.limit.rate_in;
@@ -405,6 +517,8 @@ pub fn update_sync_job(
         }
     }
 
+    check_sync_direction(&data)?;
+
     if !check_sync_job_modify_access(&user_info, &auth_id, &data) {
         bail!("permission check failed");
     }
@@ -413,6 +527,15 @@ pub fn update_sync_job(
 
     sync::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/sync/{id}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        sync::SYNC_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     if schedule_changed {
         crate::server::jobstate::update_job_last_run_time("syncjob", &id)?;
     }
@@ -435,7 +558,7 @@ pub fn update_sync_job(
     },
     access: {
         permission: &Permission::Anybody,
-        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote. Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves requires Datastore.Modify",
+        description: "User needs Datastore.Backup on target datastore, and Remote.Read on source remote (Remote.Modify for push jobs). Additionally, remove_vanished requires Datastore.Prune, and any owner other than the user themselves, preserve-owner or force-owner requires Datastore.Modify",
     },
 )]
 /// Remove a sync job configuration
@@ -456,12 +579,12 @@ pub fn delete_sync_job(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    match config.lookup("sync", &id) {
+    let audit_before = match config.lookup("sync", &id) {
         Ok(job) => {
             if !check_sync_job_modify_access(&user_info, &auth_id, &job) {
                 bail!("permission check failed");
             }
-            config.sections.remove(&id);
+            config.sections.remove(&id).map(|(_, data)| data)
         }
         Err(_) => {
             http_bail!(NOT_FOUND, "job '{}' does not exist.", id)
@@ -470,6 +593,15 @@ pub fn delete_sync_job(
 
     sync::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/sync/{id}"),
+        audit_before,
+        None,
+        sync::SYNC_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     crate::server::jobstate::remove_state_file("syncjob", &id)?;
 
     Ok(())
@@ -526,6 +658,8 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         store: "localstore0".to_string(),
         ns: None,
         owner: Some(write_auth_id.clone()),
+        preserve_owner: None,
+        force_owner: None,
         comment: None,
         remove_vanished: None,
         max_depth: None,
@@ -533,6 +667,12 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         schedule: None,
         limit: pbs_api_types::RateLimitConfig::default(), // no limit
         transfer_last: None,
+        verify_downloads: None,
+        sync_metadata: None,
+        encrypted_only: None,
+        verified_only: None,
+        sync_direction: None,
+        concurrency_group: None,
     };
 
     // should work without ACLs
@@ -662,5 +802,28 @@ acl:1:/remote/remote1/remotestore1:write@pbs:RemoteSyncOperator
         &job
     ));
 
+    // preserving or forcing the owner of synced groups requires Datastore.Modify too
+    job.owner = Some(write_auth_id.clone());
+    job.store = "localstore2".to_string();
+    job.preserve_owner = Some(true);
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+    job.preserve_owner = None;
+    job.force_owner = Some(true);
+    assert!(!check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+    job.store = "localstore3".to_string();
+    assert!(check_sync_job_modify_access(
+        &user_info,
+        &write_auth_id,
+        &job
+    ));
+
     Ok(())
 }
@@ -7,12 +7,26 @@ use proxmox_router::{http_bail, Permission, Router, RpcEnvironment};
 use proxmox_schema::{api, param_bail};
 
 use pbs_api_types::{
-    Authid, TapeBackupJobConfig, TapeBackupJobConfigUpdater, JOB_ID_SCHEMA, PRIV_TAPE_AUDIT,
-    PRIV_TAPE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    Authid, TapeBackupJobConfig, TapeBackupJobConfigUpdater, TapeBackupJobSetup, JOB_ID_SCHEMA,
+    PRIV_TAPE_AUDIT, PRIV_TAPE_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
 
+/// Extra drives must share the changer of the job's drive.
+fn check_extra_drives(setup: &TapeBackupJobSetup) -> Result<(), Error> {
+    if setup.extra_drives.is_none() {
+        return Ok(());
+    }
+
+    let (drive_config, _digest) = pbs_config::drive::config()?;
+    if let Err(err) = crate::api2::tape::backup::tape_backup_drives(&drive_config, setup) {
+        param_bail!("extra-drives", err);
+    }
+
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {},
@@ -79,6 +93,12 @@ pub fn create_tape_backup_job(
         param_bail!("id", "job '{}' already exists.", job.id);
     }
 
+    if let (Some(ns), Some(max_depth)) = (&job.setup.ns, job.setup.max_depth) {
+        ns.check_max_depth(max_depth)?;
+    }
+
+    check_extra_drives(&job.setup)?;
+
     config.set_data(&job.id, "backup", &job)?;
 
     pbs_config::tape_job::save_config(&config)?;
@@ -138,6 +158,10 @@ pub enum DeletableProperty {
     MaxDepth,
     /// Delete the 'ns' property
     Ns,
+    /// Delete the 'concurrency-group' property
+    ConcurrencyGroup,
+    /// Delete the 'extra-drives' property
+    ExtraDrives,
 }
 
 #[api(
@@ -217,6 +241,12 @@ pub fn update_tape_backup_job(
                 DeletableProperty::Ns => {
                     data.setup.ns = None;
                 }
+                DeletableProperty::ConcurrencyGroup => {
+                    data.concurrency_group = None;
+                }
+                DeletableProperty::ExtraDrives => {
+                    data.setup.extra_drives = None;
+                }
             }
         }
     }
@@ -230,6 +260,9 @@ pub fn update_tape_backup_job(
     if let Some(drive) = update.setup.drive {
         data.setup.drive = drive;
     }
+    if update.setup.extra_drives.is_some() {
+        data.setup.extra_drives = update.setup.extra_drives;
+    }
 
     if update.setup.eject_media.is_some() {
         data.setup.eject_media = update.setup.eject_media;
@@ -253,10 +286,19 @@ pub fn update_tape_backup_job(
         data.setup.max_depth = update.setup.max_depth;
     }
 
+    if let (Some(ns), Some(max_depth)) = (&data.setup.ns, data.setup.max_depth) {
+        ns.check_max_depth(max_depth)?;
+    }
+
+    check_extra_drives(&data.setup)?;
+
     let schedule_changed = data.schedule != update.schedule;
     if update.schedule.is_some() {
         data.schedule = update.schedule;
     }
+    if update.concurrency_group.is_some() {
+        data.concurrency_group = update.concurrency_group;
+    }
 
     if let Some(comment) = update.comment {
         let comment = comment.trim();
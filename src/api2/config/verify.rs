@@ -14,6 +14,8 @@ use pbs_config::verify;
 
 use pbs_config::CachedUserInfo;
 
+use crate::server::audit_log::audit_config_change;
+
 #[api(
     input: {
         properties: {},
@@ -83,7 +85,7 @@ pub fn create_verification_job(
 
     let _lock = verify::lock_config()?;
 
-    let (mut section_config, _digest) = verify::config()?;
+    let (mut section_config, digest) = verify::config()?;
 
     if section_config.sections.get(&config.id).is_some() {
         param_bail!("id", "job '{}' already exists.", config.id);
@@ -93,6 +95,15 @@ pub fn create_verification_job(
 
     verify::save_config(&section_config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/verify/{}", config.id),
+        None,
+        Some(serde_json::to_value(&config)?),
+        verify::VERIFICATION_CFG_FILENAME,
+        Some(&digest),
+    );
+
     crate::server::jobstate::create_state_file("verificationjob", &config.id)?;
 
     Ok(())
@@ -145,10 +156,14 @@ pub enum DeletableProperty {
     Schedule,
     /// Delete outdated after property.
     OutdatedAfter,
+    /// Delete the skip bad chunk report property.
+    SkipBadChunkReport,
     /// Delete namespace property, defaulting to root namespace then.
     Ns,
     /// Delete max-depth property, defaulting to full recursion again
     MaxDepth,
+    /// Delete the concurrency group, defaulting to the datastore name again
+    ConcurrencyGroup,
 }
 
 #[api(
@@ -204,6 +219,7 @@ pub fn update_verification_job(
     }
 
     let mut data: VerificationJobConfig = config.lookup("verification", &id)?;
+    let audit_before = serde_json::to_value(&data)?;
 
     // check existing store and NS
     user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_VERIFY, true)?;
@@ -217,6 +233,9 @@ pub fn update_verification_job(
                 DeletableProperty::OutdatedAfter => {
                     data.outdated_after = None;
                 }
+                DeletableProperty::SkipBadChunkReport => {
+                    data.skip_bad_chunk_report = None;
+                }
                 DeletableProperty::Comment => {
                     data.comment = None;
                 }
@@ -229,6 +248,9 @@ pub fn update_verification_job(
                 DeletableProperty::MaxDepth => {
                     data.max_depth = None;
                 }
+                DeletableProperty::ConcurrencyGroup => {
+                    data.concurrency_group = None;
+                }
             }
         }
     }
@@ -252,6 +274,9 @@ pub fn update_verification_job(
     if update.outdated_after.is_some() {
         data.outdated_after = update.outdated_after;
     }
+    if update.skip_bad_chunk_report.is_some() {
+        data.skip_bad_chunk_report = update.skip_bad_chunk_report;
+    }
     let schedule_changed = data.schedule != update.schedule;
     if update.schedule.is_some() {
         data.schedule = update.schedule;
@@ -266,6 +291,9 @@ pub fn update_verification_job(
             data.max_depth = Some(max_depth);
         }
     }
+    if update.concurrency_group.is_some() {
+        data.concurrency_group = update.concurrency_group;
+    }
 
     // check new store and NS
     user_info.check_privs(&auth_id, &data.acl_path(), PRIV_DATASTORE_VERIFY, true)?;
@@ -274,6 +302,15 @@ pub fn update_verification_job(
 
     verify::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/verify/{id}"),
+        Some(audit_before),
+        Some(serde_json::to_value(&data)?),
+        verify::VERIFICATION_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     if schedule_changed {
         crate::server::jobstate::update_job_last_run_time("verificationjob", &id)?;
     }
@@ -320,15 +357,22 @@ pub fn delete_verification_job(
         crate::tools::detect_modified_configuration_file(&digest, &expected_digest)?;
     }
 
-    match config.sections.get(&id) {
-        Some(_) => {
-            config.sections.remove(&id);
-        }
+    let audit_before = match config.sections.remove(&id) {
+        Some((_, data)) => data,
         None => http_bail!(NOT_FOUND, "job '{}' does not exist.", id),
-    }
+    };
 
     verify::save_config(&config)?;
 
+    audit_config_change(
+        &auth_id,
+        &format!("/config/verify/{id}"),
+        Some(audit_before),
+        None,
+        verify::VERIFICATION_CFG_FILENAME,
+        Some(&expected_digest),
+    );
+
     crate::server::jobstate::remove_state_file("verificationjob", &id)?;
 
     Ok(())
@@ -1,10 +1,20 @@
+use std::future::Future;
+use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::PathBuf;
 
 use anyhow::Error;
 use futures::stream::TryStreamExt;
-use hyper::{header, Body, Response, StatusCode};
+use hyper::{header, Body, HeaderMap, Response, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
-use proxmox_router::http_bail;
+use proxmox_router::{http_bail, HttpError};
+
+use pbs_api_types::{ApiError, ApiErrorCode, MaintenanceModeError};
+use pbs_datastore::cached_chunk_reader::{CachedChunkReader, SeekableCachedChunkReader};
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::read_chunk::AsyncReadChunk;
+use pbs_datastore::{task_io_stats, BACKUP_CONTENT_SHA256_HEADER};
 
 pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, Error> {
     let file = match tokio::fs::File::open(path.clone()).await {
@@ -16,7 +26,10 @@ pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, E
     };
 
     let payload = tokio_util::codec::FramedRead::new(file, tokio_util::codec::BytesCodec::new())
-        .map_ok(|bytes| bytes.freeze());
+        .map_ok(|bytes| {
+            task_io_stats::account_read(bytes.len() as u64);
+            bytes.freeze()
+        });
 
     let body = Body::wrap_stream(payload);
 
@@ -27,3 +40,382 @@ pub async fn create_download_response(path: PathBuf) -> Result<Response<Body>, E
         .body(body)
         .unwrap())
 }
+
+/// Parse the `Range` header of a download request for a file of `size` bytes.
+///
+/// Only a single range of bytes is supported. Other or malformed ranges are ignored, as allowed by
+/// RFC 9110, so that the complete file is sent instead. Ranges starting after the end of the file
+/// are rejected with `416 Range Not Satisfiable`.
+pub fn parse_range_header(headers: &HeaderMap, size: u64) -> Result<Option<Range<u64>>, Error> {
+    let spec = match headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (start.trim(), end.trim()),
+        None => return Ok(None),
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // the last `suffix` bytes of the file
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => size.saturating_sub(suffix)..size,
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        (Ok(start), Ok(end)) if start <= end => start..size.min(end.saturating_add(1)),
+        _ => return Ok(None),
+    };
+
+    if range.start >= size {
+        http_bail!(
+            RANGE_NOT_SATISFIABLE,
+            "requested range {spec} is outside of the file ({size} bytes)"
+        );
+    }
+
+    Ok(Some(range))
+}
+
+/// Create the response for the download of a file with `size` bytes.
+///
+/// If a `range` was requested, `body` must only contain that part of the file. `sha256` is the
+/// digest of the complete file and is sent in both cases, see [`BACKUP_CONTENT_SHA256_HEADER`].
+pub fn create_ranged_download_response(
+    body: Body,
+    size: u64,
+    range: Option<Range<u64>>,
+    sha256: Option<&[u8; 32]>,
+) -> Response<Body> {
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .header(header::ACCEPT_RANGES, "bytes");
+
+    response = match range {
+        Some(range) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_LENGTH, range.end - range.start)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{size}", range.start, range.end - 1),
+            ),
+        None => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size),
+    };
+
+    if let Some(sha256) = sha256 {
+        response = response.header(BACKUP_CONTENT_SHA256_HEADER, hex::encode(sha256));
+    }
+
+    response.body(body).unwrap()
+}
+
+/// Returns a reader for the `range` of the data referenced by the index of `reader`.
+pub async fn index_range_reader<I, R>(
+    reader: CachedChunkReader<I, R>,
+    range: Range<u64>,
+) -> Result<tokio::io::Take<SeekableCachedChunkReader<I, R>>, Error>
+where
+    I: IndexFile + Send + Sync + 'static,
+    R: AsyncReadChunk + Send + Sync + 'static,
+{
+    let mut reader = reader.seekable();
+    reader.seek(SeekFrom::Start(range.start)).await?;
+    Ok(reader.take(range.end - range.start))
+}
+
+/// Executor for the HTTP/2 connections of backup and reader tasks
+///
+/// Stream handlers are spawned as separate tokio tasks, this makes sure their datastore IO is
+/// still attributed to the worker task serving the connection.
+#[derive(Clone, Copy)]
+pub struct TaskIoExecutor;
+
+impl<F> hyper::rt::Executor<F> for TaskIoExecutor
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    fn execute(&self, future: F) {
+        tokio::spawn(task_io_stats::instrument(future));
+    }
+}
+
+/// Create an HTTP error with the status of the stable error `code`, the code itself is sent in the
+/// `code` field of the response (see [`crate::server::api_error_code`]).
+pub fn api_error(code: ApiErrorCode, message: impl std::fmt::Display) -> Error {
+    let status =
+        StatusCode::from_u16(code.http_status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let err = ApiError::new(code, message);
+    HttpError::new(status, err.to_string()).into()
+}
+
+/// Like `http_err!`, but takes an [`ApiErrorCode`] variant instead of a status code.
+macro_rules! api_err {
+    ($code:ident, $($fmt:tt)+) => {
+        $crate::api2::helpers::api_error(::pbs_api_types::ApiErrorCode::$code, format!($($fmt)+))
+    };
+}
+
+/// Like `http_bail!`, but takes an [`ApiErrorCode`] variant instead of a status code.
+macro_rules! api_bail {
+    ($code:ident, $($fmt:tt)+) => {
+        return Err($crate::api2::helpers::api_err!($code, $($fmt)+))
+    };
+}
+
+pub(crate) use {api_bail, api_err};
+
+/// Translate errors with a known [`ApiErrorCode`] into HTTP errors with the status of that code.
+///
+/// This covers [`ApiError`] as well as the maintenance mode of a datastore, which is reported as
+/// `503 Service Unavailable`. Other errors are passed through unchanged. Needed where the status
+/// matters before the response is sent, like for protocol upgrades, otherwise the code is added
+/// to the response anyway.
+pub fn map_api_error(err: Error) -> Error {
+    if let Some(api_err) = err.downcast_ref::<ApiError>() {
+        let code = api_err.code();
+        api_error(code, err)
+    } else if err.downcast_ref::<MaintenanceModeError>().is_some() {
+        api_error(ApiErrorCode::MaintenanceMode, err)
+    } else {
+        err
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::pin::Pin;
+
+    use anyhow::{bail, format_err};
+    use hyper::header::HeaderValue;
+
+    use pbs_datastore::index::ChunkReadInfo;
+    use pbs_datastore::DataBlob;
+
+    use super::*;
+
+    struct TestIndex {
+        chunks: Vec<ChunkReadInfo>,
+    }
+
+    impl IndexFile for TestIndex {
+        fn index_count(&self) -> usize {
+            self.chunks.len()
+        }
+
+        fn index_digest(&self, pos: usize) -> Option<&[u8; 32]> {
+            self.chunks.get(pos).map(|info| &info.digest)
+        }
+
+        fn index_bytes(&self) -> u64 {
+            self.chunks.last().map(|info| info.range.end).unwrap_or(0)
+        }
+
+        fn chunk_info(&self, pos: usize) -> Option<ChunkReadInfo> {
+            self.chunks.get(pos).cloned()
+        }
+
+        fn index_ctime(&self) -> i64 {
+            0
+        }
+
+        fn index_size(&self) -> usize {
+            self.chunks.len() * 40
+        }
+
+        fn chunk_from_offset(&self, offset: u64) -> Option<(usize, u64)> {
+            let pos = self
+                .chunks
+                .iter()
+                .position(|info| info.range.contains(&offset))?;
+            Some((pos, offset - self.chunks[pos].range.start))
+        }
+
+        fn compute_csum(&self) -> ([u8; 32], u64) {
+            let mut csum = openssl::sha::Sha256::new();
+            for info in &self.chunks {
+                csum.update(&info.range.end.to_le_bytes());
+                csum.update(&info.digest);
+            }
+            (csum.finish(), self.index_bytes())
+        }
+    }
+
+    struct TestChunkReader {
+        chunks: HashMap<[u8; 32], Vec<u8>>,
+    }
+
+    impl AsyncReadChunk for TestChunkReader {
+        fn read_raw_chunk<'a>(
+            &'a self,
+            _digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<DataBlob, Error>> + Send + 'a>> {
+            Box::pin(async { bail!("not implemented") })
+        }
+
+        fn read_chunk<'a>(
+            &'a self,
+            digest: &'a [u8; 32],
+        ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, Error>> + Send + 'a>> {
+            Box::pin(async move {
+                self.chunks
+                    .get(digest)
+                    .cloned()
+                    .ok_or_else(|| format_err!("unknown chunk"))
+            })
+        }
+    }
+
+    /// Returns the data of an archive with chunks of different sizes, and a reader for it.
+    fn test_archive() -> (Vec<u8>, CachedChunkReader<TestIndex, TestChunkReader>) {
+        let mut archive = Vec::new();
+        let mut infos = Vec::new();
+        let mut chunks = HashMap::new();
+        for (i, size) in [3000u64, 5000, 4096, 1234].into_iter().enumerate() {
+            let data: Vec<u8> = (0..size)
+                .map(|b| (b * (i as u64 + 7) % 251) as u8)
+                .collect();
+            let digest = openssl::sha::sha256(&data);
+            let start = archive.len() as u64;
+            infos.push(ChunkReadInfo {
+                range: start..(start + size),
+                digest,
+            });
+            archive.extend_from_slice(&data);
+            chunks.insert(digest, data);
+        }
+
+        let reader =
+            CachedChunkReader::new(TestChunkReader { chunks }, TestIndex { chunks: infos }, 2);
+        (archive, reader)
+    }
+
+    fn range_headers(range: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        headers
+    }
+
+    /// The error of looking up a datastore in maintenance `mode` for `operation`
+    fn lookup_error(mode: &str, operation: pbs_api_types::Operation) -> Option<Error> {
+        let mode: pbs_api_types::MaintenanceMode =
+            serde_json::from_value(serde_json::json!({ "type": mode, "message": "disk%20swap" }))
+                .unwrap();
+        mode.check(Some(operation)).err().map(|error| {
+            let message = format!("datastore 'store1' is in {error}");
+            error.context(message)
+        })
+    }
+
+    #[test]
+    fn test_map_maintenance_mode_error() {
+        use pbs_api_types::Operation;
+
+        // reader connections
+        let err = map_api_error(lookup_error("offline", Operation::Read).unwrap());
+        let err = err.downcast_ref::<HttpError>().unwrap();
+        assert_eq!(err.code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            err.message,
+            "datastore 'store1' is in offline maintenance mode: disk swap"
+        );
+        assert!(lookup_error("read-only", Operation::Read).is_none());
+
+        // writer connections
+        for mode in ["offline", "read-only"] {
+            let err = map_api_error(lookup_error(mode, Operation::Write).unwrap());
+            let err = err.downcast_ref::<HttpError>().unwrap();
+            assert_eq!(err.code, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(
+                err.message,
+                format!("datastore 'store1' is in {mode} maintenance mode: disk swap")
+            );
+        }
+
+        // other errors are kept
+        let err = map_api_error(format_err!("something else"));
+        assert!(err.downcast_ref::<HttpError>().is_none());
+    }
+
+    #[test]
+    fn test_parse_range_header() -> Result<(), Error> {
+        let parse = |range: &str| parse_range_header(&range_headers(range), 1000);
+
+        assert_eq!(parse_range_header(&HeaderMap::new(), 1000)?, None);
+        assert_eq!(parse("bytes=0-499")?, Some(0..500));
+        assert_eq!(parse("bytes=500-")?, Some(500..1000));
+        assert_eq!(parse("bytes=900-2000")?, Some(900..1000));
+        assert_eq!(parse("bytes=-100")?, Some(900..1000));
+        assert_eq!(parse("bytes=-2000")?, Some(0..1000));
+        assert_eq!(parse("bytes= 10 - 19")?, Some(10..20));
+
+        // unsupported or malformed ranges are ignored
+        assert_eq!(parse("bytes=0-9,20-29")?, None);
+        assert_eq!(parse("bytes=20-9")?, None);
+        assert_eq!(parse("bytes=-0")?, None);
+        assert_eq!(parse("items=0-9")?, None);
+        assert_eq!(parse("bytes=a-")?, None);
+
+        assert!(parse("bytes=1000-").is_err());
+        assert!(parse_range_header(&range_headers("bytes=-10"), 0).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_index_download() -> Result<(), Error> {
+        let (archive, reader) = test_archive();
+        let size = archive.len() as u64;
+        let expected = openssl::sha::sha256(&archive);
+
+        // the first download was interrupted in the middle of the second chunk
+        let mut downloaded = archive[..4500].to_vec();
+
+        let range = parse_range_header(&range_headers("bytes=4500-"), size)?.unwrap();
+        let response = create_ranged_download_response(
+            Body::empty(),
+            size,
+            Some(range.clone()),
+            Some(&expected),
+        );
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 4500-{}/{size}", size - 1)
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            (size - 4500).to_string()
+        );
+        assert_eq!(
+            response.headers()[BACKUP_CONTENT_SHA256_HEADER],
+            hex::encode(expected)
+        );
+
+        proxmox_async::runtime::block_on(async {
+            let mut remaining = index_range_reader(reader, range).await?;
+            remaining.read_to_end(&mut downloaded).await?;
+            Ok::<_, Error>(())
+        })?;
+
+        assert_eq!(downloaded.len(), archive.len());
+        assert_eq!(openssl::sha::sha256(&downloaded), expected);
+
+        // a range within the archive, spanning a whole chunk
+        let (archive, reader) = test_archive();
+        let range = parse_range_header(&range_headers("bytes=2000-9000"), size)?.unwrap();
+        let mut data = Vec::new();
+        proxmox_async::runtime::block_on(async {
+            let mut part = index_range_reader(reader, range).await?;
+            part.read_to_end(&mut data).await?;
+            Ok::<_, Error>(())
+        })?;
+        assert!(data == archive[2000..9001], "wrong data in range");
+
+        Ok(())
+    }
+}
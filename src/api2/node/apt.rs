@@ -15,9 +15,10 @@ use proxmox_apt::repositories::{
 use proxmox_http::ProxyConfig;
 
 use pbs_api_types::{
-    APTUpdateInfo, NODE_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, PROXMOX_CONFIG_DIGEST_SCHEMA,
-    UPID_SCHEMA,
+    APTUpdateInfo, Authid, ServerUpdateStatus, NODE_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    PROXMOX_CONFIG_DIGEST_SCHEMA, UPID_SCHEMA,
 };
+use pbs_config::CachedUserInfo;
 
 use crate::config::node;
 use crate::tools::apt;
@@ -223,18 +224,58 @@ pub fn apt_update_database(
 )]
 /// Retrieve the changelog of the specified package.
 fn apt_get_changelog(name: String, version: Option<String>) -> Result<Value, Error> {
-    let mut command = std::process::Command::new("apt-get");
-    command.arg("changelog");
-    command.arg("-qq"); // don't display download progress
-    if let Some(ver) = version {
-        command.arg(format!("{name}={ver}"));
-    } else {
-        command.arg(name);
-    }
-    let output = proxmox_sys::command::run_command(command, None)?;
+    let output = apt::fetch_changelog(&name, version.as_deref())?;
     Ok(json!(output))
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            changelog: {
+                description: "Include the changelog of available updates, fetched from the repository.",
+                type: Boolean,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        type: ServerUpdateStatus,
+    },
+    access: {
+        description: "Including the changelog requires Sys.Modify, like the 'changelog' call.",
+        permission: &Permission::Privilege(&[], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Get the installed and available versions of the Proxmox Backup Server packages.
+pub fn get_server_updates(
+    changelog: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<ServerUpdateStatus, Error> {
+    let mut status = apt::server_update_status();
+
+    if changelog {
+        let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+        CachedUserInfo::new()?.check_privs(&auth_id, &[], PRIV_SYS_MODIFY, false)?;
+
+        for package in status.packages.iter_mut() {
+            if !package.update_available {
+                continue;
+            }
+            let text =
+                apt::fetch_changelog(&package.package, package.available_version.as_deref())?;
+            let installed = package.installed_version.as_deref().unwrap_or_default();
+            package.changelog = Some(apt::changelog_since(&text, installed));
+        }
+    }
+
+    Ok(status)
+}
+
 #[api(
     input: {
         properties: {
@@ -599,6 +640,10 @@ const SUBDIRS: SubdirMap = &[
             .get(&API_METHOD_APT_UPDATE_AVAILABLE)
             .post(&API_METHOD_APT_UPDATE_DATABASE),
     ),
+    (
+        "server-updates",
+        &Router::new().get(&API_METHOD_GET_SERVER_UPDATES),
+    ),
     ("versions", &Router::new().get(&API_METHOD_GET_VERSIONS)),
 ];
 
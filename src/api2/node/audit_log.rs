@@ -0,0 +1,42 @@
+//! Read the audit log of configuration changes
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router};
+use proxmox_schema::api;
+
+use pbs_api_types::{AuditLogEntry, NODE_SCHEMA, PRIV_SYS_AUDIT};
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            since: {
+                type: Integer,
+                optional: true,
+                description: "Only list entries since this time (Epoch).",
+            },
+            until: {
+                type: Integer,
+                optional: true,
+                description: "Only list entries until this time (Epoch).",
+            },
+        },
+    },
+    returns: {
+        description: "List of configuration changes, oldest first.",
+        type: Array,
+        items: { type: AuditLogEntry },
+    },
+    access: {
+        permission: &Permission::Privilege(&["system", "log"], PRIV_SYS_AUDIT, false),
+    },
+)]
+/// Read the audit log of configuration changes.
+fn read_audit_log(since: Option<i64>, until: Option<i64>) -> Result<Vec<AuditLogEntry>, Error> {
+    crate::server::audit_log::read_audit_log(since, until)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_READ_AUDIT_LOG);
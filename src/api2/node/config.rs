@@ -67,6 +67,24 @@ pub enum DeletableProperty {
     Description,
     /// Delete the task-log-max-days property
     TaskLogMaxDays,
+    /// Delete the tfa-required property
+    TfaRequired,
+    /// Delete the max-datastore-jobs property
+    MaxDatastoreJobs,
+    /// Delete the job-concurrency-timeout property
+    JobConcurrencyTimeout,
+    /// Delete the owner-report-sync-limit property
+    OwnerReportSyncLimit,
+    /// Delete the token-purge-days property
+    TokenPurgeDays,
+    /// Delete the default-datastore-tuning property
+    DefaultDatastoreTuning,
+    /// Delete the default-gc-schedule property
+    DefaultGcSchedule,
+    /// Delete the default-prune-schedule property
+    DefaultPruneSchedule,
+    /// Delete the disk-monitoring property
+    DiskMonitoring,
 }
 
 #[api(
@@ -155,6 +173,33 @@ pub fn update_node_config(
                 DeletableProperty::TaskLogMaxDays => {
                     config.task_log_max_days = None;
                 }
+                DeletableProperty::TfaRequired => {
+                    config.tfa_required = None;
+                }
+                DeletableProperty::MaxDatastoreJobs => {
+                    config.max_datastore_jobs = None;
+                }
+                DeletableProperty::JobConcurrencyTimeout => {
+                    config.job_concurrency_timeout = None;
+                }
+                DeletableProperty::OwnerReportSyncLimit => {
+                    config.owner_report_sync_limit = None;
+                }
+                DeletableProperty::TokenPurgeDays => {
+                    config.token_purge_days = None;
+                }
+                DeletableProperty::DefaultDatastoreTuning => {
+                    config.default_datastore_tuning = None;
+                }
+                DeletableProperty::DefaultGcSchedule => {
+                    config.default_gc_schedule = None;
+                }
+                DeletableProperty::DefaultPruneSchedule => {
+                    config.default_prune_schedule = None;
+                }
+                DeletableProperty::DiskMonitoring => {
+                    config.disk_monitoring = None;
+                }
             }
         }
     }
@@ -198,6 +243,33 @@ pub fn update_node_config(
     if update.task_log_max_days.is_some() {
         config.task_log_max_days = update.task_log_max_days;
     }
+    if update.tfa_required.is_some() {
+        config.tfa_required = update.tfa_required;
+    }
+    if update.max_datastore_jobs.is_some() {
+        config.max_datastore_jobs = update.max_datastore_jobs;
+    }
+    if update.job_concurrency_timeout.is_some() {
+        config.job_concurrency_timeout = update.job_concurrency_timeout;
+    }
+    if update.owner_report_sync_limit.is_some() {
+        config.owner_report_sync_limit = update.owner_report_sync_limit;
+    }
+    if update.token_purge_days.is_some() {
+        config.token_purge_days = update.token_purge_days;
+    }
+    if update.default_datastore_tuning.is_some() {
+        config.default_datastore_tuning = update.default_datastore_tuning;
+    }
+    if update.default_gc_schedule.is_some() {
+        config.default_gc_schedule = update.default_gc_schedule;
+    }
+    if update.default_prune_schedule.is_some() {
+        config.default_prune_schedule = update.default_prune_schedule;
+    }
+    if update.disk_monitoring.is_some() {
+        config.disk_monitoring = update.disk_monitoring;
+    }
 
     crate::config::node::save_config(&config)?;
 
@@ -195,8 +195,9 @@ pub fn create_datastore_disk(
 
             if add_datastore {
                 let lock = pbs_config::datastore::lock_config()?;
-                let datastore: DataStoreConfig =
+                let mut datastore: DataStoreConfig =
                     serde_json::from_value(json!({ "name": name, "path": mount_point }))?;
+                crate::api2::config::datastore::apply_node_defaults(&mut datastore)?;
 
                 let (config, _digest) = pbs_config::datastore::config()?;
 
@@ -208,6 +209,7 @@ pub fn create_datastore_disk(
                     lock,
                     config,
                     datastore,
+                    false,
                     Some(&worker),
                 )?;
             }
@@ -9,13 +9,13 @@ use proxmox_sortable_macro::sortable;
 use proxmox_sys::task_log;
 
 use pbs_api_types::{
-    BLOCKDEVICE_DISK_AND_PARTITION_NAME_SCHEMA, BLOCKDEVICE_NAME_SCHEMA, NODE_SCHEMA,
-    PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, UPID_SCHEMA,
+    DataStoreConfig, BLOCKDEVICE_DISK_AND_PARTITION_NAME_SCHEMA, BLOCKDEVICE_NAME_SCHEMA,
+    NODE_SCHEMA, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY, UPID_SCHEMA,
 };
 
 use crate::tools::disks::{
-    get_smart_data, inititialize_gpt_disk, wipe_blockdev, DiskManage, DiskUsageInfo,
-    DiskUsageQuery, DiskUsageType, SmartData,
+    datastore_backing_disks, get_smart_data, inititialize_gpt_disk, wipe_blockdev, DiskFilter,
+    DiskManage, DiskUsageInfo, DiskUsageQuery, DiskUsageType, SmartData,
 };
 use proxmox_rest_server::WorkerTask;
 
@@ -45,6 +45,12 @@ pub mod zfs;
                 type: DiskUsageType,
                 optional: true,
             },
+            all: {
+                description: "List all disks, ignoring the disk monitoring scope of the node.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
         },
     },
     returns: {
@@ -63,12 +69,16 @@ pub fn list_disks(
     skipsmart: bool,
     include_partitions: bool,
     usage_type: Option<DiskUsageType>,
+    all: bool,
 ) -> Result<Vec<DiskUsageInfo>, Error> {
     let mut list = Vec::new();
 
+    let filter = if all { None } else { disk_monitoring_filter()? };
+
     for (_, info) in DiskUsageQuery::new()
         .smart(!skipsmart)
         .partitions(include_partitions)
+        .filter(filter)
         .query()?
     {
         if let Some(ref usage_type) = usage_type {
@@ -85,6 +95,37 @@ pub fn list_disks(
     Ok(list)
 }
 
+/// Build the disk filter from the disk monitoring scope of the node config, if any.
+fn disk_monitoring_filter() -> Result<Option<DiskFilter>, Error> {
+    let (node_config, _digest) = crate::config::node::config()?;
+    let scope = node_config.disk_monitoring()?;
+
+    let split = |list: Option<String>| -> Vec<String> {
+        list.map(|list| list.split(';').map(String::from).collect())
+            .unwrap_or_default()
+    };
+
+    let datastores_only = scope.datastores_only.unwrap_or(false);
+    if scope.include.is_none() && scope.exclude.is_none() && !datastores_only {
+        return Ok(None);
+    }
+
+    let mut filter = DiskFilter::new(split(scope.include), split(scope.exclude));
+
+    if datastores_only {
+        let (config, _digest) = pbs_config::datastore::config()?;
+        let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+        let paths: Vec<_> = datastores
+            .iter()
+            .map(|store| store.path.clone().into())
+            .collect();
+
+        filter = filter.datastore_disks(datastore_backing_disks(DiskManage::new(), &paths)?);
+    }
+
+    Ok(Some(filter))
+}
+
 #[api(
     protected: true,
     input: {
@@ -310,8 +310,9 @@ pub fn create_zpool(
 
             if add_datastore {
                 let lock = pbs_config::datastore::lock_config()?;
-                let datastore: DataStoreConfig =
+                let mut datastore: DataStoreConfig =
                     serde_json::from_value(json!({ "name": name, "path": mount_point }))?;
+                crate::api2::config::datastore::apply_node_defaults(&mut datastore)?;
 
                 let (config, _digest) = pbs_config::datastore::config()?;
 
@@ -323,6 +324,7 @@ pub fn create_zpool(
                     lock,
                     config,
                     datastore,
+                    false,
                     Some(&worker),
                 )?;
             }
@@ -0,0 +1,79 @@
+//! Results of the access time self-test run before garbage collection
+
+use std::path::Path;
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, DataStoreConfig, DatastoreTuning, GcAtimeCheckListItem, NODE_SCHEMA,
+    PRIV_DATASTORE_AUDIT,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::read_gc_atime_check_status;
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Access time check state per datastore.",
+        type: Array,
+        items: { type: GcAtimeCheckListItem },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Only lists datastores with Datastore.Audit privileges.",
+    },
+)]
+/// List the result of the last access time check of garbage collection for all datastores.
+pub fn list_gc_atime_checks(
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<GcAtimeCheckListItem>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let mut list = Vec::new();
+
+    for store in datastores {
+        let privs = user_info.lookup_privs(&auth_id, &["datastore", &store.name]);
+        if (privs & PRIV_DATASTORE_AUDIT) == 0 {
+            continue;
+        }
+
+        let tuning: DatastoreTuning = serde_json::from_value(
+            DatastoreTuning::API_SCHEMA
+                .parse_property_string(store.tuning.as_deref().unwrap_or(""))?,
+        )?;
+
+        let last_check = match read_gc_atime_check_status(Path::new(&store.path)) {
+            Ok(status) => status,
+            Err(err) => {
+                log::error!(
+                    "unable to read atime check result of '{}' - {err}",
+                    store.name
+                );
+                None
+            }
+        };
+
+        list.push(GcAtimeCheckListItem {
+            store: store.name,
+            enforced: tuning.gc_atime_check.unwrap_or(true),
+            last_check,
+        });
+    }
+
+    Ok(list)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_LIST_GC_ATIME_CHECKS);
@@ -1,100 +1,162 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
-use anyhow::Error;
+use anyhow::{format_err, Error};
+use futures::FutureExt;
+use http::request::Parts;
+use http::{header, Response, StatusCode};
+use hyper::Body;
 use serde_json::{json, Value};
-use std::io::{BufRead, BufReader};
+use tokio::io::AsyncBufReadExt;
+use tokio_stream::wrappers::ReceiverStream;
 
-use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment};
-use proxmox_schema::api;
-
-use pbs_api_types::{NODE_SCHEMA, PRIV_SYS_AUDIT};
-
-#[api(
-    protected: true,
-    input: {
-        properties: {
-            node: {
-                schema: NODE_SCHEMA,
-            },
-            since: {
-                type: Integer,
-                optional: true,
-                description: "Display all log since this UNIX epoch. Conflicts with 'startcursor'.",
-                minimum: 0,
-            },
-            until: {
-                type: Integer,
-                optional: true,
-                description: "Display all log until this UNIX epoch. Conflicts with 'endcursor'.",
-                minimum: 0,
-            },
-            lastentries: {
-                type: Integer,
-                optional: true,
-                description: "Limit to the last X lines. Conflicts with a range.",
-                minimum: 0,
-            },
-            startcursor: {
-                type: String,
-                description: "Start after the given Cursor. Conflicts with 'since'.",
-                optional: true,
-            },
-            endcursor: {
-                type: String,
-                description: "End before the given Cursor. Conflicts with 'until'",
-                optional: true,
-            },
-        },
-    },
-    returns: {
-        type: Array,
-        description: "Returns a list of journal entries.",
-        items: {
-            type: String,
-            description: "Line text.",
-        },
-    },
-    access: {
-        permission: &Permission::Privilege(&["system", "log"], PRIV_SYS_AUDIT, false),
-    },
-)]
-/// Read syslog entries.
-#[allow(clippy::too_many_arguments)]
-fn get_journal(
+use proxmox_router::{
+    ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment,
+};
+use proxmox_schema::{
+    param_bail, BooleanSchema, IntegerSchema, ObjectSchema, Schema, StringSchema,
+};
+use proxmox_sortable_macro::sortable;
+
+use pbs_api_types::{
+    JOURNAL_PRIORITY_SCHEMA, NODE_SCHEMA, PRIV_SYS_AUDIT, SYSTEMD_UNIT_NAME_SCHEMA,
+};
+
+const SINCE_SCHEMA: Schema =
+    IntegerSchema::new("Display all log since this UNIX epoch. Conflicts with 'startcursor'.")
+        .minimum(0)
+        .schema();
+
+const UNTIL_SCHEMA: Schema =
+    IntegerSchema::new("Display all log until this UNIX epoch. Conflicts with 'endcursor'.")
+        .minimum(0)
+        .schema();
+
+const LASTENTRIES_SCHEMA: Schema =
+    IntegerSchema::new("Limit to the last X lines. Conflicts with a range.")
+        .minimum(0)
+        .schema();
+
+const STARTCURSOR_SCHEMA: Schema =
+    StringSchema::new("Start after the given Cursor. Conflicts with 'since'.").schema();
+
+const ENDCURSOR_SCHEMA: Schema =
+    StringSchema::new("End before the given Cursor. Conflicts with 'until'").schema();
+
+const FOLLOW_SCHEMA: Schema = BooleanSchema::new(
+    "Keep the request open and stream new entries as plain text lines, until 'timeout' is \
+        reached or the client disconnects.",
+)
+.default(false)
+.schema();
+
+const TIMEOUT_SCHEMA: Schema =
+    IntegerSchema::new("Maximal duration of a 'follow' request in seconds.")
+        .minimum(1)
+        .maximum(3600)
+        .default(60)
+        .schema();
+
+/// Filters of a journal request.
+struct JournalParams {
     since: Option<i64>,
     until: Option<i64>,
     lastentries: Option<u64>,
     startcursor: Option<String>,
     endcursor: Option<String>,
-    _param: Value,
-    _info: &ApiMethod,
-    _rpcenv: &mut dyn RpcEnvironment,
-) -> Result<Value, Error> {
+    service: Option<String>,
+    priority: Option<String>,
+}
+
+impl JournalParams {
+    fn from_param(param: &Value) -> Result<Self, Error> {
+        let params = Self {
+            since: param["since"].as_i64(),
+            until: param["until"].as_i64(),
+            lastentries: param["lastentries"].as_u64(),
+            startcursor: param["startcursor"].as_str().map(String::from),
+            endcursor: param["endcursor"].as_str().map(String::from),
+            service: param["service"]
+                .as_str()
+                .map(|service| crate::api2::node::services::real_service_name(service).to_string()),
+            priority: param["priority"].as_str().map(String::from),
+        };
+
+        if params.since.is_some() && params.startcursor.is_some() {
+            param_bail!("startcursor", "conflicts with 'since'");
+        }
+        if params.until.is_some() && params.endcursor.is_some() {
+            param_bail!("endcursor", "conflicts with 'until'");
+        }
+        if let (Some(since), Some(until)) = (params.since, params.until) {
+            if since > until {
+                param_bail!("until", "must not be before 'since'");
+            }
+        }
+
+        Ok(params)
+    }
+
+    fn is_filtered(&self) -> bool {
+        self.service.is_some() || self.priority.is_some()
+    }
+
+    /// Arguments for a `journalctl` call, using the `--option=value` form so that no value can
+    /// be mistaken for an option.
+    fn journalctl_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("--output=json"), String::from("--no-pager")];
+
+        if let Some(service) = &self.service {
+            args.push(format!("--unit={service}"));
+        }
+        if let Some(priority) = &self.priority {
+            args.push(format!("--priority={priority}"));
+        }
+        if let Some(since) = self.since {
+            args.push(format!("--since=@{since}"));
+        }
+        if let Some(until) = self.until {
+            args.push(format!("--until=@{until}"));
+        }
+        if let Some(startcursor) = &self.startcursor {
+            args.push(format!("--after-cursor={startcursor}"));
+        }
+
+        args
+    }
+}
+
+/// Read the journal with `mini-journalreader`.
+///
+/// The first and last line of the result are the cursors of the first and last entry.
+fn read_journal(params: &JournalParams) -> Result<Vec<String>, Error> {
     let mut args = vec![];
 
-    if let Some(lastentries) = lastentries {
+    if let Some(lastentries) = params.lastentries {
         args.push(String::from("-n"));
         args.push(format!("{}", lastentries));
     }
 
-    if let Some(since) = since {
+    if let Some(since) = params.since {
         args.push(String::from("-b"));
         args.push(since.to_string());
     }
 
-    if let Some(until) = until {
+    if let Some(until) = params.until {
         args.push(String::from("-e"));
         args.push(until.to_string());
     }
 
-    if let Some(startcursor) = startcursor {
+    if let Some(startcursor) = &params.startcursor {
         args.push(String::from("-f"));
-        args.push(startcursor);
+        args.push(startcursor.clone());
     }
 
-    if let Some(endcursor) = endcursor {
+    if let Some(endcursor) = &params.endcursor {
         args.push(String::from("-t"));
-        args.push(endcursor);
+        args.push(endcursor.clone());
     }
 
     let mut lines: Vec<String> = vec![];
@@ -124,7 +186,331 @@ fn get_journal(
         log::error!("journalctl failed with {}", status);
     }
 
-    Ok(json!(lines))
+    Ok(lines)
+}
+
+/// Read the journal with `journalctl`, which (unlike `mini-journalreader`) can filter by unit
+/// and priority.
+///
+/// The result has the same layout as the one of [read_journal].
+fn read_filtered_journal(params: &JournalParams) -> Result<Vec<String>, Error> {
+    let mut args = params.journalctl_args();
+
+    // with an end cursor, the last entries before it are only known once it was reached
+    if let (Some(lastentries), None) = (params.lastentries, &params.endcursor) {
+        args.push(format!("--lines={lastentries}"));
+    }
+
+    let mut child = Command::new("journalctl")
+        .args(&args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut entries = VecDeque::new();
+    let mut stopped = false;
+
+    if let Some(ref mut stdout) = child.stdout {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::error!("reading journal failed: {}", err);
+                    stopped = true;
+                    break;
+                }
+            };
+            let entry: Value = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::error!("parsing journal entry failed: {}", err);
+                    continue;
+                }
+            };
+
+            let cursor = entry["__CURSOR"].as_str().unwrap_or_default().to_string();
+            if params.endcursor.as_ref() == Some(&cursor) {
+                stopped = true;
+                break;
+            }
+
+            entries.push_back((cursor, format_journal_entry(&entry)));
+            if let Some(lastentries) = params.lastentries {
+                if entries.len() as u64 > lastentries {
+                    entries.pop_front();
+                }
+            }
+        }
+    }
+
+    if stopped {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    if !stopped && !status.success() {
+        log::error!("journalctl failed with {}", status);
+    }
+
+    let mut lines = Vec::with_capacity(entries.len() + 2);
+    if let (Some((first, _)), Some((last, _))) = (entries.front(), entries.back()) {
+        let last = last.clone();
+        lines.push(first.clone());
+        lines.extend(entries.into_iter().map(|(_, line)| line));
+        lines.push(last);
+    }
+
+    Ok(lines)
+}
+
+/// Format a journal entry (as exported by `journalctl --output=json`) like a syslog line.
+fn format_journal_entry(entry: &Value) -> String {
+    let time = entry["__REALTIME_TIMESTAMP"]
+        .as_str()
+        .and_then(|usec| usec.parse::<i64>().ok())
+        .and_then(|usec| proxmox_time::strftime_local("%b %d %H:%M:%S", usec / 1_000_000).ok())
+        .unwrap_or_default();
+    let host = entry["_HOSTNAME"].as_str().unwrap_or_default();
+    let ident = entry["SYSLOG_IDENTIFIER"]
+        .as_str()
+        .or_else(|| entry["_COMM"].as_str())
+        .unwrap_or("unknown");
+    let message = journal_field_text(&entry["MESSAGE"]);
+
+    match entry["_PID"].as_str() {
+        Some(pid) => format!("{time} {host} {ident}[{pid}]: {message}"),
+        None => format!("{time} {host} {ident}: {message}"),
+    }
+}
+
+/// Fields which are not valid UTF-8 get exported as array of bytes.
+fn journal_field_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|b| b.as_u64())
+                .map(|b| b as u8)
+                .collect();
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => String::new(),
+    }
+}
+
+/// Stream new journal entries as plain text lines, until `timeout` is reached or the client
+/// disconnects.
+fn follow_journal(params: JournalParams, timeout: Duration) -> Result<Response<Body>, Error> {
+    let mut args = params.journalctl_args();
+    args.push(String::from("--follow"));
+    if let Some(lastentries) = params.lastentries {
+        args.push(format!("--lines={lastentries}"));
+    }
+
+    let mut child = tokio::process::Command::new("journalctl")
+        .args(&args)
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| format_err!("unable to read journalctl output"))?;
+
+    let (sender, receiver) = tokio::sync::mpsc::channel::<Result<Vec<u8>, Error>>(64);
+
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(stdout).lines();
+        let deadline = tokio::time::sleep(timeout);
+        tokio::pin!(deadline);
+
+        loop {
+            let line = tokio::select! {
+                _ = &mut deadline => break,
+                _ = sender.closed() => break,
+                line = lines.next_line() => line,
+            };
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    log::error!("reading journal failed: {}", err);
+                    break;
+                }
+            };
+            let mut text = match serde_json::from_str(&line) {
+                Ok(entry) => format_journal_entry(&entry),
+                Err(err) => {
+                    log::error!("parsing journal entry failed: {}", err);
+                    continue;
+                }
+            };
+            text.push('\n');
+            if sender.send(Ok(text.into_bytes())).await.is_err() {
+                break;
+            }
+        }
+
+        let _ = child.kill().await;
+    });
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::wrap_stream(ReceiverStream::new(receiver)))
+        .unwrap())
+}
+
+#[sortable]
+pub const API_METHOD_GET_JOURNAL: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&get_journal),
+    &ObjectSchema::new(
+        "Read syslog entries. Returns a list of lines, the first and last line are the cursors of \
+        the first and last entry. With 'follow', new entries are streamed as plain text instead.",
+        &sorted!([
+            ("node", false, &NODE_SCHEMA),
+            ("since", true, &SINCE_SCHEMA),
+            ("until", true, &UNTIL_SCHEMA),
+            ("lastentries", true, &LASTENTRIES_SCHEMA),
+            ("startcursor", true, &STARTCURSOR_SCHEMA),
+            ("endcursor", true, &ENDCURSOR_SCHEMA),
+            ("service", true, &SYSTEMD_UNIT_NAME_SCHEMA),
+            ("priority", true, &JOURNAL_PRIORITY_SCHEMA),
+            ("follow", true, &FOLLOW_SCHEMA),
+            ("timeout", true, &TIMEOUT_SCHEMA),
+        ]),
+    ),
+)
+.protected(true)
+.access(
+    None,
+    &Permission::Privilege(&["system", "log"], PRIV_SYS_AUDIT, false),
+);
+fn get_journal(
+    _parts: Parts,
+    _req_body: Body,
+    param: Value,
+    _info: &ApiMethod,
+    _rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        let params = JournalParams::from_param(&param)?;
+
+        if param["follow"].as_bool().unwrap_or(false) {
+            if params.until.is_some() || params.endcursor.is_some() {
+                param_bail!("follow", "cannot be used with 'until' or 'endcursor'");
+            }
+            let timeout = param["timeout"].as_u64().unwrap_or(60);
+            return follow_journal(params, Duration::from_secs(timeout));
+        } else if !param["timeout"].is_null() {
+            param_bail!("timeout", "only valid with 'follow'");
+        }
+
+        let lines = tokio::task::spawn_blocking(move || {
+            if params.is_filtered() {
+                read_filtered_journal(&params)
+            } else {
+                read_journal(&params)
+            }
+        })
+        .await
+        .map_err(|err| format_err!("failed to await blocking task: {err}"))??;
+
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                json!({ "data": lines, "success": 1 }).to_string(),
+            ))
+            .unwrap())
+    }
+    .boxed()
 }
 
 pub const ROUTER: Router = Router::new().get(&API_METHOD_GET_JOURNAL);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_journalctl_args() -> Result<(), Error> {
+        let params = JournalParams::from_param(&json!({}))?;
+        assert!(!params.is_filtered());
+        assert_eq!(params.journalctl_args(), ["--output=json", "--no-pager"]);
+
+        let params = JournalParams::from_param(&json!({
+            "priority": "warning",
+            "since": 100,
+            "until": 200,
+            "lastentries": 50,
+        }))?;
+        assert!(params.is_filtered());
+        assert_eq!(
+            params.journalctl_args(),
+            [
+                "--output=json",
+                "--no-pager",
+                "--priority=warning",
+                "--since=@100",
+                "--until=@200",
+            ],
+        );
+
+        // values starting with a dash must not turn into options of their own
+        let params = JournalParams::from_param(&json!({ "startcursor": "-s=foo" }))?;
+        assert_eq!(
+            params.journalctl_args(),
+            ["--output=json", "--no-pager", "--after-cursor=-s=foo"],
+        );
+
+        assert!(JournalParams::from_param(&json!({ "since": 1, "startcursor": "c" })).is_err());
+        assert!(JournalParams::from_param(&json!({ "until": 1, "endcursor": "c" })).is_err());
+        assert!(JournalParams::from_param(&json!({ "since": 2, "until": 1 })).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_journal_entry() {
+        let entry = json!({
+            "_HOSTNAME": "pbs",
+            "SYSLOG_IDENTIFIER": "proxmox-backup-proxy",
+            "_COMM": "proxmox-backup-",
+            "_PID": "1234",
+            "MESSAGE": "starting task",
+        });
+        assert_eq!(
+            format_journal_entry(&entry),
+            " pbs proxmox-backup-proxy[1234]: starting task",
+        );
+
+        // falls back to the command name, without a pid
+        let entry = json!({
+            "_HOSTNAME": "pbs",
+            "_COMM": "kernel",
+            "MESSAGE": "message",
+        });
+        assert_eq!(format_journal_entry(&entry), " pbs kernel: message");
+
+        // non UTF-8 messages are exported as byte arrays
+        let entry = json!({
+            "_HOSTNAME": "pbs",
+            "MESSAGE": [104, 105, 255],
+        });
+        assert_eq!(format_journal_entry(&entry), " pbs unknown: hi\u{fffd}");
+
+        let usec = 1_700_000_000_123_456i64;
+        let entry = json!({
+            "__REALTIME_TIMESTAMP": usec.to_string(),
+            "_HOSTNAME": "pbs",
+            "SYSLOG_IDENTIFIER": "systemd",
+            "MESSAGE": "started",
+        });
+        let time = proxmox_time::strftime_local("%b %d %H:%M:%S", usec / 1_000_000).unwrap();
+        assert_eq!(
+            format_journal_entry(&entry),
+            format!("{time} pbs systemd: started"),
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! List the currently held backup group and snapshot locks
+
+use std::path::Path;
+
+use anyhow::Error;
+
+use proxmox_router::{Permission, Router, RpcEnvironment};
+use proxmox_schema::api;
+
+use pbs_api_types::{
+    Authid, DataStoreConfig, NodeLockEntry, NODE_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_SYS_AUDIT,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::lock_tracking::list_lock_holders;
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "List of held locks, oldest first.",
+        type: Array,
+        items: { type: NodeLockEntry },
+    },
+    access: {
+        permission: &Permission::Anybody,
+        description: "Only lists locks on datastores with Datastore.Audit privileges, other \
+            locks require Sys.Audit on /system/status.",
+    },
+)]
+/// List the currently held backup group and snapshot locks, with their holder.
+pub fn list_locks(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<NodeLockEntry>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    let sys_audit = (user_info.lookup_privs(&auth_id, &["system", "status"]) & PRIV_SYS_AUDIT) != 0;
+
+    let now = proxmox_time::epoch_i64();
+    let mut list = Vec::new();
+
+    for holder in list_lock_holders(None)? {
+        // prefer the most specific datastore if paths are nested
+        let store = datastores
+            .iter()
+            .filter(|store| holder.path.starts_with(Path::new(&store.path)))
+            .max_by_key(|store| store.path.len())
+            .map(|store| store.name.clone());
+
+        let allowed = match store {
+            Some(ref store) => {
+                (user_info.lookup_privs(&auth_id, &["datastore", store]) & PRIV_DATASTORE_AUDIT)
+                    != 0
+            }
+            None => sys_audit,
+        };
+        if !allowed {
+            continue;
+        }
+
+        list.push(NodeLockEntry {
+            path: holder.path.to_string_lossy().into_owned(),
+            store,
+            what: holder.what,
+            exclusive: holder.exclusive,
+            pid: holder.pid,
+            upid: holder.upid,
+            operation: holder.operation,
+            since: holder.since,
+            age: (now - holder.since).max(0),
+        });
+    }
+
+    list.sort_by_key(|entry| entry.since);
+
+    Ok(list)
+}
+
+pub const ROUTER: Router = Router::new().get(&API_METHOD_LIST_LOCKS);
@@ -40,7 +40,10 @@ pub mod tasks;
 
 pub(crate) mod rrd;
 
+mod audit_log;
+mod gc_atime_check;
 mod journal;
+pub mod locks;
 mod report;
 pub(crate) mod services;
 mod status;
@@ -323,11 +326,14 @@ fn list_nodes() -> Result<Value, Error> {
 
 pub const SUBDIRS: SubdirMap = &[
     ("apt", &apt::ROUTER),
+    ("audit-log", &audit_log::ROUTER),
     ("certificates", &certificates::ROUTER),
     ("config", &config::ROUTER),
     ("disks", &disks::ROUTER),
     ("dns", &dns::ROUTER),
+    ("gc-atime-check", &gc_atime_check::ROUTER),
     ("journal", &journal::ROUTER),
+    ("locks", &locks::ROUTER),
     ("network", &network::ROUTER),
     ("report", &report::ROUTER),
     ("rrd", &rrd::ROUTER),
@@ -335,6 +341,7 @@ pub const SUBDIRS: SubdirMap = &[
     ("status", &status::ROUTER),
     ("subscription", &subscription::ROUTER),
     ("syslog", &syslog::ROUTER),
+    ("task-stats", &tasks::STATS_ROUTER),
     ("tasks", &tasks::ROUTER),
     ("termproxy", &Router::new().post(&API_METHOD_TERMPROXY)),
     ("time", &time::ROUTER),
@@ -102,6 +102,11 @@ async fn get_status(
 
     let boot_info = boot_mode_to_info(boot_mode::BootMode::query(), boot_mode::SecureBoot::query());
 
+    // only reads the local package index, cached until it changes
+    let updates = tokio::task::spawn_blocking(crate::tools::apt::server_update_status)
+        .await
+        .ok();
+
     Ok(NodeStatus {
         memory,
         swap,
@@ -121,6 +126,7 @@ async fn get_status(
             fingerprint: crate::cert_info()?.fingerprint()?,
         },
         boot_info,
+        updates,
     })
 }
 
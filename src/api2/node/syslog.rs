@@ -6,7 +6,9 @@ use serde_json::{json, Value};
 use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment};
 use proxmox_schema::api;
 
-use pbs_api_types::{NODE_SCHEMA, PRIV_SYS_AUDIT, SYSTEMD_DATETIME_FORMAT};
+use pbs_api_types::{
+    NODE_SCHEMA, PRIV_SYS_AUDIT, SYSTEMD_DATETIME_FORMAT, SYSTEMD_UNIT_NAME_SCHEMA,
+};
 
 fn dump_journal(
     start: Option<u64>,
@@ -111,10 +113,8 @@ fn dump_journal(
 	        format: &SYSTEMD_DATETIME_FORMAT,
             },
             service: {
-                type: String,
+                schema: SYSTEMD_UNIT_NAME_SCHEMA,
                 optional: true,
-                description: "Service ID.",
-                max_length: 128,
             },
         },
     },
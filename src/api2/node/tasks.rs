@@ -1,5 +1,8 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Error};
 use futures::FutureExt;
@@ -17,8 +20,8 @@ use proxmox_schema::{api, BooleanSchema, IntegerSchema, ObjectSchema, Schema};
 use proxmox_sortable_macro::sortable;
 
 use pbs_api_types::{
-    Authid, TaskListItem, TaskStateType, Tokenname, Userid, DATASTORE_SCHEMA, NODE_SCHEMA,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_VERIFY, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
+    Authid, TaskIoStats, TaskListItem, TaskStateType, Tokenname, Userid, DATASTORE_SCHEMA,
+    NODE_SCHEMA, PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_VERIFY, PRIV_SYS_AUDIT, PRIV_SYS_MODIFY,
     SYNC_JOB_WORKER_ID_REGEX, UPID, UPID_SCHEMA, VERIFICATION_JOB_WORKER_ID_REGEX,
 };
 
@@ -52,6 +55,17 @@ pub const TEST_STATUS_PARAM_SCHEMA: Schema =
     BooleanSchema::new("Test task status, and set result attribute \"active\" accordingly.")
         .schema();
 
+pub const FOLLOW_PARAM_SCHEMA: Schema = BooleanSchema::new(
+    "Wait for new lines if the task is still running (long-poll). Lines get returned with their \
+        timestamp parsed out, and the exit status is included once the task finished.",
+)
+.default(false)
+.schema();
+
+/// Maximal time a follow request waits for new lines before returning an empty result.
+const FOLLOW_TIMEOUT: Duration = Duration::from_secs(25);
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 // matches respective job execution privileges
 fn check_job_privs(auth_id: &Authid, user_info: &CachedUserInfo, upid: &UPID) -> Result<(), Error> {
     match (upid.worker_type.as_str(), &upid.worker_id) {
@@ -118,7 +132,7 @@ fn check_job_privs(auth_id: &Authid, user_info: &CachedUserInfo, upid: &UPID) ->
 }
 
 // get the store out of the worker_id
-fn check_job_store(upid: &UPID, store: &str) -> bool {
+pub(crate) fn check_job_store(upid: &UPID, store: &str) -> bool {
     match (upid.worker_type.as_str(), &upid.worker_id) {
         (workertype, Some(workerid)) if workertype.starts_with("verif") => {
             if let Some(captures) = VERIFICATION_JOB_WORKER_ID_REGEX.captures(workerid) {
@@ -148,7 +162,7 @@ fn check_job_store(upid: &UPID, store: &str) -> bool {
     false
 }
 
-fn check_task_access(auth_id: &Authid, upid: &UPID) -> Result<(), Error> {
+pub(crate) fn check_task_access(auth_id: &Authid, upid: &UPID) -> Result<(), Error> {
     let task_auth_id: Authid = upid.auth_id.parse()?;
     if auth_id == &task_auth_id
         || (task_auth_id.is_token() && &Authid::from(task_auth_id.user().clone()) == auth_id)
@@ -194,6 +208,10 @@ fn into_task_list_item(info: proxmox_rest_server::TaskListInfo) -> pbs_api_types
         user: info.upid.auth_id,
         endtime,
         status,
+        read_bytes: None,
+        write_bytes: None,
+        read_rate: None,
+        write_rate: None,
     }
 }
 
@@ -296,6 +314,79 @@ async fn get_task_status(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Resul
     Ok(result)
 }
 
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+            upid: {
+                schema: UPID_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: TaskIoStats,
+    },
+    access: {
+        description: "Users can access their own tasks, or need Sys.Audit on /system/tasks.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// Get the datastore IO of a task.
+///
+/// Tasks which are not running anymore, or did not access any datastore, report zeros.
+fn get_task_stats(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<TaskIoStats, Error> {
+    let upid = extract_upid(&param)?;
+
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    check_task_access(&auth_id, &upid)?;
+
+    let upid_str = upid.to_string();
+    let stats = pbs_datastore::task_io_stats::read_task_io_stats()?
+        .into_iter()
+        .find(|stats| stats.upid == upid_str)
+        .unwrap_or(TaskIoStats {
+            upid: upid_str,
+            ..Default::default()
+        });
+
+    Ok(stats)
+}
+
+#[api(
+    input: {
+        properties: {
+            node: {
+                schema: NODE_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        description: "Datastore IO of the running tasks.",
+        type: Array,
+        items: { type: TaskIoStats },
+    },
+    access: {
+        description: "Users can only see their own tasks, unless they have Sys.Audit on /system/tasks.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List the datastore IO of all running tasks.
+pub fn list_task_stats(rpcenv: &mut dyn RpcEnvironment) -> Result<Vec<TaskIoStats>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+
+    let list = pbs_datastore::task_io_stats::read_task_io_stats()?
+        .into_iter()
+        .filter(|stats| match stats.upid.parse::<UPID>() {
+            Ok(upid) => check_task_access(&auth_id, &upid).is_ok(),
+            Err(_) => false,
+        })
+        .collect();
+
+    Ok(list)
+}
+
 fn extract_upid(param: &Value) -> Result<UPID, Error> {
     pbs_tools::json::required_string_param(param, "upid")?.parse::<UPID>()
 }
@@ -311,7 +402,8 @@ pub const API_METHOD_READ_TASK_LOG: ApiMethod = ApiMethod::new(
             ("start", true, &START_PARAM_SCHEMA),
             ("limit", true, &LIMIT_PARAM_SCHEMA),
             ("download", true, &DOWNLOAD_PARAM_SCHEMA),
-            ("test-status", true, &TEST_STATUS_PARAM_SCHEMA)
+            ("test-status", true, &TEST_STATUS_PARAM_SCHEMA),
+            ("follow", true, &FOLLOW_PARAM_SCHEMA)
         ]),
     ),
 )
@@ -338,6 +430,7 @@ fn read_task_log(
             if !param["start"].is_null()
                 || !param["limit"].is_null()
                 || !param["test-status"].is_null()
+                || !param["follow"].is_null()
             {
                 bail!("Parameter 'download' cannot be used with other parameters");
             }
@@ -361,6 +454,15 @@ fn read_task_log(
         let mut limit = param["limit"].as_u64().unwrap_or(50);
         let test_status = param["test-status"].as_bool().unwrap_or(false);
 
+        if param["follow"].as_bool().unwrap_or(false) {
+            let json = follow_task_log(&upid, &path, start, limit).await?;
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json.to_string()))
+                .unwrap());
+        }
+
         let file = File::open(path)?;
 
         let mut count: u64 = 0;
@@ -410,6 +512,76 @@ fn read_task_log(
     .boxed()
 }
 
+/// Split a task log line into the parsed timestamp and the message.
+fn parse_task_log_line(line: &str) -> (Option<i64>, &str) {
+    match (line.get(..25), line.get(25..27)) {
+        (Some(time), Some(": ")) => match proxmox_time::parse_rfc3339(time) {
+            Ok(time) => (Some(time), &line[27..]),
+            Err(_) => (None, line),
+        },
+        _ => (None, line),
+    }
+}
+
+/// Read up to `limit` lines (0 for all) starting at line `start`, also returns the total line
+/// count. A missing log file is treated as empty.
+fn read_task_log_lines(path: &Path, start: u64, limit: u64) -> Result<(Vec<Value>, u64), Error> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = Vec::new();
+    let mut count = 0;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        count += 1;
+        if count < start || (limit != 0 && lines.len() as u64 >= limit) {
+            continue;
+        }
+        let (time, msg) = parse_task_log_line(&line);
+        lines.push(json!({ "n": count, "t": line, "time": time, "msg": msg }));
+    }
+
+    Ok((lines, count))
+}
+
+/// Long-poll variant of reading the task log, waits until there are new lines after `start` or
+/// the task finished.
+///
+/// If the log has fewer lines than `start` points at, for example because it was truncated, this
+/// sets `gap` instead of failing, clients can continue reading after `total`.
+async fn follow_task_log(upid: &UPID, path: &Path, start: u64, limit: u64) -> Result<Value, Error> {
+    let start = start.max(1);
+    let deadline = Instant::now() + FOLLOW_TIMEOUT;
+
+    loop {
+        // check the state first, a finished task won't write any more lines afterwards
+        let active = proxmox_rest_server::worker_is_active(upid).await?;
+        let (lines, total) = read_task_log_lines(path, start, limit)?;
+
+        if !lines.is_empty() || !active || Instant::now() >= deadline {
+            let mut json = json!({
+                "data": lines,
+                "total": total,
+                "active": active,
+                "success": 1,
+            });
+            if total + 1 < start {
+                json["gap"] = true.into();
+            }
+            if !active {
+                let status = upid_read_status(upid).unwrap_or(TaskState::Unknown { endtime: 0 });
+                json["exitstatus"] = status.to_string().into();
+            }
+            return Ok(json);
+        }
+
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+    }
+}
+
 #[api(
     protected: true,
     input: {
@@ -507,6 +679,12 @@ fn stop_task(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Err
                     type: TaskStateType,
                 },
             },
+            stats: {
+                type: bool,
+                description: "Include the datastore IO of running tasks.",
+                optional: true,
+                default: false,
+            },
         },
     },
     returns: pbs_api_types::NODE_TASKS_LIST_TASKS_RETURN_TYPE,
@@ -527,6 +705,7 @@ pub fn list_tasks(
     until: Option<i64>,
     typefilter: Option<String>,
     statusfilter: Option<Vec<TaskStateType>>,
+    stats: bool,
     param: Value,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Vec<TaskListItem>, Error> {
@@ -618,6 +797,22 @@ pub fn list_tasks(
         }
     }
 
+    if stats {
+        let io_stats: HashMap<String, TaskIoStats> =
+            pbs_datastore::task_io_stats::read_task_io_stats()?
+                .into_iter()
+                .map(|stats| (stats.upid.clone(), stats))
+                .collect();
+
+        for item in result.iter_mut().filter(|item| item.endtime.is_none()) {
+            let stats = io_stats.get(&item.upid).cloned().unwrap_or_default();
+            item.read_bytes = Some(stats.read_bytes);
+            item.write_bytes = Some(stats.write_bytes);
+            item.read_rate = Some(stats.read_rate);
+            item.write_rate = Some(stats.write_rate);
+        }
+    }
+
     let mut count = result.len() + start as usize;
     if !result.is_empty() && result.len() >= limit {
         // we have a 'virtual' entry as long as we have any new
@@ -632,6 +827,7 @@ pub fn list_tasks(
 #[sortable]
 const UPID_API_SUBDIRS: SubdirMap = &sorted!([
     ("log", &Router::new().get(&API_METHOD_READ_TASK_LOG)),
+    ("stats", &Router::new().get(&API_METHOD_GET_TASK_STATS)),
     ("status", &Router::new().get(&API_METHOD_GET_TASK_STATUS))
 ]);
 
@@ -640,6 +836,8 @@ pub const UPID_API_ROUTER: Router = Router::new()
     .delete(&API_METHOD_STOP_TASK)
     .subdirs(UPID_API_SUBDIRS);
 
+pub const STATS_ROUTER: Router = Router::new().get(&API_METHOD_LIST_TASK_STATS);
+
 pub const ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_TASKS)
     .match_all("upid", &UPID_API_ROUTER);
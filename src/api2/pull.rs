@@ -1,4 +1,6 @@
 //! Sync datastore from remote server
+use std::time::Instant;
+
 use anyhow::{bail, format_err, Error};
 use futures::{future::FutureExt, select};
 
@@ -7,17 +9,21 @@ use proxmox_schema::api;
 use proxmox_sys::task_log;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
-    GROUP_FILTER_LIST_SCHEMA, NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP,
-    PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ, REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA,
-    TRANSFER_LAST_SCHEMA,
+    Authid, BackupNamespace, GroupFilter, JobSummary, RateLimitConfig, SyncDirection,
+    SyncJobConfig, DATASTORE_SCHEMA, ENCRYPTED_ONLY_SCHEMA, GROUP_FILTER_LIST_SCHEMA,
+    NS_MAX_DEPTH_REDUCED_SCHEMA, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_PRUNE, PRIV_REMOTE_READ,
+    REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, SYNC_METADATA_SCHEMA, TRANSFER_LAST_SCHEMA,
+    VERIFIED_ONLY_SCHEMA, VERIFY_DOWNLOADS_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::task_io_stats::track_task_io;
 use proxmox_human_byte::HumanByte;
 use proxmox_rest_server::WorkerTask;
 
 use crate::server::jobstate::Job;
 use crate::server::pull::{pull_store, PullParameters};
+use crate::server::push::{push_store, PushParameters};
+use crate::server::snapshot_filter::SnapshotFilter;
 
 pub fn check_pull_privs(
     auth_id: &Authid,
@@ -85,14 +91,129 @@ impl TryFrom<&SyncJobConfig> for PullParameters {
                 .unwrap_or_else(|| Authid::root_auth_id())
                 .clone(),
             sync_job.remove_vanished,
+            sync_job.preserve_owner.unwrap_or(false),
+            sync_job.force_owner.unwrap_or(false),
+            sync_job.max_depth,
+            sync_job.group_filter.clone(),
+            sync_job.limit.clone(),
+            sync_job.transfer_last,
+            sync_job.verify_downloads.unwrap_or(false),
+            sync_job.sync_metadata.unwrap_or(true),
+            snapshot_filter(sync_job.encrypted_only, sync_job.verified_only),
+        )
+    }
+}
+
+impl TryFrom<&SyncJobConfig> for PushParameters {
+    type Error = Error;
+
+    fn try_from(sync_job: &SyncJobConfig) -> Result<Self, Self::Error> {
+        let remote = match sync_job.remote.as_deref() {
+            Some(remote) => remote,
+            None => bail!("push sync jobs require a remote"),
+        };
+        PushParameters::new(
+            &sync_job.store,
+            sync_job.ns.clone().unwrap_or_default(),
+            remote,
+            &sync_job.remote_store,
+            sync_job.remote_ns.clone().unwrap_or_default(),
+            sync_job
+                .owner
+                .as_ref()
+                .unwrap_or_else(|| Authid::root_auth_id())
+                .clone(),
             sync_job.max_depth,
             sync_job.group_filter.clone(),
             sync_job.limit.clone(),
             sync_job.transfer_last,
+            snapshot_filter(sync_job.encrypted_only, sync_job.verified_only),
         )
     }
 }
 
+fn snapshot_filter(encrypted_only: Option<bool>, verified_only: Option<bool>) -> SnapshotFilter {
+    SnapshotFilter {
+        encrypted_only: encrypted_only.unwrap_or(false),
+        verified_only: verified_only.unwrap_or(false),
+    }
+}
+
+fn log_snapshot_filter_summary(
+    worker: &WorkerTask,
+    skipped_not_encrypted: usize,
+    skipped_not_verified: usize,
+) {
+    if skipped_not_encrypted > 0 {
+        task_log!(
+            worker,
+            "Summary: skipped {skipped_not_encrypted} snapshots which are not fully encrypted",
+        );
+    }
+    if skipped_not_verified > 0 {
+        task_log!(
+            worker,
+            "Summary: skipped {skipped_not_verified} snapshots which are not successfully verified",
+        );
+    }
+}
+
+async fn push_sync_job(worker: &WorkerTask, sync_job: &SyncJobConfig) -> Result<JobSummary, Error> {
+    let push_params = PushParameters::try_from(sync_job)?;
+
+    task_log!(
+        worker,
+        "sync datastore '{}' to '{}/{}'",
+        sync_job.store,
+        sync_job.remote.as_deref().unwrap_or_default(),
+        sync_job.remote_store,
+    );
+
+    let push_stats = push_store(worker, push_params).await?;
+
+    if push_stats.bytes != 0 {
+        let amount = HumanByte::from(push_stats.bytes);
+        let rate =
+            HumanByte::new_binary(push_stats.bytes as f64 / push_stats.elapsed.as_secs_f64());
+        task_log!(
+            worker,
+            "Summary: sync job pushed {} snapshots, {amount} in {} chunks (average rate: {rate}/s)",
+            push_stats.snapshots,
+            push_stats.chunk_count,
+        );
+    } else {
+        task_log!(
+            worker,
+            "Summary: sync job pushed {} snapshots without new chunk data",
+            push_stats.snapshots,
+        );
+    }
+
+    if push_stats.skipped_transfer_last > 0 {
+        task_log!(
+            worker,
+            "Summary: skipped {} older snapshots due to transfer-last",
+            push_stats.skipped_transfer_last,
+        );
+    }
+
+    log_snapshot_filter_summary(
+        worker,
+        push_stats.skipped_not_encrypted,
+        push_stats.skipped_not_verified,
+    );
+
+    // the items of a push are the pushed snapshots
+    Ok(JobSummary {
+        succeeded: push_stats.snapshots as u64,
+        skipped: (push_stats.skipped_transfer_last
+            + push_stats.skipped_not_encrypted
+            + push_stats.skipped_not_verified) as u64,
+        bytes: Some(push_stats.bytes),
+        ..Default::default()
+    })
+}
+
 pub fn do_sync_job(
     mut job: Job,
     sync_job: SyncJobConfig,
@@ -114,7 +235,7 @@ pub fn do_sync_job(
         bail!("can't sync to same datastore");
     }
 
-    let (email, notify) = crate::server::lookup_datastore_notify_settings(&sync_job.store);
+    let notification = crate::server::lookup_datastore_notify_settings(&sync_job.store);
 
     let upid_str = WorkerTask::spawn(
         &worker_type,
@@ -123,17 +244,26 @@ pub fn do_sync_job(
         to_stdout,
         move |worker| async move {
             job.start(&worker.upid().to_string())?;
+            let start_time = Instant::now();
 
             let worker2 = worker.clone();
             let sync_job2 = sync_job.clone();
 
-            let worker_future = async move {
-                let pull_params = PullParameters::try_from(&sync_job)?;
-
+            let upid = worker.upid().to_string();
+            let worker_future = track_task_io(upid, async move {
                 task_log!(worker, "Starting datastore sync job '{}'", job_id);
                 if let Some(event_str) = schedule {
                     task_log!(worker, "task triggered by schedule '{}'", event_str);
                 }
+
+                if sync_job.sync_direction() == SyncDirection::Push {
+                    let summary = push_sync_job(&worker, &sync_job).await?;
+                    task_log!(worker, "sync job '{}' end", &job_id);
+                    return Ok(summary);
+                }
+
+                let pull_params = PullParameters::try_from(&sync_job)?;
+
                 task_log!(
                     worker,
                     "sync datastore '{}' from '{}{}'",
@@ -161,10 +291,42 @@ pub fn do_sync_job(
                     task_log!(worker, "Summary: sync job found no new data to pull");
                 }
 
+                if pull_stats.skipped_transfer_last > 0 {
+                    task_log!(
+                        worker,
+                        "Summary: skipped {} older snapshots due to transfer-last",
+                        pull_stats.skipped_transfer_last,
+                    );
+                }
+
+                log_snapshot_filter_summary(
+                    &worker,
+                    pull_stats.skipped_not_encrypted,
+                    pull_stats.skipped_not_verified,
+                );
+
+                if sync_job.verify_downloads.unwrap_or(false) {
+                    task_log!(
+                        worker,
+                        "Summary: verified {} downloaded chunks",
+                        pull_stats.verified_chunks,
+                    );
+                }
+
+                if pull_stats.metadata_updates > 0 {
+                    task_log!(
+                        worker,
+                        "Summary: updated notes or protection of {} existing snapshots",
+                        pull_stats.metadata_updates,
+                    );
+                }
+
                 task_log!(worker, "sync job '{}' end", &job_id);
 
-                Ok(())
-            };
+                let mut summary = pull_stats.summary;
+                summary.bytes = Some(pull_stats.bytes as u64);
+                Ok(summary)
+            });
 
             let mut abort_future = worker2
                 .abort_future()
@@ -175,21 +337,37 @@ pub fn do_sync_job(
                 abort = abort_future => abort,
             };
 
+            let (result, mut summary) = match result {
+                Ok(summary) if summary.failed > 0 => {
+                    (Err(format_err!("sync failed with some errors.")), summary)
+                }
+                Ok(summary) => (Ok(()), summary),
+                Err(err) => {
+                    let mut summary = JobSummary::default();
+                    summary.add_failure(&sync_job2.store, &err);
+                    (Err(err), summary)
+                }
+            };
+            summary.duration = start_time.elapsed().as_secs_f64();
+
             let status = worker2.create_state(&result);
+            let severity = crate::server::task_severity(&status);
 
-            match job.finish(status) {
+            match job.finish_with_summary(status, Some(summary.clone())) {
                 Ok(_) => {}
                 Err(err) => {
                     eprintln!("could not finish job state: {}", err);
                 }
             }
 
-            if let Some(email) = email {
-                if let Err(err) =
-                    crate::server::send_sync_status(&email, notify, &sync_job2, &result)
-                {
-                    eprintln!("send sync notification failed: {}", err);
-                }
+            if let Err(err) = crate::server::send_sync_status(
+                &notification,
+                &sync_job2,
+                &result,
+                severity,
+                &summary,
+            ) {
+                eprintln!("send sync notification failed: {}", err);
             }
 
             result
@@ -240,6 +418,22 @@ pub fn do_sync_job(
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "verify-downloads": {
+                schema: VERIFY_DOWNLOADS_SCHEMA,
+                optional: true,
+            },
+            "sync-metadata": {
+                schema: SYNC_METADATA_SCHEMA,
+                optional: true,
+            },
+            "encrypted-only": {
+                schema: ENCRYPTED_ONLY_SCHEMA,
+                optional: true,
+            },
+            "verified-only": {
+                schema: VERIFIED_ONLY_SCHEMA,
+                optional: true,
+            },
         },
     },
     access: {
@@ -264,6 +458,10 @@ async fn pull(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    verify_downloads: Option<bool>,
+    sync_metadata: Option<bool>,
+    encrypted_only: Option<bool>,
+    verified_only: Option<bool>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<String, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
@@ -297,10 +495,15 @@ async fn pull(
         remote_ns.unwrap_or_default(),
         auth_id.clone(),
         remove_vanished,
+        false,
+        false,
         max_depth,
         group_filter,
         limit,
         transfer_last,
+        verify_downloads.unwrap_or(false),
+        sync_metadata.unwrap_or(true),
+        snapshot_filter(encrypted_only, verified_only),
     )?;
 
     // fixme: set to_stdout to false?
@@ -319,11 +522,35 @@ async fn pull(
                 remote_store,
             );
 
-            let pull_future = pull_store(&worker, pull_params);
-            (select! {
+            let upid = worker.upid().to_string();
+            let pull_future = track_task_io(upid, pull_store(&worker, pull_params));
+            let pull_stats = (select! {
                 success = pull_future.fuse() => success,
                 abort = worker.abort_future().map(|_| Err(format_err!("pull aborted"))) => abort,
             })?;
+            pull_stats.check_errors()?;
+
+            if verify_downloads.unwrap_or(false) {
+                task_log!(
+                    worker,
+                    "Summary: verified {} downloaded chunks",
+                    pull_stats.verified_chunks,
+                );
+            }
+
+            if pull_stats.metadata_updates > 0 {
+                task_log!(
+                    worker,
+                    "Summary: updated notes or protection of {} existing snapshots",
+                    pull_stats.metadata_updates,
+                );
+            }
+
+            log_snapshot_filter_summary(
+                &worker,
+                pull_stats.skipped_not_encrypted,
+                pull_stats.skipped_not_verified,
+            );
 
             task_log!(worker, "pull datastore '{}' end", store);
 
@@ -5,7 +5,7 @@ use serde_json::{json, Value};
 
 use proxmox_router::{RpcEnvironment, RpcEnvironmentType};
 
-use pbs_api_types::Authid;
+use pbs_api_types::{Authid, WireCompression};
 use pbs_datastore::backup_info::BackupDir;
 use pbs_datastore::DataStore;
 use proxmox_rest_server::formatter::*;
@@ -18,6 +18,7 @@ pub struct ReaderEnvironment {
     result_attributes: Value,
     auth_id: Authid,
     pub debug: bool,
+    pub wire_compression: WireCompression,
     pub formatter: &'static dyn OutputFormatter,
     pub worker: Arc<WorkerTask>,
     pub datastore: Arc<DataStore>,
@@ -40,6 +41,7 @@ impl ReaderEnvironment {
             worker,
             datastore,
             debug: false,
+            wire_compression: WireCompression::None,
             formatter: JSON_FORMATTER,
             backup_dir,
             allowed_chunks: Arc::new(RwLock::new(HashSet::new())),
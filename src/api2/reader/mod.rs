@@ -23,11 +23,15 @@ use pbs_api_types::{
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::index::IndexFile;
+use pbs_datastore::lock_tracking::lock_dir_noblock_shared;
 use pbs_datastore::manifest::{archive_type, ArchiveType};
-use pbs_datastore::{DataStore, PROXMOX_BACKUP_READER_PROTOCOL_ID_V1};
+use pbs_datastore::task_io_stats::track_task_io;
+use pbs_datastore::{
+    wire_compression, DataStore, BACKUP_WIRE_COMPRESSION_HEADER,
+    PROXMOX_BACKUP_READER_PROTOCOL_ID_V1,
+};
 use pbs_tools::json::required_string_param;
 use proxmox_rest_server::{H2Service, WorkerTask};
-use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use crate::api2::backup::optional_ns_param;
 use crate::api2::helpers;
@@ -92,7 +96,8 @@ fn upgrade_to_backup_reader_protocol(
             bail!("no permissions on /{}", acl_path.join("/"));
         }
 
-        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))?;
+        let datastore = DataStore::lookup_datastore(&store, Some(Operation::Read))
+            .map_err(helpers::map_api_error)?;
 
         let backup_dir = pbs_api_types::BackupDir::deserialize(&param)?;
 
@@ -106,6 +111,13 @@ fn upgrade_to_backup_reader_protocol(
             bail!("invalid protocol name");
         }
 
+        // only sent by clients requesting compression of downloaded chunks
+        let wire_compression = parts
+            .headers
+            .get(BACKUP_WIRE_COMPRESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(wire_compression::select_codec);
+
         if parts.version >= http::version::Version::HTTP_2 {
             bail!(
                 "unexpected http version '{:?}' (expected version < 2)",
@@ -155,6 +167,8 @@ fn upgrade_to_backup_reader_protocol(
             true,
             move |worker| async move {
                 let _guard = _guard;
+                _guard.set_owner(Some(&worker.upid().to_string()), "reader");
+                datastore.set_operation_owner(&worker.upid().to_string());
 
                 let mut env = ReaderEnvironment::new(
                     env_type,
@@ -165,11 +179,15 @@ fn upgrade_to_backup_reader_protocol(
                 );
 
                 env.debug = debug;
+                env.wire_compression = wire_compression.unwrap_or_default();
 
                 env.log(format!(
                     "starting new backup reader datastore '{}': {:?}",
                     store, path
                 ));
+                if wire_compression.is_some() {
+                    env.log(format!("wire compression: {}", env.wire_compression));
+                }
 
                 let service =
                     H2Service::new(env.clone(), worker.clone(), &READER_API_ROUTER, debug);
@@ -179,11 +197,13 @@ fn upgrade_to_backup_reader_protocol(
                     .map(|_| Err(format_err!("task aborted")));
 
                 let env2 = env.clone();
-                let req_fut = async move {
+                let upid = worker.upid().to_string();
+                let req_fut = track_task_io(upid, async move {
                     let conn = hyper::upgrade::on(Request::from_parts(parts, req_body)).await?;
                     env2.debug("protocol upgrade done");
 
-                    let mut http = hyper::server::conn::Http::new();
+                    let mut http =
+                        hyper::server::conn::Http::new().with_executor(helpers::TaskIoExecutor);
                     http.http2_only(true);
                     // increase window size: todo - find optiomal size
                     let window_size = 32 * 1024 * 1024; // max = (1 << 31) - 2
@@ -194,7 +214,7 @@ fn upgrade_to_backup_reader_protocol(
                     http.serve_connection(conn, service)
                         .map_err(Error::from)
                         .await
-                };
+                });
 
                 futures::select! {
                     req = req_fut.fuse() => req?,
@@ -207,14 +227,20 @@ fn upgrade_to_backup_reader_protocol(
             },
         )?;
 
-        let response = Response::builder()
+        let mut response = Response::builder()
             .status(StatusCode::SWITCHING_PROTOCOLS)
             .header(CONNECTION, HeaderValue::from_static("upgrade"))
             .header(
                 UPGRADE,
                 HeaderValue::from_static(PROXMOX_BACKUP_READER_PROTOCOL_ID_V1!()),
-            )
-            .body(Body::empty())?;
+            );
+        if let Some(wire_compression) = wire_compression {
+            response = response.header(
+                BACKUP_WIRE_COMPRESSION_HEADER,
+                HeaderValue::from_str(&wire_compression.to_string())?,
+            );
+        }
+        let response = response.body(Body::empty())?;
 
         Ok(response)
     }
@@ -324,16 +350,19 @@ fn download_chunk(
             ));
         }
 
-        let (path, _) = env.datastore.chunk_path(&digest);
-        let path2 = path.clone();
-
-        env.debug(format!("download chunk {:?}", path));
+        env.debug(format!("download chunk {digest_str}"));
 
         let data =
-            proxmox_async::runtime::block_in_place(|| std::fs::read(path)).map_err(move |err| {
-                http_err!(BAD_REQUEST, "reading file {:?} failed: {}", path2, err)
+            env.datastore.read_raw_chunk(&digest).await.map_err(|err| {
+                http_err!(BAD_REQUEST, "reading chunk {digest_str} failed: {err}")
             })?;
 
+        let wire_compression = env.wire_compression;
+        let data = proxmox_async::runtime::block_in_place(|| {
+            wire_compression::encode(wire_compression, data)
+        })
+        .map_err(|err| http_err!(INTERNAL_SERVER_ERROR, "encoding chunk failed: {err}"))?;
+
         let body = Body::from(data);
 
         // fixme: set other headers ?
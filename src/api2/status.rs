@@ -1,25 +1,159 @@
 //! Datastote status
 
 use anyhow::Error;
+use futures::FutureExt;
+use http::request::Parts;
+use http::{header, Response, StatusCode};
+use hyper::Body;
 use serde_json::Value;
 
 use proxmox_router::list_subdirs_api_method;
-use proxmox_router::{ApiMethod, Permission, Router, RpcEnvironment, SubdirMap};
-use proxmox_schema::api;
+use proxmox_router::{
+    ApiHandler, ApiMethod, ApiResponseFuture, Permission, Router, RpcEnvironment, SubdirMap,
+};
+use proxmox_schema::{api, ObjectSchema};
 
 use pbs_api_types::{
-    Authid, DataStoreStatusListItem, Operation, RRDMode, RRDTimeFrame, PRIV_DATASTORE_AUDIT,
-    PRIV_DATASTORE_BACKUP,
+    Authid, DataStoreConfig, DataStoreStatusListItem, DataStoreUsageEstimate, DatastoreTuning,
+    Operation, RRDMode, RRDTimeFrame, DEFAULT_ESTIMATION_WINDOW, PRIV_DATASTORE_AUDIT,
+    PRIV_DATASTORE_BACKUP, PRIV_SYS_AUDIT,
 };
 
 use pbs_config::CachedUserInfo;
 use pbs_datastore::DataStore;
 
-use crate::rrd_cache::extract_rrd_data;
-use crate::tools::statistics::linear_regression;
+use crate::rrd_cache::{extract_rrd_data, prometheus_metrics};
+use crate::tools::usage_estimation::estimate_full_date;
 
 use crate::backup::can_access_any_namespace;
 
+/// Usage (between 0.0 and 1.0) history of a datastore.
+struct UsageHistory {
+    start: u64,
+    resolution: u64,
+    usage: Vec<Option<f64>>,
+}
+
+fn extract_usage_history(
+    store: &str,
+    timeframe: RRDTimeFrame,
+) -> Result<Option<UsageHistory>, Error> {
+    let rrd_dir = format!("datastore/{}", store);
+
+    let get_rrd = |what: &str| extract_rrd_data(&rrd_dir, what, timeframe, RRDMode::Average);
+
+    let total_res = get_rrd("total")?;
+    let used_res = get_rrd("used")?;
+    let avail_res = get_rrd("available")?;
+
+    let ((total_entry, used), avail) = match total_res.zip(used_res).zip(avail_res) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
+    let mut history = Vec::new();
+
+    for (idx, used) in used.data.iter().enumerate() {
+        let used = match used {
+            Some(used) => used,
+            _ => {
+                history.push(None);
+                continue;
+            }
+        };
+
+        let total = if let Some(avail) = avail.get(idx) {
+            avail + used
+        } else if let Some(total) = total_entry.get(idx) {
+            total
+        } else {
+            history.push(None);
+            continue;
+        };
+
+        history.push(Some(used / total));
+    }
+
+    Ok(Some(UsageHistory {
+        start: total_entry.start,
+        resolution: total_entry.resolution,
+        usage: history,
+    }))
+}
+
+fn datastore_tuning(config: &DataStoreConfig) -> Result<DatastoreTuning, Error> {
+    Ok(serde_json::from_value(
+        DatastoreTuning::API_SCHEMA
+            .parse_property_string(config.tuning.as_deref().unwrap_or(""))?,
+    )?)
+}
+
+/// The RRD timeframe containing the whole estimation window.
+fn estimation_timeframe(tuning: &DatastoreTuning) -> RRDTimeFrame {
+    match tuning.estimation_window {
+        Some(window) if window > DEFAULT_ESTIMATION_WINDOW => RRDTimeFrame::Year,
+        _ => RRDTimeFrame::Month,
+    }
+}
+
+fn estimate_usage(history: &UsageHistory, tuning: &DatastoreTuning) -> DataStoreUsageEstimate {
+    let window = tuning
+        .estimation_window
+        .unwrap_or(DEFAULT_ESTIMATION_WINDOW);
+    let damping = tuning.estimation_damping.unwrap_or(0);
+
+    // without a configured window all data of the RRD timeframe is used, like it always was
+    let cutoff = match tuning.estimation_window {
+        Some(window) => (proxmox_time::epoch_i64() as u64).saturating_sub(window * 86400),
+        None => 0,
+    };
+
+    let mut usage_list: Vec<f64> = Vec::new();
+    let mut time_list: Vec<u64> = Vec::new();
+
+    for (idx, usage) in history.usage.iter().enumerate() {
+        let time = history.start + (idx as u64) * history.resolution;
+        if let Some(usage) = usage {
+            if time >= cutoff {
+                time_list.push(time);
+                usage_list.push(*usage);
+            }
+        }
+    }
+
+    let estimate = estimate_full_date(&time_list, &usage_list, damping as f64 / 100.0);
+
+    DataStoreUsageEstimate {
+        window,
+        damping,
+        points: usage_list.len() as u64,
+        estimated_full_date: estimate.as_ref().map(|estimate| estimate.full_date),
+        r_squared: estimate.map(|estimate| estimate.r_squared),
+    }
+}
+
+/// Estimate when the datastore `store` is full, using the estimation options of its tuning.
+pub(crate) fn datastore_usage_estimate(store: &str) -> Result<DataStoreUsageEstimate, Error> {
+    let (config, _digest) = pbs_config::datastore::config()?;
+    let store_config: DataStoreConfig = config.lookup("datastore", store)?;
+    let tuning = datastore_tuning(&store_config)?;
+
+    let history = extract_usage_history(store, estimation_timeframe(&tuning))?;
+
+    Ok(match history {
+        Some(history) => estimate_usage(&history, &tuning),
+        None => DataStoreUsageEstimate {
+            window: tuning
+                .estimation_window
+                .unwrap_or(DEFAULT_ESTIMATION_WINDOW),
+            damping: tuning.estimation_damping.unwrap_or(0),
+            points: 0,
+            estimated_full_date: None,
+            r_squared: None,
+        },
+    })
+}
+
 #[api(
     returns: {
         description: "Lists the Status of the Datastores.",
@@ -64,7 +198,7 @@ pub async fn datastore_status(
                 continue;
             }
         };
-        let status = crate::tools::fs::fs_info(datastore.base_path()).await?;
+        let status = crate::tools::fs::datastore_fs_info(&datastore).await?;
 
         let mut entry = DataStoreStatusListItem {
             store: store.clone(),
@@ -79,68 +213,72 @@ pub async fn datastore_status(
             gc_status: Some(datastore.last_gc_status()),
         };
 
-        let rrd_dir = format!("datastore/{}", store);
-
-        let get_rrd =
-            |what: &str| extract_rrd_data(&rrd_dir, what, RRDTimeFrame::Month, RRDMode::Average);
-
-        let total_res = get_rrd("total")?;
-        let used_res = get_rrd("used")?;
-        let avail_res = get_rrd("available")?;
-
-        if let Some(((total_entry, used), avail)) = total_res.zip(used_res).zip(avail_res) {
-            let mut usage_list: Vec<f64> = Vec::new();
-            let mut time_list: Vec<u64> = Vec::new();
-            let mut history = Vec::new();
-
-            for (idx, used) in used.data.iter().enumerate() {
-                let used = match used {
-                    Some(used) => used,
-                    _ => {
-                        history.push(None);
-                        continue;
-                    }
-                };
-
-                let total = if let Some(avail) = avail.get(idx) {
-                    avail + used
-                } else if let Some(total) = total_entry.get(idx) {
-                    total
-                } else {
-                    history.push(None);
-                    continue;
-                };
-
-                let usage = used / total;
-                time_list.push(total_entry.start + (idx as u64) * total_entry.resolution);
-                usage_list.push(usage);
-                history.push(Some(usage));
-            }
+        let tuning = config
+            .lookup("datastore", store)
+            .and_then(|store_config: DataStoreConfig| datastore_tuning(&store_config))
+            .unwrap_or_default();
 
-            entry.history_start = Some(total_entry.start);
-            entry.history_delta = Some(total_entry.resolution);
-            entry.history = Some(history);
-
-            // we skip the calculation for datastores with not enough data
-            if usage_list.len() >= 7 {
-                entry.estimated_full_date = match linear_regression(&time_list, &usage_list) {
-                    Some((a, b)) if b != 0.0 => Some(((1.0 - a) / b).floor() as i64),
-                    Some((_, b)) if b == 0.0 => Some(0), // infinite estimate, set to past for gui to detect
-                    _ => None,
-                };
-            }
+        let history = extract_usage_history(store, RRDTimeFrame::Month)?;
+
+        let estimate = match estimation_timeframe(&tuning) {
+            RRDTimeFrame::Month => history
+                .as_ref()
+                .map(|history| estimate_usage(history, &tuning)),
+            timeframe => extract_usage_history(store, timeframe)?
+                .map(|history| estimate_usage(&history, &tuning)),
+        };
+
+        if let Some(history) = history {
+            entry.history_start = Some(history.start);
+            entry.history_delta = Some(history.resolution);
+            entry.history = Some(history.usage);
         }
 
+        // not set for datastores with not enough data
+        entry.estimated_full_date = estimate.and_then(|estimate| estimate.estimated_full_date);
+
         list.push(entry);
     }
 
     Ok(list)
 }
 
-const SUBDIRS: SubdirMap = &[(
-    "datastore-usage",
-    &Router::new().get(&API_METHOD_DATASTORE_STATUS),
-)];
+pub const API_METHOD_METRICS: ApiMethod = ApiMethod::new(
+    &ApiHandler::AsyncHttp(&metrics),
+    &ObjectSchema::new(
+        "Current host and datastore statistics in the Prometheus text exposition format.",
+        &[],
+    ),
+)
+.access(
+    None,
+    &Permission::Privilege(&["system", "status"], PRIV_SYS_AUDIT, false),
+);
+
+fn metrics(
+    _parts: Parts,
+    _req_body: Body,
+    _param: Value,
+    _info: &ApiMethod,
+    _rpcenv: Box<dyn RpcEnvironment>,
+) -> ApiResponseFuture {
+    async move {
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(prometheus_metrics()))
+            .unwrap())
+    }
+    .boxed()
+}
+
+const SUBDIRS: SubdirMap = &[
+    (
+        "datastore-usage",
+        &Router::new().get(&API_METHOD_DATASTORE_STATUS),
+    ),
+    ("metrics", &Router::new().get(&API_METHOD_METRICS)),
+];
 
 pub const ROUTER: Router = Router::new()
     .get(&list_subdirs_api_method!(SUBDIRS))
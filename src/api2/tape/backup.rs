@@ -6,28 +6,31 @@ use serde_json::Value;
 use proxmox_lang::try_block;
 use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
 use proxmox_schema::api;
+use proxmox_section_config::SectionConfigData;
 use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
 
 use pbs_api_types::{
-    print_ns_and_snapshot, print_store_and_ns, Authid, MediaPoolConfig, Operation,
+    print_ns_and_snapshot, print_store_and_ns, Authid, GroupFilter, MediaPoolConfig, Operation,
     TapeBackupJobConfig, TapeBackupJobSetup, TapeBackupJobStatus, Userid, JOB_ID_SCHEMA,
     PRIV_DATASTORE_READ, PRIV_TAPE_AUDIT, PRIV_TAPE_WRITE, UPID_SCHEMA,
 };
 
 use pbs_config::CachedUserInfo;
-use pbs_datastore::backup_info::{BackupDir, BackupInfo};
+use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
 use pbs_datastore::{DataStore, StoreProgress};
 use proxmox_rest_server::WorkerTask;
 
 use crate::{
     server::{
-        jobstate::{compute_schedule_status, Job, JobState},
+        jobstate::{compute_schedule_status, waiting_for_group, Job, JobState},
         lookup_user_email, TapeBackupJobSummary,
     },
     tape::{
         changer::update_changer_online_status,
-        drive::{lock_tape_device, media_changer, set_tape_device_state, TapeLockError},
-        Inventory, MediaPool, PoolWriter, TAPE_STATUS_DIR,
+        drive::{
+            lock_tape_device, media_changer, set_tape_device_state, DeviceLockGuard, TapeLockError,
+        },
+        Inventory, MediaCatalog, MediaPool, MediaSetCatalog, PoolWriter, TAPE_STATUS_DIR,
     },
 };
 
@@ -38,23 +41,74 @@ pub const ROUTER: Router = Router::new()
     .post(&API_METHOD_BACKUP)
     .match_all("id", &TAPE_BACKUP_JOB_ROUTER);
 
-fn check_backup_permission(
-    auth_id: &Authid,
-    store: &str,
-    pool: &str,
-    drive: &str,
-) -> Result<(), Error> {
+fn check_backup_permission(auth_id: &Authid, setup: &TapeBackupJobSetup) -> Result<(), Error> {
     let user_info = CachedUserInfo::new()?;
 
+    let store = &setup.store;
     user_info.check_privs(auth_id, &["datastore", store], PRIV_DATASTORE_READ, false)?;
 
-    user_info.check_privs(auth_id, &["tape", "drive", drive], PRIV_TAPE_WRITE, false)?;
+    let extra_drives = setup.extra_drives.iter().flatten();
+    for drive in std::iter::once(&setup.drive).chain(extra_drives) {
+        user_info.check_privs(auth_id, &["tape", "drive", drive], PRIV_TAPE_WRITE, false)?;
+    }
 
+    let pool = &setup.pool;
     user_info.check_privs(auth_id, &["tape", "pool", pool], PRIV_TAPE_WRITE, false)?;
 
     Ok(())
 }
 
+/// Returns the drives used by a tape backup job, the `drive` followed by the `extra-drives`.
+///
+/// Additional drives must be connected to the same changer as the main drive, because all
+/// drives of a job allocate their media from the same pool and changer.
+pub fn tape_backup_drives(
+    drive_config: &SectionConfigData,
+    setup: &TapeBackupJobSetup,
+) -> Result<Vec<String>, Error> {
+    let mut drives = vec![setup.drive.clone()];
+
+    let extra_drives = match setup.extra_drives.as_deref() {
+        Some(extra_drives) if !extra_drives.is_empty() => extra_drives,
+        _ => return Ok(drives),
+    };
+
+    let changer_name = match media_changer(drive_config, &setup.drive)? {
+        Some((_, changer_name)) => changer_name,
+        None => bail!(
+            "drive '{}' has no associated changer, cannot use extra drives",
+            setup.drive
+        ),
+    };
+
+    for drive in extra_drives {
+        if drives.contains(drive) {
+            bail!("drive '{}' is used more than once", drive);
+        }
+        match media_changer(drive_config, drive)? {
+            Some((_, name)) if name == changer_name => drives.push(drive.clone()),
+            _ => bail!(
+                "drive '{}' is not connected to changer '{}'",
+                drive,
+                changer_name
+            ),
+        }
+    }
+
+    Ok(drives)
+}
+
+/// Lock all drives of a tape backup job, fails if one of them is in use.
+fn lock_tape_devices(
+    drive_config: &SectionConfigData,
+    drives: &[String],
+) -> Result<Vec<DeviceLockGuard>, TapeLockError> {
+    drives
+        .iter()
+        .map(|drive| lock_tape_device(drive_config, drive))
+        .collect()
+}
+
 #[api(
     returns: {
         description: "List configured thape backup jobs and their status",
@@ -98,7 +152,8 @@ pub fn list_tape_backup_jobs(
         let last_state = JobState::load("tape-backup-job", &job.id)
             .map_err(|err| format_err!("could not open statefile for {}: {}", &job.id, err))?;
 
-        let status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        let mut status = compute_schedule_status(&last_state, job.schedule.as_deref())?;
+        status.waiting_for_group = waiting_for_group("tape-backup-job", &job.id);
 
         let next_run = status.next_run.unwrap_or(current_time);
 
@@ -150,16 +205,19 @@ pub fn do_tape_backup_job(
 
     let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
 
+    check_backup_namespace(&datastore, &setup)?;
+
     let (config, _digest) = pbs_config::media_pool::config()?;
     let pool_config: MediaPoolConfig = config.lookup("pool", &setup.pool)?;
 
     let (drive_config, _digest) = pbs_config::drive::config()?;
+    let drives = tape_backup_drives(&drive_config, &setup)?;
 
     // for scheduled jobs we acquire the lock later in the worker
     let drive_lock = if schedule.is_some() {
         None
     } else {
-        Some(lock_tape_device(&drive_config, &setup.drive)?)
+        Some(lock_tape_devices(&drive_config, &drives)?)
     };
 
     let notify_user = setup
@@ -174,6 +232,8 @@ pub fn do_tape_backup_job(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
+            datastore.set_operation_owner(&worker.upid().to_string());
             job.start(&worker.upid().to_string())?;
             let mut drive_lock = drive_lock;
 
@@ -184,7 +244,7 @@ pub fn do_tape_backup_job(
                     task_log!(worker, "waiting for drive lock...");
                     loop {
                         worker.check_abort()?;
-                        match lock_tape_device(&drive_config, &setup.drive) {
+                        match lock_tape_devices(&drive_config, &drives) {
                             Ok(lock) => {
                                 drive_lock = Some(lock);
                                 break;
@@ -194,7 +254,9 @@ pub fn do_tape_backup_job(
                         }
                     }
                 }
-                set_tape_device_state(&setup.drive, &worker.upid().to_string())?;
+                for drive in drives.iter() {
+                    set_tape_device_state(drive, &worker.upid().to_string())?;
+                }
 
                 task_log!(worker, "Starting tape backup job '{}'", job_id);
                 if let Some(event_str) = schedule {
@@ -206,6 +268,7 @@ pub fn do_tape_backup_job(
                     datastore,
                     &pool_config,
                     &setup,
+                    &drives,
                     email.clone(),
                     &mut summary,
                     false,
@@ -214,24 +277,25 @@ pub fn do_tape_backup_job(
 
             let status = worker.create_state(&job_result);
 
-            if let Some(email) = email {
-                if let Err(err) = crate::server::send_tape_backup_status(
-                    &email,
-                    Some(job.jobname()),
-                    &setup,
-                    &job_result,
-                    summary,
-                ) {
-                    eprintln!("send tape backup notification failed: {}", err);
-                }
+            if let Err(err) = crate::server::send_tape_backup_status(
+                email.as_deref(),
+                Some(job.jobname()),
+                &setup,
+                &job_result,
+                crate::server::task_severity(&status),
+                summary,
+            ) {
+                eprintln!("send tape backup notification failed: {}", err);
             }
 
             if let Err(err) = job.finish(status) {
                 eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
             }
 
-            if let Err(err) = set_tape_device_state(&setup.drive, "") {
-                eprintln!("could not unset drive state for {}: {}", setup.drive, err);
+            for drive in drives.iter() {
+                if let Err(err) = set_tape_device_state(drive, "") {
+                    eprintln!("could not unset drive state for {}: {}", drive, err);
+                }
             }
 
             job_result
@@ -252,7 +316,8 @@ pub fn do_tape_backup_job(
     access: {
         // Note: parameters are from job config, so we need to test inside function body
         description: "The user needs Tape.Write privilege on /tape/pool/{pool} \
-                      and /tape/drive/{drive}, Datastore.Read privilege on /datastore/{store}.",
+                      and on /tape/drive/{drive} for the drive and all extra drives, \
+                      Datastore.Read privilege on /datastore/{store}.",
         permission: &Permission::Anybody,
     },
 )]
@@ -263,12 +328,7 @@ pub fn run_tape_backup_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Resul
     let (config, _digest) = pbs_config::tape_job::config()?;
     let backup_job: TapeBackupJobConfig = config.lookup("backup", &id)?;
 
-    check_backup_permission(
-        &auth_id,
-        &backup_job.setup.store,
-        &backup_job.setup.pool,
-        &backup_job.setup.drive,
-    )?;
+    check_backup_permission(&auth_id, &backup_job.setup)?;
 
     let job = Job::new("tape-backup-job", &id)?;
 
@@ -300,7 +360,8 @@ pub fn run_tape_backup_job(id: String, rpcenv: &mut dyn RpcEnvironment) -> Resul
     access: {
         // Note: parameters are no uri parameter, so we need to test inside function body
         description: "The user needs Tape.Write privilege on /tape/pool/{pool} \
-                      and /tape/drive/{drive}, Datastore.Read privilege on /datastore/{store}.",
+                      and on /tape/drive/{drive} for the drive and all extra drives, \
+                      Datastore.Read privilege on /datastore/{store}.",
         permission: &Permission::Anybody,
     },
 )]
@@ -312,17 +373,20 @@ pub fn backup(
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
 
-    check_backup_permission(&auth_id, &setup.store, &setup.pool, &setup.drive)?;
+    check_backup_permission(&auth_id, &setup)?;
 
     let datastore = DataStore::lookup_datastore(&setup.store, Some(Operation::Read))?;
 
+    check_backup_namespace(&datastore, &setup)?;
+
     let (config, _digest) = pbs_config::media_pool::config()?;
     let pool_config: MediaPoolConfig = config.lookup("pool", &setup.pool)?;
 
     let (drive_config, _digest) = pbs_config::drive::config()?;
+    let drives = tape_backup_drives(&drive_config, &setup)?;
 
     // early check/lock before starting worker
-    let drive_lock = lock_tape_device(&drive_config, &setup.drive)?;
+    let drive_lock = lock_tape_devices(&drive_config, &drives)?;
 
     let to_stdout = rpcenv.env_type() == RpcEnvironmentType::CLI;
 
@@ -340,8 +404,12 @@ pub fn backup(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
-            let _drive_lock = drive_lock; // keep lock guard
-            set_tape_device_state(&setup.drive, &worker.upid().to_string())?;
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
+            datastore.set_operation_owner(&worker.upid().to_string());
+            let _drive_lock = drive_lock; // keep lock guards
+            for drive in drives.iter() {
+                set_tape_device_state(drive, &worker.upid().to_string())?;
+            }
 
             let mut summary = Default::default();
             let job_result = backup_worker(
@@ -349,25 +417,27 @@ pub fn backup(
                 datastore,
                 &pool_config,
                 &setup,
+                &drives,
                 email.clone(),
                 &mut summary,
                 force_media_set,
             );
 
-            if let Some(email) = email {
-                if let Err(err) = crate::server::send_tape_backup_status(
-                    &email,
-                    None,
-                    &setup,
-                    &job_result,
-                    summary,
-                ) {
-                    eprintln!("send tape backup notification failed: {}", err);
-                }
+            if let Err(err) = crate::server::send_tape_backup_status(
+                email.as_deref(),
+                None,
+                &setup,
+                &job_result,
+                crate::server::task_severity(&worker.create_state(&job_result)),
+                summary,
+            ) {
+                eprintln!("send tape backup notification failed: {}", err);
             }
 
             // ignore errors
-            let _ = set_tape_device_state(&setup.drive, "");
+            for drive in drives.iter() {
+                let _ = set_tape_device_state(drive, "");
+            }
             job_result
         },
     )?;
@@ -375,6 +445,60 @@ pub fn backup(
     Ok(upid_str.into())
 }
 
+/// Check that the namespace selected by a tape backup exists and that `max-depth` is valid for
+/// it, so that a bad selection fails before any drive is locked or tape is loaded.
+fn check_backup_namespace(datastore: &DataStore, setup: &TapeBackupJobSetup) -> Result<(), Error> {
+    let ns = setup.ns.clone().unwrap_or_default();
+
+    if let Some(max_depth) = setup.max_depth {
+        ns.check_max_depth(max_depth)?;
+    }
+
+    if !datastore.namespace_exists(&ns) {
+        bail!(
+            "namespace '{}' does not exist on datastore '{}'",
+            ns,
+            setup.store
+        );
+    }
+
+    Ok(())
+}
+
+/// Apply the group filters of a tape backup job.
+///
+/// Filters only match on the backup type and ID, so groups with the same name in different
+/// namespaces below the job's `ns` are all included or excluded together.
+fn filter_group_list<G: AsRef<pbs_api_types::BackupGroup>>(
+    group_list: Vec<G>,
+    group_filter: Option<&[GroupFilter]>,
+) -> Vec<G> {
+    match group_filter {
+        Some(filter) => group_list
+            .into_iter()
+            .filter(|group| group.as_ref().apply_filters(filter))
+            .collect(),
+        None => group_list,
+    }
+}
+
+/// Count the groups matched by each of the filters, regardless of the other filters.
+fn group_filter_matches<'a, G: AsRef<pbs_api_types::BackupGroup>>(
+    group_list: &[G],
+    group_filter: &'a [GroupFilter],
+) -> Vec<(&'a GroupFilter, usize)> {
+    group_filter
+        .iter()
+        .map(|filter| {
+            let count = group_list
+                .iter()
+                .filter(|group| group.as_ref().matches(filter))
+                .count();
+            (filter, count)
+        })
+        .collect()
+}
+
 enum SnapshotBackupResult {
     Success,
     Error,
@@ -386,6 +510,7 @@ fn backup_worker(
     datastore: Arc<DataStore>,
     pool_config: &MediaPoolConfig,
     setup: &TapeBackupJobSetup,
+    drives: &[String],
     email: Option<String>,
     summary: &mut TapeBackupJobSummary,
     force_media_set: bool,
@@ -398,11 +523,8 @@ fn backup_worker(
     let root_namespace = setup.ns.clone().unwrap_or_default();
     let ns_magic = !root_namespace.is_root() || setup.max_depth != Some(0);
 
-    let pool = MediaPool::with_config(TAPE_STATUS_DIR, pool_config, changer_name, false)?;
-
-    let mut pool_writer =
-        PoolWriter::new(pool, &setup.drive, worker, email, force_media_set, ns_magic)?;
-
+    // enumerate the groups before starting a write session, so that a vanished namespace
+    // fails the job before any media set is touched
     let mut group_list = Vec::new();
     let namespaces = datastore.recursive_iter_backup_ns_ok(root_namespace, setup.max_depth)?;
     for ns in namespaces {
@@ -413,13 +535,13 @@ fn backup_worker(
 
     let group_count_full = group_list.len();
 
-    let group_list = match &setup.group_filter {
-        Some(f) => group_list
-            .into_iter()
-            .filter(|group| group.group().apply_filters(f))
-            .collect(),
-        None => group_list,
-    };
+    if let Some(group_filter) = setup.group_filter.as_deref() {
+        for (filter, count) in group_filter_matches(&group_list, group_filter) {
+            task_log!(worker, "group filter '{filter}' matches {count} groups");
+        }
+    }
+
+    let group_list = filter_group_list(group_list, setup.group_filter.as_deref());
 
     task_log!(
         worker,
@@ -428,6 +550,184 @@ fn backup_worker(
         group_count_full
     );
 
+    if drives.len() == 1 {
+        let pool = MediaPool::with_config(TAPE_STATUS_DIR, pool_config, changer_name, false)?;
+        let pool_writer =
+            PoolWriter::new(pool, &setup.drive, worker, email, force_media_set, ns_magic)?;
+        backup_to_drive(
+            worker,
+            &datastore,
+            pool_writer,
+            setup,
+            group_list,
+            None,
+            summary,
+        )?;
+    } else {
+        // every drive writes its own media set, as a media set can only be written sequentially
+        let mut pools = MediaPool::with_config_parallel(
+            TAPE_STATUS_DIR,
+            pool_config,
+            changer_name,
+            drives.len(),
+        )?;
+        let current_time = proxmox_time::epoch_i64();
+        for (drive, pool) in drives.iter().zip(pools.iter_mut()) {
+            if let Some(reason) = pool.start_write_session(current_time, force_media_set)? {
+                task_log!(
+                    worker,
+                    "drive '{}': starting new media set - reason: {}",
+                    drive,
+                    reason
+                );
+            }
+        }
+        MediaPool::store_parallel_media_sets(TAPE_STATUS_DIR, &pools)?;
+
+        // groups only move to another drive if the number of drives changes, so snapshots
+        // already written to the media set of any drive are skipped
+        let written = load_media_set_catalogs(&pools)?;
+
+        let work_lists = split_group_list(group_list, drives.len(), |group| {
+            format!("{}/{}", group.backup_ns(), group.group())
+        });
+        for (drive, group_list) in drives.iter().zip(work_lists.iter()) {
+            task_log!(
+                worker,
+                "drive '{}': backup of {} groups",
+                drive,
+                group_list.len()
+            );
+        }
+
+        let work = pools.into_iter().zip(work_lists).collect();
+        let results = run_on_drives(drives, work, |drive, (pool, group_list), summary| {
+            let pool_writer =
+                PoolWriter::with_write_session(pool, drive, worker, email.clone(), ns_magic)?;
+            backup_to_drive(
+                worker,
+                &datastore,
+                pool_writer,
+                setup,
+                group_list,
+                Some(&written),
+                summary,
+            )
+        });
+
+        // a failing drive does not stop the others, so collect what all drives did
+        let mut failed_drives = Vec::new();
+        for (drive, (result, drive_summary)) in drives.iter().zip(results) {
+            summary.snapshot_list.extend(drive_summary.snapshot_list);
+            if let Some(tapes) = drive_summary.used_tapes {
+                summary
+                    .used_tapes
+                    .get_or_insert_with(Vec::new)
+                    .extend(tapes);
+            }
+            match result {
+                Ok(()) => task_log!(worker, "drive '{}': backup finished", drive),
+                Err(err) => {
+                    task_warn!(worker, "drive '{}': backup failed - {}", drive, err);
+                    failed_drives.push(drive.as_str());
+                }
+            }
+        }
+
+        if !failed_drives.is_empty() {
+            bail!(
+                "Tape backup failed on drive(s) {}. Please check the task log.",
+                failed_drives.join(", ")
+            );
+        }
+    }
+
+    summary.duration = start.elapsed();
+
+    Ok(())
+}
+
+/// Load the catalogs of the current media sets of all pools.
+fn load_media_set_catalogs(pools: &[MediaPool]) -> Result<MediaSetCatalog, Error> {
+    let mut catalog = MediaSetCatalog::new();
+    for pool in pools {
+        for media_uuid in pool.current_media_list()? {
+            let media_info = pool.lookup_media(media_uuid)?;
+            let media_catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_info.id(), false, false)?;
+            catalog.append_catalog(media_catalog)?;
+        }
+    }
+    Ok(catalog)
+}
+
+/// Split the groups of a job into one work list per drive.
+///
+/// The drive of a group is chosen by a hash of its `key`, so that a group is written by the same
+/// drive, and thus to the same media set, in every run as long as the number of drives does not
+/// change. All snapshots of a group end up on the same media set.
+fn split_group_list<G>(
+    group_list: Vec<G>,
+    count: usize,
+    key: impl Fn(&G) -> String,
+) -> Vec<Vec<G>> {
+    let mut lists: Vec<Vec<G>> = (0..count).map(|_| Vec::new()).collect();
+    for group in group_list {
+        let hash = openssl::sha::sha256(key(&group).as_bytes());
+        let hash = u64::from_le_bytes(hash[..8].try_into().unwrap());
+        lists[(hash % count as u64) as usize].push(group);
+    }
+    lists
+}
+
+/// Run `func` with the work of each drive, all drives in parallel.
+///
+/// A failing drive does not stop the others. Returns the result and summary of every drive, in
+/// the order of `drives`.
+fn run_on_drives<W, S, F>(drives: &[String], work: Vec<W>, func: F) -> Vec<(Result<(), Error>, S)>
+where
+    W: Send,
+    S: Default + Send,
+    F: Fn(&str, W, &mut S) -> Result<(), Error> + Sync,
+{
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = drives
+            .iter()
+            .zip(work)
+            .map(|(drive, work)| {
+                let func = &func;
+                scope.spawn(move || {
+                    let mut summary = S::default();
+                    let result = func(drive, work, &mut summary);
+                    (result, summary)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| match handle.join() {
+                Ok(result) => result,
+                Err(_) => (
+                    Err(format_err!("writer thread panicked")),
+                    Default::default(),
+                ),
+            })
+            .collect()
+    })
+}
+
+/// Write the snapshots of the given groups to a single drive.
+///
+/// Snapshots already on the media set of the writer, or in the `written` catalog, are skipped.
+fn backup_to_drive(
+    worker: &WorkerTask,
+    datastore: &Arc<DataStore>,
+    mut pool_writer: PoolWriter,
+    setup: &TapeBackupJobSetup,
+    group_list: Vec<BackupGroup>,
+    written: Option<&MediaSetCatalog>,
+    summary: &mut TapeBackupJobSummary,
+) -> Result<(), Error> {
     let mut progress = StoreProgress::new(group_list.len() as u64);
 
     let latest_only = setup.latest_only.unwrap_or(false);
@@ -445,6 +745,15 @@ fn backup_worker(
 
     let mut need_catalog = false; // avoid writing catalog for empty jobs
 
+    let already_written = |pool_writer: &PoolWriter, snapshot: &BackupDir| {
+        let ns = snapshot.backup_ns();
+        let dir: &pbs_api_types::BackupDir = snapshot.as_ref();
+        pool_writer.contains_snapshot(datastore_name, ns, dir)
+            || written.map_or(false, |catalog| {
+                catalog.contains_snapshot(datastore_name, ns, dir)
+            })
+    };
+
     for (group_number, group) in group_list.into_iter().enumerate() {
         progress.done_groups = group_number as u64;
         progress.done_snapshots = 0;
@@ -475,11 +784,7 @@ fn backup_worker(
             if let Some(info) = snapshot_list.pop() {
                 let rel_path =
                     print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
-                if pool_writer.contains_snapshot(
-                    datastore_name,
-                    info.backup_dir.backup_ns(),
-                    info.backup_dir.as_ref(),
-                ) {
+                if already_written(&pool_writer, &info.backup_dir) {
                     task_log!(worker, "skip snapshot {}", rel_path);
                     continue;
                 }
@@ -501,11 +806,7 @@ fn backup_worker(
                 let rel_path =
                     print_ns_and_snapshot(info.backup_dir.backup_ns(), info.backup_dir.as_ref());
 
-                if pool_writer.contains_snapshot(
-                    datastore_name,
-                    info.backup_dir.backup_ns(),
-                    info.backup_dir.as_ref(),
-                ) {
+                if already_written(&pool_writer, &info.backup_dir) {
                     task_log!(worker, "skip snapshot {}", rel_path);
                     continue;
                 }
@@ -563,8 +864,6 @@ fn backup_worker(
         }
     };
 
-    summary.duration = start.elapsed();
-
     Ok(())
 }
 
@@ -679,3 +978,159 @@ fn backup_snapshot(
 
     Ok(SnapshotBackupResult::Success)
 }
+
+#[cfg(test)]
+mod test {
+    use pbs_api_types::{BackupGroup, BackupNamespace};
+
+    use super::*;
+
+    struct NsGroup(BackupNamespace, BackupGroup);
+
+    impl AsRef<BackupGroup> for NsGroup {
+        fn as_ref(&self) -> &BackupGroup {
+            &self.1
+        }
+    }
+
+    fn group_list() -> Vec<NsGroup> {
+        [
+            ("", "vm/100"),
+            ("", "ct/200"),
+            ("a", "vm/100"),
+            ("a", "host/backup"),
+            ("a/b", "vm/101"),
+            ("a/b", "ct/200"),
+        ]
+        .into_iter()
+        .map(|(ns, group)| NsGroup(ns.parse().unwrap(), group.parse().unwrap()))
+        .collect()
+    }
+
+    fn filter(filters: &[&str]) -> Vec<(String, String)> {
+        let filters: Vec<GroupFilter> = filters.iter().map(|f| f.parse().unwrap()).collect();
+        filter_group_list(group_list(), Some(&filters))
+            .into_iter()
+            .map(|NsGroup(ns, group)| (ns.to_string(), group.to_string()))
+            .collect()
+    }
+
+    fn expect(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(ns, group)| (ns.to_string(), group.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_group_filter_without_filters() {
+        assert_eq!(filter_group_list(group_list(), None).len(), 6);
+        assert_eq!(filter_group_list(group_list(), Some(&[])).len(), 6);
+    }
+
+    #[test]
+    fn test_group_filter_across_namespaces() {
+        assert_eq!(
+            filter(&["group:vm/100"]),
+            expect(&[("", "vm/100"), ("a", "vm/100")]),
+        );
+        assert_eq!(
+            filter(&["type:ct"]),
+            expect(&[("", "ct/200"), ("a/b", "ct/200")]),
+        );
+        assert_eq!(
+            filter(&["regex:^vm/10[01]$", "type:host"]),
+            expect(&[
+                ("", "vm/100"),
+                ("a", "vm/100"),
+                ("a", "host/backup"),
+                ("a/b", "vm/101")
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_group_exclude_filter_across_namespaces() {
+        assert_eq!(
+            filter(&["exclude:group:ct/200"]),
+            expect(&[
+                ("", "vm/100"),
+                ("a", "vm/100"),
+                ("a", "host/backup"),
+                ("a/b", "vm/101")
+            ]),
+        );
+        assert_eq!(
+            filter(&["type:vm", "exclude:group:vm/100"]),
+            expect(&[("a/b", "vm/101")]),
+        );
+    }
+
+    #[test]
+    fn test_group_filter_match_counts() {
+        let filters: Vec<GroupFilter> = ["type:vm", "group:host/backup", "exclude:type:ct"]
+            .iter()
+            .map(|f| f.parse().unwrap())
+            .collect();
+        let counts: Vec<usize> = group_filter_matches(&group_list(), &filters)
+            .into_iter()
+            .map(|(_, count)| count)
+            .collect();
+        assert_eq!(counts, [3, 1, 2]);
+    }
+
+    #[test]
+    fn test_split_group_list() {
+        let groups: Vec<String> = (0..100).map(|n| format!("vm/{n}")).collect();
+        let lists = split_group_list(groups.clone(), 3, String::clone);
+        assert_eq!(lists.iter().map(Vec::len).sum::<usize>(), 100);
+        assert!(lists.iter().all(|list| !list.is_empty()));
+
+        // the drive of a group does not depend on the other groups of the job
+        let mut reordered = groups.clone();
+        reordered.reverse();
+        reordered.push("ct/1000".to_string());
+        let other_lists = split_group_list(reordered, 3, String::clone);
+        for (list, other_list) in lists.iter().zip(other_lists.iter()) {
+            assert!(list.iter().all(|group| other_list.contains(group)));
+        }
+
+        assert_eq!(split_group_list(groups, 1, String::clone).len(), 1);
+    }
+
+    #[test]
+    fn test_run_on_drives_failure_isolation() {
+        let drives: Vec<String> = ["a", "b", "c"].iter().map(|d| d.to_string()).collect();
+        let work = vec![vec![1, 2], vec![3, 4], vec![5, 6]];
+
+        let results = run_on_drives(&drives, work, |drive, work, done: &mut Vec<u32>| {
+            for item in work {
+                if drive == "b" && item == 4 {
+                    bail!("write failed");
+                }
+                // give the failing drive a chance to finish first
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                done.push(item);
+            }
+            Ok(())
+        });
+
+        let results: Vec<(bool, Vec<u32>)> = results
+            .into_iter()
+            .map(|(result, done)| (result.is_ok(), done))
+            .collect();
+        assert_eq!(
+            results,
+            [(true, vec![1, 2]), (false, vec![3]), (true, vec![5, 6])]
+        );
+
+        // a panicking drive is reported as failed as well
+        let results = run_on_drives(&drives, vec![(); 3], |drive, _, _: &mut ()| {
+            if drive == "c" {
+                panic!("drive c panicked");
+            }
+            Ok(())
+        });
+        let failed: Vec<bool> = results.iter().map(|(result, _)| result.is_err()).collect();
+        assert_eq!(failed, [false, false, true]);
+    }
+}
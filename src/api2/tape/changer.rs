@@ -139,6 +139,8 @@ pub async fn get_status(name: String, cache: bool) -> Result<Vec<MtxStatusEntry>
     },
 )]
 /// Transfers media from one slot to another
+///
+/// The source slot must contain media and the destination slot must be empty.
 pub async fn transfer(name: String, from: u64, to: u64) -> Result<(), Error> {
     let (config, _digest) = pbs_config::drive::config()?;
 
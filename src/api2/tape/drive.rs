@@ -15,9 +15,10 @@ use proxmox_sys::{task_log, task_warn};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, DriveListEntry, LabelUuidMap, Lp17VolumeStatistics, LtoDriveAndMediaStatus,
-    LtoTapeDrive, MamAttribute, MediaIdFlat, TapeDensity, CHANGER_NAME_SCHEMA, DRIVE_NAME_SCHEMA,
-    MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA, UPID_SCHEMA,
+    Authid, DeviceActivity, DriveListEntry, LabelUuidMap, Lp17VolumeStatistics,
+    LtoDriveAndMediaStatus, LtoTapeDrive, MamAttribute, MediaIdFlat, TapeDensity,
+    CHANGER_NAME_SCHEMA, DRIVE_NAME_SCHEMA, MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA,
+    UPID_SCHEMA,
 };
 
 use pbs_api_types::{PRIV_TAPE_AUDIT, PRIV_TAPE_READ, PRIV_TAPE_WRITE};
@@ -35,8 +36,9 @@ use crate::{
     tape::{
         changer::update_changer_online_status,
         drive::{
-            get_tape_device_state, lock_tape_device, media_changer, open_drive,
-            required_media_changer, set_tape_device_state, LtoTapeHandle, TapeDriver,
+            get_tape_device_lock_info, get_tape_device_state, lock_tape_device, media_changer,
+            open_drive, required_media_changer, set_tape_device_activity, set_tape_device_state,
+            LtoTapeHandle, TapeDriver,
         },
         encryption_keys::insert_key,
         file_formats::{MediaLabel, MediaSetLabel},
@@ -313,7 +315,7 @@ pub fn format_media(
                 let file = open_lto_tape_device(&drive_config.path)?;
                 let mut handle = LtoTapeHandle::new(file)?;
                 if let Ok(status) = handle.get_drive_and_media_status() {
-                    if status.density >= TapeDensity::LTO9 {
+                    if matches!(status.density, Some(density) if density >= TapeDensity::LTO9) {
                         task_log!(worker, "Slow formatting LTO9+ media.");
                         task_log!(
                             worker,
@@ -751,7 +753,9 @@ pub fn clean_drive(drive: String, rpcenv: &mut dyn RpcEnvironment) -> Result<Val
 
             task_log!(worker, "Starting drive clean");
 
+            set_tape_device_activity(&drive, DeviceActivity::Cleaning)?;
             changer.clean_drive()?;
+            set_tape_device_activity(&drive, DeviceActivity::NoActivity)?;
 
             if let Ok(drive_config) = config.lookup::<LtoTapeDrive>("lto", &drive) {
                 // Note: clean_drive unloads the cleaning media, so we cannot use drive_config.open
@@ -1242,7 +1246,39 @@ pub async fn volume_statistics(drive: String) -> Result<Lp17VolumeStatistics, Er
     },
 )]
 /// Get drive/media status
+///
+/// While the drive is locked by a task, only the lock owner and the activity recorded by
+/// it are returned, without sending any commands to the drive.
 pub async fn status(drive: String) -> Result<LtoDriveAndMediaStatus, Error> {
+    let (config, _digest) = pbs_config::drive::config()?;
+    let drive_config: LtoTapeDrive = config.lookup("lto", &drive)?;
+
+    if let Some((owner, duration, activity)) = get_tape_device_lock_info(&config, &drive)? {
+        let info = lookup_device_identification(&lto_tape_device_list(), &drive_config.path);
+        return Ok(LtoDriveAndMediaStatus {
+            vendor: info.vendor.unwrap_or_default(),
+            product: info.model.unwrap_or_default(),
+            revision: String::new(),
+            blocksize: None,
+            compression: None,
+            buffer_mode: None,
+            density: None,
+            drive_activity: activity,
+            lock_owner: Some(owner),
+            lock_duration: Some(duration),
+            write_protect: None,
+            alert_flags: None,
+            file_number: None,
+            block_number: None,
+            manufactured: None,
+            bytes_read: None,
+            bytes_written: None,
+            volume_mounts: None,
+            medium_passes: None,
+            medium_wearout: None,
+        });
+    }
+
     run_drive_blocking_task(
         drive.clone(),
         "reading drive status".to_string(),
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
 
@@ -7,11 +8,14 @@ use proxmox_schema::{api, param_bail};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Authid, MediaContentEntry, MediaContentListFilter, MediaListEntry, MediaPoolConfig,
-    MediaSetListEntry, MediaStatus, CHANGER_NAME_SCHEMA, MEDIA_LABEL_SCHEMA,
-    MEDIA_POOL_NAME_SCHEMA, MEDIA_UUID_SCHEMA, PRIV_TAPE_AUDIT, VAULT_NAME_SCHEMA,
+    Authid, BackupNamespace, MediaContentEntry, MediaContentListFilter, MediaForecastEntry,
+    MediaForecastState, MediaListEntry, MediaPoolConfig, MediaPoolForecast, MediaSetContentEntry,
+    MediaSetListEntry, MediaStatus, Operation, BACKUP_GROUP_SCHEMA, CHANGER_NAME_SCHEMA,
+    DATASTORE_SCHEMA, MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA, MEDIA_SET_UUID_SCHEMA,
+    MEDIA_UUID_SCHEMA, PRIV_DATASTORE_AUDIT, PRIV_TAPE_AUDIT, VAULT_NAME_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
+use pbs_datastore::DataStore;
 
 use crate::tape::{
     changer::update_online_status, media_catalog_snapshot_list, Inventory, MediaCatalog, MediaPool,
@@ -71,12 +75,22 @@ pub async fn list_media_sets(
                     .generate_media_set_name(&media_set_uuid, config.template.clone())
                     .unwrap_or_else(|_| media_set_uuid.to_string());
 
+                let encryption_key_fingerprint = label
+                    .encryption_key_fingerprint
+                    .as_ref()
+                    .map(|fp| fp.signature());
+                let key_wrapped = encryption_key_fingerprint
+                    .as_ref()
+                    .map(|_| label.wrapped_key.is_some());
+
                 media_sets.insert(media_set_uuid.clone());
                 list.push(MediaSetListEntry {
                     media_set_name,
                     media_set_uuid,
                     media_set_ctime,
                     pool: pool_name.to_string(),
+                    encryption_key_fingerprint,
+                    key_wrapped,
                 });
             }
         }
@@ -285,6 +299,138 @@ pub async fn list_media(
     Ok(list)
 }
 
+#[api(
+    input: {
+        properties: {
+            pool: {
+                schema: MEDIA_POOL_NAME_SCHEMA,
+            },
+        },
+    },
+    returns: {
+        type: MediaPoolForecast,
+    },
+    access: {
+        permission: &Permission::Privilege(&["tape", "pool", "{pool}"], PRIV_TAPE_AUDIT, false),
+    },
+)]
+/// Simulate the retention policy of a media pool.
+///
+/// Shows for each media when it becomes writable again, using the same rules a
+/// backup job would apply when allocating media.
+pub fn media_pool_forecast(pool: String) -> Result<MediaPoolForecast, Error> {
+    let (config, _digest) = pbs_config::media_pool::config()?;
+    let config: MediaPoolConfig = config.lookup("pool", &pool)?;
+
+    let changer_name = None; // assume standalone drive
+    let mut media_pool = MediaPool::with_config(TAPE_STATUS_DIR, &config, changer_name, true)?;
+
+    let current_time = proxmox_time::epoch_i64();
+
+    // Call start_write_session, so that we show the same status a
+    // backup job would see.
+    media_pool.force_media_availability();
+    media_pool.start_write_session(current_time, false)?;
+
+    let mut forecast = MediaPoolForecast {
+        pool: pool.clone(),
+        writable_now: 0,
+        writable_in_30_days: 0,
+        writable_in_90_days: 0,
+        never: 0,
+        unknown: 0,
+        media: Vec::new(),
+    };
+
+    for media in media_pool.list_media() {
+        let mut note = None;
+        let mut expire_time = None;
+
+        let state = match media.media_set_label() {
+            None => MediaForecastState::Writable,
+            Some(set) if set.unassigned() => MediaForecastState::Writable,
+            Some(set) if set.pool != pool => {
+                note = Some(format!("media belongs to pool '{}'", set.pool));
+                MediaForecastState::Unknown
+            }
+            Some(_) => match media.status() {
+                MediaStatus::Unknown => {
+                    note = Some("media status is unknown".to_string());
+                    MediaForecastState::Unknown
+                }
+                MediaStatus::Damaged | MediaStatus::Retired => MediaForecastState::Unusable,
+                MediaStatus::Writable => MediaForecastState::Writable,
+                MediaStatus::Full => match media_pool.media_expire_time(&media) {
+                    i64::MAX => MediaForecastState::Keep,
+                    time => {
+                        expire_time = Some(time);
+                        if time <= current_time {
+                            MediaForecastState::Writable
+                        } else {
+                            MediaForecastState::Expires
+                        }
+                    }
+                },
+            },
+        };
+
+        match state {
+            MediaForecastState::Writable => {
+                forecast.writable_now += 1;
+                forecast.writable_in_30_days += 1;
+                forecast.writable_in_90_days += 1;
+            }
+            MediaForecastState::Expires => {
+                let time = expire_time.unwrap_or(i64::MAX);
+                if time <= current_time + 30 * 86400 {
+                    forecast.writable_in_30_days += 1;
+                }
+                if time <= current_time + 90 * 86400 {
+                    forecast.writable_in_90_days += 1;
+                }
+            }
+            MediaForecastState::Keep | MediaForecastState::Unusable => forecast.never += 1,
+            MediaForecastState::Unknown => forecast.unknown += 1,
+        }
+
+        let media_set_name = media.media_set_label().and_then(|set| {
+            if set.unassigned() {
+                return None;
+            }
+            Some(
+                media_pool
+                    .generate_media_set_name(&set.uuid, config.template.clone())
+                    .unwrap_or_else(|_| set.uuid.to_string()),
+            )
+        });
+
+        let set = media.media_set_label().filter(|set| !set.unassigned());
+
+        forecast.media.push(MediaForecastEntry {
+            label_text: media.label_text().to_string(),
+            uuid: media.uuid().clone(),
+            location: media.location().clone(),
+            status: *media.status(),
+            state,
+            expire_time,
+            media_set_name,
+            media_set_uuid: set.map(|set| set.uuid.clone()),
+            seq_nr: set.map(|set| set.seq_nr),
+            media_set_ctime: set.map(|set| set.ctime),
+            note,
+        });
+    }
+
+    forecast.media.sort_by(|a, b| {
+        a.expire_time
+            .unwrap_or(i64::MAX)
+            .cmp(&b.expire_time.unwrap_or(i64::MAX))
+            .then_with(|| a.label_text.cmp(&b.label_text))
+    });
+
+    Ok(forecast)
+}
+
 #[api(
     input: {
         properties: {
@@ -520,6 +666,187 @@ pub fn list_content(
     Ok(list)
 }
 
+// The referenced size of a snapshot stored on tape, as long as it is still present in its
+// datastore and the user may audit it there.
+fn snapshot_referenced_size(
+    datastores: &mut HashMap<String, Option<Arc<DataStore>>>,
+    user_info: &CachedUserInfo,
+    auth_id: &Authid,
+    store: &str,
+    ns: &BackupNamespace,
+    backup_dir: &pbs_api_types::BackupDir,
+) -> Option<u64> {
+    let privs = user_info.lookup_privs(auth_id, &ns.acl_path(store));
+    if privs & PRIV_DATASTORE_AUDIT == 0 {
+        return None;
+    }
+
+    let datastore = datastores
+        .entry(store.to_string())
+        .or_insert_with(|| DataStore::lookup_datastore(store, Some(Operation::Lookup)).ok())
+        .as_ref()?;
+
+    let snapshot = datastore.backup_dir(ns.clone(), backup_dir.clone()).ok()?;
+    let (manifest, _) = snapshot.load_manifest().ok()?;
+
+    Some(manifest.logical_size())
+}
+
+#[api(
+    input: {
+        properties: {
+            "media-set": {
+                schema: MEDIA_SET_UUID_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            group: {
+                schema: BACKUP_GROUP_SCHEMA,
+                optional: true,
+            },
+            "missing-only": {
+                description: "Only list media whose content is unknown because the media \
+                    or its catalog is missing.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+        },
+    },
+    returns: {
+        description: "Media set content list.",
+        type: Array,
+        items: {
+            type: MediaSetContentEntry,
+        },
+    },
+    access: {
+        description: "Requires Tape.Audit privilege on the pool of the media set. The size of \
+            a snapshot is only included with Datastore.Audit on its namespace.",
+        permission: &Permission::Anybody,
+    },
+)]
+/// List the snapshots of a media set, as recorded in the media catalogs.
+pub fn list_media_set_content(
+    media_set: Uuid,
+    store: Option<String>,
+    group: Option<String>,
+    missing_only: bool,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Vec<MediaSetContentEntry>, Error> {
+    let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
+    let user_info = CachedUserInfo::new()?;
+
+    let inventory = Inventory::load(TAPE_STATUS_DIR)?;
+
+    let pool = inventory.lookup_media_set_pool(&media_set)?;
+    user_info.check_privs(&auth_id, &["tape", "pool", &pool], PRIV_TAPE_AUDIT, false)?;
+
+    let group: Option<pbs_api_types::BackupGroup> = match group {
+        Some(group) => Some(group.parse()?),
+        None => None,
+    };
+
+    let members = inventory.compute_media_set_members(&media_set)?;
+
+    let mut datastores = HashMap::new();
+    let mut list = Vec::new();
+
+    for (seq_nr, media_uuid) in members.media_list().iter().enumerate() {
+        let seq_nr = seq_nr as u64;
+
+        let missing_entry = |label_text: Option<String>, error: String| MediaSetContentEntry {
+            seq_nr,
+            label_text,
+            uuid: media_uuid.clone(),
+            store: None,
+            ns: None,
+            snapshot: None,
+            file_nr: None,
+            size: None,
+            error: Some(error),
+        };
+
+        let media_id = match media_uuid
+            .as_ref()
+            .and_then(|uuid| inventory.lookup_media(uuid))
+        {
+            Some(media_id) => media_id,
+            None => {
+                list.push(missing_entry(None, "media not in inventory".to_string()));
+                continue;
+            }
+        };
+        let label_text = media_id.label.label_text.clone();
+
+        let catalog = match MediaCatalog::open(TAPE_STATUS_DIR, media_id, false, false) {
+            Ok(catalog) => catalog,
+            Err(err) => {
+                list.push(missing_entry(Some(label_text), err.to_string()));
+                continue;
+            }
+        };
+
+        if missing_only {
+            continue;
+        }
+
+        let mut media_content = Vec::new();
+
+        for (store_name, content) in catalog.content() {
+            if let Some(ref store) = store {
+                if store != store_name {
+                    continue;
+                }
+            }
+
+            for (snapshot, file_nr) in content.snapshot_index.iter() {
+                let (ns, backup_dir) = match pbs_api_types::parse_ns_and_snapshot(snapshot) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        log::warn!("skipping invalid snapshot '{snapshot}' in catalog - {err}");
+                        continue;
+                    }
+                };
+
+                if let Some(ref group) = group {
+                    if &backup_dir.group != group {
+                        continue;
+                    }
+                }
+
+                let size = snapshot_referenced_size(
+                    &mut datastores,
+                    &user_info,
+                    &auth_id,
+                    store_name,
+                    &ns,
+                    &backup_dir,
+                );
+
+                media_content.push(MediaSetContentEntry {
+                    seq_nr,
+                    label_text: Some(label_text.clone()),
+                    uuid: media_uuid.clone(),
+                    store: Some(store_name.clone()),
+                    ns: Some(ns),
+                    snapshot: Some(backup_dir.to_string()),
+                    file_nr: Some(*file_nr),
+                    size,
+                    error: None,
+                });
+            }
+        }
+
+        media_content.sort_unstable_by_key(|entry| entry.file_nr);
+        list.extend(media_content);
+    }
+
+    Ok(list)
+}
+
 #[api(
     input: {
         properties: {
@@ -584,14 +911,28 @@ pub const MEDIA_LIST_ROUTER: Router = Router::new()
     .get(&API_METHOD_LIST_MEDIA)
     .match_all("uuid", &MEDIA_ROUTER);
 
+const MEDIA_SET_SUBDIRS: SubdirMap = &[(
+    "content",
+    &Router::new().get(&API_METHOD_LIST_MEDIA_SET_CONTENT),
+)];
+
+pub const MEDIA_SET_ROUTER: Router = Router::new()
+    .get(&list_subdirs_api_method!(MEDIA_SET_SUBDIRS))
+    .subdirs(MEDIA_SET_SUBDIRS);
+
+pub const MEDIA_SET_LIST_ROUTER: Router = Router::new()
+    .get(&API_METHOD_LIST_MEDIA_SETS)
+    .match_all("media-set", &MEDIA_SET_ROUTER);
+
 const SUBDIRS: SubdirMap = &[
     ("content", &Router::new().get(&API_METHOD_LIST_CONTENT)),
     ("destroy", &Router::new().get(&API_METHOD_DESTROY_MEDIA)),
-    ("list", &MEDIA_LIST_ROUTER),
     (
-        "media-sets",
-        &Router::new().get(&API_METHOD_LIST_MEDIA_SETS),
+        "forecast",
+        &Router::new().get(&API_METHOD_MEDIA_POOL_FORECAST),
     ),
+    ("list", &MEDIA_LIST_ROUTER),
+    ("media-sets", &MEDIA_SET_LIST_ROUTER),
     ("move", &Router::new().post(&API_METHOD_MOVE_TAPE)),
 ];
 
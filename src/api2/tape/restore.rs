@@ -10,7 +10,7 @@ use serde_json::Value;
 use proxmox_human_byte::HumanByte;
 use proxmox_io::ReadExt;
 use proxmox_router::{Permission, Router, RpcEnvironment, RpcEnvironmentType};
-use proxmox_schema::{api, ApiType};
+use proxmox_schema::{api, param_bail, ApiType};
 use proxmox_section_config::SectionConfigData;
 use proxmox_sys::fs::{replace_file, CreateOptions};
 use proxmox_sys::{task_log, task_warn, WorkerTaskContext};
@@ -18,9 +18,10 @@ use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
     parse_ns_and_snapshot, print_ns_and_snapshot, Authid, BackupDir, BackupNamespace, CryptMode,
-    Operation, TapeRestoreNamespace, Userid, DATASTORE_MAP_ARRAY_SCHEMA, DATASTORE_MAP_LIST_SCHEMA,
-    DRIVE_NAME_SCHEMA, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY,
-    PRIV_TAPE_READ, TAPE_RESTORE_NAMESPACE_SCHEMA, TAPE_RESTORE_SNAPSHOT_SCHEMA, UPID_SCHEMA,
+    MediaPoolConfig, Operation, TapeRestoreNamespace, Userid, BACKUP_ARCHIVE_NAME_SCHEMA,
+    DATASTORE_MAP_ARRAY_SCHEMA, DATASTORE_MAP_LIST_SCHEMA, DRIVE_NAME_SCHEMA, MAX_NAMESPACE_DEPTH,
+    PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_MODIFY, PRIV_TAPE_READ, TAPE_RESTORE_NAMESPACE_SCHEMA,
+    TAPE_RESTORE_SNAPSHOT_SCHEMA, UPID_SCHEMA,
 };
 use pbs_config::CachedUserInfo;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
@@ -38,6 +39,7 @@ use crate::{
     server::lookup_user_email,
     tape::{
         drive::{lock_tape_device, request_and_load_media, set_tape_device_state, TapeDriver},
+        encryption_keys::load_keys,
         file_formats::{
             CatalogArchiveHeader, ChunkArchiveDecoder, ChunkArchiveHeader, SnapshotArchiveHeader,
             PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_0, PROXMOX_BACKUP_CATALOG_ARCHIVE_MAGIC_1_1,
@@ -72,7 +74,16 @@ impl TryFrom<Vec<String>> for NamespaceMap {
             let mapping = mapping?;
             let source = mapping.source.unwrap_or_default();
             let target = mapping.target.unwrap_or_default();
-            let max_depth = mapping.max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
+            let max_depth = match mapping.max_depth {
+                Some(max_depth) => {
+                    // fail early instead of erroring out in the middle of a restore
+                    target.check_max_depth(max_depth).map_err(|err| {
+                        format_err!("invalid namespace mapping '{source}' -> '{target}': {err}")
+                    })?;
+                    max_depth
+                }
+                None => MAX_NAMESPACE_DEPTH - target.depth(),
+            };
 
             let ns_map: &mut HashMap<BackupNamespace, (BackupNamespace, usize)> =
                 map.entry(mapping.store).or_default();
@@ -234,6 +245,84 @@ fn check_datastore_privs(
     Ok(())
 }
 
+/// Selects the owner of backup groups created during a restore.
+pub struct RestoreOwner {
+    auth_id: Authid,
+    owner: Option<Authid>,
+    user_info: Arc<CachedUserInfo>,
+}
+
+impl RestoreOwner {
+    fn new(auth_id: Authid, owner: Option<Authid>, user_info: Arc<CachedUserInfo>) -> Self {
+        Self {
+            auth_id,
+            owner,
+            user_info,
+        }
+    }
+
+    /// The owner explicitly requested for all restored groups, if any.
+    fn override_owner(&self) -> Option<&Authid> {
+        self.owner.as_ref()
+    }
+
+    /// Returns the owner for a restored backup group.
+    ///
+    /// An explicitly requested owner always wins. Otherwise the original owner recorded on the
+    /// tape is kept, as long as it still exists and the restoring user may restore as it. In all
+    /// other cases (and for tapes written without owner information) the restoring user becomes
+    /// the owner.
+    fn group_owner(
+        &self,
+        worker: &WorkerTask,
+        store: &str,
+        ns: &BackupNamespace,
+        original: Option<&Authid>,
+    ) -> Authid {
+        if let Some(owner) = &self.owner {
+            return owner.clone();
+        }
+
+        if let Some(original) = original {
+            if !self.user_info.is_active_auth_id(original) {
+                task_log!(
+                    worker,
+                    "original owner '{original}' does not exist or is inactive, restoring as '{}'",
+                    self.auth_id,
+                );
+            } else if let Err(err) =
+                check_datastore_privs(&self.user_info, store, ns, &self.auth_id, Some(original))
+            {
+                task_log!(
+                    worker,
+                    "cannot keep original owner - {err}, restoring as '{}'",
+                    self.auth_id,
+                );
+            } else {
+                return original.clone();
+            }
+        }
+
+        self.auth_id.clone()
+    }
+}
+
+/// Re-apply group notes from a snapshot archive, existing notes are never overwritten.
+fn restore_group_notes(
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    group: &pbs_api_types::BackupGroup,
+    notes: &str,
+) -> Result<(), Error> {
+    let notes_path = datastore
+        .backup_group(ns.clone(), group.clone())
+        .notes_path();
+    if notes_path.exists() {
+        return Ok(());
+    }
+    replace_file(notes_path, notes.as_bytes(), CreateOptions::new(), false)
+}
+
 fn check_and_create_namespaces(
     user_info: &CachedUserInfo,
     store: &Arc<DataStore>,
@@ -297,6 +386,15 @@ pub const ROUTER: Router = Router::new().post(&API_METHOD_RESTORE);
                     schema: TAPE_RESTORE_SNAPSHOT_SCHEMA,
                 },
             },
+            "archives": {
+                description: "Only restore these archives of the given snapshots. The restored \
+                    snapshots are marked as partial in their manifest.",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: BACKUP_ARCHIVE_NAME_SCHEMA,
+                },
+            },
             owner: {
                 type: Authid,
                 optional: true,
@@ -323,12 +421,26 @@ pub fn restore(
     media_set: String,
     notify_user: Option<Userid>,
     snapshots: Option<Vec<String>>,
+    archives: Option<Vec<String>>,
     owner: Option<Authid>,
     rpcenv: &mut dyn RpcEnvironment,
 ) -> Result<Value, Error> {
     let auth_id: Authid = rpcenv.get_auth_id().unwrap().parse()?;
     let user_info = CachedUserInfo::new()?;
 
+    if archives.is_some() && snapshots.is_none() {
+        param_bail!(
+            "archives",
+            "restoring single archives requires a list of snapshots"
+        );
+    }
+
+    if let Some(owner) = &owner {
+        if !user_info.is_active_auth_id(owner) {
+            bail!("owner '{owner}' does not exist or is not active");
+        }
+    }
+
     let mut store_map = DataStoreMap::try_from(store)
         .map_err(|err| format_err!("cannot parse store mapping: {err}"))?;
     let namespaces = if let Some(maps) = namespaces {
@@ -388,11 +500,15 @@ pub fn restore(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
+            for (datastore, _) in store_map.used_datastores().values() {
+                datastore.set_operation_owner(&worker.upid().to_string());
+            }
             let _drive_lock = drive_lock; // keep lock guard
 
             set_tape_device_state(&drive, &worker.upid().to_string())?;
 
-            let restore_owner = owner.as_ref().unwrap_or(&auth_id);
+            let restore_owner = RestoreOwner::new(auth_id.clone(), owner, user_info.clone());
 
             let email = notify_user
                 .as_ref()
@@ -402,16 +518,19 @@ pub fn restore(
             task_log!(worker, "Mediaset '{media_set}'");
             task_log!(worker, "Pool: {pool}");
 
+            check_media_set_encryption_key(&worker, &inventory, &media_set_uuid, &pool)?;
+
             let res = if snapshots.is_some() || namespaces {
                 restore_list_worker(
                     worker.clone(),
                     snapshots.unwrap_or_default(),
+                    archives,
                     inventory,
                     media_set_uuid,
                     drive_config,
                     &drive,
                     store_map,
-                    restore_owner,
+                    &restore_owner,
                     email,
                     user_info,
                     &auth_id,
@@ -424,7 +543,7 @@ pub fn restore(
                     drive_config,
                     &drive,
                     store_map,
-                    restore_owner,
+                    &restore_owner,
                     email,
                     &auth_id,
                 )
@@ -443,6 +562,65 @@ pub fn restore(
     Ok(upid_str.into())
 }
 
+/// Make sure the encryption key the media set was written with is available.
+///
+/// The pool may have been re-keyed since, so the key history of the pool is consulted to tell
+/// whether the key used to belong to it.
+fn check_media_set_encryption_key(
+    worker: &WorkerTask,
+    inventory: &Inventory,
+    media_set_uuid: &Uuid,
+    pool: &str,
+) -> Result<(), Error> {
+    let members = inventory.compute_media_set_members(media_set_uuid)?;
+
+    let fingerprint = members
+        .media_list()
+        .iter()
+        .flatten()
+        .filter_map(|uuid| inventory.lookup_media(uuid))
+        .find_map(|media_id| {
+            media_id
+                .media_set_label
+                .as_ref()?
+                .encryption_key_fingerprint
+                .clone()
+        });
+
+    let fingerprint = match fingerprint {
+        Some(fingerprint) => fingerprint,
+        None => return Ok(()),
+    };
+
+    let (pool_config, _digest) = pbs_config::media_pool::config()?;
+    let pool_config: Option<MediaPoolConfig> = pool_config.lookup("pool", pool).ok();
+
+    let pool_info = match pool_config {
+        Some(config) if config.encrypt.as_deref() == Some(fingerprint.signature().as_str()) => {
+            "current key of the pool"
+        }
+        Some(config) if config.uses_encryption_key(&fingerprint) => {
+            task_log!(
+                worker,
+                "media set was written with previous key {fingerprint} of pool '{pool}'"
+            );
+            "previous key of the pool"
+        }
+        _ => "not in the key history of the pool",
+    };
+
+    let (key_map, _digest) = load_keys()?;
+    if !key_map.contains_key(&fingerprint) {
+        bail!(
+            "tape encryption key '{}' needed for media set {media_set_uuid} is not available \
+            ({pool_info}) - please restore the key first",
+            fingerprint.signature(),
+        );
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 fn restore_full_worker(
     worker: Arc<WorkerTask>,
@@ -451,7 +629,7 @@ fn restore_full_worker(
     drive_config: SectionConfigData,
     drive_name: &str,
     store_map: DataStoreMap,
-    restore_owner: &Authid,
+    restore_owner: &RestoreOwner,
     email: Option<String>,
     auth_id: &Authid,
 ) -> Result<(), Error> {
@@ -462,6 +640,7 @@ fn restore_full_worker(
     let mut media_id_list = Vec::new();
 
     let mut encryption_key_fingerprint = None;
+    let mut key_wrapped = false;
 
     for (seq_nr, media_uuid) in media_list.iter().enumerate() {
         match media_uuid {
@@ -477,6 +656,7 @@ fn restore_full_worker(
                     {
                         encryption_key_fingerprint = set.encryption_key_fingerprint.clone();
                     }
+                    key_wrapped |= set.wrapped_key.is_some();
                 }
                 media_id_list.push(media_id);
             }
@@ -485,6 +665,12 @@ fn restore_full_worker(
 
     if let Some(fingerprint) = encryption_key_fingerprint {
         task_log!(worker, "Encryption key fingerprint: {fingerprint}");
+        if key_wrapped {
+            task_log!(
+                worker,
+                "Media set data key is wrapped by the encryption key"
+            );
+        }
     }
 
     let used_datastores = store_map.used_datastores();
@@ -538,7 +724,7 @@ fn check_snapshot_restorable(
     required: bool,
     user_info: &CachedUserInfo,
     auth_id: &Authid,
-    restore_owner: &Authid,
+    restore_owner: &RestoreOwner,
 ) -> Result<bool, Error> {
     let (datastore, namespaces) = if required {
         let (datastore, namespaces) = match store_map.get_targets(store, ns) {
@@ -568,7 +754,7 @@ fn check_snapshot_restorable(
             datastore.name(),
             &ns,
             auth_id,
-            Some(restore_owner),
+            restore_owner.override_owner(),
         ) {
             task_warn!(worker, "cannot restore {store}:{snapshot} to {ns}: '{err}'");
             continue;
@@ -576,14 +762,30 @@ fn check_snapshot_restorable(
 
         // rechecked when we create the group!
         if let Ok(owner) = datastore.get_owner(&ns, dir.as_ref()) {
-            if restore_owner != &owner {
-                // only the owner is allowed to create additional snapshots
-                task_warn!(
-                    worker,
-                    "restore  of '{snapshot}' to {ns} failed, owner check failed ({restore_owner} \
-                    != {owner})",
-                );
-                continue;
+            match restore_owner.override_owner() {
+                Some(restore_owner) if restore_owner != &owner => {
+                    // only the owner is allowed to create additional snapshots
+                    task_warn!(
+                        worker,
+                        "restore  of '{snapshot}' to {ns} failed, owner check failed \
+                        ({restore_owner} != {owner})",
+                    );
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    // the original owner is only known once the archive is read
+                    if let Err(err) = check_datastore_privs(
+                        user_info,
+                        datastore.name(),
+                        &ns,
+                        auth_id,
+                        Some(&owner),
+                    ) {
+                        task_warn!(worker, "restore of '{snapshot}' to {ns} failed: '{err}'");
+                        continue;
+                    }
+                }
             }
         }
 
@@ -629,12 +831,13 @@ fn log_required_tapes<'a>(
 fn restore_list_worker(
     worker: Arc<WorkerTask>,
     snapshots: Vec<String>,
+    archives: Option<Vec<String>>,
     inventory: Inventory,
     media_set_uuid: Uuid,
     drive_config: SectionConfigData,
     drive_name: &str,
     store_map: DataStoreMap,
-    restore_owner: &Authid,
+    restore_owner: &RestoreOwner,
     email: Option<String>,
     user_info: Arc<CachedUserInfo>,
     auth_id: &Authid,
@@ -769,8 +972,12 @@ fn restore_list_worker(
         }
 
         task_log!(worker, "Phase 1: temporarily restore snapshots to temp dir");
+        if let Some(archives) = &archives {
+            task_log!(worker, "only restoring archives: {}", archives.join(", "));
+        }
         log_required_tapes(&worker, &inventory, snapshot_file_hash.keys());
         let mut datastore_chunk_map: HashMap<String, HashSet<[u8; 32]>> = HashMap::new();
+        let mut archive_headers = HashMap::new();
         let mut tmp_paths = Vec::new();
         for (media_uuid, file_list) in snapshot_file_hash.iter_mut() {
             let media_id = inventory.lookup_media(media_uuid).unwrap();
@@ -790,7 +997,9 @@ fn restore_list_worker(
                 drive,
                 &info,
                 &media_set_uuid,
+                archives.as_deref(),
                 &mut datastore_chunk_map,
+                &mut archive_headers,
             )
             .map_err(|err| format_err!("could not restore snapshots to tmpdir: {}", err))?;
             tmp_paths.extend(tmp_path);
@@ -851,22 +1060,32 @@ fn restore_list_worker(
                     format_err!("unexpected source datastore: {}", source_datastore)
                 })?;
 
+                let archive_header =
+                    archive_headers.get(&(source_datastore.clone(), snapshot.clone()));
+
                 for ns in target_ns.unwrap_or_else(|| vec![source_ns.clone()]) {
                     if let Err(err) = proxmox_lang::try_block!({
+                        let restore_owner = restore_owner.group_owner(
+                            &worker,
+                            datastore.name(),
+                            &ns,
+                            archive_header.and_then(|header| header.owner.as_ref()),
+                        );
+
                         check_and_create_namespaces(
                             &user_info,
                             &datastore,
                             &ns,
                             auth_id,
-                            Some(restore_owner),
+                            Some(&restore_owner),
                         )?;
 
                         let (owner, _group_lock) = datastore.create_locked_backup_group(
                             &ns,
                             backup_dir.as_ref(),
-                            restore_owner,
+                            &restore_owner,
                         )?;
-                        if restore_owner != &owner {
+                        if restore_owner != owner {
                             bail!(
                                 "cannot restore snapshot '{snapshot}' into group '{}', owner check \
                                 failed ({restore_owner} != {owner})",
@@ -874,6 +1093,12 @@ fn restore_list_worker(
                             );
                         }
 
+                        if let Some(notes) =
+                            archive_header.and_then(|header| header.group_notes.as_deref())
+                        {
+                            restore_group_notes(&datastore, &ns, &backup_dir.group, notes)?;
+                        }
+
                         let (_rel_path, is_new, _snap_lock) =
                             datastore.create_locked_backup_dir(&ns, backup_dir.as_ref())?;
 
@@ -1000,6 +1225,7 @@ fn snapshot_tmpdir(
     path
 }
 
+#[allow(clippy::too_many_arguments)]
 fn restore_snapshots_to_tmpdir(
     worker: Arc<WorkerTask>,
     store_map: &DataStoreMap,
@@ -1007,7 +1233,9 @@ fn restore_snapshots_to_tmpdir(
     mut drive: Box<dyn TapeDriver>,
     media_id: &MediaId,
     media_set_uuid: &Uuid,
+    archives: Option<&[String]>,
     chunks_list: &mut HashMap<String, HashSet<[u8; 32]>>,
+    archive_headers: &mut HashMap<(String, String), SnapshotArchiveHeader>,
 ) -> Result<Vec<PathBuf>, Error> {
     let mut tmp_paths = Vec::new();
     match media_id.media_set_label {
@@ -1058,8 +1286,8 @@ fn restore_snapshots_to_tmpdir(
                         format_err!("unable to parse snapshot archive header - {err}")
                     })?;
 
-                let source_datastore = archive_header.store;
-                let snapshot = archive_header.snapshot;
+                let source_datastore = archive_header.store.clone();
+                let snapshot = archive_header.snapshot.clone();
 
                 task_log!(
                     worker,
@@ -1087,11 +1315,20 @@ fn restore_snapshots_to_tmpdir(
                 );
                 std::fs::create_dir_all(&tmp_path)?;
 
+                archive_headers.insert((source_datastore.clone(), snapshot), archive_header);
+
                 let chunks = chunks_list.entry(source_datastore).or_default();
-                let manifest =
-                    try_restore_snapshot_archive(worker.clone(), &mut decoder, &tmp_path)?;
+                let manifest = try_restore_snapshot_archive(
+                    worker.clone(),
+                    &mut decoder,
+                    &tmp_path,
+                    archives,
+                )?;
 
                 for item in manifest.files() {
+                    if !manifest.archive_available(&item.filename) {
+                        continue;
+                    }
                     let mut archive_path = tmp_path.to_owned();
                     archive_path.push(&item.filename);
 
@@ -1240,7 +1477,7 @@ pub fn request_and_restore_media(
     drive_name: &str,
     store_map: &DataStoreMap,
     checked_chunks_map: &mut HashMap<String, HashSet<[u8; 32]>>,
-    restore_owner: &Authid,
+    restore_owner: &RestoreOwner,
     email: &Option<String>,
     auth_id: &Authid,
 ) -> Result<(), Error> {
@@ -1290,7 +1527,7 @@ pub fn restore_media(
     worker: Arc<WorkerTask>,
     drive: &mut Box<dyn TapeDriver>,
     media_id: &MediaId,
-    target: Option<(&DataStoreMap, &Authid)>,
+    target: Option<(&DataStoreMap, &RestoreOwner)>,
     checked_chunks_map: &mut HashMap<String, HashSet<[u8; 32]>>,
     verbose: bool,
     auth_id: &Authid,
@@ -1342,7 +1579,7 @@ fn restore_archive<'a>(
     worker: Arc<WorkerTask>,
     mut reader: Box<dyn 'a + TapeRead>,
     current_file_number: u64,
-    target: Option<(&DataStoreMap, &Authid)>,
+    target: Option<(&DataStoreMap, &RestoreOwner)>,
     catalog: &mut MediaCatalog,
     checked_chunks_map: &mut HashMap<String, HashSet<[u8; 32]>>,
     verbose: bool,
@@ -1372,6 +1609,8 @@ fn restore_archive<'a>(
 
             let datastore_name = archive_header.store;
             let snapshot = archive_header.snapshot;
+            let original_owner = archive_header.owner;
+            let group_notes = archive_header.group_notes;
 
             task_log!(
                 worker,
@@ -1385,19 +1624,25 @@ fn restore_archive<'a>(
 
             if let Some((store_map, restore_owner)) = target.as_ref() {
                 if let Some(datastore) = store_map.target_store(&datastore_name) {
+                    let restore_owner = restore_owner.group_owner(
+                        &worker,
+                        datastore.name(),
+                        &backup_ns,
+                        original_owner.as_ref(),
+                    );
                     check_and_create_namespaces(
                         &user_info,
                         &datastore,
                         &backup_ns,
                         auth_id,
-                        Some(restore_owner),
+                        Some(&restore_owner),
                     )?;
                     let (owner, _group_lock) = datastore.create_locked_backup_group(
                         &backup_ns,
                         backup_dir.as_ref(),
-                        restore_owner,
+                        &restore_owner,
                     )?;
-                    if *restore_owner != &owner {
+                    if restore_owner != owner {
                         // only the owner is allowed to create additional snapshots
                         bail!(
                             "restore '{}' failed - owner check failed ({} != {})",
@@ -1407,6 +1652,10 @@ fn restore_archive<'a>(
                         );
                     }
 
+                    if let Some(notes) = &group_notes {
+                        restore_group_notes(&datastore, &backup_ns, &backup_dir.group, notes)?;
+                    }
+
                     let (rel_path, is_new, _snap_lock) =
                         datastore.create_locked_backup_dir(&backup_ns, backup_dir.as_ref())?;
                     let mut path = datastore.base_path();
@@ -1678,7 +1927,7 @@ fn restore_snapshot_archive<'a>(
     snapshot_path: &Path,
 ) -> Result<bool, Error> {
     let mut decoder = pxar::decoder::sync::Decoder::from_std(reader)?;
-    match try_restore_snapshot_archive(worker, &mut decoder, snapshot_path) {
+    match try_restore_snapshot_archive(worker, &mut decoder, snapshot_path, None) {
         Ok(_) => Ok(true),
         Err(err) => {
             let reader = decoder.input();
@@ -1703,6 +1952,7 @@ fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
     worker: Arc<WorkerTask>,
     decoder: &mut pxar::decoder::sync::Decoder<R>,
     snapshot_path: &Path,
+    archives: Option<&[String]>,
 ) -> Result<BackupManifest, Error> {
     let _root = match decoder.next() {
         None => bail!("missing root entry"),
@@ -1744,6 +1994,15 @@ fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
         }
 
         let filename = entry.file_name();
+
+        if let Some(archives) = archives {
+            if filename != manifest_file_name
+                && !archives.iter().any(|name| filename == OsStr::new(name))
+            {
+                continue; // contents are skipped by the decoder
+            }
+        }
+
         let mut contents = match decoder.contents() {
             None => bail!("missing file content"),
             Some(contents) => contents,
@@ -1765,6 +2024,18 @@ fn try_restore_snapshot_archive<R: pxar::decoder::SeqRead>(
                 .as_object_mut()
                 .map(|m| m.remove("verify_state"));
 
+            if let Some(archives) = archives {
+                let mut available = Vec::new();
+                for name in archives {
+                    if old_manifest.lookup_file_info(name).is_ok() {
+                        available.push(name.clone());
+                    } else {
+                        task_warn!(worker, "archive '{name}' not found in snapshot");
+                    }
+                }
+                old_manifest.set_partial_archives(&available);
+            }
+
             let old_manifest = serde_json::to_string_pretty(&old_manifest)?;
             let blob = DataBlob::encode(old_manifest.as_bytes(), None, true)?;
 
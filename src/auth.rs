@@ -26,6 +26,9 @@ use crate::auth_helpers;
 
 pub const TERM_PREFIX: &str = "PBSTERM";
 
+/// Prefix of the limited tickets which only allow a user to add a second factor.
+pub const TFA_SETUP_PREFIX: &str = "PBSTFASETUP";
+
 struct PbsAuthenticator;
 
 const SHADOW_CONFIG_FILENAME: &str = configdir!("/shadow.json");
@@ -156,7 +159,8 @@ impl Authenticator for LdapAuthenticator {
     ) -> Result<(), Error> {
         http_bail!(
             NOT_IMPLEMENTED,
-            "storing passwords is not implemented for LDAP realms"
+            "passwords of LDAP realm users cannot be changed here, please change them in the \
+            directory server instead"
         );
     }
 
@@ -251,6 +255,57 @@ pub(crate) fn authenticate_user<'a>(
     })
 }
 
+/// Returns whether the user has at least one enabled second factor, recovery keys included.
+pub(crate) fn user_has_tfa(config: &TfaConfig, userid: &Userid) -> bool {
+    let user = match config.users.get(userid.as_str()) {
+        Some(user) => user,
+        None => return false,
+    };
+
+    user.totp.iter().any(|entry| entry.info.enable)
+        || user.u2f.iter().any(|entry| entry.info.enable)
+        || user.webauthn.iter().any(|entry| entry.info.enable)
+        || user.yubico.iter().any(|entry| entry.info.enable)
+        || user.recovery.is_some()
+}
+
+/// Returns whether the realm's configuration requires its users to log in with a second factor.
+fn realm_tfa_required(realm: &RealmRef) -> Result<bool, Error> {
+    let (domains, _digest) = pbs_config::domains::config()?;
+
+    Ok(domains
+        .sections
+        .get(realm.as_str())
+        .and_then(|(_section_type, config)| config["tfa-required"].as_bool())
+        .unwrap_or(false))
+}
+
+/// Check whether the user must set up a second factor before getting a full ticket.
+pub(crate) fn tfa_setup_required(userid: &Userid) -> Result<bool, Error> {
+    let (node_config, _digest) = crate::config::node::config()?;
+    if !node_config.tfa_required.unwrap_or(false) && !realm_tfa_required(userid.realm())? {
+        return Ok(false);
+    }
+
+    let _lock = crate::config::tfa::read_lock()?;
+    let tfa_config = crate::config::tfa::read()?;
+
+    Ok(!user_has_tfa(&tfa_config, userid))
+}
+
+/// Create a ticket which only allows `userid` to add a second factor.
+pub(crate) fn assemble_tfa_setup_ticket(
+    keyring: &Keyring,
+    userid: &Userid,
+) -> Result<String, Error> {
+    Ticket::new(TFA_SETUP_PREFIX, userid)?.sign(keyring, None)
+}
+
+/// Verify a ticket created by [`assemble_tfa_setup_ticket`] and return the user it belongs to.
+pub(crate) fn verify_tfa_setup_ticket(keyring: &Keyring, ticket: &str) -> Result<Userid, Error> {
+    Ticket::<Userid>::parse(ticket)?.verify(keyring, TFA_SETUP_PREFIX, None)
+}
+
 static PRIVATE_KEYRING: Lazy<Keyring> =
     Lazy::new(|| Keyring::with_private_key(crate::auth_helpers::private_auth_key().clone().into()));
 static PUBLIC_KEYRING: Lazy<Keyring> =
@@ -315,7 +370,11 @@ impl proxmox_auth_api::api::AuthContext for PbsAuthContext {
 
     /// Check if a userid is enabled and return a [`UserInformation`] handle.
     fn auth_id_is_active(&self, auth_id: &Authid) -> Result<bool, Error> {
-        Ok(pbs_config::CachedUserInfo::new()?.is_active_auth_id(auth_id))
+        let user_info = pbs_config::CachedUserInfo::new()?;
+        if user_info.is_expired_token(auth_id) {
+            bail!("API token '{auth_id}' has expired");
+        }
+        Ok(user_info.is_active_auth_id(auth_id))
     }
 
     /// Access the TFA config with an exclusive lock.
@@ -392,3 +451,55 @@ impl LockedTfaConfig for PbsLockedTfaConfig {
         crate::config::tfa::write(&self.config)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proxmox_auth_api::api::ApiTicket;
+
+    use super::*;
+
+    fn test_keyring() -> Keyring {
+        let key = openssl::ec::EcKey::generate(
+            &openssl::ec::EcGroup::from_curve_name(openssl::nid::Nid::X9_62_PRIME256V1).unwrap(),
+        )
+        .unwrap();
+        Keyring::with_private_key(openssl::pkey::PKey::from_ec_key(key).unwrap().into())
+    }
+
+    #[test]
+    fn test_tfa_setup_ticket() {
+        let keyring = test_keyring();
+        let userid: Userid = "test@ldap1".parse().unwrap();
+
+        let ticket = assemble_tfa_setup_ticket(&keyring, &userid).unwrap();
+        assert_eq!(verify_tfa_setup_ticket(&keyring, &ticket).unwrap(), userid);
+
+        // a ticket signed with another key must not be accepted
+        assert!(verify_tfa_setup_ticket(&test_keyring(), &ticket).is_err());
+    }
+
+    #[test]
+    fn test_tfa_setup_ticket_is_no_login_ticket() {
+        let keyring = test_keyring();
+        let userid: Userid = "test@ldap1".parse().unwrap();
+
+        // the limited ticket must not pass as regular API ticket
+        let ticket = assemble_tfa_setup_ticket(&keyring, &userid).unwrap();
+        let result = Ticket::<ApiTicket>::parse(&ticket)
+            .and_then(|ticket| ticket.verify(&keyring, "PBS", None));
+        assert!(result.is_err());
+
+        // and neither full nor partial API tickets may be used to set up a second factor
+        let full = Ticket::new("PBS", &ApiTicket::Full(userid.clone()))
+            .unwrap()
+            .sign(&keyring, None)
+            .unwrap();
+        assert!(verify_tfa_setup_ticket(&keyring, &full).is_err());
+
+        let term = Ticket::new(TERM_PREFIX, &Empty)
+            .unwrap()
+            .sign(&keyring, None)
+            .unwrap();
+        assert!(verify_tfa_setup_ticket(&keyring, &term).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use anyhow::Error;
+
+use pbs_api_types::{
+    Authid, BackupNamespace, DataStoreHealth, GroupFilter, GroupHealthProblem, SnapshotVerifyState,
+    UnhealthyGroup, VerifyState, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
+};
+use pbs_config::CachedUserInfo;
+use pbs_datastore::{BackupGroup, DataStore};
+
+use crate::backup::ListAccessibleBackupGroups;
+
+/// Returns the problem of a group, given the time of its newest finished snapshot and that
+/// snapshot's verification state.
+fn group_health_problem(
+    last_backup: Option<i64>,
+    verify_state: Option<VerifyState>,
+    now: i64,
+    max_age: i64,
+) -> Option<GroupHealthProblem> {
+    match last_backup {
+        Some(time) if now - time <= max_age => {
+            (verify_state == Some(VerifyState::Failed)).then_some(GroupHealthProblem::VerifyFailed)
+        }
+        _ => Some(GroupHealthProblem::Outdated),
+    }
+}
+
+/// Returns the verification state of the newest finished snapshot of `group`.
+fn last_verify_state(group: &BackupGroup, backup_time: i64) -> Option<VerifyState> {
+    let (manifest, _) = group.backup_dir(backup_time).ok()?.load_manifest().ok()?;
+    let verify_state: SnapshotVerifyState =
+        serde_json::from_value(manifest.unprotected["verify_state"].clone()).ok()?;
+    Some(verify_state.state)
+}
+
+/// Check the groups of the namespace subtree `ns` visible to `auth_id` for a finished snapshot
+/// newer than `max_age` seconds, whose verification did not fail.
+///
+/// Only the newest finished snapshot of each group is looked at, so this is cheap enough to be
+/// polled by monitoring systems. All groups are included in namespaces with DATASTORE_AUDIT, only
+/// the owned ones in namespaces with DATASTORE_BACKUP. Groups matching one of the `ignore` filters
+/// are skipped.
+pub fn check_group_health(
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    max_depth: usize,
+    auth_id: &Authid,
+    max_age: i64,
+    ignore: &[GroupFilter],
+) -> Result<DataStoreHealth, Error> {
+    check_group_health_with_user_info(
+        CachedUserInfo::new()?,
+        datastore,
+        ns,
+        max_depth,
+        auth_id,
+        max_age,
+        ignore,
+    )
+}
+
+fn check_group_health_with_user_info(
+    user_info: Arc<CachedUserInfo>,
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    max_depth: usize,
+    auth_id: &Authid,
+    max_age: i64,
+    ignore: &[GroupFilter],
+) -> Result<DataStoreHealth, Error> {
+    let now = proxmox_time::epoch_i64();
+    let mut health = DataStoreHealth::default();
+
+    for group in ListAccessibleBackupGroups::new_with_user_info(
+        datastore,
+        ns.clone(),
+        max_depth,
+        Some(PRIV_DATASTORE_AUDIT),
+        Some(PRIV_DATASTORE_BACKUP),
+        Some(auth_id),
+        user_info,
+    )? {
+        let group = group?;
+
+        if ignore.iter().any(|filter| group.matches(filter)) {
+            health.ignored += 1;
+            continue;
+        }
+        health.checked += 1;
+
+        let last_backup = match group.last_successful_backup() {
+            Ok(last_backup) => last_backup,
+            Err(err) => {
+                // report the group as outdated, instead of silently passing the check
+                log::warn!("failed to check group {} - {err}", group.group());
+                None
+            }
+        };
+        let verify_state = last_backup.and_then(|time| last_verify_state(&group, time));
+
+        let problem = match group_health_problem(last_backup, verify_state, now, max_age) {
+            Some(problem) => problem,
+            None => continue,
+        };
+        match problem {
+            GroupHealthProblem::Outdated => health.outdated += 1,
+            GroupHealthProblem::VerifyFailed => health.verify_failed += 1,
+        }
+        health.groups.push(UnhealthyGroup {
+            ns: group.backup_ns().clone(),
+            backup: group.group().clone(),
+            last_backup,
+            problem,
+        });
+    }
+
+    Ok(health)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pbs_api_types::{BackupType, CryptMode, DatastoreFSyncLevel, UPID};
+    use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+    use pbs_datastore::{ChunkStore, DataBlob};
+
+    fn add_snapshot(
+        datastore: &Arc<DataStore>,
+        id: &str,
+        time: i64,
+        owner: &Authid,
+        verify_state: Option<VerifyState>,
+        finished: bool,
+    ) -> Result<(), Error> {
+        let ns = BackupNamespace::root();
+        let snapshot = datastore.backup_dir_from_parts(ns.clone(), BackupType::Host, id, time)?;
+        std::fs::create_dir_all(snapshot.full_path())?;
+        datastore.set_owner(&ns, &snapshot.dir().group, owner, true)?;
+
+        if !finished {
+            return Ok(());
+        }
+
+        let mut manifest = BackupManifest::new(snapshot.dir().clone());
+        manifest.add_file("root.pxar.didx".to_string(), 1, [0u8; 32], CryptMode::None)?;
+        if let Some(state) = verify_state {
+            let upid: UPID =
+                "UPID:pbs:00000001:00000002:00000003:6531a8c0:verify:test:root@pam:".parse()?;
+            manifest.unprotected["verify_state"] = serde_json::to_value(SnapshotVerifyState {
+                upid,
+                state,
+                time: None,
+            })?;
+        }
+        let blob = DataBlob::encode(manifest.to_string(None)?.as_bytes(), None, true)?;
+        std::fs::write(
+            snapshot.full_path().join(MANIFEST_BLOB_NAME),
+            blob.raw_data(),
+        )?;
+
+        Ok(())
+    }
+
+    fn unhealthy_groups(health: &DataStoreHealth) -> Vec<(String, GroupHealthProblem)> {
+        let mut groups: Vec<_> = health
+            .groups
+            .iter()
+            .map(|group| (group.backup.to_string(), group.problem))
+            .collect();
+        groups.sort_by(|a, b| a.0.cmp(&b.0));
+        groups
+    }
+
+    #[test]
+    fn test_check_group_health() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-group-health");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let sync_level = DatastoreFSyncLevel::None;
+        ChunkStore::create("test", &path, user.uid, user.gid, None, sync_level)?;
+        let datastore = unsafe { DataStore::open_path("test", &path, None)? };
+
+        let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+            r###"
+user: alice@pbs
+
+user: auditor@pbs
+
+user: bob@pbs
+
+"###,
+        )
+        .expect("test user.cfg is not parsable");
+        let acl_tree = pbs_config::acl::AclTree::from_raw(
+            r###"
+acl:1:/datastore/test:alice@pbs:DatastoreBackup
+acl:1:/datastore/test:auditor@pbs:DatastoreAudit
+"###,
+        )
+        .expect("test acl.cfg is not parsable");
+        let user_info = Arc::new(CachedUserInfo::test_new(user_cfg, acl_tree));
+
+        let alice: Authid = "alice@pbs".parse()?;
+        let auditor: Authid = "auditor@pbs".parse()?;
+        let bob: Authid = "bob@pbs".parse()?;
+
+        let max_age = 3600;
+        let recent = proxmox_time::epoch_i64() - 60;
+        let old = recent - 2 * max_age;
+
+        add_snapshot(&datastore, "ok", old, &alice, None, true)?;
+        add_snapshot(
+            &datastore,
+            "ok",
+            recent,
+            &alice,
+            Some(VerifyState::Ok),
+            true,
+        )?;
+        add_snapshot(
+            &datastore,
+            "failed",
+            recent,
+            &alice,
+            Some(VerifyState::Failed),
+            true,
+        )?;
+        // only the newest finished snapshot counts
+        add_snapshot(&datastore, "unfinished", old, &alice, None, true)?;
+        add_snapshot(&datastore, "unfinished", recent, &alice, None, false)?;
+        add_snapshot(&datastore, "old", old, &bob, None, true)?;
+        add_snapshot(&datastore, "tmp-1", old, &bob, None, true)?;
+
+        let check = |auth_id: &Authid, ignore: &[GroupFilter]| {
+            check_group_health_with_user_info(
+                Arc::clone(&user_info),
+                &datastore,
+                &BackupNamespace::root(),
+                0,
+                auth_id,
+                max_age,
+                ignore,
+            )
+        };
+
+        // Datastore.Audit sees all groups
+        let health = check(&auditor, &[])?;
+        assert_eq!((health.checked, health.ignored), (5, 0));
+        assert_eq!((health.outdated, health.verify_failed), (3, 1));
+        assert_eq!(
+            unhealthy_groups(&health),
+            [
+                ("host/failed".to_string(), GroupHealthProblem::VerifyFailed),
+                ("host/old".to_string(), GroupHealthProblem::Outdated),
+                ("host/tmp-1".to_string(), GroupHealthProblem::Outdated),
+                ("host/unfinished".to_string(), GroupHealthProblem::Outdated),
+            ]
+        );
+
+        let ignore = ["regex:^host/tmp-".parse()?, "group:host/old".parse()?];
+        let health = check(&auditor, &ignore)?;
+        assert_eq!((health.checked, health.ignored), (3, 2));
+        assert_eq!((health.outdated, health.verify_failed), (1, 1));
+        assert_eq!(
+            unhealthy_groups(&health),
+            [
+                ("host/failed".to_string(), GroupHealthProblem::VerifyFailed),
+                ("host/unfinished".to_string(), GroupHealthProblem::Outdated),
+            ]
+        );
+
+        // Datastore.Backup only sees the owned groups
+        let health = check(&alice, &[])?;
+        assert_eq!((health.checked, health.ignored), (3, 0));
+        assert_eq!(
+            unhealthy_groups(&health),
+            [
+                ("host/failed".to_string(), GroupHealthProblem::VerifyFailed),
+                ("host/unfinished".to_string(), GroupHealthProblem::Outdated),
+            ]
+        );
+
+        // no privileges on the datastore, nothing visible
+        let health = check(&bob, &[])?;
+        assert_eq!((health.checked, health.groups.len()), (0, 0));
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_health_problem() {
+        let now = 1_700_000_000;
+        let max_age = 3600;
+
+        assert_eq!(
+            group_health_problem(Some(now - 60), None, now, max_age),
+            None
+        );
+        assert_eq!(
+            group_health_problem(Some(now - 60), Some(VerifyState::Ok), now, max_age),
+            None
+        );
+        assert_eq!(
+            group_health_problem(Some(now - 60), Some(VerifyState::Failed), now, max_age),
+            Some(GroupHealthProblem::VerifyFailed)
+        );
+        assert_eq!(
+            group_health_problem(Some(now - max_age), None, now, max_age),
+            None
+        );
+        // outdated groups are reported as such, even if the verification failed too
+        assert_eq!(
+            group_health_problem(
+                Some(now - max_age - 1),
+                Some(VerifyState::Failed),
+                now,
+                max_age
+            ),
+            Some(GroupHealthProblem::Outdated)
+        );
+        assert_eq!(
+            group_health_problem(None, None, now, max_age),
+            Some(GroupHealthProblem::Outdated)
+        );
+    }
+}
@@ -49,6 +49,28 @@ pub fn check_ns_privs_full(
     partial_access_privs: u64,
 ) -> Result<bool, Error> {
     let user_info = CachedUserInfo::new()?;
+    check_ns_privs_with_user_info(
+        &user_info,
+        store,
+        ns,
+        auth_id,
+        full_access_privs,
+        partial_access_privs,
+    )
+}
+
+/// Same as [`check_ns_privs_full`], but with an explicit `user_info` instead of the cached one.
+///
+/// Privileges are always checked on the ACL path of the namespace, so users with privileges
+/// only on a (sub-)namespace can access it without having any privileges on the datastore.
+pub fn check_ns_privs_with_user_info(
+    user_info: &CachedUserInfo,
+    store: &str,
+    ns: &BackupNamespace,
+    auth_id: &Authid,
+    full_access_privs: u64,
+    partial_access_privs: u64,
+) -> Result<bool, Error> {
     let acl_path = ns.acl_path(store);
     let privs = user_info.lookup_privs(auth_id, &acl_path);
 
@@ -85,7 +107,7 @@ pub fn can_access_any_namespace(
         PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_MODIFY | PRIV_DATASTORE_READ | PRIV_DATASTORE_BACKUP;
     let name = store.name();
     iter.any(|ns| -> bool {
-        let user_privs = user_info.lookup_privs(auth_id, &["datastore", name, &ns.to_string()]);
+        let user_privs = user_info.lookup_privs(auth_id, &ns.acl_path(name));
         user_privs & wanted != 0
     })
 }
@@ -128,6 +150,28 @@ impl<'a> ListAccessibleBackupGroups<'a> {
         override_owner_priv: Option<u64>,
         owner_and_priv: Option<u64>,
         auth_id: Option<&'a Authid>,
+    ) -> Result<Self, Error> {
+        Self::new_with_user_info(
+            store,
+            ns,
+            max_depth,
+            override_owner_priv,
+            owner_and_priv,
+            auth_id,
+            CachedUserInfo::new()?,
+        )
+    }
+
+    /// Same as [`Self::new_with_privs`], but with an explicit `user_info` instead of the cached
+    /// one.
+    pub fn new_with_user_info(
+        store: &'a Arc<DataStore>,
+        ns: BackupNamespace,
+        max_depth: usize,
+        override_owner_priv: Option<u64>,
+        owner_and_priv: Option<u64>,
+        auth_id: Option<&'a Authid>,
+        user_info: Arc<CachedUserInfo>,
     ) -> Result<Self, Error> {
         let ns_iter = ListNamespacesRecursive::new_max_depth(Arc::clone(store), ns, max_depth)?;
         Ok(ListAccessibleBackupGroups {
@@ -137,7 +181,7 @@ impl<'a> ListAccessibleBackupGroups<'a> {
             owner_and_priv: owner_and_priv.unwrap_or(0),
             state: None,
             store,
-            user_info: CachedUserInfo::new()?,
+            user_info,
         })
     }
 }
@@ -206,3 +250,101 @@ impl<'a> Iterator for ListAccessibleBackupGroups<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use proxmox_router::HttpError;
+
+    use pbs_api_types::{PRIV_DATASTORE_PRUNE, PRIV_DATASTORE_VERIFY};
+
+    fn is_forbidden(result: Result<bool, Error>) -> bool {
+        match result {
+            Err(err) => matches!(
+                err.downcast_ref::<HttpError>(),
+                Some(HttpError { code, .. }) if *code == http::StatusCode::FORBIDDEN
+            ),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn namespace_only_privs_test() -> Result<(), Error> {
+        let (user_cfg, _) = pbs_config::user::test_cfg_from_str(
+            r###"
+user: ns1@pbs
+
+user: ns1admin@pbs
+
+"###,
+        )
+        .expect("test user.cfg is not parsable");
+        let acl_tree = pbs_config::acl::AclTree::from_raw(
+            r###"
+acl:1:/datastore/store1/ns1:ns1@pbs:DatastorePowerUser
+acl:1:/datastore/store1/ns1:ns1admin@pbs:DatastoreAdmin
+"###,
+        )
+        .expect("test acl.cfg is not parsable");
+
+        let user_info = CachedUserInfo::test_new(user_cfg, acl_tree);
+
+        let user: Authid = "ns1@pbs".parse()?;
+        let admin: Authid = "ns1admin@pbs".parse()?;
+
+        let root = BackupNamespace::root();
+        let ns1: BackupNamespace = "ns1".parse()?;
+        let ns1_sub: BackupNamespace = "ns1/sub".parse()?;
+        let ns2: BackupNamespace = "ns2".parse()?;
+        let ns2_ns1: BackupNamespace = "ns2/ns1".parse()?;
+
+        assert_eq!(
+            ns1_sub.acl_path("store1"),
+            ["datastore", "store1", "ns1", "sub"]
+        );
+
+        let check = |auth_id: &Authid, store: &str, ns: &BackupNamespace, full, partial| {
+            check_ns_privs_with_user_info(&user_info, store, ns, auth_id, full, partial)
+        };
+
+        // listing groups and snapshots, limited to owned groups
+        let (full, partial) = (PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP);
+        for ns in [&ns1, &ns1_sub] {
+            assert!(check(&user, "store1", ns, full, partial)?);
+            assert!(!check(&admin, "store1", ns, full, partial)?);
+        }
+
+        // pruning, limited to owned groups
+        let (full, partial) = (PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE);
+        for ns in [&ns1, &ns1_sub] {
+            assert!(check(&user, "store1", ns, full, partial)?);
+            assert!(!check(&admin, "store1", ns, full, partial)?);
+        }
+
+        // neither the root, sibling namespaces nor other datastores are accessible
+        for (auth_id, privs) in [
+            (
+                &user,
+                PRIV_DATASTORE_AUDIT | PRIV_DATASTORE_BACKUP | PRIV_DATASTORE_PRUNE,
+            ),
+            (&admin, PRIV_DATASTORE_MODIFY | PRIV_DATASTORE_VERIFY),
+        ] {
+            for ns in [&root, &ns2, &ns2_ns1] {
+                assert!(is_forbidden(check(auth_id, "store1", ns, privs, privs)));
+            }
+            assert!(is_forbidden(check(auth_id, "store2", &ns1, privs, privs)));
+        }
+
+        // the user has no full access anywhere
+        assert!(is_forbidden(check(
+            &user,
+            "store1",
+            &ns1,
+            PRIV_DATASTORE_MODIFY,
+            0
+        )));
+
+        Ok(())
+    }
+}
@@ -8,3 +8,9 @@ pub use verify::*;
 
 mod hierarchy;
 pub use hierarchy::*;
+
+mod group_health;
+pub use group_health::*;
+
+mod owner_report;
+pub use owner_report::*;
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_warn, WorkerTaskContext};
+
+use pbs_api_types::{
+    Authid, BackupNamespace, OwnerReport, OwnerUsage, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
+};
+use pbs_buildcfg::PROXMOX_BACKUP_RUN_DIR_M;
+use pbs_datastore::{BackupGroup, DataStore};
+
+use crate::backup::ListAccessibleBackupGroups;
+
+const OWNER_REPORT_DIR: &str = concat!(PROXMOX_BACKUP_RUN_DIR_M!(), "/owner-reports");
+
+/// Number of backup groups up to which owner reports are generated synchronously by default.
+pub const OWNER_REPORT_SYNC_LIMIT_DEFAULT: usize = 100;
+
+/// List the groups of the namespace subtree `ns` visible to `auth_id` for an owner report.
+///
+/// All groups are included in namespaces with DATASTORE_AUDIT, only the owned ones in
+/// namespaces with DATASTORE_BACKUP.
+pub fn list_report_groups(
+    datastore: &Arc<DataStore>,
+    ns: &BackupNamespace,
+    max_depth: usize,
+    auth_id: &Authid,
+) -> Result<Vec<BackupGroup>, Error> {
+    ListAccessibleBackupGroups::new_with_privs(
+        datastore,
+        ns.clone(),
+        max_depth,
+        Some(PRIV_DATASTORE_AUDIT),
+        Some(PRIV_DATASTORE_BACKUP),
+        Some(auth_id),
+    )?
+    .collect()
+}
+
+/// Sum up groups, snapshots and their logical size per owner.
+///
+/// Snapshots without a readable manifest, like unfinished ones, are not counted. Groups without
+/// counted snapshots are skipped.
+pub fn generate_owner_report(
+    ns: BackupNamespace,
+    max_depth: usize,
+    groups: Vec<BackupGroup>,
+    worker: Option<&dyn WorkerTaskContext>,
+) -> Result<OwnerReport, Error> {
+    let mut usage: HashMap<Authid, OwnerUsage> = HashMap::new();
+
+    for group in groups {
+        if let Some(worker) = worker {
+            worker.check_abort()?;
+        }
+
+        let owner = match group.get_owner() {
+            Ok(owner) => owner,
+            Err(err) => {
+                if let Some(worker) = worker {
+                    task_warn!(worker, "skipping group {} - {err}", group.group());
+                }
+                continue;
+            }
+        };
+
+        let mut snapshots = 0;
+        let mut size = 0;
+        for info in group.list_backups()? {
+            if let Ok((manifest, _)) = info.backup_dir.load_manifest() {
+                snapshots += 1;
+                size += manifest.logical_size();
+            }
+        }
+        if snapshots == 0 {
+            continue;
+        }
+
+        let entry = usage.entry(owner.clone()).or_insert_with(|| OwnerUsage {
+            owner,
+            groups: 0,
+            snapshots: 0,
+            size: 0,
+        });
+        entry.groups += 1;
+        entry.snapshots += snapshots;
+        entry.size += size;
+    }
+
+    let mut owners: Vec<OwnerUsage> = usage.into_values().collect();
+    owners.sort_by(|a, b| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.owner.to_string().cmp(&b.owner.to_string()))
+    });
+
+    Ok(OwnerReport {
+        ns,
+        max_depth,
+        time: proxmox_time::epoch_i64(),
+        owners,
+    })
+}
+
+// reports depend on the privileges of the requesting user, so they are cached per user
+fn report_path(store: &str, auth_id: &Authid, ns: &BackupNamespace, max_depth: usize) -> PathBuf {
+    let key = openssl::sha::sha256(format!("{auth_id}:{ns}:{max_depth}").as_bytes());
+    let mut path = PathBuf::from(OWNER_REPORT_DIR);
+    path.push(store);
+    path.push(format!("{}.json", hex::encode(key)));
+    path
+}
+
+/// Load the last report generated for `auth_id` on the namespace subtree `ns` with `max_depth`,
+/// if any.
+pub fn load_owner_report(
+    store: &str,
+    auth_id: &Authid,
+    ns: &BackupNamespace,
+    max_depth: usize,
+) -> Result<Option<OwnerReport>, Error> {
+    let path = report_path(store, auth_id, ns, max_depth);
+    match file_read_optional_string(&path)? {
+        Some(data) => Ok(Some(serde_json::from_str(&data).map_err(|err| {
+            format_err!("unable to parse owner report {path:?} - {err}")
+        })?)),
+        None => Ok(None),
+    }
+}
+
+/// Store `report` as last report generated for `auth_id`.
+pub fn store_owner_report(
+    store: &str,
+    auth_id: &Authid,
+    report: &OwnerReport,
+) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let dir_opts = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+    let file_opts = dir_opts
+        .clone()
+        .perm(nix::sys::stat::Mode::from_bits_truncate(0o0640));
+
+    let path = report_path(store, auth_id, &report.ns, report.max_depth);
+    if let Some(parent) = path.parent() {
+        create_path(parent, Some(dir_opts.clone()), Some(dir_opts))?;
+    }
+
+    replace_file(path, &serde_json::to_vec(report)?, file_opts, false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pbs_api_types::{BackupType, CryptMode, DatastoreFSyncLevel};
+    use pbs_datastore::manifest::{BackupManifest, MANIFEST_BLOB_NAME};
+    use pbs_datastore::{ChunkStore, DataBlob};
+
+    fn add_snapshot(
+        datastore: &Arc<DataStore>,
+        id: &str,
+        time: i64,
+        owner: &Authid,
+        size: Option<u64>,
+    ) -> Result<BackupGroup, Error> {
+        let ns = BackupNamespace::root();
+        let snapshot = datastore.backup_dir_from_parts(ns.clone(), BackupType::Host, id, time)?;
+        std::fs::create_dir_all(snapshot.full_path())?;
+        datastore.set_owner(&ns, &snapshot.dir().group, owner, true)?;
+
+        // snapshots without size have no manifest, like unfinished ones
+        if let Some(size) = size {
+            let mut manifest = BackupManifest::new(snapshot.dir().clone());
+            manifest.add_file(
+                "root.pxar.didx".to_string(),
+                size,
+                [0u8; 32],
+                CryptMode::None,
+            )?;
+            manifest.add_file(
+                "qemu-server.conf.blob".to_string(),
+                1,
+                [0u8; 32],
+                CryptMode::None,
+            )?;
+            let blob = DataBlob::encode(manifest.to_string(None)?.as_bytes(), None, true)?;
+            std::fs::write(
+                snapshot.full_path().join(MANIFEST_BLOB_NAME),
+                blob.raw_data(),
+            )?;
+        }
+
+        Ok(datastore.backup_group_from_parts(ns, BackupType::Host, id))
+    }
+
+    #[test]
+    fn test_generate_owner_report() -> Result<(), Error> {
+        let mut path = std::fs::canonicalize(".")?; // we need absolute path
+        path.push(".testdir-owner-report");
+        let _ = std::fs::remove_dir_all(&path);
+
+        let user = nix::unistd::User::from_uid(nix::unistd::Uid::current())?.unwrap();
+        let sync_level = DatastoreFSyncLevel::None;
+        ChunkStore::create("test", &path, user.uid, user.gid, None, sync_level)?;
+        let datastore = unsafe { DataStore::open_path("test", &path, None)? };
+
+        let alice: Authid = "alice@pbs".parse()?;
+        let bob: Authid = "bob@pbs".parse()?;
+
+        let groups = vec![
+            add_snapshot(&datastore, "a1", 1_700_000_000, &alice, Some(100))?,
+            add_snapshot(&datastore, "a1", 1_700_000_100, &alice, Some(200))?,
+            add_snapshot(&datastore, "a2", 1_700_000_000, &alice, Some(50))?,
+            add_snapshot(&datastore, "b1", 1_700_000_000, &bob, Some(1000))?,
+            add_snapshot(&datastore, "b1", 1_700_000_100, &bob, None)?,
+            add_snapshot(&datastore, "b2", 1_700_000_000, &bob, None)?,
+        ];
+
+        let report = generate_owner_report(BackupNamespace::root(), 0, groups, None)?;
+        let _ = std::fs::remove_dir_all(&path);
+
+        assert_eq!(report.max_depth, 0);
+        let owners: Vec<_> = report
+            .owners
+            .iter()
+            .map(|usage| {
+                (
+                    usage.owner.to_string(),
+                    usage.groups,
+                    usage.snapshots,
+                    usage.size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            owners,
+            [
+                ("bob@pbs".to_string(), 1, 1, 1000),
+                ("alice@pbs".to_string(), 2, 3, 350),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_path() -> Result<(), Error> {
+        let auth_id: Authid = "alice@pbs".parse()?;
+        let ns: BackupNamespace = "a/b".parse()?;
+
+        let path = report_path("store1", &auth_id, &ns, 7);
+        assert_eq!(path, report_path("store1", &auth_id, &ns, 7));
+        assert_ne!(path, report_path("store1", &auth_id, &ns, 0));
+        assert_ne!(
+            path,
+            report_path("store1", &auth_id, &BackupNamespace::root(), 7)
+        );
+        assert_ne!(path, report_path("store1", &"bob@pbs".parse()?, &ns, 7));
+        assert_ne!(path, report_path("store2", &auth_id, &ns, 7));
+
+        Ok(())
+    }
+}
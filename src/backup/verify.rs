@@ -1,27 +1,53 @@
-use nix::dir::Dir;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use anyhow::{bail, format_err, Error};
+use hex::FromHex;
+use lazy_static::lazy_static;
 
 use proxmox_sys::{task_log, WorkerTaskContext};
 
 use pbs_api_types::{
     print_ns_and_snapshot, print_store_and_ns, Authid, BackupNamespace, BackupType, CryptMode,
-    SnapshotVerifyState, VerifyState, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_VERIFY, UPID,
+    SnapshotVerifyState, VerifyBadChunkReference, VerifySnapshotResult, VerifyState,
+    VerifyTaskStatus, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_VERIFY, UPID,
 };
 use pbs_datastore::backup_info::{BackupDir, BackupGroup, BackupInfo};
 use pbs_datastore::index::IndexFile;
+use pbs_datastore::lock_tracking::{lock_dir_noblock_shared, with_lock_owner, DirLockGuard};
 use pbs_datastore::manifest::{archive_type, ArchiveType, BackupManifest, FileInfo};
 use pbs_datastore::{DataBlob, DataStore, StoreProgress};
-use proxmox_sys::fs::lock_dir_noblock_shared;
 
 use crate::tools::parallel_handler::ParallelHandler;
 
 use crate::backup::hierarchy::ListAccessibleBackupGroups;
 
+/// Number of finished verify tasks for which the status is kept around.
+const MAX_FINISHED_VERIFY_STATUS: usize = 32;
+
+/// Maximum number of snapshot results kept in the status of a verify task.
+const MAX_VERIFY_RESULTS: usize = 1000;
+
+#[derive(Default)]
+struct VerifyStatusMap {
+    tasks: HashMap<String, Arc<Mutex<VerifyTaskStatus>>>,
+    finished: VecDeque<String>,
+}
+
+lazy_static! {
+    static ref VERIFY_STATUS_MAP: Mutex<VerifyStatusMap> = Mutex::new(VerifyStatusMap::default());
+}
+
+/// Returns the status of a running or recently finished verify task.
+pub fn verify_task_status(upid: &str) -> Option<VerifyTaskStatus> {
+    let map = VERIFY_STATUS_MAP.lock().unwrap();
+    map.tasks
+        .get(upid)
+        .map(|status| status.lock().unwrap().clone())
+}
+
 /// A VerifyWorker encapsulates a task worker, datastore and information about which chunks have
 /// already been verified or detected as corrupt.
 pub struct VerifyWorker {
@@ -29,6 +55,8 @@ pub struct VerifyWorker {
     datastore: Arc<DataStore>,
     verified_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
     corrupt_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    status: Arc<Mutex<VerifyTaskStatus>>,
+    status_upid: Mutex<Option<String>>,
 }
 
 impl VerifyWorker {
@@ -41,8 +69,179 @@ impl VerifyWorker {
             verified_chunks: Arc::new(Mutex::new(HashSet::with_capacity(16 * 1024))),
             // start with 64 chunks since we assume there are few corrupt ones
             corrupt_chunks: Arc::new(Mutex::new(HashSet::with_capacity(64))),
+            status: Arc::new(Mutex::new(VerifyTaskStatus {
+                running: true,
+                ..Default::default()
+            })),
+            status_upid: Mutex::new(None),
+        }
+    }
+
+    /// Publishes the status of this worker, so that it can be queried by the task's UPID.
+    fn publish_status(&self, upid: &UPID) {
+        let mut status_upid = self.status_upid.lock().unwrap();
+        if status_upid.is_none() {
+            let upid = upid.to_string();
+            VERIFY_STATUS_MAP
+                .lock()
+                .unwrap()
+                .tasks
+                .insert(upid.clone(), Arc::clone(&self.status));
+            *status_upid = Some(upid);
+        }
+    }
+
+    fn update_status<F: FnOnce(&mut VerifyTaskStatus)>(&self, func: F) {
+        func(&mut self.status.lock().unwrap());
+    }
+
+    /// Adds to the number of snapshots which are going to be verified.
+    pub fn add_snapshots_total(&self, count: u64) {
+        self.update_status(|status| status.progress.snapshots_total += count);
+    }
+
+    /// Marks the current snapshot as done, `result` is `None` if it was skipped.
+    fn finish_snapshot(&self, result: Option<VerifySnapshotResult>) {
+        self.update_status(|status| {
+            status.progress.snapshots_done += 1;
+            status.progress.current_snapshot = None;
+            if let Some(result) = result {
+                push_verify_result(status, result);
+            }
+        });
+    }
+
+    /// Updates the progress percentage of the task.
+    pub fn update_percentage(&self, progress: &StoreProgress) {
+        let percentage = if progress.total_groups == 0 {
+            100.0
+        } else {
+            (progress.percentage() * 100.0).min(100.0)
+        };
+        self.update_status(|status| status.progress.percentage = percentage);
+    }
+
+    /// Searches all index files of the datastore for the chunks renamed to `.bad` by this
+    /// worker, and reports which snapshots reference them.
+    ///
+    /// The report is written to the task log and published in the task status.
+    pub fn report_bad_chunks(&self) -> Result<(), Error> {
+        let bad_chunks: HashSet<[u8; 32]> = self
+            .status
+            .lock()
+            .unwrap()
+            .bad_chunks
+            .iter()
+            .filter_map(|digest| <[u8; 32]>::from_hex(digest).ok())
+            .collect();
+
+        if bad_chunks.is_empty() {
+            return Ok(());
+        }
+
+        let worker = &self.worker;
+        task_log!(
+            worker,
+            "searching all index files for references to {} bad chunks",
+            bad_chunks.len()
+        );
+
+        let base_path = self.datastore.base_path();
+        let mut references: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+        for img in self.datastore.list_images()? {
+            worker.check_abort()?;
+            worker.fail_on_shutdown()?;
+
+            let index = match self.datastore.open_index(&img) {
+                Ok(index) => index,
+                Err(_) if !img.exists() => continue, // vanished
+                Err(err) => {
+                    task_log!(worker, "unable to open index {:?} - {}", img, err);
+                    continue;
+                }
+            };
+
+            let found: BTreeSet<String> = (0..index.index_count())
+                .filter_map(|pos| index.index_digest(pos))
+                .filter(|digest| bad_chunks.contains(*digest))
+                .map(hex::encode)
+                .collect();
+
+            if found.is_empty() {
+                continue;
+            }
+
+            let snapshot = img
+                .parent()
+                .and_then(|dir| dir.strip_prefix(&base_path).ok())
+                .unwrap_or(img.as_path())
+                .to_string_lossy()
+                .into_owned();
+
+            references.entry(snapshot).or_default().extend(found);
+        }
+
+        if references.is_empty() {
+            task_log!(worker, "no snapshot references any of the bad chunks");
+        } else {
+            task_log!(worker, "snapshots referencing bad chunks:");
+        }
+
+        let report: Vec<VerifyBadChunkReference> = references
+            .into_iter()
+            .map(|(snapshot, digests)| {
+                let digests: Vec<String> = digests.into_iter().collect();
+                task_log!(worker, "\t{}: {}", snapshot, digests.join(", "));
+                VerifyBadChunkReference { snapshot, digests }
+            })
+            .collect();
+
+        self.update_status(|status| status.bad_chunk_report = Some(report));
+
+        Ok(())
+    }
+}
+
+impl Drop for VerifyWorker {
+    fn drop(&mut self) {
+        self.update_status(|status| {
+            status.running = false;
+            status.progress.current_snapshot = None;
+        });
+
+        if let Some(upid) = self.status_upid.lock().unwrap().take() {
+            let mut map = VERIFY_STATUS_MAP.lock().unwrap();
+            map.finished.push_back(upid);
+            while map.finished.len() > MAX_FINISHED_VERIFY_STATUS {
+                if let Some(old) = map.finished.pop_front() {
+                    map.tasks.remove(&old);
+                }
+            }
+        }
+    }
+}
+
+// Adds a snapshot result to the task status, keeping at most `MAX_VERIFY_RESULTS`. Once full, a
+// failed snapshot replaces the oldest successful one, other results are only counted.
+fn push_verify_result(status: &mut VerifyTaskStatus, result: VerifySnapshotResult) {
+    if status.results.len() >= MAX_VERIFY_RESULTS {
+        let replace = match result.state {
+            VerifyState::Failed => status
+                .results
+                .iter()
+                .position(|result| result.state == VerifyState::Ok),
+            VerifyState::Ok => None,
+        };
+        status.omitted_results += 1;
+        match replace {
+            Some(pos) => {
+                status.results.remove(pos);
+            }
+            None => return,
         }
     }
+    status.results.push(result);
 }
 
 fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
@@ -71,6 +270,7 @@ fn verify_blob(backup_dir: &BackupDir, info: &FileInfo) -> Result<(), Error> {
 
 fn rename_corrupted_chunk(
     datastore: Arc<DataStore>,
+    status: &Mutex<VerifyTaskStatus>,
     digest: &[u8; 32],
     worker: &dyn WorkerTaskContext,
 ) {
@@ -90,6 +290,7 @@ fn rename_corrupted_chunk(
     match std::fs::rename(&path, &new_path) {
         Ok(_) => {
             task_log!(worker, "corrupted chunk renamed to {:?}", &new_path);
+            status.lock().unwrap().bad_chunks.push(digest_str);
         }
         Err(err) => {
             match err.kind() {
@@ -121,6 +322,7 @@ fn verify_index_chunks(
     let datastore2 = Arc::clone(&verify_worker.datastore);
     let corrupt_chunks2 = Arc::clone(&verify_worker.corrupt_chunks);
     let verified_chunks2 = Arc::clone(&verify_worker.verified_chunks);
+    let status2 = Arc::clone(&verify_worker.status);
     let errors2 = Arc::clone(&errors);
 
     let decoder_pool = ParallelHandler::new(
@@ -151,7 +353,7 @@ fn verify_index_chunks(
                 corrupt_chunks2.lock().unwrap().insert(digest);
                 task_log!(worker2, "{}", err);
                 errors2.fetch_add(1, Ordering::SeqCst);
-                rename_corrupted_chunk(datastore2.clone(), &digest, &worker2);
+                rename_corrupted_chunk(datastore2.clone(), &status2, &digest, &worker2);
             } else {
                 verified_chunks2.lock().unwrap().insert(digest);
             }
@@ -226,6 +428,7 @@ fn verify_index_chunks(
                 errors.fetch_add(1, Ordering::SeqCst);
                 rename_corrupted_chunk(
                     verify_worker.datastore.clone(),
+                    &verify_worker.status,
                     &info.digest,
                     &verify_worker.worker,
                 );
@@ -233,6 +436,10 @@ fn verify_index_chunks(
             Ok(chunk) => {
                 let size = info.size();
                 read_bytes += chunk.raw_size();
+                verify_worker.update_status(|status| {
+                    status.progress.chunks_verified += 1;
+                    status.progress.bytes_read += chunk.raw_size();
+                });
                 decoder_pool.send((chunk, info.digest, size))?;
                 decoded_bytes += size;
             }
@@ -326,8 +533,10 @@ pub fn verify_backup_dir(
     verify_worker: &VerifyWorker,
     backup_dir: &BackupDir,
     upid: UPID,
-    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
+    filter: Option<&dyn Fn(&BackupManifest) -> Option<VerifySkipReason>>,
 ) -> Result<bool, Error> {
+    verify_worker.publish_status(&upid);
+
     if !backup_dir.full_path().exists() {
         task_log!(
             verify_worker.worker,
@@ -335,14 +544,17 @@ pub fn verify_backup_dir(
             verify_worker.datastore.name(),
             backup_dir.dir(),
         );
+        verify_worker.finish_snapshot(None);
         return Ok(true);
     }
 
-    let snap_lock = lock_dir_noblock_shared(
-        &backup_dir.full_path(),
-        "snapshot",
-        "locked by another operation",
-    );
+    let snap_lock = with_lock_owner(Some(&upid.to_string()), "verify", || {
+        lock_dir_noblock_shared(
+            &backup_dir.full_path(),
+            "snapshot",
+            "locked by another operation",
+        )
+    });
     match snap_lock {
         Ok(snap_lock) => {
             verify_backup_dir_with_lock(verify_worker, backup_dir, upid, filter, snap_lock)
@@ -355,6 +567,7 @@ pub fn verify_backup_dir(
                 backup_dir.dir(),
                 err,
             );
+            verify_worker.finish_snapshot(None);
             Ok(true)
         }
     }
@@ -365,9 +578,16 @@ pub fn verify_backup_dir_with_lock(
     verify_worker: &VerifyWorker,
     backup_dir: &BackupDir,
     upid: UPID,
-    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
-    _snap_lock: Dir,
+    filter: Option<&dyn Fn(&BackupManifest) -> Option<VerifySkipReason>>,
+    _snap_lock: DirLockGuard,
 ) -> Result<bool, Error> {
+    verify_worker.publish_status(&upid);
+
+    let snapshot = print_ns_and_snapshot(backup_dir.backup_ns(), backup_dir.as_ref());
+    verify_worker.update_status(|status| {
+        status.progress.current_snapshot = Some(snapshot.clone());
+    });
+
     let manifest = match backup_dir.load_manifest() {
         Ok((manifest, _)) => manifest,
         Err(err) => {
@@ -378,20 +598,24 @@ pub fn verify_backup_dir_with_lock(
                 backup_dir.dir(),
                 err,
             );
+            verify_worker.finish_snapshot(Some(VerifySnapshotResult {
+                snapshot,
+                state: VerifyState::Failed,
+                error: Some(format!("manifest load error: {err}")),
+            }));
             return Ok(false);
         }
     };
 
-    if let Some(filter) = filter {
-        if !filter(&manifest) {
-            task_log!(
-                verify_worker.worker,
-                "SKIPPED: verify {}:{} (recently verified)",
-                verify_worker.datastore.name(),
-                backup_dir.dir(),
-            );
-            return Ok(true);
-        }
+    if let Some(reason) = filter.and_then(|filter| filter(&manifest)) {
+        task_log!(
+            verify_worker.worker,
+            "SKIPPED: verify {}:{} ({reason})",
+            verify_worker.datastore.name(),
+            backup_dir.dir(),
+        );
+        verify_worker.finish_snapshot(None);
+        return Ok(true);
     }
 
     task_log!(
@@ -401,10 +625,18 @@ pub fn verify_backup_dir_with_lock(
         backup_dir.dir()
     );
 
-    let mut error_count = 0;
+    let mut errors = Vec::new();
 
     let mut verify_result = VerifyState::Ok;
     for info in manifest.files() {
+        if !manifest.archive_available(&info.filename) {
+            task_log!(
+                verify_worker.worker,
+                "  skip {} (not part of partial restore)",
+                info.filename
+            );
+            continue;
+        }
         let result = proxmox_lang::try_block!({
             task_log!(verify_worker.worker, "  check {}", info.filename);
             match archive_type(&info.filename)? {
@@ -426,7 +658,7 @@ pub fn verify_backup_dir_with_lock(
                 info.filename,
                 err,
             );
-            error_count += 1;
+            errors.push(format!("{}: {err}", info.filename));
             verify_result = VerifyState::Failed;
         }
     }
@@ -434,15 +666,20 @@ pub fn verify_backup_dir_with_lock(
     let verify_state = SnapshotVerifyState {
         state: verify_result,
         upid,
+        time: Some(proxmox_time::epoch_i64()),
     };
-    let verify_state = serde_json::to_value(verify_state)?;
     backup_dir
-        .update_manifest(|manifest| {
-            manifest.unprotected["verify_state"] = verify_state;
-        })
+        .update_manifest(|manifest| manifest.record_verify_state(verify_state))
         .map_err(|err| format_err!("unable to update manifest blob - {}", err))?;
 
-    Ok(error_count == 0)
+    let success = errors.is_empty();
+    verify_worker.finish_snapshot(Some(VerifySnapshotResult {
+        snapshot,
+        state: verify_result,
+        error: (!success).then(|| errors.join("; ")),
+    }));
+
+    Ok(success)
 }
 
 /// Verify all backups inside a backup group
@@ -457,8 +694,10 @@ pub fn verify_backup_group(
     group: &BackupGroup,
     progress: &mut StoreProgress,
     upid: &UPID,
-    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
+    filter: Option<&dyn Fn(&BackupManifest) -> Option<VerifySkipReason>>,
 ) -> Result<Vec<String>, Error> {
+    verify_worker.publish_status(upid);
+
     let mut errors = Vec::new();
     let mut list = match group.list_backups() {
         Ok(list) => list,
@@ -484,6 +723,7 @@ pub fn verify_backup_group(
     );
 
     progress.group_snapshots = snapshot_count as u64;
+    verify_worker.add_snapshots_total(snapshot_count as u64);
 
     BackupInfo::sort_list(&mut list, false); // newest first
     for (pos, info) in list.into_iter().enumerate() {
@@ -494,6 +734,7 @@ pub fn verify_backup_group(
             ));
         }
         progress.done_snapshots = pos as u64 + 1;
+        verify_worker.update_percentage(progress);
         task_log!(verify_worker.worker, "percentage done: {}", progress);
     }
 
@@ -513,8 +754,10 @@ pub fn verify_all_backups(
     ns: BackupNamespace,
     max_depth: Option<usize>,
     owner: Option<&Authid>,
-    filter: Option<&dyn Fn(&BackupManifest) -> bool>,
+    filter: Option<&dyn Fn(&BackupManifest) -> Option<VerifySkipReason>>,
 ) -> Result<Vec<String>, Error> {
+    verify_worker.publish_status(upid);
+
     let mut errors = Vec::new();
     let worker = Arc::clone(&verify_worker.worker);
 
@@ -583,12 +826,24 @@ pub fn verify_all_backups(
         let mut group_errors =
             verify_backup_group(verify_worker, &group, &mut progress, upid, filter)?;
         errors.append(&mut group_errors);
+
+        // groups without snapshots do not update the percentage themselves
+        progress.done_groups = pos as u64 + 1;
+        progress.group_snapshots = 0;
+        verify_worker.update_percentage(&progress);
+    }
+
+    if group_count == 0 {
+        verify_worker.update_percentage(&progress);
     }
 
     Ok(errors)
 }
 
 /// Filter out any snapshot from being (re-)verified where this fn returns false.
+///
+/// A failed last verification counts like a successful one here, only verify jobs always
+/// re-verify failed snapshots.
 pub fn verify_filter(
     ignore_verified_snapshots: bool,
     outdated_after: Option<i64>,
@@ -600,7 +855,8 @@ pub fn verify_filter(
 
     let raw_verify_state = manifest.unprotected["verify_state"].clone();
     match serde_json::from_value::<SnapshotVerifyState>(raw_verify_state) {
-        Err(_) => true, // no last verification, always include
+        // no last verification (or one without a valid UPID), always include
+        Err(_) => true,
         Ok(last_verify) => {
             match outdated_after {
                 None => false, // never re-verify if ignored and no max age
@@ -614,3 +870,257 @@ pub fn verify_filter(
         }
     }
 }
+
+/// Reason why a snapshot is not verified again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifySkipReason {
+    /// The last verification succeeded and is not outdated.
+    RecentlyVerified,
+    /// The last verification failed and failed snapshots should not be verified again.
+    PreviouslyFailed,
+}
+
+impl std::fmt::Display for VerifySkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifySkipReason::RecentlyVerified => f.write_str("already verified"),
+            VerifySkipReason::PreviouslyFailed => f.write_str("last verification failed"),
+        }
+    }
+}
+
+/// Check if the snapshot of `manifest` should be skipped, and why.
+pub fn verify_skip_reason(
+    ignore_verified_snapshots: bool,
+    outdated_after: Option<i64>,
+    re_verify_failed: bool,
+    manifest: &BackupManifest,
+) -> Option<VerifySkipReason> {
+    let raw_verify_state = manifest.unprotected["verify_state"].clone();
+    let last_verify = match serde_json::from_value::<SnapshotVerifyState>(raw_verify_state) {
+        Ok(last_verify) => last_verify,
+        // no last verification (or one without a valid UPID), always include
+        Err(_) => return None,
+    };
+
+    if last_verify.state != VerifyState::Ok {
+        return match re_verify_failed {
+            true => None,
+            false => Some(VerifySkipReason::PreviouslyFailed),
+        };
+    }
+
+    if !ignore_verified_snapshots {
+        return None;
+    }
+
+    match outdated_after {
+        None => Some(VerifySkipReason::RecentlyVerified), // never re-verify if no max age
+        Some(max_age) => {
+            let now = proxmox_time::epoch_i64();
+            let days_since_last_verify = (now - last_verify.upid.starttime) / 86400;
+
+            if days_since_last_verify > max_age {
+                None
+            } else {
+                Some(VerifySkipReason::RecentlyVerified)
+            }
+        }
+    }
+}
+
+/// Counts the snapshots verified, and skipped per reason, by a verification.
+#[derive(Default)]
+pub struct VerifySkipStats {
+    checked: AtomicUsize,
+    recently_verified: AtomicUsize,
+    previously_failed: AtomicUsize,
+}
+
+impl VerifySkipStats {
+    /// Like [`verify_skip_reason`], but counts the verified and skipped snapshots.
+    pub fn filter(
+        &self,
+        ignore_verified_snapshots: bool,
+        outdated_after: Option<i64>,
+        re_verify_failed: bool,
+        manifest: &BackupManifest,
+    ) -> Option<VerifySkipReason> {
+        let reason = verify_skip_reason(
+            ignore_verified_snapshots,
+            outdated_after,
+            re_verify_failed,
+            manifest,
+        );
+        match reason {
+            None => &self.checked,
+            Some(VerifySkipReason::RecentlyVerified) => &self.recently_verified,
+            Some(VerifySkipReason::PreviouslyFailed) => &self.previously_failed,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        reason
+    }
+
+    /// Number of snapshots which passed the filter and got verified.
+    pub fn checked(&self) -> usize {
+        self.checked.load(Ordering::Relaxed)
+    }
+
+    /// Number of skipped snapshots.
+    pub fn skipped(&self) -> usize {
+        self.recently_verified.load(Ordering::Relaxed)
+            + self.previously_failed.load(Ordering::Relaxed)
+    }
+
+    /// Writes the number of skipped snapshots per reason to the task log.
+    pub fn log(&self, worker: &dyn WorkerTaskContext) {
+        let recently_verified = self.recently_verified.load(Ordering::Relaxed);
+        let previously_failed = self.previously_failed.load(Ordering::Relaxed);
+
+        if recently_verified == 0 && previously_failed == 0 {
+            return;
+        }
+
+        task_log!(
+            worker,
+            "skipped {} snapshots: {} verified recently, {} failed their last verification",
+            recently_verified + previously_failed,
+            recently_verified,
+            previously_failed,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn manifest(state: Option<&str>, verified_days_ago: i64) -> BackupManifest {
+        let snapshot = pbs_api_types::BackupDir::from((BackupType::Host, "test".to_string(), 0));
+        let mut manifest = BackupManifest::new(snapshot);
+        if let Some(state) = state {
+            let starttime = proxmox_time::epoch_i64() - verified_days_ago * 86400;
+            let upid = format!(
+                "UPID:pbs:00000001:00000002:00000000:{starttime:08X}:verify:store:root@pam:"
+            );
+            manifest.unprotected["verify_state"] = json!({ "upid": upid, "state": state });
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_verify_skip_reason() {
+        use VerifySkipReason::*;
+
+        // never verified
+        let never = manifest(None, 0);
+        assert_eq!(verify_skip_reason(true, None, false, &never), None);
+
+        let failed = manifest(Some("failed"), 0);
+        assert_eq!(verify_skip_reason(false, None, true, &failed), None);
+        assert_eq!(
+            verify_skip_reason(false, None, false, &failed),
+            Some(PreviouslyFailed)
+        );
+        assert_eq!(
+            verify_skip_reason(true, Some(30), false, &failed),
+            Some(PreviouslyFailed)
+        );
+
+        let recent = manifest(Some("ok"), 0);
+        assert_eq!(verify_skip_reason(false, None, true, &recent), None);
+        assert_eq!(
+            verify_skip_reason(true, None, true, &recent),
+            Some(RecentlyVerified)
+        );
+        assert_eq!(
+            verify_skip_reason(true, Some(1), true, &recent),
+            Some(RecentlyVerified)
+        );
+
+        let outdated = manifest(Some("ok"), 10);
+        assert_eq!(verify_skip_reason(true, Some(1), true, &outdated), None);
+        assert_eq!(
+            verify_skip_reason(true, None, true, &outdated),
+            Some(RecentlyVerified)
+        );
+
+        assert_eq!(RecentlyVerified.to_string(), "already verified");
+        assert_eq!(PreviouslyFailed.to_string(), "last verification failed");
+    }
+
+    #[test]
+    fn test_verify_filter() {
+        let never = manifest(None, 0);
+        let ok = manifest(Some("ok"), 0);
+        let outdated = manifest(Some("ok"), 10);
+        let failed = manifest(Some("failed"), 0);
+        let outdated_failed = manifest(Some("failed"), 10);
+
+        for manifest in [&never, &ok, &outdated, &failed, &outdated_failed] {
+            assert!(verify_filter(false, None, manifest));
+        }
+
+        // missing
+        assert!(verify_filter(true, None, &never));
+        assert!(verify_filter(true, Some(1), &never));
+
+        // ok
+        assert!(!verify_filter(true, None, &ok));
+        assert!(!verify_filter(true, Some(1), &ok));
+
+        // outdated
+        assert!(!verify_filter(true, None, &outdated));
+        assert!(verify_filter(true, Some(1), &outdated));
+
+        // failed, only re-verified once outdated, unlike in verify jobs
+        assert!(!verify_filter(true, None, &failed));
+        assert!(!verify_filter(true, Some(1), &failed));
+        assert!(verify_filter(true, Some(1), &outdated_failed));
+        assert_eq!(verify_skip_reason(true, None, true, &failed), None);
+    }
+
+    #[test]
+    fn test_verify_skip_stats() {
+        let stats = VerifySkipStats::default();
+
+        for manifest in [
+            manifest(None, 0),
+            manifest(Some("ok"), 0),
+            manifest(Some("ok"), 10),
+            manifest(Some("failed"), 0),
+        ] {
+            stats.filter(true, Some(1), false, &manifest);
+        }
+
+        assert_eq!(stats.checked(), 2);
+        assert_eq!(stats.skipped(), 2);
+        assert_eq!(stats.recently_verified.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.previously_failed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_push_verify_result() {
+        let result = |n: usize, state: VerifyState| VerifySnapshotResult {
+            snapshot: format!("host/test/{n}"),
+            state,
+            error: None,
+        };
+
+        let mut status = VerifyTaskStatus::default();
+        for n in 0..MAX_VERIFY_RESULTS + 5 {
+            push_verify_result(&mut status, result(n, VerifyState::Ok));
+        }
+        assert_eq!(status.results.len(), MAX_VERIFY_RESULTS);
+        assert_eq!(status.omitted_results, 5);
+
+        // failed snapshots replace the oldest successful ones
+        push_verify_result(&mut status, result(0, VerifyState::Failed));
+        assert_eq!(status.results.len(), MAX_VERIFY_RESULTS);
+        assert_eq!(status.omitted_results, 6);
+        assert_eq!(status.results[0].snapshot, "host/test/1");
+        assert!(status.results[MAX_VERIFY_RESULTS - 1].state == VerifyState::Failed);
+    }
+}
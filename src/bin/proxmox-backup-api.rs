@@ -14,6 +14,7 @@ use proxmox_rest_server::{daemon, ApiConfig, RestServer};
 
 use proxmox_backup::auth_helpers::*;
 use proxmox_backup::config;
+use proxmox_backup::server::api_error_code::{self, ApiErrorCodeService};
 use proxmox_backup::server::auth::check_pbs_auth;
 
 fn main() {
@@ -55,6 +56,7 @@ async fn run() -> Result<(), Error> {
     proxmox_backup::server::create_run_dir()?;
     proxmox_backup::server::create_state_dir()?;
     proxmox_backup::server::create_active_operations_dir()?;
+    proxmox_backup::server::create_lock_registry_dir()?;
     proxmox_backup::server::jobstate::create_jobstate_dir()?;
     proxmox_backup::tape::create_tape_status_dir()?;
     proxmox_backup::tape::create_drive_state_dir()?;
@@ -103,7 +105,8 @@ async fn run() -> Result<(), Error> {
             &mut command_sock,
         )?;
 
-    let rest_server = RestServer::new(config);
+    pbs_api_types::set_api_error_hook(api_error_code::record_api_error);
+    let rest_server = ApiErrorCodeService::new(RestServer::new(config));
     proxmox_rest_server::init_worker_tasks(
         pbs_buildcfg::PROXMOX_BACKUP_LOG_DIR_M!().into(),
         file_opts.clone(),
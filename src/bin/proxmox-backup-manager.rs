@@ -2,21 +2,25 @@ use std::collections::HashMap;
 use std::io::{self, Write};
 use std::str::FromStr;
 
-use anyhow::{format_err, Error};
+use anyhow::{bail, format_err, Error};
 use serde_json::{json, Value};
 
+use proxmox_human_byte::HumanByte;
 use proxmox_router::{cli::*, RpcEnvironment};
 use proxmox_schema::api;
 use proxmox_sys::fs::CreateOptions;
 
 use pbs_api_types::percent_encoding::percent_encode_component;
 use pbs_api_types::{
-    BackupNamespace, GroupFilter, RateLimitConfig, SyncJobConfig, DATASTORE_SCHEMA,
+    BackupNamespace, DataStoreListItem, GarbageCollectionPhase, GarbageCollectionProgress,
+    GroupFilter, GroupListItem, NamespaceListItem, RateLimitConfig, SyncJobConfig, VerifyProgress,
+    VerifyState, VerifyTaskStatus, DATASTORE_SCHEMA, ENCRYPTED_ONLY_SCHEMA,
     GROUP_FILTER_LIST_SCHEMA, IGNORE_VERIFIED_BACKUPS_SCHEMA, NS_MAX_DEPTH_SCHEMA,
-    REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, TRANSFER_LAST_SCHEMA, UPID_SCHEMA,
-    VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    REMOTE_ID_SCHEMA, REMOVE_VANISHED_BACKUPS_SCHEMA, RE_VERIFY_FAILED_SCHEMA,
+    SYNC_METADATA_SCHEMA, TRANSFER_LAST_SCHEMA, UPID_SCHEMA, VERIFICATION_OUTDATED_AFTER_SCHEMA,
+    VERIFIED_ONLY_SCHEMA, VERIFY_DOWNLOADS_SCHEMA, VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA,
 };
-use pbs_client::{display_task_log, view_task_result};
+use pbs_client::{display_task_log, view_task_result, HttpClient};
 use pbs_config::sync;
 use pbs_tools::json::required_string_param;
 
@@ -86,13 +90,62 @@ async fn garbage_collection_status(param: Value) -> Result<Value, Error> {
     let mut data = result["data"].take();
     let return_type = &api2::admin::datastore::API_METHOD_GARBAGE_COLLECTION_STATUS.returns;
 
-    let options = default_table_format_options();
+    let mut options = default_table_format_options()
+        .column(ColumnConfig::new("upid"))
+        .column(ColumnConfig::new("running"));
+
+    if data["progress"].is_object() {
+        options = options.column(ColumnConfig::new("progress").renderer(render_gc_progress));
+    }
+
+    for column in [
+        "index-file-count",
+        "index-data-bytes",
+        "disk-bytes",
+        "disk-chunks",
+        "removed-bytes",
+        "removed-chunks",
+        "pending-bytes",
+        "pending-chunks",
+        "removed-bad",
+        "still-bad",
+    ] {
+        options = options.column(ColumnConfig::new(column));
+    }
 
     format_and_print_result_full(&mut data, return_type, &output_format, &options);
 
     Ok(Value::Null)
 }
 
+fn render_gc_progress(value: &Value, _record: &Value) -> Result<String, Error> {
+    let progress: GarbageCollectionProgress = serde_json::from_value(value.clone())?;
+
+    let mut text = match progress.phase {
+        GarbageCollectionPhase::IndexScan => format!(
+            "marking {}% ({} of {} index files, {} chunks touched)",
+            progress.percentage,
+            progress.index_files_processed,
+            progress.index_files_total,
+            progress.chunks_touched,
+        ),
+        GarbageCollectionPhase::Sweep => format!(
+            "sweeping {}% ({} removed)",
+            progress.percentage,
+            HumanByte::from(progress.removed_bytes),
+        ),
+    };
+
+    if let Some(eta) = progress.eta {
+        text.push_str(&format!(
+            ", phase ETA {}",
+            pbs_tools::format::render_epoch(&eta.into(), &Value::Null)?,
+        ));
+    }
+
+    Ok(text)
+}
+
 fn garbage_collection_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert(
@@ -130,7 +183,12 @@ fn garbage_collection_commands() -> CommandLineInterface {
                 type: Boolean,
                 description: "Also list stopped tasks.",
                 optional: true,
-            }
+            },
+            stats: {
+                type: Boolean,
+                description: "Show the datastore IO of running tasks.",
+                optional: true,
+            },
         }
     }
 )]
@@ -142,10 +200,12 @@ async fn task_list(param: Value) -> Result<Value, Error> {
 
     let limit = param["limit"].as_u64().unwrap_or(50) as usize;
     let running = !param["all"].as_bool().unwrap_or(false);
+    let stats = param["stats"].as_bool().unwrap_or(false);
     let args = json!({
         "running": running,
         "start": 0,
         "limit": limit,
+        "stats": stats,
     });
     let mut result = client
         .get("api2/json/nodes/localhost/tasks", Some(args))
@@ -154,8 +214,8 @@ async fn task_list(param: Value) -> Result<Value, Error> {
     let mut data = result["data"].take();
     let return_type = &api2::node::tasks::API_METHOD_LIST_TASKS.returns;
 
-    use pbs_tools::format::{render_epoch, render_task_status};
-    let options = default_table_format_options()
+    use pbs_tools::format::{render_bytes_human_readable, render_epoch, render_task_status};
+    let mut options = default_table_format_options()
         .column(
             ColumnConfig::new("starttime")
                 .right_align(false)
@@ -169,6 +229,22 @@ async fn task_list(param: Value) -> Result<Value, Error> {
         .column(ColumnConfig::new("upid"))
         .column(ColumnConfig::new("status").renderer(render_task_status));
 
+    if stats {
+        let render_rate = |value: &Value, record: &Value| -> Result<String, Error> {
+            let text = render_bytes_human_readable(value, record)?;
+            Ok(if text.is_empty() {
+                text
+            } else {
+                format!("{text}/s")
+            })
+        };
+        options = options
+            .column(ColumnConfig::new("read_bytes").renderer(render_bytes_human_readable))
+            .column(ColumnConfig::new("write_bytes").renderer(render_bytes_human_readable))
+            .column(ColumnConfig::new("read_rate").renderer(render_rate))
+            .column(ColumnConfig::new("write_rate").renderer(render_rate));
+    }
+
     format_and_print_result_full(&mut data, return_type, &output_format, &options);
 
     Ok(Value::Null)
@@ -276,6 +352,22 @@ fn task_mgmt_cli() -> CommandLineInterface {
                 schema: TRANSFER_LAST_SCHEMA,
                 optional: true,
             },
+            "verify-downloads": {
+                schema: VERIFY_DOWNLOADS_SCHEMA,
+                optional: true,
+            },
+            "sync-metadata": {
+                schema: SYNC_METADATA_SCHEMA,
+                optional: true,
+            },
+            "encrypted-only": {
+                schema: ENCRYPTED_ONLY_SCHEMA,
+                optional: true,
+            },
+            "verified-only": {
+                schema: VERIFIED_ONLY_SCHEMA,
+                optional: true,
+            },
         }
    }
 )]
@@ -292,6 +384,10 @@ async fn pull_datastore(
     group_filter: Option<Vec<GroupFilter>>,
     limit: RateLimitConfig,
     transfer_last: Option<usize>,
+    verify_downloads: Option<bool>,
+    sync_metadata: Option<bool>,
+    encrypted_only: Option<bool>,
+    verified_only: Option<bool>,
     param: Value,
 ) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
@@ -328,6 +424,22 @@ async fn pull_datastore(
         args["transfer-last"] = json!(transfer_last)
     }
 
+    if let Some(verify_downloads) = verify_downloads {
+        args["verify-downloads"] = Value::from(verify_downloads);
+    }
+
+    if let Some(sync_metadata) = sync_metadata {
+        args["sync-metadata"] = Value::from(sync_metadata);
+    }
+
+    if let Some(encrypted_only) = encrypted_only {
+        args["encrypted-only"] = Value::from(encrypted_only);
+    }
+
+    if let Some(verified_only) = verified_only {
+        args["verified-only"] = Value::from(verified_only);
+    }
+
     let mut limit_json = json!(limit);
     let limit_map = limit_json
         .as_object_mut()
@@ -348,6 +460,14 @@ async fn pull_datastore(
             "store": {
                 schema: DATASTORE_SCHEMA,
             },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
             "ignore-verified": {
                 schema: IGNORE_VERIFIED_BACKUPS_SCHEMA,
                 optional: true,
@@ -356,6 +476,14 @@ async fn pull_datastore(
                 schema: VERIFICATION_OUTDATED_AFTER_SCHEMA,
                 optional: true,
             },
+            "re-verify-failed": {
+                schema: RE_VERIFY_FAILED_SCHEMA,
+                optional: true,
+            },
+            "skip-bad-chunk-report": {
+                schema: VERIFY_SKIP_BAD_CHUNK_REPORT_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -375,11 +503,123 @@ async fn verify(store: String, mut param: Value) -> Result<Value, Error> {
 
     let result = client.post(&path, Some(args)).await?;
 
+    if output_format == "text" {
+        if let Some(upid) = result["data"].as_str() {
+            return show_verify_progress(&client, &store, upid).await;
+        }
+    }
+
     view_task_result(&client, result, &output_format).await?;
 
     Ok(Value::Null)
 }
 
+fn render_verify_progress(progress: &VerifyProgress) -> String {
+    const WIDTH: usize = 40;
+    let filled = ((progress.percentage / 100.0) * WIDTH as f64).round() as usize;
+    let filled = filled.min(WIDTH);
+
+    format!(
+        "[{}{}] {:.2}% ({}/{} snapshots, {} chunks, {} read)",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled),
+        progress.percentage,
+        progress.snapshots_done,
+        progress.snapshots_total,
+        progress.chunks_verified,
+        HumanByte::from(progress.bytes_read),
+    )
+}
+
+/// Poll the status of a verify task and render a progress bar until it is finished.
+async fn show_verify_progress(
+    client: &HttpClient,
+    store: &str,
+    upid: &str,
+) -> Result<Value, Error> {
+    let upid_encoded = percent_encode_component(upid);
+    let status_path = format!("api2/json/admin/datastore/{store}/verify-status/{upid_encoded}");
+    let task_path = format!("api2/json/nodes/localhost/tasks/{upid_encoded}/status");
+
+    let verify_status = loop {
+        match client.get(&status_path, None).await {
+            Ok(mut result) => {
+                let status: VerifyTaskStatus = serde_json::from_value(result["data"].take())?;
+                print!("\r{}", render_verify_progress(&status.progress));
+                io::stdout().flush()?;
+                if !status.running {
+                    println!();
+                    break Some(status);
+                }
+            }
+            Err(_) => {
+                // the status is only available once the task started verifying
+                let task_status = client.get(&task_path, None).await?;
+                if task_status["data"]["status"].as_str() == Some("stopped") {
+                    break None;
+                }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    };
+
+    let verify_status = match verify_status {
+        Some(status) => status,
+        None => {
+            // no status was published, show the log instead so that errors are visible
+            display_task_log(client, upid, true, false).await?;
+            return Ok(Value::Null);
+        }
+    };
+
+    let failed: Vec<_> = verify_status
+        .results
+        .iter()
+        .filter(|result| result.state == VerifyState::Failed)
+        .collect();
+
+    println!(
+        "verified {} snapshots, {} failed",
+        verify_status.results.len() as u64 + verify_status.omitted_results,
+        failed.len()
+    );
+    if verify_status.omitted_results > 0 {
+        println!(
+            "  (results of {} snapshots omitted)",
+            verify_status.omitted_results
+        );
+    }
+    for result in failed {
+        println!(
+            "  {}: {}",
+            result.snapshot,
+            result.error.as_deref().unwrap_or("unknown error")
+        );
+    }
+
+    if let Some(report) = &verify_status.bad_chunk_report {
+        println!("{} snapshots reference bad chunks", report.len());
+        for reference in report {
+            println!("  {}: {}", reference.snapshot, reference.digests.join(", "));
+        }
+    }
+
+    // wait until the worker has written its final state
+    loop {
+        let task_status = &client.get(&task_path, None).await?["data"];
+        if task_status["status"].as_str() == Some("stopped") {
+            match task_status["exitstatus"].as_str() {
+                Some(status) if status == "OK" || status.starts_with("WARNINGS") => break,
+                Some(status) => bail!("task failed (status {status})"),
+                None => bail!("task stopped with unknown status"),
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    Ok(Value::Null)
+}
+
 #[api()]
 /// System report
 async fn report() -> Result<Value, Error> {
@@ -397,6 +637,12 @@ async fn report() -> Result<Value, Error> {
                 default: false,
                 description: "Output verbose package information. It is ignored if output-format is specified.",
             },
+            "check-updates": {
+                type: Boolean,
+                optional: true,
+                default: false,
+                description: "Compare the installed with the available versions of the server and client packages, according to the local package index.",
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -405,9 +651,13 @@ async fn report() -> Result<Value, Error> {
     }
 )]
 /// List package versions for important Proxmox Backup Server packages.
-async fn get_versions(verbose: bool, param: Value) -> Result<Value, Error> {
+async fn get_versions(verbose: bool, check_updates: bool, param: Value) -> Result<Value, Error> {
     let output_format = get_output_format(&param);
 
+    if check_updates {
+        return check_server_updates(&output_format);
+    }
+
     let packages = crate::api2::node::apt::get_versions()?;
     let mut packages = json!(if verbose {
         &packages[..]
@@ -428,6 +678,42 @@ async fn get_versions(verbose: bool, param: Value) -> Result<Value, Error> {
     Ok(Value::Null)
 }
 
+fn check_server_updates(output_format: &str) -> Result<Value, Error> {
+    let status = proxmox_backup::tools::apt::server_update_status();
+
+    if output_format != "text" {
+        format_and_print_result(&json!(status), output_format);
+        return Ok(Value::Null);
+    }
+
+    for package in &status.packages {
+        let installed = package
+            .installed_version
+            .as_deref()
+            .unwrap_or("not installed");
+        let available = package.available_version.as_deref().unwrap_or("unknown");
+        let marker = if package.update_available {
+            " (update available)"
+        } else {
+            ""
+        };
+        println!(
+            "{}: installed {installed}, available {available}{marker}",
+            package.package
+        );
+    }
+
+    match status.index_updated {
+        Some(time) => println!(
+            "package index last updated: {}",
+            proxmox_time::strftime_local("%F %T", time)?
+        ),
+        None => println!("package index was never updated, run 'apt update'"),
+    }
+
+    Ok(Value::Null)
+}
+
 async fn run() -> Result<(), Error> {
     init_cli_logger("PBS_LOG", "info");
 
@@ -441,7 +727,10 @@ async fn run() -> Result<(), Error> {
         .insert("node", node_commands())
         .insert("user", user_commands())
         .insert("openid", openid_commands())
+        .insert("pam", pam_commands())
+        .insert("pbs", pbs_commands())
         .insert("remote", remote_commands())
+        .insert("role", role_commands())
         .insert("traffic-control", traffic_control_commands())
         .insert("garbage-collection", garbage_collection_commands())
         .insert("acme", acme_mgmt_cli())
@@ -450,6 +739,10 @@ async fn run() -> Result<(), Error> {
         .insert("sync-job", sync_job_commands())
         .insert("verify-job", verify_job_commands())
         .insert("prune-job", prune_job_commands())
+        .insert(
+            "namespace-prune-options",
+            namespace_prune_options_commands(),
+        )
         .insert("task", task_mgmt_cli())
         .insert(
             "pull",
@@ -466,7 +759,8 @@ async fn run() -> Result<(), Error> {
             "verify",
             CliCommand::new(&API_METHOD_VERIFY)
                 .arg_param(&["store"])
-                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_sync_local_datastore_namespace),
         )
         .insert("report", CliCommand::new(&API_METHOD_REPORT))
         .insert("versions", CliCommand::new(&API_METHOD_GET_VERSIONS));
@@ -581,14 +875,29 @@ fn get_remote_ns(param: &HashMap<String, String>) -> Option<BackupNamespace> {
     }
 }
 
+/// Query a remote scan endpoint of the local API.
+///
+/// Shell completions go through the API instead of calling the scan functions directly, so they
+/// are answered from the scan cache of the daemon instead of connecting to the remote each time.
+fn scan_remote_via_localhost<T: serde::de::DeserializeOwned>(
+    path: &str,
+    args: Option<Value>,
+) -> Option<T> {
+    proxmox_async::runtime::block_on(async move {
+        let client = connect_to_localhost().ok()?;
+        let path = format!("api2/json/config/remote/{path}");
+        let mut result = client.get(&path, args).await.ok()?;
+        serde_json::from_value(result["data"].take()).ok()
+    })
+}
+
 // shell completion helper
 pub fn complete_remote_datastore_name(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
     let mut list = Vec::new();
 
     if let Some(remote) = get_remote(param) {
-        if let Ok(data) = proxmox_async::runtime::block_on(async move {
-            crate::api2::config::remote::scan_remote_datastores(remote).await
-        }) {
+        let path = format!("{}/scan", percent_encode_component(&remote));
+        if let Some(data) = scan_remote_via_localhost::<Vec<DataStoreListItem>>(&path, None) {
             for item in data {
                 list.push(item.store);
             }
@@ -608,14 +917,14 @@ pub fn complete_remote_datastore_namespace(
     let mut list = Vec::new();
 
     if let Some(data) = match get_remote_store(param) {
-        Some((Some(remote), remote_store)) => proxmox_async::runtime::block_on(async move {
-            crate::api2::config::remote::scan_remote_namespaces(
-                remote.clone(),
-                remote_store.clone(),
-            )
-            .await
-            .ok()
-        }),
+        Some((Some(remote), remote_store)) => scan_remote_via_localhost::<Vec<NamespaceListItem>>(
+            &format!(
+                "{}/scan/{}/namespaces",
+                percent_encode_component(&remote),
+                percent_encode_component(&remote_store),
+            ),
+            None,
+        ),
         Some((None, source_store)) => {
             let mut rpcenv = CliEnvironment::new();
             rpcenv.set_auth_id(Some(String::from("root@pam")));
@@ -666,21 +975,49 @@ pub fn complete_sync_local_datastore_namespace(
     list
 }
 
+// shell completion helper
+pub fn complete_acl_path(arg: &str, param: &HashMap<String, String>) -> Vec<String> {
+    let mut list = pbs_config::datastore::complete_acl_path(arg, param);
+
+    // offer the existing namespaces once a datastore was selected
+    let store = match arg.strip_prefix("/datastore/") {
+        Some(rest) => rest.split('/').next().unwrap_or(""),
+        None => return list,
+    };
+    if store.is_empty() {
+        return list;
+    }
+
+    let mut rpcenv = CliEnvironment::new();
+    rpcenv.set_auth_id(Some(String::from("root@pam")));
+
+    if let Ok(data) =
+        crate::api2::admin::namespace::list_namespaces(store.to_string(), None, None, &mut rpcenv)
+    {
+        for item in data {
+            if !item.ns.is_root() {
+                list.push(format!("/datastore/{store}/{}", item.ns.name()));
+            }
+        }
+    }
+
+    list
+}
+
 // shell completion helper
 pub fn complete_remote_datastore_group(_arg: &str, param: &HashMap<String, String>) -> Vec<String> {
     let mut list = Vec::new();
 
     let ns = get_remote_ns(param);
     if let Some(data) = match get_remote_store(param) {
-        Some((Some(remote), remote_store)) => proxmox_async::runtime::block_on(async move {
-            crate::api2::config::remote::scan_remote_groups(
-                remote.clone(),
-                remote_store.clone(),
-                ns,
-            )
-            .await
-            .ok()
-        }),
+        Some((Some(remote), remote_store)) => scan_remote_via_localhost::<Vec<GroupListItem>>(
+            &format!(
+                "{}/scan/{}/groups",
+                percent_encode_component(&remote),
+                percent_encode_component(&remote_store),
+            ),
+            ns.map(|ns| json!({ "namespace": ns })),
+        ),
         Some((None, source_store)) => {
             let mut rpcenv = CliEnvironment::new();
             rpcenv.set_auth_id(Some(String::from("root@pam")));
@@ -1,3 +1,4 @@
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -20,7 +21,7 @@ use proxmox_sys::linux::procfs::{Loadavg, ProcFsMemInfo, ProcFsNetDev, ProcFsSta
 use proxmox_sys::logrotate::LogRotate;
 use proxmox_sys::{task_log, task_warn};
 
-use pbs_datastore::DataStore;
+use pbs_datastore::{ChunkTrafficStats, DataStore};
 
 use proxmox_rest_server::{
     cleanup_old_tasks, cookie_from_header, rotate_task_log_archive, ApiConfig, Redirector,
@@ -32,7 +33,9 @@ use proxmox_backup::rrd_cache::{
 };
 use proxmox_backup::{
     server::{
+        api_error_code::{self, ApiErrorCodeService},
         auth::check_pbs_auth,
+        job_concurrency,
         jobstate::{self, Job},
     },
     tools::disks::BlockDevStat,
@@ -43,7 +46,7 @@ use pbs_buildcfg::configdir;
 use proxmox_time::CalendarEvent;
 
 use pbs_api_types::{
-    Authid, DataStoreConfig, Operation, PruneJobConfig, SyncJobConfig, TapeBackupJobConfig,
+    Authid, DataStoreConfig, Operation, PruneJobConfig, Remote, SyncJobConfig, TapeBackupJobConfig,
     VerificationJobConfig,
 };
 
@@ -53,6 +56,7 @@ use proxmox_backup::auth_helpers::*;
 use proxmox_backup::server;
 use proxmox_backup::tools::{
     disks::{zfs_dataset_stats, DiskManage},
+    fs::add_extra_chunk_dir_usage,
     PROXMOX_BACKUP_TCP_KEEPALIVE_TIME,
 };
 
@@ -252,7 +256,8 @@ async fn run() -> Result<(), Error> {
             &mut command_sock,
         )?;
 
-    let rest_server = RestServer::new(config);
+    pbs_api_types::set_api_error_hook(api_error_code::record_api_error);
+    let rest_server = ApiErrorCodeService::new(RestServer::new(config));
     let redirector = Redirector::new();
     proxmox_rest_server::init_worker_tasks(
         pbs_buildcfg::PROXMOX_BACKUP_LOG_DIR_M!().into(),
@@ -447,13 +452,32 @@ async fn run_task_scheduler() {
     }
 }
 
+/// Returns false if the job has to wait for a free slot in its concurrency group.
+fn acquire_concurrency_slot(job: &mut Job, group: &str) -> bool {
+    match job_concurrency::try_acquire_slot(job, group) {
+        Ok(acquired) => acquired,
+        Err(err) => {
+            eprintln!(
+                "unable to check concurrency group '{group}' of {} {} - {err}",
+                job.jobtype(),
+                job.jobname(),
+            );
+            true // do not block the job
+        }
+    }
+}
+
+// Note: the order here also decides which job runs first if several jobs of the same
+// concurrency group are due.
 async fn schedule_tasks() -> Result<(), Error> {
     schedule_datastore_garbage_collection().await;
     schedule_datastore_prune_jobs().await;
     schedule_datastore_sync_jobs().await;
     schedule_datastore_verify_jobs().await;
     schedule_tape_backup_jobs().await;
+    schedule_remote_checks().await;
     schedule_task_log_rotate().await;
+    schedule_token_purge().await;
 
     Ok(())
 }
@@ -529,11 +553,15 @@ async fn schedule_datastore_garbage_collection() {
             continue;
         }
 
-        let job = match Job::new(worker_type, &store) {
+        let mut job = match Job::new(worker_type, &store) {
             Ok(job) => job,
             Err(_) => continue, // could not get lock
         };
 
+        if !acquire_concurrency_slot(&mut job, &store) {
+            continue;
+        }
+
         let datastore = match DataStore::lookup_datastore(&store, Some(Operation::Write)) {
             Ok(datastore) => datastore,
             Err(err) => {
@@ -564,6 +592,7 @@ async fn schedule_datastore_prune_jobs() {
         }
         Ok((config, _digest)) => config,
     };
+    let job_ids: HashSet<String> = config.sections.keys().cloned().collect();
     for (job_id, (_, job_config)) in config.sections {
         let job_config: PruneJobConfig = match serde_json::from_value(job_config) {
             Ok(c) => c,
@@ -584,10 +613,13 @@ async fn schedule_datastore_prune_jobs() {
         let worker_type = "prunejob";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &job_config.schedule, &job_id) {
-            let job = match Job::new(worker_type, &job_id) {
+            let mut job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !acquire_concurrency_slot(&mut job, job_config.concurrency_group()) {
+                continue;
+            }
             if let Err(err) = do_prune_job(
                 job,
                 job_config.options,
@@ -599,6 +631,60 @@ async fn schedule_datastore_prune_jobs() {
             }
         };
     }
+
+    schedule_legacy_datastore_prune(&job_ids);
+}
+
+// Datastores can still carry the deprecated prune schedule if their config was not converted to
+// prune jobs yet, keep running those like a prune job on the whole datastore.
+fn schedule_legacy_datastore_prune(job_ids: &HashSet<String>) {
+    let config = match pbs_config::datastore::config() {
+        Err(err) => {
+            eprintln!("unable to read datastore config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+
+    for (store, (_, store_config)) in config.sections {
+        let store_config: DataStoreConfig = match serde_json::from_value(store_config) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("datastore config from_value failed - {err}");
+                continue;
+            }
+        };
+
+        let job_config = match server::legacy_prune_job(&store_config) {
+            Some(job_config) => job_config,
+            None => continue,
+        };
+
+        if job_ids.contains(&job_config.id) {
+            continue; // already converted
+        }
+
+        let worker_type = "prunejob";
+        let auth_id = Authid::root_auth_id().clone();
+        if check_schedule(worker_type, &job_config.schedule, &job_config.id) {
+            let mut job = match Job::new(worker_type, &job_config.id) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+            if !acquire_concurrency_slot(&mut job, job_config.concurrency_group()) {
+                continue;
+            }
+            if let Err(err) = do_prune_job(
+                job,
+                job_config.options,
+                job_config.store,
+                &auth_id,
+                Some(job_config.schedule),
+            ) {
+                eprintln!("unable to start deprecated prune schedule of datastore {store} - {err}");
+            }
+        };
+    }
 }
 
 async fn schedule_datastore_sync_jobs() {
@@ -626,10 +712,13 @@ async fn schedule_datastore_sync_jobs() {
 
         let worker_type = "syncjob";
         if check_schedule(worker_type, &event_str, &job_id) {
-            let job = match Job::new(worker_type, &job_id) {
+            let mut job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !acquire_concurrency_slot(&mut job, job_config.concurrency_group()) {
+                continue;
+            }
 
             let auth_id = Authid::root_auth_id().clone();
             if let Err(err) = do_sync_job(job, job_config, &auth_id, Some(event_str), false) {
@@ -663,10 +752,13 @@ async fn schedule_datastore_verify_jobs() {
         let worker_type = "verificationjob";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &event_str, &job_id) {
-            let job = match Job::new(worker_type, &job_id) {
+            let mut job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !acquire_concurrency_slot(&mut job, job_config.concurrency_group()) {
+                continue;
+            }
             if let Err(err) = do_verification_job(job, job_config, &auth_id, Some(event_str), false)
             {
                 eprintln!("unable to start datastore verification job {job_id} - {err}");
@@ -699,10 +791,13 @@ async fn schedule_tape_backup_jobs() {
         let worker_type = "tape-backup-job";
         let auth_id = Authid::root_auth_id().clone();
         if check_schedule(worker_type, &event_str, &job_id) {
-            let job = match Job::new(worker_type, &job_id) {
+            let mut job = match Job::new(worker_type, &job_id) {
                 Ok(job) => job,
                 Err(_) => continue, // could not get lock
             };
+            if !acquire_concurrency_slot(&mut job, job_config.concurrency_group()) {
+                continue;
+            }
             if let Err(err) =
                 do_tape_backup_job(job, job_config.setup, &auth_id, Some(event_str), false)
             {
@@ -712,6 +807,41 @@ async fn schedule_tape_backup_jobs() {
     }
 }
 
+async fn schedule_remote_checks() {
+    let config = match pbs_config::remote::config() {
+        Err(err) => {
+            eprintln!("unable to read remote config - {err}");
+            return;
+        }
+        Ok((config, _digest)) => config,
+    };
+    for (name, (_, remote)) in config.sections {
+        let remote: Remote = match serde_json::from_value(remote) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("remote config from_value failed - {err}");
+                continue;
+            }
+        };
+        let event_str = match remote.config.check_schedule {
+            Some(ref event_str) => event_str.clone(),
+            None => continue,
+        };
+
+        let worker_type = "remote-check";
+        let auth_id = Authid::root_auth_id().clone();
+        if check_schedule(worker_type, &event_str, &name) {
+            let job = match Job::new(worker_type, &name) {
+                Ok(job) => job,
+                Err(_) => continue, // could not get lock
+            };
+            if let Err(err) = server::do_remote_check_job(job, remote, &auth_id, Some(event_str)) {
+                eprintln!("unable to start check of remote {name} - {err}");
+            }
+        };
+    }
+}
+
 async fn schedule_task_log_rotate() {
     let worker_type = "logrotate";
     let job_id = "access-log_and_task-archive";
@@ -791,7 +921,7 @@ async fn schedule_task_log_rotate() {
                     pbs_buildcfg::API_AUTH_LOG_FN,
                     true,
                     Some(max_files),
-                    Some(options),
+                    Some(options.clone()),
                 )?;
 
                 if logrotate.rotate(max_size)? {
@@ -802,6 +932,20 @@ async fn schedule_task_log_rotate() {
                     task_log!(worker, "API authentication log was not rotated");
                 }
 
+                // the audit log file is opened for every entry, no need to re-open it
+                let mut logrotate = LogRotate::new(
+                    pbs_buildcfg::API_AUDIT_LOG_FN,
+                    true,
+                    Some(max_files),
+                    Some(options),
+                )?;
+
+                if logrotate.rotate(max_size)? {
+                    task_log!(worker, "API audit log was rotated");
+                } else {
+                    task_log!(worker, "API audit log was not rotated");
+                }
+
                 if has_rotated {
                     task_log!(worker, "cleaning up old task logs");
                     if let Err(err) = cleanup_old_tasks(&worker, true) {
@@ -825,6 +969,83 @@ async fn schedule_task_log_rotate() {
     }
 }
 
+async fn schedule_token_purge() {
+    let worker_type = "token-purge";
+    let job_id = "expired-api-tokens";
+
+    let max_days = match proxmox_backup::config::node::config() {
+        Ok((config, _digest)) => match config.token_purge_days {
+            Some(days) => days,
+            None => return, // purging is disabled
+        },
+        Err(err) => {
+            eprintln!("unable to read node config for token purge - {err}");
+            return;
+        }
+    };
+
+    // daily, shortly after the log rotation
+    let schedule = "00:15";
+
+    if !check_schedule(worker_type, schedule, job_id) {
+        return;
+    }
+
+    let mut job = match Job::new(worker_type, job_id) {
+        Ok(job) => job,
+        Err(_) => return, // could not get lock
+    };
+
+    if let Err(err) = WorkerTask::new_thread(
+        worker_type,
+        None,
+        Authid::root_auth_id().to_string(),
+        false,
+        move |worker| {
+            job.start(&worker.upid().to_string())?;
+            task_log!(
+                worker,
+                "removing API tokens which expired more than {max_days} day(s) ago"
+            );
+
+            let result = try_block!({
+                let now = proxmox_time::epoch_i64();
+                let max_age = (max_days as i64).saturating_mul(24 * 3600);
+                let removed = pbs_config::user::purge_expired_tokens(now, max_age)?;
+
+                for token in removed.iter() {
+                    task_log!(worker, "removed API token '{}'", token.tokenid);
+                    server::audit_log::audit_config_change(
+                        Authid::root_auth_id(),
+                        &format!(
+                            "/access/users/{}/token/{}",
+                            token.tokenid.user(),
+                            token.tokenid.tokenname().unwrap().as_str(),
+                        ),
+                        Some(serde_json::to_value(token)?),
+                        None,
+                        pbs_config::user::USER_CFG_FILENAME,
+                        None,
+                    );
+                }
+                task_log!(worker, "removed {} expired API token(s)", removed.len());
+
+                Ok(())
+            });
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {worker_type}: {err}");
+            }
+
+            result
+        },
+    ) {
+        eprintln!("unable to start expired API token purge: {err}");
+    }
+}
+
 async fn command_reopen_access_logfiles() -> Result<(), Error> {
     // only care about the most recent daemon instance for each, proxy & api, as other older ones
     // should not respond to new requests anyway, but only finish their current one and then exit.
@@ -1133,7 +1354,18 @@ fn collect_disk_stats_sync() -> (DiskStat, Vec<DiskStat>) {
                     continue;
                 }
                 let path = std::path::Path::new(&config.path);
-                datastores.push(gather_disk_stats(disk_manager.clone(), path, &config.name));
+                let mut stat = gather_disk_stats(disk_manager.clone(), path, &config.name);
+                if let (Some(usage), Some(chunk_dirs)) = (&mut stat.usage, &config.extra_chunk_dir)
+                {
+                    let chunk_dirs: Vec<PathBuf> = chunk_dirs.iter().map(PathBuf::from).collect();
+                    if let Err(err) = add_extra_chunk_dir_usage(usage, path, &chunk_dirs) {
+                        eprintln!(
+                            "read fs info of chunk dirs of {:?} failed - {err}",
+                            config.name
+                        );
+                    }
+                }
+                datastores.push(stat);
             }
         }
         Err(err) => {
@@ -1178,12 +1410,53 @@ fn rrd_update_host_stats_sync(host: &HostStats, hostdisk: &DiskStat, datastores:
 
     rrd_update_disk_stat(hostdisk, "host");
 
+    let chunk_traffic: HashMap<String, ChunkTrafficStats> =
+        DataStore::chunk_traffic_stats().into_iter().collect();
+
     for stat in datastores {
         let rrd_prefix = format!("datastore/{}", stat.name);
         rrd_update_disk_stat(stat, &rrd_prefix);
+
+        // counters restart from zero with the daemon, derive handles that like a counter reset
+        let traffic = chunk_traffic.get(&stat.name).copied().unwrap_or_default();
+        rrd_update_chunk_traffic(&traffic, &rrd_prefix);
+
+        rrd_update_gc_status(&stat.name, &rrd_prefix);
     }
 }
 
+fn rrd_update_gc_status(store: &str, rrd_prefix: &str) {
+    let status = match DataStore::lookup_datastore(store, Some(Operation::Lookup)) {
+        Ok(datastore) => datastore.last_gc_status(),
+        Err(_) => return,
+    };
+
+    if status.upid.is_none() {
+        return; // no garbage collection run yet
+    }
+
+    let rrd_key = format!("{}/gc_disk_bytes", rrd_prefix);
+    rrd_update_gauge(&rrd_key, status.disk_bytes as f64);
+    let rrd_key = format!("{}/gc_disk_chunks", rrd_prefix);
+    rrd_update_gauge(&rrd_key, status.disk_chunks as f64);
+    let rrd_key = format!("{}/gc_removed_bytes", rrd_prefix);
+    rrd_update_gauge(&rrd_key, status.removed_bytes as f64);
+    let rrd_key = format!("{}/gc_pending_bytes", rrd_prefix);
+    rrd_update_gauge(&rrd_key, status.pending_bytes as f64);
+}
+
+fn rrd_update_chunk_traffic(traffic: &ChunkTrafficStats, rrd_prefix: &str) {
+    let rrd_key = format!("{}/chunk_read_bytes", rrd_prefix);
+    rrd_update_derive(&rrd_key, traffic.read_bytes as f64);
+    let rrd_key = format!("{}/chunk_read_count", rrd_prefix);
+    rrd_update_derive(&rrd_key, traffic.read_chunks as f64);
+
+    let rrd_key = format!("{}/chunk_write_bytes", rrd_prefix);
+    rrd_update_derive(&rrd_key, traffic.write_bytes as f64);
+    let rrd_key = format!("{}/chunk_write_count", rrd_prefix);
+    rrd_update_derive(&rrd_key, traffic.write_chunks as f64);
+}
+
 fn rrd_update_disk_stat(disk: &DiskStat, rrd_prefix: &str) {
     if let Some(status) = &disk.usage {
         let rrd_key = format!("{}/total", rrd_prefix);
@@ -12,16 +12,17 @@ use proxmox_section_config::SectionConfigData;
 use proxmox_time::strftime_local;
 
 use pbs_client::view_task_result;
-use pbs_tools::format::{render_bytes_human_readable, render_epoch};
+use pbs_tools::format::{render_bytes_human_readable, render_duration, render_epoch};
 
 use pbs_config::datastore::complete_datastore_name;
 use pbs_config::drive::complete_drive_name;
 use pbs_config::media_pool::complete_pool_name;
 
 use pbs_api_types::{
-    Authid, BackupNamespace, GroupListItem, Userid, DATASTORE_MAP_LIST_SCHEMA, DATASTORE_SCHEMA,
-    DRIVE_NAME_SCHEMA, GROUP_FILTER_LIST_SCHEMA, MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA,
-    NS_MAX_DEPTH_SCHEMA, TAPE_RESTORE_NAMESPACE_SCHEMA, TAPE_RESTORE_SNAPSHOT_SCHEMA,
+    Authid, BackupNamespace, GroupListItem, Userid, BACKUP_ARCHIVE_NAME_SCHEMA,
+    DATASTORE_MAP_LIST_SCHEMA, DATASTORE_SCHEMA, DRIVE_NAME_SCHEMA, GROUP_FILTER_LIST_SCHEMA,
+    MEDIA_LABEL_SCHEMA, MEDIA_POOL_NAME_SCHEMA, NS_MAX_DEPTH_SCHEMA, TAPE_EXTRA_DRIVE_LIST_SCHEMA,
+    TAPE_RESTORE_NAMESPACE_SCHEMA, TAPE_RESTORE_SNAPSHOT_SCHEMA,
 };
 use pbs_tape::{BlockReadError, MediaContentHeader, PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0};
 
@@ -754,6 +755,9 @@ async fn status(mut param: Value) -> Result<(), Error> {
     };
 
     let options = default_table_format_options()
+        .column(ColumnConfig::new("drive-activity"))
+        .column(ColumnConfig::new("lock-owner"))
+        .column(ColumnConfig::new("lock-duration").renderer(render_duration))
         .column(ColumnConfig::new("blocksize"))
         .column(ColumnConfig::new("density"))
         .column(ColumnConfig::new("compression"))
@@ -826,6 +830,10 @@ async fn clean_drive(mut param: Value) -> Result<(), Error> {
                 schema: DRIVE_NAME_SCHEMA,
                 optional: true,
             },
+            "extra-drives": {
+                schema: TAPE_EXTRA_DRIVE_LIST_SCHEMA,
+                optional: true,
+            },
             "eject-media": {
                 description: "Eject media upon job completion.",
                 type: bool,
@@ -845,7 +853,7 @@ async fn clean_drive(mut param: Value) -> Result<(), Error> {
                 optional: true,
                 type: Userid,
             },
-            groups: {
+            "group-filter": {
                 schema: GROUP_FILTER_LIST_SCHEMA,
                 optional: true,
             },
@@ -921,6 +929,14 @@ async fn backup(mut param: Value) -> Result<(), Error> {
                     schema: TAPE_RESTORE_SNAPSHOT_SCHEMA,
                 },
             },
+            "archives": {
+                description: "Only restore these archives of the given snapshots.",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: BACKUP_ARCHIVE_NAME_SCHEMA,
+                },
+            },
             owner: {
                 type: Authid,
                 optional: true,
@@ -1005,9 +1021,10 @@ fn main() {
             CliCommand::new(&API_METHOD_BACKUP)
                 .arg_param(&["store", "pool"])
                 .completion_cb("drive", complete_drive_name)
+                .completion_cb("extra-drives", complete_drive_name)
                 .completion_cb("store", complete_datastore_name)
                 .completion_cb("pool", complete_pool_name)
-                .completion_cb("groups", complete_datastore_group_filter),
+                .completion_cb("group-filter", complete_datastore_group_filter),
         )
         .insert(
             "restore",
@@ -1083,6 +1100,7 @@ fn main() {
         .insert("drive", drive_commands())
         .insert("pool", pool_commands())
         .insert("media", media_commands())
+        .insert("media-set", media_set_commands())
         .insert("key", encryption_key_commands())
         .insert("backup-job", backup_job_commands())
         .insert(
@@ -4,6 +4,8 @@ use serde_json::Value;
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
+use pbs_api_types::ACL_PATH_SCHEMA;
+
 use proxmox_backup::api2;
 
 #[api(
@@ -53,15 +55,68 @@ fn list_acls(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Err
     Ok(Value::Null)
 }
 
+#[api(
+    input: {
+        properties: {
+            path: {
+                schema: ACL_PATH_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the effective permissions of all users and API tokens on a path.
+fn effective_permissions(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::access::acl::API_METHOD_EFFECTIVE_PERMISSIONS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    fn render_origin(value: &Value, record: &Value) -> Result<String, Error> {
+        if record["superuser"].as_bool().unwrap_or(false) {
+            return Ok(String::from("superuser"));
+        }
+        let origin = value.as_str().unwrap_or_default();
+        if record["inherited"].as_bool().unwrap_or(false) {
+            Ok(format!("{origin} (inherited)"))
+        } else {
+            Ok(origin.to_string())
+        }
+    }
+
+    let mut options = default_table_format_options().column(ColumnConfig::new("auth-id"));
+    for column in ["backup", "read", "modify", "prune", "verify", "audit"] {
+        options = options.column(ColumnConfig::new(column));
+    }
+    let options = options.column(ColumnConfig::new("origin").renderer(render_origin));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
 pub fn acl_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_ACLS))
+        .insert(
+            "effective",
+            CliCommand::new(&API_METHOD_EFFECTIVE_PERMISSIONS)
+                .arg_param(&["path"])
+                .completion_cb("path", crate::complete_acl_path),
+        )
         .insert(
             "update",
             CliCommand::new(&api2::access::acl::API_METHOD_UPDATE_ACL)
                 .arg_param(&["path", "role"])
+                .completion_cb("role", pbs_config::roles::complete_role_id)
                 .completion_cb("auth-id", pbs_config::user::complete_authid)
-                .completion_cb("path", pbs_config::datastore::complete_acl_path),
+                .completion_cb("path", crate::complete_acl_path),
         );
 
     cmd_def.into()
@@ -1,11 +1,20 @@
-use anyhow::Error;
+use anyhow::{bail, format_err, Error};
 use serde_json::Value;
 
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
-use proxmox_schema::api;
+use proxmox_schema::{api, ApiStringFormat, ApiType, ArraySchema, ReturnType, Schema};
 
-use pbs_api_types::{DataStoreConfig, DATASTORE_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA};
+use pbs_api_types::{
+    Authid, BackupDir, BackupGroup, BackupNamespace, DataStoreConfig, DataStoreConfigUpdater,
+    DataStoreHealth, DatastoreBackendConfig, DatastoreBackendType, MaintenanceMode,
+    OwnerReportResult, OwnerUsage, UnhealthyGroup, DATASTORE_SCHEMA, DIR_NAME_SCHEMA,
+    EXTRA_CHUNK_DIR_LIST_SCHEMA, GROUP_FILTER_LIST_SCHEMA, GROUP_FILTER_SCHEMA,
+    GROUP_HEALTH_MAX_AGE_DEFAULT, NS_MAX_DEPTH_SCHEMA, PROXMOX_CONFIG_DIGEST_SCHEMA,
+    REUSE_DATASTORE_SCHEMA,
+};
 use pbs_client::view_task_result;
+use pbs_tools::format::{render_bytes_human_readable, render_epoch};
+use pbs_tools::json::required_string_param;
 
 use proxmox_backup::api2;
 use proxmox_backup::client_helpers::connect_to_localhost;
@@ -77,6 +86,485 @@ fn show_datastore(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value
                 type: DataStoreConfig,
                 flatten: true,
             },
+            "reuse-existing": {
+                schema: REUSE_DATASTORE_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Create new datastore config.
+async fn create_datastore(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let client = connect_to_localhost()?;
+
+    let result = client
+        .post("api2/json/config/datastore", Some(param))
+        .await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "keep-job-configs": {
+                description: "If enabled, the job configurations related to this datastore will be kept.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "destroy-data": {
+                description: "Delete the datastore's underlying contents. Requires the datastore \
+                    to be in offline maintenance mode.",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+            "yes-i-really-mean-it": {
+                description: "Confirm that all data should be deleted (required with 'destroy-data').",
+                optional: true,
+                type: bool,
+                default: false,
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+)]
+/// Remove a datastore configuration.
+async fn delete_datastore(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let confirmed = param
+        .as_object_mut()
+        .and_then(|param| param.remove("yes-i-really-mean-it"))
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false);
+
+    if param["destroy-data"].as_bool().unwrap_or(false) && !confirmed {
+        bail!(
+            "this irrevocably deletes all backups of datastore '{}', \
+            pass '--yes-i-really-mean-it' to confirm",
+            param["name"].as_str().unwrap_or_default(),
+        );
+    }
+
+    param["node"] = "localhost".into();
+
+    let info = &api2::config::datastore::API_METHOD_DELETE_DATASTORE;
+    let result = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    crate::wait_for_local_worker(result.as_str().unwrap()).await?;
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            name: {
+                schema: DATASTORE_SCHEMA,
+            },
+            update: {
+                type: DataStoreConfigUpdater,
+                flatten: true,
+            },
+            delete: {
+                description: "List of properties to delete.",
+                type: Array,
+                optional: true,
+                items: {
+                    type: api2::config::datastore::DeletableProperty,
+                }
+            },
+            digest: {
+                optional: true,
+                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
+            },
+        },
+    },
+)]
+/// Update datastore config.
+async fn update_datastore(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let info = &api2::config::datastore::API_METHOD_UPDATE_DATASTORE;
+    let result = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    // new chunk directories are initialized by a worker
+    if let Some(upid) = result.as_str() {
+        crate::wait_for_local_worker(upid).await?;
+    }
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "target-ns": {
+                type: BackupNamespace,
+                optional: true,
+            },
+            group: {
+                type: BackupGroup,
+                flatten: true,
+            },
+        },
+    },
+)]
+/// Move a backup group including all snapshots to another namespace.
+async fn move_group(mut param: Value) -> Result<Value, Error> {
+    let store = required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/move-group");
+    client.post(&path, Some(param)).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            filter: {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "new-owner": {
+                type: Authid,
+            },
+            "expected-owner": {
+                type: Authid,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Change the owner of all backup groups matching the given filters.
+async fn change_owner_bulk(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    // '--filter' is an alias of '--group-filter'
+    if let Some(Value::Array(filters)) = param.as_object_mut().unwrap().remove("filter") {
+        let mut group_filters = match param["group-filter"].take() {
+            Value::Array(group_filters) => group_filters,
+            _ => Vec::new(),
+        };
+        group_filters.extend(filters);
+        param["group-filter"] = group_filters.into();
+    }
+
+    let info = &api2::admin::datastore::API_METHOD_SET_BACKUP_OWNER_BULK;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("owner"))
+        .column(ColumnConfig::new("success"))
+        .column(ColumnConfig::new("error"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "group-filter": {
+                schema: GROUP_FILTER_LIST_SCHEMA,
+                optional: true,
+            },
+            "newer-than": {
+                description: "Only include snapshots with a backup time at or after this epoch.",
+                type: Integer,
+                optional: true,
+            },
+            "older-than": {
+                description: "Only include snapshots with a backup time before this epoch.",
+                type: Integer,
+                optional: true,
+            },
+            protected: {
+                description: "Enable/disable protection.",
+                type: bool,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// En- or disable protection for all snapshots matching the given filters.
+async fn protect_bulk(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::admin::datastore::API_METHOD_SET_PROTECTION_BULK;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            repair: {
+                description: "Repair the inconsistencies which can be fixed safely.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "default-owner": {
+                type: Authid,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Check the directory structure of a datastore for orphaned or incomplete groups, snapshots
+/// and namespaces.
+async fn scrub_meta(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::admin::datastore::API_METHOD_SCRUB_META;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("issue"))
+        .column(ColumnConfig::new("repaired"))
+        .column(ColumnConfig::new("note"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the quota and the recorded logical usage of a namespace
+fn show_namespace_quota(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::namespace::API_METHOD_GET_QUOTA;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("quota").renderer(render_bytes_human_readable))
+        .column(ColumnConfig::new("usage").renderer(render_bytes_human_readable))
+        .column(ColumnConfig::new("usage-time").renderer(render_epoch));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the verify-new setting of a namespace and the effective setting for new snapshots
+fn show_namespace_verify_new(
+    param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::namespace::API_METHOD_GET_VERIFY_NEW;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("verify-new"))
+        .column(ColumnConfig::new("effective"))
+        .column(ColumnConfig::new("source"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+            },
+            "delete-groups": {
+                description: "Destroy all groups in the hierarchy.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "ignore-protected": {
+                description: "Skip protected snapshots instead of refusing the deletion.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Delete a namespace, and with 'delete-groups' all groups in its hierarchy.
+fn delete_namespace(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::admin::namespace::API_METHOD_DELETE_NAMESPACE;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+const OWNER_USAGE_LIST_SCHEMA: Schema =
+    ArraySchema::new("Usage per backup owner.", &OwnerUsage::API_SCHEMA).schema();
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
@@ -84,61 +572,470 @@ fn show_datastore(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value
         },
     },
 )]
-/// Create new datastore config.
-async fn create_datastore(mut param: Value) -> Result<Value, Error> {
+/// Show the groups, snapshots and logical size per backup owner, largest first.
+async fn owner_report(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
     let output_format = extract_output_format(&mut param);
 
-    let client = connect_to_localhost()?;
+    let info = &api2::admin::datastore::API_METHOD_CREATE_OWNER_REPORT;
+    let data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param.clone(), info, rpcenv)?,
+        _ => unreachable!(),
+    };
+    let mut result: OwnerReportResult = serde_json::from_value(data)?;
 
-    let result = client
-        .post("api2/json/config/datastore", Some(param))
-        .await?;
+    if let Some(upid) = result.upid {
+        crate::wait_for_local_worker(&upid).await?;
 
-    view_task_result(&client, result, &output_format).await?;
+        let info = &api2::admin::datastore::API_METHOD_GET_OWNER_REPORT;
+        let data = match info.handler {
+            ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+            _ => unreachable!(),
+        };
+        result = serde_json::from_value(data)?;
+    }
 
-    Ok(Value::Null)
+    let report = result
+        .report
+        .ok_or_else(|| format_err!("owner report task did not store a report"))?;
+    let mut data = serde_json::to_value(report.owners)?;
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("owner"))
+        .column(ColumnConfig::new("groups"))
+        .column(ColumnConfig::new("snapshots"))
+        .column(ColumnConfig::new("size").renderer(render_bytes_human_readable));
+
+    let return_type = ReturnType::new(false, &OWNER_USAGE_LIST_SCHEMA);
+    format_and_print_result_full(&mut data, &return_type, &output_format, &options);
+
+    Ok(())
 }
 
+const UNHEALTHY_GROUP_LIST_SCHEMA: Schema =
+    ArraySchema::new("Unhealthy backup groups.", &UnhealthyGroup::API_SCHEMA).schema();
+
+/// Exit code of the 'health' command if there are unhealthy groups, 'critical' for Nagios.
+const UNHEALTHY_EXIT_CODE: i32 = 2;
+
 #[api(
-    protected: true,
     input: {
         properties: {
-            name: {
+            store: {
                 schema: DATASTORE_SCHEMA,
             },
-            "keep-job-configs": {
-                description: "If enabled, the job configurations related to this datastore will be kept.",
-                type: bool,
+            ns: {
+                type: BackupNamespace,
                 optional: true,
-                default: false,
             },
-            "destroy-data": {
-                description: "Delete the datastore's underlying contents",
+            "max-depth": {
+                schema: NS_MAX_DEPTH_SCHEMA,
+                optional: true,
+            },
+            "max-age": {
+                description: "Maximum age of the newest finished snapshot of a group, in seconds.",
+                type: Integer,
+                minimum: 0,
+                optional: true,
+                default: GROUP_HEALTH_MAX_AGE_DEFAULT as isize,
+            },
+            ignore: {
+                description: "Groups to skip, for example of decommissioned guests.",
+                type: Array,
+                optional: true,
+                items: {
+                    schema: GROUP_FILTER_SCHEMA,
+                },
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Show the groups without a recent, successful backup.
+///
+/// Exits with code 2 if there are any, for use in monitoring checks.
+fn health(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::admin::datastore::API_METHOD_GET_HEALTH;
+    let data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+    let health: DataStoreHealth = serde_json::from_value(data.clone())?;
+
+    if output_format == "text" {
+        println!(
+            "{} groups checked ({} ignored): {} outdated, {} failed verification",
+            health.checked, health.ignored, health.outdated, health.verify_failed,
+        );
+        if !health.groups.is_empty() {
+            let mut data = serde_json::to_value(&health.groups)?;
+            let options = default_table_format_options()
+                .column(ColumnConfig::new("ns"))
+                .column(ColumnConfig::new("backup-type"))
+                .column(ColumnConfig::new("backup-id"))
+                .column(ColumnConfig::new("last-backup").renderer(render_epoch))
+                .column(ColumnConfig::new("problem"));
+            let return_type = ReturnType::new(false, &UNHEALTHY_GROUP_LIST_SCHEMA);
+            format_and_print_result_full(&mut data, &return_type, &output_format, &options);
+        }
+    } else {
+        format_and_print_result(&data, &output_format);
+    }
+
+    if !health.groups.is_empty() {
+        std::process::exit(UNHEALTHY_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            path: {
+                schema: DIR_NAME_SCHEMA,
+            },
+            "extra-chunk-dir": {
+                schema: EXTRA_CHUNK_DIR_LIST_SCHEMA,
                 optional: true,
+            },
+            deep: {
+                description: "Also read all chunks and verify their checksums.",
                 type: bool,
+                optional: true,
                 default: false,
             },
-            digest: {
+            report: {
+                description: "Write the report with all problems found as JSON to this file.",
+                type: String,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
                 optional: true,
-                schema: PROXMOX_CONFIG_DIGEST_SCHEMA,
             },
         },
     },
 )]
-/// Remove a datastore configuration.
-async fn delete_datastore(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
-    param["node"] = "localhost".into();
+/// Check the index files of the datastore at 'path' and the chunks they reference, directly on
+/// disk and without the API. Fails if any problems were found.
+fn fsck(param: Value) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let path = required_string_param(&param, "path")?;
+    let deep = param["deep"].as_bool().unwrap_or(false);
+    let extra_chunk_dirs: Vec<std::path::PathBuf> = param["extra-chunk-dir"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|dir| dir.as_str().map(std::path::PathBuf::from))
+        .collect();
 
-    let info = &api2::config::datastore::API_METHOD_DELETE_DATASTORE;
-    let result = match info.handler {
+    check_fsck_backend(std::path::Path::new(path))?;
+
+    let report =
+        pbs_datastore::fsck::check_datastore(std::path::Path::new(path), &extra_chunk_dirs, deep)?;
+
+    if let Some(report_file) = param["report"].as_str() {
+        let data = serde_json::to_string_pretty(&report)?;
+        proxmox_sys::fs::replace_file(
+            report_file,
+            data.as_bytes(),
+            proxmox_sys::fs::CreateOptions::new(),
+            false,
+        )?;
+    }
+
+    if output_format == "text" {
+        println!(
+            "checked {} index files referencing {} chunks{}",
+            report.index_files,
+            report.chunks,
+            if deep { " (deep)" } else { "" },
+        );
+        for problem in report.problems.iter() {
+            println!("{}: {}", problem.path, problem.issue);
+        }
+    } else {
+        format_and_print_result(&serde_json::to_value(&report)?, &output_format);
+    }
+
+    if !report.problems.is_empty() {
+        bail!("found {} problem(s)", report.problems.len());
+    }
+
+    Ok(())
+}
+
+// With the S3 backend, the chunk directories only hold empty marker files, which the offline check
+// would all report as corrupt chunks. Without a readable datastore configuration, the path is
+// checked as a file system backed datastore.
+fn check_fsck_backend(path: &std::path::Path) -> Result<(), Error> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|err| format_err!("unable to access datastore path {path:?} - {err}"))?;
+
+    let Ok((config, _digest)) = pbs_config::datastore::config() else {
+        return Ok(());
+    };
+    let datastores: Vec<DataStoreConfig> = config.convert_to_typed_array("datastore")?;
+
+    for datastore in datastores {
+        if std::fs::canonicalize(&datastore.path).ok().as_ref() != Some(&path) {
+            continue;
+        }
+        let backend: DatastoreBackendConfig = serde_json::from_value(
+            DatastoreBackendConfig::API_SCHEMA
+                .parse_property_string(datastore.backend.as_deref().unwrap_or(""))?,
+        )?;
+        if backend.ty == Some(DatastoreBackendType::S3) {
+            bail!(
+                "datastore '{}' stores its chunks in an S3 bucket, which the offline check does \
+                not support - use a verification job instead",
+                datastore.name,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List the deleted snapshots in the trash of a datastore.
+async fn list_trash(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = extract_output_format(&mut param);
+
+    let info = &api2::admin::datastore::API_METHOD_LIST_TRASH;
+    let mut data = match info.handler {
         ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
         _ => unreachable!(),
     };
 
-    crate::wait_for_local_worker(result.as_str().unwrap()).await?;
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("backup-type"))
+        .column(ColumnConfig::new("backup-id"))
+        .column(ColumnConfig::new("backup-time").renderer(render_epoch))
+        .column(ColumnConfig::new("deleted").renderer(render_epoch))
+        .column(ColumnConfig::new("purge-after").renderer(render_epoch))
+        .column(ColumnConfig::new("owner"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
     Ok(())
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            backup_dir: {
+                type: BackupDir,
+                flatten: true,
+            },
+        },
+    },
+)]
+/// Restore a deleted snapshot from the trash.
+async fn restore_trash(mut param: Value) -> Result<Value, Error> {
+    let store = required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/trash");
+    client.post(&path, Some(param)).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            all: {
+                description: "Purge all snapshots in the trash, not only the expired ones.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Purge the expired snapshots from the trash, or all of them.
+async fn purge_trash(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+    let store = required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/trash");
+    let result = client.delete(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            "maintenance-mode": {
+                description: "The maintenance mode to set, the datastore leaves maintenance mode \
+                    if not set.",
+                optional: true,
+                format: &ApiStringFormat::PropertyString(&MaintenanceMode::API_SCHEMA),
+                type: String,
+            },
+            timeout: {
+                description: "Wait up to this many seconds for operations which are not allowed \
+                    in the new mode to finish.",
+                type: Integer,
+                minimum: 0,
+                maximum: 3600,
+                optional: true,
+                default: 0,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Set or clear the maintenance mode of a datastore.
+async fn set_maintenance_mode(mut param: Value) -> Result<Value, Error> {
+    let output_format = extract_output_format(&mut param);
+    let store = required_string_param(&param, "store")?.to_owned();
+    param.as_object_mut().unwrap().remove("store");
+
+    let client = connect_to_localhost()?;
+
+    let path = format!("api2/json/admin/datastore/{store}/maintenance");
+    let result = client.put(&path, Some(param)).await?;
+
+    view_task_result(&client, result, &output_format).await?;
+
+    Ok(Value::Null)
+}
+
+fn namespace_quota_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_NAMESPACE_QUOTA)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "set",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_SET_QUOTA)
+                .arg_param(&["store", "quota"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_DELETE_QUOTA)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        );
+
+    cmd_def.into()
+}
+
+fn namespace_verify_new_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_NAMESPACE_VERIFY_NEW)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "set",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_SET_VERIFY_NEW)
+                .arg_param(&["store", "verify-new"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_DELETE_VERIFY_NEW)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        );
+
+    cmd_def.into()
+}
+
+fn trash_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "list",
+            CliCommand::new(&API_METHOD_LIST_TRASH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "restore",
+            CliCommand::new(&API_METHOD_RESTORE_TRASH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "purge",
+            CliCommand::new(&API_METHOD_PURGE_TRASH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        );
+
+    cmd_def.into()
+}
+
 pub fn datastore_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
         .insert("list", CliCommand::new(&API_METHOD_LIST_DATASTORES))
@@ -154,7 +1051,7 @@ pub fn datastore_commands() -> CommandLineInterface {
         )
         .insert(
             "update",
-            CliCommand::new(&api2::config::datastore::API_METHOD_UPDATE_DATASTORE)
+            CliCommand::new(&API_METHOD_UPDATE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name)
                 .completion_cb(
@@ -166,11 +1063,79 @@ pub fn datastore_commands() -> CommandLineInterface {
                     pbs_config::datastore::complete_calendar_event,
                 ),
         )
+        .insert(
+            "maintenance",
+            CliCommand::new(&API_METHOD_SET_MAINTENANCE_MODE)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+        )
         .insert(
             "remove",
             CliCommand::new(&API_METHOD_DELETE_DATASTORE)
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::datastore::complete_datastore_name),
+        )
+        .insert(
+            "move-group",
+            CliCommand::new(&API_METHOD_MOVE_GROUP)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace)
+                .completion_cb("target-ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "change-owner-bulk",
+            CliCommand::new(&API_METHOD_CHANGE_OWNER_BULK)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace)
+                .completion_cb("new-owner", pbs_config::user::complete_authid)
+                .completion_cb("expected-owner", pbs_config::user::complete_authid),
+        )
+        .insert(
+            "protect-bulk",
+            CliCommand::new(&API_METHOD_PROTECT_BULK)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "scrub-meta",
+            CliCommand::new(&API_METHOD_SCRUB_META)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("default-owner", pbs_config::user::complete_authid),
+        )
+        .insert(
+            "namespace-delete",
+            CliCommand::new(&API_METHOD_DELETE_NAMESPACE)
+                .arg_param(&["store", "ns"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "fsck",
+            CliCommand::new(&API_METHOD_FSCK)
+                .arg_param(&["path"])
+                .completion_cb("path", complete_file_name)
+                .completion_cb("report", complete_file_name),
+        )
+        .insert("namespace-quota", namespace_quota_commands())
+        .insert("namespace-verify-new", namespace_verify_new_commands())
+        .insert("trash", trash_commands())
+        .insert(
+            "owner-report",
+            CliCommand::new(&API_METHOD_OWNER_REPORT)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
+        )
+        .insert(
+            "health",
+            CliCommand::new(&API_METHOD_HEALTH)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
         );
 
     cmd_def.into()
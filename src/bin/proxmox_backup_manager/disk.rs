@@ -18,6 +18,12 @@ use proxmox_backup::api2;
 #[api(
     input: {
         properties: {
+            all: {
+                description: "List all disks, ignoring the disk monitoring scope of the node.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
             "output-format": {
                 schema: OUTPUT_FORMAT,
                 optional: true,
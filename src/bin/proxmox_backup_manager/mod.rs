@@ -16,6 +16,8 @@ mod prune;
 pub use prune::*;
 mod remote;
 pub use remote::*;
+mod role;
+pub use role::*;
 mod sync;
 pub use sync::*;
 mod verify;
@@ -30,5 +32,9 @@ mod node;
 pub use node::*;
 mod openid;
 pub use openid::*;
+mod pam;
+pub use pam::*;
+mod pbs;
+pub use pbs::*;
 mod traffic_control;
 pub use traffic_control::*;
@@ -1,10 +1,14 @@
 use anyhow::Error;
 use serde_json::Value;
 
+use proxmox_http::uri::json_object_to_query;
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
+use pbs_api_types::{JOURNAL_PRIORITY_SCHEMA, SYSTEMD_UNIT_NAME_SCHEMA};
+
 use proxmox_backup::api2;
+use proxmox_backup::client_helpers::connect_to_localhost;
 
 #[api(
     input: {
@@ -32,8 +36,115 @@ fn get_node_config(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Valu
     Ok(Value::Null)
 }
 
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// List the currently held backup group and snapshot locks
+fn list_locks(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::node::locks::API_METHOD_LIST_LOCKS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("path"))
+        .column(ColumnConfig::new("exclusive"))
+        .column(ColumnConfig::new("operation"))
+        .column(ColumnConfig::new("upid"))
+        .column(ColumnConfig::new("pid"))
+        .column(ColumnConfig::new("age").renderer(pbs_tools::format::render_duration));
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+#[api(
+    input: {
+        properties: {
+            service: {
+                schema: SYSTEMD_UNIT_NAME_SCHEMA,
+                optional: true,
+            },
+            priority: {
+                schema: JOURNAL_PRIORITY_SCHEMA,
+                optional: true,
+            },
+            since: {
+                type: Integer,
+                description: "Display all log since this UNIX epoch.",
+                minimum: 0,
+                optional: true,
+            },
+            until: {
+                type: Integer,
+                description: "Display all log until this UNIX epoch.",
+                minimum: 0,
+                optional: true,
+            },
+            lastentries: {
+                type: Integer,
+                description: "Limit to the last X lines.",
+                minimum: 0,
+                optional: true,
+            },
+            follow: {
+                type: Boolean,
+                description: "Keep printing new entries as they are written.",
+                default: false,
+                optional: true,
+            },
+            timeout: {
+                type: Integer,
+                description: "Stop following the journal after this many seconds (default 60).",
+                minimum: 1,
+                maximum: 3600,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the system journal, read through the API of the local server.
+async fn show_journal(param: Value) -> Result<Value, Error> {
+    let client = connect_to_localhost()?;
+
+    if param["follow"].as_bool().unwrap_or(false) {
+        let query = json_object_to_query(param)?;
+        let path = format!("api2/json/nodes/localhost/journal?{query}");
+        client.download(&path, &mut std::io::stdout()).await?;
+    } else {
+        let result = client
+            .get("api2/json/nodes/localhost/journal", Some(param))
+            .await?;
+        let lines = result["data"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // the first and the last line are journal cursors
+        if lines.len() > 2 {
+            for line in &lines[1..lines.len() - 1] {
+                println!("{}", line.as_str().unwrap_or_default());
+            }
+        }
+    }
+
+    Ok(Value::Null)
+}
+
 pub fn node_commands() -> CommandLineInterface {
     let cmd_def = CliCommandMap::new()
+        .insert("journal", CliCommand::new(&API_METHOD_SHOW_JOURNAL))
+        .insert("locks", CliCommand::new(&API_METHOD_LIST_LOCKS))
         .insert("show", CliCommand::new(&API_METHOD_GET_NODE_CONFIG))
         .insert(
             "update",
@@ -7,7 +7,10 @@ use serde_json::Value;
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
-use pbs_api_types::{DataStoreConfig, PruneJobConfig, PruneJobOptions, JOB_ID_SCHEMA};
+use pbs_api_types::{
+    BackupNamespace, DataStoreConfig, PruneJobConfig, PruneJobOptions, DATASTORE_SCHEMA,
+    JOB_ID_SCHEMA,
+};
 use pbs_config::prune;
 
 use proxmox_backup::api2;
@@ -141,6 +144,69 @@ pub fn prune_job_commands() -> CommandLineInterface {
     cmd_def.into()
 }
 
+#[api(
+    input: {
+        properties: {
+            store: {
+                schema: DATASTORE_SCHEMA,
+            },
+            ns: {
+                type: BackupNamespace,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Show the prune options stored on a namespace
+fn show_namespace_prune_options(
+    param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::admin::namespace::API_METHOD_GET_PRUNE_OPTIONS;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(Value::Null)
+}
+
+pub fn namespace_prune_options_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert(
+            "show",
+            CliCommand::new(&API_METHOD_SHOW_NAMESPACE_PRUNE_OPTIONS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_prune_local_datastore_namespace),
+        )
+        .insert(
+            "set",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_SET_PRUNE_OPTIONS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_prune_local_datastore_namespace),
+        )
+        .insert(
+            "remove",
+            CliCommand::new(&api2::admin::namespace::API_METHOD_DELETE_PRUNE_OPTIONS)
+                .arg_param(&["store"])
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", complete_prune_local_datastore_namespace),
+        );
+
+    cmd_def.into()
+}
+
 // shell completion helper
 fn complete_prune_local_datastore_namespace(
     _arg: &str,
@@ -228,8 +294,7 @@ pub(crate) fn update_to_prune_jobs_config() -> Result<(), Error> {
             }
         };
 
-        let mut id = format!("storeconfig-{store}");
-        id.truncate(32);
+        let id = proxmox_backup::server::legacy_prune_job_id(store);
         if data.sections.contains_key(&id) {
             eprintln!("skipping existing converted prune job for datastore '{store}': {id}");
             continue;
@@ -247,6 +312,7 @@ pub(crate) fn update_to_prune_jobs_config() -> Result<(), Error> {
             comment: None,
             schedule,
             options,
+            concurrency_group: None,
         };
 
         let prune_config = serde_json::to_value(prune_config)?;
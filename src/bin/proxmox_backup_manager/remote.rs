@@ -1,4 +1,4 @@
-use anyhow::Error;
+use anyhow::{bail, Error};
 use serde_json::Value;
 
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
@@ -33,6 +33,7 @@ fn list_remotes(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value,
         .column(ColumnConfig::new("host"))
         .column(ColumnConfig::new("auth-id"))
         .column(ColumnConfig::new("fingerprint"))
+        .column(ColumnConfig::new("last-check").renderer(render_last_check))
         .column(ColumnConfig::new("comment"));
 
     format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
@@ -40,6 +41,52 @@ fn list_remotes(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value,
     Ok(Value::Null)
 }
 
+fn render_last_check(value: &Value, _record: &Value) -> Result<String, Error> {
+    if value.is_null() {
+        return Ok(String::new());
+    }
+    let time = pbs_tools::format::render_epoch(&value["time"], &Value::Null)?;
+    match value["error"].as_str() {
+        Some(err) => Ok(format!("{time}: {err}")),
+        None => Ok(format!("{time}: OK")),
+    }
+}
+
+#[api(
+    input: {
+        properties: {
+            name: {
+                schema: REMOTE_ID_SCHEMA,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Check the connection to a remote with the stored credentials.
+async fn check_remote(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, Error> {
+    let output_format = get_output_format(&param);
+
+    let info = &api2::config::remote::API_METHOD_CHECK_REMOTE;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let failed = !data["error"].is_null();
+
+    let options = default_table_format_options();
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    if failed {
+        bail!("remote check failed");
+    }
+
+    Ok(Value::Null)
+}
+
 #[api(
     input: {
         properties: {
@@ -78,6 +125,12 @@ pub fn remote_commands() -> CommandLineInterface {
                 .arg_param(&["name"])
                 .completion_cb("name", pbs_config::remote::complete_remote_name),
         )
+        .insert(
+            "check",
+            CliCommand::new(&API_METHOD_CHECK_REMOTE)
+                .arg_param(&["name"])
+                .completion_cb("name", pbs_config::remote::complete_remote_name),
+        )
         .insert(
             "create",
             // fixme: howto handle password parameter?
@@ -8,10 +8,27 @@ use pbs_api_types::JOB_ID_SCHEMA;
 
 use proxmox_backup::api2;
 
+fn render_sync_direction(value: &Value, _record: &Value) -> Result<String, Error> {
+    Ok(value.as_str().unwrap_or("pull").to_string())
+}
+
 fn render_group_filter(value: &Value, _record: &Value) -> Result<String, Error> {
     if let Some(group_filters) = value.as_array() {
-        let group_filters: Vec<&str> = group_filters.iter().filter_map(Value::as_str).collect();
-        Ok(group_filters.join(" OR "))
+        let (exclude, include): (Vec<&str>, Vec<&str>) = group_filters
+            .iter()
+            .filter_map(Value::as_str)
+            .partition(|filter| filter.starts_with("exclude:"));
+
+        let mut text = if include.is_empty() {
+            String::from("all")
+        } else {
+            include.join(" OR ")
+        };
+        if !exclude.is_empty() {
+            text.push_str(" EXCEPT ");
+            text.push_str(&exclude.join(" OR "));
+        }
+        Ok(text)
     } else {
         Ok(String::from("all"))
     }
@@ -39,6 +56,11 @@ fn list_sync_jobs(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value
 
     let options = default_table_format_options()
         .column(ColumnConfig::new("id"))
+        .column(
+            ColumnConfig::new("sync-direction")
+                .header("Direction")
+                .renderer(render_sync_direction),
+        )
         .column(ColumnConfig::new("store"))
         .column(ColumnConfig::new("remote"))
         .column(ColumnConfig::new("remote-store"))
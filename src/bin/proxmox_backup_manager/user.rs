@@ -1,4 +1,4 @@
-use anyhow::Error;
+use anyhow::{bail, Error};
 use serde_json::Value;
 
 use std::collections::HashMap;
@@ -6,7 +6,7 @@ use std::collections::HashMap;
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
-use pbs_api_types::{Authid, Userid, ACL_PATH_SCHEMA};
+use pbs_api_types::{Authid, Tokenname, Userid, ACL_PATH_SCHEMA, EXPIRE_USER_SCHEMA};
 
 use proxmox_backup::api2;
 
@@ -18,11 +18,15 @@ fn render_expire(value: &Value, _record: &Value) -> Result<String, Error> {
     let text = match value.as_i64() {
         Some(0) => never,
         Some(epoch) => {
-            if let Ok(epoch_string) = proxmox_time::strftime_local("%c", epoch) {
+            let mut text = if let Ok(epoch_string) = proxmox_time::strftime_local("%c", epoch) {
                 epoch_string
             } else {
                 epoch.to_string()
+            };
+            if epoch <= proxmox_time::epoch_i64() {
+                text.push_str(" (expired)");
             }
+            text
         }
         None => value.to_string(),
     };
@@ -101,6 +105,46 @@ fn list_tokens(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<Value, E
     Ok(Value::Null)
 }
 
+#[api(
+    protected: true,
+    input: {
+        properties: {
+            userid: {
+                type: Userid,
+            },
+            "token-name": {
+                type: Tokenname,
+            },
+            expire: {
+                schema: EXPIRE_USER_SCHEMA,
+                optional: true,
+            },
+            days: {
+                description: "Let the token expire this many days from now.",
+                type: u64,
+                minimum: 1,
+                optional: true,
+            },
+        }
+    }
+)]
+/// Extend the lifetime of an API token, either to a fixed date or by a number of days from now.
+fn token_extend(
+    userid: Userid,
+    token_name: Tokenname,
+    expire: Option<i64>,
+    days: Option<u64>,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let expire = match (expire, days) {
+        (Some(expire), None) => expire,
+        (None, Some(days)) => proxmox_time::epoch_i64() + (days as i64) * 24 * 3600,
+        _ => bail!("either 'expire' or 'days' is required"),
+    };
+
+    api2::access::user::update_token(userid, token_name, None, None, Some(expire), None, rpcenv)
+}
+
 #[api(
     input: {
         properties: {
@@ -223,6 +267,13 @@ pub fn user_commands() -> CommandLineInterface {
                 .arg_param(&["userid", "token-name"])
                 .completion_cb("userid", pbs_config::user::complete_userid),
         )
+        .insert(
+            "token-extend",
+            CliCommand::new(&API_METHOD_TOKEN_EXTEND)
+                .arg_param(&["userid", "token-name"])
+                .completion_cb("userid", pbs_config::user::complete_userid)
+                .completion_cb("token-name", pbs_config::user::complete_token_name),
+        )
         .insert(
             "delete-token",
             CliCommand::new(&api2::access::user::API_METHOD_DELETE_TOKEN)
@@ -31,6 +31,8 @@ fn list_verification_jobs(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Resu
     let options = default_table_format_options()
         .column(ColumnConfig::new("id"))
         .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("max-depth"))
         .column(ColumnConfig::new("schedule"))
         .column(ColumnConfig::new("ignore-verified"))
         .column(ColumnConfig::new("outdated-after"))
@@ -103,7 +105,8 @@ pub fn verify_job_commands() -> CommandLineInterface {
                 .arg_param(&["id"])
                 .completion_cb("id", pbs_config::verify::complete_verification_job_id)
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
-                .completion_cb("store", pbs_config::datastore::complete_datastore_name),
+                .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace),
         )
         .insert(
             "update",
@@ -112,6 +115,7 @@ pub fn verify_job_commands() -> CommandLineInterface {
                 .completion_cb("id", pbs_config::verify::complete_verification_job_id)
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
                 .completion_cb("store", pbs_config::datastore::complete_datastore_name)
+                .completion_cb("ns", crate::complete_sync_local_datastore_namespace)
                 .completion_cb("remote-store", crate::complete_remote_datastore_name),
         )
         .insert(
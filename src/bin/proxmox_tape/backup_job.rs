@@ -124,7 +124,8 @@ pub fn backup_job_commands() -> CommandLineInterface {
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
                 .completion_cb("store", pbs_config::datastore::complete_datastore_name)
                 .completion_cb("pool", pbs_config::media_pool::complete_pool_name)
-                .completion_cb("drive", crate::complete_drive_name),
+                .completion_cb("drive", crate::complete_drive_name)
+                .completion_cb("extra-drives", crate::complete_drive_name),
         )
         .insert(
             "update",
@@ -134,7 +135,8 @@ pub fn backup_job_commands() -> CommandLineInterface {
                 .completion_cb("schedule", pbs_config::datastore::complete_calendar_event)
                 .completion_cb("store", pbs_config::datastore::complete_datastore_name)
                 .completion_cb("pool", pbs_config::media_pool::complete_pool_name)
-                .completion_cb("drive", crate::complete_drive_name),
+                .completion_cb("drive", crate::complete_drive_name)
+                .completion_cb("extra-drives", crate::complete_drive_name),
         )
         .insert(
             "remove",
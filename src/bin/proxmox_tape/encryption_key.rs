@@ -8,9 +8,10 @@ use proxmox_schema::{api, param_bail};
 use proxmox_sys::linux::tty;
 
 use pbs_api_types::{
-    Fingerprint, Kdf, DRIVE_NAME_SCHEMA, PASSWORD_HINT_SCHEMA,
+    Fingerprint, Kdf, DRIVE_NAME_SCHEMA, MEDIA_POOL_NAME_SCHEMA, PASSWORD_HINT_SCHEMA,
     TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
 };
+use pbs_config::media_pool::complete_pool_name;
 
 use pbs_datastore::paperkey::{generate_paper_key, PaperkeyFormat};
 use pbs_key_config::KeyConfig;
@@ -41,6 +42,19 @@ pub fn encryption_key_commands() -> CommandLineInterface {
                 .completion_cb("fingerprint", complete_key_fingerprint),
         )
         .insert("restore", CliCommand::new(&API_METHOD_RESTORE_KEY))
+        .insert(
+            "media-sets",
+            CliCommand::new(&API_METHOD_LIST_MEDIA_SETS)
+                .arg_param(&["fingerprint"])
+                .completion_cb("fingerprint", complete_key_fingerprint),
+        )
+        .insert(
+            "rotate",
+            CliCommand::new(&API_METHOD_ROTATE_KEY)
+                .arg_param(&["pool"])
+                .completion_cb("pool", complete_pool_name)
+                .completion_cb("fingerprint", complete_key_fingerprint),
+        )
         .insert(
             "remove",
             CliCommand::new(&api2::config::tape_encryption_keys::API_METHOD_DELETE_KEY)
@@ -304,6 +318,61 @@ fn create_key(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), E
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            pool: {
+                schema: MEDIA_POOL_NAME_SCHEMA,
+            },
+            fingerprint: {
+                schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+                optional: true,
+            },
+            kdf: {
+                type: Kdf,
+                optional: true,
+            },
+            hint: {
+                schema: PASSWORD_HINT_SCHEMA,
+                optional: true,
+            },
+        },
+    },
+)]
+/// Rotate the encryption key of a media pool
+///
+/// Uses the existing key 'fingerprint', or creates a new key (reads password from stdin). Media
+/// sets written with the previous key stay restorable, the new key is used for new media sets.
+fn rotate_key(mut param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let pool = param["pool"].take();
+    param.as_object_mut().unwrap().remove("pool");
+    param["name"] = pool;
+
+    if param["fingerprint"].is_null() {
+        if param["hint"].is_null() {
+            param_bail!(
+                "hint",
+                format_err!("Please specify a hint for the new key (or use 'fingerprint')")
+            );
+        }
+        if !std::io::stdin().is_terminal() {
+            bail!("no password input mechanism available");
+        }
+        let password = tty::read_and_verify_password("Tape Encryption Key Password: ")?;
+        param["password"] = String::from_utf8(password)?.into();
+    }
+
+    let info = &api2::config::media_pool::API_METHOD_ROTATE_KEY;
+    let fingerprint = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    println!("{}", fingerprint);
+
+    Ok(())
+}
+
 #[api(
     input: {
         properties: {
@@ -331,3 +400,61 @@ fn list_keys(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error>
 
     Ok(())
 }
+
+#[api(
+    input: {
+        properties: {
+            fingerprint: {
+                schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+                optional: true,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List encrypted media sets, and the encryption key their data key is wrapped by
+///
+/// Media sets without wrapped key use the encryption key directly.
+async fn list_media_sets(
+    fingerprint: Option<Fingerprint>,
+    mut param: Value,
+    rpcenv: &mut dyn RpcEnvironment,
+) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    param.as_object_mut().unwrap().remove("fingerprint");
+
+    let info = &api2::tape::media::API_METHOD_LIST_MEDIA_SETS;
+    let data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let fingerprint = fingerprint.map(|fp| fp.signature());
+    let list: Vec<Value> = data
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|entry| match entry["encryption-key-fingerprint"].as_str() {
+            Some(fp) => fingerprint.as_deref().map_or(true, |wanted| wanted == fp),
+            None => false,
+        })
+        .cloned()
+        .collect();
+    let mut data = Value::from(list);
+
+    let options = default_table_format_options()
+        .sortby("encryption-key-fingerprint", false)
+        .sortby("media-set-ctime", false)
+        .column(ColumnConfig::new("encryption-key-fingerprint"))
+        .column(ColumnConfig::new("key-wrapped"))
+        .column(ColumnConfig::new("pool"))
+        .column(ColumnConfig::new("media-set-name"))
+        .column(ColumnConfig::new("media-set-uuid"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
@@ -6,9 +6,10 @@ use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
 use proxmox_schema::api;
 
 use pbs_api_types::{
-    MediaContentListFilter, MediaListEntry, MediaStatus, CHANGER_NAME_SCHEMA,
-    MEDIA_POOL_NAME_SCHEMA,
+    MediaContentListFilter, MediaListEntry, MediaStatus, BACKUP_GROUP_SCHEMA, CHANGER_NAME_SCHEMA,
+    DATASTORE_SCHEMA, MEDIA_POOL_NAME_SCHEMA, MEDIA_SET_UUID_SCHEMA,
 };
+use pbs_config::datastore::complete_datastore_name;
 use pbs_config::drive::complete_changer_name;
 use pbs_config::media_pool::complete_pool_name;
 
@@ -43,6 +44,20 @@ pub fn media_commands() -> CommandLineInterface {
     cmd_def.into()
 }
 
+pub fn media_set_commands() -> CommandLineInterface {
+    let cmd_def = CliCommandMap::new()
+        .insert("list", CliCommand::new(&API_METHOD_LIST_MEDIA_SETS))
+        .insert(
+            "content",
+            CliCommand::new(&API_METHOD_LIST_MEDIA_SET_CONTENT)
+                .arg_param(&["media-set"])
+                .completion_cb("media-set", complete_media_set_uuid)
+                .completion_cb("store", complete_datastore_name),
+        );
+
+    cmd_def.into()
+}
+
 #[api(
     input: {
         properties: {
@@ -166,3 +181,87 @@ fn list_content(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Err
 
     Ok(())
 }
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List media sets
+async fn list_media_sets(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let info = &api2::tape::media::API_METHOD_LIST_MEDIA_SETS;
+    let mut data = match info.handler {
+        ApiHandler::Async(handler) => (handler)(param, info, rpcenv).await?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .sortby("pool", false)
+        .sortby("media-set-ctime", false)
+        .column(ColumnConfig::new("pool"))
+        .column(ColumnConfig::new("media-set-name"))
+        .column(ColumnConfig::new("media-set-ctime").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("media-set-uuid"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
+
+#[api(
+    input: {
+        properties: {
+            "media-set": {
+                schema: MEDIA_SET_UUID_SCHEMA,
+            },
+            store: {
+                schema: DATASTORE_SCHEMA,
+                optional: true,
+            },
+            group: {
+                schema: BACKUP_GROUP_SCHEMA,
+                optional: true,
+            },
+            "missing-only": {
+                description: "Only list media whose content is unknown because the media \
+                    or its catalog is missing.",
+                type: bool,
+                optional: true,
+                default: false,
+            },
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+        },
+    },
+)]
+/// List the snapshots of a media set and the media they are stored on
+fn list_media_set_content(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let info = &api2::tape::media::API_METHOD_LIST_MEDIA_SET_CONTENT;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("seq-nr"))
+        .column(ColumnConfig::new("label-text"))
+        .column(ColumnConfig::new("file-nr"))
+        .column(ColumnConfig::new("store"))
+        .column(ColumnConfig::new("ns"))
+        .column(ColumnConfig::new("snapshot"))
+        .column(ColumnConfig::new("size").renderer(pbs_tools::format::render_bytes_human_readable))
+        .column(ColumnConfig::new("error"));
+
+    format_and_print_result_full(&mut data, &info.returns, &output_format, &options);
+
+    Ok(())
+}
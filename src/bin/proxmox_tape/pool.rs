@@ -2,9 +2,9 @@ use anyhow::Error;
 use serde_json::Value;
 
 use proxmox_router::{cli::*, ApiHandler, RpcEnvironment};
-use proxmox_schema::api;
+use proxmox_schema::{api, ApiType, ArraySchema, ReturnType, Schema};
 
-use pbs_api_types::MEDIA_POOL_NAME_SCHEMA;
+use pbs_api_types::{MediaForecastEntry, MediaPoolForecast, MEDIA_POOL_NAME_SCHEMA};
 use pbs_config::media_pool::complete_pool_name;
 
 use proxmox_backup::api2;
@@ -38,6 +38,12 @@ pub fn pool_commands() -> CommandLineInterface {
                 .arg_param(&["name"])
                 .completion_cb("name", complete_pool_name)
                 .completion_cb("encrypt", complete_key_fingerprint),
+        )
+        .insert(
+            "forecast",
+            CliCommand::new(&API_METHOD_FORECAST)
+                .arg_param(&["pool"])
+                .completion_cb("pool", complete_pool_name),
         );
 
     cmd_def.into()
@@ -115,3 +121,57 @@ fn get_config(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error
 
     Ok(())
 }
+
+const MEDIA_FORECAST_LIST_SCHEMA: Schema =
+    ArraySchema::new("Media retention forecast.", &MediaForecastEntry::API_SCHEMA).schema();
+
+#[api(
+    input: {
+        properties: {
+            "output-format": {
+                schema: OUTPUT_FORMAT,
+                optional: true,
+            },
+            pool: {
+                schema: MEDIA_POOL_NAME_SCHEMA,
+            },
+        },
+    },
+)]
+/// Simulate the retention policy and show when media become writable again
+fn forecast(param: Value, rpcenv: &mut dyn RpcEnvironment) -> Result<(), Error> {
+    let output_format = get_output_format(&param);
+    let info = &api2::tape::media::API_METHOD_MEDIA_POOL_FORECAST;
+    let mut data = match info.handler {
+        ApiHandler::Sync(handler) => (handler)(param, info, rpcenv)?,
+        _ => unreachable!(),
+    };
+
+    if output_format != "text" {
+        format_and_print_result(&data, &output_format);
+        return Ok(());
+    }
+
+    let options = default_table_format_options()
+        .column(ColumnConfig::new("label-text"))
+        .column(ColumnConfig::new("media-set-name"))
+        .column(ColumnConfig::new("seq-nr"))
+        .column(ColumnConfig::new("status"))
+        .column(ColumnConfig::new("state"))
+        .column(ColumnConfig::new("expire-time").renderer(pbs_tools::format::render_epoch))
+        .column(ColumnConfig::new("note"));
+
+    let return_type = ReturnType::new(false, &MEDIA_FORECAST_LIST_SCHEMA);
+    format_and_print_result_full(&mut data["media"], &return_type, &output_format, &options);
+
+    let forecast: MediaPoolForecast = serde_json::from_value(data)?;
+    println!("writable now: {}", forecast.writable_now);
+    println!("writable within 30 days: {}", forecast.writable_in_30_days);
+    println!("writable within 90 days: {}", forecast.writable_in_90_days);
+    println!("never writable: {}", forecast.never);
+    if forecast.unknown > 0 {
+        println!("unknown: {}", forecast.unknown);
+    }
+
+    Ok(())
+}
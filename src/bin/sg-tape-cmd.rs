@@ -1,5 +1,5 @@
 /// Helper to run tape commands as root. Currently only required
-/// to read and set the encryption key.
+/// to read and set the encryption key, and to wrap media set keys.
 ///
 /// This command can use STDIN as tape device handle.
 use std::fs::File;
@@ -7,7 +7,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd};
 
 use anyhow::{bail, Error};
 use pbs_tape::sg_tape::SgTape;
-use proxmox_backup::tape::encryption_keys::load_key;
+use proxmox_backup::tape::encryption_keys::{load_key, unwrap_media_set_key, wrap_media_set_key};
 use serde_json::Value;
 
 use proxmox_router::{cli::*, RpcEnvironment};
@@ -76,6 +76,11 @@ fn get_tape_handle(param: &Value) -> Result<SgTape, Error> {
                 schema: MEDIA_SET_UUID_SCHEMA,
                 optional: true,
             },
+            "wrapped-key": {
+                description: "Media set data key, wrapped by the encryption key.",
+                type: String,
+                optional: true,
+            },
             drive: {
                 schema: DRIVE_NAME_SCHEMA,
                 optional: true,
@@ -96,6 +101,7 @@ fn get_tape_handle(param: &Value) -> Result<SgTape, Error> {
 fn set_encryption(
     fingerprint: Option<Fingerprint>,
     uuid: Option<Uuid>,
+    wrapped_key: Option<String>,
     param: Value,
 ) -> Result<(), Error> {
     let result = proxmox_lang::try_block!({
@@ -103,7 +109,10 @@ fn set_encryption(
 
         match (fingerprint, uuid) {
             (Some(fingerprint), Some(uuid)) => {
-                let key = load_key(&fingerprint)?;
+                let mut key = load_key(&fingerprint)?;
+                if let Some(wrapped_key) = wrapped_key {
+                    key = unwrap_media_set_key(&key, &uuid, &wrapped_key)?;
+                }
                 handle.set_encryption(Some((key, uuid)))?;
             }
             (Some(_), None) => {
@@ -123,6 +132,32 @@ fn set_encryption(
     Ok(())
 }
 
+#[api(
+    input: {
+        properties: {
+            fingerprint: {
+                schema: TAPE_ENCRYPTION_KEY_FINGERPRINT_SCHEMA,
+            },
+            uuid: {
+                schema: MEDIA_SET_UUID_SCHEMA,
+            },
+        },
+    },
+)]
+/// Generate a random data key for a media set, and print it wrapped by the encryption key
+fn wrap_key(fingerprint: Fingerprint, uuid: Uuid) -> Result<(), Error> {
+    let result = proxmox_lang::try_block!({
+        let key = load_key(&fingerprint)?;
+        let (_data_key, wrapped_key) = wrap_media_set_key(&key, &uuid)?;
+        Ok(wrapped_key)
+    })
+    .map_err(|err: Error| err.to_string());
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+
+    Ok(())
+}
+
 fn main() -> Result<(), Error> {
     init_cli_logger("PBS_LOG", "info");
 
@@ -145,8 +180,9 @@ fn main() -> Result<(), Error> {
         );
     }
 
-    let cmd_def =
-        CliCommandMap::new().insert("encryption", CliCommand::new(&API_METHOD_SET_ENCRYPTION));
+    let cmd_def = CliCommandMap::new()
+        .insert("encryption", CliCommand::new(&API_METHOD_SET_ENCRYPTION))
+        .insert("wrap-key", CliCommand::new(&API_METHOD_WRAP_KEY));
 
     let mut rpcenv = CliEnvironment::new();
     rpcenv.set_auth_id(Some(String::from("root@pam")));
@@ -4,13 +4,13 @@ use anyhow::{bail, Error};
 use openssl::ssl::{SslAcceptor, SslMethod};
 use serde::{Deserialize, Serialize};
 
-use proxmox_schema::{api, ApiStringFormat, ApiType, Updater};
+use proxmox_schema::{api, const_regex, ApiStringFormat, ApiType, Schema, StringSchema, Updater};
 
 use proxmox_http::ProxyConfig;
 
 use pbs_api_types::{
-    EMAIL_SCHEMA, MULTI_LINE_COMMENT_SCHEMA, OPENSSL_CIPHERS_TLS_1_2_SCHEMA,
-    OPENSSL_CIPHERS_TLS_1_3_SCHEMA,
+    DATASTORE_TUNING_STRING_SCHEMA, EMAIL_SCHEMA, GC_SCHEDULE_SCHEMA, MULTI_LINE_COMMENT_SCHEMA,
+    OPENSSL_CIPHERS_TLS_1_2_SCHEMA, OPENSSL_CIPHERS_TLS_1_3_SCHEMA, PRUNE_SCHEDULE_SCHEMA,
 };
 
 use pbs_buildcfg::configdir;
@@ -60,6 +60,50 @@ pub struct AcmeConfig {
     account: AcmeAccountName,
 }
 
+const_regex! {
+    DISK_PATTERN_LIST_REGEX = r"^[A-Za-z0-9_.\-*?]+(?:;[A-Za-z0-9_.\-*?]+)*$";
+}
+
+const DISK_PATTERN_LIST_FORMAT: ApiStringFormat =
+    ApiStringFormat::Pattern(&DISK_PATTERN_LIST_REGEX);
+
+const DISK_PATTERN_LIST_SCHEMA: Schema = StringSchema::new(
+    "Semicolon separated list of device name patterns (e.g. 'sd*;nvme0n1'), supporting '*' and '?'.",
+)
+.format(&DISK_PATTERN_LIST_FORMAT)
+.schema();
+
+#[api(
+    properties: {
+        include: {
+            schema: DISK_PATTERN_LIST_SCHEMA,
+            optional: true,
+        },
+        exclude: {
+            schema: DISK_PATTERN_LIST_SCHEMA,
+            optional: true,
+        },
+        "datastores-only": {
+            optional: true,
+            default: false,
+        },
+    }
+)]
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+/// Scope of the disk monitoring, used for the disk list and its SMART queries.
+pub struct DiskMonitoringConfig {
+    /// Only monitor disks matching one of these patterns (and the datastore disks).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+    /// Never monitor disks matching one of these patterns.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// Monitor the disks backing the configured datastores (and the included disks).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datastores_only: Option<bool>,
+}
+
 /// All available languages in Proxmox. Taken from proxmox-i18n repository.
 /// pt_BR, zh_CN, and zh_TW use the same case in the translation files.
 // TODO: auto-generate from available translations
@@ -174,7 +218,38 @@ pub enum Translation {
         "description" : {
             optional: true,
             schema: MULTI_LINE_COMMENT_SCHEMA,
-        }
+        },
+        "max-datastore-jobs": {
+            optional: true,
+            minimum: 1,
+        },
+        "job-concurrency-timeout": {
+            optional: true,
+            minimum: 1,
+        },
+        "owner-report-sync-limit": {
+            optional: true,
+        },
+        "token-purge-days": {
+            optional: true,
+        },
+        "default-datastore-tuning": {
+            optional: true,
+            schema: DATASTORE_TUNING_STRING_SCHEMA,
+        },
+        "default-gc-schedule": {
+            optional: true,
+            schema: GC_SCHEDULE_SCHEMA,
+        },
+        "default-prune-schedule": {
+            optional: true,
+            schema: PRUNE_SCHEDULE_SCHEMA,
+        },
+        "disk-monitoring": {
+            optional: true,
+            type: String,
+            format: &ApiStringFormat::PropertyString(&DiskMonitoringConfig::API_SCHEMA),
+        },
     },
 )]
 #[derive(Deserialize, Serialize, Updater)]
@@ -225,6 +300,46 @@ pub struct NodeConfig {
     /// Maximum days to keep Task logs
     #[serde(skip_serializing_if = "Option::is_none")]
     pub task_log_max_days: Option<usize>,
+
+    /// Require two-factor authentication for the users of all realms, regardless of the
+    /// realm's own 'tfa-required' setting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tfa_required: Option<bool>,
+
+    /// Maximum number of scheduled datastore jobs (GC, prune, sync, verify, tape backup) running
+    /// at the same time on this node. Unlimited if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_datastore_jobs: Option<usize>,
+
+    /// Minutes a scheduled job waits for its concurrency group before it is started anyway.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_concurrency_timeout: Option<u64>,
+
+    /// Maximum number of backup groups for which owner reports are generated synchronously,
+    /// larger reports are generated by a worker task. Defaults to 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_report_sync_limit: Option<usize>,
+
+    /// Delete API tokens which expired more than this many days ago. Expired tokens are kept if
+    /// not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_purge_days: Option<u64>,
+
+    /// Tuning options for new datastores created without explicit tuning options.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_datastore_tuning: Option<String>,
+
+    /// Garbage collection schedule for new datastores created without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_gc_schedule: Option<String>,
+
+    /// Prune schedule for new datastores created with keep options, but without a schedule.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_prune_schedule: Option<String>,
+
+    /// Scope of the disk monitoring. All disks are monitored if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_monitoring: Option<String>,
 }
 
 impl NodeConfig {
@@ -247,6 +362,16 @@ impl NodeConfig {
         AcmeDomainIter::new(self)
     }
 
+    pub fn disk_monitoring(&self) -> Result<DiskMonitoringConfig, Error> {
+        match self.disk_monitoring.as_deref() {
+            Some(config) => crate::tools::config::from_property_string(
+                config,
+                &DiskMonitoringConfig::API_SCHEMA,
+            ),
+            None => Ok(DiskMonitoringConfig::default()),
+        }
+    }
+
     /// Returns the parsed ProxyConfig
     pub fn http_proxy(&self) -> Option<ProxyConfig> {
         if let Some(http_proxy) = &self.http_proxy {
@@ -280,6 +405,7 @@ impl NodeConfig {
         if let Some(ciphers) = self.ciphers_tls_1_2.as_deref() {
             dummy_acceptor.set_cipher_list(ciphers)?;
         }
+        self.disk_monitoring()?;
 
         Ok(())
     }
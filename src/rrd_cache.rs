@@ -3,8 +3,14 @@
 //! RRD files are stored under `/var/lib/proxmox-backup/rrdb/`. Only a
 //! single process may access and update those files, so we initialize
 //! and update RRD data inside `proxmox-backup-proxy`.
+//!
+//! The last value of every series is also kept in memory, so that it can be
+//! exported in the Prometheus text exposition format.
 
+use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::path::Path;
+use std::sync::Mutex;
 
 use anyhow::{format_err, Error};
 use once_cell::sync::OnceCell;
@@ -20,6 +26,14 @@ const RRD_CACHE_BASEDIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/rrdb");
 
 static RRD_CACHE: OnceCell<Cache> = OnceCell::new();
 
+static LATEST_VALUES: Mutex<BTreeMap<String, (MetricKind, f64)>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MetricKind {
+    Gauge,
+    Counter,
+}
+
 /// Get the RRD cache instance
 pub fn get_rrd_cache() -> Result<&'static Cache, Error> {
     RRD_CACHE
@@ -112,6 +126,7 @@ pub fn rrd_sync_journal() {
 }
 /// Update RRD Gauge values
 pub fn rrd_update_gauge(name: &str, value: f64) {
+    record_metric(name, MetricKind::Gauge, value);
     if let Ok(rrd_cache) = get_rrd_cache() {
         let now = proxmox_time::epoch_f64();
         if let Err(err) = rrd_cache.update_value(name, now, value, DataSourceType::Gauge) {
@@ -122,6 +137,7 @@ pub fn rrd_update_gauge(name: &str, value: f64) {
 
 /// Update RRD Derive values
 pub fn rrd_update_derive(name: &str, value: f64) {
+    record_metric(name, MetricKind::Counter, value);
     if let Ok(rrd_cache) = get_rrd_cache() {
         let now = proxmox_time::epoch_f64();
         if let Err(err) = rrd_cache.update_value(name, now, value, DataSourceType::Derive) {
@@ -129,3 +145,154 @@ pub fn rrd_update_derive(name: &str, value: f64) {
         }
     }
 }
+
+fn record_metric(name: &str, kind: MetricKind, value: f64) {
+    LATEST_VALUES
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), (kind, value));
+}
+
+/// Render the last values written to the RRD cache in the Prometheus text exposition format.
+///
+/// Series are named after their RRD path, e.g. `host/memused` becomes `pbs_host_memused` and
+/// `datastore/store1/used` becomes `pbs_datastore_used{datastore="store1"}`. Derive values are
+/// exported as counters with a `_total` suffix.
+pub fn prometheus_metrics() -> String {
+    format_prometheus_metrics(&LATEST_VALUES.lock().unwrap())
+}
+
+fn format_prometheus_metrics(values: &BTreeMap<String, (MetricKind, f64)>) -> String {
+    let mut metrics: BTreeMap<String, (MetricKind, Vec<(String, f64)>)> = BTreeMap::new();
+
+    for (key, (kind, value)) in values {
+        let (name, labels) = metric_name_and_labels(key, *kind);
+        metrics
+            .entry(name)
+            .or_insert_with(|| (*kind, Vec::new()))
+            .1
+            .push((labels, *value));
+    }
+
+    let mut output = String::new();
+    for (name, (kind, series)) in metrics {
+        let kind = match kind {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        };
+        let _ = writeln!(output, "# TYPE {name} {kind}");
+        for (labels, value) in series {
+            let _ = writeln!(output, "{name}{labels} {value}");
+        }
+    }
+
+    output
+}
+
+// Maps an RRD path like `datastore/<name>/<value>` to a metric name and its label set.
+fn metric_name_and_labels(key: &str, kind: MetricKind) -> (String, String) {
+    let parts: Vec<&str> = key.split('/').collect();
+
+    let (name, labels) = match parts.as_slice() {
+        [group, id @ .., value] if !id.is_empty() => (
+            format!("pbs_{group}_{value}"),
+            format!(
+                "{{{}=\"{}\"}}",
+                sanitize_metric_name(group),
+                escape_label_value(&id.join("/"))
+            ),
+        ),
+        _ => (format!("pbs_{}", parts.join("_")), String::new()),
+    };
+
+    let mut name = sanitize_metric_name(&name);
+    if kind == MetricKind::Counter && !name.ends_with("_total") {
+        name.push_str("_total");
+    }
+
+    (name, labels)
+}
+
+fn sanitize_metric_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metric_names() {
+        assert_eq!(
+            metric_name_and_labels("host/memused", MetricKind::Gauge),
+            ("pbs_host_memused".to_string(), String::new()),
+        );
+        assert_eq!(
+            metric_name_and_labels("datastore/store1/used", MetricKind::Gauge),
+            (
+                "pbs_datastore_used".to_string(),
+                "{datastore=\"store1\"}".to_string()
+            ),
+        );
+        assert_eq!(
+            metric_name_and_labels("datastore/my-store/chunk_read_bytes", MetricKind::Counter),
+            (
+                "pbs_datastore_chunk_read_bytes_total".to_string(),
+                "{datastore=\"my-store\"}".to_string()
+            ),
+        );
+        assert_eq!(
+            metric_name_and_labels("host/net-in", MetricKind::Counter),
+            ("pbs_host_net_in_total".to_string(), String::new()),
+        );
+    }
+
+    #[test]
+    fn test_label_escape() {
+        assert_eq!(escape_label_value("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[test]
+    fn test_prometheus_format() {
+        let mut values = BTreeMap::new();
+        values.insert("host/cpu".to_string(), (MetricKind::Gauge, 0.25));
+        values.insert("host/netin".to_string(), (MetricKind::Counter, 1024.0));
+        values.insert("datastore/b/used".to_string(), (MetricKind::Gauge, 20.0));
+        values.insert("datastore/a/used".to_string(), (MetricKind::Gauge, 10.0));
+        values.insert("datastore/a/total".to_string(), (MetricKind::Gauge, 100.0));
+
+        let expected = "\
+# TYPE pbs_datastore_total gauge
+pbs_datastore_total{datastore=\"a\"} 100
+# TYPE pbs_datastore_used gauge
+pbs_datastore_used{datastore=\"a\"} 10
+pbs_datastore_used{datastore=\"b\"} 20
+# TYPE pbs_host_cpu gauge
+pbs_host_cpu 0.25
+# TYPE pbs_host_netin_total counter
+pbs_host_netin_total 1024
+";
+        assert_eq!(format_prometheus_metrics(&values), expected);
+    }
+}
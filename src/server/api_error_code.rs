@@ -0,0 +1,233 @@
+//! Send the [`ApiErrorCode`] of failed API requests to the client
+//!
+//! The REST server formats errors by their message only. To get the code of an [`ApiError`] into
+//! the response, all errors created while handling a request are recorded, and if the response
+//! reports one of them, its code is added in the `code` field next to the `message` of the JSON
+//! body.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use anyhow::Error;
+use futures::future::TryFutureExt;
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::{json, Value};
+
+use pbs_api_types::{ApiError, ApiErrorCode};
+
+/// Only the last few errors of a request are kept, the one reported is usually the last one.
+const MAX_RECORDED_ERRORS: usize = 16;
+
+/// Successful responses are only inspected for an error of the ExtJS format up to this size.
+const MAX_EXTJS_ERROR_SIZE: u64 = 64 * 1024;
+
+tokio::task_local! {
+    static REQUEST_ERRORS: RefCell<Vec<(ApiErrorCode, String)>>;
+}
+
+/// Record an error for the API request handled by the current task, if any.
+///
+/// Set up with [`pbs_api_types::set_api_error_hook`] by the API daemons.
+pub fn record_api_error(err: &ApiError) {
+    let _ = REQUEST_ERRORS.try_with(|errors| {
+        let mut errors = errors.borrow_mut();
+        if errors.len() >= MAX_RECORDED_ERRORS {
+            errors.remove(0);
+        }
+        errors.push((err.code(), err.to_string()));
+    });
+}
+
+/// Wraps the REST server, and the services it creates per connection, to add the error codes.
+#[derive(Clone)]
+pub struct ApiErrorCodeService<S>(S);
+
+impl<S> ApiErrorCodeService<S> {
+    pub fn new(service: S) -> Self {
+        Self(service)
+    }
+}
+
+impl<'a, T, S> tower_service::Service<&'a T> for ApiErrorCodeService<S>
+where
+    S: tower_service::Service<&'a T>,
+{
+    type Response = ApiErrorCodeService<S::Response>;
+    type Error = S::Error;
+    type Future = futures::future::MapOk<S::Future, fn(S::Response) -> Self::Response>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, connection: &'a T) -> Self::Future {
+        self.0
+            .call(connection)
+            .map_ok(ApiErrorCodeService::new as fn(S::Response) -> Self::Response)
+    }
+}
+
+impl<S> tower_service::Service<Request<Body>> for ApiErrorCodeService<S>
+where
+    S: tower_service::Service<Request<Body>, Response = Response<Body>, Error = Error>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Response<Body>, Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let future = self.0.call(request);
+        Box::pin(REQUEST_ERRORS.scope(RefCell::new(Vec::new()), async move {
+            let response = future.await?;
+            let errors = REQUEST_ERRORS.with(|errors| errors.take());
+            add_error_code(response, &errors).await
+        }))
+    }
+}
+
+fn is_error_status(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+/// Returns the message of an error response body, in the plain text (JSON formatter) or ExtJS
+/// format.
+fn error_message(status: StatusCode, body: &[u8]) -> Option<(Value, String)> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(data) if data.is_object() => {
+            if !is_error_status(status) && data["success"] != Value::Bool(false) {
+                return None;
+            }
+            let message = data["message"].as_str()?.to_string();
+            Some((data, message))
+        }
+        _ if is_error_status(status) => {
+            let message = String::from_utf8_lossy(body).into_owned();
+            Some((json!({ "message": message }), message))
+        }
+        _ => None,
+    }
+}
+
+async fn add_error_code(
+    response: Response<Body>,
+    errors: &[(ApiErrorCode, String)],
+) -> Result<Response<Body>, Error> {
+    if errors.is_empty() {
+        return Ok(response);
+    }
+
+    // only the ExtJS formatter reports errors with a success status, in small JSON documents
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or(false);
+    let small = hyper::body::HttpBody::size_hint(response.body())
+        .upper()
+        .map(|size| size <= MAX_EXTJS_ERROR_SIZE)
+        .unwrap_or(false);
+    let status = response.status();
+    if !is_error_status(status) && !(status.is_success() && is_json && small) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+
+    let (mut data, message) = match error_message(parts.status, &body) {
+        Some(result) => result,
+        None => return Ok(Response::from_parts(parts, body.into())),
+    };
+    let code = match errors
+        .iter()
+        .rev()
+        .find(|(_, error)| error.trim_end() == message.trim_end())
+    {
+        Some((code, _)) => *code,
+        None => return Ok(Response::from_parts(parts, body.into())),
+    };
+
+    data["code"] = code.to_string().into();
+    // errors not created as HTTP errors get a generic status
+    if parts.status == StatusCode::BAD_REQUEST {
+        parts.status = StatusCode::from_u16(code.http_status()).unwrap_or(parts.status);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json;charset=UTF-8"),
+    );
+
+    Ok(Response::from_parts(parts, data.to_string().into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error_response(status: StatusCode, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json;charset=UTF-8")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn response_json(response: Response<Body>) -> (StatusCode, Value) {
+        let status = response.status();
+        let body =
+            proxmox_async::runtime::block_on(hyper::body::to_bytes(response.into_body())).unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn test_add_error_code() {
+        let errors = vec![
+            (ApiErrorCode::LockContention, "group is locked".to_string()),
+            (
+                ApiErrorCode::NotFound,
+                "no such datastore 'test'".to_string(),
+            ),
+        ];
+
+        // JSON formatter, plain message with a generic status
+        let response = error_response(StatusCode::BAD_REQUEST, "no such datastore 'test'");
+        let response = proxmox_async::runtime::block_on(add_error_code(response, &errors));
+        let (status, data) = response_json(response.unwrap());
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(data["message"], "no such datastore 'test'");
+        assert_eq!(data["code"], "not-found");
+
+        // ExtJS formatter, the status is kept
+        let body = json!({ "success": false, "message": "group is locked\n" });
+        let response = error_response(StatusCode::OK, &body.to_string());
+        let response = proxmox_async::runtime::block_on(add_error_code(response, &errors));
+        let (status, data) = response_json(response.unwrap());
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(data["message"], "group is locked\n");
+        assert_eq!(data["code"], "lock-contention");
+
+        // other errors and successful responses are passed through
+        let response = error_response(StatusCode::FORBIDDEN, "permission check failed");
+        let response = proxmox_async::runtime::block_on(add_error_code(response, &errors));
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = proxmox_async::runtime::block_on(hyper::body::to_bytes(response.into_body()));
+        assert_eq!(&body.unwrap()[..], b"permission check failed");
+
+        let body = json!({ "success": true, "data": "group is locked" });
+        let response = error_response(StatusCode::OK, &body.to_string());
+        let response = proxmox_async::runtime::block_on(add_error_code(response, &errors));
+        let (_, data) = response_json(response.unwrap());
+        assert!(data.get("code").is_none());
+    }
+}
@@ -0,0 +1,205 @@
+//! Audit log for configuration changes done via the API.
+//!
+//! Every entry is a single line of JSON, appended to [`pbs_buildcfg::API_AUDIT_LOG_FN`]. The file
+//! is rotated together with the API access and authentication logs.
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+use anyhow::{format_err, Error};
+use serde_json::Value;
+
+use proxmox_sys::fs::fchown;
+use proxmox_sys::logrotate::LogRotate;
+
+use pbs_api_types::{AuditLogChange, AuditLogEntry, Authid};
+
+const MASKED_VALUE: &str = "\"********\"";
+
+fn is_sensitive_property(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("password") || name.contains("secret") || name == "key"
+}
+
+/// Compare two serialized configuration objects property by property.
+///
+/// Values of sensitive properties (passwords, secrets) are masked, so only the fact that they
+/// changed ends up in the log.
+pub fn config_changes(before: Option<&Value>, after: Option<&Value>) -> Vec<AuditLogChange> {
+    let empty = serde_json::Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let properties: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+    properties
+        .into_iter()
+        .filter_map(|property| {
+            let old = before.get(property).filter(|value| !value.is_null());
+            let new = after.get(property).filter(|value| !value.is_null());
+            if old == new {
+                return None;
+            }
+            let format = |value: &Value| {
+                if is_sensitive_property(property) {
+                    MASKED_VALUE.to_string()
+                } else {
+                    value.to_string()
+                }
+            };
+            Some(AuditLogChange {
+                property: property.clone(),
+                old: old.map(format),
+                new: new.map(format),
+            })
+        })
+        .collect()
+}
+
+fn append_entry(entry: &AuditLogEntry) -> Result<(), Error> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o640)
+        .open(pbs_buildcfg::API_AUDIT_LOG_FN)?;
+
+    // the log is rotated and read by the unprivileged proxy
+    let backup_user = pbs_config::backup_user()?;
+    fchown(
+        file.as_raw_fd(),
+        Some(backup_user.uid),
+        Some(backup_user.gid),
+    )
+    .map_err(|err| format_err!("fchown failed - {}", err))?;
+
+    file.write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+/// Record a configuration change in the audit log.
+///
+/// `before` and `after` are the serialized configuration objects, `None` if the object was
+/// created or removed. `digest_before` is the digest of `config_file` before the change, the
+/// digest after the change is computed from the saved file. Errors are only logged, the change
+/// itself already happened at this point.
+pub fn audit_config_change(
+    auth_id: &Authid,
+    path: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+    config_file: &str,
+    digest_before: Option<&[u8; 32]>,
+) {
+    let digest_after = match proxmox_sys::fs::file_read_optional_string(config_file) {
+        Ok(content) => Some(openssl::sha::sha256(content.unwrap_or_default().as_bytes())),
+        Err(err) => {
+            log::warn!("unable to compute digest of {config_file} - {err}");
+            None
+        }
+    };
+
+    let entry = AuditLogEntry {
+        time: proxmox_time::epoch_i64(),
+        auth_id: auth_id.to_string(),
+        path: path.to_string(),
+        changes: config_changes(before.as_ref(), after.as_ref()),
+        digest_before: digest_before.map(hex::encode),
+        digest_after: digest_after.map(hex::encode),
+    };
+
+    if let Err(err) = append_entry(&entry) {
+        log::error!("unable to write audit log entry for '{path}' - {err}");
+    }
+}
+
+/// Read the audit log entries in the time range `since..=until`, oldest first.
+///
+/// Includes the already rotated log files.
+pub fn read_audit_log(since: Option<i64>, until: Option<i64>) -> Result<Vec<AuditLogEntry>, Error> {
+    let logrotate = LogRotate::new(pbs_buildcfg::API_AUDIT_LOG_FN, true, None, None)?;
+
+    let mut list = Vec::new();
+
+    // newest file first
+    for file in logrotate.files() {
+        let mut oldest = None;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry: AuditLogEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::warn!("skipping invalid audit log line - {err}");
+                    continue;
+                }
+            };
+            oldest = Some(oldest.map_or(entry.time, |oldest: i64| oldest.min(entry.time)));
+            if since.map_or(false, |since| entry.time < since)
+                || until.map_or(false, |until| entry.time > until)
+            {
+                continue;
+            }
+            list.push(entry);
+        }
+        if let (Some(since), Some(oldest)) = (since, oldest) {
+            if oldest < since {
+                break;
+            }
+        }
+    }
+
+    list.sort_by_key(|entry| entry.time);
+
+    Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_config_changes() {
+        let before = json!({
+            "name": "store1",
+            "comment": "old",
+            "password": "foo",
+            "keep-last": 3,
+        });
+        let after = json!({
+            "name": "store1",
+            "password": "bar",
+            "keep-last": 5,
+            "gc-schedule": "daily",
+        });
+
+        let changes = config_changes(Some(&before), Some(&after));
+        let changes: Vec<(&str, Option<&str>, Option<&str>)> = changes
+            .iter()
+            .map(|c| (c.property.as_str(), c.old.as_deref(), c.new.as_deref()))
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                ("comment", Some("\"old\""), None),
+                ("gc-schedule", None, Some("\"daily\"")),
+                ("keep-last", Some("3"), Some("5")),
+                ("password", Some(MASKED_VALUE), Some(MASKED_VALUE)),
+            ]
+        );
+
+        let changes = config_changes(None, Some(&json!({ "name": "store1" })));
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].old, None);
+    }
+}
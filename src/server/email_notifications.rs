@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use anyhow::Error;
 use serde_json::json;
 
@@ -7,14 +9,35 @@ use handlebars::{
 
 use proxmox_human_byte::HumanByte;
 use proxmox_lang::try_block;
+use proxmox_rest_server::TaskState;
 use proxmox_schema::ApiType;
 use proxmox_sys::email::sendmail;
 
 use pbs_api_types::{
-    APTUpdateInfo, DataStoreConfig, DatastoreNotify, GarbageCollectionStatus, Notify,
+    APTUpdateInfo, DataStoreConfig, DatastoreNotify, DatastoreNotifyTarget,
+    GarbageCollectionStatus, JobSummary, Notify, NotifyEvent, NotifySeverity, SyncDirection,
     SyncJobConfig, TapeBackupJobSetup, User, Userid, VerificationJobConfig,
 };
 
+const JOB_SUMMARY_TEMPLATE: &str = r###"
+Succeeded: {{summary.succeeded}} {{summary.items}}
+Failed:    {{summary.failed}} {{summary.items}}
+Skipped:   {{summary.skipped}} {{summary.items}}
+{{#if summary.bytes ~}}
+Bytes:     {{human-bytes summary.bytes}}
+{{/if ~}}
+Duration:  {{summary.duration}}
+{{#if summary.failures}}
+First failures:
+{{#each summary.failures}}
+  {{this.item}}: {{this.error~}}
+{{/each}}
+{{#if summary.omitted-failures}}
+  ... and {{summary.omitted-failures}} more
+{{/if}}
+{{/if}}
+"###;
+
 const GC_OK_TEMPLATE: &str = r###"
 
 Datastore:            {{datastore}}
@@ -33,7 +56,7 @@ On-Disk usage:        {{human-bytes status.disk-bytes}} ({{relative-percentage s
 On-Disk chunks:       {{status.disk-chunks}}
 
 Deduplication Factor: {{deduplication-factor}}
-
+{{> job_summary}}
 Garbage collection successful.
 
 
@@ -46,7 +69,7 @@ Please visit the web interface for further details:
 const GC_ERR_TEMPLATE: &str = r###"
 
 Datastore: {{datastore}}
-
+{{> job_summary}}
 Garbage collection failed: {{error}}
 
 
@@ -60,7 +83,7 @@ const VERIFY_OK_TEMPLATE: &str = r###"
 
 Job ID:    {{job.id}}
 Datastore: {{job.store}}
-
+{{> job_summary}}
 Verification successful.
 
 
@@ -74,12 +97,8 @@ const VERIFY_ERR_TEMPLATE: &str = r###"
 
 Job ID:    {{job.id}}
 Datastore: {{job.store}}
-
-Verification failed on these snapshots/groups:
-
-{{#each errors}}
-  {{this~}}
-{{/each}}
+{{> job_summary}}
+Verification failed, please check the task log for the full list of failed snapshots/groups.
 
 
 Please visit the web interface for further details:
@@ -97,7 +116,7 @@ Remote:             {{job.remote}}
 Remote Store:       {{job.remote-store}}
 {{else~}}
 Local Source Store: {{job.remote-store}}
-{{/if}}
+{{/if}}{{> job_summary}}
 Synchronization successful.
 
 
@@ -116,7 +135,7 @@ Remote:             {{job.remote}}
 Remote Store:       {{job.remote-store}}
 {{else~}}
 Local Source Store: {{job.remote-store}}
-{{/if}}
+{{/if}}{{> job_summary}}
 Synchronization failed: {{error}}
 
 
@@ -173,7 +192,7 @@ Job ID:     {{id}}
 {{/if~}}
 Datastore:  {{job.store}}
 Tape Pool:  {{job.pool}}
-Tape Drive: {{job.drive}}
+Tape Drive: {{job.drive}}{{#each job.extra-drives}}, {{this}}{{/each}}
 
 {{#if snapshot-list ~}}
 Snapshots included:
@@ -205,7 +224,7 @@ Job ID:     {{id}}
 {{/if~}}
 Datastore:  {{job.store}}
 Tape Pool:  {{job.pool}}
-Tape Drive: {{job.drive}}
+Tape Drive: {{job.drive}}{{#each job.extra-drives}}, {{this}}{{/each}}
 
 {{#if snapshot-list ~}}
 Snapshots included:
@@ -253,6 +272,8 @@ lazy_static::lazy_static! {
             hb.register_helper("human-bytes", Box::new(handlebars_humam_bytes_helper));
             hb.register_helper("relative-percentage", Box::new(handlebars_relative_percentage_helper));
 
+            hb.register_partial("job_summary", JOB_SUMMARY_TEMPLATE)?;
+
             hb.register_template_string("gc_ok_template", GC_OK_TEMPLATE)?;
             hb.register_template_string("gc_err_template", GC_ERR_TEMPLATE)?;
 
@@ -294,7 +315,24 @@ pub struct TapeBackupJobSummary {
     pub used_tapes: Option<Vec<String>>,
 }
 
-fn send_job_status_mail(email: &str, subject: &str, text: &str) -> Result<(), Error> {
+/// Template data of a [`JobSummary`], `items` names what the job counts.
+fn job_summary_data(summary: &JobSummary, items: &str) -> serde_json::Value {
+    let duration: proxmox_time::TimeSpan =
+        std::time::Duration::from_secs_f64(summary.duration).into();
+
+    json!({
+        "items": items,
+        "succeeded": summary.succeeded,
+        "failed": summary.failed,
+        "skipped": summary.skipped,
+        "duration": duration.to_string(),
+        "bytes": summary.bytes,
+        "failures": summary.failures,
+        "omitted-failures": summary.omitted_failures(),
+    })
+}
+
+fn send_job_status_mail(recipients: &[String], subject: &str, text: &str) -> Result<(), Error> {
     let (config, _) = crate::config::node::config()?;
     let from = config.email_from;
 
@@ -306,8 +344,10 @@ fn send_job_status_mail(email: &str, subject: &str, text: &str) -> Result<(), Er
 
     let author = format!("Proxmox Backup Server - {nodename}");
 
+    let recipients: Vec<&str> = recipients.iter().map(String::as_str).collect();
+
     sendmail(
-        &[email],
+        &recipients,
         subject,
         Some(text),
         Some(&html),
@@ -319,19 +359,16 @@ fn send_job_status_mail(email: &str, subject: &str, text: &str) -> Result<(), Er
 }
 
 pub fn send_gc_status(
-    email: &str,
-    notify: DatastoreNotify,
+    notification: &DatastoreNotification,
     datastore: &str,
     status: &GarbageCollectionStatus,
     result: &Result<(), Error>,
+    severity: NotifySeverity,
+    summary: &JobSummary,
 ) -> Result<(), Error> {
-    match notify.gc {
-        None => { /* send notifications by default */ }
-        Some(notify) => {
-            if notify == Notify::Never || (result.is_ok() && notify == Notify::Error) {
-                return Ok(());
-            }
-        }
+    let recipients = notification.recipients(NotifyEvent::Gc, severity);
+    if recipients.is_empty() {
+        return Ok(());
     }
 
     let (fqdn, port) = get_server_url();
@@ -339,6 +376,7 @@ pub fn send_gc_status(
         "datastore": datastore,
         "fqdn": fqdn,
         "port": port,
+        "summary": job_summary_data(summary, "chunks"),
     });
 
     let text = match result {
@@ -365,48 +403,38 @@ pub fn send_gc_status(
         Err(_) => format!("Garbage Collect Datastore '{datastore}' failed"),
     };
 
-    send_job_status_mail(email, &subject, &text)?;
+    send_job_status_mail(&recipients, &subject, &text)?;
 
     Ok(())
 }
 
 pub fn send_verify_status(
-    email: &str,
-    notify: DatastoreNotify,
+    notification: &DatastoreNotification,
     job: VerificationJobConfig,
     result: &Result<Vec<String>, Error>,
+    severity: NotifySeverity,
+    summary: &JobSummary,
 ) -> Result<(), Error> {
     let (fqdn, port) = get_server_url();
-    let mut data = json!({
+    let data = json!({
         "job": job,
         "fqdn": fqdn,
         "port": port,
+        "summary": job_summary_data(summary, "snapshots"),
     });
 
-    let mut result_is_ok = false;
-
     let text = match result {
-        Ok(errors) if errors.is_empty() => {
-            result_is_ok = true;
-            HANDLEBARS.render("verify_ok_template", &data)?
-        }
-        Ok(errors) => {
-            data["errors"] = json!(errors);
-            HANDLEBARS.render("verify_err_template", &data)?
-        }
+        Ok(errors) if errors.is_empty() => HANDLEBARS.render("verify_ok_template", &data)?,
+        Ok(_) => HANDLEBARS.render("verify_err_template", &data)?,
         Err(_) => {
             // aborted job - do not send any email
             return Ok(());
         }
     };
 
-    match notify.verify {
-        None => { /* send notifications by default */ }
-        Some(notify) => {
-            if notify == Notify::Never || (result_is_ok && notify == Notify::Error) {
-                return Ok(());
-            }
-        }
+    let recipients = notification.recipients(NotifyEvent::Verify, severity);
+    if recipients.is_empty() {
+        return Ok(());
     }
 
     let subject = match result {
@@ -414,7 +442,7 @@ pub fn send_verify_status(
         _ => format!("Verify Datastore '{}' failed", job.store),
     };
 
-    send_job_status_mail(email, &subject, &text)?;
+    send_job_status_mail(&recipients, &subject, &text)?;
 
     Ok(())
 }
@@ -423,14 +451,11 @@ pub fn send_prune_status(
     store: &str,
     jobname: &str,
     result: &Result<(), Error>,
+    severity: NotifySeverity,
 ) -> Result<(), Error> {
-    let (email, notify) = match lookup_datastore_notify_settings(store) {
-        (Some(email), notify) => (email, notify),
-        (None, _) => return Ok(()),
-    };
-
-    let notify_prune = notify.prune.unwrap_or(Notify::Error);
-    if notify_prune == Notify::Never || (result.is_ok() && notify_prune == Notify::Error) {
+    let notification = lookup_datastore_notify_settings(store);
+    let recipients = notification.recipients(NotifyEvent::Prune, severity);
+    if recipients.is_empty() {
         return Ok(());
     }
 
@@ -455,31 +480,35 @@ pub fn send_prune_status(
         Err(_) => format!("Pruning datastore '{store}' failed"),
     };
 
-    send_job_status_mail(&email, &subject, &text)?;
+    send_job_status_mail(&recipients, &subject, &text)?;
 
     Ok(())
 }
 
 pub fn send_sync_status(
-    email: &str,
-    notify: DatastoreNotify,
+    notification: &DatastoreNotification,
     job: &SyncJobConfig,
     result: &Result<(), Error>,
+    severity: NotifySeverity,
+    summary: &JobSummary,
 ) -> Result<(), Error> {
-    match notify.sync {
-        None => { /* send notifications by default */ }
-        Some(notify) => {
-            if notify == Notify::Never || (result.is_ok() && notify == Notify::Error) {
-                return Ok(());
-            }
-        }
+    let recipients = notification.recipients(NotifyEvent::Sync, severity);
+    if recipients.is_empty() {
+        return Ok(());
     }
 
+    // pull counts groups, push the pushed snapshots
+    let items = match job.sync_direction() {
+        SyncDirection::Pull => "groups",
+        SyncDirection::Push => "snapshots",
+    };
+
     let (fqdn, port) = get_server_url();
     let mut data = json!({
         "job": job,
         "fqdn": fqdn,
         "port": port,
+        "summary": job_summary_data(summary, items),
     });
 
     let text = match result {
@@ -503,18 +532,33 @@ pub fn send_sync_status(
         Err(_) => format!("{} datastore '{}' failed", source_str, job.remote_store,),
     };
 
-    send_job_status_mail(email, &subject, &text)?;
+    send_job_status_mail(&recipients, &subject, &text)?;
 
     Ok(())
 }
 
+/// Send the tape backup job status.
+///
+/// Without notification targets for tape jobs on the datastore, the mail goes to `email` (the
+/// address of the job's notify user).
 pub fn send_tape_backup_status(
-    email: &str,
+    email: Option<&str>,
     id: Option<&str>,
     job: &TapeBackupJobSetup,
     result: &Result<(), Error>,
+    severity: NotifySeverity,
     summary: TapeBackupJobSummary,
 ) -> Result<(), Error> {
+    let notification = lookup_datastore_notify_settings(&job.store);
+    let recipients = if notification.has_targets(NotifyEvent::Tape) {
+        notification.recipients(NotifyEvent::Tape, severity)
+    } else {
+        email.map(String::from).into_iter().collect()
+    };
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
     let (fqdn, port) = get_server_url();
     let duration: proxmox_time::TimeSpan = summary.duration.into();
     let mut data = json!({
@@ -542,7 +586,7 @@ pub fn send_tape_backup_status(
         (Err(_), None) => format!("Tape Backup datastore '{}' failed", job.store,),
     };
 
-    send_job_status_mail(email, &subject, &text)?;
+    send_job_status_mail(&recipients, &subject, &text)?;
 
     Ok(())
 }
@@ -579,7 +623,7 @@ pub fn send_load_media_email(
     }
     let _ = writeln!(text, "Media: {label_text}");
 
-    send_job_status_mail(to, &subject, &text)
+    send_job_status_mail(&[to.to_string()], &subject, &text)
 }
 
 fn get_server_url() -> (String, usize) {
@@ -617,7 +661,7 @@ pub fn send_updates_available(updates: &[&APTUpdateInfo]) -> Result<(), Error> {
             }),
         )?;
 
-        send_job_status_mail(&email, &subject, &text)?;
+        send_job_status_mail(&[email], &subject, &text)?;
     }
     Ok(())
 }
@@ -643,7 +687,7 @@ pub fn send_certificate_renewal_mail(result: &Result<(), Error>) -> Result<(), E
 
         let subject = "Could not renew certificate";
 
-        send_job_status_mail(&email, subject, &text)?;
+        send_job_status_mail(&[email], subject, &text)?;
     }
 
     Ok(())
@@ -660,10 +704,110 @@ pub fn lookup_user_email(userid: &Userid) -> Option<String> {
     None
 }
 
-/// Lookup Datastore notify settings
-pub fn lookup_datastore_notify_settings(store: &str) -> (Option<String>, DatastoreNotify) {
-    let mut email = None;
+/// Notification settings of a datastore
+pub struct DatastoreNotification {
+    /// Mail address of the 'notify-user' (or root@pam)
+    email: Option<String>,
+    /// Legacy per job type notification settings
+    notify: DatastoreNotify,
+    /// Notification targets, used instead of the above for events with at least one target
+    targets: Vec<(NotifyEvent, DatastoreNotifyTarget)>,
+}
+
+impl DatastoreNotification {
+    fn new(email: Option<String>, notify: DatastoreNotify, targets: &[String]) -> Self {
+        let targets = targets
+            .iter()
+            .filter_map(|target| match target.parse::<DatastoreNotifyTarget>() {
+                Ok(target) => match target.event_type() {
+                    Some(event) => Some((event, target)),
+                    None => {
+                        log::warn!(
+                            "ignoring notification target for unknown event '{}'",
+                            target.event
+                        );
+                        None
+                    }
+                },
+                Err(err) => {
+                    log::warn!("ignoring invalid notification target '{target}' - {err}");
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            email,
+            notify,
+            targets,
+        }
+    }
+
+    /// Returns true if there are notification targets configured for `event`.
+    pub fn has_targets(&self, event: NotifyEvent) -> bool {
+        self.targets.iter().any(|(ev, _)| *ev == event)
+    }
+
+    /// Returns the deduplicated mail addresses to notify about an `event` of `severity`.
+    pub fn recipients(&self, event: NotifyEvent, severity: NotifySeverity) -> Vec<String> {
+        self.recipients_with(event, severity, lookup_user_email)
+    }
+
+    fn recipients_with(
+        &self,
+        event: NotifyEvent,
+        severity: NotifySeverity,
+        lookup_email: impl Fn(&Userid) -> Option<String>,
+    ) -> Vec<String> {
+        if !self.has_targets(event) {
+            return self.legacy_recipients(event, severity);
+        }
+
+        let recipients: BTreeSet<String> = self
+            .targets
+            .iter()
+            .filter(|(ev, target)| *ev == event && severity >= target.min_severity())
+            .filter_map(|(_, target)| lookup_email(&target.target))
+            .collect();
+
+        recipients.into_iter().collect()
+    }
+
+    fn legacy_recipients(&self, event: NotifyEvent, severity: NotifySeverity) -> Vec<String> {
+        let notify = match event {
+            NotifyEvent::Gc => self.notify.gc.unwrap_or(Notify::Always),
+            NotifyEvent::Verify => self.notify.verify.unwrap_or(Notify::Always),
+            NotifyEvent::Sync => self.notify.sync.unwrap_or(Notify::Always),
+            NotifyEvent::Prune => self.notify.prune.unwrap_or(Notify::Error),
+            // tape jobs have their own notify user
+            NotifyEvent::Tape => return Vec::new(),
+        };
+
+        let send = match notify {
+            Notify::Never => false,
+            Notify::Always => true,
+            Notify::Error => severity == NotifySeverity::Error,
+        };
+
+        match &self.email {
+            Some(email) if send => vec![email.clone()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The notification severity of a finished job task. Tasks which only logged warnings are
+/// reported with [`NotifySeverity::Warning`].
+pub fn task_severity(state: &TaskState) -> NotifySeverity {
+    match state {
+        TaskState::OK { .. } => NotifySeverity::Info,
+        TaskState::Warning { .. } => NotifySeverity::Warning,
+        TaskState::Error { .. } | TaskState::Unknown { .. } => NotifySeverity::Error,
+    }
+}
 
+/// Lookup Datastore notify settings
+pub fn lookup_datastore_notify_settings(store: &str) -> DatastoreNotification {
     let notify = DatastoreNotify {
         gc: None,
         verify: None,
@@ -673,28 +817,32 @@ pub fn lookup_datastore_notify_settings(store: &str) -> (Option<String>, Datasto
 
     let (config, _digest) = match pbs_config::datastore::config() {
         Ok(result) => result,
-        Err(_) => return (email, notify),
+        Err(_) => return DatastoreNotification::new(None, notify, &[]),
     };
 
     let config: DataStoreConfig = match config.lookup("datastore", store) {
         Ok(result) => result,
-        Err(_) => return (email, notify),
+        Err(_) => return DatastoreNotification::new(None, notify, &[]),
     };
 
-    email = match config.notify_user {
+    let email = match config.notify_user {
         Some(ref userid) => lookup_user_email(userid),
         None => lookup_user_email(Userid::root_userid()),
     };
 
     let notify_str = config.notify.unwrap_or_default();
 
-    if let Ok(value) = DatastoreNotify::API_SCHEMA.parse_property_string(&notify_str) {
-        if let Ok(notify) = serde_json::from_value(value) {
-            return (email, notify);
-        }
-    }
-
-    (email, notify)
+    let notify = DatastoreNotify::API_SCHEMA
+        .parse_property_string(&notify_str)
+        .ok()
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or(notify);
+
+    DatastoreNotification::new(
+        email,
+        notify,
+        config.notify_target.as_deref().unwrap_or_default(),
+    )
 }
 
 // Handlerbar helper functions
@@ -761,3 +909,214 @@ fn test_template_register() {
 
     assert!(HANDLEBARS.has_template("certificate_renewal_err_template"));
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pbs_api_types::JOB_SUMMARY_MAX_FAILURES;
+
+    fn no_notify() -> DatastoreNotify {
+        DatastoreNotify {
+            gc: None,
+            verify: None,
+            sync: None,
+            prune: None,
+        }
+    }
+
+    fn test_email(userid: &Userid) -> Option<String> {
+        match userid.as_str() {
+            "nomail@pbs" => None,
+            "alias@pbs" | "alias2@pbs" => Some("ops@example.com".to_string()),
+            other => Some(format!("{}@example.com", other.replace('@', "."))),
+        }
+    }
+
+    fn recipients(
+        notification: &DatastoreNotification,
+        event: NotifyEvent,
+        severity: NotifySeverity,
+    ) -> Vec<String> {
+        notification.recipients_with(event, severity, test_email)
+    }
+
+    #[test]
+    fn test_job_summary_rendering() -> Result<(), Error> {
+        let mut summary = JobSummary {
+            succeeded: 5,
+            skipped: 1,
+            duration: 90.0,
+            ..Default::default()
+        };
+        for i in 0..JOB_SUMMARY_MAX_FAILURES + 2 {
+            summary.add_failure(format!("vm/{i}/2024-01-01T00:00:00Z"), "chunk missing");
+        }
+
+        let data = json!({
+            "job": { "id": "v1", "store": "store1" },
+            "fqdn": "pbs.example.com",
+            "port": 8007,
+            "summary": job_summary_data(&summary, "snapshots"),
+        });
+        let text = HANDLEBARS.render("verify_err_template", &data)?;
+
+        assert!(text.contains("Succeeded: 5 snapshots\n"));
+        assert!(text.contains("Failed:    12 snapshots\n"));
+        assert!(text.contains("Skipped:   1 snapshots\n"));
+        let duration: proxmox_time::TimeSpan = std::time::Duration::from_secs(90).into();
+        assert!(text.contains(&format!("Duration:  {duration}\n")));
+        assert!(!text.contains("Bytes:"));
+        assert!(text.contains("First failures:\n  vm/0/2024-01-01T00:00:00Z: chunk missing"));
+        assert!(!text.contains("vm/10/"));
+        assert!(text.contains("... and 2 more"));
+
+        // without failures, only the counts are listed
+        let summary = JobSummary {
+            succeeded: 7,
+            bytes: Some(2 * 1024 * 1024),
+            ..Default::default()
+        };
+        let data = json!({
+            "datastore": "store1",
+            "error": "interrupted",
+            "fqdn": "pbs.example.com",
+            "port": 8007,
+            "summary": job_summary_data(&summary, "chunks"),
+        });
+        let text = HANDLEBARS.render("gc_err_template", &data)?;
+        assert!(text.contains("Succeeded: 7 chunks\n"));
+        let bytes = HumanByte::from(2 * 1024 * 1024u64);
+        assert!(text.contains(&format!("Bytes:     {bytes}\n")));
+        assert!(!text.contains("First failures:"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_fallback() {
+        let email = Some("root@example.com".to_string());
+
+        let notification = DatastoreNotification::new(email.clone(), no_notify(), &[]);
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Gc, NotifySeverity::Info),
+            vec!["root@example.com"],
+        );
+        // prune defaults to errors only
+        assert!(recipients(&notification, NotifyEvent::Prune, NotifySeverity::Info).is_empty());
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Prune, NotifySeverity::Error),
+            vec!["root@example.com"],
+        );
+        // tape jobs use their own notify user
+        assert!(recipients(&notification, NotifyEvent::Tape, NotifySeverity::Error).is_empty());
+
+        let notify = DatastoreNotify {
+            gc: Some(Notify::Never),
+            verify: Some(Notify::Error),
+            ..no_notify()
+        };
+        let notification = DatastoreNotification::new(email, notify, &[]);
+        assert!(recipients(&notification, NotifyEvent::Gc, NotifySeverity::Error).is_empty());
+        assert!(recipients(&notification, NotifyEvent::Verify, NotifySeverity::Info).is_empty());
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Verify, NotifySeverity::Error),
+            vec!["root@example.com"],
+        );
+
+        let notification = DatastoreNotification::new(None, no_notify(), &[]);
+        assert!(recipients(&notification, NotifyEvent::Gc, NotifySeverity::Error).is_empty());
+    }
+
+    #[test]
+    fn test_notify_targets() {
+        let targets = [
+            "event=gc,target=alias@pbs".to_string(),
+            "event=gc,severity=info,target=alias2@pbs".to_string(),
+            "event=verify,severity=warning,target=admin@pbs".to_string(),
+            "event=verify,target=nomail@pbs".to_string(),
+        ];
+        let notify = DatastoreNotify {
+            gc: Some(Notify::Never),
+            ..no_notify()
+        };
+        let notification =
+            DatastoreNotification::new(Some("root@example.com".to_string()), notify, &targets);
+
+        // targets take precedence over 'notify', recipients are deduplicated
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Gc, NotifySeverity::Error),
+            vec!["ops@example.com"],
+        );
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Gc, NotifySeverity::Info),
+            vec!["ops@example.com"],
+        );
+
+        assert!(recipients(&notification, NotifyEvent::Verify, NotifySeverity::Info).is_empty());
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Verify, NotifySeverity::Warning),
+            vec!["admin.pbs@example.com"],
+        );
+
+        // events without targets still use the legacy settings
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Sync, NotifySeverity::Info),
+            vec!["root@example.com"],
+        );
+    }
+
+    #[test]
+    fn test_task_severity() {
+        assert_eq!(
+            task_severity(&TaskState::OK { endtime: 0 }),
+            NotifySeverity::Info
+        );
+        assert_eq!(
+            task_severity(&TaskState::Warning {
+                count: 2,
+                endtime: 0
+            }),
+            NotifySeverity::Warning,
+        );
+        assert_eq!(
+            task_severity(&TaskState::Error {
+                message: "failed".to_string(),
+                endtime: 0,
+            }),
+            NotifySeverity::Error,
+        );
+    }
+
+    #[test]
+    fn test_unknown_notify_targets() {
+        let targets = [
+            "event=unknown-event,target=admin@pbs".to_string(),
+            "not a property string".to_string(),
+        ];
+        let notification =
+            DatastoreNotification::new(Some("root@example.com".to_string()), no_notify(), &targets);
+
+        assert!(notification.targets.is_empty());
+        assert_eq!(
+            recipients(&notification, NotifyEvent::Gc, NotifySeverity::Error),
+            vec!["root@example.com"],
+        );
+    }
+
+    #[test]
+    fn test_parse_config_with_unknown_event() -> Result<(), Error> {
+        let content = "datastore: store1\n\
+            \tpath /backup/store1\n\
+            \tnotify gc=error\n\
+            \tnotify-target event=gc,target=admin@pbs\n\
+            \tnotify-target event=unknown-event,severity=info,target=admin@pbs\n";
+
+        let data = pbs_config::datastore::CONFIG.parse("datastore.cfg", content)?;
+        let config: DataStoreConfig = data.lookup("datastore", "store1")?;
+
+        assert_eq!(config.notify_target.map(|targets| targets.len()), Some(2));
+
+        Ok(())
+    }
+}
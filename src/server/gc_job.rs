@@ -1,13 +1,14 @@
 use anyhow::Error;
 use std::sync::Arc;
+use std::time::Instant;
 
-use proxmox_sys::task_log;
+use proxmox_sys::{task_log, task_warn};
 
-use pbs_api_types::Authid;
+use pbs_api_types::{Authid, JobSummary};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 
-use crate::server::{jobstate::Job, send_gc_status};
+use crate::server::{jobstate::Job, send_gc_status, task_severity};
 
 /// Runs a garbage collection job.
 pub fn do_garbage_collection_job(
@@ -19,7 +20,7 @@ pub fn do_garbage_collection_job(
 ) -> Result<String, Error> {
     let store = datastore.name().to_string();
 
-    let (email, notify) = crate::server::lookup_datastore_notify_settings(&store);
+    let notification = crate::server::lookup_datastore_notify_settings(&store);
 
     let worker_type = job.jobtype().to_string();
     let upid_str = WorkerTask::new_thread(
@@ -28,7 +29,10 @@ pub fn do_garbage_collection_job(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
             job.start(&worker.upid().to_string())?;
+            datastore.set_operation_owner(&worker.upid().to_string());
+            let start_time = Instant::now();
 
             task_log!(worker, "starting garbage collection on store {store}");
             if let Some(event_str) = schedule {
@@ -37,17 +41,45 @@ pub fn do_garbage_collection_job(
 
             let result = datastore.garbage_collection(&*worker, worker.upid());
 
+            if result.is_ok() {
+                task_log!(worker, "recording logical usage of namespaces");
+                if let Err(err) = datastore.update_namespace_usage(&*worker) {
+                    task_warn!(worker, "failed to record namespace usage - {err}");
+                }
+            }
+
+            let gc_status = datastore.last_gc_status();
+
+            // the items of a garbage collection run are the removed and pending chunks
+            let mut summary = JobSummary {
+                duration: start_time.elapsed().as_secs_f64(),
+                ..Default::default()
+            };
+            match result {
+                Ok(()) => {
+                    summary.succeeded = gc_status.removed_chunks as u64;
+                    summary.skipped = gc_status.pending_chunks as u64;
+                    summary.bytes = Some(gc_status.removed_bytes);
+                }
+                Err(ref err) => summary.add_failure(&store, err),
+            }
+
             let status = worker.create_state(&result);
+            let severity = task_severity(&status);
 
-            if let Err(err) = job.finish(status) {
+            if let Err(err) = job.finish_with_summary(status, Some(summary.clone())) {
                 eprintln!("could not finish job state for {}: {err}", job.jobtype());
             }
 
-            if let Some(email) = email {
-                let gc_status = datastore.last_gc_status();
-                if let Err(err) = send_gc_status(&email, notify, &store, &gc_status, &result) {
-                    eprintln!("send gc notification failed: {err}");
-                }
+            if let Err(err) = send_gc_status(
+                &notification,
+                &store,
+                &gc_status,
+                &result,
+                severity,
+                &summary,
+            ) {
+                eprintln!("send gc notification failed: {err}");
             }
 
             result
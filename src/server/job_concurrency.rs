@@ -0,0 +1,151 @@
+//! Serialization of scheduled jobs sharing a concurrency group
+//!
+//! Before the scheduler starts a datastore job (GC, prune, sync, verify or tape backup), it
+//! acquires a slot in the job's concurrency group, which defaults to the name of the datastore
+//! the job works on. Only one scheduled job per group runs at a time, and the node option
+//! `max-datastore-jobs` limits the number of such jobs running node-wide.
+//!
+//! Jobs which do not get a slot are tried again on the next scheduler run, so the order in which
+//! the scheduler handles the job types also decides which job runs first once a slot gets free
+//! (garbage collection, prune, sync, verify and then tape backup jobs). While waiting, this is
+//! recorded next to the job state, see [`Job::set_waiting`]. After `job-concurrency-timeout`
+//! minutes, a job is started anyway.
+//!
+//! Jobs started manually are not affected and do not take a slot.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Error;
+use lazy_static::lazy_static;
+
+use crate::server::jobstate::{self, Job};
+
+/// Default time a job waits for its concurrency group before it is started anyway.
+const DEFAULT_TIMEOUT_MINUTES: u64 = 6 * 60;
+
+lazy_static! {
+    static ref RUNNING_JOBS: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+/// Holds a slot in a concurrency group, released on drop
+pub struct ConcurrencyGuard {
+    group: String,
+}
+
+impl Drop for ConcurrencyGuard {
+    fn drop(&mut self) {
+        let mut running = RUNNING_JOBS.lock().unwrap();
+        if let Some(count) = running.get_mut(&self.group) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.group);
+            }
+        }
+    }
+}
+
+impl ConcurrencyGuard {
+    // `running` is the locked content of `RUNNING_JOBS`
+    fn acquire(running: &mut HashMap<String, usize>, group: &str) -> Self {
+        *running.entry(group.to_string()).or_default() += 1;
+        Self {
+            group: group.to_string(),
+        }
+    }
+}
+
+// A group is busy if a job of it is already running, or if the node-wide limit is reached.
+fn group_busy(running: &HashMap<String, usize>, group: &str, max_jobs: Option<usize>) -> bool {
+    let total: usize = running.values().sum();
+    running.contains_key(group) || max_jobs.map(|max| total >= max).unwrap_or(false)
+}
+
+fn limits() -> (Option<usize>, u64) {
+    match crate::config::node::config() {
+        Ok((config, _digest)) => (
+            config.max_datastore_jobs,
+            config
+                .job_concurrency_timeout
+                .unwrap_or(DEFAULT_TIMEOUT_MINUTES),
+        ),
+        Err(err) => {
+            log::error!("unable to read node config - {err}");
+            (None, DEFAULT_TIMEOUT_MINUTES)
+        }
+    }
+}
+
+/// Try to get a slot in `group` for `job`.
+///
+/// On success, the slot is attached to the job and kept until the job is dropped, and the job's
+/// wait state is removed. Returns false if the job has to wait, which is recorded in the job's
+/// wait state.
+pub fn try_acquire_slot(job: &mut Job, group: &str) -> Result<bool, Error> {
+    let (max_jobs, timeout) = limits();
+
+    let mut running = RUNNING_JOBS.lock().unwrap();
+
+    if group_busy(&running, group, max_jobs) {
+        let now = proxmox_time::epoch_i64();
+        let since = match jobstate::load_wait_state(job.jobtype(), job.jobname())? {
+            Some(state) if state.group == group => state.since,
+            _ => {
+                job.set_waiting(group, now)?;
+                now
+            }
+        };
+
+        if now - since < (timeout * 60) as i64 {
+            return Ok(false);
+        }
+
+        log::warn!(
+            "{} '{}' waited more than {timeout} minutes for concurrency group '{group}', starting it anyway",
+            job.jobtype(),
+            job.jobname(),
+        );
+    }
+
+    let guard = ConcurrencyGuard::acquire(&mut running, group);
+    drop(running);
+
+    job.set_concurrency_guard(guard);
+    job.clear_waiting();
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_group_busy() {
+        let mut running = HashMap::new();
+        assert!(!group_busy(&running, "store1", None));
+        assert!(!group_busy(&running, "store1", Some(1)));
+
+        running.insert("store1".to_string(), 1);
+        assert!(group_busy(&running, "store1", None));
+        assert!(!group_busy(&running, "store2", None));
+        assert!(!group_busy(&running, "store2", Some(2)));
+        assert!(group_busy(&running, "store2", Some(1)));
+    }
+
+    #[test]
+    fn test_concurrency_guard() {
+        let group = "test-concurrency-guard";
+        let is_running = || RUNNING_JOBS.lock().unwrap().get(group).copied();
+
+        let first = ConcurrencyGuard::acquire(&mut RUNNING_JOBS.lock().unwrap(), group);
+        // started anyway after waiting for too long
+        let second = ConcurrencyGuard::acquire(&mut RUNNING_JOBS.lock().unwrap(), group);
+        assert_eq!(is_running(), Some(2));
+
+        drop(first);
+        assert_eq!(is_running(), Some(1));
+        drop(second);
+        assert_eq!(is_running(), None);
+    }
+}
@@ -11,6 +11,10 @@
 //! 'Job' which handles locking and writing to a file
 //! 'JobState' which is the actual state
 //!
+//! Additionally, a scheduled job which has to wait for its concurrency group (see
+//! [`crate::server::job_concurrency`]) records this in a separate 'waiting' file next to the
+//! state file, which is removed again once the job gets a slot, starts or finishes.
+//!
 //! an example usage would be
 //! ```no_run
 //! # use anyhow::{bail, Error};
@@ -46,12 +50,14 @@ use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, Crea
 
 use proxmox_time::CalendarEvent;
 
-use pbs_api_types::{JobScheduleStatus, UPID};
+use pbs_api_types::{JobScheduleStatus, JobSummary, UPID};
 use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
 use pbs_config::{open_backup_lockfile, BackupLockGuard};
 
 use proxmox_rest_server::{upid_read_status, worker_is_active_local, TaskState};
 
+use crate::server::job_concurrency::ConcurrencyGuard;
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 /// Represents the State of a specific Job
@@ -65,9 +71,22 @@ pub enum JobState {
         upid: String,
         state: TaskState,
         updated: Option<i64>,
+        /// Result summary of the run, not available for state files of older versions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        summary: Option<JobSummary>,
     },
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+/// A scheduled job waiting for a free slot in its concurrency group
+pub struct JobWaitState {
+    /// The concurrency group the job waits for
+    pub group: String,
+    /// Time the job started waiting
+    pub since: i64,
+}
+
 /// Represents a Job and holds the correct lock
 pub struct Job {
     jobtype: String,
@@ -75,6 +94,7 @@ pub struct Job {
     /// The State of the job
     pub state: JobState,
     _lock: BackupLockGuard,
+    _concurrency_guard: Option<ConcurrencyGuard>,
 }
 
 const JOB_STATE_BASEDIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/jobstates");
@@ -108,10 +128,17 @@ where
     open_backup_lockfile(&path, None, true)
 }
 
+fn get_wait_path(jobtype: &str, jobname: &str) -> PathBuf {
+    let mut path = get_path(jobtype, jobname);
+    path.set_extension("waiting");
+    path
+}
+
 /// Removes the statefile of a job, this is useful if we delete a job
 pub fn remove_state_file(jobtype: &str, jobname: &str) -> Result<(), Error> {
     let mut path = get_path(jobtype, jobname);
     let _lock = get_lock(&path)?;
+    let _ = std::fs::remove_file(get_wait_path(jobtype, jobname));
     if let Err(err) = std::fs::remove_file(&path) {
         if err.kind() != std::io::ErrorKind::NotFound {
             bail!("cannot remove statefile for {jobtype} - {jobname}: {err}");
@@ -150,10 +177,12 @@ pub fn update_job_last_run_time(jobtype: &str, jobname: &str) -> Result<(), Erro
             upid,
             state,
             updated: _,
+            summary,
         } => JobState::Finished {
             upid,
             state,
             updated: Some(time),
+            summary,
         },
     };
     job.write_state()
@@ -171,8 +200,8 @@ pub fn last_run_time(jobtype: &str, jobname: &str) -> Result<i64, Error> {
         JobState::Started { upid }
         | JobState::Finished {
             upid,
-            state: _,
             updated: None,
+            ..
         } => {
             let upid: UPID = upid
                 .parse()
@@ -182,6 +211,23 @@ pub fn last_run_time(jobtype: &str, jobname: &str) -> Result<i64, Error> {
     }
 }
 
+/// Returns the wait state of a job, if it is currently waiting for its concurrency group
+/// Note that this is not locked
+pub fn load_wait_state(jobtype: &str, jobname: &str) -> Result<Option<JobWaitState>, Error> {
+    match file_read_optional_string(get_wait_path(jobtype, jobname))? {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the concurrency group a job is currently waiting for, ignoring errors
+pub fn waiting_for_group(jobtype: &str, jobname: &str) -> Option<String> {
+    load_wait_state(jobtype, jobname)
+        .ok()
+        .flatten()
+        .map(|state| state.group)
+}
+
 impl JobState {
     /// Loads and deserializes the jobstate from type and name.
     /// When the loaded state indicates a started UPID,
@@ -206,6 +252,7 @@ impl JobState {
                             upid,
                             state,
                             updated: None,
+                            summary: None,
                         })
                     } else {
                         Ok(JobState::Started { upid })
@@ -239,9 +286,42 @@ impl Job {
                 time: proxmox_time::epoch_i64(),
             },
             _lock,
+            _concurrency_guard: None,
         })
     }
 
+    /// Keep the slot of the job's concurrency group until the job is dropped
+    pub fn set_concurrency_guard(&mut self, guard: ConcurrencyGuard) {
+        self._concurrency_guard = Some(guard);
+    }
+
+    /// Record that the job waits for a free slot in its concurrency group
+    pub fn set_waiting(&mut self, group: &str, since: i64) -> Result<(), Error> {
+        let state = JobWaitState {
+            group: group.to_string(),
+            since,
+        };
+        let serialized = serde_json::to_string(&state)?;
+        replace_file(
+            get_wait_path(&self.jobtype, &self.jobname),
+            serialized.as_bytes(),
+            Self::state_file_options()?,
+            false,
+        )
+    }
+
+    /// Remove the wait state of the job, if any
+    pub fn clear_waiting(&mut self) {
+        if let Err(err) = std::fs::remove_file(get_wait_path(&self.jobtype, &self.jobname)) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                log::warn!(
+                    "unable to remove wait state of job {} - {err}",
+                    self.jobname
+                );
+            }
+        }
+    }
+
     /// Start the job and update the statefile accordingly
     /// Fails if the job was already started
     pub fn start(&mut self, upid: &str) -> Result<(), Error> {
@@ -253,12 +333,23 @@ impl Job {
             upid: upid.to_string(),
         };
 
+        self.clear_waiting();
+
         self.write_state()
     }
 
     /// Finish the job and update the statefile accordingly with the given taskstate
     /// Fails if the job was not yet started
     pub fn finish(&mut self, state: TaskState) -> Result<(), Error> {
+        self.finish_with_summary(state, None)
+    }
+
+    /// Like [`Job::finish`], but additionally records the result summary of the run
+    pub fn finish_with_summary(
+        &mut self,
+        state: TaskState,
+        summary: Option<JobSummary>,
+    ) -> Result<(), Error> {
         let upid = match &self.state {
             JobState::Created { .. } => bail!("cannot finish when not started"),
             JobState::Started { upid } => upid,
@@ -270,8 +361,11 @@ impl Job {
             upid,
             state,
             updated: None,
+            summary,
         };
 
+        self.clear_waiting();
+
         self.write_state()
     }
 
@@ -283,20 +377,27 @@ impl Job {
         &self.jobname
     }
 
-    fn write_state(&mut self) -> Result<(), Error> {
-        let serialized = serde_json::to_string(&self.state)?;
-        let path = get_path(&self.jobtype, &self.jobname);
-
+    fn state_file_options() -> Result<CreateOptions, Error> {
         let backup_user = pbs_config::backup_user()?;
         let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
         // set the correct owner/group/permissions while saving file
         // owner(rw) = backup, group(r)= backup
-        let options = CreateOptions::new()
+        Ok(CreateOptions::new()
             .perm(mode)
             .owner(backup_user.uid)
-            .group(backup_user.gid);
+            .group(backup_user.gid))
+    }
+
+    fn write_state(&mut self) -> Result<(), Error> {
+        let serialized = serde_json::to_string(&self.state)?;
+        let path = get_path(&self.jobtype, &self.jobname);
 
-        replace_file(path, serialized.as_bytes(), options, false)
+        replace_file(
+            path,
+            serialized.as_bytes(),
+            Self::state_file_options()?,
+            false,
+        )
     }
 }
 
@@ -304,16 +405,17 @@ pub fn compute_schedule_status(
     job_state: &JobState,
     schedule: Option<&str>,
 ) -> Result<JobScheduleStatus, Error> {
-    let (upid, endtime, state, last) = match job_state {
-        JobState::Created { time } => (None, None, None, *time),
+    let (upid, endtime, state, last, summary) = match job_state {
+        JobState::Created { time } => (None, None, None, *time, None),
         JobState::Started { upid } => {
             let parsed_upid: UPID = upid.parse()?;
-            (Some(upid), None, None, parsed_upid.starttime)
+            (Some(upid), None, None, parsed_upid.starttime, None)
         }
         JobState::Finished {
             upid,
             state,
             updated,
+            summary,
         } => {
             let last = updated.unwrap_or_else(|| state.endtime());
             (
@@ -321,6 +423,7 @@ pub fn compute_schedule_status(
                 Some(state.endtime()),
                 Some(state.to_string()),
                 last,
+                summary.clone(),
             )
         }
     };
@@ -329,6 +432,7 @@ pub fn compute_schedule_status(
         last_run_upid: upid.map(String::from),
         last_run_state: state,
         last_run_endtime: endtime,
+        last_run_summary: summary,
         ..Default::default()
     };
 
@@ -341,3 +445,42 @@ pub fn compute_schedule_status(
 
     Ok(status)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_job_state_without_summary() -> Result<(), Error> {
+        let mut summary = JobSummary {
+            succeeded: 3,
+            duration: 1.5,
+            ..Default::default()
+        };
+        summary.add_failure("vm/100", "broken");
+
+        let state = JobState::Finished {
+            upid: "UPID:pbs:00000001:00000002:00000003:6531a8c0:syncjob:s1:root@pam:".to_string(),
+            state: TaskState::OK {
+                endtime: 1_700_000_000,
+            },
+            updated: None,
+            summary: Some(summary.clone()),
+        };
+
+        let mut data = serde_json::to_value(&state)?;
+        let status = compute_schedule_status(&serde_json::from_value(data.clone())?, None)?;
+        assert_eq!(status.last_run_summary, Some(summary));
+
+        // state files written before summaries were recorded
+        data["finished"].as_object_mut().unwrap().remove("summary");
+        let state: JobState = serde_json::from_value(data)?;
+        assert!(matches!(state, JobState::Finished { summary: None, .. }));
+
+        let status = compute_schedule_status(&state, None)?;
+        assert_eq!(status.last_run_summary, None);
+        assert_eq!(status.last_run_endtime, Some(1_700_000_000));
+
+        Ok(())
+    }
+}
@@ -13,6 +13,8 @@ use pbs_buildcfg;
 
 pub mod jobstate;
 
+pub mod job_concurrency;
+
 mod verify_job;
 pub use verify_job::*;
 
@@ -25,15 +27,24 @@ pub use gc_job::*;
 mod realm_sync_job;
 pub use realm_sync_job::*;
 
+mod remote_check;
+pub use remote_check::*;
+
 mod email_notifications;
 pub use email_notifications::*;
 
 mod report;
 pub use report::*;
 
+pub mod api_error_code;
+
+pub mod audit_log;
+
 pub mod auth;
 
 pub(crate) mod pull;
+pub(crate) mod push;
+pub(crate) mod snapshot_filter;
 
 pub(crate) async fn reload_proxy_certificate() -> Result<(), Error> {
     let proxy_pid = proxmox_rest_server::read_pid(pbs_buildcfg::PROXMOX_BACKUP_PROXY_PID_FN)?;
@@ -92,3 +103,21 @@ pub fn create_active_operations_dir() -> Result<(), Error> {
         .map_err(|err: Error| format_err!("unable to create active operations dir - {err}"))?;
     Ok(())
 }
+
+/// Create the lock registry dir with correct permission.
+pub fn create_lock_registry_dir() -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0750);
+    let options = CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+
+    create_path(
+        pbs_datastore::lock_tracking::LOCK_REGISTRY_DIR,
+        None,
+        Some(options),
+    )
+    .map_err(|err: Error| format_err!("unable to create lock registry dir - {err}"))?;
+    Ok(())
+}
@@ -1,13 +1,16 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Error;
 
 use proxmox_sys::{task_log, task_warn};
 
 use pbs_api_types::{
-    print_store_and_ns, Authid, KeepOptions, Operation, PruneJobOptions, MAX_NAMESPACE_DEPTH,
-    PRIV_DATASTORE_MODIFY, PRIV_DATASTORE_PRUNE,
+    print_store_and_ns, Authid, BackupNamespace, DataStoreConfig, KeepOptions, Operation,
+    PruneJobConfig, PruneJobOptions, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_MODIFY,
+    PRIV_DATASTORE_PRUNE,
 };
+use pbs_datastore::lock_tracking::{retry_on_lock_contention, with_lock_owner};
 use pbs_datastore::prune::compute_prune_info;
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
@@ -21,6 +24,7 @@ pub fn prune_datastore(
     prune_options: PruneJobOptions,
     datastore: Arc<DataStore>,
     dry_run: bool,
+    wait: Option<Duration>,
 ) -> Result<(), Error> {
     let store = &datastore.name();
     let max_depth = prune_options.max_depth.unwrap_or(MAX_NAMESPACE_DEPTH);
@@ -37,15 +41,19 @@ pub fn prune_datastore(
         task_log!(worker, "(dry test run)");
     }
 
-    let keep_all = !prune_options.keeps_something();
-
-    if keep_all {
+    if !prune_options.keeps_something() {
         task_log!(worker, "No prune selection - keeping all files.");
     } else {
         let rendered_options = cli_prune_options_string(&prune_options);
         task_log!(worker, "retention options: {rendered_options}");
     }
 
+    let upid = worker.upid().to_string();
+
+    // groups are listed namespace by namespace, so only resolve the options on changes
+    let mut current_ns: Option<BackupNamespace> = None;
+    let mut keep_options = prune_options.keep.clone();
+
     for group in ListAccessibleBackupGroups::new_with_privs(
         &datastore,
         ns,
@@ -56,9 +64,29 @@ pub fn prune_datastore(
     )? {
         let group = group?;
         let ns = group.backup_ns();
+
+        if current_ns.as_ref() != Some(ns) {
+            keep_options = match datastore.lookup_namespace_prune_options(ns)? {
+                Some((options_ns, options)) => {
+                    let mut opts = Vec::new();
+                    cli_keep_options(&mut opts, &options);
+                    task_log!(
+                        worker,
+                        "using retention options of namespace '{options_ns}' for '{ns}': {}",
+                        opts.join(" "),
+                    );
+                    options
+                }
+                None => prune_options.keep.clone(),
+            };
+            current_ns = Some(ns.clone());
+        }
+
+        let keep_all = !keep_options.keeps_something();
+
         let list = group.list_backups()?;
 
-        let mut prune_info = compute_prune_info(list, &prune_options.keep)?;
+        let mut prune_info = compute_prune_info(list, &keep_options)?;
         prune_info.reverse(); // delete older snapshots first
 
         task_log!(
@@ -68,24 +96,134 @@ pub fn prune_datastore(
             group.backup_id()
         );
 
+        let mut rows = Vec::with_capacity(prune_info.len());
         for (info, mark) in prune_info {
             let keep = keep_all || mark.keep();
-            task_log!(
-                worker,
-                "{}{} {}/{}/{}",
-                if dry_run { "would " } else { "" },
-                mark,
-                group.backup_type(),
-                group.backup_id(),
-                info.backup_dir.backup_time_string()
-            );
+            rows.push((
+                format!(
+                    "{}/{}/{}",
+                    group.backup_type(),
+                    group.backup_id(),
+                    info.backup_dir.backup_time_string()
+                ),
+                format!("{}{mark}", if dry_run && !keep { "would " } else { "" }),
+            ));
             if !keep && !dry_run {
-                if let Err(err) = datastore.remove_backup_dir(ns, info.backup_dir.as_ref(), false) {
+                let result = with_lock_owner(Some(&upid), "prune", || {
+                    retry_on_lock_contention(wait, || {
+                        datastore.remove_backup_dir(ns, info.backup_dir.as_ref(), false)
+                    })
+                });
+                if let Err(err) = result {
                     let path = info.backup_dir.relative_path();
                     task_warn!(worker, "failed to remove dir {path:?}: {err}");
                 }
             }
         }
+        log_prune_table(&worker, &rows);
+    }
+
+    Ok(())
+}
+
+// Logs the snapshots of a group with their prune mark, like the table of a manual prune.
+fn log_prune_table(worker: &WorkerTask, rows: &[(String, String)]) {
+    let width = rows
+        .iter()
+        .map(|(snapshot, _)| snapshot.len())
+        .chain(std::iter::once("snapshot".len()))
+        .max()
+        .unwrap_or_default();
+
+    task_log!(worker, "  {:<width$} | action", "snapshot");
+    task_log!(worker, "  {:-<width$}-+-------", "");
+    for (snapshot, action) in rows {
+        task_log!(worker, "  {snapshot:<width$} | {action}");
+    }
+}
+
+/// Job ID used for the deprecated prune schedule of a datastore configuration.
+pub fn legacy_prune_job_id(store: &str) -> String {
+    let mut id = format!("storeconfig-{store}");
+    id.truncate(32);
+    id
+}
+
+/// Returns the prune job equivalent to the deprecated `prune-schedule` and `keep-*` settings of
+/// a datastore configuration, if those are still set.
+pub fn legacy_prune_job(config: &DataStoreConfig) -> Option<PruneJobConfig> {
+    let schedule = config.prune_schedule.clone()?;
+    if !config.keep.keeps_something() {
+        return None;
+    }
+
+    Some(PruneJobConfig {
+        id: legacy_prune_job_id(&config.name),
+        store: config.name.clone(),
+        disable: false,
+        comment: None,
+        schedule,
+        options: PruneJobOptions {
+            keep: config.keep.clone(),
+            ..Default::default()
+        },
+        concurrency_group: None,
+    })
+}
+
+fn print_job_scope(options: &PruneJobOptions) -> String {
+    let ns = options.ns.clone().unwrap_or_default();
+    match options.max_depth {
+        Some(0) => format!("namespace '{ns}' (non-recursive)"),
+        Some(depth) if depth < MAX_NAMESPACE_DEPTH => {
+            format!("namespace '{ns}' (to depth {depth})")
+        }
+        _ => format!("namespace '{ns}'"),
+    }
+}
+
+// The deprecated datastore prune schedule always covers the whole datastore, so every other
+// prune job of the same datastore overlaps with it.
+fn check_legacy_prune_overlap(
+    worker: &WorkerTask,
+    job_id: &str,
+    store: &str,
+    options: &PruneJobOptions,
+) -> Result<(), Error> {
+    let legacy_id = legacy_prune_job_id(store);
+
+    let (prune_config, _digest) = pbs_config::prune::config()?;
+    let jobs: Vec<PruneJobConfig> = prune_config.convert_to_typed_array("prune")?;
+
+    if job_id == legacy_id {
+        for job in jobs {
+            if job.store == store && !job.disable && job.id != legacy_id {
+                task_warn!(
+                    worker,
+                    "deprecated prune schedule of datastore '{store}' overlaps with prune job \
+                    '{}' on {} - please move the retention settings to prune jobs",
+                    job.id,
+                    print_job_scope(&job.options),
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    // the datastore settings were already converted to a prune job
+    if jobs.iter().any(|job| job.id == legacy_id) {
+        return Ok(());
+    }
+
+    let (store_config, _digest) = pbs_config::datastore::config()?;
+    let store_config: DataStoreConfig = store_config.lookup("datastore", store)?;
+    if legacy_prune_job(&store_config).is_some() {
+        task_warn!(
+            worker,
+            "datastore '{store}' still has a deprecated prune schedule, which also covers \
+            {} of this job - please move the retention settings to prune jobs",
+            print_job_scope(options),
+        );
     }
 
     Ok(())
@@ -149,6 +287,7 @@ pub fn do_prune_job(
         false,
         move |worker| {
             job.start(&worker.upid().to_string())?;
+            datastore.set_operation_owner(&worker.upid().to_string());
 
             task_log!(worker, "prune job '{}'", job.jobname());
 
@@ -156,15 +295,34 @@ pub fn do_prune_job(
                 task_log!(worker, "task triggered by schedule '{event_str}'");
             }
 
-            let result = prune_datastore(worker.clone(), auth_id, prune_options, datastore, false);
+            if let Err(err) =
+                check_legacy_prune_overlap(&worker, job.jobname(), &store, &prune_options)
+            {
+                task_warn!(
+                    worker,
+                    "could not check for overlapping prune schedules - {err}"
+                );
+            }
+
+            let result = prune_datastore(
+                worker.clone(),
+                auth_id,
+                prune_options,
+                datastore,
+                false,
+                None,
+            );
 
             let status = worker.create_state(&result);
+            let severity = crate::server::task_severity(&status);
 
             if let Err(err) = job.finish(status) {
                 eprintln!("could not finish job state for {}: {err}", job.jobtype());
             }
 
-            if let Err(err) = crate::server::send_prune_status(&store, job.jobname(), &result) {
+            if let Err(err) =
+                crate::server::send_prune_status(&store, job.jobname(), &result, severity)
+            {
                 log::error!("send prune notification failed: {err}");
             }
             result
@@ -11,21 +11,22 @@ use anyhow::{bail, format_err, Error};
 use http::StatusCode;
 use proxmox_human_byte::HumanByte;
 use proxmox_rest_server::WorkerTask;
-use proxmox_router::HttpError;
 use proxmox_sys::{task_log, task_warn};
 use serde_json::json;
 
 use pbs_api_types::{
-    print_store_and_ns, Authid, BackupDir, BackupGroup, BackupNamespace, CryptMode, GroupFilter,
-    GroupListItem, Operation, RateLimitConfig, Remote, SnapshotListItem, MAX_NAMESPACE_DEPTH,
-    PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+    print_store_and_ns, Authid, BackupContent, BackupDir, BackupGroup, BackupNamespace, CryptMode,
+    GroupFilter, GroupListItem, JobSummary, Operation, RateLimitConfig, Remote, SnapshotListItem,
+    VerifyState, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_AUDIT, PRIV_DATASTORE_BACKUP,
+    PRIV_DATASTORE_READ,
 };
-use pbs_client::{BackupReader, BackupRepository, HttpClient, RemoteChunkReader};
+use pbs_client::{ApiResponseError, BackupReader, BackupRepository, HttpClient, RemoteChunkReader};
 use pbs_config::CachedUserInfo;
 use pbs_datastore::data_blob::DataBlob;
 use pbs_datastore::dynamic_index::DynamicIndexReader;
 use pbs_datastore::fixed_index::FixedIndexReader;
 use pbs_datastore::index::IndexFile;
+use pbs_datastore::lock_tracking::with_lock_owner;
 use pbs_datastore::manifest::{
     archive_type, ArchiveType, BackupManifest, FileInfo, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
 };
@@ -36,6 +37,9 @@ use pbs_datastore::{
 use pbs_tools::sha::sha256;
 
 use crate::backup::{check_ns_modification_privs, check_ns_privs, ListAccessibleBackupGroups};
+use crate::server::snapshot_filter::{
+    manifest_files, manifest_verify_state, SnapshotFilter, SnapshotFilterReason,
+};
 use crate::tools::parallel_handler::ParallelHandler;
 
 struct RemoteReader {
@@ -44,7 +48,7 @@ struct RemoteReader {
 }
 
 struct LocalReader {
-    _dir_lock: Arc<Mutex<proxmox_sys::fs::DirLockGuard>>,
+    _dir_lock: Arc<Mutex<pbs_datastore::lock_tracking::DirLockGuard>>,
     path: PathBuf,
     datastore: Arc<DataStore>,
 }
@@ -70,6 +74,18 @@ pub(crate) struct PullStats {
     pub(crate) chunk_count: usize,
     pub(crate) bytes: usize,
     pub(crate) elapsed: Duration,
+    /// Snapshots not pulled because they are older than the `transfer-last` window
+    pub(crate) skipped_transfer_last: usize,
+    /// Downloaded chunks checked because of `verify-downloads`
+    pub(crate) verified_chunks: usize,
+    /// Existing snapshots which only got their notes or protection updated
+    pub(crate) metadata_updates: usize,
+    /// Snapshots not pulled because of `encrypted-only`
+    pub(crate) skipped_not_encrypted: usize,
+    /// Snapshots not pulled because of `verified-only`
+    pub(crate) skipped_not_verified: usize,
+    /// Synced, filtered and failed groups, namespaces and cleanup steps
+    pub(crate) summary: JobSummary,
 }
 
 impl PullStats {
@@ -77,9 +93,53 @@ impl PullStats {
         self.chunk_count += rhs.chunk_count;
         self.bytes += rhs.bytes;
         self.elapsed += rhs.elapsed;
+        self.skipped_transfer_last += rhs.skipped_transfer_last;
+        self.verified_chunks += rhs.verified_chunks;
+        self.metadata_updates += rhs.metadata_updates;
+        self.skipped_not_encrypted += rhs.skipped_not_encrypted;
+        self.skipped_not_verified += rhs.skipped_not_verified;
+        self.summary.merge(rhs.summary);
+    }
+
+    /// Fails if some groups or namespaces could not be synced.
+    pub(crate) fn check_errors(&self) -> Result<(), Error> {
+        if self.summary.failed > 0 {
+            bail!("sync failed with some errors.");
+        }
+        Ok(())
+    }
+}
+
+fn print_ns_and_group(ns: &BackupNamespace, group: &BackupGroup) -> String {
+    if ns.is_root() {
+        group.to_string()
+    } else {
+        format!("{}/{}", ns.display_as_path(), group)
     }
 }
 
+/// A snapshot on the source, with the metadata synced along with its data.
+#[derive(Clone)]
+struct SourceSnapshot {
+    dir: BackupDir,
+    protected: bool,
+    /// First line of the notes
+    comment: Option<String>,
+    /// Digest of the full notes, `None` if there are none or the source does not provide it
+    notes_digest: Option<String>,
+    /// Files of the snapshot, checked by the snapshot filters
+    files: Vec<BackupContent>,
+    /// Result of the last verification
+    verify_state: Option<VerifyState>,
+}
+
+fn notes_digest(notes: &str) -> Option<String> {
+    if notes.is_empty() {
+        return None;
+    }
+    Some(hex::encode(openssl::sha::sha256(notes.as_bytes())))
+}
+
 #[async_trait::async_trait]
 /// `PullSource` is a trait that provides an interface for pulling data/information from a source.
 /// The trait includes methods for listing namespaces, groups, and backup directories,
@@ -92,12 +152,13 @@ trait PullSource: Send + Sync {
         worker: &WorkerTask,
     ) -> Result<Vec<BackupNamespace>, Error>;
 
-    /// Lists groups within a specific namespace from the source.
+    /// Lists groups within a specific namespace from the source, with their owner as far as the
+    /// source exposes it.
     async fn list_groups(
         &self,
         namespace: &BackupNamespace,
         owner: &Authid,
-    ) -> Result<Vec<BackupGroup>, Error>;
+    ) -> Result<Vec<(BackupGroup, Option<Authid>)>, Error>;
 
     /// Lists backup directories for a specific group within a specific namespace from the source.
     async fn list_backup_dirs(
@@ -105,10 +166,21 @@ trait PullSource: Send + Sync {
         namespace: &BackupNamespace,
         group: &BackupGroup,
         worker: &WorkerTask,
-    ) -> Result<Vec<BackupDir>, Error>;
+    ) -> Result<Vec<SourceSnapshot>, Error>;
+
+    /// Returns the full notes of a backup directory.
+    async fn get_notes(
+        &self,
+        namespace: &BackupNamespace,
+        dir: &BackupDir,
+    ) -> Result<String, Error>;
+
     fn get_ns(&self) -> BackupNamespace;
     fn get_store(&self) -> &str;
 
+    /// Registers the task reading from this source as owner of its datastore operation.
+    fn set_operation_owner(&self, _upid: &str) {}
+
     /// Returns a reader for reading data from a specific backup directory.
     async fn reader(
         &self,
@@ -117,6 +189,28 @@ trait PullSource: Send + Sync {
     ) -> Result<Arc<dyn PullReader>, Error>;
 }
 
+impl RemoteSource {
+    async fn query_groups(&self, namespace: &BackupNamespace) -> Result<Vec<GroupListItem>, Error> {
+        let path = format!("api2/json/admin/datastore/{}/groups", self.repo.store());
+
+        let args = if !namespace.is_root() {
+            Some(json!({ "ns": namespace.clone() }))
+        } else {
+            None
+        };
+
+        self.client.login().await?;
+        let mut result =
+            self.client.get(&path, args).await.map_err(|err| {
+                format_err!("Failed to retrieve backup groups from remote - {}", err)
+            })?;
+
+        Ok(serde_json::from_value::<Vec<GroupListItem>>(
+            result["data"].take(),
+        )?)
+    }
+}
+
 #[async_trait::async_trait]
 impl PullSource for RemoteSource {
     async fn list_namespaces(
@@ -141,8 +235,10 @@ impl PullSource for RemoteSource {
 
         let mut result = match self.client.get(&path, Some(data)).await {
             Ok(res) => res,
-            Err(err) => match err.downcast_ref::<HttpError>() {
-                Some(HttpError { code, message }) => match code {
+            Err(err) => match err.downcast_ref::<ApiResponseError>() {
+                Some(ApiResponseError {
+                    status, message, ..
+                }) => match status {
                     &StatusCode::NOT_FOUND => {
                         if self.ns.is_root() && max_depth.is_none() {
                             task_warn!(worker, "Could not query remote for namespaces (404) -> temporarily switching to backwards-compat mode");
@@ -155,7 +251,7 @@ impl PullSource for RemoteSource {
                         return Ok(vec![self.ns.clone()]);
                     }
                     _ => {
-                        bail!("Querying namespaces failed - HTTP error {code} - {message}");
+                        bail!("Querying namespaces failed - HTTP error {status} - {message}");
                     }
                 },
                 None => {
@@ -177,28 +273,14 @@ impl PullSource for RemoteSource {
         &self,
         namespace: &BackupNamespace,
         _owner: &Authid,
-    ) -> Result<Vec<BackupGroup>, Error> {
-        let path = format!("api2/json/admin/datastore/{}/groups", self.repo.store());
-
-        let args = if !namespace.is_root() {
-            Some(json!({ "ns": namespace.clone() }))
-        } else {
-            None
-        };
-
-        self.client.login().await?;
-        let mut result =
-            self.client.get(&path, args).await.map_err(|err| {
-                format_err!("Failed to retrieve backup groups from remote - {}", err)
-            })?;
-
-        Ok(
-            serde_json::from_value::<Vec<GroupListItem>>(result["data"].take())
-                .map_err(Error::from)?
-                .into_iter()
-                .map(|item| item.backup)
-                .collect::<Vec<BackupGroup>>(),
-        )
+    ) -> Result<Vec<(BackupGroup, Option<Authid>)>, Error> {
+        // older remotes might not include the owner
+        Ok(self
+            .query_groups(namespace)
+            .await?
+            .into_iter()
+            .map(|item| (item.backup, item.owner))
+            .collect())
     }
 
     async fn list_backup_dirs(
@@ -206,7 +288,7 @@ impl PullSource for RemoteSource {
         namespace: &BackupNamespace,
         group: &BackupGroup,
         worker: &WorkerTask,
-    ) -> Result<Vec<BackupDir>, Error> {
+    ) -> Result<Vec<SourceSnapshot>, Error> {
         let path = format!("api2/json/admin/datastore/{}/snapshots", self.repo.store());
 
         let mut args = json!({
@@ -236,9 +318,34 @@ impl PullSource for RemoteSource {
                     return None;
                 }
 
-                Some(snapshot)
+                Some(SourceSnapshot {
+                    dir: snapshot,
+                    protected: item.protected,
+                    comment: item.comment,
+                    notes_digest: item.notes_digest,
+                    files: item.files,
+                    verify_state: item.verification.map(|verification| verification.state),
+                })
             })
-            .collect::<Vec<BackupDir>>())
+            .collect::<Vec<SourceSnapshot>>())
+    }
+
+    async fn get_notes(
+        &self,
+        namespace: &BackupNamespace,
+        dir: &BackupDir,
+    ) -> Result<String, Error> {
+        let path = format!("api2/json/admin/datastore/{}/notes", self.repo.store());
+
+        let mut args = serde_json::to_value(dir)?;
+        if !namespace.is_root() {
+            args["ns"] = serde_json::to_value(namespace)?;
+        }
+
+        self.client.login().await?;
+
+        let mut result = self.client.get(&path, Some(args)).await?;
+        Ok(serde_json::from_value(result["data"].take())?)
     }
 
     fn get_ns(&self) -> BackupNamespace {
@@ -282,7 +389,7 @@ impl PullSource for LocalSource {
         &self,
         namespace: &BackupNamespace,
         owner: &Authid,
-    ) -> Result<Vec<BackupGroup>, Error> {
+    ) -> Result<Vec<(BackupGroup, Option<Authid>)>, Error> {
         Ok(ListAccessibleBackupGroups::new_with_privs(
             &self.store,
             namespace.clone(),
@@ -292,8 +399,11 @@ impl PullSource for LocalSource {
             Some(owner),
         )?
         .filter_map(Result::ok)
-        .map(|backup_group| backup_group.group().clone())
-        .collect::<Vec<pbs_api_types::BackupGroup>>())
+        .map(|backup_group| {
+            let owner = backup_group.get_owner().ok();
+            (backup_group.group().clone(), owner)
+        })
+        .collect())
     }
 
     async fn list_backup_dirs(
@@ -301,14 +411,44 @@ impl PullSource for LocalSource {
         namespace: &BackupNamespace,
         group: &BackupGroup,
         _worker: &WorkerTask,
-    ) -> Result<Vec<BackupDir>, Error> {
+    ) -> Result<Vec<SourceSnapshot>, Error> {
         Ok(self
             .store
             .backup_group(namespace.clone(), group.clone())
             .iter_snapshots()?
             .filter_map(Result::ok)
-            .map(|snapshot| snapshot.dir().to_owned())
-            .collect::<Vec<BackupDir>>())
+            .map(|snapshot| {
+                let manifest = snapshot.load_manifest().ok().map(|(manifest, _)| manifest);
+                let notes = manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.unprotected["notes"].as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                SourceSnapshot {
+                    dir: snapshot.dir().to_owned(),
+                    protected: snapshot.is_protected(),
+                    comment: notes.lines().next().map(String::from),
+                    notes_digest: notes_digest(&notes),
+                    files: manifest.as_ref().map(manifest_files).unwrap_or_default(),
+                    verify_state: manifest.as_ref().and_then(manifest_verify_state),
+                }
+            })
+            .collect::<Vec<SourceSnapshot>>())
+    }
+
+    async fn get_notes(
+        &self,
+        namespace: &BackupNamespace,
+        dir: &BackupDir,
+    ) -> Result<String, Error> {
+        let (manifest, _) = self
+            .store
+            .backup_dir(namespace.clone(), dir.clone())?
+            .load_manifest()?;
+        Ok(manifest.unprotected["notes"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string())
     }
 
     fn get_ns(&self) -> BackupNamespace {
@@ -319,13 +459,17 @@ impl PullSource for LocalSource {
         self.store.name()
     }
 
+    fn set_operation_owner(&self, upid: &str) {
+        self.store.set_operation_owner(upid);
+    }
+
     async fn reader(
         &self,
         ns: &BackupNamespace,
         dir: &BackupDir,
     ) -> Result<Arc<dyn PullReader>, Error> {
         let dir = self.store.backup_dir(ns.clone(), dir.clone())?;
-        let dir_lock = proxmox_sys::fs::lock_dir_noblock_shared(
+        let dir_lock = pbs_datastore::lock_tracking::lock_dir_noblock_shared(
             &dir.full_path(),
             "snapshot",
             "locked by another operation",
@@ -390,8 +534,10 @@ impl PullReader for RemoteReader {
             .open(into)?;
         let download_result = self.backup_reader.download(filename, &mut tmp_file).await;
         if let Err(err) = download_result {
-            match err.downcast_ref::<HttpError>() {
-                Some(HttpError { code, message }) => match *code {
+            match err.downcast_ref::<ApiResponseError>() {
+                Some(ApiResponseError {
+                    status, message, ..
+                }) => match *status {
                     StatusCode::NOT_FOUND => {
                         task_log!(
                             worker,
@@ -401,7 +547,7 @@ impl PullReader for RemoteReader {
                         return Ok(None);
                     }
                     _ => {
-                        bail!("HTTP error {code} - {message}");
+                        bail!("HTTP error {status} - {message}");
                     }
                 },
                 None => {
@@ -499,12 +645,24 @@ pub(crate) struct PullParameters {
     owner: Authid,
     /// Whether to remove groups which exist locally, but not on the remote end
     remove_vanished: bool,
+    /// Whether newly created groups should get the owner of the source group (if known)
+    preserve_owner: bool,
+    /// Whether to change the owner of pre-existing groups which don't match the expected owner
+    force_owner: bool,
     /// How many levels of sub-namespaces to pull (0 == no recursion, None == maximum recursion)
     max_depth: Option<usize>,
     /// Filters for reducing the pull scope
     group_filter: Vec<GroupFilter>,
     /// How many snapshots should be transferred at most (taking the newest N snapshots)
     transfer_last: Option<usize>,
+    /// Rate limit applied to the connection to the remote (`None` for local pulls)
+    limit: Option<RateLimitConfig>,
+    /// Whether to fully verify every downloaded chunk before writing it
+    verify_downloads: bool,
+    /// Whether to sync snapshot notes and protection, also for already synced snapshots
+    sync_metadata: bool,
+    /// Filters for the snapshots of a group, e.g. `encrypted-only`
+    snapshot_filter: SnapshotFilter,
 }
 
 impl PullParameters {
@@ -517,10 +675,15 @@ impl PullParameters {
         remote_ns: BackupNamespace,
         owner: Authid,
         remove_vanished: Option<bool>,
+        preserve_owner: bool,
+        force_owner: bool,
         max_depth: Option<usize>,
         group_filter: Option<Vec<GroupFilter>>,
         limit: RateLimitConfig,
         transfer_last: Option<usize>,
+        verify_downloads: bool,
+        sync_metadata: bool,
+        snapshot_filter: SnapshotFilter,
     ) -> Result<Self, Error> {
         if let Some(max_depth) = max_depth {
             ns.check_max_depth(max_depth)?;
@@ -528,7 +691,7 @@ impl PullParameters {
         };
         let remove_vanished = remove_vanished.unwrap_or(false);
 
-        let source: Arc<dyn PullSource> = if let Some(remote) = remote {
+        let (source, limit) = if let Some(remote) = remote {
             let (remote_config, _digest) = pbs_config::remote::config()?;
             let remote: Remote = remote_config.lookup("remote", remote)?;
 
@@ -538,17 +701,20 @@ impl PullParameters {
                 remote.config.port,
                 remote_store.to_string(),
             );
-            let client = crate::api2::config::remote::remote_client_config(&remote, Some(limit))?;
-            Arc::new(RemoteSource {
+            let client =
+                crate::api2::config::remote::remote_client_config(&remote, Some(limit.clone()))?;
+            let source: Arc<dyn PullSource> = Arc::new(RemoteSource {
                 repo,
                 ns: remote_ns,
                 client,
-            })
+            });
+            (source, Some(limit))
         } else {
-            Arc::new(LocalSource {
+            let source: Arc<dyn PullSource> = Arc::new(LocalSource {
                 store: DataStore::lookup_datastore(remote_store, Some(Operation::Read))?,
                 ns: remote_ns,
-            })
+            });
+            (source, None)
         };
         let target = PullTarget {
             store: DataStore::lookup_datastore(store, Some(Operation::Write))?,
@@ -562,9 +728,15 @@ impl PullParameters {
             target,
             owner,
             remove_vanished,
+            preserve_owner,
+            force_owner,
             max_depth,
             group_filter,
             transfer_last,
+            limit,
+            verify_downloads,
+            sync_metadata,
+            snapshot_filter,
         })
     }
 }
@@ -575,6 +747,7 @@ async fn pull_index_chunks<I: IndexFile>(
     target: Arc<DataStore>,
     index: I,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    verify_crypt_mode: Option<CryptMode>,
 ) -> Result<PullStats, Error> {
     use futures::stream::{self, StreamExt, TryStreamExt};
 
@@ -596,12 +769,25 @@ async fn pull_index_chunks<I: IndexFile>(
     );
 
     let target2 = target.clone();
+    let verified_chunks = Arc::new(AtomicUsize::new(0));
+    let verified_chunks2 = Arc::clone(&verified_chunks);
     let verify_pool = ParallelHandler::new(
         "sync chunk writer",
         4,
         move |(chunk, digest, size): (DataBlob, [u8; 32], u64)| {
             // println!("verify and write {}", hex::encode(&digest));
-            chunk.verify_unencrypted(size as usize, &digest)?;
+            match verify_crypt_mode {
+                Some(crypt_mode) => {
+                    verify_downloaded_chunk(&chunk, crypt_mode, size, &digest).map_err(|err| {
+                        format_err!(
+                            "downloaded chunk {} failed verification - {err}",
+                            hex::encode(digest)
+                        )
+                    })?;
+                    verified_chunks2.fetch_add(1, Ordering::SeqCst);
+                }
+                None => chunk.verify_unencrypted(size as usize, &digest)?,
+            }
             target2.insert_chunk(&chunk, &digest)?;
             Ok(())
         },
@@ -655,6 +841,7 @@ async fn pull_index_chunks<I: IndexFile>(
 
     let bytes = bytes.load(Ordering::SeqCst);
     let chunk_count = chunk_count.load(Ordering::SeqCst);
+    let verified_chunks = verified_chunks.load(Ordering::SeqCst);
 
     task_log!(
         worker,
@@ -663,13 +850,41 @@ async fn pull_index_chunks<I: IndexFile>(
         HumanByte::new_binary(bytes as f64 / elapsed.as_secs_f64()),
     );
 
+    if verify_crypt_mode.is_some() {
+        task_log!(worker, "verified {verified_chunks} downloaded chunks");
+    }
+
     Ok(PullStats {
         chunk_count,
         bytes,
         elapsed,
+        verified_chunks,
+        ..Default::default()
     })
 }
 
+/// Checks a downloaded chunk the same way a verification job does: the crypt mode has to match
+/// the archive's and its digest (or CRC, for encrypted chunks) has to be valid.
+///
+/// Decoding and hashing is expensive, only call this from the blocking writer threads.
+fn verify_downloaded_chunk(
+    chunk: &DataBlob,
+    crypt_mode: CryptMode,
+    size: u64,
+    digest: &[u8; 32],
+) -> Result<(), Error> {
+    let chunk_crypt_mode = chunk.crypt_mode()?;
+    if chunk_crypt_mode != crypt_mode {
+        bail!("chunk has wrong crypt mode ({chunk_crypt_mode:?} != {crypt_mode:?})");
+    }
+    if crypt_mode == CryptMode::Encrypt {
+        // the digest can only be checked with the key
+        chunk.verify_crc()
+    } else {
+        chunk.verify_unencrypted(size as usize, digest)
+    }
+}
+
 fn verify_archive(info: &FileInfo, csum: &[u8; 32], size: u64) -> Result<(), Error> {
     if size != info.size {
         bail!(
@@ -701,6 +916,7 @@ async fn pull_single_archive<'a>(
     snapshot: &'a pbs_datastore::BackupDir,
     archive_info: &'a FileInfo,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    verify_downloads: bool,
 ) -> Result<PullStats, Error> {
     let archive_name = &archive_info.filename;
     let mut path = snapshot.full_path();
@@ -719,6 +935,8 @@ async fn pull_single_archive<'a>(
 
     let mut tmpfile = std::fs::OpenOptions::new().read(true).open(&tmp_path)?;
 
+    let verify_crypt_mode = verify_downloads.then(|| archive_info.chunk_crypt_mode());
+
     match archive_type(archive_name)? {
         ArchiveType::DynamicIndex => {
             let index = DynamicIndexReader::new(tmpfile).map_err(|err| {
@@ -736,6 +954,7 @@ async fn pull_single_archive<'a>(
                     snapshot.datastore().clone(),
                     index,
                     downloaded_chunks,
+                    verify_crypt_mode,
                 )
                 .await?;
                 pull_stats.add(stats);
@@ -757,6 +976,7 @@ async fn pull_single_archive<'a>(
                     snapshot.datastore().clone(),
                     index,
                     downloaded_chunks,
+                    verify_crypt_mode,
                 )
                 .await?;
                 pull_stats.add(stats);
@@ -788,6 +1008,7 @@ async fn pull_snapshot<'a>(
     reader: Arc<dyn PullReader + 'a>,
     snapshot: &'a pbs_datastore::BackupDir,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    verify_downloads: bool,
 ) -> Result<PullStats, Error> {
     let mut pull_stats = PullStats::default();
     let mut manifest_name = snapshot.full_path();
@@ -880,6 +1101,7 @@ async fn pull_snapshot<'a>(
             snapshot,
             item,
             downloaded_chunks.clone(),
+            verify_downloads,
         )
         .await?;
         pull_stats.add(stats);
@@ -910,6 +1132,7 @@ async fn pull_snapshot_from<'a>(
     reader: Arc<dyn PullReader + 'a>,
     snapshot: &'a pbs_datastore::BackupDir,
     downloaded_chunks: Arc<Mutex<HashSet<[u8; 32]>>>,
+    verify_downloads: bool,
 ) -> Result<PullStats, Error> {
     let (_path, is_new, _snap_lock) = snapshot
         .datastore()
@@ -918,7 +1141,15 @@ async fn pull_snapshot_from<'a>(
     let pull_stats = if is_new {
         task_log!(worker, "sync snapshot {}", snapshot.dir());
 
-        match pull_snapshot(worker, reader, snapshot, downloaded_chunks).await {
+        match pull_snapshot(
+            worker,
+            reader,
+            snapshot,
+            downloaded_chunks,
+            verify_downloads,
+        )
+        .await
+        {
             Err(err) => {
                 if let Err(cleanup_err) = snapshot.datastore().remove_backup_dir(
                     snapshot.backup_ns(),
@@ -936,7 +1167,14 @@ async fn pull_snapshot_from<'a>(
         }
     } else {
         task_log!(worker, "re-sync snapshot {}", snapshot.dir());
-        pull_snapshot(worker, reader, snapshot, downloaded_chunks).await?
+        pull_snapshot(
+            worker,
+            reader,
+            snapshot,
+            downloaded_chunks,
+            verify_downloads,
+        )
+        .await?
     };
 
     Ok(pull_stats)
@@ -1027,6 +1265,7 @@ impl std::fmt::Display for SkipInfo {
 /// - Query the list of snapshots available for this group in the source namespace on the remote
 /// - Sort by snapshot time
 /// - Get last snapshot timestamp on local datastore
+/// - Skip new snapshots not matching the snapshot filters (`encrypted-only`, `verified-only`)
 /// - Iterate over list of snapshots
 /// -- pull snapshot, unless it's not finished yet or older than last local snapshot
 /// - (remove_vanished) list all local snapshots, remove those that don't exist on remote
@@ -1048,35 +1287,65 @@ async fn pull_group(
     let mut already_synced_skip_info = SkipInfo::new(SkipReason::AlreadySynced);
     let mut transfer_last_skip_info = SkipInfo::new(SkipReason::TransferLast);
 
-    let mut raw_list: Vec<BackupDir> = params
+    let mut raw_list: Vec<SourceSnapshot> = params
         .source
         .list_backup_dirs(source_namespace, group, worker)
         .await?;
-    raw_list.sort_unstable_by(|a, b| a.time.cmp(&b.time));
-
-    let total_amount = raw_list.len();
-
-    let cutoff = params
-        .transfer_last
-        .map(|count| total_amount.saturating_sub(count))
-        .unwrap_or_default();
+    raw_list.sort_unstable_by(|a, b| a.dir.time.cmp(&b.dir.time));
 
     let target_ns = source_namespace.map_prefix(&params.source.get_ns(), &params.target.ns)?;
 
     let mut source_snapshots = HashSet::new();
+    let mut skipped_transfer_last = 0;
+    let mut skipped_not_encrypted = 0;
+    let mut skipped_not_verified = 0;
     let last_sync_time = params
         .target
         .store
         .last_successful_backup(&target_ns, group)?
         .unwrap_or(i64::MIN);
 
-    let list: Vec<BackupDir> = raw_list
+    // filter before applying `transfer-last`, so that it selects the newest matching snapshots
+    raw_list.retain(|snapshot| {
+        source_snapshots.insert(snapshot.dir.time);
+        if snapshot.dir.time < last_sync_time {
+            return true; // already synced
+        }
+        match params
+            .snapshot_filter
+            .check(&snapshot.files, snapshot.verify_state)
+        {
+            Ok(()) => true,
+            Err(reason) => {
+                task_log!(worker, "skipping snapshot {} - {}", snapshot.dir, reason);
+                match reason {
+                    SnapshotFilterReason::NotEncrypted => skipped_not_encrypted += 1,
+                    SnapshotFilterReason::NotVerified => skipped_not_verified += 1,
+                }
+                false
+            }
+        }
+    });
+
+    let total_amount = raw_list.len();
+
+    let cutoff = params
+        .transfer_last
+        .map(|count| total_amount.saturating_sub(count))
+        .unwrap_or_default();
+
+    let mut already_synced = Vec::new();
+
+    let list: Vec<SourceSnapshot> = raw_list
         .into_iter()
         .enumerate()
-        .filter(|&(pos, ref dir)| {
-            source_snapshots.insert(dir.time);
+        .filter(|&(pos, ref snapshot)| {
+            let dir = &snapshot.dir;
             if last_sync_time > dir.time {
                 already_synced_skip_info.update(dir.time);
+                if params.sync_metadata {
+                    already_synced.push(snapshot.clone());
+                }
                 return false;
             } else if already_synced_skip_info.count > 0 {
                 task_log!(worker, "{}", already_synced_skip_info);
@@ -1086,6 +1355,7 @@ async fn pull_group(
 
             if pos < cutoff && last_sync_time != dir.time {
                 transfer_last_skip_info.update(dir.time);
+                skipped_transfer_last += 1;
                 return false;
             } else if transfer_last_skip_info.count > 0 {
                 task_log!(worker, "{}", transfer_last_skip_info);
@@ -1093,7 +1363,7 @@ async fn pull_group(
             }
             true
         })
-        .map(|(_, dir)| dir)
+        .map(|(_, snapshot)| snapshot)
         .collect();
 
     // start with 65536 chunks (up to 256 GiB)
@@ -1101,25 +1371,76 @@ async fn pull_group(
 
     progress.group_snapshots = list.len() as u64;
 
-    let mut pull_stats = PullStats::default();
+    let mut pull_stats = PullStats {
+        skipped_transfer_last,
+        skipped_not_encrypted,
+        skipped_not_verified,
+        ..Default::default()
+    };
 
-    for (pos, from_snapshot) in list.into_iter().enumerate() {
+    for source_snapshot in already_synced {
+        let to_snapshot = params
+            .target
+            .store
+            .backup_dir(target_ns.clone(), source_snapshot.dir.clone())?;
+        if !to_snapshot.full_path().exists() {
+            continue; // removed locally, e.g. by prune
+        }
+
+        if sync_snapshot_metadata(
+            worker,
+            params,
+            source_namespace,
+            &source_snapshot,
+            &to_snapshot,
+        )
+        .await
+        {
+            pull_stats.metadata_updates += 1;
+        }
+    }
+
+    for (pos, source_snapshot) in list.into_iter().enumerate() {
+        let from_snapshot = &source_snapshot.dir;
         let to_snapshot = params
             .target
             .store
             .backup_dir(target_ns.clone(), from_snapshot.clone())?;
+        let existed = to_snapshot.full_path().exists();
 
         let reader = params
             .source
-            .reader(source_namespace, &from_snapshot)
+            .reader(source_namespace, from_snapshot)
             .await?;
-        let result =
-            pull_snapshot_from(worker, reader, &to_snapshot, downloaded_chunks.clone()).await;
+        let result = pull_snapshot_from(
+            worker,
+            reader,
+            &to_snapshot,
+            downloaded_chunks.clone(),
+            params.verify_downloads,
+        )
+        .await;
 
         progress.done_snapshots = pos as u64 + 1;
         task_log!(worker, "percentage done: {}", progress);
 
         let stats = result?; // stop on error
+
+        if params.sync_metadata
+            && sync_snapshot_metadata(
+                worker,
+                params,
+                source_namespace,
+                &source_snapshot,
+                &to_snapshot,
+            )
+            .await
+            && existed
+            && stats.bytes == 0
+        {
+            pull_stats.metadata_updates += 1;
+        }
+
         pull_stats.add(stats);
     }
 
@@ -1153,6 +1474,75 @@ async fn pull_group(
     Ok(pull_stats)
 }
 
+/// Applies the notes and protection flag of `source_snapshot` to the local `snapshot`.
+///
+/// Errors are only logged, as the snapshot data itself is already synced. Returns whether the
+/// local snapshot was changed.
+async fn sync_snapshot_metadata(
+    worker: &WorkerTask,
+    params: &PullParameters,
+    source_namespace: &BackupNamespace,
+    source_snapshot: &SourceSnapshot,
+    snapshot: &pbs_datastore::BackupDir,
+) -> bool {
+    let result: Result<bool, Error> = async {
+        let (manifest, _) = snapshot.load_manifest()?;
+        let local_notes = manifest.unprotected["notes"].as_str().unwrap_or_default();
+
+        let notes_changed = match (&source_snapshot.notes_digest, &source_snapshot.comment) {
+            (Some(digest), _) => notes_digest(local_notes).as_ref() != Some(digest),
+            // older sources only provide the first line of the notes
+            (None, Some(comment)) => local_notes.lines().next() != Some(comment.as_str()),
+            (None, None) => !local_notes.is_empty(),
+        };
+
+        let mut changed = false;
+
+        if notes_changed {
+            let notes = if source_snapshot.comment.is_some() {
+                params
+                    .source
+                    .get_notes(source_namespace, &source_snapshot.dir)
+                    .await?
+            } else {
+                String::new()
+            };
+            snapshot.update_manifest(|manifest| {
+                manifest.unprotected["notes"] = notes.into();
+            })?;
+            task_log!(worker, "updated notes of snapshot {}", snapshot.dir());
+            changed = true;
+        }
+
+        if snapshot.is_protected() != source_snapshot.protected {
+            snapshot
+                .datastore()
+                .update_protection(snapshot, source_snapshot.protected)?;
+            if source_snapshot.protected {
+                task_log!(worker, "protected snapshot {}", snapshot.dir());
+            } else {
+                task_log!(worker, "removed protection of snapshot {}", snapshot.dir());
+            }
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+    .await;
+
+    match result {
+        Ok(changed) => changed,
+        Err(err) => {
+            task_warn!(
+                worker,
+                "failed to sync notes and protection of snapshot {} - {err}",
+                snapshot.dir()
+            );
+            false
+        }
+    }
+}
+
 fn check_and_create_ns(params: &PullParameters, ns: &BackupNamespace) -> Result<bool, Error> {
     let mut created = false;
     let store_ns_str = print_store_and_ns(params.target.store.name(), ns);
@@ -1263,6 +1653,9 @@ fn check_and_remove_vanished_ns(
 /// Backwards compat: if the remote namespace is `/` and recursion is disabled, no namespace is
 /// passed to the remote at all to allow pulling from remotes which have no notion of namespaces.
 ///
+/// Failing namespaces and groups do not abort the sync, they are recorded in the summary of the
+/// returned stats instead. Callers need to check them with [`PullStats::check_errors`].
+///
 /// Permission checks:
 /// - access to local datastore, namespace anchor and remote entry need to be checked at call site
 /// - remote namespaces are filtered by remote
@@ -1274,7 +1667,21 @@ pub(crate) async fn pull_store(
 ) -> Result<PullStats, Error> {
     // explicit create shared lock to prevent GC on newly created chunks
     let _shared_store_lock = params.target.store.try_shared_chunk_store_lock()?;
-    let mut errors = false;
+
+    let upid = worker.upid().to_string();
+    params.target.store.set_operation_owner(&upid);
+    params.source.set_operation_owner(&upid);
+
+    if let Some(limit) = &params.limit {
+        match limit.effective_in() {
+            Some((rate, burst)) => {
+                task_log!(worker, "Rate limit: {rate}/s (burst: {burst})");
+            }
+            None => task_log!(worker, "Rate limit: unlimited"),
+        }
+    }
+
+    let mut pull_stats = PullStats::default();
 
     let old_max_depth = params.max_depth;
     let mut namespaces = if params.source.get_ns().is_root() && old_max_depth == Some(0) {
@@ -1302,12 +1709,17 @@ pub(crate) async fn pull_store(
         );
     }
 
-    errors |= old_max_depth != params.max_depth; // fail job if we switched to backwards-compat mode
+    if old_max_depth != params.max_depth {
+        // fail job if we switched to backwards-compat mode
+        pull_stats.summary.add_failure(
+            params.source.get_store(),
+            "source does not support namespaces, only the root namespace was synced",
+        );
+    }
     namespaces.sort_unstable_by_key(|a| a.name_len());
 
     let (mut groups, mut snapshots) = (0, 0);
     let mut synced_ns = HashSet::with_capacity(namespaces.len());
-    let mut pull_stats = PullStats::default();
 
     for namespace in namespaces {
         let source_store_ns_str = print_store_and_ns(params.source.get_store(), &namespace);
@@ -1336,15 +1748,13 @@ pub(crate) async fn pull_store(
                     target_store_ns_str,
                     err,
                 );
-                errors = true;
+                pull_stats.summary.add_failure(target_store_ns_str, err);
                 continue;
             }
         }
 
         match pull_ns(worker, &namespace, &mut params).await {
-            Ok((ns_progress, ns_pull_stats, ns_errors)) => {
-                errors |= ns_errors;
-
+            Ok((ns_progress, ns_pull_stats)) => {
                 pull_stats.add(ns_pull_stats);
 
                 if params.max_depth != Some(0) {
@@ -1360,26 +1770,66 @@ pub(crate) async fn pull_store(
                 }
             }
             Err(err) => {
-                errors = true;
                 task_log!(
                     worker,
                     "Encountered errors while syncing namespace {} - {}",
                     &namespace,
                     err,
                 );
+                pull_stats.summary.add_failure(source_store_ns_str, err);
             }
         };
     }
 
-    if params.remove_vanished {
-        errors |= check_and_remove_vanished_ns(worker, &params, synced_ns)?;
+    if params.remove_vanished && check_and_remove_vanished_ns(worker, &params, synced_ns)? {
+        pull_stats.summary.add_failure(
+            print_store_and_ns(params.target.store.name(), &params.target.ns),
+            "failed to remove vanished namespaces",
+        );
     }
 
-    if errors {
-        bail!("sync failed with some errors.");
+    Ok(pull_stats)
+}
+
+/// Returns the owner a pulled group is expected to have locally.
+///
+/// That is the owner of the group on the source with `preserve-owner`, if it is an active local
+/// user or token, and the owner of the sync job otherwise.
+fn expected_group_owner<'a>(
+    source_owner: Option<&'a Authid>,
+    job_owner: &'a Authid,
+    is_active: impl Fn(&Authid) -> bool,
+) -> &'a Authid {
+    match source_owner {
+        Some(source_owner) if is_active(source_owner) => source_owner,
+        _ => job_owner,
     }
+}
 
-    Ok(pull_stats)
+/// How to handle a pulled group, depending on its local owner.
+#[derive(Debug, PartialEq)]
+enum GroupOwnerAction {
+    /// The group can be synced with its current owner.
+    Sync,
+    /// The owner of the group has to be changed to the expected owner first (`force-owner`).
+    ChangeOwner,
+    /// The group belongs to someone else, so it must not be synced.
+    OwnerMismatch,
+}
+
+fn group_owner_action(
+    owner: &Authid,
+    job_owner: &Authid,
+    expected_owner: &Authid,
+    force_owner: bool,
+) -> GroupOwnerAction {
+    if owner == job_owner || owner == expected_owner {
+        GroupOwnerAction::Sync
+    } else if force_owner {
+        GroupOwnerAction::ChangeOwner
+    } else {
+        GroupOwnerAction::OwnerMismatch
+    }
 }
 
 /// Pulls a namespace according to `params`.
@@ -1398,10 +1848,10 @@ pub(crate) async fn pull_ns(
     worker: &WorkerTask,
     namespace: &BackupNamespace,
     params: &mut PullParameters,
-) -> Result<(StoreProgress, PullStats, bool), Error> {
-    let mut list: Vec<BackupGroup> = params.source.list_groups(namespace, &params.owner).await?;
+) -> Result<(StoreProgress, PullStats), Error> {
+    let mut list = params.source.list_groups(namespace, &params.owner).await?;
 
-    list.sort_unstable_by(|a, b| {
+    list.sort_unstable_by(|(a, _), (b, _)| {
         let type_order = a.ty.cmp(&b.ty);
         if type_order == std::cmp::Ordering::Equal {
             a.id.cmp(&b.id)
@@ -1411,9 +1861,9 @@ pub(crate) async fn pull_ns(
     });
 
     let unfiltered_count = list.len();
-    let list: Vec<BackupGroup> = list
+    let list: Vec<(BackupGroup, Option<Authid>)> = list
         .into_iter()
-        .filter(|group| group.apply_filters(&params.group_filter))
+        .filter(|(group, _)| group.apply_filters(&params.group_filter))
         .collect();
     task_log!(
         worker,
@@ -1422,61 +1872,119 @@ pub(crate) async fn pull_ns(
         unfiltered_count
     );
 
-    let mut errors = false;
-
     let mut new_groups = HashSet::new();
-    for group in list.iter() {
+    for (group, _) in list.iter() {
         new_groups.insert(group.clone());
     }
 
     let mut progress = StoreProgress::new(list.len() as u64);
     let mut pull_stats = PullStats::default();
+    pull_stats.summary.skipped = (unfiltered_count - list.len()) as u64;
 
     let target_ns = namespace.map_prefix(&params.source.get_ns(), &params.target.ns)?;
+    let upid = worker.upid().to_string();
+
+    let user_info = CachedUserInfo::new()?;
 
-    for (done, group) in list.into_iter().enumerate() {
+    for (done, (group, source_owner)) in list.into_iter().enumerate() {
         progress.done_groups = done as u64;
         progress.done_snapshots = 0;
         progress.group_snapshots = 0;
 
-        let (owner, _lock_guard) =
-            match params
+        let group_item = print_ns_and_group(&target_ns, &group);
+
+        let source_owner = source_owner.filter(|_| params.preserve_owner);
+        let expected_owner = expected_group_owner(source_owner.as_ref(), &params.owner, |id| {
+            user_info.is_active_auth_id(id)
+        });
+        if let Some(source_owner) = source_owner.as_ref().filter(|id| *id != expected_owner) {
+            task_log!(
+                worker,
+                "source owner '{source_owner}' of group {group} is not an active local user, \
+                using job owner '{}'",
+                params.owner
+            );
+        }
+
+        let (owner, _lock_guard) = match with_lock_owner(Some(&upid), "sync", || {
+            params
                 .target
                 .store
-                .create_locked_backup_group(&target_ns, &group, &params.owner)
-            {
-                Ok(result) => result,
-                Err(err) => {
-                    task_log!(
-                        worker,
-                        "sync group {} failed - group lock failed: {}",
-                        &group,
-                        err
-                    );
-                    errors = true;
-                    // do not stop here, instead continue
-                    task_log!(worker, "create_locked_backup_group failed");
-                    continue;
-                }
-            };
+                .create_locked_backup_group(&target_ns, &group, expected_owner)
+        }) {
+            Ok(result) => result,
+            Err(err) => {
+                task_log!(
+                    worker,
+                    "sync group {} failed - group lock failed: {}",
+                    &group,
+                    err
+                );
+                // do not stop here, instead continue
+                task_log!(worker, "create_locked_backup_group failed");
+                pull_stats
+                    .summary
+                    .add_failure(group_item, format!("group lock failed: {err}"));
+                continue;
+            }
+        };
 
         // permission check
-        if params.owner != owner {
+        let action = group_owner_action(&owner, &params.owner, expected_owner, params.force_owner);
+        let owner_ok = match action {
+            GroupOwnerAction::Sync => true,
+            GroupOwnerAction::ChangeOwner => {
+                match params
+                    .target
+                    .store
+                    .set_owner(&target_ns, &group, expected_owner, true)
+                {
+                    Ok(()) => {
+                        task_log!(
+                            worker,
+                            "changed owner of group {group} from '{owner}' to '{expected_owner}'"
+                        );
+                        true
+                    }
+                    Err(err) => {
+                        task_log!(
+                            worker,
+                            "sync group {group} failed - changing owner failed: {err}"
+                        );
+                        pull_stats
+                            .summary
+                            .add_failure(group_item, format!("changing owner failed: {err}"));
+                        continue;
+                    }
+                }
+            }
+            GroupOwnerAction::OwnerMismatch => false,
+        };
+
+        if !owner_ok {
             // only the owner is allowed to create additional snapshots
             task_log!(
                 worker,
                 "sync group {} failed - owner check failed ({} != {})",
                 &group,
-                params.owner,
+                expected_owner,
                 owner
             );
-            errors = true; // do not stop here, instead continue
+            // do not stop here, instead continue
+            pull_stats.summary.add_failure(
+                group_item,
+                format!("owner check failed ({expected_owner} != {owner})"),
+            );
         } else {
             match pull_group(worker, params, namespace, &group, &mut progress).await {
-                Ok(stats) => pull_stats.add(stats),
+                Ok(stats) => {
+                    pull_stats.add(stats);
+                    pull_stats.summary.succeeded += 1;
+                }
                 Err(err) => {
                     task_log!(worker, "sync group {} failed - {}", &group, err,);
-                    errors = true; // do not stop here, instead continue
+                    // do not stop here, instead continue
+                    pull_stats.summary.add_failure(group_item, err);
                 }
             }
         }
@@ -1513,7 +2021,9 @@ pub(crate) async fn pull_ns(
                     }
                     Err(err) => {
                         task_log!(worker, "{}", err);
-                        errors = true;
+                        pull_stats
+                            .summary
+                            .add_failure(print_ns_and_group(&target_ns, local_group), err);
                     }
                 }
             }
@@ -1521,9 +2031,92 @@ pub(crate) async fn pull_ns(
         });
         if let Err(err) = result {
             task_log!(worker, "error during cleanup: {}", err);
-            errors = true;
+            pull_stats.summary.add_failure(
+                print_store_and_ns(params.target.store.name(), &target_ns),
+                err,
+            );
         };
     }
 
-    Ok((progress, pull_stats, errors))
+    Ok((progress, pull_stats))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Owner of a group after pulling it into a datastore where it is owned by `local_owner`, or
+    /// `None` if the owner check fails.
+    fn pull_owner(
+        local_owner: Option<&Authid>,
+        source_owner: &Authid,
+        job_owner: &Authid,
+        preserve_owner: bool,
+        force_owner: bool,
+    ) -> Option<Authid> {
+        let source_owner = Some(source_owner).filter(|_| preserve_owner);
+        let expected_owner = expected_group_owner(source_owner, job_owner, |_| true);
+        // like create_locked_backup_group, new groups get the expected owner
+        let owner = local_owner.unwrap_or(expected_owner);
+        match group_owner_action(owner, job_owner, expected_owner, force_owner) {
+            GroupOwnerAction::Sync => Some(owner.clone()),
+            GroupOwnerAction::ChangeOwner => Some(expected_owner.clone()),
+            GroupOwnerAction::OwnerMismatch => None,
+        }
+    }
+
+    #[test]
+    fn test_expected_group_owner() {
+        let job_owner: Authid = "sync@pbs".parse().unwrap();
+        let source_owner: Authid = "client@pbs".parse().unwrap();
+
+        let owner = expected_group_owner(Some(&source_owner), &job_owner, |_| true);
+        assert_eq!(owner, &source_owner);
+        let owner = expected_group_owner(Some(&source_owner), &job_owner, |_| false);
+        assert_eq!(owner, &job_owner);
+        let owner = expected_group_owner(None, &job_owner, |_| true);
+        assert_eq!(owner, &job_owner);
+    }
+
+    #[test]
+    fn test_sync_failback() {
+        let sync_user: Authid = "sync@pbs".parse().unwrap();
+        let client: Authid = "client@pbs".parse().unwrap();
+        let dr_client: Authid = "client@pbs!dr".parse().unwrap();
+
+        // without preserve-owner the group on the DR site belongs to the sync user, which blocks
+        // the client from continuing its backups there
+        assert_eq!(
+            pull_owner(None, &client, &sync_user, false, false),
+            Some(sync_user.clone())
+        );
+
+        // with preserve-owner the client keeps owning its group on the DR site
+        let dr_owner = pull_owner(None, &client, &sync_user, true, false).unwrap();
+        assert_eq!(dr_owner, client);
+
+        // after the client continued backing up at the DR site, syncing back to the original
+        // site with preserve-owner continues the group there
+        assert_eq!(
+            pull_owner(Some(&client), &dr_owner, &sync_user, true, false),
+            Some(client.clone())
+        );
+
+        // if the client used a different token at the DR site, syncing back fails the owner
+        // check, unless force-owner hands the group over to the owner on the DR site
+        assert_eq!(
+            pull_owner(Some(&client), &dr_client, &sync_user, true, false),
+            None
+        );
+        assert_eq!(
+            pull_owner(Some(&client), &dr_client, &sync_user, true, true),
+            Some(dr_client.clone())
+        );
+
+        // groups owned by the sync user are always synced
+        assert_eq!(
+            pull_owner(Some(&sync_user), &dr_client, &sync_user, true, false),
+            Some(sync_user.clone())
+        );
+    }
 }
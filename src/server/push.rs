@@ -0,0 +1,862 @@
+//! Sync datastore by pushing its contents to a remote server
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, format_err, Error};
+use http::StatusCode;
+use proxmox_human_byte::HumanByte;
+use proxmox_rest_server::WorkerTask;
+use proxmox_sys::{task_log, task_warn};
+use serde_json::json;
+
+use pbs_api_types::{
+    print_store_and_ns, Authid, BackupContent, BackupGroup, BackupNamespace, CryptMode,
+    GroupFilter, GroupListItem, NamespaceListItem, Operation, RateLimitConfig, Remote,
+    SnapshotListItem, MAX_NAMESPACE_DEPTH, PRIV_DATASTORE_BACKUP, PRIV_DATASTORE_READ,
+};
+use pbs_client::{
+    ApiResponseError, BackupReader, BackupRepository, BackupWriter, HttpClient, UploadOptions,
+};
+use pbs_datastore::index::IndexFile;
+use pbs_datastore::lock_tracking::{lock_dir_noblock_shared, with_lock_owner};
+use pbs_datastore::manifest::{
+    archive_type, ArchiveType, BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME,
+};
+use pbs_datastore::{BackupInfo, DataBlob, DataStore, ListNamespacesRecursive, StoreProgress};
+
+use crate::backup::ListAccessibleBackupGroups;
+use crate::server::snapshot_filter::{SnapshotFilter, SnapshotFilterReason};
+
+pub(crate) struct PushTarget {
+    remote: Remote,
+    repo: BackupRepository,
+    ns: BackupNamespace,
+    client: HttpClient,
+}
+
+impl PushTarget {
+    /// The backup writer consumes its client, so every pushed snapshot gets a fresh one.
+    fn new_client(&self, limit: &RateLimitConfig) -> Result<HttpClient, Error> {
+        crate::api2::config::remote::remote_client_config(&self.remote, Some(limit.clone()))
+    }
+}
+
+/// Parameters for a push operation.
+pub(crate) struct PushParameters {
+    /// Where data is pushed from
+    source: Arc<DataStore>,
+    /// Local namespace to push from
+    ns: BackupNamespace,
+    /// Where data should be pushed to
+    target: PushTarget,
+    /// Only groups owned by this user are pushed
+    owner: Authid,
+    /// How many levels of sub-namespaces to push (0 == no recursion, None == maximum recursion)
+    max_depth: Option<usize>,
+    /// Filters for reducing the push scope
+    group_filter: Vec<GroupFilter>,
+    /// How many snapshots should be transferred at most (taking the newest N snapshots)
+    transfer_last: Option<usize>,
+    /// Rate limit applied to the connection to the remote
+    limit: RateLimitConfig,
+    /// Filters for the snapshots of a group, e.g. `encrypted-only`
+    snapshot_filter: SnapshotFilter,
+}
+
+impl PushParameters {
+    /// Creates a new instance of `PushParameters`.
+    pub(crate) fn new(
+        store: &str,
+        ns: BackupNamespace,
+        remote: &str,
+        remote_store: &str,
+        remote_ns: BackupNamespace,
+        owner: Authid,
+        max_depth: Option<usize>,
+        group_filter: Option<Vec<GroupFilter>>,
+        limit: RateLimitConfig,
+        transfer_last: Option<usize>,
+        snapshot_filter: SnapshotFilter,
+    ) -> Result<Self, Error> {
+        if let Some(max_depth) = max_depth {
+            ns.check_max_depth(max_depth)?;
+            remote_ns.check_max_depth(max_depth)?;
+        };
+
+        let (remote_config, _digest) = pbs_config::remote::config()?;
+        let remote: Remote = remote_config.lookup("remote", remote)?;
+
+        let repo = BackupRepository::new(
+            Some(remote.config.auth_id.clone()),
+            Some(remote.config.host.clone()),
+            remote.config.port,
+            remote_store.to_string(),
+        );
+        let client =
+            crate::api2::config::remote::remote_client_config(&remote, Some(limit.clone()))?;
+
+        Ok(Self {
+            source: DataStore::lookup_datastore(store, Some(Operation::Read))?,
+            ns,
+            target: PushTarget {
+                remote,
+                repo,
+                ns: remote_ns,
+                client,
+            },
+            owner,
+            max_depth,
+            group_filter: group_filter.unwrap_or_default(),
+            transfer_last,
+            limit,
+            snapshot_filter,
+        })
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PushStats {
+    pub(crate) snapshots: usize,
+    pub(crate) chunk_count: usize,
+    pub(crate) bytes: u64,
+    pub(crate) elapsed: Duration,
+    /// Snapshots not pushed because they are older than the `transfer-last` window
+    pub(crate) skipped_transfer_last: usize,
+    /// Snapshots existing on both sides with differing manifests
+    pub(crate) conflicts: usize,
+    /// Snapshots not pushed because of `encrypted-only`
+    pub(crate) skipped_not_encrypted: usize,
+    /// Snapshots not pushed because of `verified-only`
+    pub(crate) skipped_not_verified: usize,
+}
+
+impl PushStats {
+    fn add(&mut self, rhs: PushStats) {
+        self.snapshots += rhs.snapshots;
+        self.chunk_count += rhs.chunk_count;
+        self.bytes += rhs.bytes;
+        self.elapsed += rhs.elapsed;
+        self.skipped_transfer_last += rhs.skipped_transfer_last;
+        self.conflicts += rhs.conflicts;
+        self.skipped_not_encrypted += rhs.skipped_not_encrypted;
+        self.skipped_not_verified += rhs.skipped_not_verified;
+    }
+}
+
+/// Query the namespaces below (and including) `parent` on the remote.
+///
+/// Returns `None` if the remote does not support namespaces.
+async fn list_remote_namespaces(
+    params: &PushParameters,
+    parent: &BackupNamespace,
+) -> Result<Option<HashSet<BackupNamespace>>, Error> {
+    let path = format!(
+        "api2/json/admin/datastore/{}/namespace",
+        params.target.repo.store()
+    );
+    let mut args = json!({});
+    if !parent.is_root() {
+        args["parent"] = json!(parent);
+    }
+
+    let mut result = match params.target.client.get(&path, Some(args)).await {
+        Ok(result) => result,
+        Err(err) => match err.downcast_ref::<ApiResponseError>() {
+            Some(ApiResponseError { status, .. }) if *status == StatusCode::NOT_FOUND => {
+                return Ok(None)
+            }
+            _ => bail!("Querying remote namespaces failed - {err}"),
+        },
+    };
+
+    let list: Vec<NamespaceListItem> = serde_json::from_value(result["data"].take())?;
+    Ok(Some(list.into_iter().map(|item| item.ns).collect()))
+}
+
+/// Create the target namespaces which do not exist on the remote yet, parents first.
+async fn check_and_create_remote_ns(
+    worker: &WorkerTask,
+    params: &PushParameters,
+    target_namespaces: &[BackupNamespace],
+) -> Result<(), Error> {
+    let existing = match list_remote_namespaces(params, &params.target.ns).await? {
+        Some(existing) => existing,
+        None => {
+            if target_namespaces.iter().any(|ns| !ns.is_root()) {
+                bail!("Namespace push requested, but remote does not support namespaces.");
+            }
+            return Ok(());
+        }
+    };
+
+    let path = format!(
+        "api2/json/admin/datastore/{}/namespace",
+        params.target.repo.store()
+    );
+
+    let mut missing: Vec<&BackupNamespace> = target_namespaces
+        .iter()
+        .filter(|ns| !ns.is_root() && !existing.contains(ns))
+        .collect();
+    missing.sort_unstable_by_key(|ns| ns.depth());
+
+    for ns in missing {
+        let name = ns.components().last().unwrap_or_default();
+        let mut args = json!({ "name": name });
+        let parent = ns.parent();
+        if !parent.is_root() {
+            args["parent"] = json!(parent);
+        }
+        params
+            .target
+            .client
+            .post(&path, Some(args))
+            .await
+            .map_err(|err| format_err!("Creating remote namespace {ns} failed - {err}"))?;
+        task_log!(worker, "Created remote namespace {}", ns);
+    }
+
+    Ok(())
+}
+
+async fn list_remote_groups(
+    params: &PushParameters,
+    target_ns: &BackupNamespace,
+) -> Result<HashSet<BackupGroup>, Error> {
+    let path = format!(
+        "api2/json/admin/datastore/{}/groups",
+        params.target.repo.store()
+    );
+    let args = (!target_ns.is_root()).then(|| json!({ "ns": target_ns }));
+
+    let mut result = params
+        .target
+        .client
+        .get(&path, args)
+        .await
+        .map_err(|err| format_err!("Failed to retrieve backup groups from remote - {}", err))?;
+
+    Ok(
+        serde_json::from_value::<Vec<GroupListItem>>(result["data"].take())?
+            .into_iter()
+            .map(|item| item.backup)
+            .collect(),
+    )
+}
+
+/// The snapshots of `group` on the remote, with their files by backup time.
+async fn list_remote_snapshots(
+    params: &PushParameters,
+    target_ns: &BackupNamespace,
+    group: &BackupGroup,
+) -> Result<HashMap<i64, Vec<BackupContent>>, Error> {
+    let path = format!(
+        "api2/json/admin/datastore/{}/snapshots",
+        params.target.repo.store()
+    );
+    let mut args = json!({
+        "backup-type": group.ty,
+        "backup-id": group.id,
+    });
+    if !target_ns.is_root() {
+        args["ns"] = serde_json::to_value(target_ns)?;
+    }
+
+    let mut result = params.target.client.get(&path, Some(args)).await?;
+    let list: Vec<SnapshotListItem> = serde_json::from_value(result["data"].take())?;
+
+    Ok(list
+        .into_iter()
+        .map(|item| (item.backup.time, item.files))
+        .collect())
+}
+
+/// Check whether the archives in the remote snapshot listing match the local manifest.
+///
+/// The listing has no checksums, only names, sizes and crypt modes are compared. The manifest
+/// itself is left out, as its unprotected part differs between both sides.
+fn remote_files_match(local_manifest: &BackupManifest, remote_files: &[BackupContent]) -> bool {
+    let remote_files: Vec<&BackupContent> = remote_files
+        .iter()
+        .filter(|remote| remote.filename != MANIFEST_BLOB_NAME && remote.size.is_some())
+        .collect();
+    let local_files = local_manifest.files();
+
+    local_files.len() == remote_files.len()
+        && local_files.iter().all(|local| {
+            remote_files.iter().any(|remote| {
+                remote.filename == local.filename
+                    && remote.size == Some(local.size)
+                    && remote.crypt_mode == Some(local.crypt_mode)
+            })
+        })
+}
+
+/// Check whether the remote snapshot has the same contents as the local one.
+async fn remote_manifest_matches(
+    params: &PushParameters,
+    target_ns: &BackupNamespace,
+    dir: &pbs_api_types::BackupDir,
+    local_manifest: &BackupManifest,
+) -> Result<bool, Error> {
+    let reader = BackupReader::start(
+        &params.target.client,
+        None,
+        params.target.repo.store(),
+        target_ns,
+        dir,
+        false,
+    )
+    .await?;
+    let (remote_manifest, _) = reader.download_manifest().await?;
+
+    let local_files = local_manifest.files();
+    let remote_files = remote_manifest.files();
+
+    Ok(local_files.len() == remote_files.len()
+        && local_files.iter().all(|local| {
+            remote_files.iter().any(|remote| {
+                remote.filename == local.filename
+                    && remote.size == local.size
+                    && remote.csum == local.csum
+            })
+        }))
+}
+
+fn index_chunk_list(index: &dyn IndexFile) -> Vec<([u8; 32], u64)> {
+    (0..index.index_count())
+        .filter_map(|pos| index.chunk_info(pos))
+        .map(|info| (info.digest, info.size()))
+        .collect()
+}
+
+/// Push a single local snapshot to the remote.
+///
+/// Only chunks referenced by the previous snapshot on the remote are treated as known, everything
+/// else is uploaded as the already encoded blob stored locally.
+async fn push_snapshot(
+    worker: &WorkerTask,
+    params: &PushParameters,
+    snapshot: &pbs_datastore::BackupDir,
+    target_ns: &BackupNamespace,
+) -> Result<PushStats, Error> {
+    let _snapshot_lock = with_lock_owner(Some(&worker.upid().to_string()), "push", || {
+        lock_dir_noblock_shared(
+            &snapshot.full_path(),
+            "snapshot",
+            "locked by another operation",
+        )
+    })?;
+    let (manifest, _) = snapshot.load_manifest()?;
+    let datastore = snapshot.datastore().clone();
+
+    task_log!(worker, "push snapshot {}", snapshot.dir());
+
+    let client = params.target.new_client(&params.limit)?;
+    let writer = BackupWriter::start(
+        client,
+        None,
+        params.target.repo.store(),
+        target_ns,
+        snapshot.dir(),
+        false,
+        false,
+        false,
+        None,
+    )
+    .await?;
+
+    let previous_manifest = writer.download_previous_manifest().await.ok();
+    let known_chunks = Arc::new(Mutex::new(HashSet::new()));
+
+    let start_time = Instant::now();
+    let mut stats = PushStats {
+        snapshots: 1,
+        ..Default::default()
+    };
+
+    for item in manifest.files() {
+        let mut path = snapshot.full_path();
+        path.push(&item.filename);
+
+        let archive_type = archive_type(&item.filename)?;
+        let previous = previous_manifest
+            .as_ref()
+            .filter(|previous| previous.lookup_file_info(&item.filename).is_ok());
+
+        let (index, fixed_size): (Box<dyn IndexFile + Send>, _) = match archive_type {
+            ArchiveType::Blob => {
+                let file = std::fs::File::open(&path)
+                    .map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+                writer.upload_blob(file, &item.filename).await?;
+                continue;
+            }
+            ArchiveType::FixedIndex => {
+                if let Some(previous) = previous {
+                    if let Err(err) = writer
+                        .download_previous_fixed_index(
+                            &item.filename,
+                            previous,
+                            known_chunks.clone(),
+                        )
+                        .await
+                    {
+                        task_log!(worker, "could not use previous {} - {err}", item.filename);
+                    }
+                }
+                let index = datastore.open_fixed_reader(&path)?;
+                let size = index.index_bytes();
+                (Box::new(index), Some(size))
+            }
+            ArchiveType::DynamicIndex => {
+                if let Some(previous) = previous {
+                    if let Err(err) = writer
+                        .download_previous_dynamic_index(
+                            &item.filename,
+                            previous,
+                            known_chunks.clone(),
+                        )
+                        .await
+                    {
+                        task_log!(worker, "could not use previous {} - {err}", item.filename);
+                    }
+                }
+                (Box::new(datastore.open_dynamic_reader(&path)?), None)
+            }
+        };
+
+        let upload_stats = writer
+            .upload_index_chunks(
+                &item.filename,
+                index_chunk_list(index.as_ref()),
+                |digest| datastore.load_chunk(digest),
+                known_chunks.clone(),
+                UploadOptions {
+                    fixed_size,
+                    ..UploadOptions::default()
+                },
+            )
+            .await?;
+
+        if upload_stats.stats.csum != item.csum || upload_stats.stats.size != item.size {
+            bail!(
+                "pushed archive {} does not match its manifest entry",
+                item.filename
+            );
+        }
+
+        task_log!(
+            worker,
+            "pushed {}: uploaded {} of {} chunks ({})",
+            item.filename,
+            upload_stats.uploaded_chunks,
+            upload_stats.chunk_count,
+            HumanByte::from(upload_stats.uploaded_bytes),
+        );
+
+        stats.chunk_count += upload_stats.uploaded_chunks;
+        stats.bytes += upload_stats.uploaded_bytes;
+    }
+
+    // upload the manifest as is, so signatures and verification state stay intact
+    let mut path = snapshot.full_path();
+    path.push(MANIFEST_BLOB_NAME);
+    let file =
+        std::fs::File::open(&path).map_err(|err| format_err!("unable to open {path:?} - {err}"))?;
+    writer.upload_blob(file, MANIFEST_BLOB_NAME).await?;
+
+    writer.finish().await?;
+
+    let mut path = snapshot.full_path();
+    path.push(CLIENT_LOG_BLOB_NAME);
+    let client_log = std::fs::read(&path).ok().filter(|data| {
+        // the archives are all encrypted with `encrypted-only`, the log must be as well
+        !params.snapshot_filter.encrypted_only
+            || DataBlob::from_raw(data.clone())
+                .and_then(|blob| blob.crypt_mode())
+                .map_or(false, |mode| mode == CryptMode::Encrypt)
+    });
+    if let Some(data) = client_log {
+        let upload_path = format!(
+            "api2/json/admin/datastore/{}/upload-backup-log",
+            params.target.repo.store()
+        );
+        let mut args = json!({
+            "backup-type": snapshot.backup_type(),
+            "backup-id": snapshot.backup_id(),
+            "backup-time": snapshot.backup_time(),
+        });
+        if !target_ns.is_root() {
+            args["ns"] = serde_json::to_value(target_ns)?;
+        }
+        if let Err(err) = params
+            .target
+            .client
+            .upload(
+                "application/octet-stream",
+                hyper::Body::from(data),
+                &upload_path,
+                Some(args),
+            )
+            .await
+        {
+            task_warn!(worker, "could not upload client log - {err}");
+        }
+    }
+
+    stats.elapsed = start_time.elapsed();
+
+    Ok(stats)
+}
+
+/// Pushes a group to the remote.
+///
+/// Only snapshots newer than the newest snapshot on the remote can be added there. Snapshots
+/// existing on both sides are compared with the remote snapshot listing and a mismatch is reported
+/// as conflict - remote snapshots are never overwritten.
+async fn push_group(
+    worker: &WorkerTask,
+    params: &PushParameters,
+    namespace: &BackupNamespace,
+    target_ns: &BackupNamespace,
+    group: &BackupGroup,
+    remote_group_exists: bool,
+    progress: &mut StoreProgress,
+) -> Result<PushStats, Error> {
+    let mut list = params
+        .source
+        .backup_group(namespace.clone(), group.clone())
+        .list_backups()?;
+    list.retain(|info| info.is_finished());
+    BackupInfo::sort_list(&mut list, true);
+
+    let remote_snapshots = if remote_group_exists {
+        list_remote_snapshots(params, target_ns, group).await?
+    } else {
+        HashMap::new()
+    };
+    let last_remote_time = remote_snapshots.keys().copied().max();
+
+    let mut stats = PushStats::default();
+
+    // filter before applying `transfer-last`, so that it selects the newest matching snapshots
+    let mut filtered = Vec::with_capacity(list.len());
+    for info in list {
+        let time = info.backup_dir.backup_time();
+        if !params.snapshot_filter.is_active()
+            || last_remote_time.map_or(false, |last| time <= last)
+        {
+            filtered.push(info); // already synced ones are handled below
+            continue;
+        }
+        let (manifest, _) = info.backup_dir.load_manifest()?;
+        match params.snapshot_filter.check_manifest(&manifest) {
+            Ok(()) => filtered.push(info),
+            Err(reason) => {
+                task_log!(
+                    worker,
+                    "skipping snapshot {} - {reason}",
+                    info.backup_dir.dir()
+                );
+                match reason {
+                    SnapshotFilterReason::NotEncrypted => stats.skipped_not_encrypted += 1,
+                    SnapshotFilterReason::NotVerified => stats.skipped_not_verified += 1,
+                }
+            }
+        }
+    }
+    let list = filtered;
+
+    let cutoff = params
+        .transfer_last
+        .map(|count| list.len().saturating_sub(count))
+        .unwrap_or_default();
+
+    let mut already_synced = 0;
+    let mut to_push = Vec::new();
+
+    for (pos, info) in list.into_iter().enumerate() {
+        let time = info.backup_dir.backup_time();
+        match last_remote_time {
+            Some(last) if time <= last && !remote_snapshots.contains_key(&time) => {
+                already_synced += 1;
+            }
+            Some(last) if time <= last => {
+                let (manifest, _) = info.backup_dir.load_manifest()?;
+                // only download the remote manifest to confirm a mismatch of the listing
+                if remote_files_match(&manifest, &remote_snapshots[&time])
+                    || remote_manifest_matches(params, target_ns, info.backup_dir.dir(), &manifest)
+                        .await?
+                {
+                    already_synced += 1;
+                } else {
+                    task_warn!(
+                        worker,
+                        "snapshot {} exists on the remote with different contents - not overwriting",
+                        info.backup_dir.dir(),
+                    );
+                    stats.conflicts += 1;
+                }
+            }
+            _ if pos < cutoff => stats.skipped_transfer_last += 1,
+            _ => to_push.push(info.backup_dir),
+        }
+    }
+
+    if already_synced > 0 {
+        task_log!(
+            worker,
+            "skipped {already_synced} snapshot(s) not newer than the last remote snapshot",
+        );
+    }
+
+    progress.group_snapshots = to_push.len() as u64;
+
+    for (pos, snapshot) in to_push.iter().enumerate() {
+        let result = push_snapshot(worker, params, snapshot, target_ns).await;
+
+        progress.done_snapshots = pos as u64 + 1;
+        task_log!(worker, "percentage done: {}", progress);
+
+        stats.add(result?); // stop on error
+    }
+
+    Ok(stats)
+}
+
+/// Pushes a namespace according to `params`.
+///
+/// Permission checks:
+/// - local groups are filtered by owner and privileges
+/// - remote access is checked by the remote (owner of existing groups, namespace privileges)
+async fn push_ns(
+    worker: &WorkerTask,
+    namespace: &BackupNamespace,
+    params: &PushParameters,
+) -> Result<(StoreProgress, PushStats, bool), Error> {
+    let mut list: Vec<BackupGroup> = ListAccessibleBackupGroups::new_with_privs(
+        &params.source,
+        namespace.clone(),
+        0,
+        Some(PRIV_DATASTORE_READ),
+        Some(PRIV_DATASTORE_BACKUP),
+        Some(&params.owner),
+    )?
+    .filter_map(Result::ok)
+    .map(|group| group.group().clone())
+    .collect();
+
+    list.sort_unstable_by(|a, b| a.ty.cmp(&b.ty).then_with(|| a.id.cmp(&b.id)));
+
+    let unfiltered_count = list.len();
+    let list: Vec<BackupGroup> = list
+        .into_iter()
+        .filter(|group| group.apply_filters(&params.group_filter))
+        .collect();
+    task_log!(
+        worker,
+        "found {} groups to sync (out of {} total)",
+        list.len(),
+        unfiltered_count
+    );
+
+    let target_ns = namespace.map_prefix(&params.ns, &params.target.ns)?;
+    let remote_groups = list_remote_groups(params, &target_ns).await?;
+
+    let mut errors = false;
+    let mut progress = StoreProgress::new(list.len() as u64);
+    let mut push_stats = PushStats::default();
+
+    for (done, group) in list.into_iter().enumerate() {
+        progress.done_groups = done as u64;
+        progress.done_snapshots = 0;
+        progress.group_snapshots = 0;
+
+        let remote_group_exists = remote_groups.contains(&group);
+        match push_group(
+            worker,
+            params,
+            namespace,
+            &target_ns,
+            &group,
+            remote_group_exists,
+            &mut progress,
+        )
+        .await
+        {
+            Ok(stats) => push_stats.add(stats),
+            Err(err) => {
+                task_log!(worker, "sync group {} failed - {}", &group, err);
+                errors = true; // do not stop here, instead continue
+            }
+        }
+    }
+
+    Ok((progress, push_stats, errors))
+}
+
+/// Pushes the local datastore (namespace) to a remote, according to `params`.
+///
+/// Permission checks:
+/// - access to local datastore, namespace anchor and remote entry need to be checked at call site
+/// - local groups are filtered by owner and privileges
+/// - creation of remote namespaces is checked by the remote
+pub(crate) async fn push_store(
+    worker: &WorkerTask,
+    params: PushParameters,
+) -> Result<PushStats, Error> {
+    params
+        .source
+        .set_operation_owner(&worker.upid().to_string());
+
+    match params.limit.effective_out() {
+        Some((rate, burst)) => task_log!(worker, "Rate limit: {rate}/s (burst: {burst})"),
+        None => task_log!(worker, "Rate limit: unlimited"),
+    }
+
+    let mut namespaces: Vec<BackupNamespace> = ListNamespacesRecursive::new_max_depth(
+        params.source.clone(),
+        params.ns.clone(),
+        params.max_depth.unwrap_or(MAX_NAMESPACE_DEPTH),
+    )?
+    .collect::<Result<_, Error>>()?;
+
+    let ns_layers_to_be_pushed = namespaces
+        .iter()
+        .map(BackupNamespace::depth)
+        .max()
+        .map_or(0, |v| v - params.ns.depth());
+    let target_depth = params.target.ns.depth();
+
+    if ns_layers_to_be_pushed + target_depth > MAX_NAMESPACE_DEPTH {
+        bail!(
+            "Syncing would exceed max allowed namespace depth. ({}+{} > {})",
+            ns_layers_to_be_pushed,
+            target_depth,
+            MAX_NAMESPACE_DEPTH
+        );
+    }
+
+    namespaces.sort_unstable_by_key(|a| a.name_len());
+
+    params.target.client.login().await?;
+
+    let target_namespaces = namespaces
+        .iter()
+        .map(|ns| ns.map_prefix(&params.ns, &params.target.ns))
+        .collect::<Result<Vec<_>, Error>>()?;
+    check_and_create_remote_ns(worker, &params, &target_namespaces).await?;
+
+    let mut errors = false;
+    let (mut groups, mut snapshots) = (0, 0);
+    let mut push_stats = PushStats::default();
+
+    for (namespace, target_ns) in namespaces.iter().zip(target_namespaces.iter()) {
+        task_log!(worker, "----");
+        task_log!(
+            worker,
+            "Syncing {} into {}",
+            print_store_and_ns(params.source.name(), namespace),
+            print_store_and_ns(params.target.repo.store(), target_ns),
+        );
+
+        match push_ns(worker, namespace, &params).await {
+            Ok((ns_progress, ns_push_stats, ns_errors)) => {
+                errors |= ns_errors;
+                push_stats.add(ns_push_stats);
+
+                if params.max_depth != Some(0) {
+                    groups += ns_progress.done_groups;
+                    snapshots += ns_progress.done_snapshots;
+                    task_log!(
+                        worker,
+                        "Finished syncing namespace {}, current progress: {} groups, {} snapshots",
+                        namespace,
+                        groups,
+                        snapshots,
+                    );
+                }
+            }
+            Err(err) => {
+                errors = true;
+                task_log!(
+                    worker,
+                    "Encountered errors while syncing namespace {} - {}",
+                    namespace,
+                    err,
+                );
+            }
+        }
+    }
+
+    if push_stats.conflicts > 0 {
+        task_warn!(
+            worker,
+            "{} snapshot(s) differ between local and remote datastore",
+            push_stats.conflicts
+        );
+        errors = true;
+    }
+
+    if errors {
+        bail!("sync failed with some errors.");
+    }
+
+    Ok(push_stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn content(filename: &str, size: Option<u64>, crypt_mode: CryptMode) -> BackupContent {
+        BackupContent {
+            filename: filename.to_string(),
+            crypt_mode: Some(crypt_mode),
+            size,
+            excludes: None,
+        }
+    }
+
+    #[test]
+    fn test_remote_files_match() -> Result<(), Error> {
+        let dir: pbs_api_types::BackupDir = "host/test/2024-01-01T00:00:00Z".parse()?;
+        let mut manifest = BackupManifest::new(dir);
+        manifest.add_file(
+            "root.pxar.didx".to_string(),
+            100,
+            [1u8; 32],
+            CryptMode::None,
+        )?;
+        manifest.add_file("qemu.conf.blob".to_string(), 10, [2u8; 32], CryptMode::None)?;
+
+        let remote = vec![
+            content("qemu.conf.blob", Some(10), CryptMode::None),
+            content("root.pxar.didx", Some(100), CryptMode::None),
+            content(MANIFEST_BLOB_NAME, Some(500), CryptMode::None),
+            content(CLIENT_LOG_BLOB_NAME, None, CryptMode::None),
+        ];
+        assert!(remote_files_match(&manifest, &remote));
+
+        let mut other_size = remote.clone();
+        other_size[1].size = Some(101);
+        assert!(!remote_files_match(&manifest, &other_size));
+
+        let mut encrypted = remote.clone();
+        encrypted[0].crypt_mode = Some(CryptMode::Encrypt);
+        assert!(!remote_files_match(&manifest, &encrypted));
+
+        // a remote that failed to read its manifest lists the files without sizes
+        let mut unknown = remote.clone();
+        unknown[0].size = None;
+        assert!(!remote_files_match(&manifest, &unknown));
+
+        let mut extra = remote;
+        extra.push(content("extra.img.fidx", Some(1), CryptMode::None));
+        assert!(!remote_files_match(&manifest, &extra));
+
+        Ok(())
+    }
+}
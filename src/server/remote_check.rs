@@ -0,0 +1,315 @@
+//! Health check of sync remotes
+//!
+//! A check connects to the remote, verifies its certificate, logs in with the stored credentials
+//! and queries the version and datastore list. The result of the last check of every remote is
+//! kept in a state file, so that broken remotes show up before a sync job fails.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Error};
+use hyper::StatusCode;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::X509VerifyResult;
+
+use proxmox_sys::fs::{create_path, file_read_optional_string, replace_file, CreateOptions};
+use proxmox_sys::{task_log, task_warn};
+
+use pbs_api_types::{ApiError, ApiErrorCode, Authid, Remote, RemoteCheckResult};
+use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
+use pbs_client::ApiResponseError;
+use pbs_tools::cert::CertInfo;
+use proxmox_rest_server::WorkerTask;
+
+use crate::api2::config::remote::remote_client_config;
+use crate::server::jobstate::Job;
+
+const REMOTE_CHECK_STATE_DIR: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/remote-check");
+
+/// Timeout for establishing the connection and for single reads and writes of the TLS probe.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Upper bound for a whole check, so that a dead remote cannot block the caller.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn state_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(REMOTE_CHECK_STATE_DIR);
+    path.push(format!("{name}.json"));
+    path
+}
+
+/// Record `result` as the last check result of remote `name`.
+pub fn save_remote_check_result(name: &str, result: &RemoteCheckResult) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let options = CreateOptions::new()
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+
+    create_path(REMOTE_CHECK_STATE_DIR, None, Some(options.clone()))?;
+
+    replace_file(
+        state_path(name),
+        serde_json::to_string(result)?.as_bytes(),
+        options.perm(nix::sys::stat::Mode::from_bits_truncate(0o644)),
+        false,
+    )
+}
+
+/// Returns the result of the last check of remote `name`, if any.
+pub fn read_remote_check_result(name: &str) -> Result<Option<RemoteCheckResult>, Error> {
+    match file_read_optional_string(state_path(name))? {
+        Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Remove the recorded check result of remote `name`.
+pub fn remove_remote_check_result(name: &str) -> Result<(), Error> {
+    match std::fs::remove_file(state_path(name)) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => bail!("unable to remove check result of remote '{name}' - {err}"),
+    }
+}
+
+/// Connect to `host` and return the fingerprint of its certificate, and whether the certificate
+/// is trusted by the system.
+fn probe_certificate(host: &str, port: u16) -> Result<(String, bool), Error> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| format_err!("unable to resolve '{host}' - {err}"))?;
+
+    let mut last_err = format_err!("no address found for '{host}'");
+    let mut stream = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(conn) => {
+                stream = Some(conn);
+                break;
+            }
+            Err(err) => last_err = format_err!("unable to connect to {addr} - {err}"),
+        }
+    }
+    let stream = stream.ok_or(last_err)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    // the certificate is checked below, to tell a wrong fingerprint from other errors
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    builder.set_verify(SslVerifyMode::NONE);
+    let conn = builder
+        .build()
+        .connect(host, stream)
+        .map_err(|err| format_err!("TLS handshake with '{host}' failed - {err}"))?;
+
+    let cert = conn
+        .ssl()
+        .peer_certificate()
+        .ok_or_else(|| format_err!("remote did not send a certificate"))?;
+    let fingerprint = CertInfo::from_pem(&cert.to_pem()?)?.fingerprint()?;
+    let trusted = conn.ssl().verify_result() == X509VerifyResult::OK;
+
+    Ok((fingerprint, trusted))
+}
+
+async fn check_remote_do(remote: &Remote, result: &mut RemoteCheckResult) -> Result<(), Error> {
+    let host = remote.config.host.clone();
+    let port = remote.config.port.unwrap_or(8007);
+    let (fingerprint, trusted) =
+        tokio::task::spawn_blocking(move || probe_certificate(&host, port)).await??;
+    result.reachable = true;
+
+    match remote.config.fingerprint {
+        Some(ref expected) if expected.to_lowercase() != fingerprint => {
+            bail!("certificate fingerprint {fingerprint} does not match configured {expected}")
+        }
+        None if !trusted => {
+            bail!("certificate is not trusted and no fingerprint configured ({fingerprint})")
+        }
+        _ => result.fingerprint_ok = true,
+    }
+
+    let client = remote_client_config(remote, None)?;
+    client
+        .login()
+        .await
+        .map_err(|err| format_err!("authentication failed - {err}"))?;
+    result.auth_ok = true;
+
+    let version = client.get("api2/json/version", None).await?;
+    let data = &version["data"];
+    result.version = match (data["version"].as_str(), data["release"].as_str()) {
+        (Some(version), Some(release)) => Some(format!("{version}-{release}")),
+        (Some(version), None) => Some(version.to_string()),
+        _ => None,
+    };
+
+    let datastores = client.get("api2/json/admin/datastore", None).await?;
+    result.datastore_count = datastores["data"].as_array().map(|list| list.len() as u64);
+
+    Ok(())
+}
+
+/// Check the connection to `remote`, the whole check is limited to 30 seconds.
+///
+/// Failures are reported in the `error` field of the result.
+pub async fn check_remote(remote: &Remote) -> RemoteCheckResult {
+    let mut result = RemoteCheckResult {
+        time: proxmox_time::epoch_i64(),
+        ..Default::default()
+    };
+
+    let check = tokio::time::timeout(CHECK_TIMEOUT, check_remote_do(remote, &mut result)).await;
+    let error = match check {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some(format!("timeout after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    result.error = error;
+
+    result
+}
+
+/// Find out from `err` why connecting to `remote` failed.
+///
+/// Returns an [`ApiError`] telling whether the remote is unreachable, its certificate does not
+/// match, or the login failed. Other errors are returned unchanged.
+pub fn classify_remote_error(remote: &Remote, err: Error) -> Error {
+    match remote_error_code(&err) {
+        Some(code) => ApiError::new(code, format!("remote '{}' - {err}", remote.name)).into(),
+        None => err,
+    }
+}
+
+fn remote_error_code(err: &Error) -> Option<ApiErrorCode> {
+    if let Some(response) = err.downcast_ref::<ApiResponseError>() {
+        return match response.status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Some(ApiErrorCode::RemoteAuthFailed)
+            }
+            _ => None,
+        };
+    }
+
+    // errors of the first login are passed on as plain message, so look at the text
+    let msg = format!("{err:#}");
+    if msg.contains("certificate verify failed") {
+        Some(ApiErrorCode::RemoteCertificate)
+    } else if msg.contains("error trying to connect")
+        || msg.contains("dns error")
+        || msg.contains("http request timed out")
+    {
+        Some(ApiErrorCode::RemoteUnreachable)
+    } else if msg.contains("authentication failed") || msg.contains("permission check failed") {
+        Some(ApiErrorCode::RemoteAuthFailed)
+    } else {
+        None
+    }
+}
+
+/// Runs a scheduled health check of a remote.
+pub fn do_remote_check_job(
+    mut job: Job,
+    remote: Remote,
+    auth_id: &Authid,
+    schedule: Option<String>,
+) -> Result<String, Error> {
+    let worker_type = job.jobtype().to_string();
+    let upid_str = WorkerTask::spawn(
+        &worker_type,
+        Some(remote.name.clone()),
+        auth_id.to_string(),
+        false,
+        move |worker| async move {
+            job.start(&worker.upid().to_string())?;
+
+            task_log!(worker, "checking remote '{}'", remote.name);
+            if let Some(event_str) = schedule {
+                task_log!(worker, "task triggered by schedule '{event_str}'");
+            }
+
+            let check = check_remote(&remote).await;
+            if let Err(err) = save_remote_check_result(&remote.name, &check) {
+                task_warn!(worker, "unable to save check result - {err}");
+            }
+
+            let result = match check.error {
+                Some(ref err) => Err(format_err!("remote check failed - {err}")),
+                None => {
+                    task_log!(
+                        worker,
+                        "remote reachable, version {}, {} datastore(s)",
+                        check.version.as_deref().unwrap_or("unknown"),
+                        check.datastore_count.unwrap_or(0),
+                    );
+                    Ok(())
+                }
+            };
+
+            let status = worker.create_state(&result);
+
+            if let Err(err) = job.finish(status) {
+                eprintln!("could not finish job state for {}: {err}", job.jobtype());
+            }
+
+            result
+        },
+    )?;
+
+    Ok(upid_str)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remote_error_code() {
+        let response = |status: StatusCode| -> Error {
+            ApiResponseError::from_response(status, b"no access").into()
+        };
+        assert_eq!(
+            remote_error_code(&response(StatusCode::UNAUTHORIZED)),
+            Some(ApiErrorCode::RemoteAuthFailed)
+        );
+        assert_eq!(remote_error_code(&response(StatusCode::NOT_FOUND)), None);
+
+        let cases = [
+            (
+                "remote connection to 'pbs' failed - error trying to connect: tcp connect \
+                error: Connection refused (os error 111)",
+                Some(ApiErrorCode::RemoteUnreachable),
+            ),
+            (
+                "remote connection to 'pbs' failed - error trying to connect: dns error: failed \
+                to lookup address information",
+                Some(ApiErrorCode::RemoteUnreachable),
+            ),
+            (
+                "remote connection to 'pbs' failed - http request timed out",
+                Some(ApiErrorCode::RemoteUnreachable),
+            ),
+            (
+                "remote connection to 'pbs' failed - error trying to connect: error:0A000086:SSL \
+                routines:tls_post_process_server_certificate:certificate verify failed",
+                Some(ApiErrorCode::RemoteCertificate),
+            ),
+            (
+                "remote connection to 'pbs' failed - permission check failed.",
+                Some(ApiErrorCode::RemoteAuthFailed),
+            ),
+            ("datastore does not exist", None),
+        ];
+        for (msg, code) in cases {
+            assert_eq!(remote_error_code(&format_err!("{msg}")), code, "{msg}");
+        }
+
+        // the cause is looked at as well
+        let err = format_err!("certificate verify failed").context("remote connection failed");
+        assert_eq!(
+            remote_error_code(&err),
+            Some(ApiErrorCode::RemoteCertificate)
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Filters selecting which snapshots of a group are synced, based on their contents.
+
+use pbs_api_types::{BackupContent, CryptMode, VerifyState};
+use pbs_datastore::manifest::{BackupManifest, CLIENT_LOG_BLOB_NAME, MANIFEST_BLOB_NAME};
+
+/// Snapshot filters of a sync job, applied after the group filters and before `transfer-last`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SnapshotFilter {
+    /// Only sync snapshots with all archives encrypted
+    pub(crate) encrypted_only: bool,
+    /// Only sync snapshots whose last verification was successful
+    pub(crate) verified_only: bool,
+}
+
+/// Why a snapshot was rejected by a [`SnapshotFilter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SnapshotFilterReason {
+    NotEncrypted,
+    NotVerified,
+}
+
+impl std::fmt::Display for SnapshotFilterReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotFilterReason::NotEncrypted => write!(f, "not fully encrypted"),
+            SnapshotFilterReason::NotVerified => write!(f, "not successfully verified"),
+        }
+    }
+}
+
+impl SnapshotFilter {
+    /// Whether any filter is enabled.
+    pub(crate) fn is_active(&self) -> bool {
+        self.encrypted_only || self.verified_only
+    }
+
+    /// Check a snapshot with the given `files` and last verification result.
+    ///
+    /// The manifest and the client log are not archives and ignored for `encrypted-only`.
+    /// Snapshots mixing encrypted and unencrypted archives are rejected, as are snapshots
+    /// without any archive.
+    pub(crate) fn check(
+        &self,
+        files: &[BackupContent],
+        verify_state: Option<VerifyState>,
+    ) -> Result<(), SnapshotFilterReason> {
+        if self.encrypted_only {
+            let mut archives = files
+                .iter()
+                .filter(|file| {
+                    file.filename != MANIFEST_BLOB_NAME && file.filename != CLIENT_LOG_BLOB_NAME
+                })
+                .peekable();
+
+            if archives.peek().is_none()
+                || !archives.all(|file| file.crypt_mode == Some(CryptMode::Encrypt))
+            {
+                return Err(SnapshotFilterReason::NotEncrypted);
+            }
+        }
+
+        if self.verified_only && verify_state != Some(VerifyState::Ok) {
+            return Err(SnapshotFilterReason::NotVerified);
+        }
+
+        Ok(())
+    }
+
+    /// Check a snapshot by its manifest.
+    pub(crate) fn check_manifest(
+        &self,
+        manifest: &BackupManifest,
+    ) -> Result<(), SnapshotFilterReason> {
+        self.check(&manifest_files(manifest), manifest_verify_state(manifest))
+    }
+}
+
+/// The archives listed in `manifest`, like they are returned by the snapshot list API.
+pub(crate) fn manifest_files(manifest: &BackupManifest) -> Vec<BackupContent> {
+    manifest
+        .files()
+        .iter()
+        .map(|file| BackupContent {
+            filename: file.filename.clone(),
+            crypt_mode: Some(file.crypt_mode),
+            size: Some(file.size),
+            excludes: None,
+        })
+        .collect()
+}
+
+/// The result of the last verification recorded in `manifest`.
+pub(crate) fn manifest_verify_state(manifest: &BackupManifest) -> Option<VerifyState> {
+    manifest.verify_history().pop().map(|state| state.state)
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+
+    use pbs_api_types::{SnapshotVerifyState, UPID};
+
+    use super::*;
+
+    fn manifest(crypt_modes: &[CryptMode]) -> Result<BackupManifest, Error> {
+        let mut manifest = BackupManifest::new("host/elsa/2020-06-26T13:56:05Z".parse()?);
+        for (idx, crypt_mode) in crypt_modes.iter().enumerate() {
+            manifest.add_file(
+                format!("archive{idx}.pxar.didx"),
+                1024,
+                [0u8; 32],
+                *crypt_mode,
+            )?;
+        }
+        Ok(manifest)
+    }
+
+    fn record_verify_state(manifest: &mut BackupManifest, state: VerifyState) -> Result<(), Error> {
+        let upid: UPID =
+            "UPID:elsa:00000F5B:00055E9C:00000000:5EF5F7D5:verify:store\\x3ahost-elsa:root@pam:"
+                .parse()?;
+        manifest.record_verify_state(SnapshotVerifyState {
+            upid,
+            state,
+            time: Some(0),
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_only() -> Result<(), Error> {
+        let filter = SnapshotFilter {
+            encrypted_only: true,
+            ..Default::default()
+        };
+        let not_encrypted = Err(SnapshotFilterReason::NotEncrypted);
+
+        assert_eq!(
+            filter.check_manifest(&manifest(&[CryptMode::Encrypt])?),
+            Ok(())
+        );
+        assert_eq!(
+            filter.check_manifest(&manifest(&[CryptMode::Encrypt, CryptMode::Encrypt])?),
+            Ok(()),
+        );
+        assert_eq!(
+            filter.check_manifest(&manifest(&[CryptMode::None])?),
+            not_encrypted
+        );
+        assert_eq!(
+            filter.check_manifest(&manifest(&[CryptMode::SignOnly])?),
+            not_encrypted
+        );
+        assert_eq!(filter.check_manifest(&manifest(&[])?), not_encrypted);
+
+        // mixed mode snapshots are skipped if any archive is not encrypted
+        for mode in [CryptMode::None, CryptMode::SignOnly] {
+            let mixed = manifest(&[CryptMode::Encrypt, mode])?;
+            assert_eq!(filter.check_manifest(&mixed), not_encrypted);
+        }
+
+        // the manifest and client log entries of the snapshot list are not archives
+        let mut files = manifest_files(&manifest(&[CryptMode::Encrypt])?);
+        for filename in [MANIFEST_BLOB_NAME, CLIENT_LOG_BLOB_NAME] {
+            files.push(BackupContent {
+                filename: filename.to_string(),
+                crypt_mode: Some(CryptMode::None),
+                size: None,
+                excludes: None,
+            });
+        }
+        assert_eq!(filter.check(&files, None), Ok(()));
+
+        // snapshots listed by old remotes lack the crypt mode
+        files[0].crypt_mode = None;
+        assert_eq!(filter.check(&files, None), not_encrypted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verified_only() -> Result<(), Error> {
+        let filter = SnapshotFilter {
+            verified_only: true,
+            ..Default::default()
+        };
+        let not_verified = Err(SnapshotFilterReason::NotVerified);
+
+        let mut manifest = manifest(&[CryptMode::None])?;
+        assert_eq!(filter.check_manifest(&manifest), not_verified);
+
+        record_verify_state(&mut manifest, VerifyState::Ok)?;
+        assert_eq!(filter.check_manifest(&manifest), Ok(()));
+
+        // only the last verification counts
+        record_verify_state(&mut manifest, VerifyState::Failed)?;
+        assert_eq!(filter.check_manifest(&manifest), not_verified);
+
+        let both = SnapshotFilter {
+            encrypted_only: true,
+            verified_only: true,
+        };
+        assert_eq!(
+            both.check_manifest(&manifest),
+            Err(SnapshotFilterReason::NotEncrypted),
+        );
+        assert_eq!(SnapshotFilter::default().check_manifest(&manifest), Ok(()));
+
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
+use std::time::Instant;
+
 use anyhow::{format_err, Error};
 
-use pbs_api_types::{Authid, Operation, VerificationJobConfig};
+use pbs_api_types::{Authid, JobSummary, Operation, VerificationJobConfig};
 use pbs_datastore::DataStore;
 use proxmox_rest_server::WorkerTask;
 use proxmox_sys::task_log;
 
 use crate::{
-    backup::{verify_all_backups, verify_filter},
+    backup::{verify_all_backups, VerifySkipStats},
     server::jobstate::Job,
 };
 
@@ -22,8 +24,9 @@ pub fn do_verification_job(
 
     let outdated_after = verification_job.outdated_after;
     let ignore_verified_snapshots = verification_job.ignore_verified.unwrap_or(true);
+    let skip_bad_chunk_report = verification_job.skip_bad_chunk_report.unwrap_or(false);
 
-    let (email, notify) = crate::server::lookup_datastore_notify_settings(&verification_job.store);
+    let notification = crate::server::lookup_datastore_notify_settings(&verification_job.store);
 
     // FIXME encode namespace here for filter/ACL check?
     let job_id = format!("{}:{}", &verification_job.store, job.jobname());
@@ -34,7 +37,10 @@ pub fn do_verification_job(
         auth_id.to_string(),
         to_stdout,
         move |worker| {
+            let _io_guard = pbs_datastore::task_io_stats::enter_task(&worker.upid().to_string());
             job.start(&worker.upid().to_string())?;
+            datastore.set_operation_owner(&worker.upid().to_string());
+            let start_time = Instant::now();
 
             task_log!(worker, "Starting datastore verify job '{}'", job_id);
             if let Some(event_str) = schedule {
@@ -46,6 +52,7 @@ pub fn do_verification_job(
                 None => Default::default(),
             };
 
+            let skip_stats = VerifySkipStats::default();
             let verify_worker = crate::backup::VerifyWorker::new(worker.clone(), datastore);
             let result = verify_all_backups(
                 &verify_worker,
@@ -53,10 +60,17 @@ pub fn do_verification_job(
                 ns,
                 verification_job.max_depth,
                 None,
-                Some(&move |manifest| {
-                    verify_filter(ignore_verified_snapshots, outdated_after, manifest)
+                // jobs always re-verify snapshots which failed their last verification
+                Some(&|manifest| {
+                    skip_stats.filter(ignore_verified_snapshots, outdated_after, true, manifest)
                 }),
-            );
+            )
+            .and_then(|failed_dirs| {
+                if !skip_bad_chunk_report {
+                    verify_worker.report_bad_chunks()?;
+                }
+                Ok(failed_dirs)
+            });
             let job_result = match result {
                 Ok(ref failed_dirs) if failed_dirs.is_empty() => Ok(()),
                 Ok(ref failed_dirs) => {
@@ -72,18 +86,39 @@ pub fn do_verification_job(
                 Err(_) => Err(format_err!("verification failed - job aborted")),
             };
 
+            skip_stats.log(&*worker);
+
+            let mut summary = JobSummary {
+                skipped: skip_stats.skipped() as u64,
+                duration: start_time.elapsed().as_secs_f64(),
+                ..Default::default()
+            };
+            match result {
+                Ok(ref failed_dirs) => {
+                    for dir in failed_dirs {
+                        summary.add_failure(dir, "verification failed");
+                    }
+                    summary.succeeded =
+                        (skip_stats.checked() as u64).saturating_sub(summary.failed);
+                }
+                Err(ref err) => summary.add_failure(&verification_job.store, err),
+            }
+
             let status = worker.create_state(&job_result);
+            let severity = crate::server::task_severity(&status);
 
-            if let Err(err) = job.finish(status) {
+            if let Err(err) = job.finish_with_summary(status, Some(summary.clone())) {
                 eprintln!("could not finish job state for {}: {}", job.jobtype(), err);
             }
 
-            if let Some(email) = email {
-                if let Err(err) =
-                    crate::server::send_verify_status(&email, notify, verification_job, &result)
-                {
-                    eprintln!("send verify notification failed: {}", err);
-                }
+            if let Err(err) = crate::server::send_verify_status(
+                &notification,
+                verification_job,
+                &result,
+                severity,
+                &summary,
+            ) {
+                eprintln!("send verify notification failed: {}", err);
             }
 
             job_result
@@ -20,7 +20,8 @@ use pbs_tape::sg_tape::drive_get_encryption;
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
-    Fingerprint, Lp17VolumeStatistics, LtoDriveAndMediaStatus, LtoTapeDrive, MamAttribute,
+    DeviceActivity, Fingerprint, Lp17VolumeStatistics, LtoDriveAndMediaStatus, LtoTapeDrive,
+    MamAttribute,
 };
 use pbs_key_config::KeyConfig;
 use pbs_tape::{
@@ -30,7 +31,7 @@ use pbs_tape::{
 use proxmox_sys::command::run_command;
 
 use crate::tape::{
-    drive::TapeDriver,
+    drive::{set_tape_device_activity, TapeDriver},
     file_formats::{MediaSetLabel, PROXMOX_BACKUP_MEDIA_SET_LABEL_MAGIC_1_0},
 };
 
@@ -43,6 +44,7 @@ impl Drop for LtoTapeHandle {
                 log::error!("could not unload encryption key from drive: {err}");
             }
         }
+        self.record_activity(DeviceActivity::NoActivity);
     }
 }
 
@@ -50,6 +52,10 @@ impl Drop for LtoTapeHandle {
 pub struct LtoTapeHandle {
     sg_tape: SgTape,
     encryption_key_loaded: bool,
+    // configured drive name, used to record the current activity
+    drive_name: Option<String>,
+    // last recorded activity
+    activity: DeviceActivity,
 }
 
 impl LtoTapeHandle {
@@ -59,6 +65,8 @@ impl LtoTapeHandle {
         Ok(Self {
             sg_tape,
             encryption_key_loaded: false,
+            drive_name: None,
+            activity: DeviceActivity::NoActivity,
         })
     }
 
@@ -72,27 +80,65 @@ impl LtoTapeHandle {
         let handle = Self {
             sg_tape,
             encryption_key_loaded: false,
+            drive_name: Some(config.name.clone()),
+            activity: DeviceActivity::NoActivity,
         };
 
         Ok(handle)
     }
 
+    /// Record the current activity for status queries while the drive is locked
+    fn record_activity(&mut self, activity: DeviceActivity) {
+        if self.activity == activity {
+            return;
+        }
+        self.activity = activity;
+        if let Some(drive) = &self.drive_name {
+            if let Err(err) = set_tape_device_activity(drive, activity) {
+                log::warn!("could not record activity of drive '{drive}': {err}");
+            }
+        }
+    }
+
+    /// Run a (possibly long running) drive operation, recording its activity
+    ///
+    /// Every operation changing the position ends reading or writing the current file, so the
+    /// drive is idle afterwards, even if the operation failed.
+    fn with_activity<R>(
+        &mut self,
+        activity: DeviceActivity,
+        op: impl FnOnce(&mut SgTape) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        self.record_activity(activity);
+        let result = op(&mut self.sg_tape);
+        self.record_activity(DeviceActivity::NoActivity);
+        result
+    }
+
     /// Get Tape and Media status
     pub fn get_drive_and_media_status(&mut self) -> Result<LtoDriveAndMediaStatus, Error> {
         self.sg_tape.get_drive_and_media_status()
     }
 
     pub fn forward_space_count_files(&mut self, count: usize) -> Result<(), Error> {
-        self.sg_tape.space_filemarks(count.try_into()?)
+        let count = count.try_into()?;
+        self.with_activity(DeviceActivity::Locating, |sg_tape| {
+            sg_tape.space_filemarks(count)
+        })
     }
 
     pub fn backward_space_count_files(&mut self, count: usize) -> Result<(), Error> {
-        self.sg_tape.space_filemarks(-count.try_into()?)
+        let count: isize = count.try_into()?;
+        self.with_activity(DeviceActivity::Locating, |sg_tape| {
+            sg_tape.space_filemarks(-count)
+        })
     }
 
     /// Position the tape after filemark count. Count 0 means BOT.
     pub fn locate_file(&mut self, position: u64) -> Result<(), Error> {
-        self.sg_tape.locate_file(position)
+        self.with_activity(DeviceActivity::Locating, |sg_tape| {
+            sg_tape.locate_file(position)
+        })
     }
 
     /// Read Cartridge Memory (MAM Attributes)
@@ -119,7 +165,9 @@ impl TapeDriver for LtoTapeHandle {
 
     /// Go to the end of the recorded media (for appending files).
     fn move_to_eom(&mut self, write_missing_eof: bool) -> Result<(), Error> {
-        self.sg_tape.move_to_eom(write_missing_eof)
+        self.with_activity(DeviceActivity::Locating, |sg_tape| {
+            sg_tape.move_to_eom(write_missing_eof)
+        })
     }
 
     fn move_to_last_file(&mut self) -> Result<(), Error> {
@@ -149,7 +197,7 @@ impl TapeDriver for LtoTapeHandle {
     }
 
     fn rewind(&mut self) -> Result<(), Error> {
-        self.sg_tape.rewind()
+        self.with_activity(DeviceActivity::Rewinding, |sg_tape| sg_tape.rewind())
     }
 
     fn current_file_number(&mut self) -> Result<u64, Error> {
@@ -157,16 +205,23 @@ impl TapeDriver for LtoTapeHandle {
     }
 
     fn format_media(&mut self, fast: bool) -> Result<(), Error> {
-        self.sg_tape.format_media(fast)
+        let activity = if fast {
+            DeviceActivity::Formatting
+        } else {
+            DeviceActivity::Erasing
+        };
+        self.with_activity(activity, |sg_tape| sg_tape.format_media(fast))
     }
 
     fn read_next_file<'a>(&'a mut self) -> Result<Box<dyn TapeRead + 'a>, BlockReadError> {
+        self.record_activity(DeviceActivity::Reading);
         let reader = self.sg_tape.open_reader()?;
         let handle: Box<dyn TapeRead> = Box::new(reader);
         Ok(handle)
     }
 
     fn write_file<'a>(&'a mut self) -> Result<Box<dyn TapeWrite + 'a>, std::io::Error> {
+        self.record_activity(DeviceActivity::Writing);
         let handle = self.sg_tape.open_writer();
         Ok(Box::new(handle))
     }
@@ -221,7 +276,10 @@ impl TapeDriver for LtoTapeHandle {
         let encrypt_fingerprint = media_set_label
             .encryption_key_fingerprint
             .clone()
-            .map(|fp| (fp, media_set_label.uuid.clone()));
+            .map(|fp| {
+                let wrapped_key = media_set_label.wrapped_key.clone();
+                (fp, media_set_label.uuid.clone(), wrapped_key)
+            });
 
         self.set_encryption(encrypt_fingerprint)?;
 
@@ -230,7 +288,7 @@ impl TapeDriver for LtoTapeHandle {
 
     /// Rewind and put the drive off line (Eject media).
     fn eject_media(&mut self) -> Result<(), Error> {
-        self.sg_tape.eject()
+        self.with_activity(DeviceActivity::Unloading, |sg_tape| sg_tape.eject())
     }
 
     /// Read Tape Alert Flags
@@ -244,15 +302,16 @@ impl TapeDriver for LtoTapeHandle {
     /// to spawn setuid binary 'sg-tape-cmd'.
     fn set_encryption(
         &mut self,
-        key_fingerprint: Option<(Fingerprint, Uuid)>,
+        key_fingerprint: Option<(Fingerprint, Uuid, Option<String>)>,
     ) -> Result<(), Error> {
-        if let Some((fingerprint, uuid)) = key_fingerprint {
+        if let Some((fingerprint, uuid, wrapped_key)) = key_fingerprint {
             let fingerprint = fingerprint.signature();
-            let output = run_sg_tape_cmd(
-                "encryption",
-                &["--fingerprint", &fingerprint, "--uuid", &uuid.to_string()],
-                self.sg_tape.file_mut().as_raw_fd(),
-            )?;
+            let uuid = uuid.to_string();
+            let mut args = vec!["--fingerprint", &fingerprint, "--uuid", &uuid];
+            if let Some(ref wrapped_key) = wrapped_key {
+                args.extend(["--wrapped-key", wrapped_key]);
+            }
+            let output = run_sg_tape_cmd("encryption", &args, self.sg_tape.file_mut().as_raw_fd())?;
             self.encryption_key_loaded = true;
             let result: Result<(), String> = serde_json::from_str(&output)?;
             result.map_err(|err| format_err!("{}", err))
@@ -270,9 +329,26 @@ impl TapeDriver for LtoTapeHandle {
     }
 }
 
+const SG_TAPE_CMD_PATH: &str = "/usr/lib/x86_64-linux-gnu/proxmox-backup/sg-tape-cmd";
+
+/// Generate a new data key for media set `uuid`, wrapped by the encryption key `fingerprint`
+///
+/// Note: Only 'root' can read secret encryption keys, so we need
+/// to spawn setuid binary 'sg-tape-cmd'.
+pub fn generate_wrapped_media_set_key(
+    fingerprint: &Fingerprint,
+    uuid: &Uuid,
+) -> Result<String, Error> {
+    let mut command = std::process::Command::new(SG_TAPE_CMD_PATH);
+    command.args(["wrap-key", "--fingerprint", &fingerprint.signature()]);
+    command.args(["--uuid", &uuid.to_string()]);
+    let output = run_command(command, None)?;
+    let result: Result<String, String> = serde_json::from_str(&output)?;
+    result.map_err(|err| format_err!("{}", err))
+}
+
 fn run_sg_tape_cmd(subcmd: &str, args: &[&str], fd: RawFd) -> Result<String, Error> {
-    let mut command =
-        std::process::Command::new("/usr/lib/x86_64-linux-gnu/proxmox-backup/sg-tape-cmd");
+    let mut command = std::process::Command::new(SG_TAPE_CMD_PATH);
     command.args([subcmd]);
     command.args(["--stdin"]);
     command.args(args);
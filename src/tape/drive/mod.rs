@@ -1,6 +1,8 @@
 //! Tape drivers
 
 mod virtual_tape;
+#[cfg(test)]
+pub(crate) use virtual_tape::open_virtual_tape_drive;
 
 mod lto;
 pub use lto::*;
@@ -22,7 +24,7 @@ use proxmox_section_config::SectionConfigData;
 use proxmox_sys::{task_log, WorkerTaskContext};
 use proxmox_uuid::Uuid;
 
-use pbs_api_types::{Fingerprint, LtoTapeDrive, VirtualTapeDrive};
+use pbs_api_types::{DeviceActivity, Fingerprint, LtoTapeDrive, VirtualTapeDrive};
 use pbs_key_config::KeyConfig;
 
 use pbs_tape::{sg_tape::TapeAlertFlags, BlockReadError, MediaContentHeader, TapeRead, TapeWrite};
@@ -220,13 +222,14 @@ pub trait TapeDriver {
     ///
     /// We use the media_set_uuid to XOR the secret key with the
     /// uuid (first 16 bytes), so that each media set uses an unique
-    /// key for encryption.
+    /// key for encryption. If the media set label contains a wrapped
+    /// data key, that key is unwrapped and used instead of the secret key.
     ///
     /// Should be called as part of write_media_set_label or read_label,
     /// so this should not be called manually.
     fn set_encryption(
         &mut self,
-        key_fingerprint: Option<(Fingerprint, Uuid)>,
+        key_fingerprint: Option<(Fingerprint, Uuid, Option<String>)>,
     ) -> Result<(), Error> {
         if key_fingerprint.is_some() {
             bail!("drive does not support encryption");
@@ -566,9 +569,62 @@ pub fn set_tape_device_state(drive: &str, state: &str) -> Result<(), Error> {
         .owner(backup_user.uid)
         .group(backup_user.gid);
 
+    if state.is_empty() {
+        // the drive gets unlocked, the recorded activity is stale from now on
+        let _ = std::fs::remove_file(tape_device_activity_path(drive));
+    }
+
     replace_file(path, state.as_bytes(), options, false)
 }
 
+// Drive names cannot start with a dot, so this never clashes with a drive state file.
+fn tape_device_activity_path(drive: &str) -> PathBuf {
+    let mut path = PathBuf::from(crate::tape::DRIVE_STATE_DIR);
+    path.push(format!(".{drive}.activity"));
+    path
+}
+
+/// Records the current activity of a locked drive
+///
+/// Status queries use this instead of asking the drive, which could
+/// interfere with the running operation. This function does not lock,
+/// so make sure the drive is locked.
+pub fn set_tape_device_activity(drive: &str, activity: DeviceActivity) -> Result<(), Error> {
+    let backup_user = pbs_config::backup_user()?;
+    let mode = nix::sys::stat::Mode::from_bits_truncate(0o0644);
+    let options = CreateOptions::new()
+        .perm(mode)
+        .owner(backup_user.uid)
+        .group(backup_user.gid);
+
+    let data = serde_json::to_vec(&activity)?;
+    replace_file(tape_device_activity_path(drive), &data, options, false)
+}
+
+/// Get the lock owner, the time since the drive got locked and the
+/// last recorded activity, if the drive is locked.
+pub fn get_tape_device_lock_info(
+    config: &SectionConfigData,
+    drive: &str,
+) -> Result<Option<(String, u64, Option<DeviceActivity>)>, Error> {
+    let state = match get_tape_device_state(config, drive)? {
+        Some(state) if !state.is_empty() => state,
+        _ => return Ok(None),
+    };
+
+    let path = format!("{}/{}", crate::tape::DRIVE_STATE_DIR, drive);
+    let duration = std::fs::metadata(path)?
+        .modified()?
+        .elapsed()
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let activity = file_read_optional_string(tape_device_activity_path(drive))?
+        .and_then(|data| serde_json::from_str(&data).ok());
+
+    Ok(Some((state, duration, activity)))
+}
+
 /// Get the device state
 pub fn get_tape_device_state(
     config: &SectionConfigData,
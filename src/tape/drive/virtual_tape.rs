@@ -2,16 +2,20 @@
 
 use std::fs::File;
 use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use anyhow::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
 
 use proxmox_sys::fs::{replace_file, CreateOptions};
 
+use pbs_api_types::VirtualTapeFailMode;
 use pbs_key_config::KeyConfig;
 use pbs_tape::{
-    BlockReadError, BlockedReader, BlockedWriter, DriveStatus, ElementStatus, EmulateTapeReader,
-    EmulateTapeWriter, MediaContentHeader, MtxStatus, StorageElementStatus, TapeRead, TapeWrite,
+    BlockRead, BlockReadError, BlockWrite, BlockedReader, BlockedWriter, DriveStatus,
+    ElementStatus, EmulateTapeReader, EmulateTapeWriter, MediaContentHeader, MtxStatus,
+    StorageElementStatus, TapeRead, TapeWrite, PROXMOX_TAPE_BLOCK_SIZE,
 };
 
 use crate::tape::{
@@ -34,6 +38,13 @@ pub fn open_virtual_tape_drive(config: &VirtualTapeDrive) -> Result<VirtualTapeH
             drive_name: config.name.clone(),
             max_size: config.max_size.unwrap_or(64 * 1024 * 1024),
             path: std::path::PathBuf::from(&config.path),
+            fault: config
+                .fail_after_bytes
+                .map(|fail_after_bytes| FaultInjection {
+                    fail_after_bytes,
+                    mode: config.fail_mode.unwrap_or_default(),
+                    transferred: Arc::new(AtomicU64::new(0)),
+                }),
         })
     })
     .map_err(|err: Error| {
@@ -62,10 +73,89 @@ struct TapeIndex {
     files: usize,
 }
 
+/// Failure injection state, shared by all files read or written through a drive handle
+#[derive(Clone)]
+struct FaultInjection {
+    fail_after_bytes: u64,
+    mode: VirtualTapeFailMode,
+    transferred: Arc<AtomicU64>,
+}
+
+impl FaultInjection {
+    /// Account `len` bytes, returns the number of bytes transferred before.
+    fn account(&self, len: usize) -> u64 {
+        self.transferred.fetch_add(len as u64, Ordering::SeqCst)
+    }
+
+    fn triggered(&self) -> bool {
+        self.transferred.load(Ordering::SeqCst) >= self.fail_after_bytes
+    }
+}
+
+/// Block writer which injects write failures (modes 'eot' and 'io-error')
+struct FaultInjectingWriter<W> {
+    writer: W,
+    fault: FaultInjection,
+}
+
+impl<W: BlockWrite> BlockWrite for FaultInjectingWriter<W> {
+    fn write_block(&mut self, buffer: &[u8]) -> Result<bool, io::Error> {
+        if !self.fault.triggered() {
+            self.fault.account(buffer.len());
+            return self.writer.write_block(buffer);
+        }
+
+        match self.fault.mode {
+            VirtualTapeFailMode::Eot => {
+                // like a real drive, allow a few blocks after the early warning
+                let overrun = self.fault.account(buffer.len()) - self.fault.fail_after_bytes;
+                if overrun >= 2 * PROXMOX_TAPE_BLOCK_SIZE as u64 {
+                    return Err(io::Error::from_raw_os_error(
+                        nix::errno::Errno::ENOSPC as i32,
+                    ));
+                }
+                self.writer.write_block(buffer)?;
+                Ok(true)
+            }
+            VirtualTapeFailMode::IoError => {
+                Err(io::Error::from_raw_os_error(nix::errno::Errno::EIO as i32))
+            }
+            VirtualTapeFailMode::ReadError => self.writer.write_block(buffer),
+        }
+    }
+
+    fn write_filemark(&mut self) -> Result<(), io::Error> {
+        if self.fault.mode == VirtualTapeFailMode::IoError && self.fault.triggered() {
+            return Err(io::Error::from_raw_os_error(nix::errno::Errno::EIO as i32));
+        }
+        self.writer.write_filemark()
+    }
+}
+
+/// Block reader which injects read failures (mode 'read-error')
+struct FaultInjectingReader<R> {
+    reader: R,
+    fault: FaultInjection,
+}
+
+impl<R: BlockRead> BlockRead for FaultInjectingReader<R> {
+    fn read_block(&mut self, buffer: &mut [u8]) -> Result<usize, BlockReadError> {
+        if self.fault.triggered() {
+            return Err(BlockReadError::Error(io::Error::from_raw_os_error(
+                nix::errno::Errno::EIO as i32,
+            )));
+        }
+        let bytes = self.reader.read_block(buffer)?;
+        self.fault.account(bytes);
+        Ok(bytes)
+    }
+}
+
 pub struct VirtualTapeHandle {
     drive_name: String,
     path: std::path::PathBuf,
     max_size: usize,
+    fault: Option<FaultInjection>,
     _lock: File,
 }
 
@@ -295,8 +385,13 @@ impl TapeDriver for VirtualTapeHandle {
                 })?;
 
                 let reader = EmulateTapeReader::new(file);
-                let reader = BlockedReader::open(reader)?;
-                Ok(Box::new(reader))
+                match self.fault.clone() {
+                    Some(fault) if fault.mode == VirtualTapeFailMode::ReadError => {
+                        let reader = FaultInjectingReader { reader, fault };
+                        Ok(Box::new(BlockedReader::open(reader)?))
+                    }
+                    _ => Ok(Box::new(BlockedReader::open(reader)?)),
+                }
             }
             None => Err(BlockReadError::Error(proxmox_lang::io_format_err!(
                 "drive is empty (no tape loaded)."
@@ -351,9 +446,13 @@ impl TapeDriver for VirtualTapeHandle {
                 }
 
                 let writer = EmulateTapeWriter::new(file, free_space);
-                let writer = Box::new(BlockedWriter::new(writer));
-
-                Ok(writer)
+                match self.fault.clone() {
+                    Some(fault) if fault.mode != VirtualTapeFailMode::ReadError => {
+                        let writer = FaultInjectingWriter { writer, fault };
+                        Ok(Box::new(BlockedWriter::new(writer)))
+                    }
+                    _ => Ok(Box::new(BlockedWriter::new(writer))),
+                }
             }
             None => proxmox_lang::io_bail!("drive is empty (no tape loaded)."),
         }
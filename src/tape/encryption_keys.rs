@@ -9,6 +9,10 @@
 //! Tape backups store the password protected version on tape, so that
 //! it is possible to restore the key from tape if you know the
 //! password.
+//!
+//! New media sets do not use those keys directly. Instead, each media
+//! set gets a random data key, which is stored wrapped (encrypted) by
+//! the pool key in the media set label.
 
 use std::collections::HashMap;
 
@@ -17,6 +21,9 @@ use serde::{Deserialize, Serialize};
 
 use proxmox_sys::fs::file_read_optional_string;
 
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use proxmox_uuid::Uuid;
+
 use pbs_api_types::Fingerprint;
 use pbs_config::{open_backup_lockfile, replace_backup_config, replace_secret_config};
 use pbs_key_config::KeyConfig;
@@ -179,6 +186,70 @@ pub fn insert_key(key: [u8; 32], key_config: KeyConfig, force: bool) -> Result<(
     Ok(())
 }
 
+const WRAPPED_KEY_IV_LEN: usize = 12;
+const WRAPPED_KEY_TAG_LEN: usize = 16;
+
+/// Generate a random media set data key and wrap it with `master_key`
+///
+/// The key is encrypted using AES-256-GCM, authenticating the media set
+/// `uuid`, so that a wrapped key cannot be used for another media set.
+/// Returns the plain data key and the wrapped key (hex encoded `iv|tag|key`).
+pub fn wrap_media_set_key(master_key: &[u8; 32], uuid: &Uuid) -> Result<([u8; 32], String), Error> {
+    let mut data_key = [0u8; 32];
+    proxmox_sys::linux::fill_with_random_data(&mut data_key)?;
+
+    let mut iv = [0u8; WRAPPED_KEY_IV_LEN];
+    proxmox_sys::linux::fill_with_random_data(&mut iv)?;
+
+    let mut tag = [0u8; WRAPPED_KEY_TAG_LEN];
+    let encrypted = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        master_key,
+        Some(&iv),
+        uuid.as_bytes(),
+        &data_key,
+        &mut tag,
+    )?;
+
+    let mut wrapped = Vec::with_capacity(iv.len() + tag.len() + encrypted.len());
+    wrapped.extend_from_slice(&iv);
+    wrapped.extend_from_slice(&tag);
+    wrapped.extend_from_slice(&encrypted);
+
+    Ok((data_key, hex::encode(wrapped)))
+}
+
+/// Unwrap a media set data key generated by [`wrap_media_set_key`]
+pub fn unwrap_media_set_key(
+    master_key: &[u8; 32],
+    uuid: &Uuid,
+    wrapped_key: &str,
+) -> Result<[u8; 32], Error> {
+    let wrapped = hex::decode(wrapped_key)
+        .map_err(|err| format_err!("unable to decode wrapped media set key - {err}"))?;
+
+    if wrapped.len() != WRAPPED_KEY_IV_LEN + WRAPPED_KEY_TAG_LEN + 32 {
+        bail!("wrapped media set key has wrong length");
+    }
+
+    let (iv, rest) = wrapped.split_at(WRAPPED_KEY_IV_LEN);
+    let (tag, encrypted) = rest.split_at(WRAPPED_KEY_TAG_LEN);
+
+    let data_key = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        master_key,
+        Some(iv),
+        uuid.as_bytes(),
+        encrypted,
+        tag,
+    )
+    .map_err(|_| format_err!("unable to unwrap media set key - wrong encryption key?"))?;
+
+    data_key
+        .try_into()
+        .map_err(|_| format_err!("unwrapped media set key has wrong length"))
+}
+
 // shell completion helper
 /// Complete tape encryption key fingerprints
 pub fn complete_key_fingerprint(_arg: &str, _param: &HashMap<String, String>) -> Vec<String> {
@@ -189,3 +260,28 @@ pub fn complete_key_fingerprint(_arg: &str, _param: &HashMap<String, String>) ->
 
     data.keys().map(|fp| fp.signature()).collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wrap_media_set_key() -> Result<(), Error> {
+        let master_key = [7u8; 32];
+        let uuid = Uuid::generate();
+
+        let (data_key, wrapped) = wrap_media_set_key(&master_key, &uuid)?;
+        assert_ne!(data_key, master_key);
+        assert_eq!(
+            unwrap_media_set_key(&master_key, &uuid, &wrapped)?,
+            data_key
+        );
+
+        // wrong master key
+        assert!(unwrap_media_set_key(&[8u8; 32], &uuid, &wrapped).is_err());
+        // wrong media set
+        assert!(unwrap_media_set_key(&master_key, &Uuid::generate(), &wrapped).is_err());
+
+        Ok(())
+    }
+}
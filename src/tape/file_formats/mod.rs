@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use proxmox_uuid::Uuid;
 
-use pbs_api_types::Fingerprint;
+use pbs_api_types::{Authid, Fingerprint};
 
 mod chunk_archive;
 pub use chunk_archive::*;
@@ -106,6 +106,12 @@ pub struct SnapshotArchiveHeader {
     pub snapshot: String,
     /// Datastore name
     pub store: String,
+    /// Owner of the backup group (not included in archives written by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Authid>,
+    /// Notes of the backup group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_notes: Option<String>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -153,6 +159,11 @@ pub struct MediaSetLabel {
     /// Encryption key finkerprint (if encryped)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encryption_key_fingerprint: Option<Fingerprint>,
+    /// Media set data key, wrapped by the encryption key (hex encoded)
+    ///
+    /// Older media sets use the encryption key directly.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub wrapped_key: Option<String>,
 }
 
 impl MediaSetLabel {
@@ -169,6 +180,7 @@ impl MediaSetLabel {
             seq_nr,
             ctime,
             encryption_key_fingerprint,
+            wrapped_key: None,
         }
     }
 
@@ -31,7 +31,21 @@ pub fn tape_write_snapshot_archive<'a>(
     let store = snapshot_reader.datastore_name().to_string();
     let file_list = snapshot_reader.file_list();
 
-    let archive_header = SnapshotArchiveHeader { snapshot, store };
+    let group = backup_dir
+        .datastore()
+        .backup_group(backup_dir.backup_ns().clone(), backup_dir.group().clone());
+
+    // older snapshots might lack an owner, restore falls back to the restoring user then
+    let owner = group.get_owner().ok();
+    let group_notes = proxmox_sys::fs::file_read_optional_string(group.notes_path())
+        .map_err(|err| proxmox_lang::io_format_err!("unable to read group notes - {err}"))?;
+
+    let archive_header = SnapshotArchiveHeader {
+        snapshot,
+        store,
+        owner,
+        group_notes,
+    };
 
     let header_data = serde_json::to_string_pretty(&archive_header)?
         .as_bytes()
@@ -71,9 +71,11 @@ impl MediaId {
         }
         self.label.pool.to_owned()
     }
-    pub(crate) fn get_encryption_fp(&self) -> Option<(Fingerprint, Uuid)> {
+    pub(crate) fn get_encryption_fp(&self) -> Option<(Fingerprint, Uuid, Option<String>)> {
         let label = self.clone().media_set_label?;
-        label.encryption_key_fingerprint.map(|fp| (fp, label.uuid))
+        label
+            .encryption_key_fingerprint
+            .map(|fp| (fp, label.uuid, label.wrapped_key))
     }
 }
 
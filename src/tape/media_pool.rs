@@ -11,7 +11,9 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Error};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use proxmox_sys::fs::{file_get_json, replace_file, CreateOptions};
 use proxmox_uuid::Uuid;
 
 use pbs_api_types::{
@@ -20,11 +22,23 @@ use pbs_api_types::{
 use pbs_config::BackupLockGuard;
 
 use crate::tape::{
+    drive::generate_wrapped_media_set_key,
     file_formats::{MediaLabel, MediaSetLabel},
     lock_media_pool, lock_media_set, lock_unassigned_media_pool, Inventory, MediaCatalog, MediaId,
     MediaSet,
 };
 
+fn parallel_media_sets_path(state_path: &Path, pool: &str) -> PathBuf {
+    state_path.join(format!("parallel-media-sets-{pool}.json"))
+}
+
+/// Returns the media sets last written by the drives of parallel jobs, see
+/// [MediaPool::with_config_parallel]
+fn load_parallel_media_sets(state_path: &Path, pool: &str) -> Result<Vec<Uuid>, Error> {
+    let data = file_get_json(parallel_media_sets_path(state_path, pool), Some(json!([])))?;
+    Ok(serde_json::from_value(data)?)
+}
+
 /// Media Pool
 pub struct MediaPool {
     name: String,
@@ -106,6 +120,11 @@ impl MediaPool {
         self.force_media_availability = true;
     }
 
+    /// Returns the path of the tape status directory (inventory and catalogs)
+    pub fn state_path(&self) -> &Path {
+        &self.state_path
+    }
+
     /// Returns the the current media set
     pub fn current_media_set(&self) -> &MediaSet {
         &self.current_media_set
@@ -146,6 +165,98 @@ impl MediaPool {
         )
     }
 
+    /// Creates one instance per drive, for jobs writing to several drives in parallel
+    ///
+    /// A media set can only be written sequentially, so every drive gets its own media set. The
+    /// media sets of the drives are remembered per pool (see
+    /// [MediaPool::store_parallel_media_sets]), so that each drive continues its media set in the
+    /// next run, subject to the allocation policy applied by [MediaPool::start_write_session]. The
+    /// first drive falls back to the latest media set of the pool, like a single drive would.
+    pub fn with_config_parallel<P: AsRef<Path>>(
+        state_path: P,
+        config: &MediaPoolConfig,
+        changer_name: Option<String>,
+        count: usize,
+    ) -> Result<Vec<Self>, Error> {
+        let state_path = state_path.as_ref();
+        let _pool_lock = lock_media_pool(state_path, &config.name)?;
+
+        let recorded_sets = load_parallel_media_sets(state_path, &config.name)?;
+
+        let mut pools: Vec<Self> = Vec::with_capacity(count);
+        for index in 0..count {
+            // we already hold the pool lock, and lock the media set below
+            let mut pool = Self::with_config(state_path, config, changer_name.clone(), true)?;
+
+            let set_uuid = match recorded_sets.get(index) {
+                Some(uuid) => Some(uuid.clone()),
+                None if index == 0 => pool
+                    .inventory
+                    .latest_media_set(&config.name)
+                    .filter(|uuid| !recorded_sets.contains(uuid)),
+                None => None,
+            };
+            let media_set = match set_uuid {
+                Some(uuid) if !pools.iter().any(|p| p.current_media_set.uuid() == &uuid) => {
+                    let set = pool.inventory.compute_media_set_members(&uuid)?;
+                    if set.media_list().is_empty() {
+                        MediaSet::new() // never written, or removed from the inventory
+                    } else {
+                        set
+                    }
+                }
+                _ => MediaSet::new(),
+            };
+
+            pool.current_media_set_lock = Some(lock_media_set(state_path, media_set.uuid(), None)?);
+            pool.current_media_set = media_set;
+            pool.no_media_set_locking = false;
+
+            pools.push(pool);
+        }
+
+        Ok(pools)
+    }
+
+    /// Remember the current media sets of instances created by
+    /// [MediaPool::with_config_parallel], in the order of the drives.
+    pub fn store_parallel_media_sets<P: AsRef<Path>>(
+        state_path: P,
+        pools: &[MediaPool],
+    ) -> Result<(), Error> {
+        let state_path = state_path.as_ref();
+        let name = match pools.first() {
+            Some(pool) => &pool.name,
+            None => return Ok(()),
+        };
+        let _pool_lock = lock_media_pool(state_path, name)?;
+
+        let list: Vec<&Uuid> = pools
+            .iter()
+            .map(|pool| pool.current_media_set.uuid())
+            .collect();
+        let raw = serde_json::to_vec_pretty(&list)?;
+
+        let mode = nix::sys::stat::Mode::from_bits_truncate(0o0640);
+        let options = if cfg!(test) {
+            CreateOptions::new().perm(mode)
+        } else {
+            let backup_user = pbs_config::backup_user()?;
+            CreateOptions::new()
+                .perm(mode)
+                .owner(backup_user.uid)
+                .group(backup_user.gid)
+        };
+        replace_file(
+            parallel_media_sets_path(state_path, name),
+            &raw,
+            options,
+            true,
+        )?;
+
+        Ok(())
+    }
+
     /// Returns the pool name
     pub fn name(&self) -> &str {
         &self.name
@@ -319,17 +430,19 @@ impl MediaPool {
         Ok(list)
     }
 
+    /// Returns the time when the media data expires (`i64::MAX` if it never expires)
+    pub fn media_expire_time(&self, media: &BackupMedia) -> i64 {
+        self.inventory
+            .media_expire_time(media.id(), &self.media_set_policy, &self.retention)
+    }
+
     // tests if the media data is considered as expired at specified time
     pub fn media_is_expired(&self, media: &BackupMedia, current_time: i64) -> bool {
         if media.status() != &MediaStatus::Full {
             return false;
         }
 
-        let expire_time =
-            self.inventory
-                .media_expire_time(media.id(), &self.media_set_policy, &self.retention);
-
-        current_time >= expire_time
+        current_time >= self.media_expire_time(media)
     }
 
     // check if a location is considered on site
@@ -372,13 +485,19 @@ impl MediaPool {
 
         let encrypt_fingerprint = self.encrypt_fingerprint();
 
-        let set = MediaSetLabel::with_data(
+        let wrapped_key = match encrypt_fingerprint {
+            Some(ref fingerprint) => self.current_media_set_wrapped_key(fingerprint, seq_nr)?,
+            None => None,
+        };
+
+        let mut set = MediaSetLabel::with_data(
             &pool,
             self.current_media_set.uuid().clone(),
             seq_nr,
             current_time,
             encrypt_fingerprint,
         );
+        set.wrapped_key = wrapped_key;
 
         media_id.media_set_label = Some(set);
 
@@ -393,6 +512,37 @@ impl MediaPool {
         Ok(())
     }
 
+    /// Returns the wrapped data key for the next media of the current media set
+    ///
+    /// The first media of a set gets a newly generated key, all other media
+    /// reuse the key of the set. Media sets started without a wrapped key
+    /// keep using the encryption key directly.
+    ///
+    /// Note: Each media stores its own media set label, so the key of a
+    /// media can always be unwrapped on its own.
+    fn current_media_set_wrapped_key(
+        &self,
+        fingerprint: &Fingerprint,
+        seq_nr: u64,
+    ) -> Result<Option<String>, Error> {
+        if seq_nr > 0 {
+            for media_uuid in self.current_media_set.media_list().iter().flatten() {
+                let label = self
+                    .inventory
+                    .lookup_media(media_uuid)
+                    .and_then(|media_id| media_id.media_set_label.as_ref());
+                if let Some(label) = label {
+                    if label.encryption_key_fingerprint.as_ref() == Some(fingerprint) {
+                        return Ok(label.wrapped_key.clone());
+                    }
+                }
+            }
+        }
+
+        let uuid = self.current_media_set.uuid();
+        Ok(Some(generate_wrapped_media_set_key(fingerprint, uuid)?))
+    }
+
     // Get next unassigned media (media not assigned to any pool)
     pub fn next_unassigned_media(&self, media_list: &[MediaId]) -> Option<MediaId> {
         let mut free_media = Vec::new();
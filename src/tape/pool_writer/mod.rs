@@ -6,16 +6,18 @@ pub use new_chunks_iterator::*;
 
 use std::collections::HashSet;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
 use anyhow::{bail, Error};
 
+use proxmox_section_config::SectionConfigData;
 use proxmox_sys::{task_log, task_warn};
 use proxmox_uuid::Uuid;
 
 use pbs_datastore::{DataStore, SnapshotReader};
+use pbs_key_config::KeyConfig;
 use pbs_tape::{sg_tape::tape_alert_flags_critical, TapeWrite};
 use proxmox_rest_server::WorkerTask;
 
@@ -25,7 +27,7 @@ use crate::tape::{
     file_formats::{
         tape_write_catalog, tape_write_snapshot_archive, ChunkArchiveWriter, MediaSetLabel,
     },
-    MediaCatalog, MediaId, MediaPool, COMMIT_BLOCK_SIZE, MAX_CHUNK_ARCHIVE_SIZE, TAPE_STATUS_DIR,
+    MediaCatalog, MediaId, MediaPool, COMMIT_BLOCK_SIZE, MAX_CHUNK_ARCHIVE_SIZE,
 };
 
 use super::file_formats::{
@@ -73,6 +75,18 @@ impl PoolWriter {
             task_log!(worker, "starting new media set - reason: {}", reason,);
         }
 
+        Self::with_write_session(pool, drive_name, worker, notify_email, ns_magic)
+    }
+
+    /// Creates a writer for a pool, whose write session was already started with
+    /// [MediaPool::start_write_session].
+    pub fn with_write_session(
+        pool: MediaPool,
+        drive_name: &str,
+        worker: &WorkerTask,
+        notify_email: Option<String>,
+        ns_magic: bool,
+    ) -> Result<Self, Error> {
         let media_set_uuid = pool.current_media_set().uuid();
         task_log!(worker, "media set uuid: {}", media_set_uuid);
 
@@ -81,7 +95,8 @@ impl PoolWriter {
         // load all catalogs read-only at start
         for media_uuid in pool.current_media_list()? {
             let media_info = pool.lookup_media(media_uuid).unwrap();
-            let media_catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_info.id(), false, false)?;
+            let media_catalog =
+                MediaCatalog::open(pool.state_path(), media_info.id(), false, false)?;
             catalog_set.append_read_only_catalog(media_catalog)?;
         }
 
@@ -208,6 +223,16 @@ impl PoolWriter {
 
     /// Load a writable media into the drive
     pub fn load_writable_media(&mut self, worker: &WorkerTask) -> Result<Uuid, Error> {
+        let (drive_config, _digest) = pbs_config::drive::config()?;
+        self.load_writable_media_with_config(worker, &drive_config)
+    }
+
+    /// Load a writable media into the drive, using the passed drive configuration
+    pub(crate) fn load_writable_media_with_config(
+        &mut self,
+        worker: &WorkerTask,
+        drive_config: &SectionConfigData,
+    ) -> Result<Uuid, Error> {
         let last_media_uuid = match self.status {
             Some(PoolWriterState { ref media_uuid, .. }) => Some(media_uuid.clone()),
             None => None,
@@ -241,11 +266,9 @@ impl PoolWriter {
             }
         }
 
-        let (drive_config, _digest) = pbs_config::drive::config()?;
-
         let (mut drive, old_media_id) = request_and_load_media(
             worker,
-            &drive_config,
+            drive_config,
             &self.drive_name,
             media.label(),
             &self.notify_email,
@@ -267,6 +290,7 @@ impl PoolWriter {
 
         let (catalog, is_new_media) = update_media_set_label(
             worker,
+            self.pool.state_path(),
             drive.as_mut(),
             old_media_id.media_set_label,
             media.id(),
@@ -302,8 +326,8 @@ impl PoolWriter {
         Ok(media_uuid)
     }
 
-    fn open_catalog_file(uuid: &Uuid) -> Result<File, Error> {
-        let mut path = PathBuf::from(TAPE_STATUS_DIR);
+    fn open_catalog_file(state_path: &Path, uuid: &Uuid) -> Result<File, Error> {
+        let mut path = state_path.to_owned();
         path.push(uuid.to_string());
         path.set_extension("log");
 
@@ -376,7 +400,7 @@ impl PoolWriter {
 
         let mut writer: Box<dyn TapeWrite> = status.drive.write_file()?;
 
-        let mut file = Self::open_catalog_file(uuid)?;
+        let mut file = Self::open_catalog_file(self.pool.state_path(), uuid)?;
 
         let done = tape_write_catalog(
             writer.as_mut(),
@@ -418,7 +442,7 @@ impl PoolWriter {
 
             let mut writer: Box<dyn TapeWrite> = status.drive.write_file()?;
 
-            let mut file = Self::open_catalog_file(uuid)?;
+            let mut file = Self::open_catalog_file(self.pool.state_path(), uuid)?;
 
             task_log!(worker, "write catalog for previous media: {}", uuid);
 
@@ -608,11 +632,36 @@ fn write_chunk_archive<'a>(
     Ok((chunk_list, content_uuid, leom, writer.bytes_written()))
 }
 
+// Write the media set label and a new media catalog. The catalog is
+// created first, so that we do not touch the media if we are unable
+// to write the catalog.
+fn write_media_set_label(
+    state_path: &Path,
+    drive: &mut dyn TapeDriver,
+    media_id: &MediaId,
+    new_set: &MediaSetLabel,
+    key_config: Option<&KeyConfig>,
+) -> Result<MediaCatalog, Error> {
+    let uuid = &media_id.label.uuid;
+
+    let media_catalog = MediaCatalog::create_temporary_database(state_path, media_id, false)?;
+
+    if let Err(err) = drive.write_media_set_label(new_set, key_config) {
+        let _ = MediaCatalog::finish_temporary_database(state_path, uuid, false);
+        return Err(err);
+    }
+
+    MediaCatalog::finish_temporary_database(state_path, uuid, true)?;
+
+    Ok(media_catalog)
+}
+
 // Compare the media set label. If the media is empty, or the existing
 // set label does not match the expected media set, overwrite the
 // media set label.
 fn update_media_set_label(
     worker: &WorkerTask,
+    state_path: &Path,
     drive: &mut dyn TapeDriver,
     old_set: Option<MediaSetLabel>,
     media_id: &MediaId,
@@ -642,8 +691,8 @@ fn update_media_set_label(
     let new_media = match old_set {
         None => {
             task_log!(worker, "writing new media set label");
-            drive.write_media_set_label(new_set, key_config.as_ref())?;
-            media_catalog = MediaCatalog::overwrite(TAPE_STATUS_DIR, media_id, false)?;
+            media_catalog =
+                write_media_set_label(state_path, drive, media_id, new_set, key_config.as_ref())?;
             true
         }
         Some(media_set_label) => {
@@ -659,7 +708,10 @@ fn update_media_set_label(
                 {
                     bail!("detected changed encryption fingerprint - internal error");
                 }
-                media_catalog = MediaCatalog::open(TAPE_STATUS_DIR, media_id, true, false)?;
+                if new_set.wrapped_key != media_set_label.wrapped_key {
+                    bail!("detected changed media set key - internal error");
+                }
+                media_catalog = MediaCatalog::open(state_path, media_id, true, false)?;
 
                 // todo: verify last content/media_catalog somehow?
 
@@ -672,8 +724,13 @@ fn update_media_set_label(
                     media_set_label.seq_nr,
                 );
 
-                drive.write_media_set_label(new_set, key_config.as_ref())?;
-                media_catalog = MediaCatalog::overwrite(TAPE_STATUS_DIR, media_id, false)?;
+                media_catalog = write_media_set_label(
+                    state_path,
+                    drive,
+                    media_id,
+                    new_set,
+                    key_config.as_ref(),
+                )?;
                 true
             }
         }
@@ -3,7 +3,7 @@ use std::sync::{Arc, Mutex};
 
 use anyhow::{format_err, Error};
 
-use pbs_datastore::{DataBlob, DataStore, SnapshotReader};
+use pbs_datastore::{task_io_stats, DataBlob, DataStore, SnapshotReader};
 
 use crate::tape::CatalogSet;
 
@@ -27,7 +27,10 @@ impl NewChunksIterator {
     ) -> Result<(std::thread::JoinHandle<()>, Self), Error> {
         let (tx, rx) = std::sync::mpsc::sync_channel(3);
 
+        let task_io = task_io_stats::current();
+
         let reader_thread = std::thread::spawn(move || {
+            let _task_io = task_io_stats::set_task_io(task_io);
             let snapshot_reader = snapshot_reader.lock().unwrap();
 
             let mut chunk_index: HashSet<[u8; 32]> = HashSet::new();
@@ -2,3 +2,5 @@ mod alloc_writable_media;
 mod compute_media_state;
 mod current_set_usable;
 mod inventory;
+mod snapshot_archive_header;
+mod virtual_tape_faults;
@@ -0,0 +1,137 @@
+// Snapshot archive header tests
+//
+// # cargo test --release tape::test::snapshot_archive_header
+
+use anyhow::Error;
+use std::path::{Path, PathBuf};
+
+use proxmox_io::ReadExt;
+
+use pbs_api_types::{Authid, VirtualTapeDrive};
+use pbs_tape::{MediaContentHeader, PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0};
+
+use crate::tape::{
+    changer::MediaChange,
+    drive::{open_virtual_tape_drive, TapeDriver},
+    file_formats::{SnapshotArchiveHeader, PROXMOX_BACKUP_SNAPSHOT_ARCHIVE_MAGIC_1_2},
+};
+
+fn create_testdir(name: &str) -> Result<PathBuf, Error> {
+    let mut testdir: PathBuf = String::from("./target/testout").into();
+    testdir.push(std::module_path!());
+    testdir.push(name);
+
+    let _ = std::fs::remove_dir_all(&testdir);
+    let _ = std::fs::create_dir_all(&testdir);
+
+    Ok(testdir)
+}
+
+fn write_and_read_header(
+    testdir: &Path,
+    archive_header: &SnapshotArchiveHeader,
+) -> Result<SnapshotArchiveHeader, Error> {
+    let config = VirtualTapeDrive {
+        name: "test-drive".to_string(),
+        path: testdir.to_string_lossy().to_string(),
+        max_size: None,
+        fail_after_bytes: None,
+        fail_mode: None,
+    };
+    let mut drive = open_virtual_tape_drive(&config)?;
+    drive.load_media("tape1")?;
+
+    let header_data = serde_json::to_string_pretty(archive_header)?
+        .as_bytes()
+        .to_vec();
+    let header = MediaContentHeader::new(
+        PROXMOX_BACKUP_SNAPSHOT_ARCHIVE_MAGIC_1_2,
+        header_data.len() as u32,
+    );
+
+    let mut writer = drive.write_file()?;
+    writer.write_header(&header, &header_data)?;
+    writer.finish(false)?;
+    drop(writer);
+
+    drive.move_to_file(0)?;
+    let mut reader = drive.read_next_file()?;
+
+    let header: MediaContentHeader = unsafe { reader.read_le_value()? };
+    assert_eq!(header.magic, PROXMOX_BACKUP_CONTENT_HEADER_MAGIC_1_0);
+    assert_eq!(
+        header.content_magic,
+        PROXMOX_BACKUP_SNAPSHOT_ARCHIVE_MAGIC_1_2
+    );
+
+    let header_data = reader.read_exact_allocated(header.size as usize)?;
+    Ok(serde_json::from_slice(&header_data)?)
+}
+
+#[test]
+fn test_owner_and_notes_round_trip() -> Result<(), Error> {
+    let testdir = create_testdir("test_owner_and_notes_round_trip")?;
+
+    let owner: Authid = "backup@pbs!tape".parse()?;
+    let archive_header = SnapshotArchiveHeader {
+        snapshot: "ns/a/vm/100/2023-01-01T00:00:00Z".to_string(),
+        store: "store1".to_string(),
+        owner: Some(owner.clone()),
+        group_notes: Some("first line\nsecond line".to_string()),
+    };
+
+    let restored = write_and_read_header(&testdir, &archive_header)?;
+
+    assert_eq!(restored.snapshot, archive_header.snapshot);
+    assert_eq!(restored.store, archive_header.store);
+    assert_eq!(restored.owner, Some(owner));
+    assert_eq!(
+        restored.group_notes.as_deref(),
+        Some("first line\nsecond line")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_header_without_group_metadata() -> Result<(), Error> {
+    let testdir = create_testdir("test_header_without_group_metadata")?;
+
+    let archive_header = SnapshotArchiveHeader {
+        snapshot: "vm/100/2023-01-01T00:00:00Z".to_string(),
+        store: "store1".to_string(),
+        owner: None,
+        group_notes: None,
+    };
+
+    let restored = write_and_read_header(&testdir, &archive_header)?;
+
+    assert_eq!(restored.owner, None);
+    assert_eq!(restored.group_notes, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_old_header() -> Result<(), Error> {
+    // header as written by versions without owner and notes support
+    let data = r#"{ "snapshot": "vm/100/2023-01-01T00:00:00Z", "store": "store1" }"#;
+
+    let header: SnapshotArchiveHeader = serde_json::from_str(data)?;
+
+    assert_eq!(header.snapshot, "vm/100/2023-01-01T00:00:00Z");
+    assert_eq!(header.owner, None);
+    assert_eq!(header.group_notes, None);
+
+    // new fields are plain optional properties, ignored by older readers
+    let new_data = serde_json::to_value(SnapshotArchiveHeader {
+        snapshot: header.snapshot,
+        store: header.store,
+        owner: Some("root@pam".parse()?),
+        group_notes: Some("notes".to_string()),
+    })?;
+    assert_eq!(new_data["owner"], "root@pam");
+    assert_eq!(new_data["group_notes"], "notes");
+
+    Ok(())
+}
@@ -0,0 +1,351 @@
+// Virtual tape failure injection tests
+//
+// # cargo test --release tape::test::virtual_tape_faults
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Once};
+
+use anyhow::{bail, Error};
+
+use proxmox_rest_server::WorkerTask;
+use proxmox_section_config::SectionConfigData;
+use proxmox_sys::fs::CreateOptions;
+use proxmox_sys::WorkerTaskContext;
+use proxmox_uuid::Uuid;
+
+use pbs_api_types::{MediaSetPolicy, RetentionPolicy, VirtualTapeDrive, VirtualTapeFailMode};
+use pbs_tape::BlockReadError;
+
+use crate::tape::{
+    changer::MediaChange,
+    drive::{open_virtual_tape_drive, TapeDriver},
+    Inventory, MediaCatalog, MediaPool, PoolWriter,
+};
+
+const DATA_SIZE: usize = 64 * 1024;
+
+fn create_testdir(name: &str) -> Result<PathBuf, Error> {
+    let mut testdir: PathBuf = String::from("./target/testout").into();
+    testdir.push(std::module_path!());
+    testdir.push(name);
+
+    let _ = std::fs::remove_dir_all(&testdir);
+    let _ = std::fs::create_dir_all(&testdir);
+
+    Ok(testdir)
+}
+
+fn drive_config(
+    testdir: &Path,
+    fail_after_bytes: Option<u64>,
+    fail_mode: Option<VirtualTapeFailMode>,
+) -> VirtualTapeDrive {
+    VirtualTapeDrive {
+        name: "test-drive".to_string(),
+        path: testdir.to_string_lossy().to_string(),
+        max_size: None,
+        fail_after_bytes,
+        fail_mode,
+    }
+}
+
+fn test_worker(name: &str) -> Result<Arc<WorkerTask>, Error> {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let uid = nix::unistd::Uid::current();
+        let gid = nix::unistd::Gid::current();
+        let file_opts = CreateOptions::new().owner(uid).group(gid);
+        proxmox_rest_server::init_worker_tasks("./target/testout/tasks".into(), file_opts).unwrap();
+    });
+    WorkerTask::new(
+        "tape-test",
+        Some(name.to_string()),
+        "root@pam".to_string(),
+        false,
+    )
+}
+
+/// Create pool 'p1' with a single labeled virtual tape 'tape1'
+///
+/// The inventory and catalogs are stored in `testdir`, the virtual tapes
+/// in the 'drive' subdirectory. Returns the drive configuration (with
+/// the passed failure injection) and the tape uuid.
+fn setup_pool(
+    testdir: &Path,
+    fail_after_bytes: Option<u64>,
+    fail_mode: Option<VirtualTapeFailMode>,
+) -> Result<(SectionConfigData, Uuid), Error> {
+    let drive_dir = testdir.join("drive");
+    std::fs::create_dir_all(&drive_dir)?;
+
+    let mut inventory = Inventory::load(testdir)?;
+    let uuid = inventory.generate_assigned_tape("tape1", "p1", 0);
+    let label = inventory.lookup_media(&uuid).unwrap().label.clone();
+
+    {
+        let mut drive = open_virtual_tape_drive(&drive_config(&drive_dir, None, None))?;
+        drive.load_media("tape1")?;
+        drive.label_tape(&label)?;
+    }
+
+    let mut config = SectionConfigData::new();
+    config.set_data(
+        "test-drive",
+        "virtual",
+        drive_config(&drive_dir, fail_after_bytes, fail_mode),
+    )?;
+
+    Ok((config, uuid))
+}
+
+fn open_pool(testdir: &Path) -> Result<MediaPool, Error> {
+    MediaPool::new(
+        "p1",
+        testdir,
+        MediaSetPolicy::ContinueCurrent,
+        RetentionPolicy::KeepForever,
+        None,
+        None,
+        false,
+    )
+}
+
+/// Read the label of 'tape1', bypassing any failure injection
+fn read_tape_label(testdir: &Path) -> Result<crate::tape::MediaId, Error> {
+    let mut drive = open_virtual_tape_drive(&drive_config(&testdir.join("drive"), None, None))?;
+    drive.load_media("tape1")?;
+    match drive.read_label()? {
+        (Some(media_id), _) => Ok(media_id),
+        (None, _) => bail!("tape1 is empty"),
+    }
+}
+
+/// Write a file with `count` blocks of test data, returns if LEOM was reached
+fn write_test_file(drive: &mut dyn TapeDriver, count: usize) -> Result<bool, Error> {
+    let data = vec![0x55u8; DATA_SIZE];
+    let mut writer = drive.write_file()?;
+    let mut leom = false;
+    for _ in 0..count {
+        if writer.write_all(&data)? {
+            leom = true;
+            break;
+        }
+    }
+    leom |= writer.finish(false)?;
+    Ok(leom)
+}
+
+fn read_test_file(drive: &mut dyn TapeDriver) -> Result<Vec<u8>, Error> {
+    let mut reader = match drive.read_next_file() {
+        Ok(reader) => reader,
+        Err(BlockReadError::Error(err)) => return Err(err.into()),
+        Err(err) => bail!("unexpected read result - {err}"),
+    };
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[test]
+fn test_eot_continues_on_next_media() -> Result<(), Error> {
+    let testdir = create_testdir("test_eot_continues_on_next_media")?;
+
+    let config = drive_config(&testdir, Some(1024 * 1024), None);
+
+    {
+        let mut drive = open_virtual_tape_drive(&config)?;
+        drive.load_media("tape1")?;
+        assert!(
+            write_test_file(&mut drive, 64)?,
+            "expected early EOT on tape1"
+        );
+        assert_eq!(drive.current_file_number()?, 1);
+    }
+
+    // counting restarts with the next drive handle, i.e. on the next media
+    let mut drive = open_virtual_tape_drive(&config)?;
+    drive.load_media("tape2")?;
+    assert!(!write_test_file(&mut drive, 4)?);
+
+    drive.move_to_file(0)?;
+    assert_eq!(read_test_file(&mut drive)?.len(), 4 * DATA_SIZE);
+
+    Ok(())
+}
+
+#[test]
+fn test_io_error_keeps_written_files() -> Result<(), Error> {
+    let testdir = create_testdir("test_io_error_keeps_written_files")?;
+
+    let config = drive_config(
+        &testdir,
+        Some(1024 * 1024),
+        Some(VirtualTapeFailMode::IoError),
+    );
+
+    {
+        let mut drive = open_virtual_tape_drive(&config)?;
+        drive.load_media("tape1")?;
+        assert!(!write_test_file(&mut drive, 4)?);
+
+        let err = write_test_file(&mut drive, 64).unwrap_err();
+        let err = err.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(err.raw_os_error(), Some(nix::errno::Errno::EIO as i32));
+    }
+
+    // the file written before the failure is still intact
+    let mut drive = open_virtual_tape_drive(&drive_config(&testdir, None, None))?;
+    drive.load_media("tape1")?;
+    drive.move_to_file(0)?;
+    let data = read_test_file(&mut drive)?;
+    assert_eq!(data, vec![0x55u8; 4 * DATA_SIZE]);
+
+    Ok(())
+}
+
+#[test]
+fn test_read_error() -> Result<(), Error> {
+    let testdir = create_testdir("test_read_error")?;
+
+    {
+        let mut drive = open_virtual_tape_drive(&drive_config(&testdir, None, None))?;
+        drive.load_media("tape1")?;
+        write_test_file(&mut drive, 4)?;
+        write_test_file(&mut drive, 16)?;
+    }
+
+    let config = drive_config(
+        &testdir,
+        Some(1024 * 1024),
+        Some(VirtualTapeFailMode::ReadError),
+    );
+    let mut drive = open_virtual_tape_drive(&config)?;
+    drive.load_media("tape1")?;
+
+    // writes are not affected
+    drive.move_to_eom(false)?;
+    write_test_file(&mut drive, 32)?;
+
+    drive.move_to_file(0)?;
+    assert_eq!(read_test_file(&mut drive)?.len(), 4 * DATA_SIZE);
+    assert!(read_test_file(&mut drive).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_pool_writer_abort_keeps_inventory() -> Result<(), Error> {
+    let testdir = create_testdir("test_pool_writer_abort_keeps_inventory")?;
+    let (config, uuid) = setup_pool(&testdir, None, None)?;
+    let worker = test_worker("test_pool_writer_abort_keeps_inventory")?;
+
+    {
+        let pool = open_pool(&testdir)?;
+        let mut pool_writer = PoolWriter::new(pool, "test-drive", &worker, None, false, true)?;
+        assert_eq!(
+            pool_writer.load_writable_media_with_config(&worker, &config)?,
+            uuid
+        );
+
+        worker.request_abort();
+        assert!(worker.check_abort().is_err());
+        // the backup task bails out and drops the writer without commit
+    }
+
+    let inventory = Inventory::load(&testdir)?;
+    let media_id = inventory.lookup_media(&uuid).unwrap().clone();
+    let media_set = media_id.media_set_label.clone().unwrap();
+    assert_eq!(media_set.seq_nr, 0);
+
+    // media and catalog agree with the inventory
+    let tape_set = read_tape_label(&testdir)?.media_set_label.unwrap();
+    assert_eq!(tape_set.uuid, media_set.uuid);
+    MediaCatalog::open(&testdir, &media_id, false, false)?;
+
+    // the next session continues the media set on the same media
+    let mut pool = open_pool(&testdir)?;
+    pool.start_write_session(proxmox_time::epoch_i64(), false)?;
+    let mut pool_writer = PoolWriter::with_write_session(pool, "test-drive", &worker, None, true)?;
+    assert_eq!(
+        pool_writer.load_writable_media_with_config(&worker, &config)?,
+        uuid
+    );
+    pool_writer.commit()?;
+
+    let inventory = Inventory::load(&testdir)?;
+    let media_id = inventory.lookup_media(&uuid).unwrap();
+    assert_eq!(
+        media_id.media_set_label.as_ref().unwrap().uuid,
+        media_set.uuid
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_pool_writer_label_read_error() -> Result<(), Error> {
+    let testdir = create_testdir("test_pool_writer_label_read_error")?;
+    let (config, uuid) = setup_pool(&testdir, Some(0), Some(VirtualTapeFailMode::ReadError))?;
+    let worker = test_worker("test_pool_writer_label_read_error")?;
+
+    {
+        let pool = open_pool(&testdir)?;
+        let mut pool_writer = PoolWriter::new(pool, "test-drive", &worker, None, false, true)?;
+        let err = pool_writer
+            .load_writable_media_with_config(&worker, &config)
+            .unwrap_err();
+        assert!(err.to_string().contains("read label failed"), "{err}");
+    }
+
+    // nothing was written, neither to the media nor to the catalog
+    let media_id = read_tape_label(&testdir)?;
+    assert_eq!(media_id.label.uuid, uuid);
+    assert!(media_id.media_set_label.is_none());
+    assert!(!MediaCatalog::exists(&testdir, &uuid));
+
+    let inventory = Inventory::load(&testdir)?;
+    assert_eq!(
+        inventory.lookup_media(&uuid).unwrap().label.label_text,
+        "tape1"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_pool_writer_catalog_write_error() -> Result<(), Error> {
+    let testdir = create_testdir("test_pool_writer_catalog_write_error")?;
+    let (config, uuid) = setup_pool(&testdir, None, None)?;
+    let worker = test_worker("test_pool_writer_catalog_write_error")?;
+
+    // block the temporary catalog file, so that creating the catalog fails
+    std::fs::create_dir(testdir.join(format!("{uuid}.tmp")))?;
+
+    {
+        let pool = open_pool(&testdir)?;
+        let mut pool_writer = PoolWriter::new(pool, "test-drive", &worker, None, false, true)?;
+        let err = pool_writer
+            .load_writable_media_with_config(&worker, &config)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("unable to create temporary media catalog"),
+            "{err}"
+        );
+    }
+
+    // the media set label is not written without a catalog
+    let media_id = read_tape_label(&testdir)?;
+    assert_eq!(media_id.label.uuid, uuid);
+    assert!(media_id.media_set_label.is_none());
+    assert!(!MediaCatalog::exists(&testdir, &uuid));
+
+    let inventory = Inventory::load(&testdir)?;
+    assert_eq!(
+        inventory.lookup_media(&uuid).unwrap().label.label_text,
+        "tape1"
+    );
+
+    Ok(())
+}
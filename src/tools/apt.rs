@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
 
 use anyhow::{bail, format_err, Error};
 use apt_pkg_native::Cache;
@@ -7,10 +9,21 @@ use apt_pkg_native::Cache;
 use proxmox_schema::const_regex;
 use proxmox_sys::fs::{file_read_optional_string, replace_file, CreateOptions};
 
-use pbs_api_types::APTUpdateInfo;
+use pbs_api_types::{APTUpdateInfo, ServerPackageUpdate, ServerUpdateStatus};
 use pbs_buildcfg::PROXMOX_BACKUP_STATE_DIR_M;
 
 const APT_PKG_STATE_FN: &str = concat!(PROXMOX_BACKUP_STATE_DIR_M!(), "/pkg-state.json");
+const APT_PKGCACHE_FN: &str = "/var/cache/apt/pkgcache.bin";
+const APT_LISTS_DIR: &str = "/var/lib/apt/lists";
+const DPKG_STATUS_FN: &str = "/var/lib/dpkg/status";
+
+/// Packages included in the [`server_update_status`].
+pub const SERVER_UPDATE_PACKAGES: &[&str] = &["proxmox-backup-server", "proxmox-backup-client"];
+
+/// Upper bound for fetching a changelog from the repository, in seconds.
+const CHANGELOG_TIMEOUT: u64 = 30;
+
+static SERVER_UPDATE_STATUS: Mutex<Option<ServerUpdateStatus>> = Mutex::new(None);
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 /// Some information we cache about the package (update) state, like what pending update version
@@ -49,8 +62,8 @@ pub fn read_pkg_state() -> Result<Option<PkgState>, Error> {
 
 pub fn pkg_cache_expired() -> Result<bool, Error> {
     if let Ok(pbs_cache) = std::fs::metadata(APT_PKG_STATE_FN) {
-        let apt_pkgcache = std::fs::metadata("/var/cache/apt/pkgcache.bin")?;
-        let dpkg_status = std::fs::metadata("/var/lib/dpkg/status")?;
+        let apt_pkgcache = std::fs::metadata(APT_PKGCACHE_FN)?;
+        let dpkg_status = std::fs::metadata(DPKG_STATUS_FN)?;
 
         let mtime = pbs_cache.modified()?;
 
@@ -85,9 +98,131 @@ pub fn update_cache() -> Result<PkgState, Error> {
     Ok(cache)
 }
 
+fn modification_time(path: &str) -> Option<i64> {
+    let modified = std::fs::metadata(Path::new(path)).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs() as i64)
+}
+
+fn server_package_update(package: &str) -> ServerPackageUpdate {
+    let info = list_installed_apt_packages(
+        |data| data.active_version == data.candidate_version,
+        Some(package),
+    )
+    .pop();
+
+    let (installed_version, available_version) = match info {
+        Some(info) if info.old_version.is_empty() => (None, Some(info.version)),
+        Some(info) => (Some(info.old_version), Some(info.version)),
+        None => (None, None),
+    };
+
+    let update_available = match (&installed_version, &available_version) {
+        (Some(installed), Some(available)) => {
+            let cache = Cache::get_singleton();
+            cache.compare_versions(available, installed) == std::cmp::Ordering::Greater
+        }
+        _ => false,
+    };
+
+    ServerPackageUpdate {
+        package: package.to_string(),
+        installed_version,
+        available_version,
+        update_available,
+        changelog: None,
+    }
+}
+
+/// Get the update state of the [`SERVER_UPDATE_PACKAGES`].
+///
+/// This only uses the local package index, which is refreshed by the daily update task, so it
+/// never waits for the network. The result is cached until the package index or the installed
+/// packages change.
+pub fn server_update_status() -> ServerUpdateStatus {
+    let changed = [APT_PKGCACHE_FN, DPKG_STATUS_FN]
+        .into_iter()
+        .filter_map(modification_time)
+        .max()
+        .unwrap_or(0);
+
+    let mut cached = SERVER_UPDATE_STATUS.lock().unwrap();
+    if let Some(status) = cached.as_ref() {
+        if status.checked > changed {
+            return status.clone();
+        }
+    }
+
+    let packages: Vec<ServerPackageUpdate> = SERVER_UPDATE_PACKAGES
+        .iter()
+        .map(|package| server_package_update(package))
+        .collect();
+
+    let status = ServerUpdateStatus {
+        updates_available: packages.iter().any(|package| package.update_available),
+        packages,
+        index_updated: modification_time(APT_LISTS_DIR),
+        checked: proxmox_time::epoch_i64(),
+    };
+    *cached = Some(status.clone());
+
+    status
+}
+
+/// Fetch the changelog of `package` from the repository, for `version` or the candidate version.
+///
+/// The download is aborted after a timeout, so that an unreachable repository results in an
+/// error instead of a hanging request.
+pub fn fetch_changelog(package: &str, version: Option<&str>) -> Result<String, Error> {
+    let mut command = std::process::Command::new("timeout");
+    command.arg(CHANGELOG_TIMEOUT.to_string());
+    command.args(["apt-get", "changelog", "-qq"]); // don't display download progress
+    for method in ["http", "https"] {
+        command.arg("-o");
+        command.arg(format!("Acquire::{method}::Timeout={CHANGELOG_TIMEOUT}"));
+    }
+    match version {
+        Some(version) => command.arg(format!("{package}={version}")),
+        None => command.arg(package),
+    };
+
+    let output = command
+        .output()
+        .map_err(|err| format_err!("failed to execute apt-get - {err}"))?;
+
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        // exit code of `timeout` if the command timed out
+        Some(124) => {
+            bail!("fetching the changelog of {package} timed out after {CHANGELOG_TIMEOUT} seconds")
+        }
+        _ => bail!(
+            "fetching the changelog of {package} failed - {}",
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ),
+    }
+}
+
+/// Strip the entries of `installed_version` and older versions from a debian `changelog`.
+///
+/// The full changelog is returned if no entry of the installed version is found.
+pub fn changelog_since(changelog: &str, installed_version: &str) -> String {
+    let mut offset = 0;
+    for line in changelog.split_inclusive('\n') {
+        if let Some(captures) = CHANGELOG_ENTRY_REGEX.captures(line) {
+            if &captures[1] == installed_version {
+                return changelog[..offset].trim_end().to_string();
+            }
+        }
+        offset += line.len();
+    }
+    changelog.trim_end().to_string()
+}
+
 const_regex! {
     VERSION_EPOCH_REGEX = r"^\d+:";
     FILENAME_EXTRACT_REGEX = r"^.*/.*?_(.*)_Packages$";
+    CHANGELOG_ENTRY_REGEX = r"^\S+ \(([^)\s]+)\)";
 }
 
 pub struct FilterData<'a> {
@@ -291,3 +426,43 @@ where
 
     None
 }
+
+#[cfg(test)]
+mod test {
+    use super::changelog_since;
+
+    const CHANGELOG: &str = "\
+proxmox-backup (3.2.8-1) bookworm; urgency=medium
+
+  * fix sync of namespaces
+
+ -- Proxmox Support Team <support@proxmox.com>  Thu, 01 Aug 2024 10:00:00 +0200
+
+proxmox-backup (3.2.7-1) bookworm; urgency=medium
+
+  * add tuning option (3.2.7-1) for the chunk order
+
+ -- Proxmox Support Team <support@proxmox.com>  Mon, 01 Jul 2024 10:00:00 +0200
+
+proxmox-backup (3.2.6-1) bookworm; urgency=medium
+
+  * initial entry
+
+ -- Proxmox Support Team <support@proxmox.com>  Mon, 03 Jun 2024 10:00:00 +0200
+";
+
+    #[test]
+    fn test_changelog_since() {
+        let newer = changelog_since(CHANGELOG, "3.2.7-1");
+        assert!(newer.starts_with("proxmox-backup (3.2.8-1)"));
+        assert!(newer.ends_with("+0200"));
+        assert!(!newer.contains("3.2.7-1"));
+
+        let newer = changelog_since(CHANGELOG, "3.2.6-1");
+        assert!(newer.contains("(3.2.7-1) for the chunk order"));
+        assert!(!newer.contains("initial entry"));
+
+        assert_eq!(changelog_since(CHANGELOG, "3.2.8-1"), "");
+        assert_eq!(changelog_since(CHANGELOG, "1.0.0-1"), CHANGELOG.trim_end());
+    }
+}
@@ -0,0 +1,313 @@
+//! Restrict disk monitoring to a subset of the block devices of the node.
+
+use std::collections::HashSet;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{format_err, Error};
+
+use super::{zpool_list, DiskManage};
+
+/// Selects the disks which are listed and queried for SMART data.
+///
+/// Disks matching one of the `include` patterns or backing a datastore are selected, or all
+/// disks if neither is configured. Disks matching one of the `exclude` patterns are never
+/// selected.
+#[derive(Clone, Debug, Default)]
+pub struct DiskFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    datastore_disks: Option<HashSet<String>>,
+}
+
+impl DiskFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self {
+            include,
+            exclude,
+            datastore_disks: None,
+        }
+    }
+
+    /// Also select the disks backing the given datastore paths.
+    pub fn datastore_disks(mut self, disks: HashSet<String>) -> Self {
+        self.datastore_disks = Some(disks);
+        self
+    }
+
+    /// Check if the disk with the kernel name `name` (e.g. `sda`) is selected.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.exclude.iter().any(|pattern| glob_match(pattern, name)) {
+            return false;
+        }
+
+        if self.include.is_empty() && self.datastore_disks.is_none() {
+            return true;
+        }
+
+        self.include.iter().any(|pattern| glob_match(pattern, name))
+            || matches!(&self.datastore_disks, Some(disks) if disks.contains(name))
+    }
+}
+
+/// Match `name` against a shell style pattern, supporting `*` and `?`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` in the pattern and the name position it matched up to
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(b'?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Resolve the disks backing the block device `name`.
+///
+/// Device mapper devices (LVM, LUKS) and software RAIDs are followed through their `slaves`,
+/// partitions are resolved to their disk.
+pub(crate) fn resolve_backing_disks<S, P>(name: &str, slaves: &S, parent: &P) -> HashSet<String>
+where
+    S: Fn(&str) -> Vec<String>,
+    P: Fn(&str) -> Option<String>,
+{
+    fn resolve<S, P>(name: &str, slaves: &S, parent: &P, depth: usize, disks: &mut HashSet<String>)
+    where
+        S: Fn(&str) -> Vec<String>,
+        P: Fn(&str) -> Option<String>,
+    {
+        if depth > 16 {
+            return; // loops cannot happen in sysfs, but better be safe
+        }
+
+        let lower = slaves(name);
+        if !lower.is_empty() {
+            for slave in lower {
+                resolve(&slave, slaves, parent, depth + 1, disks);
+            }
+            return;
+        }
+
+        match parent(name) {
+            Some(disk) => resolve(&disk, slaves, parent, depth + 1, disks),
+            None => {
+                disks.insert(name.to_string());
+            }
+        }
+    }
+
+    let mut disks = HashSet::new();
+    resolve(name, slaves, parent, 0, &mut disks);
+    disks
+}
+
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+
+fn sysfs_slaves(name: &str) -> Vec<String> {
+    let path = Path::new(SYS_CLASS_BLOCK).join(name).join("slaves");
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn sysfs_parent(name: &str) -> Option<String> {
+    let path = Path::new(SYS_CLASS_BLOCK).join(name);
+    if !path.join("partition").exists() {
+        return None;
+    }
+    let path = std::fs::canonicalize(path).ok()?;
+    path.parent()?.file_name()?.to_str().map(String::from)
+}
+
+fn sysfs_name_by_dev_num(devnum: u64) -> Option<String> {
+    let path = PathBuf::from(format!(
+        "/sys/dev/block/{}:{}",
+        nix::sys::stat::major(devnum),
+        nix::sys::stat::minor(devnum),
+    ));
+    let path = std::fs::canonicalize(path).ok()?;
+    path.file_name()?.to_str().map(String::from)
+}
+
+/// Get the kernel names of the disks backing the file systems mounted at `paths`.
+///
+/// Paths which cannot be resolved, e.g. on network storage, are skipped.
+pub fn datastore_backing_disks(
+    disk_manager: Arc<DiskManage>,
+    paths: &[PathBuf],
+) -> Result<HashSet<String>, Error> {
+    let mut disks = HashSet::new();
+
+    for path in paths {
+        let (fs_type, device, source) = match disk_manager.find_mounted_device(path)? {
+            Some(mounted) => mounted,
+            None => continue,
+        };
+
+        let devices = if fs_type == "zfs" {
+            let pool = source
+                .as_ref()
+                .and_then(|source| source.to_str())
+                .and_then(|dataset| dataset.split('/').next())
+                .ok_or_else(|| format_err!("unable to get zfs pool of {path:?}"))?;
+
+            let pool_list = match zpool_list(Some(pool.to_string()), true) {
+                Ok(list) => list,
+                Err(err) => {
+                    log::warn!("unable to list devices of zpool {pool} - {err}");
+                    continue;
+                }
+            };
+
+            let mut devices = Vec::new();
+            for entry in pool_list {
+                for device in entry.devices {
+                    match std::fs::metadata(&device) {
+                        Ok(meta) => devices.push(meta.rdev()),
+                        Err(err) => log::warn!("unable to stat zpool device {device} - {err}"),
+                    }
+                }
+            }
+            devices
+        } else {
+            vec![device.into_dev_t()]
+        };
+
+        for devnum in devices {
+            if let Some(name) = sysfs_name_by_dev_num(devnum) {
+                disks.extend(resolve_backing_disks(&name, &sysfs_slaves, &sysfs_parent));
+            }
+        }
+    }
+
+    Ok(disks)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("sd*", "sda"));
+        assert!(glob_match("sd*", "sd"));
+        assert!(glob_match("nvme?n1", "nvme0n1"));
+        assert!(glob_match("*n1", "nvme12n1"));
+        assert!(glob_match("s*d*z", "sdbdz"));
+        assert!(glob_match("sda", "sda"));
+        assert!(!glob_match("sda", "sdaa"));
+        assert!(!glob_match("sd?", "sdaa"));
+        assert!(!glob_match("nvme*", "sda"));
+        assert!(!glob_match("", "sda"));
+    }
+
+    #[test]
+    fn test_disk_filter() {
+        let all = DiskFilter::default();
+        assert!(all.matches("sda"));
+
+        let filter = DiskFilter::new(vec![], vec!["loop*".into(), "zd*".into()]);
+        assert!(filter.matches("sda"));
+        assert!(!filter.matches("zd0"));
+        assert!(!filter.matches("loop3"));
+
+        let filter = DiskFilter::new(vec!["nvme*".into()], vec!["nvme1n1".into()])
+            .datastore_disks(["sdb".to_string()].into());
+        assert!(filter.matches("nvme0n1"));
+        assert!(!filter.matches("nvme1n1"));
+        assert!(filter.matches("sdb"));
+        assert!(!filter.matches("sdc"));
+
+        // datastores on unresolvable storage select no disks at all
+        let filter = DiskFilter::default().datastore_disks(HashSet::new());
+        assert!(!filter.matches("sda"));
+    }
+
+    struct Topology {
+        slaves: HashMap<&'static str, Vec<&'static str>>,
+        parents: HashMap<&'static str, &'static str>,
+    }
+
+    impl Topology {
+        fn resolve(&self, name: &str) -> Vec<String> {
+            let slaves = |name: &str| {
+                self.slaves
+                    .get(name)
+                    .map(|list| list.iter().map(|s| s.to_string()).collect())
+                    .unwrap_or_default()
+            };
+            let parent = |name: &str| self.parents.get(name).map(|s| s.to_string());
+
+            let mut disks: Vec<String> = resolve_backing_disks(name, &slaves, &parent)
+                .into_iter()
+                .collect();
+            disks.sort();
+            disks
+        }
+    }
+
+    #[test]
+    fn test_resolve_backing_disks() {
+        let topology = Topology {
+            slaves: HashMap::from([
+                // LVM thick volume on a partition
+                ("dm-0", vec!["sda3"]),
+                // LVM volume spanning two partitions
+                ("dm-1", vec!["sdb1", "sdc1"]),
+                // LVM on LUKS on a software RAID of two disks
+                ("dm-2", vec!["dm-3"]),
+                ("dm-3", vec!["md0"]),
+                ("md0", vec!["sdd", "sde"]),
+            ]),
+            parents: HashMap::from([
+                ("sda1", "sda"),
+                ("sda3", "sda"),
+                ("sdb1", "sdb"),
+                ("sdc1", "sdc"),
+                ("nvme0n1p2", "nvme0n1"),
+            ]),
+        };
+
+        // plain partitions and whole disks
+        assert_eq!(topology.resolve("sda1"), ["sda"]);
+        assert_eq!(topology.resolve("nvme0n1p2"), ["nvme0n1"]);
+        assert_eq!(topology.resolve("sdf"), ["sdf"]);
+
+        // LVM
+        assert_eq!(topology.resolve("dm-0"), ["sda"]);
+        assert_eq!(topology.resolve("dm-1"), ["sdb", "sdc"]);
+        assert_eq!(topology.resolve("dm-2"), ["sdd", "sde"]);
+    }
+}
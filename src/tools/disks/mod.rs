@@ -33,6 +33,8 @@ mod lvm;
 pub use lvm::*;
 mod smart;
 pub use smart::*;
+mod filter;
+pub use filter::*;
 
 lazy_static::lazy_static! {
     static ref ISCSI_PATH_REGEX: regex::Regex =
@@ -824,6 +826,7 @@ fn scan_partitions(
 pub struct DiskUsageQuery {
     smart: bool,
     partitions: bool,
+    filter: Option<DiskFilter>,
 }
 
 impl DiskUsageQuery {
@@ -831,9 +834,16 @@ impl DiskUsageQuery {
         Self {
             smart: true,
             partitions: false,
+            filter: None,
         }
     }
 
+    /// Only query the disks selected by `filter`.
+    pub fn filter(&mut self, filter: Option<DiskFilter>) -> &mut Self {
+        self.filter = filter;
+        self
+    }
+
     pub fn smart(&mut self, smart: bool) -> &mut Self {
         self.smart = smart;
         self
@@ -845,11 +855,16 @@ impl DiskUsageQuery {
     }
 
     pub fn query(&self) -> Result<HashMap<String, DiskUsageInfo>, Error> {
-        get_disks(None, !self.smart, self.partitions)
+        get_disks(None, self.filter.as_ref(), !self.smart, self.partitions)
     }
 
     pub fn find(&self, disk: &str) -> Result<DiskUsageInfo, Error> {
-        let mut map = get_disks(Some(vec![disk.to_string()]), !self.smart, self.partitions)?;
+        let mut map = get_disks(
+            Some(vec![disk.to_string()]),
+            self.filter.as_ref(),
+            !self.smart,
+            self.partitions,
+        )?;
         if let Some(info) = map.remove(disk) {
             Ok(info)
         } else {
@@ -858,7 +873,12 @@ impl DiskUsageQuery {
     }
 
     pub fn find_all(&self, disks: Vec<String>) -> Result<HashMap<String, DiskUsageInfo>, Error> {
-        get_disks(Some(disks), !self.smart, self.partitions)
+        get_disks(
+            Some(disks),
+            self.filter.as_ref(),
+            !self.smart,
+            self.partitions,
+        )
     }
 }
 
@@ -924,6 +944,8 @@ fn get_partitions_info(
 fn get_disks(
     // filter - list of device names (without leading /dev)
     disks: Option<Vec<String>>,
+    // filter - disk monitoring scope
+    filter: Option<&DiskFilter>,
     // do no include data from smartctl
     no_smart: bool,
     // include partitions
@@ -959,6 +981,10 @@ fn get_disks(
             }
         }
 
+        if matches!(filter, Some(filter) if !filter.matches(&name)) {
+            continue;
+        }
+
         let sys_path = format!("/sys/block/{}", name);
 
         if let Ok(target) = std::fs::read_link(&sys_path) {
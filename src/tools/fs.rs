@@ -1,5 +1,5 @@
 use std::ffi::CStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{format_err, Error};
 use tokio::task::spawn_blocking;
@@ -22,3 +22,46 @@ pub async fn fs_info_static(
         .await
         .map_err(|err| format_err!("error waiting for fs_info call: {err}"))??)
 }
+
+/// Add the usage of the file systems holding the additional chunk directories of a datastore to
+/// the `usage` of its base path.
+///
+/// File systems are only counted once, even if they hold several of the directories.
+pub fn add_extra_chunk_dir_usage(
+    usage: &mut proxmox_sys::fs::FileSystemInformation,
+    base: &Path,
+    extra_chunk_dirs: &[PathBuf],
+) -> Result<(), Error> {
+    let mut devices = vec![nix::sys::stat::stat(base)?.st_dev];
+
+    for chunk_dir in extra_chunk_dirs {
+        let device = nix::sys::stat::stat(chunk_dir.as_path())?.st_dev;
+        if devices.contains(&device) {
+            continue;
+        }
+        devices.push(device);
+
+        let info = proxmox_sys::fs::fs_info(chunk_dir.as_path())?;
+        usage.total += info.total;
+        usage.used += info.used;
+        usage.available += info.available;
+    }
+
+    Ok(())
+}
+
+/// File system usage of a datastore, including its additional chunk directories.
+pub async fn datastore_fs_info(
+    datastore: &pbs_datastore::DataStore,
+) -> Result<proxmox_sys::fs::FileSystemInformation, Error> {
+    let base = datastore.base_path();
+    let extra_chunk_dirs = datastore.extra_chunk_dirs();
+
+    spawn_blocking(move || -> Result<_, Error> {
+        let mut usage = proxmox_sys::fs::fs_info(&base)?;
+        add_extra_chunk_dir_usage(&mut usage, &base, &extra_chunk_dirs)?;
+        Ok(usage)
+    })
+    .await
+    .map_err(|err| format_err!("error waiting for fs_info call: {err}"))?
+}
@@ -2,10 +2,12 @@
 //!
 //! This is a collection of small and useful tools.
 
-use anyhow::{bail, Error};
+use anyhow::Error;
 
 use proxmox_http::{client::Client, HttpOptions, ProxyConfig};
 
+use crate::api2::helpers::api_bail;
+
 pub mod apt;
 pub mod config;
 pub mod disks;
@@ -17,12 +19,16 @@ pub use shared_rate_limiter::SharedRateLimiter;
 pub mod statistics;
 pub mod systemd;
 pub mod ticket;
+pub mod usage_estimation;
 
 pub mod parallel_handler;
 
 pub fn assert_if_modified(digest1: &str, digest2: &str) -> Result<(), Error> {
     if digest1 != digest2 {
-        bail!("detected modified configuration - file changed by other user? Try again.");
+        api_bail!(
+            DigestMismatch,
+            "detected modified configuration - file changed by other user? Try again."
+        );
     }
     Ok(())
 }
@@ -35,7 +41,10 @@ pub fn detect_modified_configuration_file(
     digest2: &[u8; 32],
 ) -> Result<(), Error> {
     if digest1 != digest2 {
-        bail!("detected modified configuration - file changed by other user? Try again.");
+        api_bail!(
+            DigestMismatch,
+            "detected modified configuration - file changed by other user? Try again."
+        );
     }
     Ok(())
 }
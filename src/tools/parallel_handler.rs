@@ -6,6 +6,8 @@ use std::thread::JoinHandle;
 use anyhow::{bail, format_err, Error};
 use crossbeam_channel::{bounded, Sender};
 
+use pbs_datastore::task_io_stats;
+
 /// A handle to send data to the worker thread (implements clone)
 pub struct SendHandle<I> {
     input: Sender<I>,
@@ -71,11 +73,14 @@ impl<I: Send + 'static> ParallelHandler<I> {
             let input_rx = input_rx.clone();
             let abort = Arc::clone(&abort);
             let handler_fn = handler_fn.clone();
+            // account the IO of the handler threads to the task creating them
+            let task_io = task_io_stats::current();
 
             handles.push(
                 std::thread::Builder::new()
                     .name(format!("{} ({})", name, i))
                     .spawn(move || loop {
+                        let _task_io = task_io_stats::set_task_io(task_io.clone());
                         let data = match input_rx.recv() {
                             Ok(data) => data,
                             Err(_) => return,
@@ -0,0 +1,175 @@
+//! Estimation of the date a storage runs full, based on its usage history.
+
+use crate::tools::statistics::{linear_regression, mean};
+
+/// Minimum number of data points required for an estimate.
+const MIN_POINTS: usize = 7;
+
+/// Result of [`estimate_full_date`].
+#[derive(Debug, PartialEq)]
+pub struct FullDateEstimate {
+    /// UNIX epoch when the usage reaches 1.0, in the past if the usage does not grow.
+    pub full_date: i64,
+    /// Coefficient of determination of the linear fit.
+    pub r_squared: f64,
+    /// Number of data points used.
+    pub points: usize,
+}
+
+/// Replace the `damping` fraction of the steepest changes between consecutive points with the
+/// median change, keeping the last (current) value.
+///
+/// This removes steps like the drop after a prune, while the growth in between is kept.
+fn damp_outliers(usage: &[f64], damping: f64) -> Vec<f64> {
+    let mut deltas: Vec<f64> = usage.windows(2).map(|w| w[1] - w[0]).collect();
+
+    let ignore = ((deltas.len() as f64) * damping).floor() as usize;
+    if ignore == 0 {
+        return usage.to_vec();
+    }
+
+    let mut order: Vec<usize> = (0..deltas.len()).collect();
+    order.sort_unstable_by(|&a, &b| deltas[b].abs().total_cmp(&deltas[a].abs()));
+    let (steepest, rest) = order.split_at(ignore);
+
+    let mut kept: Vec<f64> = rest.iter().map(|&idx| deltas[idx]).collect();
+    kept.sort_unstable_by(f64::total_cmp);
+    let median = kept[kept.len() / 2];
+
+    for &idx in steepest {
+        deltas[idx] = median;
+    }
+
+    // rebuild the series backwards, so that it still ends at the current usage
+    let mut damped = vec![0.0; usage.len()];
+    damped[usage.len() - 1] = usage[usage.len() - 1];
+    for idx in (0..deltas.len()).rev() {
+        damped[idx] = damped[idx + 1] - deltas[idx];
+    }
+    damped
+}
+
+/// Coefficient of determination of the fit `y = a + bx`.
+fn r_squared(x: &[u64], y: &[f64], a: f64, b: f64) -> Option<f64> {
+    let mean_y = mean(y)?;
+
+    let mut ss_res = 0.0;
+    let mut ss_tot = 0.0;
+    for (x, y) in x.iter().zip(y) {
+        let fitted = a + b * (*x as f64);
+        ss_res += (y - fitted) * (y - fitted);
+        ss_tot += (y - mean_y) * (y - mean_y);
+    }
+
+    if ss_tot == 0.0 {
+        // constant usage is perfectly described by a flat line
+        return Some(if ss_res == 0.0 { 1.0 } else { 0.0 });
+    }
+    Some((1.0 - ss_res / ss_tot).clamp(0.0, 1.0))
+}
+
+/// Estimate when `usage` (between 0.0 and 1.0, sampled at the epochs in `time`) reaches 1.0
+/// via a linear regression.
+///
+/// `damping` is the fraction (0.0 to 0.5) of the steepest changes between consecutive data
+/// points which are replaced by the median change, so that single big steps, for example after
+/// a prune, do not dominate the estimate.
+///
+/// Returns `None` if there are not enough data points. If the usage does not change, the full
+/// date is `0`.
+pub fn estimate_full_date(time: &[u64], usage: &[f64], damping: f64) -> Option<FullDateEstimate> {
+    if time.len() != usage.len() || usage.len() < MIN_POINTS {
+        return None;
+    }
+
+    let damping = damping.clamp(0.0, 0.5);
+    let usage = if damping > 0.0 {
+        damp_outliers(usage, damping)
+    } else {
+        usage.to_vec()
+    };
+
+    let (a, b) = linear_regression(time, &usage)?;
+
+    let full_date = if b != 0.0 {
+        ((1.0 - a) / b).floor() as i64
+    } else {
+        0 // infinite estimate, set to past for gui to detect
+    };
+
+    Some(FullDateEstimate {
+        full_date,
+        r_squared: r_squared(time, &usage, a, b)?,
+        points: usage.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const START: u64 = 1_700_000_000;
+    const STEP: u64 = 1800;
+    const DAY: f64 = 86400.0;
+
+    fn series(count: usize, usage: impl Fn(u64) -> f64) -> (Vec<u64>, Vec<f64>) {
+        let time: Vec<u64> = (0..count as u64).map(|i| START + i * STEP).collect();
+        let usage = time.iter().map(|t| usage(t - START)).collect();
+        (time, usage)
+    }
+
+    // full date of `usage` growing by `per_day` from the end of a series of `count` points
+    fn expected_full_date(count: usize, usage: f64, per_day: f64) -> f64 {
+        (START + (count as u64 - 1) * STEP) as f64 + (1.0 - usage) / per_day * DAY
+    }
+
+    #[test]
+    fn test_estimate_growth() {
+        let per_day = 0.01;
+        let (time, usage) = series(1440, |t| 0.2 + per_day * t as f64 / DAY);
+        let last = *usage.last().unwrap();
+
+        for damping in [0.0, 0.05] {
+            let estimate = estimate_full_date(&time, &usage, damping).unwrap();
+            let expected = expected_full_date(time.len(), last, per_day);
+            assert!((estimate.full_date as f64 - expected).abs() < 3600.0);
+            assert!(estimate.r_squared > 0.999);
+            assert_eq!(estimate.points, 1440);
+        }
+    }
+
+    #[test]
+    fn test_estimate_sawtooth_after_prune() {
+        // grows 1% per day, a prune every 10 days frees 8%
+        let per_day = 0.01;
+        let (time, usage) = series(1440, |t| {
+            let days = t as f64 / DAY;
+            0.4 + per_day * days - 0.08 * (days / 10.0).floor()
+        });
+        let last = *usage.last().unwrap();
+        let expected = expected_full_date(time.len(), last, per_day);
+
+        let plain = estimate_full_date(&time, &usage, 0.0).unwrap();
+        let damped = estimate_full_date(&time, &usage, 0.05).unwrap();
+
+        // the prunes flatten the plain regression, pushing the estimate far into the future
+        assert!(plain.full_date as f64 > expected + 30.0 * DAY);
+        assert!((damped.full_date as f64 - expected).abs() < DAY);
+        assert!(damped.r_squared > plain.r_squared);
+    }
+
+    #[test]
+    fn test_estimate_idle() {
+        let (time, usage) = series(100, |_| 0.5);
+        let estimate = estimate_full_date(&time, &usage, 0.05).unwrap();
+        assert_eq!(estimate.full_date, 0);
+        assert_eq!(estimate.r_squared, 1.0);
+    }
+
+    #[test]
+    fn test_estimate_not_enough_data() {
+        let (time, usage) = series(MIN_POINTS - 1, |t| t as f64 / DAY);
+        assert_eq!(estimate_full_date(&time, &usage, 0.0), None);
+        assert_eq!(estimate_full_date(&time[1..], &usage, 0.0), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Error};
+
+use pbs_client::pxar::*;
+
+const TEST_DIR: &str = ".testdir-pxar-resume";
+
+// Fails once `limit` bytes were read, like a restore losing its connection.
+struct InterruptedReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    limit: usize,
+}
+
+impl Read for InterruptedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "connection lost",
+            ));
+        }
+        let end = self.data.len().min(self.limit).min(self.pos + buf.len());
+        let len = end - self.pos;
+        buf[..len].copy_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        Ok(len)
+    }
+}
+
+fn create_source(path: &Path) -> Result<(), Error> {
+    for dir in 0..4 {
+        let dir_path = path.join(format!("dir{dir}"));
+        std::fs::create_dir_all(&dir_path)?;
+        for file in 0..8 {
+            let data: Vec<u8> = (0..(dir * 8 + file + 1) * 4096)
+                .map(|b| (b % (file + 7)) as u8)
+                .collect();
+            std::fs::write(dir_path.join(format!("file{file}")), data)?;
+        }
+        std::os::unix::fs::symlink("file0", dir_path.join("link"))?;
+    }
+    Ok(())
+}
+
+fn create_archive_data(source: &Path, archive: &Path) -> Result<Vec<u8>, Error> {
+    let writer = std::fs::File::create(archive)?;
+    let writer = pxar::encoder::sync::StandardWriter::new(writer);
+
+    let dir = nix::dir::Dir::open(
+        source,
+        nix::fcntl::OFlag::O_NOFOLLOW,
+        nix::sys::stat::Mode::empty(),
+    )?;
+
+    let options = PxarCreateOptions {
+        entries_max: ENCODER_MAX_ENTRIES,
+        ..PxarCreateOptions::default()
+    };
+
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(create_archive(
+        dir,
+        writer,
+        Flags::DEFAULT,
+        |_| Ok(()),
+        None,
+        options,
+    ))?;
+
+    Ok(std::fs::read(archive)?)
+}
+
+fn extract<R: Read>(reader: R, target: &Path, journal: &Path) -> Result<(), Error> {
+    let options = PxarExtractOptions {
+        match_list: &[],
+        extract_match_default: true,
+        allow_existing_dirs: false,
+        overwrite_flags: OverwriteFlags::empty(),
+        on_error: None,
+        journal: Some(ExtractJournal::open(
+            journal.to_owned(),
+            "test-archive".to_string(),
+        )?),
+    };
+
+    extract_archive(
+        pxar::decoder::Decoder::from_std(reader)?,
+        target,
+        Flags::DEFAULT,
+        |_| {},
+        options,
+    )
+}
+
+fn compare_trees(source: &Path, target: &Path) -> Result<(), Error> {
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+        let source_meta = std::fs::symlink_metadata(&source_path)?;
+        let target_meta = std::fs::symlink_metadata(&target_path)?;
+
+        if source_meta.file_type() != target_meta.file_type() {
+            bail!("file type of {target_path:?} differs");
+        }
+        if source_meta.is_dir() {
+            compare_trees(&source_path, &target_path)?;
+        } else if source_meta.is_symlink() {
+            if std::fs::read_link(&source_path)? != std::fs::read_link(&target_path)? {
+                bail!("symlink {target_path:?} differs");
+            }
+        } else {
+            if std::fs::read(&source_path)? != std::fs::read(&target_path)? {
+                bail!("contents of {target_path:?} differ");
+            }
+            if source_meta.mtime() != target_meta.mtime()
+                || source_meta.mtime_nsec() != target_meta.mtime_nsec()
+            {
+                bail!("mtime of {target_path:?} differs");
+            }
+        }
+    }
+    Ok(())
+}
+
+// first regular file found in the tree below `path`
+fn find_file(path: &Path) -> Result<Option<PathBuf>, Error> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            return Ok(Some(entry.path()));
+        } else if file_type.is_dir() {
+            if let Some(file) = find_file(&entry.path())? {
+                return Ok(Some(file));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn pxar_resume_extraction() -> Result<(), Error> {
+    let base = std::env::current_dir()?.join(TEST_DIR);
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(&base)?;
+
+    let source = base.join("source");
+    let target = base.join("target");
+    let journal = base.join("journal");
+
+    create_source(&source)?;
+    let data = create_archive_data(&source, &base.join("archive.pxar"))?;
+
+    // interrupted in the middle of the archive
+    let reader = InterruptedReader {
+        data: &data,
+        pos: 0,
+        limit: data.len() / 2,
+    };
+    assert!(extract(reader, &target, &journal).is_err());
+    assert!(
+        journal.exists(),
+        "no journal left by the interrupted extraction"
+    );
+
+    let reached = ExtractJournal::open(journal.clone(), "test-archive".to_string())?.reached();
+    assert!(reached > 0);
+
+    // a journal of another archive must not be used
+    let other = ExtractJournal::open(journal.clone(), "other-archive".to_string())?;
+    assert_eq!(other.reached(), 0);
+
+    // damage an already extracted file, the resumed extraction has to notice
+    if let Some(file) = find_file(&target)? {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(file)?
+            .set_len(1)?;
+    }
+
+    extract(&data[..], &target, &journal)?;
+    assert!(
+        !journal.exists(),
+        "journal not removed after completed extraction"
+    );
+
+    compare_trees(&source, &target)?;
+
+    let _ = std::fs::remove_dir_all(&base);
+
+    Ok(())
+}
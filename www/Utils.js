@@ -229,6 +229,11 @@ Ext.define('PBS.Utils', {
 	    text = gettext("OK");
 	}
 
+	let summary = record.data['last-run-summary'];
+	if (summary && summary.failed > 0) {
+	    text += ` (${Ext.String.format(gettext('{0} failed'), summary.failed)})`;
+	}
+
 	return `<i class="fa fa-${icon}"></i> ${text}`;
     },
 
@@ -398,11 +403,13 @@ Ext.define('PBS.Utils', {
 	    backup: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Backup')),
 	    'barcode-label-media': [gettext('Drive'), gettext('Barcode-Label Media')],
 	    'catalog-media': [gettext('Drive'), gettext('Catalog Media')],
+	    'dedup-stats': ['Group', gettext('Deduplication Statistics')],
 	    'delete-datastore': [gettext('Datastore'), gettext('Remove Datastore')],
 	    'delete-namespace': [gettext('Namespace'), gettext('Remove Namespace')],
 	    dircreate: [gettext('Directory Storage'), gettext('Create')],
 	    dirremove: [gettext('Directory'), gettext('Remove')],
 	    'eject-media': [gettext('Drive'), gettext('Eject Media')],
+	    'extend-datastore': [gettext('Datastore'), gettext('Add Chunk Directories')],
 	    "format-media": [gettext('Drive'), gettext('Format media')],
 	    "forget-group": [gettext('Group'), gettext('Remove Group')],
 	    garbage_collection: ['Datastore', gettext('Garbage Collect')],
@@ -411,12 +418,14 @@ Ext.define('PBS.Utils', {
 	    'label-media': [gettext('Drive'), gettext('Label Media')],
 	    'load-media': (type, id) => PBS.Utils.render_drive_load_media_id(id, gettext('Load Media')),
 	    logrotate: [null, gettext('Log Rotation')],
+	    'maintenance-mode': [gettext('Datastore'), gettext('Maintenance Mode')],
 	    prune: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Prune')),
 	    prunejob: (type, id) => PBS.Utils.render_prune_job_worker_id(id, gettext('Prune Job')),
 	    reader: (type, id) => PBS.Utils.render_datastore_worker_id(id, gettext('Read Objects')),
 	    'rewind-media': [gettext('Drive'), gettext('Rewind Media')],
 	    sync: ['Datastore', gettext('Remote Sync')],
 	    syncjob: [gettext('Sync Job'), gettext('Remote Sync')],
+	    'token-purge': [null, gettext('Purge Expired API Tokens')],
 	    'tape-backup': (type, id) => PBS.Utils.render_tape_backup_id(id, gettext('Tape Backup')),
 	    'tape-backup-job': (type, id) => PBS.Utils.render_tape_backup_id(id, gettext('Tape Backup Job')),
 	    'tape-restore': ['Datastore', gettext('Tape Restore')],
@@ -701,6 +710,8 @@ Ext.define('PBS.Utils', {
 	switch (type) {
 	    case 'read-only': modeText = gettext("Read-only");
 		break;
+	    case 'sync-source': modeText = gettext("Sync source only");
+		break;
 	    case 'offline': modeText = gettext("Offline");
 		break;
 	}
@@ -724,6 +735,7 @@ Ext.define('PBS.Utils', {
 	    '__default__': Proxmox.Utils.defaultText + ` (${gettext('Inode')})`,
 	    none: gettext('None'),
 	    inode: gettext('Inode'),
+	    random: gettext('Random'),
 	},
 	'sync-level': {
 	    '__default__': Proxmox.Utils.defaultText + ` (${gettext('Filesystem')})`,
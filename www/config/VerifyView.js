@@ -2,7 +2,7 @@ Ext.define('pbs-verify-jobs-status', {
     extend: 'Ext.data.Model',
     fields: [
 	'id', 'store', 'outdated-after', 'ignore-verified', 'schedule',
-	'next-run', 'last-run-upid', 'last-run-state', 'last-run-endtime',
+	'next-run', 'last-run-upid', 'last-run-state', 'last-run-endtime', 'last-run-summary',
 	{
 	    name: 'duration',
 	    calculate: function(data) {
@@ -203,6 +203,26 @@ Ext.define('PBS.Datastore.Options', {
 		},
 	    },
 	},
+	"trash-retention": {
+	    required: true,
+	    header: gettext('Trash Retention'),
+	    defaultValue: 0,
+	    renderer: v => v ? Ext.String.format(gettext('{0} hours'), v) : gettext('Disabled'),
+	    editor: {
+		xtype: 'proxmoxWindowEdit',
+		title: gettext('Trash Retention'),
+		width: 350,
+		items: {
+		    xtype: 'proxmoxintegerfield',
+		    name: 'trash-retention',
+		    fieldLabel: gettext('Hours'),
+		    emptyText: gettext('Disabled'),
+		    minValue: 0,
+		    maxValue: 8760,
+		    deleteEmpty: true,
+		},
+	    },
+	},
 	"maintenance-mode": {
 	    required: true,
 	    header: gettext('Maintenance mode'),
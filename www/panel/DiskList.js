@@ -0,0 +1,35 @@
+Ext.define('PBS.DiskList', {
+    extend: 'Proxmox.DiskList',
+    alias: 'widget.pbsDiskList',
+
+    // list disks outside the disk monitoring scope of the node too
+    showAll: false,
+
+    initComponent: function() {
+	let me = this;
+
+	me.callParent();
+
+	me.mon(me.getStore(), 'beforeload', function(store, operation) {
+	    if (me.showAll) {
+		operation.setParams(Ext.apply(operation.getParams() || {}, { all: 1 }));
+	    }
+	});
+
+	let tbar = me.getDockedItems('toolbar[dock="top"]')[0];
+	tbar.add([
+	    '->',
+	    {
+		xtype: 'proxmoxcheckbox',
+		boxLabel: gettext('Show all disks'),
+		value: me.showAll,
+		listeners: {
+		    change: function(cb, value) {
+			me.showAll = !!value;
+			me.getController().reload();
+		    },
+		},
+	    },
+	]);
+    },
+});
@@ -171,6 +171,26 @@ Ext.define('PBS.NodeInfoPanel', {
 	    },
 	    value: '',
 	},
+	{
+	    colspan: 2,
+	    title: gettext('Updates'),
+	    printBar: false,
+	    textField: 'updates',
+	    renderer: updates => {
+		if (!updates) {
+		    return Proxmox.Utils.unknownText;
+		}
+		let available = updates.packages.filter(pkg => pkg['update-available']);
+		if (available.length === 0) {
+		    return `<i class="fa fa-fw fa-check good"></i> ${gettext('Up to date')}`;
+		}
+		let versions = available
+		    .map(pkg => `${pkg.package} ${pkg['available-version']}`)
+		    .join(', ');
+		return `<i class="fa fa-fw fa-arrow-circle-up warning"></i> ${versions}`;
+	    },
+	    value: '',
+	},
 	{
 	    xtype: 'pmxNodeInfoRepoStatus',
 	    itemId: 'repositoryStatus',
@@ -14,7 +14,7 @@ Ext.define('PBS.StorageAndDiskPanel', {
 
     items: [
 	{
-	    xtype: 'pmxDiskList',
+	    xtype: 'pbsDiskList',
 	    title: gettext('Disks'),
 	    includePartitions: true,
 	    supportsWipeDisk: true,
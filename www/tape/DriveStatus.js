@@ -323,6 +323,9 @@ Ext.define('PBS.TapeManagement.DriveStatusGrid', {
     title: gettext('Status'),
 
     rows: {
+	'drive-activity': {
+	    header: gettext('Activity'),
+	},
 	'density': {
 	    required: true,
 	    header: gettext('Tape Density'),
@@ -5,6 +5,7 @@ Ext.define('PBS.form.maintenanceType', {
     comboItems: [
 	['__default__', gettext('None')],
 	['read-only', gettext('Read only')],
+	['sync-source', gettext('Sync source only')],
 	['offline', gettext('Offline')],
     ],
 });
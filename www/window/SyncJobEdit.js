@@ -321,6 +321,87 @@ Ext.define('PBS.window.SyncJobEdit', {
 			    deleteEmpty: '{!isCreate}',
 			},
 		    },
+		    {
+			fieldLabel: gettext('Verify Downloads'),
+			xtype: 'proxmoxcheckbox',
+			name: 'verify-downloads',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Verify every downloaded chunk before writing it to the local datastore'),
+			},
+			uncheckedValue: false,
+			value: false,
+		    },
+		    {
+			fieldLabel: gettext('Sync Metadata'),
+			xtype: 'proxmoxcheckbox',
+			name: 'sync-metadata',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Sync notes and protection of snapshots, also for already synced ones'),
+			},
+			defaultValue: true,
+			value: true,
+			cbind: {
+			    deleteDefaultValue: '{!isCreate}',
+			},
+		    },
+		    {
+			fieldLabel: gettext('Encrypted Only'),
+			xtype: 'proxmoxcheckbox',
+			name: 'encrypted-only',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Skip snapshots containing any archive which is not encrypted'),
+			},
+			defaultValue: false,
+			value: false,
+			cbind: {
+			    deleteDefaultValue: '{!isCreate}',
+			},
+		    },
+		    {
+			fieldLabel: gettext('Verified Only'),
+			xtype: 'proxmoxcheckbox',
+			name: 'verified-only',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Skip snapshots whose last verification did not succeed'),
+			},
+			defaultValue: false,
+			value: false,
+			cbind: {
+			    deleteDefaultValue: '{!isCreate}',
+			},
+		    },
+		    {
+			fieldLabel: gettext('Preserve Owner'),
+			xtype: 'proxmoxcheckbox',
+			name: 'preserve-owner',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Use the owner of the source group for new groups, if it exists locally'),
+			},
+			defaultValue: false,
+			value: false,
+			cbind: {
+			    deleteDefaultValue: '{!isCreate}',
+			},
+		    },
+		    {
+			fieldLabel: gettext('Force Owner'),
+			xtype: 'proxmoxcheckbox',
+			name: 'force-owner',
+			autoEl: {
+			    tag: 'div',
+			    'data-qtip': gettext('Change the owner of existing local groups owned by someone else'),
+			},
+			defaultValue: false,
+			value: false,
+			cbind: {
+			    deleteDefaultValue: '{!isCreate}',
+			},
+		    },
 		],
 	    },
 	    {